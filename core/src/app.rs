@@ -0,0 +1,5821 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use base64::{Engine, engine::general_purpose::STANDARD as B64};
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+use zeroize::Zeroizing;
+
+use crate::{
+    bus::EventBus,
+    config::{Config, ConfigChanges, Persona, RecentRoom},
+    contacts::Contacts,
+    crypto::{self, PakeHandshake, RoomKey, RoomSalt, SALT_LEN, legacy_salt, random_salt},
+    export,
+    identity::{self, Identity},
+    links, logger,
+    notes::Notes,
+    notify,
+    plugin::{Plugin, PluginAction, PluginContext},
+    room::{
+        DIRECTORY_TOPIC, Invite, RoomCodeData, RoomState, code_addrs, discovery_key, inbox_topic,
+        invite_token, normalize_room_name, rendezvous_namespace, sign_ownership, split_invite,
+        topic_for_room, verify_ownership,
+    },
+    types::{
+        AdminGrant, Announcement, AppEvent, Chunk, CliCommand, Delivery, DirectEnvelope,
+        DisplayMessage, ForwardBatch, HistoryEntry, JoinState, NetworkCommand, NetworkEvent,
+        NoteEdit, NoteLine, PakeReply, PakeStart, PeerInfo, Poll, PollTally, Presence,
+        PresenceStatus, PublicRoom, Quote, Reachability, Reaction, RoomRekey, RoomSaltReply,
+        RosterEntry, ThreadSummary, UiEvent, Vote, WireMessage, WireMessageType,
+    },
+    vault::{Credentials, Vault},
+    wire::{
+        self, FORWARD_MAX_AGE_MS, MAX_ABSENT, MAX_CIPHERTEXT_LEN, MAX_DESCRIPTION_LEN, MAX_FORWARD,
+        MAX_HISTORY_SYNC, MAX_NICK_LEN, MAX_NOTE_LINES, MAX_POLL_SECS, MAX_SLOW_MODE_SECS,
+        MAX_SUBJECT_LEN, MAX_TEXT_LEN, RateLimiter, RateVerdict, Reassembler, SeenCache,
+        SendLimiter, SkewEstimator,
+    },
+};
+
+/// Messages kept per room so switching back can restore the view.
+const MAX_HISTORY: usize = 500;
+/// Mentions kept for `/mentions`, across all rooms.
+const MAX_MENTIONS: usize = 50;
+/// Links listed by `/links`, the newest of the active room's history.
+const MAX_LISTED_LINKS: usize = 20;
+/// Rooms on the main menu's recent list (keys 5–9).
+const MAX_RECENT_ROOMS: usize = 5;
+/// The in-room commands as (usage, what it does), for help and the
+/// command palette.  The frontend parses them.
+const COMMANDS: &[(&str, &str)] = &[
+    ("/quit", "leave the current room"),
+    ("/switch <room>", "show another joined room (or Alt+1…9)"),
+    ("/rooms", "list joined rooms"),
+    ("/menu", "main menu (stay in rooms)"),
+    ("/mouse", "toggle mouse capture (off: select text)"),
+    ("/peers", "list members and how each is connected"),
+    ("/mentions", "list recent messages that mention you"),
+    ("/links", "list the links posted lately in this room"),
+    ("/away [why]", "show as away"),
+    ("/dnd [why]", "do not disturb: away, and no notifications"),
+    ("/back", "show as online again"),
+    (
+        "/history [n]",
+        "show the last n (20) lines of this room's log",
+    ),
+    (
+        "/export [fmt]",
+        "save this room's history as markdown, html or txt",
+    ),
+    ("/msg <nick> <text>", "private message to one member"),
+    ("/verify <nick> [confirm|revoke]", "compare safety numbers"),
+    ("/trust <nick>", "pin a member's key in your contacts"),
+    (
+        "/alias <nick> [name]",
+        "show a member by a name of your own (none: its nickname)",
+    ),
+    ("/contacts", "list your contacts"),
+    ("/block <nick>", "drop a member's messages"),
+    ("/unblock <nick>", "show a blocked member's messages again"),
+    ("/blocks", "list blocked members"),
+    (
+        "/react [nick] <emoji>",
+        "react to the latest message (from nick)",
+    ),
+    (
+        "/reply <nick> <text>",
+        "reply to nick's latest message (or Alt+Up)",
+    ),
+    (
+        "/poll [min] \"question\" \"a\" \"b\"…",
+        "ask the room to vote (open 10 minutes)",
+    ),
+    ("/vote <n>", "vote for option n of the latest open poll"),
+    ("/endpoll", "close your latest open poll"),
+    (
+        "/notes",
+        "open this room's shared notes, which every member can edit",
+    ),
+    (
+        "/note add|set|del [n] [text]",
+        "change a line of the shared notes",
+    ),
+    (
+        "/thread [name]",
+        "read and write in a thread of this room (alone: back to the room)",
+    ),
+    ("/threads", "list this room's threads"),
+    (
+        "/topic [text]",
+        "show or (room owner or co-owner) set the room topic",
+    ),
+    (
+        "/slow [s|off]",
+        "show or (room owner or co-owner) set seconds between messages",
+    ),
+    (
+        "/announce [on|off]",
+        "show or (room owner or co-owner) set whether only speakers write",
+    ),
+    (
+        "/speaker <nick> [off]",
+        "let a member write in an announcement room (room owner or co-owner)",
+    ),
+    (
+        "/token [hours] [once]",
+        "mint an invite token that expires, for one joiner with once (room owner only)",
+    ),
+    (
+        "/inviteonly [on|off]",
+        "show or (room owner) set whether joiners need an invite token",
+    ),
+    (
+        "/capacity [n|off]",
+        "show or (room owner) set the most members the room holds",
+    ),
+    (
+        "/public [description|off]",
+        "list the room in the public directory, or take it off (room owner only)",
+    ),
+    ("/browse", "list the public rooms in the directory"),
+    ("/kick <nick>", "remove a member (room owner or co-owner)"),
+    (
+        "/ban <nick>",
+        "remove and ignore a member (room owner or co-owner)",
+    ),
+    (
+        "/admin <nick> [off]",
+        "make a member a co-owner, or no longer (room owner only)",
+    ),
+    (
+        "/transfer <nick>",
+        "hand the room to a member and stay a co-owner (room owner only)",
+    ),
+    ("/whois <nick>", "show a member's role, key and alias"),
+    ("/rekey", "rotate the room key (room owner only)"),
+    (
+        "/invite",
+        "show a fresh room code that leads to you at your current addresses",
+    ),
+    (
+        "/rename <name>",
+        "give the room a new name; old codes stop working (room owner only)",
+    ),
+    ("/code", "show the room code (and its QR code) again"),
+    ("/save", "keep this room's code and password for /rejoin"),
+    ("/rejoin [room]", "enter a saved room again, or list them"),
+    ("/forget <room>", "drop a saved room's code and password"),
+    ("/mute", "toggle notifications for this room"),
+    ("/reload", "read the config file again (also SIGHUP)"),
+    ("/help", "show this help (or F1)"),
+];
+/// How long a poll stays open unless `/poll` says otherwise.
+const POLL_MINUTES: u32 = 10;
+/// How long an invite token is good for unless `/token` says otherwise…
+const INVITE_HOURS: u32 = 24;
+/// …and at most.
+const MAX_INVITE_HOURS: u32 = 30 * 24;
+/// Log lines `/history` shows without a count.
+const HISTORY_LINES: usize = 20;
+/// Most log lines `/history` shows.
+const MAX_HISTORY_LINES: usize = MAX_HISTORY;
+
+pub struct App {
+    identity: Identity,
+    config: Config,
+    // Peers pinned with `/trust` or `/verify`, from `config.contacts_file`
+    contacts: Contacts,
+    // Room codes and passwords kept with `/save`, from `config.rooms_file`
+    vault: Vault,
+    // Passphrase the vault was unlocked with at startup, if it has one
+    vault_passphrase: Option<Zeroizing<String>>,
+    // Keys already flagged as using a contact's nickname this session
+    name_clashes: HashSet<String>,
+
+    // Rooms we are subscribed to, in the order they were entered
+    rooms: Vec<JoinedRoom>,
+    // Name of the room shown in the TUI (None when in menu)
+    active: Option<String>,
+
+    // Listen addresses gathered from the network layer
+    listen_addrs: Vec<String>,
+    // Whether peers can dial us directly, per AutoNAT
+    nat: Reachability,
+
+    // In-progress room join (None once it reaches a terminal state)
+    join: Option<JoinFlow>,
+
+    // Direct message IDs already received, to drop replays
+    inbox_seen: SeenCache,
+
+    // Inbound payload rate per publishing peer, to stop floods
+    rate: RateLimiter,
+
+    // Our own chat message rate, so we do not flood rooms ourselves; None
+    // when `without_send_limit` lifted it
+    send_limiter: Option<SendLimiter>,
+    // Where room messages go once decrypted, for `chat --record`
+    capture: Option<mpsc::UnboundedSender<NetworkEvent>>,
+
+    // How far each member's clock is off ours, to place relayed messages
+    skew: SkewEstimator,
+
+    // Redialing room peers after every connection dropped; `None` online
+    reconnect: Option<Reconnect>,
+
+    // Recent messages that mentioned us, in any room: room name, message
+    mentions: VecDeque<(String, DisplayMessage)>,
+
+    // Room whose `/peers` waits for the network's `PeerInfo`
+    peers_query: Option<String>,
+
+    // Extensions added with `with_plugin`, called in that order
+    plugins: Vec<Box<dyn Plugin>>,
+
+    // Whether the terminal has focus (assumed until told otherwise)
+    focused: bool,
+
+    // Our availability, announced to every room with `Presence`
+    presence: Presence,
+    // Set away by `SetIdle` rather than by the user
+    auto_away: bool,
+    // When `presence` is announced again while not online
+    presence_due: tokio::time::Instant,
+
+    // Public rooms heard on the directory topic, by (owner, name), and when
+    directory: BTreeMap<(String, String), (PublicRoom, tokio::time::Instant)>,
+    // Subscribed to the directory topic, to list rooms or browse them
+    in_directory: bool,
+    // Whether the UI asked for the directory (and gets its changes)
+    browsing: bool,
+    // When our listed rooms are listed again
+    listing_due: tokio::time::Instant,
+    // When we last sent, and last answered, a `ListingQuery`
+    listing_queried: Option<tokio::time::Instant>,
+    listing_answered: Option<tokio::time::Instant>,
+    // Directory message IDs already received, to drop replays
+    directory_seen: SeenCache,
+
+    // Channels
+    net_event_rx: mpsc::UnboundedReceiver<NetworkEvent>,
+    net_cmd_tx: mpsc::UnboundedSender<NetworkCommand>,
+    cli_cmd_rx: mpsc::UnboundedReceiver<CliCommand>,
+    bus: EventBus,
+}
+
+/// A room we are a member of, with everything needed to read and write it.
+struct JoinedRoom {
+    state: RoomState,
+    /// Derived from the password: answers joiners' handshakes, and encrypts
+    /// the room until the owner first rotates the key.
+    key: RoomKey,
+    /// Key epoch, bumped by each `Rekey`; 0 while `key` is in use.
+    epoch: u32,
+    /// The current epoch's key, which room traffic is encrypted with once
+    /// set.
+    epoch_key: Option<RoomKey>,
+    /// The key before the last rotation and until when messages encrypted
+    /// with it, already in flight, are still read.
+    retired: Option<(RoomKey, tokio::time::Instant)>,
+    /// Salt `key` was derived with; handed to peers joining by name.
+    salt: RoomSalt,
+    /// Recent transcript, replayed to the UI when switching to this room.
+    history: VecDeque<DisplayMessage>,
+    /// Verified members other than us: display name → peer ID (empty if
+    /// unknown).  Maintained from `Join` / `Leave` / `RosterAnnounce`.
+    members: BTreeMap<String, String>,
+    history_sync: HistorySync,
+    /// Message IDs already received, to drop replays.
+    seen: SeenCache,
+    /// Peer ID of the room's owner — its creator, or whoever ownership
+    /// was transferred to; empty if unknown.
+    owner: String,
+    /// Timestamp of the `TransferOwnership` that made `owner` the owner
+    /// (0 for the creator), so an older one arriving late does not win.
+    owner_ms: i64,
+    /// The owner's statement that it owns the room under its current name
+    /// (`room::sign_ownership`), from its code, a member or the owner, for
+    /// the codes we make and the joiners we answer; empty if none yet.
+    owner_proof: Vec<u8>,
+    /// Another peer that showed its own statement that it owns the room:
+    /// the member who let us in named it rather than `owner`, the owner
+    /// our code named.  While set, neither is obeyed as owner, until
+    /// `owner` hands the room to it.
+    owner_disputed: String,
+    /// Peer IDs of the co-owners, who may also set the topic, slow mode
+    /// and speakers and kick and ban; from the owner's `GrantAdmin`s.
+    admins: BTreeSet<String>,
+    /// Timestamp of the latest `GrantAdmin` for each member, as for slow
+    /// mode.
+    admin_ms: BTreeMap<String, i64>,
+    /// The `GrantAdmin` and `TransferOwnership` messages taken, in order,
+    /// replayed to joiners as `TopicSync`.
+    roles_wire: Vec<Vec<u8>>,
+    /// Peer IDs banned by the owner or a co-owner; their messages are
+    /// dropped.
+    banned: BTreeSet<String>,
+    /// From the owner's `InviteOnly`: whether we check the password only
+    /// of joiners with a valid invite.  Its timestamp and the message
+    /// itself for joiners, as for slow mode.
+    invite_only: bool,
+    invite_only_ms: i64,
+    invite_only_wire: Option<Vec<u8>>,
+    /// Description the room is listed with in the public directory, if
+    /// we (its owner) listed it.
+    listing: Option<String>,
+    /// From the owner's `Capacity`: the most members here, the owner
+    /// included; 0 for no limit.  Its timestamp and the message itself
+    /// for joiners, as for slow mode.
+    max_members: u32,
+    max_members_ms: i64,
+    max_members_wire: Option<Vec<u8>>,
+    /// Joiners whose password we checked on an invite, by peer ID: no
+    /// history for them once it expired.
+    invited: BTreeMap<String, Invite>,
+    /// Single-use invites presented to us, by ID, and the peer each was
+    /// spent on.
+    spent_invites: BTreeMap<u64, String>,
+    /// Members who left or disconnected: peer ID → signed chat messages
+    /// (wire-encoded) sent since, forwarded when they return.
+    absent: BTreeMap<String, VecDeque<Vec<u8>>>,
+    /// `(sender, msg_id)` of recent chat messages, so a forwarded copy of
+    /// one we already have is dropped.
+    chat_ids: VecDeque<(String, u64)>,
+    /// Parts of long chat messages still coming in.
+    chunks: Reassembler,
+    /// The owner's signed `Topic` message behind `state.subject`, encoded,
+    /// replayed to joiners as `TopicSync`.
+    subject_wire: Option<Vec<u8>>,
+    /// Seconds members wait between chat messages, from the owner's
+    /// `SlowMode`; 0 for off.
+    slow_mode: u32,
+    /// Timestamp of that `SlowMode` message, so an older one arriving
+    /// late does not win.
+    slow_mode_ms: i64,
+    /// The message itself, encoded, replayed to joiners as `TopicSync`.
+    slow_mode_wire: Option<Vec<u8>>,
+    /// From the owner's `Announcement`: whether only the owner and the
+    /// speakers write here.
+    announcement: Announcement,
+    /// Timestamp of that message, and the message itself for joiners, as
+    /// for slow mode.
+    announcement_ms: i64,
+    announcement_wire: Option<Vec<u8>>,
+    /// When we last sent a chat message here (ms), for slow mode.
+    last_sent_ms: i64,
+    /// Reactions to messages in `history`: message ID → emoji → members
+    /// who chose it.  Dropped with the message.
+    reactions: BTreeMap<u64, BTreeMap<String, BTreeSet<String>>>,
+    /// Polls asked in `history`, by message ID.  Dropped with the message.
+    polls: BTreeMap<u64, PollState>,
+    /// The notes members write together, from `Notes` messages.
+    notes: Notes,
+    /// Thread shown and sent to (`/thread`); `None` for the room itself.
+    thread: Option<String>,
+    /// Resubscribed after a reconnect: our `Join` goes out again once a
+    /// member's subscription shows up, so it has somewhere to go.
+    rejoin: bool,
+    /// What members announced with `Presence`, by name; those not in it
+    /// are online.
+    presence: BTreeMap<String, Presence>,
+    /// Code and password we entered with, for `/save`.  The code is empty
+    /// when we created the room (`/save` makes a fresh one) or joined by
+    /// name.
+    credentials: Credentials,
+    /// Names (`nick#disc`) two peers here go by; whoever uses one is shown
+    /// with a longer discriminator.
+    clashes: BTreeSet<String>,
+    /// Who we are here, if not our usual identity (`[personas]`).
+    persona: Option<Identity>,
+    /// Names the room went by before the owner renamed it, oldest first;
+    /// codes naming them no longer lead here.
+    former_names: Vec<String>,
+    /// Avatars of members who have one, by name: from their signed
+    /// messages, or second hand from a `RosterAnnounce`.
+    avatars: BTreeMap<String, String>,
+}
+
+/// A poll in a room and the votes cast in it.
+struct PollState {
+    /// Peer ID of whoever asked; only it may close the poll early.
+    creator: String,
+    options: Vec<String>,
+    /// Voters' peer IDs → index of the option each chose.
+    votes: BTreeMap<String, usize>,
+    closes_ms: i64,
+    closed: bool,
+}
+
+impl PollState {
+    fn new(creator: String, poll: Poll, sent_ms: i64) -> Self {
+        Self {
+            creator,
+            options: poll.options,
+            votes: BTreeMap::new(),
+            closes_ms: sent_ms + i64::from(poll.duration_secs) * 1000,
+            closed: false,
+        }
+    }
+
+    fn tally(&self) -> PollTally {
+        let options = self
+            .options
+            .iter()
+            .enumerate()
+            .map(|(i, option)| {
+                (
+                    option.clone(),
+                    self.votes.values().filter(|&&v| v == i).count(),
+                )
+            })
+            .collect();
+        PollTally {
+            options,
+            closes_at: chrono::DateTime::from_timestamp_millis(self.closes_ms).unwrap_or_default(),
+            closed: self.closed,
+        }
+    }
+}
+
+/// The avatar `wire` carries, if it is a valid one.
+fn avatar_of(wire: &WireMessage) -> Option<String> {
+    wire::validate_avatar(&wire.avatar)
+        .is_ok()
+        .then(|| wire.avatar.clone())
+}
+
+/// The thread `wire` was written in, if any (checked by `wire::decode`).
+fn thread_of(wire: &WireMessage) -> Option<String> {
+    (!wire.thread.is_empty()).then(|| wire.thread.clone())
+}
+
+/// `label` after `avatar`, if there is one.
+fn with_avatar(avatar: Option<&String>, label: String) -> String {
+    match avatar {
+        Some(avatar) => format!("{avatar} {label}"),
+        None => label,
+    }
+}
+
+/// `nick` with a discriminator of `peer` twice as long as `disc`, to tell
+/// it from another peer that has the same name.
+fn extended_name(nick: &str, disc: &str, peer: &PeerId) -> String {
+    format!(
+        "{nick}#{}",
+        identity::discriminator(peer, (disc.len() * 2).min(wire::MAX_DISC_LEN))
+    )
+}
+
+/// Fetching recent messages from an existing member after joining.
+#[derive(Debug, Clone, PartialEq)]
+enum HistorySync {
+    /// Joined; ask the first member whose roster arrives.
+    Wanted,
+    /// Asked this member; only its response is accepted.
+    Requested(String),
+    /// Nothing (more) to fetch.
+    Done,
+}
+
+impl JoinedRoom {
+    fn new(state: RoomState, key: RoomKey, salt: RoomSalt, owner: String) -> Self {
+        Self {
+            state,
+            key,
+            epoch: 0,
+            epoch_key: None,
+            retired: None,
+            salt,
+            history: VecDeque::new(),
+            members: BTreeMap::new(),
+            history_sync: HistorySync::Done,
+            seen: SeenCache::default(),
+            owner,
+            owner_ms: 0,
+            owner_proof: Vec::new(),
+            owner_disputed: String::new(),
+            admins: BTreeSet::new(),
+            admin_ms: BTreeMap::new(),
+            roles_wire: Vec::new(),
+            banned: BTreeSet::new(),
+            invite_only: false,
+            invite_only_ms: 0,
+            invite_only_wire: None,
+            listing: None,
+            max_members: 0,
+            max_members_ms: 0,
+            max_members_wire: None,
+            invited: BTreeMap::new(),
+            spent_invites: BTreeMap::new(),
+            absent: BTreeMap::new(),
+            chat_ids: VecDeque::new(),
+            chunks: Reassembler::default(),
+            subject_wire: None,
+            slow_mode: 0,
+            slow_mode_ms: 0,
+            slow_mode_wire: None,
+            announcement: Announcement::default(),
+            announcement_ms: 0,
+            announcement_wire: None,
+            last_sent_ms: 0,
+            reactions: BTreeMap::new(),
+            polls: BTreeMap::new(),
+            notes: Notes::default(),
+            thread: None,
+            rejoin: false,
+            presence: BTreeMap::new(),
+            avatars: BTreeMap::new(),
+            credentials: Credentials {
+                code: String::new(),
+                password: Zeroizing::new(String::new()),
+            },
+            clashes: BTreeSet::new(),
+            persona: None,
+            former_names: Vec::new(),
+        }
+    }
+
+    /// Note that `peer` goes by `nick#disc`.  If another member, or we,
+    /// already do, both are shown with longer discriminators from now on;
+    /// returns what to tell the user then.
+    fn note_name(
+        &mut self,
+        nick: &str,
+        disc: &str,
+        peer: &PeerId,
+        own: &Identity,
+    ) -> Option<String> {
+        let name = format!("{nick}#{disc}");
+        if self.clashes.contains(&name) {
+            return None;
+        }
+        let other = self
+            .members
+            .get(&name)
+            .filter(|id| **id != peer.to_string())
+            .and_then(|id| id.parse::<PeerId>().ok());
+        let ours = name == own.display_name() && *peer != own.peer_id;
+        if other.is_none() && !ours {
+            return None;
+        }
+        self.clashes.insert(name.clone());
+        let shown = extended_name(nick, disc, peer);
+        match other {
+            Some(other) => {
+                // The member known by that name moves to its longer one.
+                let id = self.members.remove(&name).unwrap_or_default();
+                let other_name = extended_name(nick, disc, &other);
+                self.members.insert(other_name.clone(), id);
+                if let Some(avatar) = self.avatars.remove(&name) {
+                    self.avatars.insert(other_name.clone(), avatar);
+                }
+                Some(format!(
+                    "Two members are called {name}: shown as {other_name} and {shown} from now on."
+                ))
+            }
+            None => Some(format!(
+                "A member is called {name}, as you are: shown as {shown} from now on."
+            )),
+        }
+    }
+
+    /// How `peer` calling itself `nick#disc` is shown here: with a longer
+    /// discriminator if two peers go by that name (`note_name`).
+    fn shown_name(&self, nick: &str, disc: &str, peer: Option<&PeerId>) -> String {
+        let name = format!("{nick}#{disc}");
+        match peer {
+            Some(peer) if self.clashes.contains(&name) => extended_name(nick, disc, peer),
+            _ => name,
+        }
+    }
+
+    /// Whether `signer` is this room's owner.
+    fn is_owner(&self, signer: Option<PeerId>) -> bool {
+        self.owner_disputed.is_empty() && self.claims_owner(signer)
+    }
+
+    /// Whether `signer` is who we take for the owner, even while another
+    /// peer disputes it (see `owner_disputed`).
+    fn claims_owner(&self, signer: Option<PeerId>) -> bool {
+        !self.owner.is_empty() && signer.is_some_and(|p| p.to_string() == self.owner)
+    }
+
+    /// Whether `signer` is this room's owner or a co-owner.
+    fn is_admin(&self, signer: Option<PeerId>) -> bool {
+        self.is_owner(signer) || signer.is_some_and(|p| self.admins.contains(&p.to_string()))
+    }
+
+    /// Whether `signer` may send an owner setting of type `msg_type`:
+    /// co-owners set the topic, slow mode and speakers, the rest is the
+    /// owner's.
+    fn may_set(&self, msg_type: &WireMessageType, signer: Option<PeerId>) -> bool {
+        match msg_type {
+            WireMessageType::Topic | WireMessageType::SlowMode | WireMessageType::Announcement => {
+                self.is_admin(signer)
+            }
+            // Settles a dispute, if it hands the room to the other claimant.
+            WireMessageType::TransferOwnership => self.claims_owner(signer),
+            _ => self.is_owner(signer),
+        }
+    }
+
+    /// "owner" or "co-owner" for the member with `peer_id`, if either.
+    fn role(&self, peer_id: &str) -> Option<&'static str> {
+        if !peer_id.is_empty() && peer_id == self.owner {
+            Some("owner")
+        } else if self.admins.contains(peer_id) {
+            Some("co-owner")
+        } else {
+            None
+        }
+    }
+
+    /// Who the owner or co-owner with `peer_id` is, for lines about what
+    /// it did.
+    fn acted_by(&self, peer_id: &str) -> String {
+        match self.role(peer_id) {
+            Some("owner") => "the room owner".to_string(),
+            _ => format!("{}, a co-owner", self.name_of(peer_id)),
+        }
+    }
+
+    /// Whether we check the password of joiner `peer`, which presented
+    /// invite grant `grant` (empty: none); why not otherwise.  A valid
+    /// single-use invite is spent on `peer`.
+    fn admit(&mut self, peer: &str, grant: &str, now_ms: i64) -> Result<(), &'static str> {
+        if grant.is_empty() {
+            return match self.invite_only && peer != self.owner {
+                true => Err("the room takes joiners with an invite only"),
+                false => Ok(()),
+            };
+        }
+        let invite = Invite::decode(grant)
+            .ok()
+            .filter(|i| i.verify(&self.owner, &self.state.name, &self.salt))
+            .ok_or("the invite is not valid")?;
+        if invite.expires_ms <= now_ms {
+            return Err("the invite expired");
+        }
+        if invite.single_use {
+            if self
+                .spent_invites
+                .get(&invite.id)
+                .is_some_and(|p| p != peer)
+            {
+                return Err("the invite was already used");
+            }
+            self.spent_invites.insert(invite.id, peer.to_string());
+        }
+        self.invited.insert(peer.to_string(), invite);
+        Ok(())
+    }
+
+    /// Whether `peer_id`, not a member yet, would take the room past its
+    /// member limit.  The owner always fits.
+    fn is_full_for(&self, peer_id: &str) -> bool {
+        self.max_members != 0
+            && peer_id != self.owner
+            && !self.members.values().any(|id| id == peer_id)
+            && self.members.len() + 1 >= self.max_members as usize
+    }
+
+    /// Whether `peer_id` came in on an invite that has expired since.
+    fn invite_expired(&self, peer_id: &str, now_ms: i64) -> bool {
+        self.invited
+            .get(peer_id)
+            .is_some_and(|i| i.expires_ms <= now_ms)
+    }
+
+    /// Whether `signer` may write chat messages here: anyone, unless this
+    /// is an announcement room.
+    fn may_speak(&self, signer: Option<PeerId>) -> bool {
+        let speakers = &self.announcement.speakers;
+        !self.announcement.on
+            || self.is_admin(signer)
+            || signer.is_some_and(|p| speakers.contains(&p.to_string()))
+    }
+
+    /// The name a member with `peer_id` goes by here, or the start of the
+    /// peer ID if it is not here.
+    fn name_of(&self, peer_id: &str) -> String {
+        match self.members.iter().find(|(_, id)| *id == peer_id) {
+            Some((name, _)) => name.clone(),
+            None => format!("{}…", peer_id.chars().take(12).collect::<String>()),
+        }
+    }
+
+    /// Key room traffic is encrypted with.
+    fn traffic_key(&self) -> &RoomKey {
+        self.epoch_key.as_ref().unwrap_or(&self.key)
+    }
+
+    /// Decrypt a room message with the current key, or the retired one
+    /// while it is still accepted.
+    fn decrypt(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        self.traffic_key()
+            .decrypt(payload)
+            .or_else(|e| match &self.retired {
+                Some((key, until)) if tokio::time::Instant::now() < *until => key.decrypt(payload),
+                _ => Err(e),
+            })
+    }
+
+    /// Move to key `epoch`, keeping the old key for `RETIRED_KEY_GRACE`.
+    fn install_key(&mut self, epoch: u32, key: RoomKey) {
+        let old = self.traffic_key().clone();
+        self.retired = Some((old, tokio::time::Instant::now() + RETIRED_KEY_GRACE));
+        self.epoch = epoch;
+        self.epoch_key = Some(key);
+    }
+
+    /// Start buffering chat for a member that went away.
+    fn mark_absent(&mut self, peer_id: &str) {
+        if !peer_id.is_empty() && self.absent.len() < MAX_ABSENT {
+            self.absent.entry(peer_id.to_string()).or_default();
+        }
+    }
+
+    /// Keep a signed chat message for every absent member.
+    fn hold_for_absent(&mut self, wire: &[u8]) {
+        for queue in self.absent.values_mut() {
+            if queue.len() >= MAX_FORWARD {
+                queue.pop_front();
+            }
+            queue.push_back(wire.to_vec());
+        }
+    }
+
+    /// Remember a chat message's ID; `false` if it was already known.
+    fn note_chat(&mut self, sender: &str, msg_id: u64) -> bool {
+        let key = (sender.to_string(), msg_id);
+        if msg_id != 0 && self.chat_ids.contains(&key) {
+            return false;
+        }
+        if self.chat_ids.len() >= MAX_HISTORY {
+            self.chat_ids.pop_front();
+        }
+        self.chat_ids.push_back(key);
+        true
+    }
+
+    /// Whether a message comes from a banned peer.  Unsigned messages are
+    /// matched on the discriminator, which is all they prove.
+    fn is_banned(&self, signer: Option<PeerId>, disc: &str) -> bool {
+        match signer {
+            Some(peer) => self.banned.contains(&peer.to_string()),
+            None => self.banned.iter().any(|id| {
+                id.parse::<PeerId>()
+                    .is_ok_and(|p| identity::discriminator(&p, disc.len()) == disc)
+            }),
+        }
+    }
+
+    /// Our recent chat messages for a `HistoryResponse`, oldest first, cut
+    /// to what fits in one wire message.
+    fn history_snapshot(&self) -> Vec<HistoryEntry> {
+        let mut budget = MAX_TEXT_LEN.saturating_sub(2); // the enclosing []
+        let mut entries: Vec<HistoryEntry> = self
+            .history
+            .iter()
+            .rev()
+            .filter(|m| !m.is_system)
+            .take(MAX_HISTORY_SYNC)
+            .map(|m| HistoryEntry {
+                sender: m.sender.clone(),
+                timestamp_ms: m.timestamp.timestamp_millis(),
+                text: m.text.clone(),
+                thread: m.thread.clone().unwrap_or_default(),
+            })
+            .take_while(|e| {
+                // Entry plus its separating comma
+                let len = serde_json::to_string(e).map_or(usize::MAX, |j| j.len() + 1);
+                match budget.checked_sub(len) {
+                    Some(rest) => {
+                        budget = rest;
+                        true
+                    }
+                    None => false,
+                }
+            })
+            .collect();
+        entries.reverse();
+        entries
+    }
+
+    /// Put synced messages into the transcript by their time, corrected by
+    /// `skew`, skipping any we already have.  Returns how many were added.
+    fn prepend_history(
+        &mut self,
+        entries: Vec<HistoryEntry>,
+        from: &str,
+        skew: &SkewEstimator,
+    ) -> usize {
+        let now_ms = Utc::now().timestamp_millis();
+        let older: Vec<DisplayMessage> = entries
+            .into_iter()
+            .filter(|e| {
+                !self
+                    .history
+                    .iter()
+                    .any(|m| !m.is_system && m.sender == e.sender && m.text == e.text)
+            })
+            .map(|e| {
+                // Relayed by another member; the original signature is gone.
+                let thread = (!e.thread.is_empty()).then_some(e.thread);
+                let mut msg = DisplayMessage::chat(&e.sender, &e.text)
+                    .unverified()
+                    .in_thread(thread);
+                let ts = skew.adjust(&e.sender, e.timestamp_ms, now_ms);
+                if let Some(ts) = chrono::DateTime::from_timestamp_millis(ts) {
+                    msg.timestamp = ts;
+                }
+                msg
+            })
+            .collect();
+        let added = older.len();
+        if let Some(last) = older.iter().map(|m| m.timestamp).max() {
+            let mut marker =
+                DisplayMessage::system(&format!("{} earlier message(s) from {}", added, from));
+            marker.timestamp = last;
+            for msg in older.into_iter().chain(std::iter::once(marker)).rev() {
+                self.history.push_front(msg);
+            }
+            self.sort_history();
+            self.history.truncate(MAX_HISTORY);
+        }
+        added
+    }
+
+    /// Order the transcript by time, keeping the order of equal times.
+    /// Returns whether anything moved.
+    fn sort_history(&mut self) -> bool {
+        if self.history.iter().is_sorted_by_key(|m| m.timestamp) {
+            return false;
+        }
+        self.history.make_contiguous().sort_by_key(|m| m.timestamp);
+        true
+    }
+
+    /// Look up a member by `nick#disc`, or by bare nickname if only one
+    /// member uses it.  Returns the full name and the peer ID.
+    fn find_member(&self, query: &str) -> Result<(String, String)> {
+        if let Some(peer_id) = self.members.get(query) {
+            return Ok((query.to_string(), peer_id.clone()));
+        }
+        let matches: Vec<_> = self
+            .members
+            .iter()
+            .filter(|(name, _)| name.rsplit_once('#').is_some_and(|(nick, _)| nick == query))
+            .collect();
+        match matches.as_slice() {
+            [(name, peer_id)] => Ok(((*name).clone(), (*peer_id).clone())),
+            [] => bail!("No member called '{query}' in this room."),
+            _ => {
+                let names: Vec<&str> = matches.iter().map(|(n, _)| n.as_str()).collect();
+                bail!("'{query}' is ambiguous: {}", names.join(", "))
+            }
+        }
+    }
+
+    fn push_history(&mut self, msg: DisplayMessage) {
+        if self.history.len() >= MAX_HISTORY
+            && let Some(old) = self.history.pop_front()
+        {
+            self.reactions.remove(&old.msg_id);
+            self.polls.remove(&old.msg_id);
+        }
+        self.history.push_back(msg);
+    }
+
+    /// The transcript as shown: the messages of the thread we are in, or
+    /// of the room itself.
+    fn view(&self) -> Vec<DisplayMessage> {
+        self.history
+            .iter()
+            .filter(|m| m.thread == self.thread)
+            .cloned()
+            .collect()
+    }
+
+    /// Threads with messages in our history, and the one we are in,
+    /// most recently active first.
+    fn threads(&self) -> Vec<ThreadSummary> {
+        let mut threads: BTreeMap<&str, ThreadSummary> = BTreeMap::new();
+        for msg in &self.history {
+            let Some(name) = &msg.thread else { continue };
+            let thread = threads.entry(name).or_insert_with(|| ThreadSummary {
+                name: name.clone(),
+                messages: 0,
+                last: msg.timestamp,
+            });
+            thread.messages += 1;
+            thread.last = thread.last.max(msg.timestamp);
+        }
+        // Opened but still empty.
+        if let Some(name) = &self.thread {
+            threads.entry(name).or_insert_with(|| ThreadSummary {
+                name: name.clone(),
+                messages: 0,
+                last: Utc::now(),
+            });
+        }
+        let mut threads: Vec<ThreadSummary> = threads.into_values().collect();
+        threads.sort_by_key(|t| std::cmp::Reverse(t.last));
+        threads
+    }
+
+    /// What a reply to `msg_id` shows of it: the start of that message, if
+    /// it is still in our history.
+    fn quote(&self, msg_id: u64) -> Quote {
+        self.history
+            .iter()
+            .rev()
+            .find(|m| !m.is_system && m.msg_id == msg_id)
+            .map_or_else(Quote::missing, Quote::of)
+    }
+
+    /// Count `reactor`'s reaction; the message's new tally, or `None` if
+    /// the target is not in our history or the reaction was already known.
+    fn add_reaction(&mut self, reactor: &str, reaction: Reaction) -> Option<Vec<(String, usize)>> {
+        let msg = self
+            .history
+            .iter_mut()
+            .rev()
+            .find(|m| !m.is_system && m.msg_id == reaction.target_msg_id)?;
+        let emojis = self.reactions.entry(reaction.target_msg_id).or_default();
+        if !emojis
+            .entry(reaction.emoji)
+            .or_default()
+            .insert(reactor.to_string())
+        {
+            return None;
+        }
+        msg.reactions = emojis
+            .iter()
+            .map(|(e, who)| (e.clone(), who.len()))
+            .collect();
+        Some(msg.reactions.clone())
+    }
+
+    /// The latest poll here still open, and asked by `creator` if given.
+    fn open_poll(&self, creator: Option<&str>) -> Option<u64> {
+        self.history
+            .iter()
+            .rev()
+            .filter(|m| m.poll.as_ref().is_some_and(|p| !p.closed))
+            .map(|m| m.msg_id)
+            .find(|id| {
+                self.polls
+                    .get(id)
+                    .is_some_and(|p| creator.is_none_or(|c| p.creator == c))
+            })
+    }
+
+    /// Show poll `msg_id` as it now stands; its tally, if it is still in
+    /// our history.
+    fn update_poll(&mut self, msg_id: u64) -> Option<PollTally> {
+        let tally = self.polls.get(&msg_id)?.tally();
+        let msg = self
+            .history
+            .iter_mut()
+            .rev()
+            .find(|m| m.poll.is_some() && m.msg_id == msg_id)?;
+        msg.poll = Some(tally.clone());
+        Some(tally)
+    }
+}
+
+/// How long each non-terminal join state may last before the flow moves on.
+const JOIN_STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Delay before the second reconnect attempt; it doubles with each one
+/// after, up to `RECONNECT_MAX_DELAY`.
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How often a status other than online is announced again, for members
+/// that missed it.
+const PRESENCE_INTERVAL: Duration = Duration::from_secs(120);
+
+/// How often we list our public rooms again, with their member counts…
+const LISTING_INTERVAL: Duration = Duration::from_secs(60);
+/// …and how long a listing not sent again stays in the directory.
+const LISTING_TTL: Duration = Duration::from_secs(180);
+/// At most one `ListingQuery` sent, and one answered, this often.
+const LISTING_QUERY_GAP: Duration = Duration::from_secs(5);
+/// Public rooms kept from the directory.
+const MAX_DIRECTORY: usize = 500;
+
+/// How long after a key rotation messages under the previous key are still
+/// read, so those sent before a member got the new key are not lost.
+const RETIRED_KEY_GRACE: Duration = Duration::from_secs(30);
+
+/// Backoff state while offline (see `App::check_reconnect`).
+struct Reconnect {
+    /// Dial rounds so far.
+    attempt: u32,
+    next_at: tokio::time::Instant,
+}
+
+/// A room join in progress; see `JoinState` for the transitions.
+struct JoinFlow {
+    room_name: String,
+    key: JoinKey,
+    /// What we are joining with, kept by the room for `/save`.
+    credentials: Credentials,
+    /// Owner's peer ID — from the room code, or the `SaltResponse`.
+    owner: String,
+    /// The owner's statement that it owns the room, from a member's code
+    /// or the member who let us in; empty if none yet.
+    owner_proof: Vec<u8>,
+    /// Another owner the member who let us in showed a statement of; see
+    /// `JoinedRoom::owner_disputed`.
+    disputed: String,
+    /// Peers being dialed: the creator from the room code plus members
+    /// found at a rendezvous point, or the members found in the DHT when
+    /// joining by name.
+    hosts: Vec<String>,
+    /// Dials still in flight; Dialing ends early only once all of them have
+    /// failed.
+    dials_pending: usize,
+    /// A rendezvous lookup is outstanding, so running out of dials does not
+    /// end Dialing yet.
+    discovering: bool,
+    /// Peers seen on the room topic, in order.
+    members: Vec<String>,
+    /// Topics peers subscribed to while we lacked the salt, and so the
+    /// room topic: (topic, peer ID).
+    subscribed: Vec<(String, String)>,
+    /// Password handshakes sent to members and not yet answered.
+    handshakes: HashMap<String, PakeHandshake>,
+    /// The room's key epoch and key, from the member whose handshake
+    /// confirmed our password; `None` at epoch 0.
+    epoch: Option<(u32, RoomKey)>,
+    state: JoinState,
+    /// When the current state times out.
+    deadline: tokio::time::Instant,
+    /// Who we join as, if not our usual identity; the room keeps it.
+    persona: Option<Identity>,
+    /// The invite grant from the token we join with, for `PakeStart`;
+    /// empty without one.
+    invite: String,
+    /// Why a member refused to check our password, if one did, and the
+    /// room's member limit if it was full.
+    refused: Option<String>,
+    full: u32,
+}
+
+/// Key of the room being joined.  A join by name has no room code to take
+/// the salt from, so it derives the key from the password in `credentials`
+/// once a member sends the salt.
+enum JoinKey {
+    Ready(RoomKey, RoomSalt),
+    AwaitingSalt,
+}
+
+impl JoinFlow {
+    fn room_key(&self) -> Option<&RoomKey> {
+        match &self.key {
+            JoinKey::Ready(key, _) => Some(key),
+            JoinKey::AwaitingSalt => None,
+        }
+    }
+
+    /// The room's topic, once we have its salt.
+    fn topic(&self) -> Option<String> {
+        match &self.key {
+            JoinKey::Ready(_, salt) => Some(topic_for_room(&self.room_name, salt)),
+            JoinKey::AwaitingSalt => None,
+        }
+    }
+
+    /// We have the room's salt and `key`: take the peers seen on its topic
+    /// meanwhile as members.
+    fn key_ready(&mut self, key: RoomKey, salt: RoomSalt) {
+        let topic = topic_for_room(&self.room_name, &salt);
+        for (_, peer) in std::mem::take(&mut self.subscribed)
+            .into_iter()
+            .filter(|(t, _)| *t == topic)
+        {
+            if !self.members.contains(&peer) {
+                self.members.push(peer);
+            }
+        }
+        self.key = JoinKey::Ready(key, salt);
+    }
+}
+
+impl App {
+    pub fn new(
+        identity: Identity,
+        config: Config,
+        net_event_rx: mpsc::UnboundedReceiver<NetworkEvent>,
+        net_cmd_tx: mpsc::UnboundedSender<NetworkCommand>,
+        cli_cmd_rx: mpsc::UnboundedReceiver<CliCommand>,
+        bus: EventBus,
+    ) -> Self {
+        let contacts = Contacts::load(config.resolve(&config.contacts_file).into());
+        let vault = Vault::load(config.resolve(&config.rooms_file).into());
+        Self {
+            identity,
+            config,
+            contacts,
+            vault,
+            vault_passphrase: None,
+            name_clashes: HashSet::new(),
+            rooms: Vec::new(),
+            active: None,
+            listen_addrs: Vec::new(),
+            nat: Reachability::Unknown,
+            join: None,
+            inbox_seen: SeenCache::default(),
+            rate: RateLimiter::default(),
+            send_limiter: Some(SendLimiter::default()),
+            capture: None,
+            skew: SkewEstimator::default(),
+            reconnect: None,
+            mentions: VecDeque::new(),
+            peers_query: None,
+            plugins: Vec::new(),
+            focused: true,
+            presence: Presence::default(),
+            auto_away: false,
+            presence_due: tokio::time::Instant::now(),
+            directory: BTreeMap::new(),
+            in_directory: false,
+            browsing: false,
+            listing_due: tokio::time::Instant::now(),
+            listing_queried: None,
+            listing_answered: None,
+            directory_seen: SeenCache::default(),
+            net_event_rx,
+            net_cmd_tx,
+            cli_cmd_rx,
+            bus,
+        }
+    }
+
+    /// Register a plugin; its hooks run after those of plugins added before.
+    pub fn with_plugin(mut self, plugin: impl Plugin + 'static) -> Self {
+        self.plugins.push(Box::new(plugin));
+        self
+    }
+
+    /// Send chat messages as fast as they are asked for, for load tests
+    /// (`chat simulate`), which would otherwise measure `SendLimiter`.
+    pub fn without_send_limit(mut self) -> Self {
+        self.send_limiter = None;
+        self
+    }
+
+    /// Hand each room message to `tx` as `NetworkEvent::RoomPlaintext` once
+    /// decrypted, so a capture (`chat --record`) can be replayed without
+    /// the room keys.
+    pub fn with_capture(mut self, tx: mpsc::UnboundedSender<NetworkEvent>) -> Self {
+        self.capture = Some(tx);
+        self
+    }
+
+    /// Unlock the room vault with its passphrase, for `/save` and `/rejoin`.
+    pub fn with_vault_passphrase(mut self, passphrase: Zeroizing<String>) -> Self {
+        self.vault_passphrase = Some(passphrase);
+        self
+    }
+
+    /// The key sealing the room vault; fails while it is locked.
+    fn vault_key(&self) -> Result<RoomKey> {
+        self.vault.key(
+            &self.identity.keypair,
+            self.vault_passphrase.as_deref().map(String::as_str),
+        )
+    }
+
+    /// Main event loop — runs until the CLI sends `Quit`.
+    pub async fn run(mut self) -> Result<()> {
+        // Ask network layer to report its listen addresses.
+        let _ = self.net_cmd_tx.send(NetworkCommand::QueryListenAddrs);
+        // Direct messages to us arrive on our own inbox topic.
+        let inbox = inbox_topic(&self.identity.peer_id.to_string());
+        let _ = self.net_cmd_tx.send(NetworkCommand::Subscribe(inbox));
+        self.emit(UiEvent::Commands(self.commands()));
+        self.emit_recent_rooms();
+        if self.identity.avatar.is_some() {
+            self.emit(UiEvent::AvatarChanged(self.identity.avatar.clone()));
+        }
+        if let Some(warning) = identity::key_file_warning(&self.config) {
+            self.emit(UiEvent::Error(warning));
+        }
+
+        loop {
+            // Join timeout check interval
+            let timeout = tokio::time::sleep(Duration::from_millis(500));
+
+            tokio::select! {
+                // CLI command from the user
+                Some(cmd) = self.cli_cmd_rx.recv() => {
+                    match self.handle_cli_command(cmd).await {
+                        Ok(true) => break,   // Quit requested
+                        Ok(false) => {}
+                        Err(e) => self.emit(UiEvent::Error(e.to_string())),
+                    }
+                }
+
+                // Event from network layer
+                Some(event) = self.net_event_rx.recv() => {
+                    if let Err(e) = self.handle_network_event(event).await {
+                        warn!("Network event error: {e}");
+                    }
+                }
+
+                // Join step timeout, reconnect backoff
+                _ = timeout => {
+                    self.check_join_timeout();
+                    self.check_reconnect();
+                    self.check_presence();
+                    self.check_polls();
+                    self.check_directory();
+                }
+            }
+        }
+
+        // Tell every room we are going and leave it, then let the network
+        // flush those messages before it stops.
+        while !self.rooms.is_empty() {
+            self.announce_leave(0);
+            self.remove_room(0);
+        }
+        let _ = self.net_cmd_tx.send(NetworkCommand::Shutdown);
+        Ok(())
+    }
+
+    // ── CLI commands ──────────────────────────────────────────────────────────
+
+    /// Returns `Ok(true)` to signal quit.
+    async fn handle_cli_command(&mut self, cmd: CliCommand) -> Result<bool> {
+        match cmd {
+            CliCommand::Quit => return Ok(true),
+
+            CliCommand::SendMessage(text) => {
+                self.send_message(text).await?;
+            }
+
+            CliCommand::CreateRoom {
+                name,
+                password,
+                persona,
+                max_members,
+            } => {
+                self.create_room(name, password, persona, max_members)
+                    .await?;
+            }
+
+            CliCommand::JoinRoom {
+                code,
+                password,
+                persona,
+            } => {
+                self.join_room(code, password, persona).await?;
+            }
+
+            CliCommand::JoinRoomByName {
+                name,
+                password,
+                persona,
+            } => {
+                self.join_room_by_name(name, password, persona).await?;
+            }
+
+            CliCommand::LeaveRoom => {
+                self.leave_room().await?;
+            }
+
+            CliCommand::SwitchRoom(name) => {
+                self.switch_room(&name)?;
+            }
+
+            CliCommand::ListRooms => {
+                let list = self
+                    .rooms
+                    .iter()
+                    .enumerate()
+                    .map(|(i, r)| format!("{}:{}", i + 1, r.state.name))
+                    .collect::<Vec<_>>()
+                    .join("  ");
+                self.notice(&format!("Rooms: {}", list));
+            }
+
+            CliCommand::ListMentions => {
+                if self.mentions.is_empty() {
+                    self.notice("No mentions yet.");
+                }
+                for (room, msg) in &self.mentions {
+                    let time = msg.timestamp.format("%H:%M");
+                    self.notice(&format!(
+                        "#{room} [{time}] {}: {}",
+                        msg.sender_label(),
+                        msg.text
+                    ));
+                }
+            }
+
+            CliCommand::ListLinks => {
+                let Some(room) = self.active_room() else {
+                    bail!("Not in a room.");
+                };
+                let found: Vec<String> = room
+                    .history
+                    .iter()
+                    .filter(|msg| !msg.is_system)
+                    .flat_map(|msg| {
+                        links::find(&msg.text)
+                            .into_iter()
+                            .map(|r| format!("{}: {}", msg.sender, &msg.text[r]))
+                    })
+                    .collect();
+                if found.is_empty() {
+                    self.notice("No links in this room yet.");
+                }
+                let from = found.len().saturating_sub(MAX_LISTED_LINKS);
+                for (n, link) in found[from..].iter().enumerate() {
+                    self.notice(&format!("[{}] {}", n + 1, link));
+                }
+            }
+
+            CliCommand::ListPeers => match self.active_room() {
+                // Connection details come from the network; list on the answer.
+                Some(room) if !room.members.is_empty() => {
+                    let peers = room.members.values().cloned().collect();
+                    self.peers_query = Some(room.state.name.clone());
+                    let _ = self.net_cmd_tx.send(NetworkCommand::QueryPeerInfo(peers));
+                }
+                _ => self.list_members(None, &[]),
+            },
+
+            CliCommand::SetPresence(presence) => {
+                self.auto_away = false;
+                self.set_presence(presence)?;
+            }
+
+            CliCommand::SetIdle(true) if self.presence.status == PresenceStatus::Online => {
+                self.auto_away = true;
+                self.set_presence(Presence {
+                    status: PresenceStatus::Away,
+                    reason: "idle".to_string(),
+                })?;
+            }
+
+            CliCommand::SetIdle(false) if std::mem::take(&mut self.auto_away) => {
+                self.set_presence(Presence::default())?;
+            }
+
+            CliCommand::SetIdle(_) => {}
+
+            CliCommand::DirectMessage { to, text } => {
+                self.send_direct(&to, text)?;
+            }
+
+            CliCommand::Verify { who, mark } => {
+                self.verify(&who, mark)?;
+            }
+
+            CliCommand::Trust(who) => {
+                self.trust(&who)?;
+            }
+
+            CliCommand::Alias { who, alias } => {
+                self.alias(&who, alias)?;
+            }
+
+            CliCommand::Block(who) => {
+                let Some(room) = self.active_room() else {
+                    bail!("Not in a room.");
+                };
+                let (name, peer_id) = room.find_member(&who)?;
+                if peer_id.parse::<PeerId>().is_err() {
+                    bail!("Peer ID of {name} is not known yet.");
+                }
+                self.config.blocked_peers.insert(peer_id, name.clone());
+                let _ = self.config.save();
+                self.notice(&format!(
+                    "Blocked {name}; their messages are dropped. /unblock {name} to undo."
+                ));
+            }
+
+            CliCommand::Unblock(who) => {
+                let blocked = &mut self.config.blocked_peers;
+                let before = blocked.len();
+                blocked.retain(|peer_id, name| {
+                    *peer_id != who
+                        && *name != who
+                        && name.split_once('#').is_none_or(|(nick, _)| nick != who)
+                });
+                if blocked.len() == before {
+                    bail!("'{who}' is not blocked.");
+                }
+                let _ = self.config.save();
+                self.notice(&format!("Unblocked {who}."));
+            }
+
+            CliCommand::ListBlocked => {
+                if self.config.blocked_peers.is_empty() {
+                    self.notice("Nobody is blocked.");
+                }
+                for (peer_id, name) in &self.config.blocked_peers {
+                    self.notice(&format!("  {} — {}", name, peer_id));
+                }
+            }
+
+            CliCommand::ListContacts => {
+                if self.contacts.iter().next().is_none() {
+                    self.notice("No contacts yet — add members with /trust or /verify.");
+                }
+                let lines: Vec<String> = self
+                    .contacts
+                    .iter()
+                    .map(|(peer_id, c)| {
+                        let badge = if c.verified { " ✔" } else { "" };
+                        let alias = c
+                            .alias
+                            .as_ref()
+                            .map(|a| format!(" \"{a}\""))
+                            .unwrap_or_default();
+                        format!("  {}{}{} — {}", c.name, badge, alias, peer_id)
+                    })
+                    .collect();
+                for line in lines {
+                    self.notice(&line);
+                }
+            }
+
+            CliCommand::React { to, emoji } => {
+                self.react(to.as_deref(), emoji)?;
+            }
+
+            CliCommand::Reply { to, text } => {
+                let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+                    bail!("Not in a room.");
+                };
+                if !self.rooms[index]
+                    .history
+                    .iter()
+                    .any(|m| !m.is_system && m.msg_id == to)
+                {
+                    bail!("That message is no longer there to reply to.");
+                }
+                wire::validate_chat(&text)?;
+                self.check_send_rate(index)?;
+                self.send_chat(index, text, to)?;
+            }
+
+            CliCommand::Poll {
+                question,
+                options,
+                minutes,
+            } => {
+                self.start_poll(question, options, minutes.unwrap_or(POLL_MINUTES))?;
+            }
+
+            CliCommand::Vote(n) => {
+                self.vote(n)?;
+            }
+
+            CliCommand::EndPoll => {
+                self.end_poll()?;
+            }
+
+            CliCommand::ShowNotes => {
+                let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+                    bail!("Not in a room.");
+                };
+                self.show_notes(index);
+            }
+
+            CliCommand::OpenThread(thread) => {
+                self.open_thread(thread)?;
+            }
+
+            CliCommand::ShowThreads => {
+                let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+                    bail!("Not in a room.");
+                };
+                self.show_threads(index);
+            }
+
+            CliCommand::EditNote(edit) => {
+                self.edit_note(edit)?;
+            }
+
+            CliCommand::History(count) => {
+                let Some(room) = self.active.clone() else {
+                    bail!("Not in a room.");
+                };
+                let count = count.unwrap_or(HISTORY_LINES).clamp(1, MAX_HISTORY_LINES);
+                let lines = logger::tail(
+                    &self.config.resolve(&self.config.log_dir),
+                    &room,
+                    count,
+                    self.config.log_format,
+                )
+                .map_err(|e| anyhow!("No log of '{room}' to read: {e}"))?;
+                self.notice(&format!(
+                    "── Last {} line(s) of the log of '{}' ──",
+                    lines.len(),
+                    room
+                ));
+                for msg in lines {
+                    self.emit(UiEvent::NewMessage(msg));
+                }
+            }
+
+            CliCommand::Export(format) => {
+                let Some(room) = self.active_room() else {
+                    bail!("Not in a room.");
+                };
+                let name = room.state.name.clone();
+                let messages: Vec<DisplayMessage> = room.history.iter().cloned().collect();
+                let path = export::write(
+                    &self.config.resolve(&self.config.log_dir),
+                    &name,
+                    &messages,
+                    format,
+                )
+                .map_err(|e| anyhow!("Export failed: {e}"))?;
+                self.notice(&format!(
+                    "Exported {} line(s) of '{}' to {}",
+                    messages.len(),
+                    name,
+                    path.display()
+                ));
+            }
+
+            CliCommand::Topic(None) => {
+                let Some(room) = self.active_room() else {
+                    bail!("Not in a room.");
+                };
+                let text = match room.state.subject.as_str() {
+                    "" => "No topic set.".to_string(),
+                    subject => format!("Topic: {}", subject),
+                };
+                self.notice(&text);
+            }
+
+            CliCommand::Topic(Some(subject)) => {
+                self.set_subject(subject)?;
+            }
+
+            CliCommand::SlowMode(None) => {
+                let Some(room) = self.active_room() else {
+                    bail!("Not in a room.");
+                };
+                let text = match room.slow_mode {
+                    0 => "Slow mode is off.".to_string(),
+                    secs => format!("Slow mode: members wait {secs}s between messages."),
+                };
+                self.notice(&text);
+            }
+
+            CliCommand::SlowMode(Some(secs)) => {
+                self.set_slow_mode(secs)?;
+            }
+
+            CliCommand::Announcement(None) => {
+                let Some(room) = self.active_room() else {
+                    bail!("Not in a room.");
+                };
+                let speakers: Vec<String> = room
+                    .announcement
+                    .speakers
+                    .iter()
+                    .map(|p| room.name_of(p))
+                    .collect();
+                let speakers = match speakers.as_slice() {
+                    [] => "no speakers".to_string(),
+                    names => format!("speakers: {}", names.join(", ")),
+                };
+                let text = match room.announcement.on {
+                    true => format!(
+                        "Announcement room: only the owner and speakers write here ({speakers})."
+                    ),
+                    false => format!("Everyone writes here ({speakers} for announcement mode)."),
+                };
+                self.notice(&text);
+            }
+
+            CliCommand::Announcement(Some(on)) => {
+                self.set_announcement(|a| a.on = on)?;
+            }
+
+            CliCommand::Speaker { who, allow } => {
+                let Some(room) = self.active_room() else {
+                    bail!("Not in a room.");
+                };
+                let (name, peer_id) = room.find_member(&who)?;
+                if peer_id.is_empty() {
+                    bail!("Peer ID of {name} is not known yet.");
+                }
+                self.set_announcement(|a| {
+                    a.speakers.retain(|p| *p != peer_id);
+                    if allow {
+                        a.speakers.push(peer_id);
+                    }
+                })?;
+            }
+
+            CliCommand::MintInvite { hours, single_use } => {
+                self.mint_invite(hours, single_use)?;
+            }
+
+            CliCommand::InviteOnly(None) => {
+                let Some(room) = self.active_room() else {
+                    bail!("Not in a room.");
+                };
+                let name = &room.state.name;
+                let text = match room.invite_only {
+                    true => format!("'{name}' takes joiners with an invite token only."),
+                    false => format!(
+                        "'{name}' takes anyone with its code and password; /token mints invites that expire."
+                    ),
+                };
+                self.notice(&text);
+            }
+
+            CliCommand::InviteOnly(Some(on)) => {
+                self.set_invite_only(on)?;
+            }
+
+            CliCommand::Capacity(None) => {
+                let Some(room) = self.active_room() else {
+                    bail!("Not in a room.");
+                };
+                let name = &room.state.name;
+                let text = match room.max_members {
+                    0 => format!("'{name}' has no member limit."),
+                    max => format!(
+                        "'{name}' holds at most {max} members; {} now.",
+                        room.members.len() + 1
+                    ),
+                };
+                self.notice(&text);
+            }
+
+            CliCommand::ListPublic(description) => {
+                self.list_public(description)?;
+            }
+
+            CliCommand::BrowseRooms => {
+                self.join_directory();
+                self.browsing = true;
+                self.emit_directory();
+                self.query_directory();
+            }
+
+            CliCommand::Capacity(Some(max)) => {
+                let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+                    bail!("Not in a room.");
+                };
+                self.set_capacity(index, max)?;
+            }
+
+            CliCommand::Kick(who) => {
+                self.moderate(&who, WireMessageType::Kick)?;
+            }
+
+            CliCommand::Ban(who) => {
+                self.moderate(&who, WireMessageType::Ban)?;
+            }
+
+            CliCommand::GrantAdmin { who, admin } => {
+                self.set_admin(&who, admin)?;
+            }
+
+            CliCommand::TransferOwnership(who) => {
+                self.transfer_ownership(&who)?;
+            }
+
+            CliCommand::Whois(who) => {
+                self.whois(&who)?;
+            }
+
+            CliCommand::Rekey => {
+                let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+                    bail!("Not in a room.");
+                };
+                if !self.rooms[index].is_owner(Some(self.me(index).peer_id)) {
+                    bail!("Only the room owner can rotate the room key.");
+                }
+                self.rekey(index);
+            }
+
+            CliCommand::RenameRoom(name) => {
+                self.rename_room(&name)?;
+            }
+
+            CliCommand::ShowCode => {
+                let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+                    bail!("Not in a room.");
+                };
+                let name = self.rooms[index].state.name.clone();
+                let code = self
+                    .active_code_data(index)?
+                    .encode(&self.identity.keypair)?;
+                self.emit(UiEvent::RoomCode { name, code });
+            }
+
+            CliCommand::Invite => {
+                let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+                    bail!("Not in a room.");
+                };
+                let name = self.rooms[index].state.name.clone();
+                let data = self.active_code_data(index)?;
+                if data.addrs.is_empty() {
+                    bail!("Not listening on any address yet; try again in a moment.");
+                }
+                let relayed = data
+                    .addrs
+                    .iter()
+                    .filter(|a| a.contains("/p2p-circuit"))
+                    .count();
+                let code = data.encode(&self.identity.keypair)?;
+                self.notice(&format!(
+                    "A fresh code to '{name}', leading to you at {} address(es), {relayed} relayed — share it with the password:",
+                    data.addrs.len()
+                ));
+                self.emit(UiEvent::RoomCode { name, code });
+            }
+
+            CliCommand::SaveRoom => {
+                let Some(room) = self.active_room() else {
+                    bail!("Not in a room.");
+                };
+                let name = room.state.name.clone();
+                // A code of our own leads to wherever we are now.
+                let code = if room.owner == self.identity.peer_id.to_string() {
+                    self.room_code(&name, room.salt, "")
+                } else {
+                    room.credentials.code.clone()
+                };
+                let credentials = Credentials {
+                    code,
+                    password: room.credentials.password.clone(),
+                };
+                let key = self.vault_key()?;
+                self.vault.store(&key, &name, &credentials)?;
+                self.emit_recent_rooms();
+                self.notice(&format!(
+                    "Saved '{name}' — /rejoin {name} enters it again without code or password."
+                ));
+            }
+
+            CliCommand::Rejoin(None) => {
+                let names: Vec<&str> = self.vault.names().map(String::as_str).collect();
+                if names.is_empty() {
+                    self.notice("No saved rooms. /save in a room keeps its code and password.");
+                } else {
+                    self.notice(&format!("Saved rooms: {}", names.join(", ")));
+                }
+            }
+
+            CliCommand::Rejoin(Some(name)) => {
+                let key = self.vault_key()?;
+                let Some(mut saved) = self
+                    .vault
+                    .open(&key, &name)
+                    .context("Cannot open the saved room")?
+                else {
+                    bail!("No saved room '{name}'.");
+                };
+                let password = std::mem::take(&mut *saved.password);
+                if saved.code.is_empty() {
+                    self.join_room_by_name(name, password, None).await?;
+                } else {
+                    self.join_room(saved.code, password, None).await?;
+                }
+            }
+
+            CliCommand::ForgetRoom(name) => {
+                if !self.vault.forget(&name)? {
+                    bail!("No saved room '{name}'.");
+                }
+                self.emit_recent_rooms();
+                self.notice(&format!("Forgot the code and password of '{name}'."));
+            }
+
+            CliCommand::ForgetRecent(name) => {
+                self.config.recent_rooms.retain(|r| r.name != name);
+                let _ = self.config.save();
+                self.emit_recent_rooms();
+            }
+
+            CliCommand::ReloadConfig => self.reload_config(),
+
+            CliCommand::ToggleMute => {
+                let Some(room) = self.active.clone() else {
+                    bail!("Not in a room.");
+                };
+                let muted = &mut self.config.muted_rooms;
+                let now_muted = match muted.iter().position(|r| *r == room) {
+                    Some(i) => {
+                        muted.remove(i);
+                        false
+                    }
+                    None => {
+                        muted.push(room.clone());
+                        true
+                    }
+                };
+                let _ = self.config.save();
+                let state = if now_muted { "muted" } else { "unmuted" };
+                self.notice(&format!("Notifications for '{}' {}.", room, state));
+            }
+
+            CliCommand::SetFocus(focused) => {
+                self.focused = focused;
+            }
+
+            CliCommand::ChangeNickname(new_nick) => {
+                let new_nick: String = new_nick.trim().chars().take(MAX_NICK_LEN).collect();
+                if let Err(e) = wire::validate_nickname(&new_nick) {
+                    self.emit(UiEvent::Error(format!("Invalid nickname: {e}.")));
+                } else {
+                    self.identity.nickname = new_nick.clone();
+                    self.config.nickname = Some(new_nick.clone());
+                    let _ = self.config.save();
+                    self.emit(UiEvent::NicknameChanged(new_nick));
+                }
+            }
+
+            CliCommand::ChangeAvatar(avatar) => {
+                let avatar = avatar.trim();
+                let avatar = (!avatar.is_empty()).then(|| avatar.to_string());
+                match avatar.as_deref().map(wire::validate_avatar) {
+                    Some(Err(e)) => self.emit(UiEvent::Error(format!("Invalid avatar: {e}."))),
+                    _ => {
+                        self.identity.avatar = avatar.clone();
+                        self.config.avatar = avatar.clone();
+                        let _ = self.config.save();
+                        self.emit(UiEvent::AvatarChanged(avatar));
+                        self.emit_status();
+                    }
+                }
+            }
+
+            CliCommand::Plugin { name, args } => {
+                if !self.call_plugins(|p, ctx| p.on_command(ctx, &name, &args))? {
+                    self.emit(UiEvent::ShowHelp);
+                }
+            }
+
+            CliCommand::Help => self.emit(UiEvent::ShowHelp),
+        }
+        Ok(false)
+    }
+
+    /// Every command, ours then those the plugins add, as (usage, what it
+    /// does).
+    fn commands(&self) -> Vec<(String, String)> {
+        let ours = COMMANDS
+            .iter()
+            .map(|(usage, about)| (usage.to_string(), about.to_string()));
+        let theirs =
+            self.plugins
+                .iter()
+                .flat_map(|p| p.help())
+                .map(|line| match line.split_once(" — ") {
+                    Some((usage, about)) => (usage.trim().to_string(), about.trim().to_string()),
+                    None => (line, String::new()),
+                });
+        ours.chain(theirs).collect()
+    }
+
+    // ── Room operations ───────────────────────────────────────────────────────
+
+    async fn create_room(
+        &mut self,
+        name: String,
+        password: String,
+        persona: Option<Persona>,
+        max_members: u32,
+    ) -> Result<()> {
+        let password = Zeroizing::new(password);
+        let name = normalize_room_name(&name)?;
+        if self.room_index(&name).is_some() {
+            return self.switch_room(&name);
+        }
+        let persona = self.take_persona(&name, persona, true)?;
+
+        let salt = random_salt();
+        let room_key = RoomKey::derive(&password, &salt)?;
+        let topic = topic_for_room(&name, &salt);
+
+        // Subscribe to the GossipSub topic.
+        let _ = self
+            .net_cmd_tx
+            .send(NetworkCommand::Subscribe(topic.clone()));
+
+        let code = self.room_code(&name, salt, "");
+        self.remember_room(&name, &code);
+
+        // Let others find the room by name through the DHT, and joiners
+        // with this code find us through rendezvous once we moved.
+        self.announce_room(&name);
+
+        // Update state.
+        let owner = self.identity.peer_id.to_string();
+        let mut room = JoinedRoom::new(RoomState::new(&name, &salt), room_key, salt, owner);
+        room.credentials.password = password;
+        room.persona = persona;
+        self.rooms.push(room);
+        self.active = Some(name.clone());
+
+        self.bus.publish(AppEvent::RoomEntered(name.clone()));
+        self.emit(UiEvent::RoomCreated {
+            name: name.clone(),
+            code,
+        });
+        self.note_persona(self.rooms.len() - 1);
+        if max_members != 0 {
+            self.set_capacity(self.rooms.len() - 1, max_members)?;
+        }
+
+        self.emit_status();
+        Ok(())
+    }
+
+    /// A code for room `name` that leads joiners to us, with our best QUIC
+    /// and TCP addresses, signed with our identity key.  It names `owner`
+    /// as the room's owner if that is not us (empty: unknown, so us).
+    fn room_code(&self, name: &str, salt: RoomSalt, owner: &str) -> String {
+        self.code_data(name, salt, owner)
+            .encode(&self.identity.keypair)
+            .unwrap_or_default()
+    }
+
+    /// What a code to room `index` leading to us encodes.  A member's code
+    /// names the owner, and needs the owner's statement that it owns the
+    /// room to do so.
+    fn active_code_data(&self, index: usize) -> Result<RoomCodeData> {
+        let room = &self.rooms[index];
+        let mut data = self.code_data(&room.state.name, room.salt, &room.owner);
+        if !data.owner.is_empty() {
+            let Some(proof) = self.ownership(index) else {
+                bail!(
+                    "You have no word from the owner of '{}' that it owns the room, which a member's code must carry; ask the owner for the code.",
+                    room.state.name
+                );
+            };
+            data.owner_proof = proof;
+        }
+        Ok(data)
+    }
+
+    /// The owner's statement that it owns room `index`
+    /// (`room::sign_ownership`): made afresh if that is us, else the one a
+    /// member or code handed us, if it holds for the current owner and name.
+    fn ownership(&self, index: usize) -> Option<Vec<u8>> {
+        let room = &self.rooms[index];
+        let me = self.me(index);
+        if room.is_owner(Some(me.peer_id)) {
+            return sign_ownership(&me.keypair, &room.state.name, &room.salt).ok();
+        }
+        verify_ownership(&room.owner, &room.state.name, &room.salt, &room.owner_proof)
+            .then(|| room.owner_proof.clone())
+    }
+
+    /// What `room_code` encodes: the addresses we listen on and are relayed
+    /// at as of now.
+    fn code_data(&self, name: &str, salt: RoomSalt, owner: &str) -> RoomCodeData {
+        let peer_id = self.identity.peer_id.to_string();
+        RoomCodeData {
+            room_name: name.to_string(),
+            addrs: code_addrs(&self.listen_addrs, &peer_id, &self.nat),
+            owner: if owner == peer_id {
+                String::new()
+            } else {
+                owner.to_string()
+            },
+            peer_id,
+            salt,
+            owner_proof: Vec::new(),
+            signed: false,
+        }
+    }
+
+    async fn join_room(
+        &mut self,
+        code: String,
+        password: String,
+        persona: Option<Persona>,
+    ) -> Result<()> {
+        let password = Zeroizing::new(password);
+        let (room_code, invite) = split_invite(&code)?;
+        let code_data = RoomCodeData::decode(room_code)?;
+        let room_name = code_data.room_name.clone();
+        if self.room_index(&room_name).is_some() {
+            return self.switch_room(&room_name);
+        }
+        if let Some(room) = self
+            .rooms
+            .iter()
+            .find(|r| r.salt == code_data.salt && r.former_names.contains(&room_name))
+        {
+            let name = room.state.name.clone();
+            self.notice(&format!(
+                "This code is stale: '{room_name}' was renamed to '{name}'."
+            ));
+            return self.switch_room(&name);
+        }
+        if let Some(invite) = &invite {
+            if !invite.verify(code_data.owner(), &room_name, &code_data.salt) {
+                bail!("This invite is not valid for '{room_name}'.");
+            }
+            if invite.expires_ms <= Utc::now().timestamp_millis() {
+                let expired =
+                    DateTime::from_timestamp_millis(invite.expires_ms).unwrap_or_default();
+                bail!(
+                    "This invite to '{room_name}' expired at {} UTC.",
+                    expired.format("%Y-%m-%d %H:%M")
+                );
+            }
+        }
+        let invite = invite.map(|i| i.encode()).unwrap_or_default();
+        let room_key = RoomKey::derive(&password, &code_data.salt)?;
+        if !code_data.signed {
+            self.notice("This room code is unsigned (made by an older version), so the owner it names cannot be checked.");
+        }
+        let ours = code_data.owner() == self.identity.peer_id.to_string();
+        let persona = self.take_persona(&room_name, persona, ours)?;
+
+        self.start_join(JoinFlow {
+            room_name,
+            key: JoinKey::Ready(room_key, code_data.salt),
+            credentials: Credentials { code, password },
+            owner: code_data.owner().to_string(),
+            owner_proof: code_data.owner_proof.clone(),
+            disputed: String::new(),
+            hosts: vec![code_data.peer_id.clone()],
+            dials_pending: code_data.addrs.len(),
+            discovering: true,
+            members: Vec::new(),
+            subscribed: Vec::new(),
+            handshakes: HashMap::new(),
+            epoch: None,
+            state: JoinState::Dialing,
+            deadline: tokio::time::Instant::now() + JOIN_STEP_TIMEOUT,
+            persona,
+            invite,
+            refused: None,
+            full: 0,
+        });
+
+        // Dial the room creator on every address we have (QUIC first), and
+        // ask the rendezvous points for current members, as the creator may
+        // have moved or left.
+        for addr in &code_data.addrs {
+            let _ = self.net_cmd_tx.send(NetworkCommand::Dial(addr.clone()));
+        }
+        let namespace = rendezvous_namespace(&code_data.room_name);
+        let _ = self.net_cmd_tx.send(NetworkCommand::Discover(namespace));
+        self.set_join_state(JoinState::Dialing);
+
+        Ok(())
+    }
+
+    /// Join without a code: look up the room's members in the DHT, dial them
+    /// as `join_room` would dial the creator, and ask them for the room salt
+    /// (`SaltRequest`) once their inbox topics show up.
+    async fn join_room_by_name(
+        &mut self,
+        name: String,
+        password: String,
+        persona: Option<Persona>,
+    ) -> Result<()> {
+        let room_name = normalize_room_name(&name)?;
+        if self.room_index(&room_name).is_some() {
+            return self.switch_room(&room_name);
+        }
+        let key = discovery_key(&room_name);
+        let persona = self.take_persona(&room_name, persona, false)?;
+
+        self.start_join(JoinFlow {
+            room_name,
+            key: JoinKey::AwaitingSalt,
+            credentials: Credentials {
+                code: String::new(),
+                password: Zeroizing::new(password),
+            },
+            owner: String::new(),
+            owner_proof: Vec::new(),
+            disputed: String::new(),
+            hosts: Vec::new(),
+            dials_pending: 0,
+            discovering: false,
+            members: Vec::new(),
+            subscribed: Vec::new(),
+            handshakes: HashMap::new(),
+            epoch: None,
+            state: JoinState::Resolving,
+            deadline: tokio::time::Instant::now() + JOIN_STEP_TIMEOUT,
+            persona,
+            invite: String::new(),
+            refused: None,
+            full: 0,
+        });
+        let _ = self.net_cmd_tx.send(NetworkCommand::FindProviders(key));
+        self.set_join_state(JoinState::Resolving);
+        Ok(())
+    }
+
+    /// Put room `name`, entered with `code`, at the top of the recent rooms
+    /// list and save it.
+    fn remember_room(&mut self, name: &str, code: &str) {
+        let recent = &mut self.config.recent_rooms;
+        recent.retain(|r| r.name != name);
+        recent.insert(
+            0,
+            RecentRoom {
+                name: name.to_string(),
+                code: code.to_string(),
+                last_joined: chrono::Utc::now(),
+                saved: false,
+            },
+        );
+        recent.truncate(MAX_RECENT_ROOMS);
+        let _ = self.config.save();
+        self.emit_recent_rooms();
+    }
+
+    /// Read the config file again.  What the running app can use takes
+    /// effect at once, here and through `SettingsChanged` in the other
+    /// tasks; the rest is kept in the file for the next start, and named.
+    fn reload_config(&mut self) {
+        let new = match self.config.reload() {
+            Ok(config) => config,
+            Err(e) => {
+                self.emit(UiEvent::Error(format!("Config not reloaded: {e:#}.")));
+                return;
+            }
+        };
+        if let Err(e) = new.ensure_log_dir() {
+            self.emit(UiEvent::Error(format!(
+                "Config not reloaded: cannot create {}: {e}.",
+                new.log_dir
+            )));
+            return;
+        }
+        let changes = self.config.changes(&new);
+        self.config = new;
+        self.bus.publish(AppEvent::SettingsChanged {
+            theme: self.config.theme.clone(),
+            format_messages: self.config.format_messages,
+            log_dir: self.config.resolve(&self.config.log_dir),
+            log_format: self.config.log_format,
+        });
+        if changes.applied.contains(&"recent_rooms") {
+            self.emit_recent_rooms();
+        }
+        let path = self.config.path.display();
+        if changes == ConfigChanges::default() {
+            self.notice(&format!("Reloaded {path}: nothing changed."));
+            return;
+        }
+        self.notice(&format!("Reloaded {path}."));
+        if !changes.applied.is_empty() {
+            self.notice(&format!("Applied: {}.", changes.applied.join(", ")));
+        }
+        if !changes.restart.is_empty() {
+            self.notice(&format!("Takes a restart: {}.", changes.restart.join(", ")));
+        }
+    }
+
+    /// Send the recent rooms to the UI, marking those in the vault.
+    fn emit_recent_rooms(&self) {
+        let saved: HashSet<&String> = self.vault.names().collect();
+        let recent = self
+            .config
+            .recent_rooms
+            .iter()
+            .map(|r| RecentRoom {
+                saved: saved.contains(&r.name),
+                ..r.clone()
+            })
+            .collect();
+        self.emit(UiEvent::RecentRooms(recent));
+    }
+
+    /// Make room `name` findable: by name in the DHT, and by its code at
+    /// the rendezvous points.
+    fn announce_room(&self, name: &str) {
+        let _ = self
+            .net_cmd_tx
+            .send(NetworkCommand::StartProviding(discovery_key(name)));
+        let _ = self
+            .net_cmd_tx
+            .send(NetworkCommand::Register(rendezvous_namespace(name)));
+    }
+
+    /// Only one join at a time; a new one replaces any unfinished flow.
+    /// The room topic is only subscribed once a flow ends, so there is
+    /// nothing to undo.
+    fn start_join(&mut self, flow: JoinFlow) {
+        if let Some(old) = self.join.replace(flow) {
+            self.drop_persona(old.persona);
+        }
+    }
+
+    /// The persona to be in room `name`: `persona` if given, which is
+    /// remembered, else the one from last time; `None` for our usual
+    /// identity.  In rooms we `own` only the nickname changes: their code
+    /// names our usual key as the owner.
+    fn take_persona(
+        &mut self,
+        name: &str,
+        persona: Option<Persona>,
+        own: bool,
+    ) -> Result<Option<Identity>> {
+        if let Some(persona) = persona {
+            if let Some(nick) = &persona.nickname
+                && let Err(e) = wire::validate_nickname(nick)
+            {
+                bail!("Invalid nickname: {e}.");
+            }
+            if persona.is_usual() {
+                self.config.personas.remove(name);
+            } else {
+                self.config.personas.insert(name.to_string(), persona);
+            }
+            let _ = self.config.save();
+        }
+        let Some(mut persona) = self.config.personas.get(name).cloned() else {
+            return Ok(None);
+        };
+        if own && persona.own_key {
+            persona.own_key = false;
+            self.notice("Your own room keeps your usual key, which its code names as the owner; only the nickname changes.");
+        }
+        if persona.is_usual() {
+            return Ok(None);
+        }
+        let identity = self.identity.persona(&persona);
+        if identity.peer_id != self.identity.peer_id {
+            let inbox = inbox_topic(&identity.peer_id.to_string());
+            let _ = self.net_cmd_tx.send(NetworkCommand::Subscribe(inbox));
+        }
+        Ok(Some(identity))
+    }
+
+    /// Stop listening for direct messages to a persona we are done with.
+    fn drop_persona(&self, persona: Option<Identity>) {
+        if let Some(persona) = persona.filter(|p| p.peer_id != self.identity.peer_id) {
+            let inbox = inbox_topic(&persona.peer_id.to_string());
+            let _ = self.net_cmd_tx.send(NetworkCommand::Unsubscribe(inbox));
+        }
+    }
+
+    /// Tell the user who they are in room `index`, if not their usual self.
+    fn note_persona(&mut self, index: usize) {
+        if self.rooms[index].persona.is_some() {
+            let room_name = self.rooms[index].state.name.clone();
+            let text = format!(
+                "In '{room_name}' you are {}.",
+                self.me(index).display_name()
+            );
+            self.record(&room_name, DisplayMessage::system(&text));
+        }
+    }
+
+    /// Who we are in room `index`: its persona, or our usual identity.
+    fn me(&self, index: usize) -> &Identity {
+        self.rooms[index].persona.as_ref().unwrap_or(&self.identity)
+    }
+
+    /// Who we are in the room being joined.
+    fn joining_as(&self) -> &Identity {
+        self.join
+            .as_ref()
+            .and_then(|j| j.persona.as_ref())
+            .unwrap_or(&self.identity)
+    }
+
+    /// Leave the active room and show the next remaining one (or the menu).
+    async fn leave_room(&mut self) -> Result<()> {
+        if let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) {
+            self.announce_leave(index);
+            self.remove_room(index);
+        }
+        self.active = None;
+        self.show_next_room()
+    }
+
+    /// Drop room `index` from our state and the network, without announcing.
+    fn remove_room(&mut self, index: usize) {
+        if let Some(room) = self.rooms.get(index).filter(|r| r.listing.is_some()) {
+            let name = room.state.name.clone();
+            if let Err(e) = self.publish_directory(WireMessageType::Unlisting, name) {
+                debug!("Cannot unlist room: {e}");
+            }
+        }
+        let mut room = self.rooms.remove(index);
+        self.drop_persona(room.persona.take());
+        let _ = self
+            .net_cmd_tx
+            .send(NetworkCommand::Unsubscribe(room.state.topic.clone()));
+        let _ = self
+            .net_cmd_tx
+            .send(NetworkCommand::StopProviding(discovery_key(
+                &room.state.name,
+            )));
+        let _ = self
+            .net_cmd_tx
+            .send(NetworkCommand::Unregister(rendezvous_namespace(
+                &room.state.name,
+            )));
+        info!("Left room '{}'", room.state.name);
+        self.bus.publish(AppEvent::RoomLeft(room.state.name));
+    }
+
+    /// Owner only: give the active room the name `name`.  The `Rename`
+    /// goes out on the old topic with a fresh key, so only members can
+    /// follow it; then we move as they do.
+    fn rename_room(&mut self, name: &str) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        let room = &self.rooms[index];
+        if !room.is_owner(Some(self.me(index).peer_id)) {
+            bail!("Only the room owner can rename the room.");
+        }
+        let name = normalize_room_name(name)?;
+        if name == room.state.name {
+            bail!("The room is called '{name}' already.");
+        }
+        if self.room_index(&name).is_some() {
+            bail!("You are in a room called '{name}' already.");
+        }
+        let rename = RoomRekey {
+            owner_proof: B64.encode(sign_ownership(&self.me(index).keypair, &name, &room.salt)?),
+            room: name,
+            epoch: room.epoch + 1,
+            key: B64.encode(RoomKey::generate().to_bytes()),
+        };
+        let text = serde_json::to_string(&rename)?;
+        let (_, raw) = self.signed_wire(self.me(index), WireMessageType::Rename, text)?;
+        self.publish_wire(index, &raw)?;
+        self.apply_rename(index, &wire::decode(&raw)?)
+    }
+
+    /// Move room `index` to the name in the owner's `Rename` message
+    /// `wire`: to the new name's topic and the key sent along, with its
+    /// log, settings and saved credentials.  Codes naming the old name
+    /// are stale, so the one we entered with is dropped.
+    fn apply_rename(&mut self, index: usize, wire: &WireMessage) -> Result<()> {
+        let rename = wire::decode_rename(&wire.text)?;
+        let old = self.rooms[index].state.name.clone();
+        let new = rename.room;
+        if new == old {
+            return Ok(());
+        }
+        if self.room_index(&new).is_some() {
+            // Two rooms cannot go by one name here.
+            self.announce_leave(index);
+            self.remove_room(index);
+            if self.active.as_deref() == Some(old.as_str()) {
+                self.active = None;
+                self.show_next_room()?;
+            } else {
+                self.emit_status();
+            }
+            self.notice(&format!(
+                "'{old}' was renamed to '{new}', like another room you are in, so you left it."
+            ));
+            return Ok(());
+        }
+        let key = Zeroizing::new(B64.decode(&rename.key)?);
+        let key = RoomKey::from_bytes(&key)?;
+        let ours = self.rooms[index].is_owner(Some(self.me(index).peer_id));
+        let room = &mut self.rooms[index];
+        let old_topic = std::mem::replace(&mut room.state.topic, topic_for_room(&new, &room.salt));
+        room.state.name = new.clone();
+        room.former_names.push(old.clone());
+        if rename.epoch > room.epoch {
+            room.install_key(rename.epoch, key);
+        }
+        room.credentials.code.clear();
+        room.owner_proof = B64.decode(&rename.owner_proof).unwrap_or_default();
+        let (salt, topic, listed) = (room.salt, room.state.topic.clone(), room.listing.is_some());
+
+        let _ = self.net_cmd_tx.send(NetworkCommand::Unsubscribe(old_topic));
+        let _ = self
+            .net_cmd_tx
+            .send(NetworkCommand::StopProviding(discovery_key(&old)));
+        let _ = self
+            .net_cmd_tx
+            .send(NetworkCommand::Unregister(rendezvous_namespace(&old)));
+        let _ = self.net_cmd_tx.send(NetworkCommand::Subscribe(topic));
+        self.announce_room(&new);
+        if listed {
+            if let Err(e) = self.publish_directory(WireMessageType::Unlisting, old.clone()) {
+                debug!("Cannot unlist room: {e}");
+            }
+            if let Err(e) = self.publish_listing(index) {
+                debug!("Cannot list room: {e}");
+            }
+        }
+        // The logger closes the old file and goes on in the new one.
+        self.bus.publish(AppEvent::RoomLeft(old.clone()));
+        self.bus.publish(AppEvent::RoomEntered(new.clone()));
+        let code = if ours {
+            self.room_code(&new, salt, "")
+        } else {
+            String::new()
+        };
+        self.rename_records(&old, &new, &code);
+        if self.active.as_deref() == Some(old.as_str()) {
+            self.active = Some(new.clone());
+        }
+        for (room, _) in self.mentions.iter_mut().filter(|(room, _)| *room == old) {
+            room.clone_from(&new);
+        }
+        self.emit(UiEvent::RoomRenamed {
+            from: old.clone(),
+            to: new.clone(),
+        });
+        let text = format!(
+            "The room owner renamed '{old}' to '{new}'; codes for the old name no longer lead here"
+        );
+        self.record(&new, DisplayMessage::system(&text));
+        self.emit_status();
+        Ok(())
+    }
+
+    /// Carry what we keep about room `old` over to `new`: its place in
+    /// the recent rooms, whether it is muted, its persona and its saved
+    /// credentials, with `code` (empty: none) for the stale code.
+    fn rename_records(&mut self, old: &str, new: &str, code: &str) {
+        let config = &mut self.config;
+        for recent in config.recent_rooms.iter_mut().filter(|r| r.name == old) {
+            recent.name = new.to_string();
+            recent.code = code.to_string();
+        }
+        for muted in config.muted_rooms.iter_mut().filter(|r| *r == old) {
+            *muted = new.to_string();
+        }
+        if let Some(persona) = config.personas.remove(old) {
+            config.personas.insert(new.to_string(), persona);
+        }
+        let _ = config.save();
+        // Only ask for the key when there is an entry: a locked vault
+        // holding nothing for this room is not worth a warning.
+        let moved = if self.vault.names().any(|name| name == old) {
+            self.vault_key().and_then(|key| {
+                let Some(mut saved) = self.vault.open(&key, old)? else {
+                    return Ok(());
+                };
+                saved.code = code.to_string();
+                self.vault.store(&key, new, &saved)?;
+                self.vault.forget(old).map(drop)
+            })
+        } else {
+            Ok(())
+        };
+        if let Err(e) = moved {
+            warn!("Cannot move the saved room '{old}' to '{new}': {e}");
+        }
+        self.emit_recent_rooms();
+    }
+
+    /// With no active room, show the first remaining one (or the menu).
+    fn show_next_room(&mut self) -> Result<()> {
+        match self.rooms.first().map(|r| r.state.name.clone()) {
+            Some(next) => self.switch_room(&next)?,
+            None => {
+                self.emit(UiEvent::ShowMainMenu);
+                self.emit_status();
+            }
+        }
+        Ok(())
+    }
+
+    /// Make `name` the active room and hand its history to the UI.
+    fn switch_room(&mut self, name: &str) -> Result<()> {
+        let Some(index) = self.room_index(name) else {
+            bail!("Not in a room called '{name}'.");
+        };
+        self.active = Some(name.to_string());
+        let history = self.rooms[index].view();
+        self.emit(UiEvent::RoomSwitched {
+            name: name.to_string(),
+            history,
+        });
+        self.emit_status();
+        Ok(())
+    }
+
+    // ── Message sending ───────────────────────────────────────────────────────
+
+    async fn send_message(&mut self, text: String) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            self.emit(UiEvent::Error("Not in a room.".to_string()));
+            return Ok(());
+        };
+
+        wire::validate_chat(&text)?;
+        self.check_send_rate(index)?;
+        self.send_chat(index, text, 0)
+    }
+
+    /// Refuse to send to room `index` if it is an announcement room we may
+    /// not write in, or yet if its slow mode (which does not bind the
+    /// owner) or our own rate limit says to wait, saying how long.
+    fn check_send_rate(&mut self, index: usize) -> Result<()> {
+        let now = Utc::now().timestamp_millis();
+        let room = &self.rooms[index];
+        if !room.may_speak(Some(self.me(index).peer_id)) {
+            bail!(
+                "'{}' is an announcement room: only the owner and speakers write here.",
+                room.state.name
+            );
+        }
+        if room.slow_mode > 0 && !room.is_admin(Some(self.me(index).peer_id)) {
+            let wait = room.last_sent_ms + i64::from(room.slow_mode) * 1000 - now;
+            if wait > 0 {
+                bail!(
+                    "Slow mode is on in '{}': wait {}s before sending again.",
+                    room.state.name,
+                    (wait + 999) / 1000
+                );
+            }
+        }
+        if let Some(limiter) = &mut self.send_limiter
+            && let Err(wait) = limiter.check(now)
+        {
+            bail!(
+                "You are sending too fast: wait {}s.",
+                wait.as_millis().div_ceil(1000)
+            );
+        }
+        self.rooms[index].last_sent_ms = now;
+        Ok(())
+    }
+
+    /// Publish a chat message from us to room `index`, answering the
+    /// message with ID `reply_to` unless it is 0, and show it.  Long text
+    /// goes out in parts (`Chunk`), reassembled by the receivers.
+    fn send_chat(&mut self, index: usize, text: String, reply_to: u64) -> Result<()> {
+        let room_name = self.rooms[index].state.name.clone();
+        let parts = wire::split_chat(&text);
+        let total = u16::try_from(parts.len())?;
+        let mut msg_id = rand::random();
+        for (part_index, part) in (0..).zip(&parts) {
+            let mut wire =
+                self.wire_message(self.me(index), WireMessageType::Chat, part.to_string());
+            wire.reply_to = reply_to;
+            wire.thread = self.rooms[index].thread.clone().unwrap_or_default();
+            if total > 1 {
+                wire.chunk = Some(Chunk {
+                    id: msg_id,
+                    index: part_index,
+                    total,
+                });
+            }
+            let (part_id, wire) = self.sign_and_encode(self.me(index), wire)?;
+            if total == 1 {
+                msg_id = part_id;
+            }
+            self.publish_wire(index, &wire)?;
+            self.rooms[index].hold_for_absent(&wire);
+        }
+
+        // Show our own message locally immediately.
+        let me = self.me(index);
+        let mut msg = DisplayMessage::chat(&me.display_name(), &text)
+            .sent(msg_id)
+            .with_avatar(me.avatar.clone())
+            .in_thread(self.rooms[index].thread.clone());
+        if reply_to != 0 {
+            msg = msg.with_quote(self.rooms[index].quote(reply_to));
+        }
+        self.record(&room_name, msg);
+
+        Ok(())
+    }
+
+    /// Show the safety number shared with a member of the active room, or
+    /// record that the user compared it (`mark`).
+    fn verify(&mut self, who: &str, mark: Option<bool>) -> Result<()> {
+        let Some(room) = self.active_room() else {
+            bail!("Not in a room.");
+        };
+        let (name, peer_id) = room.find_member(who)?;
+        let peer: PeerId = peer_id
+            .parse()
+            .map_err(|_| anyhow!("Peer ID of {name} is not known yet."))?;
+        match mark {
+            None => {
+                let own = room.persona.as_ref().unwrap_or(&self.identity).peer_id;
+                let number = crypto::safety_number(&own, &peer)?;
+                let state = if self.contacts.is_verified(&peer_id) {
+                    "verified"
+                } else {
+                    "not verified"
+                };
+                self.notice(&format!("Safety number with {name} ({state}):"));
+                self.notice(&format!("  {number}"));
+                self.notice(&format!(
+                    "Compare it with {name} out-of-band, then: /verify {name} confirm"
+                ));
+            }
+            Some(verified) => {
+                self.contacts.set_verified(&peer_id, &name, verified)?;
+                let state = if verified {
+                    "marked as verified"
+                } else {
+                    "no longer verified"
+                };
+                self.notice(&format!("{name} is {state}."));
+            }
+        }
+        Ok(())
+    }
+
+    /// Take on `presence` and tell every room.
+    fn set_presence(&mut self, presence: Presence) -> Result<()> {
+        wire::decode_presence(&serde_json::to_string(&presence)?)?;
+        self.presence = presence;
+        for index in 0..self.rooms.len() {
+            self.announce_presence(index)?;
+        }
+        self.presence_due = tokio::time::Instant::now() + PRESENCE_INTERVAL;
+        self.notice(&format!("You are {}.", self.presence.describe()));
+        self.emit_status();
+        Ok(())
+    }
+
+    /// Send our `Presence` to the room at `index`.
+    fn announce_presence(&mut self, index: usize) -> Result<()> {
+        let text = serde_json::to_string(&self.presence)?;
+        self.publish(index, WireMessageType::Presence, text)?;
+        Ok(())
+    }
+
+    /// Repeat a status other than online every `PRESENCE_INTERVAL`, for
+    /// members who missed it.
+    fn check_presence(&mut self) {
+        let now = tokio::time::Instant::now();
+        if self.presence.status == PresenceStatus::Online || now < self.presence_due {
+            return;
+        }
+        self.presence_due = now + PRESENCE_INTERVAL;
+        for index in 0..self.rooms.len() {
+            if let Err(e) = self.announce_presence(index) {
+                debug!("Cannot announce presence: {e}");
+            }
+        }
+    }
+
+    /// List our public rooms again when due, and drop listings not sent
+    /// again in time.
+    fn check_directory(&mut self) {
+        let now = tokio::time::Instant::now();
+        if now >= self.listing_due {
+            self.listing_due = now + LISTING_INTERVAL;
+            self.publish_listings();
+        }
+        let before = self.directory.len();
+        self.directory
+            .retain(|_, (_, seen)| now.duration_since(*seen) < LISTING_TTL);
+        if self.directory.len() != before {
+            self.emit_directory();
+        }
+    }
+
+    /// Whether `/block` covers a message from `signer`, or for unsigned
+    /// ones (synced history) from `sender` ("nick#disc").
+    fn is_blocked(&self, signer: Option<PeerId>, sender: &str) -> bool {
+        let blocked = &self.config.blocked_peers;
+        match signer {
+            Some(peer) => blocked.contains_key(&peer.to_string()),
+            None => blocked.values().any(|name| name == sender),
+        }
+    }
+
+    /// Pin the key of a member of the active room in the contacts.
+    fn trust(&mut self, who: &str) -> Result<()> {
+        let Some(room) = self.active_room() else {
+            bail!("Not in a room.");
+        };
+        let (name, peer_id) = room.find_member(who)?;
+        if peer_id.parse::<PeerId>().is_err() {
+            bail!("Peer ID of {name} is not known yet.");
+        }
+        self.contacts.trust(&peer_id, &name)?;
+        self.notice(&format!(
+            "{name} is in your contacts; anyone else using that name will be flagged."
+        ));
+        Ok(())
+    }
+
+    /// Give a member of the active room a name of our own, kept with its
+    /// key in the contacts, or drop it (`None`).
+    fn alias(&mut self, who: &str, alias: Option<String>) -> Result<()> {
+        let Some(room) = self.active_room() else {
+            bail!("Not in a room.");
+        };
+        let (name, peer_id) = room.find_member(who)?;
+        if peer_id.parse::<PeerId>().is_err() {
+            bail!("Peer ID of {name} is not known yet.");
+        }
+        let alias = alias
+            .map(|a| a.trim().to_string())
+            .filter(|a| !a.is_empty());
+        if let Some(alias) = &alias {
+            ensure!(
+                alias.chars().count() <= MAX_NICK_LEN && !alias.chars().any(char::is_control),
+                "An alias is at most {} printable characters.",
+                MAX_NICK_LEN
+            );
+        }
+        self.contacts.set_alias(&peer_id, &name, alias.clone())?;
+        match alias {
+            Some(alias) => self.notice(&format!("{name} is shown as \"{alias}\" from now on.")),
+            None => self.notice(&format!("{name} is shown by its nickname again.")),
+        }
+        self.emit_status();
+        Ok(())
+    }
+
+    /// How a member of `room` called `name` with key `peer_id` is shown:
+    /// after its avatar and by our alias for it, if any.
+    fn member_label(&self, room: &JoinedRoom, name: &str, peer_id: &str) -> String {
+        let label = self
+            .contacts
+            .alias(peer_id)
+            .map_or_else(|| name.to_string(), str::to_string);
+        with_avatar(room.avatars.get(name), label)
+    }
+
+    /// Warn in `room_name` if `sender`, with key `peer_id`, uses the
+    /// nickname of a contact with another key — once per key.
+    fn check_pinned_name(&mut self, room_name: &str, sender: &str, peer_id: &str) {
+        let Some(contact) = self
+            .contacts
+            .name_clash(peer_id, sender)
+            .filter(|_| !peer_id.is_empty())
+        else {
+            return;
+        };
+        if !self.name_clashes.insert(peer_id.to_string()) {
+            return;
+        }
+        let text = format!(
+            "⚠ {} is not the {} in your contacts — its identity key differs. /verify it before trusting it.",
+            sender, contact.name
+        );
+        self.record(room_name, DisplayMessage::system(&text));
+    }
+
+    /// Set the active room's subject — owner or co-owner only.
+    fn set_subject(&mut self, subject: String) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        if !self.rooms[index].is_admin(Some(self.me(index).peer_id)) {
+            bail!("Only the room owner or a co-owner can set the topic.");
+        }
+        let subject: String = subject.trim().chars().take(MAX_SUBJECT_LEN).collect();
+        let (_, raw) = self.signed_wire(self.me(index), WireMessageType::Topic, subject)?;
+        self.publish_wire(index, &raw)?;
+        let wire = wire::decode(&raw)?;
+        self.apply_subject(index, &wire, raw);
+        Ok(())
+    }
+
+    /// Set the active room's slow mode — owner or co-owner only.
+    fn set_slow_mode(&mut self, secs: u32) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        if !self.rooms[index].is_admin(Some(self.me(index).peer_id)) {
+            bail!("Only the room owner or a co-owner can set slow mode.");
+        }
+        if secs > MAX_SLOW_MODE_SECS {
+            bail!("Slow mode is at most {MAX_SLOW_MODE_SECS}s.");
+        }
+        let (_, raw) =
+            self.signed_wire(self.me(index), WireMessageType::SlowMode, secs.to_string())?;
+        self.publish_wire(index, &raw)?;
+        let wire = wire::decode(&raw)?;
+        self.apply_slow_mode(index, &wire, raw)
+    }
+
+    /// Take the slow mode from an owner's or co-owner's `SlowMode`
+    /// message `wire` (encoded as `raw`) unless we already have a newer one.
+    fn apply_slow_mode(&mut self, index: usize, wire: &WireMessage, raw: Vec<u8>) -> Result<()> {
+        let secs = wire::decode_slow_mode(&wire.text)?;
+        let by = wire::verify(wire)
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        let room = &mut self.rooms[index];
+        if wire.timestamp_ms <= room.slow_mode_ms {
+            return Ok(());
+        }
+        room.slow_mode = secs;
+        room.slow_mode_ms = wire.timestamp_ms;
+        room.slow_mode_wire = Some(raw);
+        let text = match secs {
+            0 => format!("Slow mode was turned off by {}", room.acted_by(&by)),
+            secs => format!(
+                "Slow mode was set by {}: members wait {secs}s between messages",
+                room.acted_by(&by)
+            ),
+        };
+        let room_name = room.state.name.clone();
+        self.record(&room_name, DisplayMessage::system(&text));
+        Ok(())
+    }
+
+    // ── Public directory ──────────────────────────────────────────────────────
+
+    /// Owner only: list the active room in the public directory with
+    /// `description`, or with `None` take it off.
+    fn list_public(&mut self, description: Option<String>) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        if !self.rooms[index].is_owner(Some(self.identity.peer_id)) {
+            bail!("Only the room owner can list the room.");
+        }
+        let name = self.rooms[index].state.name.clone();
+        let Some(description) = description else {
+            if self.rooms[index].listing.take().is_none() {
+                bail!("'{name}' is not listed.");
+            }
+            self.publish_directory(WireMessageType::Unlisting, name.clone())?;
+            self.notice(&format!("'{name}' is off the public directory."));
+            return Ok(());
+        };
+        if description.chars().count() > MAX_DESCRIPTION_LEN {
+            bail!("A description is at most {MAX_DESCRIPTION_LEN} characters.");
+        }
+        self.rooms[index].listing = Some(description);
+        self.join_directory();
+        self.publish_listing(index)?;
+        self.notice(&format!(
+            "'{name}' is in the public directory: anyone browsing sees it and its code, and joins with the password if it has one."
+        ));
+        Ok(())
+    }
+
+    /// Subscribe to the directory topic, once.
+    fn join_directory(&mut self) {
+        if !std::mem::replace(&mut self.in_directory, true) {
+            let _ = self
+                .net_cmd_tx
+                .send(NetworkCommand::Subscribe(DIRECTORY_TOPIC.to_string()));
+        }
+    }
+
+    /// Ask the owners on the directory topic for their listings, unless we
+    /// just did.
+    fn query_directory(&mut self) {
+        let now = tokio::time::Instant::now();
+        if self
+            .listing_queried
+            .is_some_and(|at| now.duration_since(at) < LISTING_QUERY_GAP)
+        {
+            return;
+        }
+        self.listing_queried = Some(now);
+        if let Err(e) = self.publish_directory(WireMessageType::ListingQuery, String::new()) {
+            debug!("Cannot query the directory: {e}");
+        }
+    }
+
+    /// Sign a message with our identity, which room codes carry, and
+    /// publish it on the directory topic as is.
+    fn publish_directory(&self, msg_type: WireMessageType, text: String) -> Result<()> {
+        let (_, raw) = self.signed_wire(&self.identity, msg_type, text)?;
+        let _ = self.net_cmd_tx.send(NetworkCommand::Publish {
+            topic: DIRECTORY_TOPIC.to_string(),
+            data: raw,
+        });
+        Ok(())
+    }
+
+    /// List room `index`, which we listed, with its current member count.
+    fn publish_listing(&self, index: usize) -> Result<()> {
+        let room = &self.rooms[index];
+        let Some(description) = &room.listing else {
+            return Ok(());
+        };
+        let listing = PublicRoom {
+            name: room.state.name.clone(),
+            description: description.clone(),
+            members: room.members.len() + 1,
+            code: self.room_code(&room.state.name, room.salt, ""),
+        };
+        self.publish_directory(WireMessageType::Listing, serde_json::to_string(&listing)?)
+    }
+
+    fn publish_listings(&self) {
+        for index in (0..self.rooms.len()).filter(|&i| self.rooms[i].listing.is_some()) {
+            if let Err(e) = self.publish_listing(index) {
+                debug!("Cannot list room: {e}");
+            }
+        }
+    }
+
+    /// A message on the directory topic.  Listings must carry a room code
+    /// their signer made for that room, so nobody lists others' rooms.
+    fn handle_directory(&mut self, payload: &[u8]) -> Result<()> {
+        let wire = wire::decode(payload)?;
+        let Some(signer) = wire::verify(&wire) else {
+            bail!("unsigned directory message");
+        };
+        if signer == self.identity.peer_id || self.is_blocked(Some(signer), "") {
+            return Ok(());
+        }
+        let now = tokio::time::Instant::now();
+        if !self
+            .directory_seen
+            .check(&wire, Utc::now().timestamp_millis())
+        {
+            bail!("replayed or stale directory message from {signer}");
+        }
+        let owner = signer.to_string();
+        match wire.msg_type {
+            WireMessageType::Listing => {
+                let room = wire::decode_listing(&wire.text)?;
+                let code = RoomCodeData::decode(&room.code)?;
+                ensure!(
+                    code.signed && code.peer_id == owner && code.room_name == room.name,
+                    "listing of '{}' with a code not its signer's",
+                    room.name
+                );
+                let key = (owner, room.name.clone());
+                if self.directory.len() >= MAX_DIRECTORY && !self.directory.contains_key(&key) {
+                    return Ok(());
+                }
+                let changed = self
+                    .directory
+                    .get(&key)
+                    .is_none_or(|(known, _)| *known != room);
+                self.directory.insert(key, (room, now));
+                if changed {
+                    self.emit_directory();
+                }
+            }
+            WireMessageType::Unlisting => {
+                if self.directory.remove(&(owner, wire.text)).is_some() {
+                    self.emit_directory();
+                }
+            }
+            WireMessageType::ListingQuery => {
+                if self
+                    .listing_answered
+                    .is_some_and(|at| now.duration_since(at) < LISTING_QUERY_GAP)
+                {
+                    return Ok(());
+                }
+                self.listing_answered = Some(now);
+                self.publish_listings();
+            }
+            other => bail!("unexpected {other:?} in the directory"),
+        }
+        Ok(())
+    }
+
+    /// Tell the UI what the directory holds, if it asked.
+    fn emit_directory(&self) {
+        if !self.browsing {
+            return;
+        }
+        let mut rooms: Vec<PublicRoom> = self
+            .directory
+            .values()
+            .map(|(room, _)| room.clone())
+            .collect();
+        rooms.sort_by(|a, b| b.members.cmp(&a.members).then_with(|| a.name.cmp(&b.name)));
+        self.emit(UiEvent::PublicRooms(rooms));
+    }
+
+    /// Owner only: let room `index` hold at most `max` members (0: any
+    /// number), and tell its members.  Those already in stay.
+    fn set_capacity(&mut self, index: usize, max: u32) -> Result<()> {
+        if !self.rooms[index].is_owner(Some(self.me(index).peer_id)) {
+            bail!("Only the room owner can limit the members.");
+        }
+        let (_, raw) =
+            self.signed_wire(self.me(index), WireMessageType::Capacity, max.to_string())?;
+        self.publish_wire(index, &raw)?;
+        let wire = wire::decode(&raw)?;
+        self.apply_capacity(index, &wire, raw)
+    }
+
+    /// Take the member limit from the owner's `Capacity` message `wire`
+    /// (encoded as `raw`) unless we already have a newer one.
+    fn apply_capacity(&mut self, index: usize, wire: &WireMessage, raw: Vec<u8>) -> Result<()> {
+        let max = wire::decode_capacity(&wire.text)?;
+        let by = wire::verify(wire)
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        let room = &mut self.rooms[index];
+        if wire.timestamp_ms <= room.max_members_ms {
+            return Ok(());
+        }
+        let changed = room.max_members != max;
+        room.max_members = max;
+        room.max_members_ms = wire.timestamp_ms;
+        room.max_members_wire = Some(raw);
+        if changed {
+            let text = match max {
+                0 => format!("The member limit was lifted by {}", room.acted_by(&by)),
+                max => format!(
+                    "The member limit was set by {}: the room holds at most {max} members",
+                    room.acted_by(&by)
+                ),
+            };
+            let room_name = room.state.name.clone();
+            self.record(&room_name, DisplayMessage::system(&text));
+        }
+        Ok(())
+    }
+
+    /// Owner only: show a new invite token to the active room, good for
+    /// `hours` (0: `INVITE_HOURS`) and for one joiner if `single_use`.
+    fn mint_invite(&mut self, hours: u32, single_use: bool) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        if !self.rooms[index].is_owner(Some(self.me(index).peer_id)) {
+            bail!("Only the room owner can mint invites.");
+        }
+        let hours = if hours == 0 { INVITE_HOURS } else { hours };
+        if hours > MAX_INVITE_HOURS {
+            bail!("An invite is good for at most {MAX_INVITE_HOURS} hours.");
+        }
+        let expires = Utc::now() + chrono::Duration::hours(hours.into());
+        let room = &self.rooms[index];
+        let name = room.state.name.clone();
+        let invite = Invite::mint(
+            &self.me(index).keypair,
+            &name,
+            &room.salt,
+            expires.timestamp_millis(),
+            single_use,
+        )?;
+        let code = self.room_code(&name, room.salt, "");
+        let whom = if single_use { "one joiner" } else { "anyone" };
+        self.notice(&format!(
+            "Invite to '{name}' for {whom} until {} UTC — it joins in place of the code, with the password:",
+            expires.format("%Y-%m-%d %H:%M")
+        ));
+        self.emit(UiEvent::RoomCode {
+            name,
+            code: invite_token(&code, &invite),
+        });
+        Ok(())
+    }
+
+    /// Owner only: take joiners to the active room with an invite only
+    /// (`on`), or anyone again, and tell its members.
+    fn set_invite_only(&mut self, on: bool) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        if !self.rooms[index].is_owner(Some(self.me(index).peer_id)) {
+            bail!("Only the room owner can choose who joins.");
+        }
+        let (_, raw) =
+            self.signed_wire(self.me(index), WireMessageType::InviteOnly, on.to_string())?;
+        self.publish_wire(index, &raw)?;
+        let wire = wire::decode(&raw)?;
+        self.apply_invite_only(index, &wire, raw)
+    }
+
+    /// Take the invite-only mode from the owner's `InviteOnly` message
+    /// `wire` (encoded as `raw`) unless we already have a newer one.
+    fn apply_invite_only(&mut self, index: usize, wire: &WireMessage, raw: Vec<u8>) -> Result<()> {
+        let on = wire::decode_invite_only(&wire.text)?;
+        let by = wire::verify(wire)
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        let room = &mut self.rooms[index];
+        if wire.timestamp_ms <= room.invite_only_ms {
+            return Ok(());
+        }
+        let changed = room.invite_only != on;
+        room.invite_only = on;
+        room.invite_only_ms = wire.timestamp_ms;
+        room.invite_only_wire = Some(raw);
+        if changed {
+            let text = match on {
+                true => format!(
+                    "Joining was made invite-only by {}: joiners need an invite token from the room owner",
+                    room.acted_by(&by)
+                ),
+                false => format!(
+                    "Anyone with the code and password may join again, as set by {}",
+                    room.acted_by(&by)
+                ),
+            };
+            let room_name = room.state.name.clone();
+            self.record(&room_name, DisplayMessage::system(&text));
+        }
+        Ok(())
+    }
+
+    /// Owner or co-owner only: change who may write in the active room
+    /// with `change` and tell its members.
+    fn set_announcement(&mut self, change: impl FnOnce(&mut Announcement)) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        if !self.rooms[index].is_admin(Some(self.me(index).peer_id)) {
+            bail!("Only the room owner or a co-owner can choose who writes.");
+        }
+        let mut announcement = self.rooms[index].announcement.clone();
+        change(&mut announcement);
+        let text = serde_json::to_string(&announcement)?;
+        wire::decode_announcement(&text).map_err(|e| anyhow!("Cannot do that: {e}."))?;
+        let (_, raw) = self.signed_wire(self.me(index), WireMessageType::Announcement, text)?;
+        self.publish_wire(index, &raw)?;
+        let wire = wire::decode(&raw)?;
+        self.apply_announcement(index, &wire, raw)
+    }
+
+    /// Take who may write from an owner's or co-owner's `Announcement`
+    /// message `wire` (encoded as `raw`) unless we already have a newer one.
+    fn apply_announcement(&mut self, index: usize, wire: &WireMessage, raw: Vec<u8>) -> Result<()> {
+        let new = wire::decode_announcement(&wire.text)?;
+        let by = wire::verify(wire)
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        let room = &mut self.rooms[index];
+        if wire.timestamp_ms <= room.announcement_ms {
+            return Ok(());
+        }
+        let old = std::mem::replace(&mut room.announcement, new.clone());
+        room.announcement_ms = wire.timestamp_ms;
+        room.announcement_wire = Some(raw);
+        let mut lines = Vec::new();
+        if new.on != old.on {
+            lines.push(match new.on {
+                true => format!(
+                    "This was made an announcement room by {}: only the owner and speakers write here",
+                    room.acted_by(&by)
+                ),
+                false => format!(
+                    "Everyone may write here again, as set by {}",
+                    room.acted_by(&by)
+                ),
+            });
+        }
+        for peer_id in new.speakers.iter().filter(|p| !old.speakers.contains(p)) {
+            lines.push(format!(
+                "{} was made a speaker by {}",
+                room.name_of(peer_id),
+                room.acted_by(&by)
+            ));
+        }
+        for peer_id in old.speakers.iter().filter(|p| !new.speakers.contains(p)) {
+            lines.push(format!(
+                "{} is no longer a speaker, as set by {}",
+                room.name_of(peer_id),
+                room.acted_by(&by)
+            ));
+        }
+        let room_name = room.state.name.clone();
+        for line in lines {
+            self.record(&room_name, DisplayMessage::system(&line));
+        }
+        Ok(())
+    }
+
+    /// Take the subject from an owner's or co-owner's `Topic` message
+    /// `wire` (encoded as `raw`) unless we already have a newer one.
+    fn apply_subject(&mut self, index: usize, wire: &WireMessage, raw: Vec<u8>) {
+        let by = wire::verify(wire)
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        let room = &mut self.rooms[index];
+        if wire.timestamp_ms <= room.state.subject_ms {
+            return;
+        }
+        room.state.subject = wire.text.chars().take(MAX_SUBJECT_LEN).collect();
+        room.state.subject_ms = wire.timestamp_ms;
+        room.subject_wire = Some(raw);
+        let text = match room.state.subject.as_str() {
+            "" => format!("The topic was cleared by {}", room.acted_by(&by)),
+            subject => format!("The topic was set by {}: {subject}", room.acted_by(&by)),
+        };
+        let room_name = room.state.name.clone();
+        self.record(&room_name, DisplayMessage::system(&text));
+        self.emit_status();
+    }
+
+    /// Owner only: make the member called `who` in the active room a
+    /// co-owner (`admin`), or no longer.
+    fn set_admin(&mut self, who: &str, admin: bool) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        let room = &self.rooms[index];
+        if !room.is_owner(Some(self.me(index).peer_id)) {
+            bail!("Only the room owner can choose co-owners.");
+        }
+        let (name, peer_id) = room.find_member(who)?;
+        if peer_id.is_empty() {
+            bail!("Peer ID of {name} is not known yet.");
+        }
+        match (room.role(&peer_id), admin) {
+            (Some("owner"), _) => bail!("{name} owns the room."),
+            (Some(_), true) => bail!("{name} is a co-owner already."),
+            (None, false) => bail!("{name} is not a co-owner."),
+            _ => self.grant_admin(index, &peer_id, admin),
+        }
+    }
+
+    /// Sign and send a `GrantAdmin` for `peer_id` in room `index`, and
+    /// take it ourselves.
+    fn grant_admin(&mut self, index: usize, peer_id: &str, admin: bool) -> Result<()> {
+        let grant = AdminGrant {
+            peer_id: peer_id.to_string(),
+            admin,
+        };
+        let text = serde_json::to_string(&grant)?;
+        let (_, raw) = self.signed_wire(self.me(index), WireMessageType::GrantAdmin, text)?;
+        self.publish_wire(index, &raw)?;
+        let wire = wire::decode(&raw)?;
+        self.apply_admin_grant(index, &wire, raw)
+    }
+
+    /// Take a co-owner change from the owner's `GrantAdmin` message `wire`
+    /// (encoded as `raw`) unless we already have a newer one for that
+    /// member.
+    fn apply_admin_grant(&mut self, index: usize, wire: &WireMessage, raw: Vec<u8>) -> Result<()> {
+        let grant = wire::decode_admin_grant(&wire.text)?;
+        let me = self.me(index).peer_id.to_string();
+        let room = &mut self.rooms[index];
+        if room
+            .admin_ms
+            .get(&grant.peer_id)
+            .is_some_and(|ms| wire.timestamp_ms <= *ms)
+        {
+            return Ok(());
+        }
+        room.admin_ms
+            .insert(grant.peer_id.clone(), wire.timestamp_ms);
+        room.roles_wire.push(raw);
+        let changed = match grant.admin {
+            true => room.admins.insert(grant.peer_id.clone()),
+            false => room.admins.remove(&grant.peer_id),
+        };
+        if changed {
+            let text = match (grant.peer_id == me, grant.admin) {
+                (true, true) => "You are a co-owner now".to_string(),
+                (true, false) => "You are no longer a co-owner".to_string(),
+                (false, true) => format!("{} is a co-owner now", room.name_of(&grant.peer_id)),
+                (false, false) => {
+                    format!("{} is no longer a co-owner", room.name_of(&grant.peer_id))
+                }
+            };
+            let room_name = room.state.name.clone();
+            self.record(&room_name, DisplayMessage::system(&text));
+            self.emit_status();
+        }
+        Ok(())
+    }
+
+    /// Owner only: hand the active room to the member called `who`; we
+    /// stay a co-owner.
+    fn transfer_ownership(&mut self, who: &str) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        let room = &self.rooms[index];
+        if !room.is_owner(Some(self.me(index).peer_id)) {
+            bail!("Only the room owner can hand the room on.");
+        }
+        let (name, peer_id) = room.find_member(who)?;
+        if peer_id.is_empty() {
+            bail!("Peer ID of {name} is not known yet.");
+        }
+        let (_, raw) =
+            self.signed_wire(self.me(index), WireMessageType::TransferOwnership, peer_id)?;
+        self.publish_wire(index, &raw)?;
+        let wire = wire::decode(&raw)?;
+        self.apply_transfer(index, &wire, raw)
+    }
+
+    /// Take a new owner from the owner's `TransferOwnership` message
+    /// `wire` (encoded as `raw`) unless we already have a newer one.  The
+    /// previous owner stays a co-owner; if that was us, the room leaves
+    /// the public directory, where only its owner may list it.
+    fn apply_transfer(&mut self, index: usize, wire: &WireMessage, raw: Vec<u8>) -> Result<()> {
+        let new_owner = wire::decode_new_owner(&wire.text)?.to_string();
+        let me = self.me(index).peer_id.to_string();
+        let room = &mut self.rooms[index];
+        if wire.timestamp_ms <= room.owner_ms || new_owner == room.owner {
+            return Ok(());
+        }
+        let old_owner = std::mem::replace(&mut room.owner, new_owner.clone());
+        room.owner_ms = wire.timestamp_ms;
+        if room.owner_disputed == new_owner {
+            room.owner_disputed.clear();
+        }
+        room.roles_wire.push(raw);
+        room.admins.remove(&new_owner);
+        room.admins.insert(old_owner.clone());
+        let text = format!(
+            "{} handed the room to {}, and stays a co-owner",
+            if old_owner == me {
+                "You".to_string()
+            } else {
+                room.name_of(&old_owner)
+            },
+            if new_owner == me {
+                "you".to_string()
+            } else {
+                room.name_of(&new_owner)
+            },
+        );
+        let room_name = room.state.name.clone();
+        let unlist = old_owner == me && room.listing.take().is_some();
+        self.record(&room_name, DisplayMessage::system(&text));
+        if unlist {
+            self.publish_directory(WireMessageType::Unlisting, room_name)?;
+        }
+        self.emit_status();
+        Ok(())
+    }
+
+    /// Tell the user the role, peer ID and our alias of the member called
+    /// `who` in the active room.
+    fn whois(&self, who: &str) -> Result<()> {
+        let Some(room) = self.active_room() else {
+            bail!("Not in a room.");
+        };
+        let (name, peer_id) = room.find_member(who)?;
+        let role = room.role(&peer_id).unwrap_or("member");
+        let mut text = format!("{name}: {role} of '{}'", room.state.name);
+        text += &match peer_id.as_str() {
+            "" => ", peer ID not known yet".to_string(),
+            peer_id => format!(", peer ID {peer_id}"),
+        };
+        if let Some(alias) = self.contacts.alias(&peer_id) {
+            text += &format!(", shown as {alias}");
+        }
+        if self.contacts.is_verified(&peer_id) {
+            text += ", verified";
+        }
+        self.notice(&format!("{text}."));
+        Ok(())
+    }
+
+    /// Kick or ban a member of the active room — owner or co-owner only;
+    /// only the owner removes co-owners.  Banned peers are also blocked at
+    /// the GossipSub layer, so we stop relaying them.
+    fn moderate(&mut self, who: &str, action: WireMessageType) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        let me = self.me(index).peer_id;
+        let room = &self.rooms[index];
+        if !room.is_admin(Some(me)) {
+            bail!("Only the room owner or a co-owner can kick or ban.");
+        }
+        let (name, peer_id) = room.find_member(who)?;
+        if peer_id.is_empty() {
+            bail!("Peer ID of {name} is not known yet.");
+        }
+        match room.role(&peer_id) {
+            Some("owner") => bail!("{name} owns the room."),
+            Some(_) if !room.is_owner(Some(me)) => {
+                bail!("Only the room owner can remove a co-owner.")
+            }
+            // Not a co-owner when back.
+            Some(_) => self.grant_admin(index, &peer_id, false)?,
+            None => {}
+        }
+        self.publish(index, action.clone(), peer_id.clone())?;
+        self.remove_member(index, &peer_id, action, &me.to_string());
+        // The removed peer still holds the current key.
+        self.rekey(index);
+        Ok(())
+    }
+
+    /// Owner or co-owner only: move room `index` to a fresh random key
+    /// and send it to every member whose peer ID we know, each copy
+    /// encrypted to that member's identity key.
+    fn rekey(&mut self, index: usize) {
+        let room = &mut self.rooms[index];
+        let epoch = room.epoch + 1;
+        room.install_key(epoch, RoomKey::generate());
+        let peers: BTreeSet<String> = room.members.values().cloned().collect();
+        let room_name = room.state.name.clone();
+        let mut sent = 0;
+        for peer in peers.iter().filter_map(|id| id.parse::<PeerId>().ok()) {
+            match self.send_room_key(index, &peer) {
+                Ok(()) => sent += 1,
+                Err(e) => warn!("Cannot send the new room key to {peer}: {e}"),
+            }
+        }
+        self.record(
+            &room_name,
+            DisplayMessage::system(&format!("Room key rotated; sent to {sent} member(s)")),
+        );
+    }
+
+    /// Send `peer` the current key of room `index` as a `Rekey`.
+    fn send_room_key(&self, index: usize, peer: &PeerId) -> Result<()> {
+        let room = &self.rooms[index];
+        let Some(key) = &room.epoch_key else {
+            return Ok(());
+        };
+        let rekey = RoomRekey {
+            room: room.state.name.clone(),
+            epoch: room.epoch,
+            key: B64.encode(key.to_bytes()),
+            owner_proof: String::new(),
+        };
+        let text = serde_json::to_string(&rekey)?;
+        self.send_direct_wire(self.me(index), peer, WireMessageType::Rekey, text)?;
+        Ok(())
+    }
+
+    /// Apply a `Kick` / `Ban` of `peer_id` to room `index` and tell the user.
+    fn remove_member(&mut self, index: usize, peer_id: &str, action: WireMessageType, by: &str) {
+        let room = &mut self.rooms[index];
+        let by = room.acted_by(by);
+        let verb = if action == WireMessageType::Ban {
+            room.banned.insert(peer_id.to_string());
+            let _ = self
+                .net_cmd_tx
+                .send(NetworkCommand::BlockPeer(peer_id.to_string()));
+            "banned"
+        } else {
+            "kicked"
+        };
+        let names: Vec<String> = room
+            .members
+            .iter()
+            .filter(|(_, id)| *id == peer_id)
+            .map(|(name, _)| name.clone())
+            .collect();
+        room.members.retain(|_, id| id != peer_id);
+        let room_name = room.state.name.clone();
+        let who = names
+            .first()
+            .cloned()
+            .unwrap_or_else(|| peer_id.to_string());
+        self.record(
+            &room_name,
+            DisplayMessage::system(&format!("{} was {} by {}", who, verb, by)),
+        );
+        self.emit_status();
+    }
+
+    /// Encrypt `text` for one member of the active room only and publish it
+    /// on that member's inbox topic.
+    fn send_direct(&mut self, to: &str, text: String) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        let room = &self.rooms[index];
+        let (name, peer_id) = room.find_member(to)?;
+        let peer: PeerId = peer_id
+            .parse()
+            .map_err(|_| anyhow!("Peer ID of {name} is not known yet."))?;
+        let room_name = room.state.name.clone();
+
+        let msg_id =
+            self.send_direct_wire(self.me(index), &peer, WireMessageType::Direct, text.clone())?;
+        let me = self.me(index);
+        let msg = DisplayMessage::direct(&me.display_name(), &name, &text)
+            .sent(msg_id)
+            .with_avatar(me.avatar.clone());
+        self.record(&room_name, msg);
+        Ok(())
+    }
+
+    /// Encrypt a wire message from `me` with the key shared with `peer` and
+    /// publish it on `peer`'s inbox topic.  Returns its message ID.
+    fn send_direct_wire(
+        &self,
+        me: &Identity,
+        peer: &PeerId,
+        msg_type: WireMessageType,
+        text: String,
+    ) -> Result<u64> {
+        let key = RoomKey::direct(&me.keypair, peer)?;
+        let (msg_id, wire) = self.signed_wire(me, msg_type, text)?;
+        let data = key.encrypt(&wire)?;
+        let envelope = DirectEnvelope {
+            from: me.peer_id.to_string(),
+            data: B64.encode(data),
+        };
+        let _ = self.net_cmd_tx.send(NetworkCommand::Publish {
+            topic: inbox_topic(&peer.to_string()),
+            data: serde_json::to_vec(&envelope)?,
+        });
+        Ok(msg_id)
+    }
+
+    /// Tell the signer of a message we received as `me` that it arrived.
+    fn send_ack(&self, me: &Identity, to: Option<PeerId>, msg_id: u64) {
+        let Some(peer) = to.filter(|_| msg_id != 0) else {
+            return;
+        };
+        if let Err(e) = self.send_direct_wire(me, &peer, WireMessageType::Ack, msg_id.to_string()) {
+            debug!("Cannot acknowledge message: {e}");
+        }
+    }
+
+    /// React to the latest message in the active room from `to`, or from
+    /// anyone but us.
+    fn react(&mut self, to: Option<&str>, emoji: String) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        wire::validate_emoji(&emoji)?;
+        let own_name = self.me(index).display_name();
+        let room = &self.rooms[index];
+        let author = to
+            .map(|who| room.find_member(who))
+            .transpose()?
+            .map(|(name, _)| name);
+        let target = room
+            .history
+            .iter()
+            .rev()
+            .filter(|m| !m.is_system && m.msg_id != 0)
+            .find(|m| match &author {
+                Some(name) => &m.sender == name,
+                None => m.sender != own_name,
+            })
+            .map(|m| m.msg_id)
+            .ok_or_else(|| anyhow!("No message to react to."))?;
+        let reaction = Reaction {
+            target_msg_id: target,
+            emoji,
+        };
+        self.publish(
+            index,
+            WireMessageType::Reaction,
+            serde_json::to_string(&reaction)?,
+        )?;
+        self.apply_reaction(index, &own_name, reaction);
+        Ok(())
+    }
+
+    /// Count a reaction in room `index` and update the UI.
+    fn apply_reaction(&mut self, index: usize, reactor: &str, reaction: Reaction) {
+        let msg_id = reaction.target_msg_id;
+        if let Some(reactions) = self.rooms[index].add_reaction(reactor, reaction) {
+            let room = self.rooms[index].state.name.clone();
+            self.emit(UiEvent::Reactions {
+                room,
+                msg_id,
+                reactions,
+            });
+        }
+    }
+
+    /// Ask the active room `question`, open for `minutes`.
+    fn start_poll(&mut self, question: String, options: Vec<String>, minutes: u32) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        if minutes == 0 || minutes > MAX_POLL_SECS / 60 {
+            bail!("A poll stays open 1 to {} minutes.", MAX_POLL_SECS / 60);
+        }
+        let poll = Poll {
+            question,
+            options,
+            duration_secs: minutes * 60,
+        };
+        let text = serde_json::to_string(&poll)?;
+        let poll = wire::decode_poll(&text).map_err(|e| anyhow!("Cannot ask that: {e}."))?;
+        self.check_send_rate(index)?;
+        let me = self.me(index);
+        let (msg_id, raw) = self.signed_wire(me, WireMessageType::Poll, text)?;
+        let msg = DisplayMessage::chat(&me.display_name(), &poll.question)
+            .with_id(msg_id)
+            .with_avatar(me.avatar.clone());
+        let creator = me.peer_id.to_string();
+        self.publish_wire(index, &raw)?;
+        let sent_ms = wire::decode(&raw)?.timestamp_ms;
+        self.add_poll(index, creator, poll, msg, sent_ms);
+        Ok(())
+    }
+
+    /// Show poll `msg` (its question) in room `index` and start counting
+    /// votes for it.
+    fn add_poll(
+        &mut self,
+        index: usize,
+        creator: String,
+        poll: Poll,
+        msg: DisplayMessage,
+        sent_ms: i64,
+    ) {
+        let state = PollState::new(creator, poll, sent_ms);
+        let msg = msg.with_poll(state.tally());
+        self.rooms[index].polls.insert(msg.msg_id, state);
+        let room_name = self.rooms[index].state.name.clone();
+        self.record(&room_name, msg);
+    }
+
+    /// Vote for option `n` (from 1) of the latest open poll in the active
+    /// room.
+    fn vote(&mut self, n: usize) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        let room = &self.rooms[index];
+        let poll_msg_id = room
+            .open_poll(None)
+            .ok_or_else(|| anyhow!("No open poll to vote in."))?;
+        let options = room.polls[&poll_msg_id].options.len();
+        if !(1..=options).contains(&n) {
+            bail!("Pick an option from 1 to {options}.");
+        }
+        let vote = Vote {
+            poll_msg_id,
+            option: n - 1,
+        };
+        self.publish(index, WireMessageType::Vote, serde_json::to_string(&vote)?)?;
+        let voter = self.me(index).peer_id.to_string();
+        self.apply_vote(index, voter, vote, Utc::now().timestamp_millis());
+        Ok(())
+    }
+
+    /// Count `voter`'s vote, cast at `cast_ms`, in room `index`; it
+    /// replaces any earlier one.  Votes for a poll that is closed (or was
+    /// by then) or for an option it does not have are dropped.
+    fn apply_vote(&mut self, index: usize, voter: String, vote: Vote, cast_ms: i64) {
+        let Some(poll) = self.rooms[index]
+            .polls
+            .get_mut(&vote.poll_msg_id)
+            .filter(|p| !p.closed && cast_ms <= p.closes_ms && vote.option < p.options.len())
+        else {
+            return;
+        };
+        if poll.votes.insert(voter, vote.option) != Some(vote.option) {
+            self.show_poll(index, vote.poll_msg_id);
+        }
+    }
+
+    /// Close our latest open poll in the active room.
+    fn end_poll(&mut self) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        let creator = self.me(index).peer_id.to_string();
+        let Some(msg_id) = self.rooms[index].open_poll(Some(&creator)) else {
+            bail!("You have no open poll here.");
+        };
+        self.publish(index, WireMessageType::PollEnd, msg_id.to_string())?;
+        self.close_poll(index, msg_id);
+        Ok(())
+    }
+
+    /// Close poll `msg_id` in room `index` and announce the result.
+    fn close_poll(&mut self, index: usize, msg_id: u64) {
+        let room = &mut self.rooms[index];
+        let Some(poll) = room.polls.get_mut(&msg_id).filter(|p| !p.closed) else {
+            return;
+        };
+        poll.closed = true;
+        let Some(tally) = room.update_poll(msg_id) else {
+            return;
+        };
+        let question = room
+            .history
+            .iter()
+            .rev()
+            .find(|m| m.poll.is_some() && m.msg_id == msg_id)
+            .map(|m| m.text.clone())
+            .unwrap_or_default();
+        let room_name = room.state.name.clone();
+        let results: Vec<String> = tally
+            .options
+            .iter()
+            .map(|(option, n)| format!("{option} {n}"))
+            .collect();
+        self.emit(UiEvent::PollTally {
+            room: room_name.clone(),
+            msg_id,
+            poll: tally,
+        });
+        self.record(
+            &room_name,
+            DisplayMessage::system(&format!("Poll closed: {question} — {}", results.join(", "))),
+        );
+    }
+
+    /// Close the polls whose time is up.
+    fn check_polls(&mut self) {
+        let now_ms = Utc::now().timestamp_millis();
+        for index in 0..self.rooms.len() {
+            let due: Vec<u64> = self.rooms[index]
+                .polls
+                .iter()
+                .filter(|(_, p)| !p.closed && p.closes_ms <= now_ms)
+                .map(|(&msg_id, _)| msg_id)
+                .collect();
+            for msg_id in due {
+                self.close_poll(index, msg_id);
+            }
+        }
+    }
+
+    /// Redraw poll `msg_id` of room `index` with its current votes.
+    fn show_poll(&mut self, index: usize, msg_id: u64) {
+        if let Some(poll) = self.rooms[index].update_poll(msg_id) {
+            let room = self.rooms[index].state.name.clone();
+            self.emit(UiEvent::PollTally { room, msg_id, poll });
+        }
+    }
+
+    /// Change the active room's notes and tell its members.
+    fn edit_note(&mut self, edit: NoteEdit) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        if let NoteEdit::Insert { text, .. } | NoteEdit::Append(text) | NoteEdit::Set { text, .. } =
+            &edit
+        {
+            wire::validate_note(text).map_err(|e| anyhow!("Cannot write that: {e}."))?;
+        }
+        let notes = &mut self.rooms[index].notes;
+        let line = match edit {
+            NoteEdit::Insert { after, text } => notes.insert(after, text)?,
+            NoteEdit::Append(text) => {
+                notes.insert(notes.visible().last().map(|&(id, _)| id), text)?
+            }
+            NoteEdit::Set { id, text } => notes.set(id, text)?,
+            NoteEdit::Delete(id) => notes.delete(id)?,
+        };
+        self.publish_notes(index, [&line])?;
+        self.show_notes(index);
+        Ok(())
+    }
+
+    /// Publish `lines` of the notes to room `index`, in as few `Notes`
+    /// messages as fit, of at most `MAX_NOTE_LINES` lines each.
+    fn publish_notes<'a>(
+        &self,
+        index: usize,
+        lines: impl IntoIterator<Item = &'a NoteLine>,
+    ) -> Result<()> {
+        let mut batch: Vec<&NoteLine> = Vec::new();
+        // Brackets, then each line and its comma
+        let mut len = 2;
+        for line in lines {
+            let line_len = serde_json::to_string(line)?.len() + 1;
+            if (len + line_len > MAX_TEXT_LEN && !batch.is_empty()) || batch.len() == MAX_NOTE_LINES
+            {
+                self.publish(
+                    index,
+                    WireMessageType::Notes,
+                    serde_json::to_string(&batch)?,
+                )?;
+                batch.clear();
+                len = 2;
+            }
+            batch.push(line);
+            len += line_len;
+        }
+        if !batch.is_empty() {
+            self.publish(
+                index,
+                WireMessageType::Notes,
+                serde_json::to_string(&batch)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Send the notes of room `index` to the frontend.
+    fn show_notes(&mut self, index: usize) {
+        let room = self.rooms[index].state.name.clone();
+        let lines = self.rooms[index].notes.visible();
+        self.emit(UiEvent::Notes { room, lines });
+    }
+
+    /// Show thread `thread` of the active room (`None`: the room itself)
+    /// and send there from now on.
+    fn open_thread(&mut self, thread: Option<String>) -> Result<()> {
+        let Some(index) = self.active.as_deref().and_then(|n| self.room_index(n)) else {
+            bail!("Not in a room.");
+        };
+        if let Some(name) = &thread {
+            wire::validate_thread(name).map_err(|e| anyhow!("Cannot open that thread: {e}."))?;
+        }
+        let room = &mut self.rooms[index];
+        room.thread = thread;
+        let name = room.state.name.clone();
+        let text = match &room.thread {
+            Some(thread) => format!(
+                "In thread '{thread}' of '{name}': messages go there. /thread alone goes back."
+            ),
+            None => format!("Back in '{name}'."),
+        };
+        self.emit(UiEvent::RoomSwitched {
+            name,
+            history: self.rooms[index].view(),
+        });
+        self.emit_status();
+        self.show_threads(index);
+        self.notice(&text);
+        Ok(())
+    }
+
+    fn show_threads(&mut self, index: usize) {
+        let room = self.rooms[index].state.name.clone();
+        let threads = self.rooms[index].threads();
+        self.emit(UiEvent::Threads { room, threads });
+    }
+
+    /// Flag our message `msg_id` as delivered, wherever it is.
+    fn mark_delivered(&mut self, msg_id: u64) {
+        for room in &mut self.rooms {
+            let Some(msg) = room
+                .history
+                .iter_mut()
+                .find(|m| m.msg_id == msg_id && m.delivery == Some(Delivery::Pending))
+            else {
+                continue;
+            };
+            msg.delivery = Some(Delivery::Delivered);
+            let room = room.state.name.clone();
+            self.emit(UiEvent::MessageDelivered { room, msg_id });
+            return;
+        }
+    }
+
+    // ── Network events ────────────────────────────────────────────────────────
+
+    async fn handle_network_event(&mut self, event: NetworkEvent) -> Result<()> {
+        match event {
+            NetworkEvent::MessageReceived {
+                topic,
+                source,
+                payload,
+            } => {
+                self.handle_message(topic, source, payload).await?;
+            }
+
+            NetworkEvent::RoomPlaintext {
+                room,
+                owner,
+                data,
+                received_ms,
+            } => {
+                let index = match self.room_index(&room) {
+                    Some(index) => index,
+                    None => self.enter_replayed_room(&room, owner),
+                };
+                self.handle_room_plaintext(index, data, received_ms)?;
+            }
+
+            NetworkEvent::PeerSubscribed { topic, peer_id } => {
+                // Subscribing proves nothing: a peer only becomes a member
+                // once its encrypted `Join` arrives.
+                if let Some(index) = self.room_index_by_topic(&topic) {
+                    let room = &mut self.rooms[index];
+                    tracing::debug!("Peer {peer_id} subscribed to room '{}'", room.state.name);
+                    if std::mem::take(&mut room.rejoin) {
+                        let own = self.me(index).peer_id.to_string();
+                        self.publish(index, WireMessageType::Join, own)?;
+                    }
+                }
+                // Someone else browses or lists: ask for listings again.
+                if topic == DIRECTORY_TOPIC && self.browsing {
+                    self.query_directory();
+                }
+                // A member of the room we are joining showed up — check our
+                // password with it once dialing is over.
+                if let Some(join) = self.join.as_mut()
+                    && !join.members.contains(&peer_id)
+                {
+                    match join.topic() {
+                        Some(room_topic) if topic == room_topic => {
+                            join.members.push(peer_id.clone());
+                            self.start_handshakes()?;
+                        }
+                        Some(_) => {}
+                        None => join.subscribed.push((topic.clone(), peer_id.clone())),
+                    }
+                }
+                // A member we dialed for a join by name can now be asked for
+                // the room salt through its inbox.
+                if let Some(join) = self.join.as_ref()
+                    && join.state == JoinState::Dialing
+                    && matches!(join.key, JoinKey::AwaitingSalt)
+                    && join.hosts.contains(&peer_id)
+                    && topic == inbox_topic(&peer_id)
+                    && let Ok(peer) = peer_id.parse::<PeerId>()
+                {
+                    let room = join.room_name.clone();
+                    self.send_direct_wire(
+                        self.joining_as(),
+                        &peer,
+                        WireMessageType::SaltRequest,
+                        room,
+                    )?;
+                }
+            }
+
+            NetworkEvent::PeerDisconnected(peer_id) => {
+                let mut left = Vec::new();
+                for room in &mut self.rooms {
+                    room.members.retain(|name, id| {
+                        let gone = *id == peer_id;
+                        if gone {
+                            left.push((room.state.name.clone(), name.clone()));
+                        }
+                        !gone
+                    });
+                    if left
+                        .last()
+                        .is_some_and(|(name, _)| *name == room.state.name)
+                    {
+                        room.mark_absent(&peer_id);
+                    }
+                }
+                if !left.is_empty() {
+                    for (room, name) in left {
+                        let msg = DisplayMessage::system(&format!("{} disconnected", name));
+                        self.record(&room, msg);
+                    }
+                    self.emit_status();
+                }
+            }
+
+            NetworkEvent::ListeningOn(addr) => {
+                if !self.listen_addrs.contains(&addr) {
+                    if addr.contains("/p2p-circuit") {
+                        info!("Reachable through relay: {addr}");
+                        self.notice(
+                            "Relay reservation obtained — new room codes work across NATs.",
+                        );
+                    }
+                    self.listen_addrs.push(addr);
+                }
+            }
+
+            NetworkEvent::NewExternalAddr(addr) => {
+                info!("External address: {addr}");
+                if !self.listen_addrs.contains(&addr) {
+                    self.listen_addrs.insert(0, addr);
+                }
+            }
+
+            NetworkEvent::NatStatus(status) => {
+                if status == self.nat {
+                    return Ok(());
+                }
+                match &status {
+                    Reachability::Private => {
+                        self.notice("You are behind NAT — joiners will connect via relay.")
+                    }
+                    Reachability::Public(addr) => self.notice(&format!(
+                        "Directly reachable at {addr} — new room codes lead with it."
+                    )),
+                    Reachability::Unknown => {}
+                }
+                self.nat = status;
+            }
+
+            NetworkEvent::PeerInfo(infos) => {
+                if let Some(room) = self.peers_query.take() {
+                    self.list_members(Some(&room), &infos);
+                }
+            }
+
+            NetworkEvent::ConnectionLost => {
+                if !self.rooms.is_empty() && self.reconnect.is_none() {
+                    self.notice("Connection lost — reconnecting…");
+                    self.reconnect = Some(Reconnect {
+                        attempt: 0,
+                        next_at: tokio::time::Instant::now(),
+                    });
+                    self.emit_status();
+                }
+            }
+
+            NetworkEvent::PeerConnected(peer_id) => {
+                if self.reconnect.take().is_some() {
+                    // Resubscribing is a no-op for a swarm that kept the
+                    // topics, and restores them for one that did not.
+                    for room in &mut self.rooms {
+                        room.rejoin = true;
+                        let topic = room.state.topic.clone();
+                        let _ = self.net_cmd_tx.send(NetworkCommand::Subscribe(topic));
+                    }
+                    self.notice("Connection restored — rejoining rooms.");
+                }
+                // Joins by name wait for the salt (see `PeerSubscribed`).
+                if self.join.as_ref().is_some_and(|j| {
+                    j.state == JoinState::Dialing
+                        && j.hosts.contains(&peer_id)
+                        && j.room_key().is_some()
+                }) {
+                    self.search_members()?;
+                }
+            }
+
+            NetworkEvent::DialFailed(peer_id) => {
+                // Once every address / host is unreachable, subscribe
+                // anyway and let mDNS / the mesh find other members.
+                if let Some(join) = self.join.as_mut()
+                    && join.state == JoinState::Dialing
+                    && (peer_id.is_empty() || join.hosts.contains(&peer_id))
+                {
+                    join.dials_pending = join.dials_pending.saturating_sub(1);
+                    if join.dials_pending == 0 && !join.discovering {
+                        self.search_members()?;
+                    }
+                }
+            }
+
+            NetworkEvent::RendezvousFound { namespace, peers } => {
+                let own_id = self.identity.peer_id.to_string();
+                let Some(join) = self.join.as_mut().filter(|j| {
+                    j.state == JoinState::Dialing && namespace == rendezvous_namespace(&j.room_name)
+                }) else {
+                    return Ok(());
+                };
+                join.discovering = false;
+                let new: Vec<String> = peers
+                    .into_iter()
+                    .filter(|p| *p != own_id && !join.hosts.contains(p))
+                    .collect();
+                join.dials_pending += new.len();
+                join.hosts.extend(new.iter().cloned());
+                if join.dials_pending == 0 {
+                    // Nothing left to dial: fall back to mDNS / the mesh.
+                    self.search_members()?;
+                }
+                for peer in new {
+                    let _ = self.net_cmd_tx.send(NetworkCommand::DialPeer(peer));
+                }
+            }
+
+            NetworkEvent::ProvidersFound { key, peers } => {
+                let own_id = self.identity.peer_id.to_string();
+                let Some(join) = self.join.as_mut().filter(|j| {
+                    j.state == JoinState::Resolving && key == discovery_key(&j.room_name)
+                }) else {
+                    return Ok(());
+                };
+                join.hosts = peers.into_iter().filter(|p| *p != own_id).collect();
+                join.dials_pending = join.hosts.len();
+                if join.hosts.is_empty() {
+                    // Nobody announced the room (yet) — fall back to mDNS /
+                    // the mesh, like a code without addresses.
+                    self.search_members()?;
+                } else {
+                    for peer in join.hosts.clone() {
+                        let _ = self.net_cmd_tx.send(NetworkCommand::DialPeer(peer));
+                    }
+                    self.set_join_state(JoinState::Dialing);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_message(
+        &mut self,
+        topic: String,
+        source: String,
+        payload: Vec<u8>,
+    ) -> Result<()> {
+        if payload.len() > MAX_CIPHERTEXT_LEN {
+            warn!("Dropping oversized payload ({} bytes)", payload.len());
+            return Ok(());
+        }
+        // Before decrypting: a flood must not cost an AES-GCM attempt each.
+        if !source.is_empty() {
+            match self.rate.check(&source, Utc::now().timestamp_millis()) {
+                RateVerdict::Allow => {}
+                RateVerdict::Drop => return Ok(()),
+                RateVerdict::Flood => {
+                    warn!("Peer {source} is flooding; graylisting it");
+                    let _ = self.net_cmd_tx.send(NetworkCommand::GraylistPeer(source));
+                    return Ok(());
+                }
+            }
+        }
+
+        if let Some(me) = self.inbox_owner(&topic) {
+            if let Err(e) = self.handle_direct(&payload, &me) {
+                debug!("Rejected direct message: {e}");
+            }
+            return Ok(());
+        }
+        if topic == DIRECTORY_TOPIC {
+            if let Err(e) = self.handle_directory(&payload) {
+                debug!("Rejected directory message: {e}");
+            }
+            return Ok(());
+        }
+
+        // ── Normal message for one of our rooms ───────────────────────────────
+        let Some(index) = self.room_index_by_topic(&topic) else {
+            return Ok(());
+        };
+        let room = &self.rooms[index];
+
+        let plaintext = match room.decrypt(&payload) {
+            Ok(p) => p,
+            Err(_) => return Ok(()), // Silently discard — wrong key or noise.
+        };
+        let now_ms = Utc::now().timestamp_millis();
+        if let Some(capture) = &self.capture {
+            let _ = capture.send(NetworkEvent::RoomPlaintext {
+                room: room.state.name.clone(),
+                owner: room.owner.clone(),
+                data: plaintext.clone(),
+                received_ms: now_ms,
+            });
+        }
+        self.handle_room_plaintext(index, plaintext, now_ms)
+    }
+
+    /// Act on a decrypted message of room `index`, received at `now_ms` —
+    /// when it was recorded, for a replayed one.
+    fn handle_room_plaintext(
+        &mut self,
+        index: usize,
+        plaintext: Vec<u8>,
+        now_ms: i64,
+    ) -> Result<()> {
+        let room = &self.rooms[index];
+        let wire = match wire::decode(&plaintext) {
+            Ok(w) => w,
+            Err(e) => {
+                tracing::debug!("Rejected malformed message: {e}");
+                return Ok(());
+            }
+        };
+
+        // `None` for unsigned messages and forged names.
+        let signer = wire::verify(&wire);
+
+        // Skip echo of our own messages (we display them immediately on send).
+        if signer == Some(self.me(index).peer_id) {
+            return Ok(());
+        }
+
+        let room_name = room.state.name.clone();
+        let (nick, disc) = (&wire.sender_nick, &wire.sender_disc);
+        let me = self.me(index).clone();
+        if let Some(peer) = signer
+            && let Some(notice) = self.rooms[index].note_name(nick, disc, &peer, &me)
+        {
+            self.record(&room_name, DisplayMessage::system(&notice));
+        }
+        let sender = self.rooms[index].shown_name(nick, disc, signer.as_ref());
+        // Signed, so it is the sender's own avatar.
+        let avatar_changed = signer.is_some() && {
+            let avatars = &mut self.rooms[index].avatars;
+            match avatar_of(&wire) {
+                Some(avatar) => avatars.insert(sender.clone(), avatar.clone()) != Some(avatar),
+                None => avatars.remove(&sender).is_some(),
+            }
+        };
+        if avatar_changed && self.rooms[index].members.contains_key(&sender) {
+            self.emit_status();
+        }
+
+        // A captured payload published again carries the same ID.
+        if !self.rooms[index].seen.check(&wire, now_ms) {
+            debug!("Dropping replayed or stale message from {sender}");
+            return Ok(());
+        }
+        if signer.is_some() {
+            self.skew.observe(&sender, wire.timestamp_ms, now_ms);
+        }
+        if self.rooms[index].is_banned(signer, &wire.sender_disc) {
+            debug!("Dropping message from banned {sender}");
+            return Ok(());
+        }
+        if matches!(wire.msg_type, WireMessageType::Chat | WireMessageType::Poll)
+            && !self.rooms[index].may_speak(signer)
+        {
+            debug!("Dropping message from {sender}, not a speaker in '{room_name}'");
+            return Ok(());
+        }
+        // Blocked peers stay members; only what they say is dropped.
+        if matches!(
+            wire.msg_type,
+            WireMessageType::Chat | WireMessageType::Reaction | WireMessageType::Poll
+        ) && self.is_blocked(signer, &sender)
+        {
+            return Ok(());
+        }
+
+        match wire.msg_type {
+            // Only valid on the inbox topic.
+            WireMessageType::Direct
+            | WireMessageType::SaltRequest
+            | WireMessageType::SaltResponse
+            | WireMessageType::PakeStart
+            | WireMessageType::PakeReply
+            | WireMessageType::Rekey
+            | WireMessageType::Ack => {}
+            // Only valid on the directory topic.
+            WireMessageType::Listing
+            | WireMessageType::Unlisting
+            | WireMessageType::ListingQuery => {}
+
+            WireMessageType::Join => {
+                let peer_id = match signer {
+                    Some(id) => id.to_string(),
+                    None => wire::valid_peer_id(&wire.text),
+                };
+                // No password check turned them away: someone got in
+                // past the limit, or while we were full.
+                if self.rooms[index].is_full_for(&peer_id) {
+                    debug!("Not taking {sender} into full '{room_name}'");
+                    return Ok(());
+                }
+                let previous = self.rooms[index]
+                    .members
+                    .insert(sender.clone(), peer_id.clone());
+                let new = previous.is_none();
+                if new {
+                    self.record(
+                        &room_name,
+                        DisplayMessage::system(&format!("{} joined the room", sender)),
+                    );
+                }
+                // Also when a known name comes back with another key.
+                if signer.is_some() && previous.as_ref() != Some(&peer_id) {
+                    self.check_pinned_name(&room_name, &sender, &peer_id);
+                }
+                // Tell the newcomer who is here.
+                let mut roster: Vec<RosterEntry> = self.rooms[index]
+                    .members
+                    .iter()
+                    .filter(|(name, _)| **name != sender)
+                    .map(|(name, peer_id)| RosterEntry {
+                        name: name.clone(),
+                        peer_id: peer_id.clone(),
+                        avatar: self.rooms[index]
+                            .avatars
+                            .get(name)
+                            .cloned()
+                            .unwrap_or_default(),
+                    })
+                    .collect();
+                roster.push(RosterEntry {
+                    name: me.display_name(),
+                    peer_id: me.peer_id.to_string(),
+                    avatar: me.avatar.clone().unwrap_or_default(),
+                });
+                let text = serde_json::to_string(&roster)?;
+                self.publish(index, WireMessageType::RosterAnnounce, text)?;
+                let room = &self.rooms[index];
+                // Who owns the room first, in order, so the settings
+                // after are checked against the right owners.
+                let synced: Vec<String> = room
+                    .roles_wire
+                    .iter()
+                    .chain(&room.subject_wire)
+                    .chain(&room.slow_mode_wire)
+                    .chain(&room.announcement_wire)
+                    .chain(&room.invite_only_wire)
+                    .chain(&room.max_members_wire)
+                    .map(|raw| B64.encode(raw))
+                    .collect();
+                for text in synced {
+                    self.publish(index, WireMessageType::TopicSync, text)?;
+                }
+                // Merging is idempotent, so every member may send them.
+                self.publish_notes(index, self.rooms[index].notes.all())?;
+                self.emit_status();
+                // Newcomers assume everyone is online.
+                if new && self.presence.status != PresenceStatus::Online {
+                    self.announce_presence(index)?;
+                }
+                if let Some(peer) = signer {
+                    self.forward_missed(index, &peer.to_string())?;
+                    // A member back from a partition may have missed a rekey.
+                    if self.rooms[index].is_owner(Some(self.me(index).peer_id)) {
+                        self.send_room_key(index, &peer)?;
+                    }
+                }
+                if new {
+                    self.call_plugins(|p, ctx| {
+                        p.on_peer_join(ctx, &room_name, &sender);
+                        false
+                    })?;
+                }
+            }
+
+            WireMessageType::Presence => {
+                // Signed only: nobody may set someone else away.
+                if signer.is_none() {
+                    return Ok(());
+                }
+                let presence = match wire::decode_presence(&wire.text) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        debug!("Rejected malformed presence: {e}");
+                        return Ok(());
+                    }
+                };
+                let known = &mut self.rooms[index].presence;
+                let changed = if presence.status == PresenceStatus::Online {
+                    known.remove(&sender).is_some()
+                } else {
+                    known.insert(sender, presence.clone()).as_ref() != Some(&presence)
+                };
+                if changed {
+                    self.emit_status();
+                }
+            }
+
+            WireMessageType::Leave => {
+                self.rooms[index].presence.remove(&sender);
+                self.rooms[index].avatars.remove(&sender);
+                if let Some(peer_id) = self.rooms[index].members.remove(&sender) {
+                    self.rooms[index].mark_absent(&peer_id);
+                    self.record(
+                        &room_name,
+                        DisplayMessage::system(&format!("{} left the room", sender)),
+                    );
+                    self.emit_status();
+                }
+            }
+
+            WireMessageType::RosterAnnounce => {
+                let own_name = me.display_name();
+                let roster = match wire::decode_roster(&wire.text) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        tracing::debug!("Rejected malformed roster: {e}");
+                        return Ok(());
+                    }
+                };
+                let own_id = me.peer_id.to_string();
+                let others = roster.into_iter().filter(|e| {
+                    if e.peer_id.is_empty() {
+                        e.name != own_name
+                    } else {
+                        e.peer_id != own_id
+                    }
+                });
+                for entry in others {
+                    let name = match (entry.peer_id.parse::<PeerId>(), entry.name.rsplit_once('#'))
+                    {
+                        (Ok(peer), Some((nick, disc))) => {
+                            if let Some(notice) =
+                                self.rooms[index].note_name(nick, disc, &peer, &me)
+                            {
+                                self.record(&room_name, DisplayMessage::system(&notice));
+                            }
+                            self.rooms[index].shown_name(nick, disc, Some(&peer))
+                        }
+                        _ => entry.name,
+                    };
+                    self.check_pinned_name(&room_name, &name, &entry.peer_id);
+                    if !entry.avatar.is_empty() {
+                        self.rooms[index]
+                            .avatars
+                            .entry(name.clone())
+                            .or_insert(entry.avatar);
+                    }
+                    self.rooms[index]
+                        .members
+                        .entry(name)
+                        .or_insert(entry.peer_id);
+                }
+                self.emit_status();
+
+                // First member to answer our `Join` serves the history.
+                if self.rooms[index].history_sync == HistorySync::Wanted {
+                    self.publish(index, WireMessageType::HistoryRequest, sender.clone())?;
+                    self.rooms[index].history_sync = HistorySync::Requested(sender);
+                }
+            }
+
+            WireMessageType::HistoryRequest => {
+                let expired = signer
+                    .is_some_and(|p| self.rooms[index].invite_expired(&p.to_string(), now_ms));
+                if expired {
+                    debug!("Not serving history to {sender}: their invite expired");
+                } else if wire.text == me.display_name() {
+                    let snapshot = self.rooms[index].history_snapshot();
+                    let text = serde_json::to_string(&snapshot)?;
+                    self.publish(index, WireMessageType::HistoryResponse, text)?;
+                }
+            }
+
+            WireMessageType::HistoryResponse => {
+                if self.rooms[index].history_sync != HistorySync::Requested(sender.clone()) {
+                    return Ok(()); // Meant for someone else.
+                }
+                self.rooms[index].history_sync = HistorySync::Done;
+                let mut entries = match wire::decode_history(&wire.text) {
+                    Ok(e) => e,
+                    Err(e) => {
+                        tracing::debug!("Rejected malformed history: {e}");
+                        return Ok(());
+                    }
+                };
+                entries.retain(|e| !self.is_blocked(None, &e.sender));
+                if self.rooms[index].prepend_history(entries, &sender, &self.skew) > 0
+                    && self.active.as_deref() == Some(room_name.as_str())
+                {
+                    self.emit(UiEvent::RoomSwitched {
+                        name: room_name,
+                        history: self.rooms[index].view(),
+                    });
+                }
+            }
+
+            WireMessageType::Topic => {
+                if !self.rooms[index].is_admin(signer) {
+                    debug!("Ignoring topic not from an owner of '{room_name}'");
+                    return Ok(());
+                }
+                self.apply_subject(index, &wire, plaintext);
+            }
+
+            WireMessageType::SlowMode => {
+                if !self.rooms[index].is_admin(signer) {
+                    debug!("Ignoring slow mode not from an owner of '{room_name}'");
+                    return Ok(());
+                }
+                self.apply_slow_mode(index, &wire, plaintext)?;
+            }
+
+            WireMessageType::Capacity => {
+                if !self.rooms[index].is_owner(signer) {
+                    debug!("Ignoring member limit not from the owner of '{room_name}'");
+                    return Ok(());
+                }
+                self.apply_capacity(index, &wire, plaintext)?;
+            }
+
+            WireMessageType::InviteOnly => {
+                if !self.rooms[index].is_owner(signer) {
+                    debug!("Ignoring invite-only mode not from the owner of '{room_name}'");
+                    return Ok(());
+                }
+                self.apply_invite_only(index, &wire, plaintext)?;
+            }
+
+            WireMessageType::Announcement => {
+                if !self.rooms[index].is_admin(signer) {
+                    debug!("Ignoring announcement mode not from an owner of '{room_name}'");
+                    return Ok(());
+                }
+                self.apply_announcement(index, &wire, plaintext)?;
+            }
+
+            WireMessageType::Rename => {
+                if !self.rooms[index].is_owner(signer) {
+                    debug!("Ignoring rename not from the owner of '{room_name}'");
+                    return Ok(());
+                }
+                self.apply_rename(index, &wire)?;
+            }
+
+            WireMessageType::GrantAdmin => {
+                if !self.rooms[index].is_owner(signer) {
+                    debug!("Ignoring co-owner grant not from the owner of '{room_name}'");
+                    return Ok(());
+                }
+                self.apply_admin_grant(index, &wire, plaintext)?;
+            }
+
+            WireMessageType::TransferOwnership => {
+                if !self.rooms[index].may_set(&wire.msg_type, signer) {
+                    debug!("Ignoring ownership transfer not from the owner of '{room_name}'");
+                    return Ok(());
+                }
+                self.apply_transfer(index, &wire, plaintext)?;
+            }
+
+            WireMessageType::TopicSync => {
+                // The original message, so its signature still proves
+                // who set the topic.
+                let inner = B64
+                    .decode(&wire.text)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|raw| Ok((wire::decode(&raw)?, raw)));
+                match inner {
+                    Ok((inner, raw))
+                        if self.rooms[index].may_set(&inner.msg_type, wire::verify(&inner)) =>
+                    {
+                        match inner.msg_type {
+                            WireMessageType::Topic => self.apply_subject(index, &inner, raw),
+                            WireMessageType::SlowMode => {
+                                self.apply_slow_mode(index, &inner, raw)?
+                            }
+                            WireMessageType::Announcement => {
+                                self.apply_announcement(index, &inner, raw)?
+                            }
+                            WireMessageType::InviteOnly => {
+                                self.apply_invite_only(index, &inner, raw)?
+                            }
+                            WireMessageType::Capacity => self.apply_capacity(index, &inner, raw)?,
+                            WireMessageType::GrantAdmin => {
+                                self.apply_admin_grant(index, &inner, raw)?
+                            }
+                            WireMessageType::TransferOwnership => {
+                                self.apply_transfer(index, &inner, raw)?
+                            }
+                            _ => {
+                                debug!("Rejected topic sync of {:?} from {sender}", inner.msg_type)
+                            }
+                        }
+                    }
+                    _ => debug!("Rejected topic sync from {sender}"),
+                }
+            }
+
+            WireMessageType::Kick | WireMessageType::Ban => {
+                let room = &self.rooms[index];
+                if !room.is_admin(signer) {
+                    debug!(
+                        "Ignoring {:?} not from an owner of '{room_name}'",
+                        wire.msg_type
+                    );
+                    return Ok(());
+                }
+                // Co-owners cannot remove the owner or each other.
+                if room.role(&wire.text).is_some() && !room.is_owner(signer) {
+                    debug!(
+                        "Ignoring {:?} of an owner of '{room_name}' by a co-owner",
+                        wire.msg_type
+                    );
+                    return Ok(());
+                }
+                let by = signer.map(|p| p.to_string()).unwrap_or_default();
+                let acted_by = room.acted_by(&by);
+                if wire.text == me.peer_id.to_string() {
+                    let verb = match wire.msg_type {
+                        WireMessageType::Ban => "banned",
+                        _ => "kicked",
+                    };
+                    let was_active = self.active.as_deref() == Some(room_name.as_str());
+                    self.remove_room(index);
+                    if was_active {
+                        self.active = None;
+                        self.show_next_room()?;
+                    } else {
+                        self.emit_status();
+                    }
+                    self.notice(&format!(
+                        "You were {} from '{}' by {}.",
+                        verb, room_name, acted_by
+                    ));
+                } else {
+                    self.remove_member(index, &wire.text, wire.msg_type, &by);
+                }
+            }
+
+            WireMessageType::Reaction => match wire::decode_reaction(&wire.text) {
+                Ok(reaction) => self.apply_reaction(index, &sender, reaction),
+                Err(e) => debug!("Rejected reaction from {sender}: {e}"),
+            },
+
+            // Signed only: votes count once per key, and only the
+            // creator may close a poll.
+            WireMessageType::Poll | WireMessageType::Vote | WireMessageType::PollEnd
+                if signer.is_none() =>
+            {
+                debug!("Dropping unsigned {:?} from {sender}", wire.msg_type);
+            }
+
+            WireMessageType::Poll => match wire::decode_poll(&wire.text) {
+                Ok(poll) => {
+                    let creator = signer.map(|p| p.to_string()).unwrap_or_default();
+                    let mut msg = DisplayMessage::chat(&sender, &poll.question)
+                        .with_id(wire.msg_id)
+                        .with_alias(self.contacts.alias(&creator).map(str::to_string))
+                        .with_avatar(avatar_of(&wire));
+                    if self.contacts.is_verified(&creator) {
+                        msg = msg.trusted();
+                    }
+                    self.add_poll(index, creator, poll, msg, wire.timestamp_ms);
+                }
+                Err(e) => debug!("Rejected poll from {sender}: {e}"),
+            },
+
+            WireMessageType::Vote => match wire::decode_vote(&wire.text) {
+                Ok(vote) => {
+                    let voter = signer.map(|p| p.to_string()).unwrap_or_default();
+                    self.apply_vote(index, voter, vote, wire.timestamp_ms);
+                }
+                Err(e) => debug!("Rejected vote from {sender}: {e}"),
+            },
+
+            WireMessageType::PollEnd => {
+                let creator = signer.map(|p| p.to_string());
+                let poll = wire.text.parse().ok().filter(|id| {
+                    self.rooms[index]
+                        .polls
+                        .get(id)
+                        .is_some_and(|p| Some(&p.creator) == creator.as_ref())
+                });
+                match poll {
+                    Some(msg_id) => self.close_poll(index, msg_id),
+                    None => debug!("Rejected poll end from {sender}"),
+                }
+            }
+
+            WireMessageType::Notes => match wire::decode_notes(&wire.text) {
+                Ok(lines) => {
+                    let notes = &mut self.rooms[index].notes;
+                    // Every line is merged, changed or not.
+                    let changed = lines
+                        .into_iter()
+                        .fold(false, |changed, line| notes.merge(line) | changed);
+                    if changed {
+                        self.show_notes(index);
+                    }
+                }
+                Err(e) => debug!("Rejected notes from {sender}: {e}"),
+            },
+
+            WireMessageType::Forward => {
+                if let Err(e) = self.receive_forwarded(index, &sender, &wire.text) {
+                    debug!("Rejected forwarded messages from {sender}: {e}");
+                }
+            }
+
+            WireMessageType::Chat => {
+                let room = &mut self.rooms[index];
+                let chunked = wire.chunk.is_some();
+                let wire = if chunked {
+                    // Parts are held (and forwarded) one by one.
+                    if signer.is_some() {
+                        room.hold_for_absent(&plaintext);
+                    }
+                    let from = signer.map_or_else(|| sender.clone(), |p| p.to_string());
+                    match room.chunks.accept(&from, wire, now_ms) {
+                        Some(whole) => whole,
+                        None => return Ok(()),
+                    }
+                } else {
+                    wire
+                };
+                // Already forwarded to us by another member.
+                if !room.note_chat(&sender, wire.msg_id) {
+                    return Ok(());
+                }
+                if signer.is_some() && !chunked {
+                    room.hold_for_absent(&plaintext);
+                }
+                // A sender we missed the `Join` of — it holds the key, so it
+                // is a member.  If it was away, it may have missed messages.
+                if !room.members.contains_key(&sender) {
+                    let peer_id = signer.map(|p| p.to_string()).unwrap_or_default();
+                    room.members.insert(sender.clone(), peer_id.clone());
+                    if signer.is_some() {
+                        self.forward_missed(index, &peer_id)?;
+                    }
+                }
+                if let Some(peer) = signer {
+                    self.check_pinned_name(&room_name, &sender, &peer.to_string());
+                }
+                let mut msg = DisplayMessage::chat(&sender, &wire.text)
+                    .with_id(wire.msg_id)
+                    .in_thread(thread_of(&wire));
+                if wire.reply_to != 0 {
+                    msg = msg.with_quote(self.rooms[index].quote(wire.reply_to));
+                }
+                match signer {
+                    None => msg = msg.unverified(),
+                    Some(peer) if self.contacts.is_verified(&peer.to_string()) => {
+                        msg = msg.trusted()
+                    }
+                    Some(_) => {}
+                }
+                if let Some(peer) = signer {
+                    msg = msg
+                        .with_alias(self.contacts.alias(&peer.to_string()).map(str::to_string))
+                        .with_avatar(avatar_of(&wire));
+                }
+                self.record(&room_name, msg.clone());
+                self.send_ack(&me, signer, wire.msg_id);
+                self.call_plugins(|p, ctx| {
+                    p.on_message(ctx, &room_name, &msg);
+                    false
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stand in for room `name` of a replayed capture, whose key we never
+    /// had: its messages arrive already decrypted.  `owner` is who the
+    /// recording member took for the owner.
+    fn enter_replayed_room(&mut self, name: &str, owner: String) -> usize {
+        let salt = random_salt();
+        let state = RoomState::new(name, &salt);
+        self.rooms
+            .push(JoinedRoom::new(state, RoomKey::generate(), salt, owner));
+        self.active = Some(name.to_string());
+        self.bus.publish(AppEvent::RoomEntered(name.to_string()));
+        self.emit(UiEvent::RoomJoined(name.to_string()));
+        self.emit_status();
+        self.rooms.len() - 1
+    }
+
+    /// Who `topic` is the inbox of: us, or the persona of a room or of the
+    /// room being joined.
+    fn inbox_owner(&self, topic: &str) -> Option<Identity> {
+        let joining = self.join.as_ref().and_then(|j| j.persona.as_ref());
+        std::iter::once(&self.identity)
+            .chain(self.rooms.iter().filter_map(|r| r.persona.as_ref()))
+            .chain(joining)
+            .find(|id| topic == inbox_topic(&id.peer_id.to_string()))
+            .cloned()
+    }
+
+    /// Handle a message from the inbox topic of `me`.  It must decrypt with
+    /// the key we share with the claimed sender *and* carry that sender's
+    /// signature; anything else is dropped.
+    fn handle_direct(&mut self, payload: &[u8], me: &Identity) -> Result<()> {
+        let envelope: DirectEnvelope = serde_json::from_slice(payload)?;
+        let from: PeerId = envelope.from.parse()?;
+        let key = RoomKey::direct(&me.keypair, &from)?;
+        let wire = wire::decode(&key.decrypt(&B64.decode(&envelope.data)?)?)?;
+        if wire::verify(&wire) != Some(from) {
+            bail!("direct message not signed by {from}");
+        }
+        if !self.inbox_seen.check(&wire, Utc::now().timestamp_millis()) {
+            bail!("replayed or stale direct message from {from}");
+        }
+
+        match wire.msg_type {
+            WireMessageType::Direct if self.is_blocked(Some(from), "") => return Ok(()),
+            WireMessageType::Direct => {}
+            WireMessageType::SaltRequest => {
+                // The salt is not secret; any member may hand it out, so
+                // whoever guesses a room's name can find its topic.
+                if let Some(index) = self.room_index(&wire.text) {
+                    let reply = RoomSaltReply {
+                        room: wire.text,
+                        salt: B64.encode(self.rooms[index].salt),
+                        owner: self.rooms[index].owner.clone(),
+                        owner_proof: self
+                            .ownership(index)
+                            .map(|proof| B64.encode(proof))
+                            .unwrap_or_default(),
+                    };
+                    let text = serde_json::to_string(&reply)?;
+                    self.send_direct_wire(me, &from, WireMessageType::SaltResponse, text)?;
+                }
+                return Ok(());
+            }
+            WireMessageType::SaltResponse => return self.apply_salt(&from, &wire.text),
+            WireMessageType::PakeStart => {
+                let start: PakeStart = serde_json::from_str(&wire.text)?;
+                let Some(index) = self.room_index(&start.room) else {
+                    return Ok(());
+                };
+                if self.rooms[index].is_banned(Some(from), &wire.sender_disc) {
+                    return Ok(());
+                }
+                let room = &self.rooms[index];
+                let full = room
+                    .is_full_for(&from.to_string())
+                    .then_some(room.max_members);
+                let now_ms = Utc::now().timestamp_millis();
+                let admitted = match full {
+                    Some(_) => Err("the room is full"),
+                    None => self.rooms[index].admit(&from.to_string(), &start.invite, now_ms),
+                };
+                if let Err(why) = admitted {
+                    debug!("Not checking the password of {from}: {why}");
+                    let reply = PakeReply {
+                        room: start.room,
+                        msg: String::new(),
+                        confirm: String::new(),
+                        epoch: 0,
+                        key: String::new(),
+                        refused: why.to_string(),
+                        full: full.unwrap_or(0),
+                        owner: String::new(),
+                        owner_proof: String::new(),
+                    };
+                    let text = serde_json::to_string(&reply)?;
+                    self.send_direct_wire(me, &from, WireMessageType::PakeReply, text)?;
+                    return Ok(());
+                }
+                let room = &self.rooms[index];
+                let response =
+                    room.key
+                        .pake_respond(&me.peer_id, &from, &B64.decode(&start.msg)?)?;
+                // Only a joiner with the password can open the epoch key.
+                let key = match &room.epoch_key {
+                    Some(key) => B64.encode(response.session.encrypt(key.to_bytes().as_slice())?),
+                    None => String::new(),
+                };
+                let owner_proof = self.ownership(index);
+                let reply = PakeReply {
+                    room: start.room,
+                    msg: B64.encode(response.msg),
+                    confirm: B64.encode(response.confirm),
+                    epoch: room.epoch,
+                    key,
+                    refused: String::new(),
+                    full: 0,
+                    owner: match owner_proof {
+                        Some(_) => room.owner.clone(),
+                        None => String::new(),
+                    },
+                    owner_proof: owner_proof
+                        .map(|proof| B64.encode(proof))
+                        .unwrap_or_default(),
+                };
+                let text = serde_json::to_string(&reply)?;
+                self.send_direct_wire(me, &from, WireMessageType::PakeReply, text)?;
+                return Ok(());
+            }
+            WireMessageType::PakeReply => return self.apply_pake_reply(&from, &wire.text),
+            WireMessageType::Rekey => return self.apply_rekey(&from, &wire.text),
+            WireMessageType::Ack => {
+                self.mark_delivered(wire.text.parse()?);
+                return Ok(());
+            }
+            other => bail!("unexpected {other:?} in inbox"),
+        }
+
+        self.send_ack(me, Some(from), wire.msg_id);
+        let sender = format!("{}#{}", wire.sender_nick, wire.sender_disc);
+        let me = me.display_name();
+        let mut msg = DisplayMessage::direct(&sender, &me, &wire.text)
+            .with_alias(
+                self.contacts
+                    .alias(&from.to_string())
+                    .map(|a| format!("{a} → {me}")),
+            )
+            .with_avatar(avatar_of(&wire));
+        if self.contacts.is_verified(&from.to_string()) {
+            msg = msg.trusted();
+        }
+        // Show it where the user is looking; it belongs to no room.
+        match self
+            .active
+            .clone()
+            .or_else(|| self.rooms.first().map(|r| r.state.name.clone()))
+        {
+            Some(room) => self.record(&room, msg),
+            None => self.emit(UiEvent::NewMessage(msg)),
+        }
+        Ok(())
+    }
+
+    /// A member we are joining through answered our `SaltRequest`: derive
+    /// the room key and go on to the topic.
+    fn apply_salt(&mut self, from: &PeerId, text: &str) -> Result<()> {
+        let reply: RoomSaltReply = serde_json::from_str(text)?;
+        let Some(join) = self
+            .join
+            .as_mut()
+            .filter(|j| j.room_name == reply.room && j.hosts.contains(&from.to_string()))
+        else {
+            return Ok(()); // Not asked for, or a late answer.
+        };
+        let JoinKey::AwaitingSalt = &join.key else {
+            return Ok(()); // Another member answered first.
+        };
+        let salt: RoomSalt = B64
+            .decode(&reply.salt)?
+            .try_into()
+            .map_err(|_| anyhow!("salt is not {SALT_LEN} bytes"))?;
+        join.key_ready(RoomKey::derive(&join.credentials.password, &salt)?, salt);
+        // An owner without its statement is only that member's word.
+        let proof = B64.decode(&reply.owner_proof).unwrap_or_default();
+        if verify_ownership(&reply.owner, &reply.room, &salt, &proof) {
+            join.owner = reply.owner;
+            join.owner_proof = proof;
+        }
+        self.search_members()
+    }
+
+    /// A member answered our `PakeStart`.  One confirmed handshake lets us
+    /// in; we are denied once every member asked has answered (or refused
+    /// our invite) and none confirmed.
+    fn apply_pake_reply(&mut self, from: &PeerId, text: &str) -> Result<()> {
+        let reply: PakeReply = serde_json::from_str(text)?;
+        let Some(join) = self.join.as_mut().filter(|j| j.room_name == reply.room) else {
+            return Ok(()); // Not asked for, or a late answer.
+        };
+        let Some(handshake) = join.handshakes.remove(&from.to_string()) else {
+            return Ok(());
+        };
+        if !reply.refused.is_empty() {
+            join.refused = Some(reply.refused);
+            join.full = reply.full;
+            if join.handshakes.is_empty() {
+                self.finish_join(JoinState::Denied);
+            }
+            return Ok(());
+        }
+        let (msg, confirm) = (B64.decode(&reply.msg)?, B64.decode(&reply.confirm)?);
+        match handshake.finish(&msg, &confirm) {
+            Some(session) => {
+                if !reply.key.is_empty() {
+                    let key = Zeroizing::new(session.decrypt(&B64.decode(&reply.key)?)?);
+                    join.epoch = Some((reply.epoch, RoomKey::from_bytes(&key)?));
+                }
+                let proof = B64.decode(&reply.owner_proof).unwrap_or_default();
+                if let JoinKey::Ready(_, salt) = &join.key
+                    && verify_ownership(&reply.owner, &join.room_name, salt, &proof)
+                {
+                    if join.owner.is_empty() || join.owner == reply.owner {
+                        join.owner = reply.owner;
+                        join.owner_proof = proof;
+                    } else {
+                        join.disputed = reply.owner;
+                    }
+                }
+                self.finish_join(JoinState::Joined);
+            }
+            None if join.handshakes.is_empty() => self.finish_join(JoinState::Denied),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// The owner or a co-owner of a room moved it to a new key epoch.
+    fn apply_rekey(&mut self, from: &PeerId, text: &str) -> Result<()> {
+        let rekey: RoomRekey = serde_json::from_str(text)?;
+        let Some(index) = self.room_index(&rekey.room) else {
+            return Ok(());
+        };
+        let room = &mut self.rooms[index];
+        // A co-owner rotates the key after removing a member.
+        if !room.is_admin(Some(*from)) {
+            bail!("rekey of '{}' not from an owner", rekey.room);
+        }
+        if rekey.epoch <= room.epoch {
+            return Ok(()); // Sent again on our `Join`, or out of order.
+        }
+        let key = Zeroizing::new(B64.decode(&rekey.key)?);
+        room.install_key(rekey.epoch, RoomKey::from_bytes(&key)?);
+        let text = format!(
+            "The room key was rotated by {}",
+            room.acted_by(&from.to_string())
+        );
+        self.record(&rekey.room, DisplayMessage::system(&text));
+        Ok(())
+    }
+
+    // ── Join flow ─────────────────────────────────────────────────────────────
+
+    /// Move the join flow to `state`, restart its timeout and tell the UI.
+    fn set_join_state(&mut self, state: JoinState) {
+        let Some(join) = self.join.as_mut() else {
+            return;
+        };
+        join.state = state;
+        join.deadline = tokio::time::Instant::now() + JOIN_STEP_TIMEOUT;
+        let room = join.room_name.clone();
+        self.emit(UiEvent::JoinProgress { room, state });
+    }
+
+    /// Dialing is over (connected, failed or timed out) — check our
+    /// password with the members seen so far, and any that show up later.
+    fn search_members(&mut self) -> Result<()> {
+        let Some(join) = self.join.as_mut() else {
+            return Ok(());
+        };
+        if let JoinKey::AwaitingSalt = &join.key {
+            // No member told us the salt: assume a room from before room
+            // code v2, salted with its name.
+            let salt = legacy_salt(&join.room_name);
+            match RoomKey::derive(&join.credentials.password, &salt) {
+                Ok(key) => join.key_ready(key, salt),
+                Err(e) => {
+                    warn!("Cannot derive room key: {e}");
+                    self.join = None;
+                    return Ok(());
+                }
+            }
+        }
+        self.set_join_state(JoinState::Searching);
+        self.start_handshakes()
+    }
+
+    /// Send a `PakeStart` to every member of the room being joined that has
+    /// none yet.  The handshake proves we hold the same room key without
+    /// revealing anything about it, so nothing encrypted under the key
+    /// reaches us before it succeeds.
+    fn start_handshakes(&mut self) -> Result<()> {
+        let own = self.joining_as().peer_id;
+        let Some(join) = self.join.as_mut() else {
+            return Ok(());
+        };
+        if !matches!(
+            join.state,
+            JoinState::Searching | JoinState::AwaitingVerification
+        ) {
+            return Ok(());
+        }
+        let Some(key) = join.room_key() else {
+            return Ok(());
+        };
+        let mut started = Vec::new();
+        for member in join
+            .members
+            .iter()
+            .filter(|m| !join.handshakes.contains_key(*m))
+        {
+            let Ok(peer) = member.parse::<PeerId>() else {
+                continue;
+            };
+            let (handshake, msg) = key.pake_start(&own, &peer);
+            let start = PakeStart {
+                room: join.room_name.clone(),
+                msg: B64.encode(msg),
+                invite: join.invite.clone(),
+            };
+            started.push((
+                member.clone(),
+                handshake,
+                peer,
+                serde_json::to_string(&start)?,
+            ));
+        }
+        if started.is_empty() {
+            return Ok(());
+        }
+        let mut starts = Vec::new();
+        for (member, handshake, peer, text) in started {
+            join.handshakes.insert(member, handshake);
+            starts.push((peer, text));
+        }
+        if join.state == JoinState::Searching {
+            self.set_join_state(JoinState::AwaitingVerification);
+        }
+        for (peer, text) in starts {
+            self.send_direct_wire(self.joining_as(), &peer, WireMessageType::PakeStart, text)?;
+        }
+        Ok(())
+    }
+
+    /// Take the join flow to a terminal `outcome` and act on it.
+    fn finish_join(&mut self, outcome: JoinState) {
+        debug_assert!(outcome.is_terminal());
+        let Some(join) = self.join.take() else { return };
+        let room_name = join.room_name;
+        self.emit(UiEvent::JoinProgress {
+            room: room_name.clone(),
+            state: outcome,
+        });
+
+        if outcome == JoinState::Denied {
+            self.drop_persona(join.persona);
+            match join.full {
+                0 => self.emit(UiEvent::AccessDenied(join.refused)),
+                max_members => self.emit(UiEvent::RoomFull {
+                    room: room_name,
+                    max_members,
+                }),
+            }
+            if self.active.is_none() {
+                self.emit(UiEvent::ShowMainMenu);
+            }
+            return;
+        }
+
+        // Joined, or TimedOut — no member answered, so assume an empty room /
+        // creator offline and let the user in with the key they provided.
+        let JoinKey::Ready(key, salt) = join.key else {
+            warn!("Join of '{room_name}' ended without a room key");
+            self.drop_persona(join.persona);
+            return;
+        };
+        self.remember_room(&room_name, &join.credentials.code);
+        let mut room = JoinedRoom::new(RoomState::new(&room_name, &salt), key, salt, join.owner);
+        room.owner_proof = join.owner_proof;
+        room.owner_disputed = join.disputed;
+        room.credentials = join.credentials;
+        if let Some((epoch, key)) = join.epoch {
+            room.epoch = epoch;
+            room.epoch_key = Some(key);
+        }
+        room.history_sync = HistorySync::Wanted;
+        room.persona = join.persona;
+        let _ = self
+            .net_cmd_tx
+            .send(NetworkCommand::Subscribe(room.state.topic.clone()));
+        self.rooms.push(room);
+        self.active = Some(room_name.clone());
+        let index = self.rooms.len() - 1;
+        self.announce_room(&room_name);
+        let peer_id = self.me(index).peer_id.to_string();
+        // A single-use invite is spent on us, for when we answer joiners.
+        let _ = self.rooms[index].admit(&peer_id, &join.invite, Utc::now().timestamp_millis());
+        if let Err(e) = self.publish(index, WireMessageType::Join, peer_id) {
+            warn!("Cannot announce join: {e}");
+        }
+        if self.presence.status != PresenceStatus::Online
+            && let Err(e) = self.announce_presence(index)
+        {
+            warn!("Cannot announce presence: {e}");
+        }
+        self.bus.publish(AppEvent::RoomEntered(room_name.clone()));
+        self.emit(UiEvent::RoomJoined(room_name.clone()));
+        let room = &self.rooms[index];
+        if !room.owner_disputed.is_empty() {
+            let text = format!(
+                "Your code names {} as the owner of '{room_name}', but the member who let you in names {}, and both signed for the room. Neither is obeyed as owner until one hands the room to the other; ask the owner for a new code.",
+                room.owner, room.owner_disputed
+            );
+            self.record(&room_name, DisplayMessage::system(&text));
+        }
+        self.note_persona(index);
+        self.emit_status();
+    }
+
+    fn check_join_timeout(&mut self) {
+        let Some(state) = self
+            .join
+            .as_ref()
+            .filter(|j| tokio::time::Instant::now() >= j.deadline)
+            .map(|j| j.state)
+        else {
+            return;
+        };
+
+        match state {
+            JoinState::Resolving | JoinState::Dialing => {
+                if let Err(e) = self.search_members() {
+                    warn!("Cannot check password: {e}");
+                }
+            }
+            _ => self.finish_join(JoinState::TimedOut),
+        }
+    }
+
+    /// While offline, dial every peer we shared a room with, backing off
+    /// exponentially between rounds.  The first connection ends it (see
+    /// `PeerConnected`).
+    fn check_reconnect(&mut self) {
+        let now = tokio::time::Instant::now();
+        let Some(reconnect) = self.reconnect.as_mut().filter(|r| now >= r.next_at) else {
+            return;
+        };
+        let delay = RECONNECT_MIN_DELAY
+            .saturating_mul(1 << reconnect.attempt.min(16))
+            .min(RECONNECT_MAX_DELAY);
+        reconnect.attempt += 1;
+        reconnect.next_at = now + delay;
+
+        let own = self.identity.peer_id.to_string();
+        let peers: BTreeSet<&String> = self
+            .rooms
+            .iter()
+            .flat_map(|r| r.members.values().chain(r.absent.keys()).chain([&r.owner]))
+            .filter(|p| !p.is_empty() && **p != own)
+            .collect();
+        debug!(
+            "Reconnect attempt {}: dialing {} peers",
+            reconnect.attempt,
+            peers.len()
+        );
+        for peer in peers {
+            let _ = self.net_cmd_tx.send(NetworkCommand::DialPeer(peer.clone()));
+        }
+    }
+
+    /// Run `hook` on each plugin in turn until one returns `true`, then
+    /// carry out what they asked for.  Returns whether one did.
+    fn call_plugins(
+        &mut self,
+        mut hook: impl FnMut(&mut dyn Plugin, &mut PluginContext) -> bool,
+    ) -> Result<bool> {
+        if self.plugins.is_empty() {
+            return Ok(false);
+        }
+        let me = self
+            .active
+            .as_deref()
+            .and_then(|n| self.room_index(n))
+            .map_or(&self.identity, |i| self.me(i));
+        let mut ctx = PluginContext::new(me.display_name(), self.active.clone());
+        let mut plugins = std::mem::take(&mut self.plugins);
+        let handled = plugins.iter_mut().any(|p| hook(p.as_mut(), &mut ctx));
+        self.plugins = plugins;
+        for action in ctx.actions {
+            match action {
+                PluginAction::Send { room, text } => match self.room_index(&room) {
+                    Some(index) => self.send_chat(index, text, 0)?,
+                    None => debug!("Plugin message for unknown room '{room}' dropped"),
+                },
+                PluginAction::Notice(text) => self.notice(&text),
+            }
+        }
+        Ok(handled)
+    }
+
+    // ── Helpers ───────────────────────────────────────────────────────────────
+
+    fn room_index(&self, name: &str) -> Option<usize> {
+        self.rooms.iter().position(|r| r.state.name == name)
+    }
+
+    fn room_index_by_topic(&self, topic: &str) -> Option<usize> {
+        self.rooms.iter().position(|r| r.state.topic == topic)
+    }
+
+    /// `/peers`: the members of `room` with their presence, then one line
+    /// per member on how we are connected to them (`infos`).
+    fn list_members(&self, room: Option<&str>, infos: &[PeerInfo]) {
+        let room = room
+            .and_then(|name| self.room_index(name))
+            .map(|i| &self.rooms[i]);
+        let list = match room {
+            Some(room) if !room.members.is_empty() => room
+                .members
+                .iter()
+                .map(|(name, peer_id)| {
+                    let label = self.member_label(room, name, peer_id);
+                    let details: Vec<String> = room
+                        .role(peer_id)
+                        .map(str::to_string)
+                        .into_iter()
+                        .chain(room.presence.get(name).map(Presence::describe))
+                        .collect();
+                    match details.is_empty() {
+                        true => label,
+                        false => format!("{} ({})", label, details.join(", ")),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            _ => "No other members.".to_string(),
+        };
+        let me = room
+            .and_then(|r| r.persona.as_ref())
+            .unwrap_or(&self.identity);
+        let mut you = format!(
+            "{} (you",
+            with_avatar(me.avatar.as_ref(), me.display_name())
+        );
+        if let Some(role) = room.and_then(|r| r.role(&me.peer_id.to_string())) {
+            you.push_str(&format!(", {role}"));
+        }
+        if self.presence.status != PresenceStatus::Online {
+            you.push_str(&format!(", {}", self.presence.describe()));
+        }
+        self.notice(&format!("Members: {}), {}", you, list));
+        for (name, peer_id) in room.iter().flat_map(|room| &room.members) {
+            let link = match infos.iter().find(|info| info.peer_id == *peer_id) {
+                Some(info) => info.describe(),
+                None => "not connected".to_string(),
+            };
+            let avatar = room.and_then(|room| room.avatars.get(name));
+            match self.contacts.alias(peer_id) {
+                Some(alias) => self.notice(&format!(
+                    "  {} ({name}): {link}",
+                    with_avatar(avatar, alias.to_string())
+                )),
+                None => self.notice(&format!("  {}: {link}", with_avatar(avatar, name.clone()))),
+            }
+        }
+    }
+
+    fn active_room(&self) -> Option<&JoinedRoom> {
+        self.active
+            .as_deref()
+            .and_then(|name| self.room_index(name))
+            .map(|i| &self.rooms[i])
+    }
+
+    /// Sign a WireMessage from us, encrypt it with room `index`'s key and
+    /// publish it.  Returns its message ID.
+    fn publish(&self, index: usize, msg_type: WireMessageType, text: String) -> Result<u64> {
+        let (msg_id, wire) = self.signed_wire(self.me(index), msg_type, text)?;
+        self.publish_wire(index, &wire)?;
+        Ok(msg_id)
+    }
+
+    /// Encrypt an encoded wire message with room `index`'s key and publish it.
+    fn publish_wire(&self, index: usize, wire: &[u8]) -> Result<()> {
+        let room = &self.rooms[index];
+        let data = room.traffic_key().encrypt(wire)?;
+        // Gossipsub would refuse it with no more than a log line.
+        ensure!(
+            data.len() <= MAX_CIPHERTEXT_LEN,
+            "Message too large to send ({} bytes).",
+            data.len()
+        );
+        let _ = self.net_cmd_tx.send(NetworkCommand::Publish {
+            topic: room.state.topic.clone(),
+            data,
+        });
+        Ok(())
+    }
+
+    /// `peer_id` is back in room `index`: send it the chat it missed, in as
+    /// few `Forward` messages as fit.
+    fn forward_missed(&mut self, index: usize, peer_id: &str) -> Result<()> {
+        let Some(queue) = self.rooms[index].absent.remove(peer_id) else {
+            return Ok(());
+        };
+        if self.rooms[index].invite_expired(peer_id, Utc::now().timestamp_millis()) {
+            return Ok(());
+        }
+        let mut batch = ForwardBatch {
+            to: peer_id.to_string(),
+            messages: Vec::new(),
+        };
+        let empty_len = serde_json::to_string(&batch)?.len();
+        let mut len = empty_len;
+        for wire in queue {
+            let entry = B64.encode(wire);
+            // Quotes and separating comma
+            let entry_len = entry.len() + 3;
+            if empty_len + entry_len > MAX_TEXT_LEN {
+                continue; // A long message that fits no batch.
+            }
+            if len + entry_len > MAX_TEXT_LEN {
+                let full = std::mem::take(&mut batch.messages);
+                let full = ForwardBatch {
+                    to: batch.to.clone(),
+                    messages: full,
+                };
+                self.publish(
+                    index,
+                    WireMessageType::Forward,
+                    serde_json::to_string(&full)?,
+                )?;
+                len = empty_len;
+            }
+            batch.messages.push(entry);
+            len += entry_len;
+        }
+        if !batch.messages.is_empty() {
+            self.publish(
+                index,
+                WireMessageType::Forward,
+                serde_json::to_string(&batch)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Show chat messages another member kept for us while we were away.
+    fn receive_forwarded(&mut self, index: usize, from: &str, text: &str) -> Result<()> {
+        let (to, messages) = wire::decode_forward(text)?;
+        let me = self.me(index).clone();
+        if to != me.peer_id.to_string() {
+            return Ok(()); // For someone else.
+        }
+        let now_ms = Utc::now().timestamp_millis();
+        let room_name = self.rooms[index].state.name.clone();
+        let mut added = Vec::new();
+        for wire in messages {
+            // Only signed messages: the forwarder must not put words in
+            // anyone's mouth.
+            let Some(signer) = wire::verify(&wire) else {
+                continue;
+            };
+            let wire = match wire.chunk {
+                None => wire,
+                Some(_) => match self.rooms[index]
+                    .chunks
+                    .accept(&signer.to_string(), wire, now_ms)
+                {
+                    Some(whole) => whole,
+                    None => continue,
+                },
+            };
+            let sender =
+                self.rooms[index].shown_name(&wire.sender_nick, &wire.sender_disc, Some(&signer));
+            let blocked = self.is_blocked(Some(signer), &sender);
+            let room = &mut self.rooms[index];
+            if signer == me.peer_id
+                || blocked
+                || now_ms - wire.timestamp_ms > FORWARD_MAX_AGE_MS
+                || wire.timestamp_ms - now_ms > wire::REPLAY_WINDOW_MS
+                || room.is_banned(Some(signer), &wire.sender_disc)
+                || !room.may_speak(Some(signer))
+                || room.history.iter().any(|m| {
+                    !m.is_system
+                        && m.sender == sender
+                        && m.text == wire.text
+                        && m.timestamp.timestamp_millis() == wire.timestamp_ms
+                })
+                || !room.note_chat(&sender, wire.msg_id)
+            {
+                continue;
+            }
+            let mut msg = DisplayMessage::chat(&sender, &wire.text)
+                .with_id(wire.msg_id)
+                .in_thread(thread_of(&wire));
+            if wire.reply_to != 0 {
+                msg = msg.with_quote(room.quote(wire.reply_to));
+            }
+            let ts = self.skew.adjust(&sender, wire.timestamp_ms, now_ms);
+            if let Some(ts) = chrono::DateTime::from_timestamp_millis(ts) {
+                msg.timestamp = ts;
+            }
+            if self.contacts.is_verified(&signer.to_string()) {
+                msg = msg.trusted();
+            }
+            msg = msg
+                .with_alias(self.contacts.alias(&signer.to_string()).map(str::to_string))
+                .with_avatar(avatar_of(&wire));
+            added.push((msg, signer, wire.msg_id));
+        }
+        let Some(first) = added.iter().map(|(msg, ..)| msg.timestamp).min() else {
+            return Ok(());
+        };
+        let mut marker = DisplayMessage::system(&format!(
+            "{} message(s) you missed, kept by {}",
+            added.len(),
+            from
+        ));
+        marker.timestamp = first;
+        self.record(&room_name, marker);
+        for (msg, signer, msg_id) in added {
+            self.record(&room_name, msg);
+            self.send_ack(&me, Some(signer), msg_id);
+        }
+        // They were shown as they came; put them in their place.
+        if self.rooms[index].sort_history() && self.active.as_deref() == Some(room_name.as_str()) {
+            self.emit(UiEvent::RoomSwitched {
+                name: room_name,
+                history: self.rooms[index].view(),
+            });
+        }
+        Ok(())
+    }
+
+    /// A WireMessage from `me`, signed and serialized, ready for
+    /// encryption, with its message ID.
+    fn signed_wire(
+        &self,
+        me: &Identity,
+        msg_type: WireMessageType,
+        text: String,
+    ) -> Result<(u64, Vec<u8>)> {
+        self.sign_and_encode(me, self.wire_message(me, msg_type, text))
+    }
+
+    /// A fresh, unsigned WireMessage from `me`.
+    fn wire_message(&self, me: &Identity, msg_type: WireMessageType, text: String) -> WireMessage {
+        WireMessage {
+            msg_type,
+            sender_nick: me.nickname.clone(),
+            sender_disc: me.discriminator.clone(),
+            timestamp_ms: Utc::now().timestamp_millis(),
+            msg_id: rand::random(),
+            text,
+            avatar: me.avatar.clone().unwrap_or_default(),
+            reply_to: 0,
+            chunk: None,
+            thread: String::new(),
+            public_key: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    /// Sign `wire` and serialize it (compressed if long and enabled);
+    /// returns its message ID too.
+    fn sign_and_encode(&self, me: &Identity, mut wire: WireMessage) -> Result<(u64, Vec<u8>)> {
+        wire::sign(&mut wire, &me.keypair)?;
+        let mut payload = wire::encode(&wire)?;
+        if self.config.compress_messages {
+            payload = wire::compress(payload);
+        }
+        Ok((wire.msg_id, payload))
+    }
+
+    fn announce_leave(&self, index: usize) {
+        if let Err(e) = self.publish(index, WireMessageType::Leave, String::new()) {
+            warn!("Cannot announce leave: {e}");
+        }
+    }
+
+    /// Publish a UI event on the bus.
+    fn emit(&self, event: UiEvent) {
+        self.bus.publish(AppEvent::Ui(event));
+    }
+
+    /// Show a transient system line that is not part of the room transcript.
+    fn notice(&self, text: &str) {
+        self.emit(UiEvent::NewMessage(DisplayMessage::system(text)));
+    }
+
+    /// Add a message to `room`'s transcript: kept in its history, logged, and
+    /// shown if the room is active.
+    fn record(&mut self, room: &str, mut msg: DisplayMessage) {
+        let Some(index) = self.room_index(room) else {
+            return;
+        };
+        let me = self.me(index);
+        let own_name = me.display_name();
+        if !msg.is_system
+            && msg.delivery.is_none()
+            && (notify::mentions(&msg.text, &me.nickname)
+                || msg.quote.as_ref().is_some_and(|q| q.sender == own_name))
+        {
+            msg.mention = true;
+            if self.mentions.len() >= MAX_MENTIONS {
+                self.mentions.pop_front();
+            }
+            self.mentions.push_back((room.to_string(), msg.clone()));
+        }
+        // The first message of a thread is pointed out in the room itself.
+        if let Some(thread) = &msg.thread
+            && msg.delivery.is_none()
+            && !self.rooms[index]
+                .history
+                .iter()
+                .any(|m| m.thread.as_ref() == Some(thread))
+        {
+            let label = msg.sender_label();
+            self.record(
+                room,
+                DisplayMessage::system(&format!(
+                    "{label} started thread '{thread}': /thread {thread} to read it"
+                )),
+            );
+        }
+        self.rooms[index].push_history(msg.clone());
+        self.bus.publish(AppEvent::RoomMessage {
+            room: room.to_string(),
+            msg: msg.clone(),
+        });
+        if self.should_notify(room, &msg) {
+            if self.config.notify_bell {
+                self.emit(UiEvent::Bell);
+            }
+            if self.config.notify_desktop {
+                self.bus.publish(AppEvent::Notify {
+                    room: room.to_string(),
+                    msg: msg.clone(),
+                });
+            }
+        }
+        if self.active.as_deref() != Some(room) {
+            return;
+        }
+        let threaded = msg.thread.is_some();
+        if msg.thread == self.rooms[index].thread {
+            self.emit(UiEvent::NewMessage(msg));
+        }
+        if threaded {
+            self.show_threads(index);
+        }
+    }
+
+    /// Someone else's message that mentions us or is addressed to us, or any
+    /// message while the terminal is unfocused — unless `room` is muted.
+    fn should_notify(&self, room: &str, msg: &DisplayMessage) -> bool {
+        if msg.is_system
+            || msg.delivery.is_some()
+            || self.presence.status == PresenceStatus::DoNotDisturb
+            || self.config.muted_rooms.iter().any(|r| r == room)
+        {
+            return false;
+        }
+        // Direct messages reach us, or a persona, wherever we look.
+        let to_us = std::iter::once(&self.identity)
+            .chain(self.rooms.iter().filter_map(|r| r.persona.as_ref()))
+            .any(|me| msg.sender.ends_with(&format!(" → {}", me.display_name())));
+        !self.focused || to_us || msg.mention
+    }
+
+    fn emit_status(&self) {
+        self.emit(UiEvent::StatusUpdate {
+            room: self.active.clone(),
+            subject: self
+                .active_room()
+                .map(|r| r.state.subject.clone())
+                .unwrap_or_default(),
+            thread: self.active_room().and_then(|r| r.thread.clone()),
+            // Members plus ourselves
+            peers: self.active_room().map(|r| r.members.len() + 1).unwrap_or(0),
+            rooms: self.rooms.iter().map(|r| r.state.name.clone()).collect(),
+            members: self
+                .active_room()
+                .map(|r| {
+                    let me = r.persona.as_ref().unwrap_or(&self.identity);
+                    let you = with_avatar(me.avatar.as_ref(), me.display_name());
+                    let you = match r.role(&me.peer_id.to_string()) {
+                        Some(role) => format!("{you} (you, {role})"),
+                        None => format!("{you} (you)"),
+                    };
+                    std::iter::once((self.presence.status, you))
+                        .chain(r.members.iter().map(|(name, peer_id)| {
+                            let status = r
+                                .presence
+                                .get(name)
+                                .map_or(PresenceStatus::Online, |p| p.status);
+                            let label = self.member_label(r, name, peer_id);
+                            match r.role(peer_id) {
+                                Some(role) => (status, format!("{label} ({role})")),
+                                None => (status, label),
+                            }
+                        }))
+                        .map(|(status, name)| format!("{} {}", status.icon(), name))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::KeyStorage;
+    use libp2p::identity::Keypair;
+    use tokio::sync::broadcast;
+
+    /// An `App` that is not running, so a test can drive its join flow
+    /// step by step; with the commands it sends the network task and
+    /// its bus.
+    fn idle_app() -> (
+        App,
+        mpsc::UnboundedReceiver<NetworkCommand>,
+        broadcast::Receiver<AppEvent>,
+    ) {
+        let temp = |kind: &str| {
+            std::env::temp_dir().join(format!("chat-test-{kind}-{}.toml", rand::random::<u64>()))
+        };
+        let mut config = Config {
+            nickname: Some("guest".to_string()),
+            key_storage: KeyStorage::Config,
+            path: temp("config"),
+            contacts_file: temp("contacts").to_string_lossy().into_owned(),
+            rooms_file: temp("rooms").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let identity = Identity::load_or_create(&mut config).expect("identity");
+        let (_, net_event_rx) = mpsc::unbounded_channel();
+        let (net_cmd_tx, net_cmd_rx) = mpsc::unbounded_channel();
+        let (_, cli_rx) = mpsc::unbounded_channel();
+        let bus = EventBus::new();
+        let events = bus.subscribe();
+        let app = App::new(identity, config, net_event_rx, net_cmd_tx, cli_rx, bus);
+        (app, net_cmd_rx, events)
+    }
+
+    /// A code for room "lobby" hosted by a fresh peer.
+    fn lobby_code() -> String {
+        let host = Keypair::generate_ed25519();
+        RoomCodeData {
+            room_name: "lobby".to_string(),
+            peer_id: host.public().to_peer_id().to_string(),
+            owner: String::new(),
+            owner_proof: Vec::new(),
+            addrs: vec!["/ip4/10.0.0.1/tcp/4001".to_string()],
+            salt: [7; SALT_LEN],
+            signed: false,
+        }
+        .encode(&host)
+        .unwrap()
+    }
+
+    /// The join states and outcome events published so far.
+    fn progress(events: &mut broadcast::Receiver<AppEvent>) -> Vec<String> {
+        let mut seen = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            match event {
+                AppEvent::Ui(UiEvent::JoinProgress { state, .. }) => {
+                    seen.push(format!("{state:?}"))
+                }
+                AppEvent::Ui(UiEvent::RoomJoined(_)) => seen.push("RoomJoined".to_string()),
+                AppEvent::Ui(UiEvent::AccessDenied(_)) => seen.push("AccessDenied".to_string()),
+                _ => {}
+            }
+        }
+        seen
+    }
+
+    fn state(app: &App) -> Option<JoinState> {
+        app.join.as_ref().map(|j| j.state)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_join_dials_searches_and_enters_unverified_when_no_one_answers() {
+        let (mut app, mut net, mut events) = idle_app();
+        app.join_room(lobby_code(), "pw".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(state(&app), Some(JoinState::Dialing));
+        let mut commands = Vec::new();
+        while let Ok(command) = net.try_recv() {
+            commands.push(command);
+        }
+        assert!(
+            commands
+                .iter()
+                .any(|c| matches!(c, NetworkCommand::Dial(a) if a.ends_with("/tcp/4001")))
+        );
+        assert!(
+            commands
+                .iter()
+                .any(|c| matches!(c, NetworkCommand::Discover(_)))
+        );
+
+        // Each step has its own deadline.
+        tokio::time::advance(JOIN_STEP_TIMEOUT - Duration::from_millis(1)).await;
+        app.check_join_timeout();
+        assert_eq!(state(&app), Some(JoinState::Dialing));
+        tokio::time::advance(Duration::from_millis(1)).await;
+        app.check_join_timeout();
+        assert_eq!(state(&app), Some(JoinState::Searching));
+        tokio::time::advance(JOIN_STEP_TIMEOUT).await;
+        app.check_join_timeout();
+        assert_eq!(state(&app), None);
+        assert_eq!(app.rooms.len(), 1);
+        assert_eq!(
+            progress(&mut events),
+            ["Dialing", "Searching", "TimedOut", "RoomJoined"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_member_found_restarts_the_clock_and_its_refusal_denies() {
+        let (mut app, _net, mut events) = idle_app();
+        app.join_room(lobby_code(), "pw".to_string(), None)
+            .await
+            .unwrap();
+        tokio::time::advance(JOIN_STEP_TIMEOUT).await;
+        app.check_join_timeout();
+        assert_eq!(state(&app), Some(JoinState::Searching));
+
+        tokio::time::advance(JOIN_STEP_TIMEOUT / 2).await;
+        let member = Keypair::generate_ed25519().public().to_peer_id();
+        app.join.as_mut().unwrap().members.push(member.to_string());
+        app.start_handshakes().unwrap();
+        assert_eq!(state(&app), Some(JoinState::AwaitingVerification));
+        tokio::time::advance(JOIN_STEP_TIMEOUT - Duration::from_millis(1)).await;
+        app.check_join_timeout();
+        assert_eq!(state(&app), Some(JoinState::AwaitingVerification));
+
+        let refusal = PakeReply {
+            room: "lobby".to_string(),
+            msg: String::new(),
+            confirm: String::new(),
+            epoch: 0,
+            key: String::new(),
+            refused: "invite expired".to_string(),
+            full: 0,
+            owner: String::new(),
+            owner_proof: String::new(),
+        };
+        app.apply_pake_reply(&member, &serde_json::to_string(&refusal).unwrap())
+            .unwrap();
+        assert_eq!(state(&app), None);
+        assert!(app.rooms.is_empty());
+        assert_eq!(
+            progress(&mut events),
+            [
+                "Dialing",
+                "Searching",
+                "AwaitingVerification",
+                "Denied",
+                "AccessDenied"
+            ]
+        );
+        // A late reply to a finished join changes nothing.
+        app.check_join_timeout();
+        assert!(app.rooms.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_join_by_name_resolves_then_falls_back_to_the_legacy_salt() {
+        let (mut app, mut net, _events) = idle_app();
+        app.join_room_by_name("lobby".to_string(), "pw".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(state(&app), Some(JoinState::Resolving));
+        assert!(
+            std::iter::from_fn(|| net.try_recv().ok())
+                .any(|c| matches!(c, NetworkCommand::FindProviders(_)))
+        );
+
+        tokio::time::advance(JOIN_STEP_TIMEOUT).await;
+        app.check_join_timeout();
+        assert_eq!(state(&app), Some(JoinState::Searching));
+        let join = app.join.as_ref().unwrap();
+        assert!(matches!(&join.key, JoinKey::Ready(_, salt) if *salt == legacy_salt("lobby")));
+    }
+}
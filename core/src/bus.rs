@@ -0,0 +1,93 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{broadcast, mpsc};
+
+use crate::types::AppEvent;
+
+/// Number of events a slow subscriber may fall behind before it starts
+/// losing the oldest ones.
+const BUS_CAPACITY: usize = 1024;
+
+/// Broadcast bus carrying `AppEvent`s out of the application task.
+///
+/// The app publishes every event exactly once; each subsystem (TUI, logger,
+/// …) holds its own receiver and picks the variants it cares about, so new
+/// consumers never need a dedicated channel or a new match arm in `App`.
+///
+/// Optional subscribers (notifications, load tests) [`subscribe`](Self::subscribe)
+/// and may lose events when they lag; those whose state would go wrong
+/// after a gap — the screen, the transcript logger — use
+/// [`subscribe_lossless`](Self::subscribe_lossless) instead.
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<AppEvent>,
+    lossless: Arc<Mutex<Vec<mpsc::UnboundedSender<AppEvent>>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(BUS_CAPACITY);
+        Self {
+            tx,
+            lossless: Arc::default(),
+        }
+    }
+
+    /// Publish an event to all current subscribers.
+    /// Events published while nobody is subscribed are dropped.
+    pub fn publish(&self, event: AppEvent) {
+        if let Ok(mut lossless) = self.lossless.lock() {
+            lossless.retain(|tx| tx.send(event.clone()).is_ok());
+        }
+        let _ = self.tx.send(event);
+    }
+
+    /// Create a new independent receiver; it sees events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<AppEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Like [`subscribe`](Self::subscribe), but the receiver never lags:
+    /// events queue up until it reads them. It closes once every clone of
+    /// the bus is dropped.
+    pub fn subscribe_lossless(&self) -> mpsc::UnboundedReceiver<AppEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        if let Ok(mut lossless) = self.lossless.lock() {
+            lossless.push(tx);
+        }
+        rx
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::UiEvent;
+
+    #[tokio::test]
+    async fn a_lossless_subscriber_keeps_what_a_lagging_one_loses() {
+        let bus = EventBus::new();
+        let mut lossy = bus.subscribe();
+        let mut lossless = bus.subscribe_lossless();
+        for n in 0..BUS_CAPACITY + 10 {
+            bus.publish(AppEvent::Ui(UiEvent::Error(n.to_string())));
+        }
+        assert!(matches!(
+            lossy.recv().await,
+            Err(broadcast::error::RecvError::Lagged(10))
+        ));
+        drop(bus);
+        let mut seen = 0;
+        while let Some(event) = lossless.recv().await {
+            assert!(matches!(event, AppEvent::Ui(UiEvent::Error(n)) if n == seen.to_string()));
+            seen += 1;
+        }
+        assert_eq!(seen, BUS_CAPACITY + 10);
+    }
+}
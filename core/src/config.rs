@@ -0,0 +1,928 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, ensure};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Layout of the config file written by this build.  Older files are
+/// brought up to it by [`MIGRATIONS`] when loaded.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// `MIGRATIONS[n]` turns a version-`n` file into a version-`n + 1` one.
+/// A step that renames or moves a setting goes here, so old files keep
+/// their values instead of losing them to defaults.
+const MIGRATIONS: [fn(&mut toml::Table) -> Result<()>; CONFIG_VERSION as usize] = [
+    // 0: files from before versioning, already laid out as version 1.
+    |_| Ok(()),
+    // 1: the key moves out of the file into a key file of its own (on the
+    // next start, see `identity::stored_key`) unless it is in the keyring.
+    |table| {
+        if table
+            .get("key_storage")
+            .and_then(toml::Value::as_str)
+            .is_none_or(|storage| storage == "config")
+        {
+            table.insert("key_storage".to_string(), "file".into());
+        }
+        Ok(())
+    },
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Layout of the file; missing in files from before versioning.
+    #[serde(default)]
+    pub version: u32,
+    /// Display nickname chosen by the user.
+    pub nickname: Option<String>,
+    /// One emoji or other character shown before the nickname.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+    /// Ed25519 keypair encoded as protobuf then base64, with
+    /// `key_storage = "config"`.
+    pub private_key_b64: Option<String>,
+    /// Where the private key is kept.
+    #[serde(default)]
+    pub key_storage: KeyStorage,
+    /// The key file of `key_storage = "file"`; beside this file by default
+    /// (see `key_path`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<String>,
+    /// Hex digits of our discriminator, 4 to 16: more make a peer with
+    /// the same name less likely.
+    #[serde(default = "default_discriminator_len")]
+    pub discriminator_len: usize,
+    /// Directory for per-room chat logs.
+    #[serde(default = "default_log_dir")]
+    pub log_dir: String,
+    /// How log lines are written: readable text, or one JSON object per
+    /// line for other tools.
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Ring the terminal bell on mentions and while unfocused.
+    #[serde(default = "default_true")]
+    pub notify_bell: bool,
+    /// Show desktop notifications on mentions and while unfocused.
+    #[serde(default = "default_true")]
+    pub notify_desktop: bool,
+    /// Rooms that never notify (`/mute`).
+    #[serde(default)]
+    pub muted_rooms: Vec<String>,
+    /// Rooms created or joined lately, newest first, for the main menu.
+    #[serde(default)]
+    pub recent_rooms: Vec<RecentRoom>,
+    /// Who we are in some rooms instead of our usual identity: room name →
+    /// persona, set when creating or joining.
+    #[serde(default)]
+    pub personas: BTreeMap<String, Persona>,
+    /// Peers whose messages are dropped (`/block`): peer ID → their name
+    /// when blocked.
+    #[serde(default)]
+    pub blocked_peers: BTreeMap<String, String>,
+    /// Pre-shared key of a private swarm: 64 hex digits, or the contents of
+    /// a go-ipfs `swarm.key`.  Unset joins the public network.
+    #[serde(default)]
+    pub swarm_key: Option<String>,
+    /// DHT bootstrap nodes (multiaddrs ending in `/p2p/<peer ID>`).  The
+    /// public IPFS nodes by default; an empty list bootstraps from nobody,
+    /// leaving mDNS, rendezvous points and room codes to find peers.
+    #[serde(default = "default_bootstrap_peers")]
+    pub bootstrap_peers: Vec<String>,
+    /// Rendezvous points (multiaddrs ending in `/p2p/<peer ID>`) where
+    /// members register their rooms and joiners look them up.
+    #[serde(default)]
+    pub rendezvous_points: Vec<String>,
+    /// Act as a rendezvous point for others (needs a public address).
+    #[serde(default)]
+    pub rendezvous_server: bool,
+    /// File of peers verified with `/verify`.
+    #[serde(default = "default_contacts_file")]
+    pub contacts_file: String,
+    /// File of room credentials saved with `/save`, sealed with a key
+    /// derived from the identity key.
+    #[serde(default = "default_rooms_file")]
+    pub rooms_file: String,
+    /// Capture the mouse in the terminal UI (wheel scrolling, clicks); the
+    /// terminal's own text selection then needs its modifier, usually Shift.
+    #[serde(default = "default_true")]
+    pub mouse: bool,
+    /// Draw `*bold*`, `_italic_` and `` `code` `` in messages as such;
+    /// off shows the markers as typed.
+    #[serde(default = "default_true")]
+    pub format_messages: bool,
+    /// Colors of the terminal UI (`[theme]`).
+    #[serde(default)]
+    pub theme: ThemeConfig,
+    /// Key chords of the terminal UI (`[keys]`).
+    #[serde(default)]
+    pub keys: KeysConfig,
+    /// zstd-compress long outgoing messages.  Turn off while peers too old
+    /// to read them share your rooms.
+    #[serde(default = "default_true")]
+    pub compress_messages: bool,
+    /// Transport tuning (`[network]`).
+    #[serde(default)]
+    pub network: NetworkConfig,
+    /// File this config is loaded from and saved to (`--config`).
+    #[serde(skip, default = "Config::default_path")]
+    pub path: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            nickname: None,
+            avatar: None,
+            private_key_b64: None,
+            log_dir: default_log_dir(),
+            log_format: LogFormat::default(),
+            key_storage: KeyStorage::default(),
+            key_file: None,
+            discriminator_len: default_discriminator_len(),
+            notify_bell: true,
+            notify_desktop: true,
+            muted_rooms: Vec::new(),
+            recent_rooms: Vec::new(),
+            personas: BTreeMap::new(),
+            blocked_peers: BTreeMap::new(),
+            swarm_key: None,
+            bootstrap_peers: default_bootstrap_peers(),
+            rendezvous_points: Vec::new(),
+            rendezvous_server: false,
+            contacts_file: default_contacts_file(),
+            rooms_file: default_rooms_file(),
+            mouse: true,
+            format_messages: true,
+            theme: ThemeConfig::default(),
+            keys: KeysConfig::default(),
+            compress_messages: true,
+            network: NetworkConfig::default(),
+            path: Self::default_path(),
+        }
+    }
+}
+
+/// Where the private key is kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStorage {
+    /// In a file of its own that only the user may read (see `key_path`).
+    #[default]
+    File,
+    /// In this file, `private_key_b64`.
+    Config,
+    /// In the OS keyring (macOS Keychain, Windows Credential Manager,
+    /// Secret Service), under this file's path.
+    Keyring,
+}
+
+/// Format of the room logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `[timestamp] sender: text` in `<room>.log`.
+    #[default]
+    Text,
+    /// `{"timestamp", "room", "sender", "type", "text", "verified"}` per
+    /// line in `<room>.jsonl`.
+    Jsonl,
+}
+
+/// The `[keys]` section: chords for actions of the terminal UI, for
+/// terminals that cannot type the usual ones.  A chord is a key name (`p`,
+/// `enter`, `pageup`, `f2`, …) after any of `ctrl+`, `alt+` and `shift+`;
+/// unset keeps the usual chord.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeysConfig {
+    /// Send the message or command (`enter`).
+    pub send: Option<String>,
+    /// Quit (`ctrl+c`).
+    pub quit: Option<String>,
+    /// Scroll the chat back (`pageup`).
+    pub scroll_up: Option<String>,
+    /// Scroll the chat forward (`pagedown`).
+    pub scroll_down: Option<String>,
+    /// Modifiers only, held with 1…9 to switch to the Nth joined room
+    /// (`alt`).
+    pub switch_room: Option<String>,
+    /// Open the command palette (`ctrl+p`).
+    pub command_palette: Option<String>,
+}
+
+/// The `[theme]` section.  A color is a name (`red`, `lightblue`,
+/// `darkgray`, …), `#rrggbb` or a 256-color index; unset keeps the
+/// preset's.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub preset: ThemePreset,
+    pub header_fg: Option<String>,
+    pub header_bg: Option<String>,
+    /// System lines, dimmed text and quotes.
+    pub system: Option<String>,
+    /// Our own messages.
+    pub own: Option<String>,
+    /// Messages mentioning us.
+    pub mention: Option<String>,
+    /// Border of the input box.
+    pub input: Option<String>,
+    /// Dividers, headings and the "Viewing history" bar.
+    pub accent: Option<String>,
+}
+
+/// Built-in colors to start a theme from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemePreset {
+    /// For dark terminal backgrounds.
+    #[default]
+    Dark,
+    /// For light terminal backgrounds.
+    Light,
+    /// No colors at all, only bold, dim and reverse video; also what
+    /// `NO_COLOR` gives.
+    Mono,
+}
+
+/// The `[network]` section.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    #[serde(default)]
+    pub gossipsub: GossipsubConfig,
+}
+
+/// The `[network.gossipsub]` section: how room messages spread.  The
+/// defaults suit rooms of a few dozen members; see `02-networking.md`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GossipsubConfig {
+    /// Milliseconds between mesh maintenance rounds; lower repairs the
+    /// mesh and gossips missed messages sooner, at the cost of traffic.
+    pub heartbeat_ms: u64,
+    /// Peers each node keeps in its mesh per topic (D).
+    pub mesh_n: usize,
+    /// Fewer mesh peers than this and the heartbeat adds some (D_low).
+    pub mesh_n_low: usize,
+    /// More mesh peers than this and the heartbeat prunes some (D_high).
+    pub mesh_n_high: usize,
+    /// Send our own messages to every subscribed peer, not just the mesh:
+    /// faster first delivery, more bandwidth in large rooms.
+    pub flood_publish: bool,
+    /// Largest message accepted or sent, in bytes.  Below
+    /// `MIN_TRANSMIT_SIZE` the longest chat chunks could not get through.
+    pub max_transmit_size: usize,
+}
+
+impl Default for GossipsubConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_ms: 10_000,
+            mesh_n: 6,
+            mesh_n_low: 5,
+            mesh_n_high: 12,
+            flood_publish: true,
+            // Room for the largest payload the app sends, plus the envelope.
+            max_transmit_size: crate::wire::MAX_CIPHERTEXT_LEN + 1024,
+        }
+    }
+}
+
+/// Who we are in one room, so that it cannot be told from the names and
+/// keys shown elsewhere that we are the same person.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Persona {
+    /// Nickname in the room; our usual one if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    /// Sign with a key pair of its own, made afresh each session, so the
+    /// discriminator, safety numbers and peer ID members see differ too.
+    #[serde(default)]
+    pub own_key: bool,
+}
+
+impl Persona {
+    /// Our usual identity: nothing to change.
+    pub fn is_usual(&self) -> bool {
+        self.nickname.is_none() && !self.own_key
+    }
+}
+
+/// A room on the main menu's "Recent rooms" list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecentRoom {
+    pub name: String,
+    /// Code it was entered with (our own for a room we created); empty if
+    /// it was joined by name.
+    #[serde(default)]
+    pub code: String,
+    /// When it was last created or joined.
+    pub last_joined: DateTime<Utc>,
+    /// The vault holds its code and password (`/save`).  Filled in for the
+    /// UI, not stored.
+    #[serde(skip)]
+    pub saved: bool,
+}
+
+fn default_discriminator_len() -> usize {
+    crate::wire::MIN_DISC_LEN
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_bootstrap_peers() -> Vec<String> {
+    crate::network::DEFAULT_BOOTSTRAP_PEERS
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect()
+}
+
+fn default_log_dir() -> String {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".chat_logs")
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn default_contacts_file() -> String {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".chat_contacts.toml")
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn default_rooms_file() -> String {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".chat_rooms.toml")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Longest profile name.
+pub const MAX_PROFILE_NAME_LEN: usize = 32;
+
+/// Directory of the identity profiles, `~/.chat_profiles`: one
+/// subdirectory per profile, with its config, contacts, saved rooms and
+/// logs.
+pub fn profiles_dir() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".chat_profiles")
+}
+
+/// A profile name is a directory name: letters, digits, `-` and `_`.
+pub fn validate_profile_name(name: &str) -> Result<()> {
+    ensure!(
+        !name.is_empty()
+            && name.len() <= MAX_PROFILE_NAME_LEN
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        "profile name '{name}' must be 1–{MAX_PROFILE_NAME_LEN} letters, digits, '-' or '_'"
+    );
+    Ok(())
+}
+
+impl Config {
+    /// Path to `~/.chatrc`.
+    pub fn default_path() -> PathBuf {
+        dirs::home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(".chatrc")
+    }
+
+    /// Load from `path`, or return `Default` if there is no file yet.  A
+    /// file in an older layout is migrated, and kept as `<path>.v<N>.bak`
+    /// until it is saved over.  A file that cannot be read or parsed is an
+    /// error rather than a fresh default, which would lose the keypair.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self {
+                    path,
+                    ..Self::default()
+                });
+            }
+            Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+        };
+        let (config, version) = Self::parse(&content).with_context(|| {
+            format!(
+                "cannot load {} (fix or move it; it was left untouched)",
+                path.display()
+            )
+        })?;
+        if version < CONFIG_VERSION {
+            // It may hold the private key.
+            let backup = PathBuf::from(format!("{}.v{version}.bak", path.display()));
+            write_private(&backup, &content)
+                .with_context(|| format!("cannot back up {}", path.display()))?;
+        }
+        Ok(Self { path, ..config })
+    }
+
+    /// Parse a config file, migrating it to [`CONFIG_VERSION`].  Also
+    /// returns the version it was written in.
+    fn parse(content: &str) -> Result<(Self, u32)> {
+        let mut table: toml::Table = toml::from_str(content)?;
+        let version = match table.get("version") {
+            None => 0,
+            Some(v) => v
+                .as_integer()
+                .and_then(|v| u32::try_from(v).ok())
+                .context("version is not a number")?,
+        };
+        ensure!(
+            version <= CONFIG_VERSION,
+            "written by a newer release (config version {version}, this one reads up to {CONFIG_VERSION})"
+        );
+        for step in &MIGRATIONS[version as usize..] {
+            step(&mut table)?;
+        }
+        table.insert("version".to_string(), i64::from(CONFIG_VERSION).into());
+        Ok((toml::Value::Table(table).try_into()?, version))
+    }
+
+    /// Names of the profiles made so far, sorted.
+    pub fn profiles() -> Vec<String> {
+        Self::profiles_in(&profiles_dir())
+    }
+
+    /// `profiles` of the profiles directory `dir`.
+    pub fn profiles_in(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().join("chatrc").is_file())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| validate_profile_name(name).is_ok())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Load profile `name`, creating it if it is new.  A new profile keeps
+    /// its contacts, saved rooms and logs in its own directory too, so its
+    /// identity shares nothing with the others.
+    pub fn load_profile(name: &str) -> Result<Self> {
+        Self::load_profile_in(&profiles_dir(), name)
+    }
+
+    /// `load_profile` with the profiles in `dir` (a portable data
+    /// directory's) rather than `~/.chat_profiles`.
+    pub fn load_profile_in(dir: &Path, name: &str) -> Result<Self> {
+        validate_profile_name(name)?;
+        Self::load_dir(&dir.join(name))
+    }
+
+    /// Load `dir/chatrc`, the config of a data directory (`--data-dir`, a
+    /// profile), making the directory if it is new.  A new config keeps
+    /// contacts, saved rooms and logs in `dir` too, by paths relative to it,
+    /// so the directory still works moved or mounted elsewhere.
+    pub fn load_dir(dir: &Path) -> Result<Self> {
+        let path = dir.join("chatrc");
+        if path.exists() {
+            return Self::load(path);
+        }
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            log_dir: "logs".to_string(),
+            contacts_file: "contacts.toml".to_string(),
+            rooms_file: "rooms.toml".to_string(),
+            path,
+            ..Self::default()
+        })
+    }
+
+    /// The key file of `key_storage = "file"`: `key_file`, or else beside
+    /// the config file — `identity.key` by a data directory's `chatrc`,
+    /// `.chat_identity.key` by `~/.chatrc`, `<name>.key` by any other.
+    pub fn key_path(&self) -> PathBuf {
+        let beside = match self.path.file_name().and_then(|name| name.to_str()) {
+            Some("chatrc") | None => "identity.key".to_string(),
+            Some(".chatrc") => ".chat_identity.key".to_string(),
+            Some(name) => format!("{name}.key"),
+        };
+        PathBuf::from(self.resolve(self.key_file.as_deref().unwrap_or(&beside)))
+    }
+
+    /// `file`, a path from this config, with a relative one taken from the
+    /// directory of the config file rather than the working directory.
+    pub fn resolve(&self, file: &str) -> String {
+        match self.path.parent() {
+            Some(dir) => dir.join(file).to_string_lossy().into_owned(),
+            None => file.to_string(),
+        }
+    }
+
+    /// Persist the current config to the file it was loaded from.
+    pub fn save(&self) -> Result<()> {
+        let content = toml::to_string_pretty(self)?;
+        // With `key_storage = "config"` the file holds the private key.
+        match self.private_key_b64 {
+            Some(_) => write_private(&self.path, &content)?,
+            None => std::fs::write(&self.path, content)?,
+        }
+        Ok(())
+    }
+
+    /// Ensure the log directory exists.
+    pub fn ensure_log_dir(&self) -> Result<()> {
+        std::fs::create_dir_all(self.resolve(&self.log_dir))?;
+        Ok(())
+    }
+
+    /// Read the file this config came from again (`/reload`).  Unlike
+    /// `load`, a missing file is an error too, and nothing is backed up.
+    pub fn reload(&self) -> Result<Self> {
+        let content = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("cannot read {}", self.path.display()))?;
+        let (config, _) = Self::parse(&content)
+            .with_context(|| format!("cannot parse {}", self.path.display()))?;
+        Ok(Self {
+            path: self.path.clone(),
+            ..config
+        })
+    }
+
+    /// Settings that differ in `new`, by their name in the file: those
+    /// the running app picks up, and those that wait for a restart —
+    /// the identity, the network, and files opened at start.
+    pub fn changes(&self, new: &Config) -> ConfigChanges {
+        let mut changes = ConfigChanges::default();
+        let live = [
+            ("theme", self.theme != new.theme),
+            (
+                "format_messages",
+                self.format_messages != new.format_messages,
+            ),
+            ("notify_bell", self.notify_bell != new.notify_bell),
+            ("notify_desktop", self.notify_desktop != new.notify_desktop),
+            ("muted_rooms", self.muted_rooms != new.muted_rooms),
+            ("log_dir", self.log_dir != new.log_dir),
+            ("log_format", self.log_format != new.log_format),
+            (
+                "compress_messages",
+                self.compress_messages != new.compress_messages,
+            ),
+            ("blocked_peers", self.blocked_peers != new.blocked_peers),
+            ("personas", self.personas != new.personas),
+            ("recent_rooms", self.recent_rooms != new.recent_rooms),
+        ];
+        let at_start = [
+            ("nickname", self.nickname != new.nickname),
+            ("avatar", self.avatar != new.avatar),
+            (
+                "private_key_b64",
+                self.private_key_b64 != new.private_key_b64,
+            ),
+            ("key_storage", self.key_storage != new.key_storage),
+            ("key_file", self.key_file != new.key_file),
+            (
+                "discriminator_len",
+                self.discriminator_len != new.discriminator_len,
+            ),
+            ("swarm_key", self.swarm_key != new.swarm_key),
+            (
+                "bootstrap_peers",
+                self.bootstrap_peers != new.bootstrap_peers,
+            ),
+            (
+                "rendezvous_points",
+                self.rendezvous_points != new.rendezvous_points,
+            ),
+            (
+                "rendezvous_server",
+                self.rendezvous_server != new.rendezvous_server,
+            ),
+            ("contacts_file", self.contacts_file != new.contacts_file),
+            ("rooms_file", self.rooms_file != new.rooms_file),
+            ("mouse", self.mouse != new.mouse),
+            ("keys", self.keys != new.keys),
+            ("network", self.network != new.network),
+        ];
+        changes.applied = live
+            .into_iter()
+            .filter(|(_, differs)| *differs)
+            .map(|(name, _)| name)
+            .collect();
+        changes.restart = at_start
+            .into_iter()
+            .filter(|(_, differs)| *differs)
+            .map(|(name, _)| name)
+            .collect();
+        changes
+    }
+
+    /// The setting `key` as the file has it, `theme.preset` for one in a
+    /// table (`chat config get`); `None` if it is unset.
+    pub fn get(&self, key: &str) -> Result<Option<toml::Value>> {
+        ensure!(
+            !HIDDEN_SETTINGS.contains(&key),
+            "{key} is kept by `chat identity`, not `chat config`"
+        );
+        let value = toml::Value::try_from(self)?;
+        Ok(key
+            .split('.')
+            .try_fold(value, |value, part| value.get(part).cloned()))
+    }
+
+    /// Change the setting `key` to `value` (`chat config set`).  The value
+    /// is read as TOML — `false`, `4001`, `["a", "b"]` — or else taken as a
+    /// string.  An unknown key, or a value that does not fit the setting or
+    /// fails `validate`, leaves the config as it was.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        ensure!(
+            !HIDDEN_SETTINGS.contains(&key),
+            "{key} is kept by `chat identity`, not `chat config`"
+        );
+        let parsed = toml::from_str::<toml::Table>(&format!("value = {value}"))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .filter(|parsed| !parsed.is_str());
+        let mut first_error = None;
+        for candidate in parsed
+            .into_iter()
+            .chain([toml::Value::String(value.to_string())])
+        {
+            match self.with(key, candidate) {
+                Ok(config) => {
+                    *self = config;
+                    return Ok(());
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.expect("a string is always tried"))
+    }
+
+    /// This config with `key` set to `value`, checked.
+    fn with(&self, key: &str, value: toml::Value) -> Result<Self> {
+        let mut table = toml::Table::try_from(self)?;
+        let mut parts: Vec<&str> = key.split('.').collect();
+        let last = parts.pop().unwrap_or_default();
+        let mut slot = &mut table;
+        for part in parts {
+            slot = slot
+                .entry(part)
+                .or_insert_with(|| toml::Table::new().into())
+                .as_table_mut()
+                .with_context(|| format!("{part} is not a table"))?;
+        }
+        slot.insert(last.to_string(), value.clone());
+        let config: Self = toml::Value::Table(table)
+            .try_into()
+            .with_context(|| format!("{value} does not fit {key}"))?;
+        ensure!(
+            config.get(key)?.as_ref() == Some(&value),
+            "there is no setting {key}"
+        );
+        ensure!(
+            self.key_storage != KeyStorage::Keyring || config.key_storage == KeyStorage::Keyring,
+            "the key would be left behind in the keyring: export the identity, switch, then import it"
+        );
+        config.validate()?;
+        Ok(Self {
+            path: self.path.clone(),
+            ..config
+        })
+    }
+
+    /// Check what the types alone do not: names, lengths, the swarm key,
+    /// addresses and mesh sizes.
+    pub fn validate(&self) -> Result<()> {
+        use crate::{network, wire};
+        let names = self
+            .nickname
+            .iter()
+            .chain(self.personas.values().filter_map(|p| p.nickname.as_ref()));
+        for nickname in names {
+            wire::validate_nickname(nickname).with_context(|| format!("nickname {nickname:?}"))?;
+        }
+        if let Some(avatar) = &self.avatar {
+            wire::validate_avatar(avatar)?;
+        }
+        ensure!(
+            (wire::MIN_DISC_LEN..=wire::MAX_DISC_LEN).contains(&self.discriminator_len),
+            "discriminator_len must be {} to {}",
+            wire::MIN_DISC_LEN,
+            wire::MAX_DISC_LEN
+        );
+        if let Some(key) = &self.swarm_key {
+            network::parse_swarm_key(key).context("swarm_key")?;
+        }
+        for addr in &self.bootstrap_peers {
+            network::parse_bootstrap_peer(addr)
+                .with_context(|| format!("bootstrap_peers: {addr}"))?;
+        }
+        for addr in &self.rendezvous_points {
+            network::parse_rendezvous_point(addr)
+                .with_context(|| format!("rendezvous_points: {addr}"))?;
+        }
+        network::gossipsub_config(&self.network.gossipsub).context("network.gossipsub")?;
+        Ok(())
+    }
+}
+
+/// Write `content` to `path` so that only the owner may read it (0600 on
+/// Unix), for files holding a private key.  It goes through a temporary
+/// file, so a crash leaves the old one whole.
+pub(crate) fn write_private(path: &Path, content: &str) -> Result<()> {
+    use std::io::Write;
+    let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(&tmp)
+        .with_context(|| format!("cannot write {}", tmp.display()))?;
+    // A temporary file left over from a crash keeps its old mode.
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path).with_context(|| format!("cannot write {}", path.display()))?;
+    Ok(())
+}
+
+/// Settings `get` and `set` leave alone: the key (see `chat identity`)
+/// and the layout, which only migrations change.
+const HIDDEN_SETTINGS: [&str; 2] = ["private_key_b64", "version"];
+
+/// What `Config::changes` found.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ConfigChanges {
+    /// Changed settings the running app uses from now on.
+    pub applied: Vec<&'static str>,
+    /// Changed settings that only take effect after a restart.
+    pub restart: Vec<&'static str>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_keep_their_files_apart() {
+        let dir = std::env::temp_dir().join(format!("chat-profiles-{}", std::process::id()));
+        let mut work = Config::load_profile_in(&dir, "work").unwrap();
+        assert_eq!(work.path, dir.join("work").join("chatrc"));
+        assert!(
+            work.resolve(&work.contacts_file)
+                .starts_with(dir.join("work").to_str().unwrap())
+        );
+        assert!(Config::profiles_in(&dir).is_empty());
+
+        work.nickname = Some("alice".to_string());
+        work.save().unwrap();
+        Config::load_profile_in(&dir, "home")
+            .unwrap()
+            .save()
+            .unwrap();
+        assert_eq!(Config::profiles_in(&dir), ["home", "work"]);
+        let reloaded = Config::load_profile_in(&dir, "work").unwrap();
+        assert_eq!(reloaded.nickname.as_deref(), Some("alice"));
+        let home = Config::load_profile_in(&dir, "home").unwrap();
+        assert_ne!(
+            reloaded.resolve(&reloaded.rooms_file),
+            home.resolve(&home.rooms_file)
+        );
+
+        for bad in ["", "../x", "a b", &"x".repeat(MAX_PROFILE_NAME_LEN + 1)] {
+            assert!(Config::load_profile_in(&dir, bad).is_err(), "{bad}");
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reload_tells_live_settings_from_those_needing_a_restart() {
+        let path = std::env::temp_dir().join(format!("chatrc-reload-{}", std::process::id()));
+        let running = Config {
+            path: path.clone(),
+            ..Config::default()
+        };
+        running.save().unwrap();
+        assert_eq!(
+            running.changes(&running.reload().unwrap()),
+            ConfigChanges::default()
+        );
+
+        let mut edited = running.clone();
+        edited.theme.preset = ThemePreset::Light;
+        edited.notify_bell = false;
+        edited.bootstrap_peers.clear();
+        edited.save().unwrap();
+        let changes = running.changes(&running.reload().unwrap());
+        assert_eq!(changes.applied, ["theme", "notify_bell"]);
+        assert_eq!(changes.restart, ["bootstrap_peers"]);
+
+        std::fs::write(&path, "notify_bell = maybe").unwrap();
+        assert!(running.reload().is_err());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn a_data_directory_still_works_moved_elsewhere() {
+        let base = std::env::temp_dir().join(format!("chat-data-{}", std::process::id()));
+        let (old, new) = (base.join("usb"), base.join("moved"));
+        let config = Config::load_dir(&old).unwrap();
+        config.ensure_log_dir().unwrap();
+        config.save().unwrap();
+        assert!(old.join("logs").is_dir());
+
+        std::fs::rename(&old, &new).unwrap();
+        let config = Config::load_dir(&new).unwrap();
+        assert_eq!(config.path, new.join("chatrc"));
+        assert_eq!(
+            config.resolve(&config.log_dir),
+            new.join("logs").to_string_lossy()
+        );
+        assert_eq!(config.resolve("/var/log/chat"), "/var/log/chat");
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn set_takes_known_settings_with_values_that_fit() {
+        let mut config = Config::default();
+        config.set("nickname", "alice").unwrap();
+        config.set("notify_bell", "false").unwrap();
+        config.set("theme.preset", "light").unwrap();
+        config.set("network.gossipsub.mesh_n", "8").unwrap();
+        config.set("muted_rooms", r#"["lobby", "dev"]"#).unwrap();
+        config.set("personas.work.nickname", "1234").unwrap();
+        assert_eq!(config.nickname.as_deref(), Some("alice"));
+        assert!(!config.notify_bell);
+        assert_eq!(config.theme.preset, ThemePreset::Light);
+        assert_eq!(config.network.gossipsub.mesh_n, 8);
+        assert_eq!(config.muted_rooms, ["lobby", "dev"]);
+        assert_eq!(config.personas["work"].nickname.as_deref(), Some("1234"));
+        assert_eq!(
+            config.get("notify_bell").unwrap(),
+            Some(toml::Value::Boolean(false))
+        );
+        assert_eq!(config.get("avatar").unwrap(), None);
+
+        let before = config.clone();
+        for (key, value) in [
+            ("nickame", "bob"),
+            ("notify_bell", "loud"),
+            ("theme.preset", "purple"),
+            ("discriminator_len", "2"),
+            ("network.gossipsub.mesh_n_low", "9"),
+            ("bootstrap_peers", r#"["not an address"]"#),
+            ("avatar", "ab"),
+            ("private_key_b64", "a2V5"),
+            ("version", "7"),
+        ] {
+            assert!(config.set(key, value).is_err(), "{key} = {value}");
+        }
+        assert_eq!(config.changes(&before), ConfigChanges::default());
+        assert!(config.get("private_key_b64").is_err());
+
+        config.set("key_storage", "config").unwrap();
+        config.set("key_storage", "keyring").unwrap();
+        assert!(config.set("key_storage", "file").is_err());
+    }
+
+    #[test]
+    fn an_old_file_is_migrated_and_backed_up_not_replaced_by_defaults() {
+        let path = std::env::temp_dir().join(format!("chatrc-migrate-{}", std::process::id()));
+        let old = "nickname = \"alice\"\nprivate_key_b64 = \"a2V5\"\n";
+        std::fs::write(&path, old).unwrap();
+        let config = Config::load(path.clone()).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.private_key_b64.as_deref(), Some("a2V5"));
+        assert_eq!(config.key_storage, KeyStorage::File);
+        let backup = PathBuf::from(format!("{}.v0.bak", path.display()));
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), old);
+        let _ = std::fs::remove_file(backup);
+        std::fs::write(&path, "version = 1\nkey_storage = \"keyring\"\n").unwrap();
+        assert_eq!(
+            Config::load(path.clone()).unwrap().key_storage,
+            KeyStorage::Keyring
+        );
+        let _ = std::fs::remove_file(PathBuf::from(format!("{}.v1.bak", path.display())));
+
+        std::fs::write(&path, "nickname = [").unwrap();
+        assert!(Config::load(path.clone()).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "nickname = [");
+
+        std::fs::write(&path, format!("version = {}", CONFIG_VERSION + 1)).unwrap();
+        let err = Config::load(path.clone()).unwrap_err();
+        assert!(format!("{err:#}").contains("newer release"));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,207 @@
+//! Peers whose identity key the user has pinned.
+//!
+//! `/trust <nick>` stores a member's key here as is (trust on first use);
+//! `/verify <nick>` shows the safety number shared with a member (see
+//! `crypto::safety_number`), and once both sides have compared it, `/verify
+//! <nick> confirm` stores the peer as verified and its messages carry a ✔
+//! badge.  A stranger using the nickname of a contact is flagged, see
+//! [`Contacts::name_clash`].  `/alias <nick> <name>` gives a peer a name
+//! of our own, shown instead of its nickname.  The file is TOML keyed by
+//! peer ID, which embeds the public key.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Contacts {
+    /// Peer ID → what we know about that peer.
+    #[serde(default)]
+    contacts: BTreeMap<String, Contact>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contact {
+    /// `nick#disc` when the peer was last verified.
+    pub name: String,
+    /// The safety number was compared and confirmed.
+    #[serde(default)]
+    pub verified: bool,
+    /// Our own name for the peer (`/alias`), shown instead of its nickname.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+}
+
+impl Contacts {
+    /// Load from `path`; a missing file is an empty list, a corrupt one is
+    /// logged and treated as empty (it is overwritten on the next change).
+    pub fn load(path: PathBuf) -> Self {
+        let contacts = match std::fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable contacts file {}: {e}", path.display());
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        Self { path, ..contacts }
+    }
+
+    /// Persist to the file the list was loaded from.
+    pub fn save(&self) -> Result<()> {
+        std::fs::write(&self.path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_verified(&self, peer_id: &str) -> bool {
+        self.contacts.get(peer_id).is_some_and(|c| c.verified)
+    }
+
+    /// Our name for `peer_id`, if we gave it one.
+    pub fn alias(&self, peer_id: &str) -> Option<&str> {
+        self.contacts.get(peer_id).and_then(|c| c.alias.as_deref())
+    }
+
+    /// Every contact by peer ID.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Contact)> {
+        self.contacts.iter()
+    }
+
+    /// Pin `peer_id` (currently called `name`) without verifying it, and
+    /// save.  A contact already verified stays verified.
+    pub fn trust(&mut self, peer_id: &str, name: &str) -> Result<()> {
+        let contact = self.contacts.entry(peer_id.to_string()).or_insert(Contact {
+            name: String::new(),
+            verified: false,
+            alias: None,
+        });
+        contact.name = name.to_string();
+        self.save()
+    }
+
+    /// Call `peer_id` (currently called `name`) `alias` from now on, or by
+    /// its nickname again with `None`, and save.  A peer without an entry
+    /// is pinned as by `trust`.
+    pub fn set_alias(&mut self, peer_id: &str, name: &str, alias: Option<String>) -> Result<()> {
+        let contact = self.contacts.entry(peer_id.to_string()).or_insert(Contact {
+            name: name.to_string(),
+            verified: false,
+            alias: None,
+        });
+        contact.alias = alias;
+        self.save()
+    }
+
+    /// Add `contacts` (from an identity archive), and save.  A peer
+    /// verified on either side stays verified.
+    pub fn import(&mut self, contacts: impl IntoIterator<Item = (String, Contact)>) -> Result<()> {
+        for (peer_id, contact) in contacts {
+            let verified = contact.verified || self.is_verified(&peer_id);
+            self.contacts.insert(
+                peer_id,
+                Contact {
+                    verified,
+                    ..contact
+                },
+            );
+        }
+        self.save()
+    }
+
+    /// The contact whose nickname `name` ("nick#disc") copies, if `peer_id`
+    /// is no contact itself: a different key under a known nickname.
+    /// Nicknames compare case-insensitively and without the discriminator,
+    /// which an impostor cannot choose.
+    pub fn name_clash(&self, peer_id: &str, name: &str) -> Option<&Contact> {
+        if self.contacts.contains_key(peer_id) {
+            return None;
+        }
+        let nick = |n: &str| n.split_once('#').map_or(n, |(nick, _)| nick).to_lowercase();
+        self.contacts.values().find(|c| nick(&c.name) == nick(name))
+    }
+
+    /// Mark `peer_id` (currently called `name`) verified or not, and save.
+    /// Revoking drops the contact unless it has an alias.
+    pub fn set_verified(&mut self, peer_id: &str, name: &str, verified: bool) -> Result<()> {
+        let alias = self.contacts.remove(peer_id).and_then(|c| c.alias);
+        if verified || alias.is_some() {
+            self.contacts.insert(
+                peer_id.to_string(),
+                Contact {
+                    name: name.to_string(),
+                    verified,
+                    alias,
+                },
+            );
+        }
+        self.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verified_peers_survive_a_reload() {
+        let path = std::env::temp_dir().join(format!("chat-contacts-{}.toml", std::process::id()));
+        let mut contacts = Contacts::load(path.clone());
+        contacts
+            .set_verified("12D3KooWpeer", "Bob#1a2b", true)
+            .unwrap();
+
+        let reloaded = Contacts::load(path.clone());
+        assert!(reloaded.is_verified("12D3KooWpeer"));
+        assert!(!reloaded.is_verified("12D3KooWother"));
+
+        contacts
+            .set_verified("12D3KooWpeer", "Bob#1a2b", false)
+            .unwrap();
+        assert!(!Contacts::load(path.clone()).is_verified("12D3KooWpeer"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn known_nickname_with_another_key_clashes() {
+        let path =
+            std::env::temp_dir().join(format!("chat-contacts-tofu-{}.toml", std::process::id()));
+        let mut contacts = Contacts::load(path.clone());
+        contacts.trust("12D3KooWbob", "Bob#1a2b").unwrap();
+        assert!(!contacts.is_verified("12D3KooWbob"));
+
+        assert!(contacts.name_clash("12D3KooWbob", "Bob#1a2b").is_none());
+        assert!(contacts.name_clash("12D3KooWeve", "carol#9f3c").is_none());
+        let clash = contacts.name_clash("12D3KooWeve", "bob#9f3c").unwrap();
+        assert_eq!(clash.name, "Bob#1a2b");
+        assert_eq!(Contacts::load(path.clone()).iter().count(), 1);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn aliases_are_kept_per_key() {
+        let path =
+            std::env::temp_dir().join(format!("chat-contacts-alias-{}.toml", std::process::id()));
+        let mut contacts = Contacts::load(path.clone());
+        contacts
+            .set_alias("12D3KooWbob", "Bob#1a2b", Some("Bob from work".to_string()))
+            .unwrap();
+        contacts
+            .set_verified("12D3KooWbob", "Bob#1a2b", true)
+            .unwrap();
+        contacts
+            .set_verified("12D3KooWbob", "Bob#1a2b", false)
+            .unwrap();
+
+        let reloaded = Contacts::load(path.clone());
+        assert_eq!(reloaded.alias("12D3KooWbob"), Some("Bob from work"));
+        assert!(!reloaded.is_verified("12D3KooWbob"));
+        assert_eq!(reloaded.alias("12D3KooWeve"), None);
+
+        contacts.set_alias("12D3KooWbob", "Bob#1a2b", None).unwrap();
+        assert_eq!(Contacts::load(path.clone()).alias("12D3KooWbob"), None);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,409 @@
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce, aead::Aead};
+use anyhow::{Result, anyhow, bail};
+use argon2::{Algorithm, Argon2, Params, Version};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use libp2p::{
+    PeerId,
+    identity::{Keypair, PublicKey},
+};
+use rand::RngCore;
+use sha2::{Digest, Sha256, Sha512};
+use spake2::{Ed25519Group, Identity as PakeIdentity, Password, Spake2};
+use zeroize::{Zeroize, Zeroizing};
+
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+pub const SALT_LEN: usize = 16;
+/// Domain separator for the tag confirming a password handshake.
+const PAKE_CONFIRM_DOMAIN: &str = "chatapp-pake-confirm-v1";
+/// Domain separator for the key a password handshake leaves both sides with.
+const PAKE_KEY_DOMAIN: &str = "chatapp-pake-key-v1";
+/// Domain separator for the direct-message key derivation.
+const DIRECT_KDF_DOMAIN: &str = "chatapp-direct-v1";
+/// Domain separator for safety numbers.
+const SAFETY_DOMAIN: &str = "chatapp-safety-v1";
+/// Domain separator for the key sealing saved room credentials.
+const VAULT_KDF_DOMAIN: &str = "chatapp-vault-v1";
+
+/// Argon2 salt of a room.  Random per room since room code v2; not secret.
+pub type RoomSalt = [u8; SALT_LEN];
+
+/// A fresh random room salt.
+pub fn random_salt() -> RoomSalt {
+    let mut salt = [0u8; SALT_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// The salt rooms used before room code v2: the room name, zero-padded or
+/// truncated to `SALT_LEN`.  Only for joining such rooms.
+pub fn legacy_salt(room_name: &str) -> RoomSalt {
+    let mut salt = [0u8; SALT_LEN];
+    let room_bytes = room_name.as_bytes();
+    let copy_len = room_bytes.len().min(SALT_LEN);
+    salt[..copy_len].copy_from_slice(&room_bytes[..copy_len]);
+    salt
+}
+
+/// A symmetric AES-256-GCM key derived from a room password, chosen at
+/// random for a key epoch (`RoomKey::generate`), or shared by two peers for
+/// direct messages (see `RoomKey::direct`).  Zeroed when dropped.
+#[derive(Clone)]
+pub struct RoomKey {
+    key: [u8; KEY_LEN],
+}
+
+impl RoomKey {
+    /// Derive a room key using Argon2id.
+    ///
+    /// `salt` is the room's random salt, so the same name and password give
+    /// different keys in different rooms.
+    ///
+    /// For a password-less room, pass `password = ""`.
+    pub fn derive(password: &str, salt: &RoomSalt) -> Result<Self> {
+        // Use conservative parameters compatible with iSH (x86 emulation).
+        // m_cost = 8 MiB, t_cost = 2 iterations, p_cost = 1 thread.
+        let params = Params::new(8 * 1024, 2, 1, Some(KEY_LEN))
+            .map_err(|e| anyhow!("Argon2 params: {}", e))?;
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+        let mut key = Self::zeroed();
+        argon2
+            .hash_password_into(password.as_bytes(), salt, &mut key.key)
+            .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+
+        Ok(key)
+    }
+
+    /// A fresh random key, for a new key epoch after a member was removed.
+    pub fn generate() -> Self {
+        let mut key = Self::zeroed();
+        rand::rngs::OsRng.fill_bytes(&mut key.key);
+        key
+    }
+
+    /// Filled in place by the constructors, so the key bytes never sit in
+    /// a local that outlives them.
+    fn zeroed() -> Self {
+        Self {
+            key: [0u8; KEY_LEN],
+        }
+    }
+
+    /// The raw key, to hand to another member over an encrypted channel.
+    pub fn to_bytes(&self) -> Zeroizing<[u8; KEY_LEN]> {
+        Zeroizing::new(self.key)
+    }
+
+    /// Inverse of `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != KEY_LEN {
+            bail!("room key must be {KEY_LEN} bytes");
+        }
+        let mut key = Self::zeroed();
+        key.key.copy_from_slice(bytes);
+        Ok(key)
+    }
+
+    /// Derive the key shared by us and `peer` for direct messages: X25519
+    /// ECDH over both Ed25519 identity keys, hashed with both public keys.
+    /// `peer` must be an Ed25519 peer ID (the key is inlined in it).
+    pub fn direct(own: &Keypair, peer: &PeerId) -> Result<Self> {
+        let own = own
+            .clone()
+            .try_into_ed25519()
+            .map_err(|_| anyhow!("identity key is not Ed25519"))?;
+        let peer_key = ed25519_key_of(peer)?;
+
+        // Ed25519 → X25519: the secret scalar is the (clamped) first half of
+        // SHA-512(seed); the public point maps from Edwards to Montgomery form.
+        let mut expanded = Sha512::digest(own.secret().as_ref());
+        let mut scalar = Zeroizing::new([0u8; 32]);
+        scalar.copy_from_slice(&expanded[..32]);
+        expanded.as_mut_slice().zeroize();
+        let point = CompressedEdwardsY(peer_key.to_bytes())
+            .decompress()
+            .ok_or_else(|| anyhow!("invalid peer key"))?
+            .to_montgomery();
+        let shared = Zeroizing::new(point.mul_clamped(*scalar));
+
+        // Both sides must hash the public keys in the same order.
+        let mut keys = [own.public().to_bytes(), peer_key.to_bytes()];
+        keys.sort();
+        let mut key = Self::zeroed();
+        Sha256::new()
+            .chain_update(DIRECT_KDF_DOMAIN)
+            .chain_update(shared.as_bytes())
+            .chain_update(keys[0])
+            .chain_update(keys[1])
+            .finalize_into((&mut key.key).into());
+        Ok(key)
+    }
+
+    /// Derive the key sealing our saved room credentials (`vault.rs`) from
+    /// our Ed25519 identity key and, when the vault has one, its passphrase
+    /// stretched with Argon2 over `salt`.  With a passphrase, neither the
+    /// identity key nor the passphrase alone opens the vault.
+    pub fn vault(own: &Keypair, passphrase: Option<(&str, &RoomSalt)>) -> Result<Self> {
+        let own = own
+            .clone()
+            .try_into_ed25519()
+            .map_err(|_| anyhow!("identity key is not Ed25519"))?;
+        let stretched = passphrase
+            .map(|(passphrase, salt)| Self::derive(passphrase, salt))
+            .transpose()?;
+        let mut key = Self::zeroed();
+        let mut hash = Sha256::new()
+            .chain_update(VAULT_KDF_DOMAIN)
+            .chain_update(own.secret().as_ref());
+        if let Some(stretched) = &stretched {
+            hash.update(stretched.key);
+        }
+        hash.finalize_into((&mut key.key).into());
+        Ok(key)
+    }
+
+    // ── Encryption ────────────────────────────────────────────────────────────
+
+    /// Encrypt `plaintext` and return `nonce(12) ++ ciphertext+tag`.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let cipher = self.cipher();
+
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut nonce_bytes);
+        let nonce = Nonce::from_slice(&nonce_bytes);
+
+        let ciphertext = cipher
+            .encrypt(nonce, plaintext)
+            .map_err(|_| anyhow!("Encryption failed"))?;
+
+        let mut out = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        out.extend_from_slice(&nonce_bytes);
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    /// Decrypt `nonce(12) ++ ciphertext+tag` and return the plaintext.
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < NONCE_LEN + 16 {
+            bail!("Ciphertext too short");
+        }
+        let cipher = self.cipher();
+        let nonce = Nonce::from_slice(&data[..NONCE_LEN]);
+        let ciphertext = &data[NONCE_LEN..];
+
+        cipher
+            .decrypt(nonce, ciphertext)
+            .map_err(|_| anyhow!("Decryption failed — wrong key or corrupted data"))
+    }
+
+    // ── Password check ────────────────────────────────────────────────────────
+
+    /// Joiner's side of the password check: start a SPAKE2 handshake with
+    /// `member`, using this key as the shared secret.  Returns the state to
+    /// finish with the member's reply and the message to send it.
+    pub fn pake_start(&self, own: &PeerId, member: &PeerId) -> (PakeHandshake, Vec<u8>) {
+        let (state, msg) = Spake2::<Ed25519Group>::start_a(
+            &Password::new(self.key),
+            &PakeIdentity::new(&own.to_bytes()),
+            &PakeIdentity::new(&member.to_bytes()),
+        );
+        (
+            PakeHandshake {
+                state,
+                msg: msg.clone(),
+            },
+            msg,
+        )
+    }
+
+    /// Member's side: answer `joiner`'s first handshake message.  The reply
+    /// carries a tag proving we hold the same key, and a session key that
+    /// only a joiner holding it derives too; the joiner learns nothing from
+    /// either if it does not.
+    pub fn pake_respond(
+        &self,
+        own: &PeerId,
+        joiner: &PeerId,
+        joiner_msg: &[u8],
+    ) -> Result<PakeResponse> {
+        let (state, msg) = Spake2::<Ed25519Group>::start_b(
+            &Password::new(self.key),
+            &PakeIdentity::new(&joiner.to_bytes()),
+            &PakeIdentity::new(&own.to_bytes()),
+        );
+        let session = state
+            .finish(joiner_msg)
+            .map_err(|e| anyhow!("bad handshake message: {e:?}"))?;
+        Ok(PakeResponse {
+            confirm: pake_confirm(&session, joiner_msg, &msg),
+            msg,
+            session: pake_session_key(&session),
+        })
+    }
+
+    // ── Helpers ───────────────────────────────────────────────────────────────
+
+    fn cipher(&self) -> Aes256Gcm {
+        let key = Key::<Aes256Gcm>::from_slice(&self.key);
+        Aes256Gcm::new(key)
+    }
+}
+
+impl Drop for RoomKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// A password handshake the joiner started with one member (see
+/// `RoomKey::pake_start`).
+pub struct PakeHandshake {
+    state: Spake2<Ed25519Group>,
+    /// Our first message, which the confirmation tag covers.
+    msg: Vec<u8>,
+}
+
+impl PakeHandshake {
+    /// Finish with the member's reply.  If its tag shows it derived the room
+    /// key from the same password as we did, returns the session key we now
+    /// share with it (see `PakeResponse::session`).
+    pub fn finish(self, member_msg: &[u8], confirm: &[u8]) -> Option<RoomKey> {
+        let session = self.state.finish(member_msg).ok()?;
+        (pake_confirm(&session, &self.msg, member_msg) == confirm)
+            .then(|| pake_session_key(&session))
+    }
+}
+
+/// A member's answer to a joiner's password handshake (see
+/// `RoomKey::pake_respond`).
+pub struct PakeResponse {
+    /// Our SPAKE2 message.
+    pub msg: Vec<u8>,
+    /// Tag over the session key and both messages.
+    pub confirm: Vec<u8>,
+    /// Key shared with the joiner iff it used the same password; the
+    /// current epoch key travels encrypted with it.
+    pub session: RoomKey,
+}
+
+/// Key derived from a handshake's SPAKE2 session key.
+fn pake_session_key(session: &[u8]) -> RoomKey {
+    let mut key = RoomKey::zeroed();
+    Sha256::new()
+        .chain_update(PAKE_KEY_DOMAIN)
+        .chain_update(session)
+        .finalize_into((&mut key.key).into());
+    key
+}
+
+/// Tag over a handshake's session key and both messages, in the order
+/// joiner, member.
+fn pake_confirm(session: &[u8], joiner_msg: &[u8], member_msg: &[u8]) -> Vec<u8> {
+    Sha256::new()
+        .chain_update(PAKE_CONFIRM_DOMAIN)
+        .chain_update(session)
+        .chain_update(joiner_msg)
+        .chain_update(member_msg)
+        .finalize()
+        .to_vec()
+}
+
+/// The safety number of two identities: 60 digits in groups of five, the
+/// same whichever side computes it.  Users read it to each other to rule
+/// out a peer impersonating someone under their nickname.
+pub fn safety_number(a: &PeerId, b: &PeerId) -> Result<String> {
+    let mut keys = [ed25519_key_of(a)?.to_bytes(), ed25519_key_of(b)?.to_bytes()];
+    keys.sort();
+    let digest = Sha512::new()
+        .chain_update(SAFETY_DOMAIN)
+        .chain_update(keys[0])
+        .chain_update(keys[1])
+        .finalize();
+    // Each 5-byte chunk becomes one 5-digit group.
+    let groups: Vec<String> = digest
+        .chunks_exact(5)
+        .map(|chunk| {
+            let n = chunk.iter().fold(0u64, |n, b| n << 8 | *b as u64);
+            format!("{:05}", n % 100_000)
+        })
+        .collect();
+    Ok(groups.join(" "))
+}
+
+/// The Ed25519 public key inlined in `peer_id`.
+pub(crate) fn ed25519_key_of(peer_id: &PeerId) -> Result<libp2p::identity::ed25519::PublicKey> {
+    let multihash = peer_id.as_ref();
+    // Code 0 is the identity multihash: the digest is the key itself.
+    if multihash.code() != 0 {
+        bail!("peer ID does not embed its public key");
+    }
+    PublicKey::try_decode_protobuf(multihash.digest())
+        .ok()
+        .and_then(|k| k.try_into_ed25519().ok())
+        .ok_or_else(|| anyhow!("peer ID does not embed an Ed25519 key"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direct_key_is_shared_by_exactly_the_pair() {
+        let alice = Keypair::generate_ed25519();
+        let bob = Keypair::generate_ed25519();
+        let eve = Keypair::generate_ed25519();
+
+        let sealed = RoomKey::direct(&alice, &bob.public().to_peer_id())
+            .unwrap()
+            .encrypt(b"psst")
+            .unwrap();
+        let opened = RoomKey::direct(&bob, &alice.public().to_peer_id())
+            .unwrap()
+            .decrypt(&sealed)
+            .unwrap();
+        assert_eq!(opened, b"psst");
+
+        let eve_key = RoomKey::direct(&eve, &alice.public().to_peer_id()).unwrap();
+        assert!(eve_key.decrypt(&sealed).is_err());
+    }
+
+    #[test]
+    fn handshake_confirms_only_the_same_password() {
+        let salt = random_salt();
+        let joiner = Keypair::generate_ed25519().public().to_peer_id();
+        let member = Keypair::generate_ed25519().public().to_peer_id();
+        let room_key = RoomKey::derive("hunter2", &salt).unwrap();
+
+        let attempt = |password: &str| {
+            let key = RoomKey::derive(password, &salt).unwrap();
+            let (handshake, msg) = key.pake_start(&joiner, &member);
+            let reply = room_key.pake_respond(&member, &joiner, &msg).unwrap();
+            let session = handshake.finish(&reply.msg, &reply.confirm)?;
+            // Both sides end up with the same session key.
+            let sealed = reply.session.encrypt(b"epoch key").unwrap();
+            Some(session.decrypt(&sealed).unwrap())
+        };
+        assert_eq!(attempt("hunter2").as_deref(), Some(&b"epoch key"[..]));
+        assert!(attempt("hunter3").is_none());
+
+        // A reply meant for another joiner does not confirm.
+        let key = RoomKey::derive("hunter2", &salt).unwrap();
+        let (handshake, msg) = key.pake_start(&joiner, &member);
+        let reply = room_key.pake_respond(&member, &member, &msg).unwrap();
+        assert!(handshake.finish(&reply.msg, &reply.confirm).is_none());
+    }
+
+    #[test]
+    fn safety_number_is_symmetric_and_pair_specific() {
+        let alice = Keypair::generate_ed25519().public().to_peer_id();
+        let bob = Keypair::generate_ed25519().public().to_peer_id();
+        let eve = Keypair::generate_ed25519().public().to_peer_id();
+
+        let number = safety_number(&alice, &bob).unwrap();
+        assert_eq!(number, safety_number(&bob, &alice).unwrap());
+        assert_ne!(number, safety_number(&alice, &eve).unwrap());
+        assert_eq!(number.split(' ').count(), 12);
+        assert!(number.split(' ').all(|g| g.len() == 5));
+    }
+}
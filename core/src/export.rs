@@ -0,0 +1,238 @@
+//! Room transcripts written out as Markdown, HTML or plain text (`/export`).
+
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+
+use crate::{
+    logger,
+    types::{DisplayMessage, ExportFormat, Quote},
+};
+
+/// Timestamps in exports carry the date too, unlike the chat view.  UTC,
+/// as everywhere else.
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Write `messages`, the history of `room`, to a new file in `dir` named
+/// after the room and the time of the export.  Returns its path.
+pub fn write(
+    dir: &str,
+    room: &str,
+    messages: &[DisplayMessage],
+    format: ExportFormat,
+) -> Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("create {dir}"))?;
+    let name = format!(
+        "{}-{}.{}",
+        logger::file_stem(room),
+        Utc::now().format("%Y%m%d-%H%M%S"),
+        format.extension()
+    );
+    let path = PathBuf::from(dir).join(name);
+    fs::write(&path, render(room, messages, format))
+        .with_context(|| format!("write {}", path.display()))?;
+    Ok(path)
+}
+
+/// The whole export document.
+pub fn render(room: &str, messages: &[DisplayMessage], format: ExportFormat) -> String {
+    let chats = messages.iter().filter(|m| !m.is_system).count();
+    let about = format!(
+        "Exported {} · {} message(s) · times in UTC",
+        Utc::now().format(TIME_FORMAT),
+        chats
+    );
+    match format {
+        ExportFormat::Text => {
+            let mut out = format!("Room: {room}\n{about}\n\n");
+            for msg in messages {
+                out.push_str(&text_entry(msg));
+            }
+            out
+        }
+        ExportFormat::Markdown => {
+            let mut out = format!("# {}\n\n*{}*\n", escape_markdown(room), about);
+            for msg in messages {
+                out.push('\n');
+                out.push_str(&markdown_entry(msg));
+            }
+            out
+        }
+        ExportFormat::Html => {
+            let mut out = format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+                 <title>{room} — chat export</title>\n<style>{HTML_STYLE}</style>\n\
+                 </head>\n<body>\n<h1>{room}</h1>\n<p class=\"about\">{about}</p>\n",
+                room = escape_html(room),
+            );
+            for msg in messages {
+                out.push_str(&html_entry(msg));
+            }
+            out.push_str("</body>\n</html>\n");
+            out
+        }
+    }
+}
+
+/// `[time] sender: text`, continuation lines indented, a reply's quote on a
+/// line of its own above.
+fn text_entry(msg: &DisplayMessage) -> String {
+    let time = msg.timestamp.format(TIME_FORMAT);
+    let mut out = String::new();
+    if let Some(quote) = &msg.quote {
+        out.push_str(&format!("    > {}\n", quoted(quote)));
+    }
+    let text = msg.text.replace('\n', "\n    ");
+    if msg.is_system {
+        out.push_str(&format!("[{time}] *** {text}\n"));
+    } else {
+        out.push_str(&format!("[{time}] {}: {text}\n", msg.real_label()));
+    }
+    if let Some(counts) = reaction_counts(msg) {
+        out.push_str(&format!("    {counts}\n"));
+    }
+    out
+}
+
+fn markdown_entry(msg: &DisplayMessage) -> String {
+    let time = msg.timestamp.format(TIME_FORMAT);
+    // Two trailing spaces: a line break within the paragraph.
+    let text = escape_markdown(&msg.text).replace('\n', "  \n");
+    if msg.is_system {
+        return format!("`{time}` *{text}*\n");
+    }
+    let mut out = format!("**{}** `{time}`  \n", escape_markdown(&msg.real_label()));
+    if let Some(quote) = &msg.quote {
+        out.push_str(&format!("> {}\n\n", escape_markdown(&quoted(quote))));
+    }
+    out.push_str(&text);
+    out.push('\n');
+    if let Some(counts) = reaction_counts(msg) {
+        out.push_str(&format!("\n{counts}\n"));
+    }
+    out
+}
+
+fn html_entry(msg: &DisplayMessage) -> String {
+    let time = msg.timestamp.format(TIME_FORMAT);
+    let text = escape_html(&msg.text).replace('\n', "<br>");
+    if msg.is_system {
+        return format!("<div class=\"system\"><time>{time}</time> {text}</div>\n");
+    }
+    let mut out = format!(
+        "<div class=\"msg\"><time>{time}</time> <b>{}</b>",
+        escape_html(&msg.real_label())
+    );
+    if let Some(quote) = &msg.quote {
+        out.push_str(&format!(
+            "<blockquote>{}</blockquote>",
+            escape_html(&quoted(quote))
+        ));
+    }
+    out.push_str(&format!("<p>{text}</p>"));
+    if let Some(counts) = reaction_counts(msg) {
+        out.push_str(&format!(
+            "<p class=\"reactions\">{}</p>",
+            escape_html(&counts)
+        ));
+    }
+    out.push_str("</div>\n");
+    out
+}
+
+/// "sender: start of the original", or just the text if we never had the
+/// original.
+fn quoted(quote: &Quote) -> String {
+    match quote.sender.as_str() {
+        "" => quote.text.clone(),
+        sender => format!("{}: {}", sender, quote.text),
+    }
+}
+
+/// "👍 2  🎉 1", or `None` without reactions.
+fn reaction_counts(msg: &DisplayMessage) -> Option<String> {
+    let counts: Vec<String> = msg
+        .reactions
+        .iter()
+        .map(|(emoji, n)| format!("{} {}", emoji, n))
+        .collect();
+    (!counts.is_empty()).then(|| counts.join("  "))
+}
+
+const HTML_STYLE: &str = "body{font-family:sans-serif;max-width:50em;margin:2em auto;padding:0 1em}\
+.about,time,.system{color:#777}time{font-size:.85em;margin-right:.5em}\
+.msg{margin:.8em 0}.msg p{margin:.2em 0 0 0;white-space:pre-wrap}\
+blockquote{margin:.2em 0;padding-left:.6em;border-left:3px solid #ccc;color:#555}\
+.system{font-style:italic;margin:.4em 0}.reactions{font-size:.9em}";
+
+/// Chat text as literal Markdown: characters that would start emphasis,
+/// links, code, headings or HTML are backslash-escaped.
+fn escape_markdown(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|'
+        ) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transcript() -> Vec<DisplayMessage> {
+        let mut hello = DisplayMessage::chat("alice#1a2b", "hello <b>*all*</b>\nsecond line");
+        hello.timestamp = chrono::DateTime::from_timestamp(60, 0).unwrap();
+        hello.reactions = vec![("👍".to_string(), 2)];
+        let mut reply = DisplayMessage::chat("bob#3c4d", "hi");
+        reply.quote = Some(Quote::of(&hello));
+        vec![DisplayMessage::system("bob#3c4d joined"), hello, reply]
+    }
+
+    #[test]
+    fn text_export_keeps_times_senders_and_quotes() {
+        let out = render("lobby", &transcript(), ExportFormat::Text);
+        assert!(out.starts_with("Room: lobby\n"), "{out}");
+        assert!(out.contains("· 2 message(s) ·"));
+        assert!(out.contains("] *** bob#3c4d joined\n"));
+        assert!(out.contains(
+            "[1970-01-01 00:01:00] alice#1a2b: hello <b>*all*</b>\n    second line\n    👍 2\n"
+        ));
+        assert!(out.contains("    > alice#1a2b: hello <b>*all*</b>…\n["));
+    }
+
+    #[test]
+    fn markdown_and_html_exports_escape_chat_text() {
+        let md = render("lobby", &transcript(), ExportFormat::Markdown);
+        assert!(md.starts_with("# lobby\n"), "{md}");
+        assert!(md.contains("**alice\\#1a2b** `1970-01-01 00:01:00`  \n"));
+        assert!(md.contains("hello \\<b\\>\\*all\\*\\</b\\>  \nsecond line\n"));
+
+        let html = render("a<b", &transcript(), ExportFormat::Html);
+        assert!(html.contains("<h1>a&lt;b</h1>"), "{html}");
+        assert!(html.contains("<p>hello &lt;b&gt;*all*&lt;/b&gt;<br>second line</p>"));
+        assert!(html.contains("<blockquote>alice#1a2b: hello"));
+        assert!(html.trim_end().ends_with("</html>"));
+    }
+}
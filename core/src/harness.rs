@@ -0,0 +1,2462 @@
+//! Deterministic multi-node test harness built on `MemoryNetwork`.
+//!
+//! Every node is a real `App` wired to an in-memory network backend, so
+//! the whole join / verify / chat pipeline runs in one process.  The
+//! libp2p swarm of `network.rs` is not part of it: `MemoryNetwork`
+//! replaces the network task, relaying commands and events itself.
+//! Tests use `#[tokio::test(start_paused = true)]` so timeouts elapse
+//! instantly.
+//!
+//! Built for this crate's tests, and for other crates with the `harness`
+//! feature:
+//!
+//! ```no_run
+//! use chatting1_core::harness::TestNet;
+//! use chatting1_core::types::JoinState;
+//!
+//! # async fn two_nodes() {
+//! let net = TestNet::new();
+//! let [mut alice, mut bob] = net.spawn_nodes(2).try_into().ok().unwrap();
+//! let code = alice.create_room("lobby", "pw").await;
+//! assert_eq!(bob.join_room(&code, "pw").await, JoinState::Joined);
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use base64::{Engine, engine::general_purpose::STANDARD as B64};
+use libp2p::identity::Keypair;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::{
+    app::App,
+    bus::EventBus,
+    config::{Config, KeyStorage},
+    identity::Identity,
+    memnet::MemoryNetwork,
+    plugin::Plugin,
+    types::{AppEvent, CliCommand, JoinState, UiEvent},
+};
+
+/// Upper bound (virtual time) for any single expectation.
+const WAIT_LIMIT: Duration = Duration::from_secs(60);
+
+/// A set of nodes sharing one in-memory network.
+#[derive(Default)]
+pub struct TestNet {
+    net: MemoryNetwork,
+}
+
+impl TestNet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The shared network, to cut nodes off or block peers.
+    pub fn network(&self) -> &MemoryNetwork {
+        &self.net
+    }
+
+    /// Start a fresh `App` with a new identity called `nickname`.
+    pub fn spawn_node(&self, nickname: &str) -> TestNode {
+        self.spawn_with(nickname, None, |app| app)
+    }
+
+    /// Like `spawn_node`, with the identity of `keypair`.
+    pub fn spawn_keyed(&self, nickname: &str, keypair: &Keypair) -> TestNode {
+        self.spawn_with(nickname, Some(keypair), |app| app)
+    }
+
+    /// Like `spawn_node`, with `plugin` registered.
+    pub fn spawn_bot(&self, nickname: &str, plugin: impl Plugin + 'static) -> TestNode {
+        self.spawn_with(nickname, None, |app| app.with_plugin(plugin))
+    }
+
+    fn spawn_with(
+        &self,
+        nickname: &str,
+        keypair: Option<&Keypair>,
+        setup: impl FnOnce(App) -> App,
+    ) -> TestNode {
+        let mut config = test_config(nickname, keypair);
+        let identity = Identity::load_or_create(&mut config).expect("identity");
+        let config_path = config.path.clone();
+        let peer_id = identity.peer_id.to_string();
+        let display_name = identity.display_name();
+
+        let (node, net_event_rx, net_cmd_tx) = self.net.attach(&peer_id);
+        let (cli_tx, cli_rx) = mpsc::unbounded_channel();
+        let bus = EventBus::new();
+        let events = bus.subscribe();
+
+        tokio::spawn(node.run());
+        let app = setup(App::new(
+            identity,
+            config,
+            net_event_rx,
+            net_cmd_tx,
+            cli_rx,
+            bus,
+        ));
+        tokio::spawn(async move {
+            let _ = app.run().await;
+        });
+
+        TestNode {
+            display_name,
+            peer_id,
+            config_path,
+            cli_tx,
+            events,
+        }
+    }
+
+    /// Start `n` nodes named `node0`, `node1`, ….
+    pub fn spawn_nodes(&self, n: usize) -> Vec<TestNode> {
+        (0..n)
+            .map(|i| self.spawn_node(&format!("node{i}")))
+            .collect()
+    }
+}
+
+/// Handle to one running `App`: drive it with CLI commands, observe its bus.
+pub struct TestNode {
+    pub display_name: String,
+    pub peer_id: String,
+    /// The node's config file, written when a command saves the config.
+    pub config_path: std::path::PathBuf,
+    cli_tx: mpsc::UnboundedSender<CliCommand>,
+    events: broadcast::Receiver<AppEvent>,
+}
+
+impl TestNode {
+    pub fn send(&self, cmd: CliCommand) {
+        self.cli_tx.send(cmd).expect("app is running");
+    }
+
+    /// Skip UI events until `f` returns `Some`; panics after `WAIT_LIMIT`.
+    pub async fn wait_for<T>(&mut self, mut f: impl FnMut(&UiEvent) -> Option<T>) -> T {
+        let found = tokio::time::timeout(WAIT_LIMIT, async {
+            loop {
+                match self.events.recv().await {
+                    Ok(AppEvent::Ui(event)) => {
+                        if let Some(value) = f(&event) {
+                            return value;
+                        }
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => panic!("app stopped"),
+                }
+            }
+        })
+        .await;
+        found.unwrap_or_else(|_| panic!("{}: expected event never arrived", self.display_name))
+    }
+
+    /// Create a room and return its code.
+    pub async fn create_room(&mut self, name: &str, password: &str) -> String {
+        self.send(CliCommand::CreateRoom {
+            name: name.to_string(),
+            password: password.to_string(),
+            persona: None,
+            max_members: 0,
+        });
+        self.wait_for(|e| match e {
+            UiEvent::RoomCreated { code, .. } => Some(code.clone()),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Join via `code` and return the terminal state of the join flow.
+    pub async fn join_room(&mut self, code: &str, password: &str) -> JoinState {
+        self.send(CliCommand::JoinRoom {
+            code: code.to_string(),
+            password: password.to_string(),
+            persona: None,
+        });
+        self.wait_for(|e| match e {
+            UiEvent::JoinProgress { state, .. } if state.is_terminal() => Some(*state),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Ask for `/peers` and return the member line.
+    pub async fn members(&mut self) -> String {
+        self.send(CliCommand::ListPeers);
+        self.wait_for(|e| match e {
+            UiEvent::NewMessage(m) if m.text.starts_with("Members:") => Some(m.text.clone()),
+            _ => None,
+        })
+        .await
+    }
+}
+
+/// A config for `nickname` (with the identity of `keypair`, else a new
+/// one) whose files are all in the temporary directory.
+fn test_config(nickname: &str, keypair: Option<&Keypair>) -> Config {
+    Config {
+        nickname: Some(nickname.to_string()),
+        private_key_b64: keypair
+            .map(|k| B64.encode(k.to_protobuf_encoding().expect("encode keypair"))),
+        key_storage: KeyStorage::Config,
+        // Commands like `/mute` save the config; keep ~/.chatrc out of it.
+        path: std::env::temp_dir().join(format!("chat-test-{}.toml", rand::random::<u64>())),
+        contacts_file: std::env::temp_dir()
+            .join(format!("chat-test-contacts-{}.toml", rand::random::<u64>()))
+            .to_string_lossy()
+            .into_owned(),
+        rooms_file: std::env::temp_dir()
+            .join(format!("chat-test-rooms-{}.toml", rand::random::<u64>()))
+            .to_string_lossy()
+            .into_owned(),
+        ..Config::default()
+    }
+}
+
+/// Let all spawned tasks process their queues.
+pub async fn settle() {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Persona;
+    use crate::plugin::PluginContext;
+    use crate::room::{RoomCodeData, topic_for_room};
+    use crate::types::{Delivery, DisplayMessage, NoteEdit, NoteId, Presence, PresenceStatus};
+
+    #[tokio::test(start_paused = true)]
+    async fn join_with_correct_password_is_verified() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "secret").await;
+        assert_eq!(guest.join_room(&code, "secret").await, JoinState::Joined);
+        let room = guest
+            .wait_for(|e| match e {
+                UiEvent::RoomJoined(name) => Some(name.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(room, "lobby");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn join_with_wrong_password_is_denied() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "secret").await;
+        assert_eq!(guest.join_room(&code, "guess").await, JoinState::Denied);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wrong_password_never_subscribes_to_the_room() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut intruder = net.spawn_node("intruder");
+        settle().await;
+
+        let code = host.create_room("lobby", "secret").await;
+        assert_eq!(intruder.join_room(&code, "guess").await, JoinState::Denied);
+        settle().await;
+        assert_eq!(
+            net.net.subscribers(&topic_for_room(
+                "lobby",
+                &RoomCodeData::decode(&code).unwrap().salt
+            )),
+            1
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn join_by_name_finds_the_creator_in_the_dht() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        host.create_room("lobby", "secret").await;
+        guest.send(CliCommand::JoinRoomByName {
+            name: "lobby".to_string(),
+            password: "secret".to_string(),
+            persona: None,
+        });
+        let mut states = Vec::new();
+        guest
+            .wait_for(|e| match e {
+                UiEvent::JoinProgress { state, .. } => {
+                    states.push(*state);
+                    state.is_terminal().then_some(())
+                }
+                _ => None,
+            })
+            .await;
+        assert_eq!(states.first(), Some(&JoinState::Resolving));
+        assert!(states.contains(&JoinState::Dialing));
+        assert_eq!(states.last(), Some(&JoinState::Joined));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn join_without_members_times_out_unverified() {
+        let net = TestNet::new();
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let nobody = libp2p::identity::Keypair::generate_ed25519();
+        let peer_id = nobody.public().to_peer_id().to_string();
+        let code = RoomCodeData {
+            room_name: "empty".to_string(),
+            addrs: vec![MemoryNetwork::addr_of(&peer_id)],
+            peer_id,
+            owner: String::new(),
+            owner_proof: Vec::new(),
+            salt: crate::crypto::random_salt(),
+            signed: false,
+        }
+        .encode(&nobody)
+        .unwrap();
+        assert_eq!(guest.join_room(&code, "").await, JoinState::TimedOut);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stale_code_reaches_members_found_by_rendezvous() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        assert_eq!(nodes[1].join_room(&code, "pw").await, JoinState::Joined);
+        // The creator is gone; the address in the code leads nowhere.
+        nodes[0].send(CliCommand::Quit);
+        settle().await;
+
+        let mut states = Vec::new();
+        nodes[2].send(CliCommand::JoinRoom {
+            code,
+            password: "pw".to_string(),
+            persona: None,
+        });
+        nodes[2]
+            .wait_for(|e| match e {
+                UiEvent::JoinProgress { state, .. } => {
+                    states.push(*state);
+                    state.is_terminal().then_some(())
+                }
+                _ => None,
+            })
+            .await;
+        assert_eq!(states.first(), Some(&JoinState::Dialing));
+        assert_eq!(states.last(), Some(&JoinState::Joined));
+        assert!(nodes[2].members().await.contains(&nodes[1].display_name));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_node_reconnects_and_rejoins() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        net.net.go_offline(&guest.peer_id);
+        guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text.starts_with("Connection lost") => Some(()),
+                _ => None,
+            })
+            .await;
+        // Let a few dial rounds fail before the uplink returns.
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        net.net.go_online(&guest.peer_id);
+
+        guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text.starts_with("Connection restored") => Some(()),
+                _ => None,
+            })
+            .await;
+        let name = guest.display_name.clone();
+        host.wait_for(|e| match e {
+            UiEvent::NewMessage(m) if m.text == format!("{name} joined the room") => Some(()),
+            _ => None,
+        })
+        .await;
+        settle().await;
+        assert!(guest.members().await.contains(&host.display_name));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn mentions_are_flagged_and_listed() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        guest.send(CliCommand::SendMessage("just chatting".to_string()));
+        guest.send(CliCommand::SendMessage("@host look at this".to_string()));
+        let mut flags = Vec::new();
+        host.wait_for(|e| match e {
+            UiEvent::NewMessage(m) if !m.is_system => {
+                flags.push(m.mention);
+                (flags.len() == 2).then_some(())
+            }
+            _ => None,
+        })
+        .await;
+        assert_eq!(flags, vec![false, true]);
+
+        host.send(CliCommand::ListMentions);
+        let line = host
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text.starts_with("#lobby") => Some(m.text.clone()),
+                _ => None,
+            })
+            .await;
+        assert!(line.ends_with("@host look at this"), "{line}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn links_lists_the_urls_posted_in_the_room() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        host.send(CliCommand::ListLinks);
+        host.wait_for(|e| match e {
+            UiEvent::NewMessage(m) if m.text == "No links in this room yet." => Some(()),
+            _ => None,
+        })
+        .await;
+
+        guest.send(CliCommand::SendMessage(
+            "see https://example.com/a, and http://b.org.".to_string(),
+        ));
+        host.wait_for(|e| match e {
+            UiEvent::NewMessage(m) if !m.is_system => Some(()),
+            _ => None,
+        })
+        .await;
+        host.send(CliCommand::ListLinks);
+        let mut lines = Vec::new();
+        host.wait_for(|e| match e {
+            UiEvent::NewMessage(m) if m.text.starts_with('[') => {
+                lines.push(m.text.clone());
+                (lines.len() == 2).then_some(())
+            }
+            _ => None,
+        })
+        .await;
+        assert!(
+            lines[0].starts_with("[1] guest#") && lines[0].ends_with(": https://example.com/a"),
+            "{lines:?}"
+        );
+        assert!(
+            lines[1].starts_with("[2] guest#") && lines[1].ends_with(": http://b.org"),
+            "{lines:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reply_quotes_the_original_and_mentions_its_author() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        host.send(CliCommand::SendMessage("lunch at noon?".to_string()));
+        let msg_id = guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "lunch at noon?" => Some(m.msg_id),
+                _ => None,
+            })
+            .await;
+        guest.send(CliCommand::Reply {
+            to: msg_id,
+            text: "sure".to_string(),
+        });
+        let reply = host
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "sure" => Some(m.clone()),
+                _ => None,
+            })
+            .await;
+        let quote = reply.quote.expect("reply carries a quote");
+        assert_eq!(quote.sender, host.display_name);
+        assert_eq!(quote.text, "lunch at noon?");
+        assert!(reply.mention);
+
+        // Nothing to reply to once the ID is unknown.
+        guest.send(CliCommand::Reply {
+            to: msg_id.wrapping_add(1),
+            text: "huh".to_string(),
+        });
+        guest
+            .wait_for(|e| {
+                matches!(e, UiEvent::Error(text) if text.contains("reply to")).then_some(())
+            })
+            .await;
+    }
+
+    /// Greets newcomers, answers "!ping", and handles `/pings`.
+    struct PingBot(usize);
+
+    impl Plugin for PingBot {
+        fn name(&self) -> &str {
+            "pingbot"
+        }
+
+        fn on_message(&mut self, ctx: &mut PluginContext, room: &str, msg: &DisplayMessage) {
+            if msg.text == "!ping" {
+                self.0 += 1;
+                ctx.send(room, "pong");
+            }
+        }
+
+        fn on_peer_join(&mut self, ctx: &mut PluginContext, room: &str, member: &str) {
+            ctx.send(room, format!("welcome {member}"));
+        }
+
+        fn on_command(&mut self, ctx: &mut PluginContext, name: &str, _args: &str) -> bool {
+            if name != "pings" {
+                return false;
+            }
+            ctx.notice(format!("{} pings", self.0));
+            true
+        }
+
+        fn help(&self) -> Vec<String> {
+            vec!["/pings — how many pings were answered".to_string()]
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn plugins_see_messages_joins_and_commands() {
+        let net = TestNet::new();
+        let mut bot = net.spawn_bot("bot", PingBot(0));
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        // The command list at start has the plugin's after ours.
+        let commands = bot
+            .wait_for(|e| match e {
+                UiEvent::Commands(commands) => Some(commands.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(commands[0].0, "/quit");
+        let pings = (
+            "/pings".to_string(),
+            "how many pings were answered".to_string(),
+        );
+        assert_eq!(commands.last(), Some(&pings));
+
+        let code = bot.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        let greeting = format!("welcome {}", guest.display_name);
+        guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == greeting => Some(()),
+                _ => None,
+            })
+            .await;
+
+        guest.send(CliCommand::SendMessage("!ping".to_string()));
+        let from = guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "pong" => Some(m.sender.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(from, bot.display_name);
+
+        bot.send(CliCommand::Plugin {
+            name: "pings".to_string(),
+            args: String::new(),
+        });
+        bot.wait_for(|e| match e {
+            UiEvent::NewMessage(m) if m.text == "1 pings" => Some(()),
+            _ => None,
+        })
+        .await;
+        // Commands no plugin claims fall back to the help.
+        bot.send(CliCommand::Plugin {
+            name: "nope".to_string(),
+            args: String::new(),
+        });
+        bot.wait_for(|e| matches!(e, UiEvent::ShowHelp).then_some(()))
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn chat_reaches_every_member() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        for node in &mut nodes[1..] {
+            assert_eq!(node.join_room(&code, "pw").await, JoinState::Joined);
+        }
+        settle().await;
+
+        nodes[2].send(CliCommand::SendMessage("hello".to_string()));
+        let sender = nodes[2].display_name.clone();
+        for node in &mut nodes[..2] {
+            let from = node
+                .wait_for(|e| match e {
+                    UiEvent::NewMessage(m) if !m.is_system && m.text == "hello" => {
+                        Some(m.sender.clone())
+                    }
+                    _ => None,
+                })
+                .await;
+            assert_eq!(from, sender);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn long_message_arrives_whole_and_too_long_is_refused() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        // Three parts, cut between multi-byte characters.
+        let paste = "한글 text ".repeat(2000);
+        host.send(CliCommand::SendMessage(paste.clone()));
+        let sent_id = host
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == paste => Some(m.msg_id),
+                _ => None,
+            })
+            .await;
+        let received = guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if !m.is_system && m.text.len() > 100 => Some(m.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(received.text, paste);
+        assert_eq!(received.msg_id, sent_id);
+
+        host.send(CliCommand::SendMessage(
+            "x".repeat(crate::wire::MAX_CHAT_LEN + 1),
+        ));
+        host.wait_for(|e| {
+            matches!(e, UiEvent::Error(text) if text.contains("too long")).then_some(())
+        })
+        .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn returning_member_gets_missed_messages_once() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        for node in &mut nodes[1..] {
+            assert_eq!(node.join_room(&code, "pw").await, JoinState::Joined);
+        }
+        settle().await;
+
+        nodes[2].send(CliCommand::LeaveRoom);
+        settle().await;
+        nodes[0].send(CliCommand::SendMessage("while you were away".to_string()));
+        nodes[1].send(CliCommand::SendMessage("me too".to_string()));
+        settle().await;
+
+        // Both members kept the messages; each must show up once, signed.
+        assert_eq!(nodes[2].join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+        nodes[0].send(CliCommand::SendMessage("welcome back".to_string()));
+        let mut missed = Vec::new();
+        nodes[2]
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "welcome back" => Some(()),
+                UiEvent::NewMessage(m) if !m.is_system => {
+                    missed.push((m.text.clone(), m.verified));
+                    None
+                }
+                _ => None,
+            })
+            .await;
+        missed.sort();
+        assert_eq!(
+            missed,
+            [
+                ("me too".to_string(), true),
+                ("while you were away".to_string(), true)
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn owner_topic_reaches_members_and_late_joiners() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        assert_eq!(nodes[1].join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        // Only the owner may set it.
+        nodes[1].send(CliCommand::Topic(Some("hijacked".to_string())));
+        nodes[1]
+            .wait_for(|e| matches!(e, UiEvent::Error(t) if t.contains("owner")).then_some(()))
+            .await;
+        nodes[0].send(CliCommand::Topic(Some("release planning".to_string())));
+        let subject = |e: &UiEvent| match e {
+            UiEvent::StatusUpdate { subject, .. } if !subject.is_empty() => Some(subject.clone()),
+            _ => None,
+        };
+        assert_eq!(nodes[1].wait_for(subject).await, "release planning");
+
+        // Replayed to a joiner by whoever is there.
+        assert_eq!(nodes[2].join_room(&code, "pw").await, JoinState::Joined);
+        assert_eq!(nodes[2].wait_for(subject).await, "release planning");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_mode_holds_back_members_but_not_the_owner() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        assert_eq!(nodes[1].join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        nodes[1].send(CliCommand::SlowMode(Some(1)));
+        nodes[1]
+            .wait_for(|e| matches!(e, UiEvent::Error(t) if t.contains("owner")).then_some(()))
+            .await;
+        nodes[0].send(CliCommand::SlowMode(Some(30)));
+        let announced = |e: &UiEvent| {
+            matches!(e, UiEvent::NewMessage(m) if m.is_system && m.text.contains("wait 30s"))
+                .then_some(())
+        };
+        nodes[1].wait_for(announced).await;
+
+        // Replayed to a joiner, who is held to it too.
+        assert_eq!(nodes[2].join_room(&code, "pw").await, JoinState::Joined);
+        nodes[2].wait_for(announced).await;
+        for node in &mut nodes[1..] {
+            node.send(CliCommand::SendMessage("first".to_string()));
+            node.send(CliCommand::SendMessage("second".to_string()));
+            node.wait_for(|e| match e {
+                UiEvent::Error(t) if t.contains("Slow mode") => Some(()),
+                UiEvent::NewMessage(m) if m.text == "second" => panic!("slow mode ignored"),
+                _ => None,
+            })
+            .await;
+        }
+        for text in ["one", "two"] {
+            nodes[0].send(CliCommand::SendMessage(text.to_string()));
+        }
+        nodes[1]
+            .wait_for(|e| matches!(e, UiEvent::NewMessage(m) if m.text == "two").then_some(()))
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn announcement_rooms_let_only_the_owner_and_speakers_write() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        assert_eq!(nodes[1].join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        nodes[1].send(CliCommand::Announcement(Some(true)));
+        nodes[1]
+            .wait_for(|e| matches!(e, UiEvent::Error(t) if t.contains("owner")).then_some(()))
+            .await;
+        nodes[0].send(CliCommand::Announcement(Some(true)));
+        let announced = |e: &UiEvent| {
+            matches!(e, UiEvent::NewMessage(m) if m.is_system && m.text.contains("announcement room")).then_some(())
+        };
+        nodes[1].wait_for(announced).await;
+
+        // Replayed to a joiner, who is held to it too.
+        assert_eq!(nodes[2].join_room(&code, "pw").await, JoinState::Joined);
+        nodes[2].wait_for(announced).await;
+        for node in &mut nodes[1..] {
+            node.send(CliCommand::SendMessage("hello?".to_string()));
+            node.wait_for(|e| {
+                matches!(e, UiEvent::Error(t) if t.contains("announcement room")).then_some(())
+            })
+            .await;
+        }
+
+        nodes[0].send(CliCommand::Speaker {
+            who: "node1".to_string(),
+            allow: true,
+        });
+        nodes[1]
+            .wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text.contains("made a speaker"))
+                    .then_some(())
+            })
+            .await;
+        nodes[1].send(CliCommand::SendMessage("on air".to_string()));
+        nodes[0].send(CliCommand::SendMessage("welcome".to_string()));
+        let mut heard = Vec::new();
+        while heard.len() < 2 {
+            heard.push(
+                nodes[2]
+                    .wait_for(|e| match e {
+                        UiEvent::NewMessage(m) if !m.is_system => Some(m.text.clone()),
+                        _ => None,
+                    })
+                    .await,
+            );
+        }
+        heard.sort();
+        assert_eq!(heard, ["on air", "welcome"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn threads_keep_their_messages_out_of_the_room() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(2);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        assert_eq!(nodes[1].join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        nodes[1].send(CliCommand::OpenThread(Some("design".to_string())));
+        nodes[1].send(CliCommand::SendMessage("mockups are up".to_string()));
+        nodes[1].send(CliCommand::OpenThread(None));
+        nodes[1].send(CliCommand::SendMessage("lunch?".to_string()));
+        // The room shows that the thread started, but not what is in it.
+        nodes[0]
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "mockups are up" => {
+                    panic!("thread shown in the room")
+                }
+                UiEvent::NewMessage(m)
+                    if m.is_system && m.text.contains("started thread 'design'") =>
+                {
+                    Some(())
+                }
+                _ => None,
+            })
+            .await;
+        nodes[0]
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "mockups are up" => {
+                    panic!("thread shown in the room")
+                }
+                UiEvent::NewMessage(m) if m.text == "lunch?" => Some(()),
+                _ => None,
+            })
+            .await;
+
+        nodes[0].send(CliCommand::ShowThreads);
+        let threads = nodes[0]
+            .wait_for(|e| match e {
+                UiEvent::Threads { threads, .. } => Some(threads.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(
+            (threads[0].name.as_str(), threads[0].messages),
+            ("design", 1)
+        );
+
+        // Opening it shows only its messages, and answers go there.
+        nodes[0].send(CliCommand::OpenThread(Some("design".to_string())));
+        let shown = nodes[0]
+            .wait_for(|e| match e {
+                UiEvent::RoomSwitched { history, .. } => Some(history.clone()),
+                _ => None,
+            })
+            .await;
+        let texts: Vec<&str> = shown.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["mockups are up"]);
+        nodes[1].send(CliCommand::OpenThread(Some("design".to_string())));
+        settle().await;
+        nodes[0].send(CliCommand::SendMessage("looks good".to_string()));
+        let reply = nodes[1]
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "looks good" => Some(m.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(reply.thread.as_deref(), Some("design"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reactions_are_counted_once_per_member() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        for node in &mut nodes[1..] {
+            assert_eq!(node.join_room(&code, "pw").await, JoinState::Joined);
+        }
+        settle().await;
+
+        nodes[0].send(CliCommand::SendMessage("ship it?".to_string()));
+        for node in &mut nodes[1..] {
+            node.wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text == "ship it?").then_some(())
+            })
+            .await;
+        }
+        let react = |emoji: &str| CliCommand::React {
+            to: None,
+            emoji: emoji.to_string(),
+        };
+        nodes[1].send(react("👍"));
+        nodes[1].send(react("👍"));
+        nodes[2].send(react("👍"));
+        nodes[2].send(react("🎉"));
+        let tally = nodes[0]
+            .wait_for(|e| match e {
+                UiEvent::Reactions { reactions, .. } if reactions.len() == 2 => {
+                    Some(reactions.clone())
+                }
+                _ => None,
+            })
+            .await;
+        assert_eq!(tally, [("🎉".to_string(), 1), ("👍".to_string(), 2)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn poll_votes_count_once_per_member_until_closed() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        for node in &mut nodes[1..] {
+            assert_eq!(node.join_room(&code, "pw").await, JoinState::Joined);
+        }
+        settle().await;
+
+        nodes[0].send(CliCommand::Poll {
+            question: "Lunch?".to_string(),
+            options: vec!["Pizza".to_string(), "Sushi".to_string()],
+            minutes: None,
+        });
+        for node in &mut nodes[1..] {
+            node.wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.poll.is_some()).then_some(())
+            })
+            .await;
+        }
+        // A second vote replaces the first.
+        nodes[1].send(CliCommand::Vote(1));
+        nodes[1].send(CliCommand::Vote(2));
+        nodes[2].send(CliCommand::Vote(2));
+        nodes[2].send(CliCommand::Vote(3));
+        nodes[2]
+            .wait_for(|e| matches!(e, UiEvent::Error(t) if t.contains("1 to 2")).then_some(()))
+            .await;
+        let tally = |e: &UiEvent| match e {
+            UiEvent::PollTally { poll, .. } if poll.options[1].1 == 2 => Some(poll.options.clone()),
+            _ => None,
+        };
+        let options = nodes[0].wait_for(tally).await;
+        assert_eq!(
+            options,
+            [("Pizza".to_string(), 0), ("Sushi".to_string(), 2)]
+        );
+
+        // Only the creator can end it; then votes are turned away.
+        nodes[1].send(CliCommand::EndPoll);
+        nodes[1]
+            .wait_for(|e| {
+                matches!(e, UiEvent::Error(t) if t.contains("no open poll")).then_some(())
+            })
+            .await;
+        nodes[0].send(CliCommand::EndPoll);
+        for node in &mut nodes[1..] {
+            node.wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text == "Poll closed: Lunch? — Pizza 0, Sushi 2").then_some(())
+            })
+            .await;
+        }
+        nodes[1].send(CliCommand::Vote(1));
+        nodes[1]
+            .wait_for(|e| {
+                matches!(e, UiEvent::Error(t) if t.contains("No open poll")).then_some(())
+            })
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn notes_are_edited_by_everyone_and_handed_to_joiners() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        assert_eq!(nodes[1].join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        let notes_with = |n: usize| {
+            move |e: &UiEvent| match e {
+                UiEvent::Notes { lines, .. } if lines.len() == n => Some(lines.clone()),
+                _ => None,
+            }
+        };
+        nodes[0].send(CliCommand::EditNote(NoteEdit::Append("milk".to_string())));
+        let lines = nodes[1].wait_for(notes_with(1)).await;
+        nodes[1].send(CliCommand::EditNote(NoteEdit::Append("eggs".to_string())));
+        nodes[1].send(CliCommand::EditNote(NoteEdit::Set {
+            id: lines[0].0,
+            text: "oat milk".to_string(),
+        }));
+        let texts = |lines: Vec<(NoteId, String)>| {
+            lines.into_iter().map(|(_, text)| text).collect::<Vec<_>>()
+        };
+        nodes[0]
+            .wait_for(|e| match e {
+                UiEvent::Notes { lines, .. }
+                    if lines.first().is_some_and(|(_, t)| t == "oat milk") =>
+                {
+                    Some(())
+                }
+                _ => None,
+            })
+            .await;
+
+        // A joiner gets them from the members already there.
+        assert_eq!(nodes[2].join_room(&code, "pw").await, JoinState::Joined);
+        let lines = nodes[2].wait_for(notes_with(2)).await;
+        assert_eq!(texts(lines), ["oat milk", "eggs"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn mention_rings_the_bell_unless_muted() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        // Focused: only a mention rings.
+        host.send(CliCommand::SendMessage("plain".to_string()));
+        host.send(CliCommand::SendMessage("hi @guest".to_string()));
+        let mut seen_plain = false;
+        let rang_after_plain = guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "plain" => {
+                    seen_plain = true;
+                    None
+                }
+                UiEvent::Bell => Some(seen_plain),
+                _ => None,
+            })
+            .await;
+        assert!(rang_after_plain, "bell before the mention");
+
+        guest.send(CliCommand::ToggleMute);
+        host.send(CliCommand::SendMessage("guest again".to_string()));
+        host.send(CliCommand::SendMessage("end".to_string()));
+        guest
+            .wait_for(|e| match e {
+                UiEvent::Bell => panic!("muted room rang"),
+                UiEvent::NewMessage(m) if m.text == "end" => Some(()),
+                _ => None,
+            })
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn own_message_turns_delivered_on_ack() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        host.send(CliCommand::SendMessage("anyone?".to_string()));
+        let (msg_id, delivery) = host
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "anyone?" => Some((m.msg_id, m.delivery)),
+                _ => None,
+            })
+            .await;
+        assert_eq!(delivery, Some(Delivery::Pending));
+        let room = host
+            .wait_for(|e| match e {
+                UiEvent::MessageDelivered { room, msg_id: id } if *id == msg_id => {
+                    Some(room.clone())
+                }
+                _ => None,
+            })
+            .await;
+        assert_eq!(room, "lobby");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn verified_contact_gets_a_badge() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        // Both sides see the same safety number.
+        let mut numbers = Vec::new();
+        for (node, other) in [(&mut host, "guest"), (&mut guest, "host")] {
+            node.send(CliCommand::Verify {
+                who: other.to_string(),
+                mark: None,
+            });
+            let number = node
+                .wait_for(|e| match e {
+                    UiEvent::NewMessage(m) if m.is_system && m.text.starts_with("  ") => {
+                        Some(m.text.trim().to_string())
+                    }
+                    _ => None,
+                })
+                .await;
+            numbers.push(number);
+        }
+        assert_eq!(numbers[0], numbers[1]);
+
+        let mut badges = Vec::new();
+        for text in ["before", "after"] {
+            guest.send(CliCommand::SendMessage(text.to_string()));
+            let badge = host
+                .wait_for(|e| match e {
+                    UiEvent::NewMessage(m) if m.text == text => Some(m.trusted),
+                    _ => None,
+                })
+                .await;
+            badges.push(badge);
+            host.send(CliCommand::Verify {
+                who: "guest".to_string(),
+                mark: Some(true),
+            });
+        }
+        assert_eq!(badges, [false, true]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn presence_reaches_members_and_late_joiners() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        let mut late = net.spawn_node("late");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        host.send(CliCommand::SetPresence(Presence {
+            status: PresenceStatus::Away,
+            reason: "lunch".to_string(),
+        }));
+        let away = format!("◐ {} (owner)", host.display_name);
+        guest
+            .wait_for(|e| match e {
+                UiEvent::StatusUpdate { members, .. } if members.contains(&away) => Some(()),
+                _ => None,
+            })
+            .await;
+        assert!(
+            guest
+                .members()
+                .await
+                .contains(&format!("{} (owner, away: lunch)", host.display_name))
+        );
+
+        // The newcomer hears it in answer to its `Join`.
+        assert_eq!(late.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+        assert!(late.members().await.contains("(owner, away: lunch)"));
+
+        // Idling does not override a status the user chose; coming back
+        // from a chosen one is explicit.
+        host.send(CliCommand::SetIdle(false));
+        host.send(CliCommand::SetPresence(Presence::default()));
+        settle().await;
+        assert!(!guest.members().await.contains("away"));
+
+        host.send(CliCommand::SetIdle(true));
+        settle().await;
+        assert!(guest.members().await.contains("(owner, away: idle)"));
+        host.send(CliCommand::SetIdle(false));
+        settle().await;
+        assert!(!guest.members().await.contains("away"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn blocked_member_is_silenced_until_unblocked() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        host.send(CliCommand::Block("guest".to_string()));
+        settle().await;
+        guest.send(CliCommand::SendMessage("hidden".to_string()));
+        settle().await;
+        host.send(CliCommand::Unblock("guest".to_string()));
+        settle().await;
+        guest.send(CliCommand::SendMessage("visible".to_string()));
+
+        let first = host
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if !m.is_system => Some(m.text.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(first, "visible");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trusted_nickname_with_another_key_is_flagged() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut bob = net.spawn_node("bob");
+        let mut impostor = net.spawn_node("bob");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(bob.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+        host.send(CliCommand::Trust(bob.display_name.clone()));
+        host.wait_for(|e| match e {
+            UiEvent::NewMessage(m) if m.text.contains("is in your contacts") => Some(()),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(impostor.join_room(&code, "pw").await, JoinState::Joined);
+        let warning = host
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.is_system && m.text.starts_with('⚠') => {
+                    Some(m.text.clone())
+                }
+                _ => None,
+            })
+            .await;
+        assert!(warning.contains(&impostor.display_name));
+        assert!(warning.contains(&bob.display_name));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn direct_message_reaches_only_its_recipient() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        for node in &mut nodes[1..] {
+            assert_eq!(node.join_room(&code, "pw").await, JoinState::Joined);
+        }
+        settle().await;
+
+        nodes[1].send(CliCommand::DirectMessage {
+            to: "node2".to_string(),
+            text: "psst".to_string(),
+        });
+        nodes[1].send(CliCommand::SendMessage("done".to_string()));
+
+        let expected = format!("{} → {}", nodes[1].display_name, nodes[2].display_name);
+        let sender = nodes[2]
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "psst" => Some(m.sender.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(sender, expected);
+        nodes[0]
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "psst" => panic!("bystander saw it"),
+                UiEvent::NewMessage(m) if m.text == "done" => Some(()),
+                _ => None,
+            })
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn owner_ban_removes_and_silences_a_member() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        for node in &mut nodes[1..] {
+            assert_eq!(node.join_room(&code, "pw").await, JoinState::Joined);
+        }
+        settle().await;
+
+        nodes[1].send(CliCommand::Ban("node2".to_string()));
+        nodes[1]
+            .wait_for(|e| match e {
+                UiEvent::Error(text) if text.contains("owner") => Some(()),
+                _ => None,
+            })
+            .await;
+
+        nodes[0].send(CliCommand::Ban("node2".to_string()));
+        nodes[2]
+            .wait_for(|e| match e {
+                UiEvent::ShowMainMenu => Some(()),
+                _ => None,
+            })
+            .await;
+        let banned = nodes[2].display_name.clone();
+        nodes[1]
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m)
+                    if m.text == format!("{banned} was banned by the room owner") =>
+                {
+                    Some(())
+                }
+                _ => None,
+            })
+            .await;
+
+        // Coming back with the code does not make it heard again.
+        assert_ne!(nodes[2].join_room(&code, "pw").await, JoinState::Denied);
+        nodes[2].send(CliCommand::SendMessage("still here".to_string()));
+        settle().await;
+        nodes[0].send(CliCommand::SendMessage("done".to_string()));
+        nodes[1]
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "still here" => panic!("banned peer shown"),
+                UiEvent::NewMessage(m) if m.text == "done" => Some(()),
+                _ => None,
+            })
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn kick_rotates_the_room_key_for_everyone_left() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        for node in &mut nodes[1..] {
+            assert_eq!(node.join_room(&code, "pw").await, JoinState::Joined);
+        }
+        settle().await;
+
+        nodes[0].send(CliCommand::Kick("node2".to_string()));
+        nodes[1]
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m)
+                    if m.text == "The room key was rotated by the room owner" =>
+                {
+                    Some(())
+                }
+                _ => None,
+            })
+            .await;
+        // Long enough that the old key is no longer read.
+        tokio::time::sleep(Duration::from_secs(60)).await;
+
+        for (from, to, text) in [(0, 1, "from the owner"), (1, 0, "from a member")] {
+            nodes[from].send(CliCommand::SendMessage(text.to_string()));
+            nodes[to]
+                .wait_for(|e| match e {
+                    UiEvent::NewMessage(m) if m.text == text => Some(()),
+                    _ => None,
+                })
+                .await;
+        }
+
+        // A newcomer gets the current key with its password check.
+        let mut late = net.spawn_node("late");
+        settle().await;
+        assert_eq!(late.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+        nodes[1].send(CliCommand::SendMessage("welcome".to_string()));
+        late.wait_for(|e| match e {
+            UiEvent::NewMessage(m) if m.text == "welcome" => Some(()),
+            _ => None,
+        })
+        .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn background_room_keeps_history_until_switched_to() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code_a = host.create_room("a", "pw").await;
+        let code_b = host.create_room("b", "pw").await;
+        assert_eq!(guest.join_room(&code_a, "pw").await, JoinState::Joined);
+        assert_eq!(guest.join_room(&code_b, "pw").await, JoinState::Joined);
+        settle().await;
+
+        // Guest is looking at "b"; a message in "a" must not be shown yet.
+        host.send(CliCommand::SwitchRoom("a".to_string()));
+        host.send(CliCommand::SendMessage("in a".to_string()));
+        settle().await;
+        guest.send(CliCommand::SwitchRoom("a".to_string()));
+        let history = guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "in a" => panic!("shown while in background"),
+                UiEvent::RoomSwitched { name, history } if name == "a" => Some(history.clone()),
+                _ => None,
+            })
+            .await;
+        assert!(history.iter().any(|m| !m.is_system && m.text == "in a"));
+    }
+    #[tokio::test(start_paused = true)]
+    async fn roster_lists_only_verified_members() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        let mut intruder = net.spawn_node("intruder");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        assert_eq!(intruder.join_room(&code, "nope").await, JoinState::Denied);
+        settle().await;
+
+        let host_view = host.members().await;
+        assert!(host_view.contains(&guest.display_name));
+        assert!(!host_view.contains(&intruder.display_name));
+        // The guest learned about the host from its roster announcement.
+        assert!(guest.members().await.contains(&host.display_name));
+
+        guest.send(CliCommand::LeaveRoom);
+        let left = format!("{} left the room", guest.display_name);
+        host.wait_for(|e| matches!(e, UiEvent::NewMessage(m) if m.text == left).then_some(()))
+            .await;
+        assert!(!host.members().await.contains(&guest.display_name));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn saved_room_is_rejoined_without_code_or_password() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        guest.send(CliCommand::SaveRoom);
+        guest
+            .wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text.starts_with("Saved 'lobby'"))
+                    .then_some(())
+            })
+            .await;
+        guest.send(CliCommand::LeaveRoom);
+        settle().await;
+
+        guest.send(CliCommand::Rejoin(Some("lobby".to_string())));
+        let state = guest
+            .wait_for(|e| match e {
+                UiEvent::JoinProgress { state, .. } if state.is_terminal() => Some(*state),
+                _ => None,
+            })
+            .await;
+        assert_eq!(state, JoinState::Joined);
+
+        guest.send(CliCommand::ForgetRoom("lobby".to_string()));
+        guest.send(CliCommand::Rejoin(Some("lobby".to_string())));
+        guest
+            .wait_for(|e| {
+                matches!(e, UiEvent::Error(t) if t.contains("No saved room")).then_some(())
+            })
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn entered_rooms_are_listed_as_recent_until_forgotten() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let recent = |e: &UiEvent| match e {
+            UiEvent::RecentRooms(rooms) => Some(rooms.clone()),
+            _ => None,
+        };
+        host.send(CliCommand::CreateRoom {
+            name: "lobby".to_string(),
+            password: "pw".to_string(),
+            persona: None,
+            max_members: 0,
+        });
+        let rooms = host.wait_for(|e| recent(e).filter(|r| !r.is_empty())).await;
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].name, "lobby");
+        let code = rooms[0].code.clone();
+
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        guest.send(CliCommand::SaveRoom);
+        // Entering the room, then saving it.
+        let rooms = guest
+            .wait_for(|e| recent(e).filter(|r| r.iter().any(|r| r.saved)))
+            .await;
+        assert_eq!(rooms[0].code, code);
+
+        guest.send(CliCommand::ForgetRecent("lobby".to_string()));
+        assert!(guest.wait_for(recent).await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn peers_lists_how_each_member_is_connected() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        assert!(host.members().await.contains(&guest.display_name));
+        let line = format!("  {}: ", guest.display_name);
+        let link = host
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) => m.text.strip_prefix(&line).map(str::to_string),
+                _ => None,
+            })
+            .await;
+        assert_eq!(link, "direct, 0 ms, memnet, connected 0s");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn quitting_announces_leave_before_disconnecting() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        guest.send(CliCommand::Quit);
+        let left = format!("{} left the room", guest.display_name);
+        host.wait_for(|e| match e {
+            UiEvent::NewMessage(m) if m.text.ends_with("disconnected") => {
+                panic!("quit was seen as a disconnect: {}", m.text)
+            }
+            UiEvent::NewMessage(m) if m.text == left => Some(()),
+            _ => None,
+        })
+        .await;
+        assert!(!host.members().await.contains(&guest.display_name));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn joiner_receives_recent_history() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = host.create_room("lobby", "pw").await;
+        host.send(CliCommand::SendMessage("first".to_string()));
+        host.send(CliCommand::SendMessage("second".to_string()));
+        settle().await;
+
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        let history = guest
+            .wait_for(|e| match e {
+                UiEvent::RoomSwitched { history, .. } => Some(history.clone()),
+                _ => None,
+            })
+            .await;
+        let texts: Vec<&str> = history
+            .iter()
+            .filter(|m| !m.is_system)
+            .map(|m| m.text.as_str())
+            .collect();
+        assert_eq!(texts, ["first", "second"]);
+        assert!(
+            history
+                .iter()
+                .all(|m| m.is_system || m.sender == host.display_name)
+        );
+    }
+    #[tokio::test(start_paused = true)]
+    async fn replayed_payload_is_dropped() {
+        use crate::{
+            crypto::RoomKey,
+            room::topic_for_room,
+            types::{NetworkCommand, NetworkEvent},
+            wire,
+        };
+
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+
+        // An observer on the topic captures the ciphertext of one message.
+        let (node, mut events, cmds) = net.net.attach("tap");
+        tokio::spawn(node.run());
+        let topic = topic_for_room("lobby", &RoomCodeData::decode(&code).unwrap().salt);
+        cmds.send(NetworkCommand::Subscribe(topic.clone())).unwrap();
+        settle().await;
+        host.send(CliCommand::SendMessage("once".to_string()));
+        let salt = RoomCodeData::decode(&code).unwrap().salt;
+        let key = RoomKey::derive("pw", &salt).unwrap();
+        let captured = loop {
+            if let Some(NetworkEvent::MessageReceived { payload, .. }) = events.recv().await
+                && let Ok(plain) = key.decrypt(&payload)
+                && wire::decode(&plain).is_ok_and(|w| w.text == "once")
+            {
+                break payload;
+            }
+        };
+        guest
+            .wait_for(|e| matches!(e, UiEvent::NewMessage(m) if m.text == "once").then_some(()))
+            .await;
+
+        cmds.send(NetworkCommand::Publish {
+            topic,
+            data: captured,
+        })
+        .unwrap();
+        settle().await;
+        host.send(CliCommand::SendMessage("after".to_string()));
+        guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "once" => panic!("replay shown"),
+                UiEvent::NewMessage(m) if m.text == "after" => Some(()),
+                _ => None,
+            })
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flooding_peer_is_cut_off() {
+        use crate::{
+            crypto::RoomKey,
+            room::topic_for_room,
+            types::{NetworkCommand, WireMessage, WireMessageType},
+            wire::RATE_BURST,
+        };
+
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+
+        // A peer that knows the password floods the topic with junk, then
+        // sends a real message.
+        let (node, _events, cmds) = net.net.attach("flooder");
+        tokio::spawn(node.run());
+        let topic = topic_for_room("lobby", &RoomCodeData::decode(&code).unwrap().salt);
+        cmds.send(NetworkCommand::Subscribe(topic.clone())).unwrap();
+        for _ in 0..2 * RATE_BURST as usize {
+            let data = vec![0; 64];
+            cmds.send(NetworkCommand::Publish {
+                topic: topic.clone(),
+                data,
+            })
+            .unwrap();
+        }
+        settle().await;
+        let spam = WireMessage {
+            msg_type: WireMessageType::Chat,
+            sender_nick: "flooder".to_string(),
+            sender_disc: "0000".to_string(),
+            timestamp_ms: chrono::Utc::now().timestamp_millis(),
+            msg_id: 1,
+            text: "spam".to_string(),
+            avatar: String::new(),
+            reply_to: 0,
+            chunk: None,
+            thread: String::new(),
+            public_key: String::new(),
+            signature: String::new(),
+        };
+        let key = RoomKey::derive("pw", &RoomCodeData::decode(&code).unwrap().salt).unwrap();
+        let data = key.encrypt(&serde_json::to_vec(&spam).unwrap()).unwrap();
+        cmds.send(NetworkCommand::Publish { topic, data }).unwrap();
+        settle().await;
+
+        host.send(CliCommand::SendMessage("after".to_string()));
+        guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "spam" => panic!("flooder still heard"),
+                UiEvent::NewMessage(m) if m.text == "after" => Some(()),
+                _ => None,
+            })
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forged_sender_is_marked_unverified() {
+        use crate::{
+            crypto::RoomKey,
+            room::topic_for_room,
+            types::{NetworkCommand, WireMessage, WireMessageType},
+        };
+
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+
+        // A raw peer that knows the password claims to be the host.
+        let (node, _events, cmds) = net.net.attach("forger");
+        tokio::spawn(node.run());
+        let (nick, disc) = host.display_name.rsplit_once('#').unwrap();
+        let forged = WireMessage {
+            msg_type: WireMessageType::Chat,
+            sender_nick: nick.to_string(),
+            sender_disc: disc.to_string(),
+            timestamp_ms: chrono::Utc::now().timestamp_millis(),
+            msg_id: 1,
+            text: "send me your password".to_string(),
+            avatar: String::new(),
+            reply_to: 0,
+            chunk: None,
+            thread: String::new(),
+            public_key: String::new(),
+            signature: String::new(),
+        };
+        let salt = RoomCodeData::decode(&code).unwrap().salt;
+        let key = RoomKey::derive("pw", &salt).unwrap();
+        let topic = topic_for_room("lobby", &salt);
+        cmds.send(NetworkCommand::Subscribe(topic.clone())).unwrap();
+        cmds.send(NetworkCommand::Publish {
+            topic,
+            data: key.encrypt(&serde_json::to_vec(&forged).unwrap()).unwrap(),
+        })
+        .unwrap();
+
+        let msg = guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == forged.text => Some(m.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(msg.sender, host.display_name);
+        assert!(!msg.verified);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_capture_replays_room_messages_older_than_the_replay_window() {
+        use crate::{
+            types::{NetworkEvent, WireMessage, WireMessageType},
+            wire::{self, REPLAY_WINDOW_MS},
+        };
+
+        let net = TestNet::new();
+        let keypair = Keypair::generate_ed25519();
+        let mut host = net.spawn_keyed("host", &keypair);
+        let (capture_tx, mut capture) = mpsc::unbounded_channel();
+        let mut member = net.spawn_with("member", None, |app| app.with_capture(capture_tx));
+        settle().await;
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(member.join_room(&code, "pw").await, JoinState::Joined);
+
+        // The member's capture holds what it read, not the ciphertext.
+        host.send(CliCommand::SendMessage("hello".to_string()));
+        member
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "hello" => Some(()),
+                _ => None,
+            })
+            .await;
+        let mut read = Vec::new();
+        while let Ok(event) = capture.try_recv() {
+            if let NetworkEvent::RoomPlaintext {
+                room, owner, data, ..
+            } = event
+            {
+                assert_eq!(
+                    (room.as_str(), owner.as_str()),
+                    ("lobby", host.peer_id.as_str())
+                );
+                read.push(wire::decode(&data).unwrap().text);
+            }
+        }
+        assert!(read.contains(&"hello".to_string()), "{read:?}");
+
+        // An hour later, a fresh app with no key for the room replays a
+        // message the host signed back then.
+        let sent_ms = chrono::Utc::now().timestamp_millis() - 6 * REPLAY_WINDOW_MS;
+        let (nick, disc) = host.display_name.rsplit_once('#').unwrap();
+        let mut old = WireMessage {
+            msg_type: WireMessageType::Chat,
+            sender_nick: nick.to_string(),
+            sender_disc: disc.to_string(),
+            timestamp_ms: sent_ms,
+            msg_id: 7,
+            text: "from an hour ago".to_string(),
+            avatar: String::new(),
+            reply_to: 0,
+            chunk: None,
+            thread: String::new(),
+            public_key: String::new(),
+            signature: String::new(),
+        };
+        wire::sign(&mut old, &keypair).unwrap();
+        let mut config = test_config("replay", None);
+        let identity = Identity::load_or_create(&mut config).unwrap();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let (cmd_tx, _cmds) = mpsc::unbounded_channel();
+        let (_cli_tx, cli_rx) = mpsc::unbounded_channel();
+        let bus = EventBus::new();
+        let mut events = bus.subscribe();
+        let app = App::new(identity, config, event_rx, cmd_tx, cli_rx, bus);
+        tokio::spawn(async move {
+            let _ = app.run().await;
+        });
+        event_tx
+            .send(NetworkEvent::RoomPlaintext {
+                room: "lobby".to_string(),
+                owner: host.peer_id.clone(),
+                data: wire::encode(&old).unwrap(),
+                received_ms: sent_ms + 150,
+            })
+            .unwrap();
+        let msg = tokio::time::timeout(WAIT_LIMIT, async {
+            loop {
+                if let Ok(AppEvent::Ui(UiEvent::NewMessage(m))) = events.recv().await
+                    && !m.is_system
+                {
+                    return m;
+                }
+            }
+        })
+        .await
+        .expect("the old message is shown");
+        assert_eq!(
+            (msg.sender.as_str(), msg.text.as_str(), msg.verified),
+            (host.display_name.as_str(), "from an hour ago", true)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aliases_replace_nicknames_in_the_chat_and_member_list() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        host.send(CliCommand::Alias {
+            who: "guest".to_string(),
+            alias: Some("Guest from work".to_string()),
+        });
+        host.wait_for(|e| {
+            matches!(e, UiEvent::NewMessage(m) if m.text.contains("shown as")).then_some(())
+        })
+        .await;
+        guest.send(CliCommand::SendMessage("hi".to_string()));
+        let msg = host
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "hi" => Some(m.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(msg.sender, guest.display_name);
+        assert_eq!(msg.sender_label(), "Guest from work");
+        assert_eq!(msg.real_label(), guest.display_name);
+        let members = host.members().await;
+        assert!(
+            members.contains("Guest from work") && !members.contains(&guest.display_name),
+            "{members}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reload_applies_the_edited_config_and_names_what_waits_for_a_restart() {
+        let net = TestNet::new();
+        let mut node = net.spawn_node("node");
+        settle().await;
+        let notice = |text: &'static str| {
+            move |e: &UiEvent| match e {
+                UiEvent::NewMessage(m) if m.text.starts_with(text) => Some(m.text.clone()),
+                UiEvent::Error(e) if e.starts_with(text) => Some(e.clone()),
+                _ => None,
+            }
+        };
+        node.send(CliCommand::ReloadConfig);
+        node.wait_for(notice("Config not reloaded")).await;
+
+        // Any change saves the config; then edit the file behind its back.
+        node.send(CliCommand::ChangeAvatar("🦊".to_string()));
+        node.wait_for(|e| matches!(e, UiEvent::AvatarChanged(_)).then_some(()))
+            .await;
+        let mut edited = Config::load(node.config_path.clone()).unwrap();
+        edited.notify_bell = false;
+        edited.bootstrap_peers.clear();
+        edited.save().unwrap();
+
+        node.send(CliCommand::ReloadConfig);
+        assert_eq!(
+            node.wait_for(notice("Applied")).await,
+            "Applied: notify_bell."
+        );
+        assert_eq!(
+            node.wait_for(notice("Takes a restart")).await,
+            "Takes a restart: bootstrap_peers."
+        );
+        node.send(CliCommand::ReloadConfig);
+        node.wait_for(notice("Reloaded")).await;
+        let _ = std::fs::remove_file(&node.config_path);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn avatars_go_before_the_name_in_the_chat_and_member_list() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+        host.send(CliCommand::ChangeAvatar("ab".to_string()));
+        host.wait_for(|e| {
+            matches!(e, UiEvent::Error(e) if e.starts_with("Invalid avatar")).then_some(())
+        })
+        .await;
+        host.send(CliCommand::ChangeAvatar("🦊".to_string()));
+        host.wait_for(|e| matches!(e, UiEvent::AvatarChanged(Some(a)) if a == "🦊").then_some(()))
+            .await;
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+        let members = guest.members().await;
+        assert!(
+            members.contains(&format!("🦊 {}", host.display_name)),
+            "{members}"
+        );
+
+        host.send(CliCommand::SendMessage("hi".to_string()));
+        let msg = guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "hi" => Some(m.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(msg.sender_label(), format!("🦊 {}", host.display_name));
+        assert_eq!(msg.real_label(), host.display_name);
+
+        host.send(CliCommand::ChangeAvatar(String::new()));
+        host.send(CliCommand::SendMessage("plain".to_string()));
+        let msg = guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "plain" => Some(m.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(msg.avatar, None);
+        let members = guest.members().await;
+        assert!(!members.contains('🦊'), "{members}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_persona_with_its_own_key_is_not_linked_to_the_usual_identity() {
+        let net = TestNet::new();
+        let mut host = net.spawn_node("host");
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+        let code = host.create_room("lobby", "pw").await;
+        let persona = Persona {
+            nickname: Some("Ghost".to_string()),
+            own_key: true,
+        };
+        guest.send(CliCommand::JoinRoom {
+            code,
+            password: "pw".to_string(),
+            persona: Some(persona),
+        });
+        guest
+            .wait_for(|e| matches!(e, UiEvent::RoomJoined(_)).then_some(()))
+            .await;
+        settle().await;
+
+        guest.send(CliCommand::SendMessage("boo".to_string()));
+        let msg = host
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text == "boo" => Some(m.clone()),
+                _ => None,
+            })
+            .await;
+        let (nick, disc) = msg.sender.split_once('#').unwrap();
+        assert_eq!(nick, "Ghost");
+        assert!(msg.verified);
+        assert!(
+            !guest.display_name.ends_with(&format!("#{disc}")),
+            "{} vs {}",
+            msg.sender,
+            guest.display_name
+        );
+        let members = host.members().await;
+        assert!(
+            members.contains("Ghost") && !members.contains(&guest.display_name),
+            "{members}"
+        );
+
+        host.send(CliCommand::DirectMessage {
+            to: "Ghost".to_string(),
+            text: "psst".to_string(),
+        });
+        guest
+            .wait_for(|e| matches!(e, UiEvent::NewMessage(m) if m.text == "psst").then_some(()))
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn members_with_the_same_name_get_longer_discriminators() {
+        // Two keys whose short discriminators collide.
+        let mut seen = std::collections::HashMap::new();
+        let (a, b) = loop {
+            let key = Keypair::generate_ed25519();
+            let disc = crate::identity::discriminator(
+                &key.public().to_peer_id(),
+                crate::wire::MIN_DISC_LEN,
+            );
+            if let Some(other) = seen.insert(disc, key.clone()) {
+                break (other, key);
+            }
+        };
+        let net = TestNet::new();
+        let mut host = net.spawn_keyed("twin", &a);
+        let mut guest = net.spawn_keyed("twin", &b);
+        let mut watcher = net.spawn_node("watcher");
+        settle().await;
+        assert_eq!(host.display_name, guest.display_name);
+
+        let code = host.create_room("lobby", "pw").await;
+        assert_eq!(guest.join_room(&code, "pw").await, JoinState::Joined);
+        assert_eq!(watcher.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        host.send(CliCommand::SendMessage("from a".to_string()));
+        guest.send(CliCommand::SendMessage("from b".to_string()));
+        let (mut from_a, mut from_b) = (None, None);
+        watcher
+            .wait_for(|e| {
+                match e {
+                    UiEvent::NewMessage(m) if m.text == "from a" => from_a = Some(m.sender.clone()),
+                    UiEvent::NewMessage(m) if m.text == "from b" => from_b = Some(m.sender.clone()),
+                    _ => {}
+                }
+                (from_a.is_some() && from_b.is_some()).then_some(())
+            })
+            .await;
+        let (from_a, from_b) = (from_a.unwrap(), from_b.unwrap());
+        assert_ne!(from_a, from_b);
+        assert!(
+            from_a.starts_with(&host.display_name) && from_a.len() == host.display_name.len() + 4
+        );
+        assert!(
+            from_b.starts_with(&guest.display_name) && from_b.len() == guest.display_name.len() + 4
+        );
+
+        // The twins are told someone shares their name.
+        let notice = host
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text.contains("as you are") => Some(m.text.clone()),
+                _ => None,
+            })
+            .await;
+        assert!(notice.contains(&from_b));
+        let members = watcher.members().await;
+        assert!(
+            members.contains(&from_a) && members.contains(&from_b),
+            "{members}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invite_only_rooms_take_a_single_use_token_once() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        nodes[0].send(CliCommand::InviteOnly(Some(true)));
+        settle().await;
+        let denied = |e: &UiEvent| match e {
+            UiEvent::AccessDenied(why) => Some(why.clone().unwrap_or_default()),
+            _ => None,
+        };
+        nodes[1].send(CliCommand::JoinRoom {
+            code: code.clone(),
+            password: "pw".to_string(),
+            persona: None,
+        });
+        assert!(nodes[1].wait_for(denied).await.contains("invite only"));
+
+        nodes[0].send(CliCommand::MintInvite {
+            hours: 1,
+            single_use: true,
+        });
+        let token = nodes[0]
+            .wait_for(|e| match e {
+                UiEvent::RoomCode { code, .. } => Some(code.clone()),
+                _ => None,
+            })
+            .await;
+        assert!(token.starts_with(&code));
+        assert_eq!(nodes[1].join_room(&token, "pw").await, JoinState::Joined);
+        settle().await;
+
+        // Both members know the token is spent.
+        nodes[2].send(CliCommand::JoinRoom {
+            code: token,
+            password: "pw".to_string(),
+            persona: None,
+        });
+        assert!(nodes[2].wait_for(denied).await.contains("already used"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_full_room_turns_joiners_away_until_the_owner_lifts_the_limit() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        nodes[0].send(CliCommand::CreateRoom {
+            name: "lobby".to_string(),
+            password: "pw".to_string(),
+            persona: None,
+            max_members: 2,
+        });
+        let code = nodes[0]
+            .wait_for(|e| match e {
+                UiEvent::RoomCreated { code, .. } => Some(code.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(nodes[1].join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        nodes[2].send(CliCommand::JoinRoom {
+            code: code.clone(),
+            password: "pw".to_string(),
+            persona: None,
+        });
+        let full = nodes[2]
+            .wait_for(|e| match e {
+                UiEvent::RoomFull { room, max_members } => Some((room.clone(), *max_members)),
+                _ => None,
+            })
+            .await;
+        assert_eq!(full, ("lobby".to_string(), 2));
+
+        nodes[0].send(CliCommand::Capacity(Some(0)));
+        nodes[1]
+            .wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text.contains("member limit was lifted"))
+                    .then_some(())
+            })
+            .await;
+        assert_eq!(nodes[2].join_room(&code, "pw").await, JoinState::Joined);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_listed_room_is_found_in_the_directory_and_joined_from_it() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(2);
+        settle().await;
+
+        nodes[0].create_room("lobby", "").await;
+        nodes[0].send(CliCommand::ListPublic(Some("board games".to_string())));
+        settle().await;
+        nodes[1].send(CliCommand::BrowseRooms);
+        let room = nodes[1]
+            .wait_for(|e| match e {
+                UiEvent::PublicRooms(rooms) => rooms.first().cloned(),
+                _ => None,
+            })
+            .await;
+        assert_eq!(
+            (room.name.as_str(), room.description.as_str(), room.members),
+            ("lobby", "board games", 1)
+        );
+        assert_eq!(nodes[1].join_room(&room.code, "").await, JoinState::Joined);
+
+        nodes[0].send(CliCommand::ListPublic(None));
+        nodes[1]
+            .wait_for(|e| {
+                matches!(e, UiEvent::PublicRooms(rooms) if rooms.is_empty()).then_some(())
+            })
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn co_owners_set_the_topic_and_ownership_moves_on() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        for node in &mut nodes[1..] {
+            assert_eq!(node.join_room(&code, "pw").await, JoinState::Joined);
+        }
+        settle().await;
+
+        nodes[1].send(CliCommand::Topic(Some("mine".to_string())));
+        nodes[1]
+            .wait_for(|e| {
+                matches!(e, UiEvent::Error(text) if text.contains("co-owner")).then_some(())
+            })
+            .await;
+        nodes[0].send(CliCommand::GrantAdmin {
+            who: "node1".to_string(),
+            admin: true,
+        });
+        nodes[1]
+            .wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text == "You are a co-owner now")
+                    .then_some(())
+            })
+            .await;
+        nodes[1].send(CliCommand::Topic(Some("mine".to_string())));
+        nodes[2]
+            .wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text.ends_with("a co-owner: mine"))
+                    .then_some(())
+            })
+            .await;
+        nodes[1].send(CliCommand::Topic(Some(String::new())));
+        let cleared = format!(
+            "The topic was cleared by {}, a co-owner",
+            nodes[1].display_name
+        );
+        nodes[2]
+            .wait_for(|e| matches!(e, UiEvent::NewMessage(m) if m.text == cleared).then_some(()))
+            .await;
+        nodes[1].send(CliCommand::Kick("node0".to_string()));
+        nodes[1]
+            .wait_for(|e| {
+                matches!(e, UiEvent::Error(text) if text.contains("owns the room")).then_some(())
+            })
+            .await;
+
+        nodes[0].send(CliCommand::TransferOwnership("node2".to_string()));
+        nodes[2]
+            .wait_for(|e| matches!(e, UiEvent::NewMessage(m) if m.text.ends_with("handed the room to you, and stays a co-owner")).then_some(()))
+            .await;
+        nodes[2].send(CliCommand::Whois("node0".to_string()));
+        nodes[2]
+            .wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text.contains(": co-owner of 'lobby'"))
+                    .then_some(())
+            })
+            .await;
+        nodes[0].send(CliCommand::Capacity(Some(5)));
+        nodes[0]
+            .wait_for(|e| matches!(e, UiEvent::Error(text) if text.contains("owner")).then_some(()))
+            .await;
+        // Co-owners cannot remove each other; the new owner can.
+        nodes[1].send(CliCommand::Kick("node0".to_string()));
+        nodes[1]
+            .wait_for(|e| {
+                matches!(e, UiEvent::Error(text) if text.contains("remove a co-owner"))
+                    .then_some(())
+            })
+            .await;
+        nodes[2].send(CliCommand::Kick("node0".to_string()));
+        nodes[0]
+            .wait_for(|e| matches!(e, UiEvent::ShowMainMenu).then_some(()))
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_renamed_room_takes_its_members_along_and_old_codes_go_stale() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        assert_eq!(nodes[1].join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        nodes[1].send(CliCommand::RenameRoom("den".to_string()));
+        nodes[1]
+            .wait_for(|e| matches!(e, UiEvent::Error(text) if text.contains("owner")).then_some(()))
+            .await;
+        nodes[0].send(CliCommand::RenameRoom("den".to_string()));
+        let renamed = nodes[1]
+            .wait_for(|e| match e {
+                UiEvent::RoomRenamed { from, to } => Some((from.clone(), to.clone())),
+                _ => None,
+            })
+            .await;
+        assert_eq!(renamed, ("lobby".to_string(), "den".to_string()));
+        settle().await;
+        // The owner signed for the new name, so members still make codes.
+        nodes[1].send(CliCommand::Invite);
+        let invite = nodes[1]
+            .wait_for(|e| match e {
+                UiEvent::RoomCode { code, .. } => Some(code.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(RoomCodeData::decode(&invite).unwrap().room_name, "den");
+        nodes[0].send(CliCommand::SendMessage("on the new topic".to_string()));
+        nodes[1]
+            .wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text == "on the new topic").then_some(())
+            })
+            .await;
+
+        nodes[1].send(CliCommand::JoinRoom {
+            code: code.clone(),
+            password: "pw".to_string(),
+            persona: None,
+        });
+        nodes[1]
+            .wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text.starts_with("This code is stale"))
+                    .then_some(())
+            })
+            .await;
+        nodes[0].send(CliCommand::ShowCode);
+        let fresh = nodes[0]
+            .wait_for(|e| match e {
+                UiEvent::RoomCode { code, .. } => Some(code.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(nodes[2].join_room(&fresh, "pw").await, JoinState::Joined);
+        nodes[2].send(CliCommand::SendMessage("found it".to_string()));
+        nodes[1]
+            .wait_for(|e| matches!(e, UiEvent::NewMessage(m) if m.text == "found it").then_some(()))
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_members_invite_leads_to_it_and_keeps_the_owner() {
+        let net = TestNet::new();
+        let mut nodes = net.spawn_nodes(3);
+        settle().await;
+
+        let code = nodes[0].create_room("lobby", "pw").await;
+        assert_eq!(nodes[1].join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+
+        nodes[1].send(CliCommand::Invite);
+        let invite = nodes[1]
+            .wait_for(|e| match e {
+                UiEvent::RoomCode { code, .. } => Some(code.clone()),
+                _ => None,
+            })
+            .await;
+        let data = RoomCodeData::decode(&invite).unwrap();
+        assert_eq!(data.peer_id, nodes[1].peer_id);
+        assert_eq!(data.owner(), nodes[0].peer_id);
+        assert_eq!(data.addrs, [MemoryNetwork::addr_of(&data.peer_id)]);
+
+        assert_eq!(nodes[2].join_room(&invite, "pw").await, JoinState::Joined);
+        // Only the owner's topic counts, so the joiner must know who it is.
+        nodes[0].send(CliCommand::Topic(Some("still mine".to_string())));
+        nodes[2]
+            .wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text == "The topic was set by the room owner: still mine").then_some(())
+            })
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_member_posing_as_owner_in_its_own_code_is_not_obeyed() {
+        let net = TestNet::new();
+        let forger_key = Keypair::generate_ed25519();
+        let mut owner = net.spawn_node("owner");
+        let mut forger = net.spawn_keyed("forger", &forger_key);
+        let mut guest = net.spawn_node("guest");
+        settle().await;
+
+        let code = owner.create_room("lobby", "pw").await;
+        assert_eq!(forger.join_room(&code, "pw").await, JoinState::Joined);
+        settle().await;
+        forger.send(CliCommand::LeaveRoom);
+        settle().await;
+
+        // An owner's code, but signed by the member: it names itself.
+        let forged = RoomCodeData {
+            peer_id: forger.peer_id.clone(),
+            addrs: vec![MemoryNetwork::addr_of(&owner.peer_id)],
+            ..RoomCodeData::decode(&code).unwrap()
+        }
+        .encode(&forger_key)
+        .unwrap();
+        assert_eq!(guest.join_room(&forged, "pw").await, JoinState::Joined);
+        guest
+            .wait_for(|e| {
+                matches!(e, UiEvent::NewMessage(m) if m.text.contains("Neither is obeyed as owner"))
+                    .then_some(())
+            })
+            .await;
+
+        owner.send(CliCommand::Topic(Some("mine".to_string())));
+        owner.send(CliCommand::SendMessage("after".to_string()));
+        let mut topics = 0;
+        guest
+            .wait_for(|e| match e {
+                UiEvent::NewMessage(m) if m.text.starts_with("The topic was set") => {
+                    topics += 1;
+                    None
+                }
+                UiEvent::NewMessage(m) if m.text == "after" => Some(()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(topics, 0);
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result, bail};
+use base64::{Engine, engine::general_purpose::STANDARD as B64};
+use libp2p::{
+    PeerId,
+    identity::{self, Keypair},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{
+    config::{Config, KeyStorage, Persona, write_private},
+    contacts::Contact,
+    crypto::{self, RoomKey, RoomSalt},
+    wire::{self, MAX_DISC_LEN, MIN_DISC_LEN},
+};
+
+/// First line of an identity archive.
+const ARCHIVE_HEADER: &str = "chatting1 identity archive v1";
+/// Service of the key pair's OS keyring entry; the account is the config
+/// file's path, so each profile has its own.
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "chatting1";
+/// Ends errors from the keyring.
+#[cfg(feature = "keyring")]
+const KEYRING_HINT: &str = " (key_storage = \"config\" keeps it in the config file)";
+
+#[derive(Clone)]
+pub struct Identity {
+    pub keypair: Keypair,
+    pub peer_id: PeerId,
+    pub nickname: String,
+    /// `discriminator_len` hex digits derived from the public key, e.g.
+    /// "3f2a".
+    pub discriminator: String,
+    /// Shown before the nickname; `None` for none.
+    pub avatar: Option<String>,
+}
+
+impl Identity {
+    /// Load or generate an Ed25519 keypair from where `config` keeps it,
+    /// then build the identity.  A new keypair is stored right away, but
+    /// the caller saves `config`.
+    pub fn load_or_create(config: &mut Config) -> Result<Self> {
+        let keypair = match stored_key(config)? {
+            Some(b64) => {
+                let bytes = Zeroizing::new(
+                    B64.decode(b64.as_str())
+                        .context("decode private key base64")?,
+                );
+                Keypair::from_protobuf_encoding(&bytes).context("decode keypair from protobuf")?
+            }
+            None => {
+                let kp = identity::Keypair::generate_ed25519();
+                let bytes = Zeroizing::new(
+                    kp.to_protobuf_encoding()
+                        .context("encode keypair to protobuf")?,
+                );
+                store_key(config, &Zeroizing::new(B64.encode(&bytes)))?;
+                kp
+            }
+        };
+
+        let peer_id = PeerId::from(keypair.public());
+        let len = config.discriminator_len.clamp(MIN_DISC_LEN, MAX_DISC_LEN);
+        let discriminator = discriminator(&peer_id, len);
+
+        let nickname = config
+            .nickname
+            .clone()
+            .unwrap_or_else(|| format!("Peer{}", &discriminator));
+        let avatar = config
+            .avatar
+            .clone()
+            .filter(|a| wire::validate_avatar(a).is_ok());
+
+        Ok(Self {
+            keypair,
+            peer_id,
+            nickname,
+            discriminator,
+            avatar,
+        })
+    }
+
+    /// Who we are as `persona`: its nickname, and with `own_key` a fresh
+    /// key pair with a discriminator as long as ours.  No avatar, which
+    /// would give the usual identity away.
+    pub fn persona(&self, persona: &Persona) -> Self {
+        let mut identity = self.clone();
+        identity.avatar = None;
+        if persona.own_key {
+            identity.keypair = Keypair::generate_ed25519();
+            identity.peer_id = PeerId::from(identity.keypair.public());
+            identity.discriminator = discriminator(&identity.peer_id, self.discriminator.len());
+        }
+        if let Some(nickname) = &persona.nickname {
+            identity.nickname = nickname.clone();
+        }
+        identity
+    }
+
+    /// Returns the formatted display name, e.g. `"Seung#3f2a"`.
+    pub fn display_name(&self) -> String {
+        format!("{}#{}", self.nickname, self.discriminator)
+    }
+}
+
+/// The private key (base64 of the protobuf keypair) from where
+/// `key_storage` keeps it; `None` if there is none yet.  A key still in the
+/// config, or in the key file when `key_storage` no longer says so, is
+/// moved over, so save the config after.  The key file is left in place.
+pub fn stored_key(config: &mut Config) -> Result<Option<Zeroizing<String>>> {
+    let in_config = config.private_key_b64.clone().map(Zeroizing::new);
+    let (stored, elsewhere) = match config.key_storage {
+        KeyStorage::File => {
+            let in_file = read_key_file(config)?;
+            if let (Some(file), Some(old)) = (&in_file, &in_config)
+                && file != old
+            {
+                bail!(
+                    "{} and {} hold different private keys; remove the one not to keep",
+                    config.key_path().display(),
+                    config.path.display()
+                );
+            }
+            (in_file, in_config)
+        }
+        KeyStorage::Config => match in_config {
+            Some(key) => (Some(key), None),
+            None => (None, read_key_file(config)?),
+        },
+        KeyStorage::Keyring => match in_config {
+            Some(key) => (None, Some(key)),
+            None => match keyring_get(config)? {
+                Some(key) => (Some(key), None),
+                None => (None, read_key_file(config)?),
+            },
+        },
+    };
+    match (stored, elsewhere) {
+        (None, Some(key)) => {
+            store_key(config, &key)?;
+            Ok(Some(key))
+        }
+        (stored, _) => {
+            if config.key_storage != KeyStorage::Config {
+                config.private_key_b64 = None;
+            }
+            Ok(stored)
+        }
+    }
+}
+
+/// Keep `key` (base64 of the protobuf keypair) where `key_storage` says.
+pub fn store_key(config: &mut Config, key: &str) -> Result<()> {
+    match config.key_storage {
+        KeyStorage::File => {
+            write_private(&config.key_path(), &format!("{key}\n"))?;
+            config.private_key_b64 = None;
+        }
+        KeyStorage::Config => config.private_key_b64 = Some(key.to_string()),
+        KeyStorage::Keyring => {
+            keyring_set(config, key)?;
+            config.private_key_b64 = None;
+        }
+    }
+    Ok(())
+}
+
+/// The key in `config`'s key file, `None` if there is no such file.
+fn read_key_file(config: &Config) -> Result<Option<Zeroizing<String>>> {
+    let path = config.key_path();
+    match std::fs::read_to_string(&path) {
+        Ok(text) => Ok(Some(Zeroizing::new(
+            Zeroizing::new(text).trim().to_string(),
+        ))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("cannot read {}", path.display())),
+    }
+}
+
+/// A warning if the file holding the private key of `config` — its key
+/// file, or the config file itself with `key_storage = "config"` — can be
+/// read by others than its owner, as it can after a copy or an archive
+/// restore.
+pub fn key_file_warning(config: &Config) -> Option<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let path = match config.key_storage {
+            KeyStorage::File => config.key_path(),
+            KeyStorage::Config if config.private_key_b64.is_some() => config.path.clone(),
+            _ => return None,
+        };
+        let mode = std::fs::metadata(&path).ok()?.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Some(format!(
+                "{} holds your private key but others may read it (mode {mode:o}): chmod 600 it.",
+                path.display()
+            ));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = config;
+    None
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_entry(config: &Config) -> Result<keyring::Entry> {
+    let account = std::path::absolute(&config.path)?;
+    keyring::Entry::new(KEYRING_SERVICE, &account.to_string_lossy())
+        .context("cannot open the OS keyring")
+}
+
+/// Run `f`, which blocks on the keyring (D-Bus on Linux).  Within a Tokio
+/// runtime the worker thread must say so, or the keyring's own runtime
+/// refuses to start.
+#[cfg(feature = "keyring")]
+fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match tokio::runtime::Handle::try_current() {
+        Ok(_) => tokio::task::block_in_place(f),
+        Err(_) => f(),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_get(config: &Config) -> Result<Option<Zeroizing<String>>> {
+    let entry = keyring_entry(config)?;
+    match blocking(|| entry.get_password()) {
+        Ok(key) => Ok(Some(Zeroizing::new(key))),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).context(format!(
+            "cannot read the private key from the OS keyring{KEYRING_HINT}"
+        )),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_set(config: &Config, key: &str) -> Result<()> {
+    let entry = keyring_entry(config)?;
+    blocking(|| entry.set_password(key)).context(format!(
+        "cannot store the private key in the OS keyring{KEYRING_HINT}"
+    ))
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_get(_config: &Config) -> Result<Option<Zeroizing<String>>> {
+    bail!("key_storage = \"keyring\" needs a build with the keyring feature")
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_set(_config: &Config, _key: &str) -> Result<()> {
+    bail!("key_storage = \"keyring\" needs a build with the keyring feature")
+}
+
+/// An identity to move to another machine (`chat identity export`): the
+/// key pair, nickname and contacts.
+#[derive(Serialize, Deserialize)]
+pub struct Archive {
+    /// The keypair as the config holds it: protobuf, then base64.
+    pub private_key_b64: String,
+    pub nickname: Option<String>,
+    /// Contacts by peer ID.
+    pub contacts: BTreeMap<String, Contact>,
+}
+
+impl Archive {
+    /// The archive as a text file sealed with `passphrase`: a header line,
+    /// then base64 of the Argon2 salt and of the encrypted JSON, a line
+    /// each.
+    pub fn seal(&self, passphrase: &str) -> Result<String> {
+        let salt = crypto::random_salt();
+        let key = RoomKey::derive(passphrase, &salt)?;
+        let plain = Zeroizing::new(serde_json::to_vec(self)?);
+        let sealed = key.encrypt(&plain)?;
+        Ok(format!(
+            "{ARCHIVE_HEADER}\n{}\n{}\n",
+            B64.encode(salt),
+            B64.encode(sealed)
+        ))
+    }
+
+    /// Open an archive written by [`Archive::seal`].  Fails on anything
+    /// else, and on a wrong passphrase.
+    pub fn open(text: &str, passphrase: &str) -> Result<Self> {
+        let mut lines = text.lines().map(str::trim);
+        if lines.next() != Some(ARCHIVE_HEADER) {
+            bail!("not an identity archive");
+        }
+        let (Some(salt), Some(sealed)) = (lines.next(), lines.next()) else {
+            bail!("identity archive is cut short");
+        };
+        let salt: RoomSalt = B64
+            .decode(salt)
+            .ok()
+            .and_then(|s| s.try_into().ok())
+            .context("identity archive is corrupt")?;
+        let sealed = B64.decode(sealed).context("identity archive is corrupt")?;
+        let key = RoomKey::derive(passphrase, &salt)?;
+        let plain = Zeroizing::new(
+            key.decrypt(&sealed)
+                .context("wrong passphrase, or the archive was altered")?,
+        );
+        let archive: Self =
+            serde_json::from_slice(&plain).context("identity archive is corrupt")?;
+        let key = Zeroizing::new(
+            B64.decode(&archive.private_key_b64)
+                .context("archived key is not base64")?,
+        );
+        Keypair::from_protobuf_encoding(&key).context("archived key is not a key pair")?;
+        Ok(archive)
+    }
+}
+
+impl Drop for Archive {
+    fn drop(&mut self) {
+        self.private_key_b64.zeroize();
+    }
+}
+
+/// The first `len` hex digits of the SHA-256 of `peer_id`, which holds the
+/// whole Ed25519 public key.  A shorter discriminator of a peer is a
+/// prefix of a longer one.
+pub fn discriminator(peer_id: &PeerId, len: usize) -> String {
+    let hash = Sha256::digest(peer_id.to_bytes());
+    let mut hex: String = hash.iter().map(|b| format!("{b:02x}")).collect();
+    hex.truncate(len);
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_key_moves_out_of_the_config_into_a_private_file() {
+        let dir = std::env::temp_dir().join(format!("chat-key-{}", std::process::id()));
+        let mut config = Config {
+            key_storage: KeyStorage::Config,
+            ..Config::load_dir(&dir).unwrap()
+        };
+        let identity = Identity::load_or_create(&mut config).unwrap();
+        assert_eq!(config.key_path(), dir.join("identity.key"));
+        assert!(!config.key_path().exists());
+
+        config.key_storage = KeyStorage::File;
+        assert_eq!(
+            Identity::load_or_create(&mut config).unwrap().peer_id,
+            identity.peer_id
+        );
+        assert!(config.private_key_b64.is_none());
+        assert!(
+            std::fs::read_to_string(config.key_path())
+                .unwrap()
+                .starts_with("CAES")
+        );
+        assert_eq!(key_file_warning(&config), None);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(config.key_path())
+                    .unwrap()
+                    .permissions()
+                    .mode()
+                    & 0o777,
+                0o600
+            );
+            std::fs::set_permissions(config.key_path(), std::fs::Permissions::from_mode(0o644))
+                .unwrap();
+            assert!(key_file_warning(&config).unwrap().contains("mode 644"));
+        }
+
+        // Back into the config, and a different key left there is refused.
+        config.key_storage = KeyStorage::Config;
+        assert_eq!(
+            Identity::load_or_create(&mut config).unwrap().peer_id,
+            identity.peer_id
+        );
+        assert!(config.private_key_b64.is_some());
+        config.save().unwrap();
+        assert_eq!(key_file_warning(&config), None);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&config.path, std::fs::Permissions::from_mode(0o644)).unwrap();
+            assert!(key_file_warning(&config).unwrap().contains("mode 644"));
+            config.save().unwrap();
+            assert_eq!(key_file_warning(&config), None);
+        }
+        config.key_storage = KeyStorage::File;
+        config.private_key_b64 =
+            Some(B64.encode(Keypair::generate_ed25519().to_protobuf_encoding().unwrap()));
+        assert!(Identity::load_or_create(&mut config).is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn archives_open_only_with_their_passphrase() {
+        let mut config = Config {
+            key_storage: KeyStorage::Config,
+            ..Config::default()
+        };
+        let identity = Identity::load_or_create(&mut config).unwrap();
+        let contact = Contact {
+            name: "Bob#1a2b".to_string(),
+            verified: true,
+            alias: None,
+        };
+        let archive = Archive {
+            private_key_b64: config.private_key_b64.clone().unwrap(),
+            nickname: Some("Seung".to_string()),
+            contacts: BTreeMap::from([("12D3KooWbob".to_string(), contact)]),
+        };
+        let text = archive.seal("correct horse").unwrap();
+        assert!(!text.contains(&archive.private_key_b64));
+
+        let opened = Archive::open(&text, "correct horse").unwrap();
+        assert_eq!(opened.nickname.as_deref(), Some("Seung"));
+        assert!(opened.contacts["12D3KooWbob"].verified);
+        let mut moved = Config {
+            key_storage: KeyStorage::Config,
+            private_key_b64: Some(opened.private_key_b64.clone()),
+            ..Config::default()
+        };
+        assert_eq!(
+            Identity::load_or_create(&mut moved).unwrap().peer_id,
+            identity.peer_id
+        );
+
+        let err = Archive::open(&text, "wrong").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "wrong passphrase, or the archive was altered"
+        );
+        assert!(Archive::open("nickname = \"x\"", "correct horse").is_err());
+    }
+}
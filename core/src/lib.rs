@@ -0,0 +1,84 @@
+//! The P2P chat engine behind the `chat` TUI, usable from any frontend.
+//!
+//! Three tasks talk over channels:
+//!
+//! * [`NetworkService`] drives the libp2p swarm.  It emits
+//!   [`NetworkEvent`](types::NetworkEvent)s and takes
+//!   [`NetworkCommand`](types::NetworkCommand)s.  Tests and the simulator swap
+//!   in [`memnet::MemoryNetwork`], which speaks the same channels.
+//! * [`App`] owns all chat state: rooms, members, keys and history.  It takes
+//!   [`CliCommand`]s from the frontend and publishes [`AppEvent`]s on an
+//!   [`EventBus`].
+//! * The frontend sends commands and renders whatever it receives from its
+//!   own bus subscription.  It is not limited to `AppEvent::Ui`: the
+//!   transcript logger ([`logger::run`]) is just another subscriber.
+//!
+//! ```no_run
+//! use chatting1_core::{
+//!     App, AppEvent, CliCommand, Config, EventBus, Identity, NetworkService, UiEvent,
+//! };
+//!
+//! # async fn embed() -> anyhow::Result<()> {
+//! let mut config = Config::load(Config::default_path())?;
+//! let identity = Identity::load_or_create(&mut config)?;
+//!
+//! let (network, net_events, net_commands) =
+//!     NetworkService::new(identity.keypair.clone(), 0, None, Vec::new(), Vec::new(), false, &config.network.gossipsub)?;
+//! tokio::spawn(network.run());
+//!
+//! // Subscribe before the app starts so no early event is missed.
+//! let bus = EventBus::new();
+//! let mut events = bus.subscribe();
+//! let (commands, command_rx) = tokio::sync::mpsc::unbounded_channel();
+//! let app = App::new(identity, config, net_events, net_commands, command_rx, bus);
+//! tokio::spawn(app.run());
+//!
+//! commands.send(CliCommand::CreateRoom {
+//!     name: "lobby".to_string(),
+//!     password: "secret".to_string(),
+//!     persona: None,
+//!     max_members: 0,
+//! })?;
+//! while let Ok(event) = events.recv().await {
+//!     match event {
+//!         AppEvent::Ui(UiEvent::RoomCreated { code, .. }) => println!("Share: {code}"),
+//!         AppEvent::Ui(UiEvent::NewMessage(msg)) => println!("{}", msg.render_lines(80).join("\n")),
+//!         _ => {}
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `App::run` returns after `CliCommand::Quit`.  Bots that should act on
+//! messages rather than just watch them implement [`Plugin`] and are added
+//! with `App::with_plugin` before `run`.
+
+pub mod app;
+pub mod bus;
+pub mod config;
+pub mod contacts;
+pub mod crypto;
+pub mod export;
+#[cfg(any(test, feature = "harness"))]
+pub mod harness;
+pub mod identity;
+pub mod links;
+pub mod logger;
+pub mod memnet;
+pub mod network;
+pub mod notes;
+pub mod notify;
+pub mod plugin;
+pub mod room;
+pub mod types;
+pub mod vault;
+pub mod wire;
+
+pub use app::App;
+pub use bus::EventBus;
+pub use config::Config;
+pub use identity::Identity;
+pub use network::NetworkService;
+pub use plugin::Plugin;
+pub use types::{AppEvent, CliCommand, DisplayMessage, UiEvent};
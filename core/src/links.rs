@@ -0,0 +1,76 @@
+//! Web links in message text: `/links` lists them, and the terminal UI
+//! makes them clickable.
+
+use std::ops::Range;
+
+/// Byte ranges of the `http://` and `https://` URLs in `text`.  A URL
+/// starts at a word boundary and runs to whitespace; punctuation ending a
+/// sentence is left out, and a closing parenthesis only counts with its
+/// opener, so `(see https://example.com/a_(b))` gives
+/// `https://example.com/a_(b)`.
+pub fn find(text: &str) -> Vec<Range<usize>> {
+    // Same byte offsets as `text`.
+    let lower = text.to_ascii_lowercase();
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(pos) = lower[from..].find("http") {
+        let start = from + pos;
+        let rest = &text[start..];
+        let scheme = ["https://", "http://"]
+            .into_iter()
+            .find(|s| lower[start..].starts_with(s));
+        let at_boundary = text[..start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !c.is_alphanumeric());
+        let Some(scheme) = scheme.filter(|_| at_boundary) else {
+            from = start + "http".len();
+            continue;
+        };
+        // Control characters end a URL too: it is written out in an escape
+        // sequence, which they could break out of.
+        let stop =
+            |c: char| c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | '"' | '`');
+        let mut end = start + rest.find(stop).unwrap_or(rest.len());
+        loop {
+            let url = &text[start..end];
+            match url.chars().next_back() {
+                Some('.' | ',' | ';' | ':' | '!' | '?' | '\'' | '*' | '_') => end -= 1,
+                Some(')') if url.matches('(').count() < url.matches(')').count() => end -= 1,
+                _ => break,
+            }
+        }
+        if end > start + scheme.len() {
+            found.push(start..end);
+        }
+        from = end.max(start + scheme.len());
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(text: &str) -> Vec<&str> {
+        find(text).into_iter().map(|r| &text[r]).collect()
+    }
+
+    #[test]
+    fn urls_end_before_trailing_punctuation() {
+        assert_eq!(
+            urls("see https://example.com/a?b=1, or (HTTP://x.org/wiki/A_(b)).\nhttp://y.net"),
+            [
+                "https://example.com/a?b=1",
+                "HTTP://x.org/wiki/A_(b)",
+                "http://y.net"
+            ]
+        );
+        assert_eq!(
+            urls("*https://a.io/x* <https://b.io> `http://c.io`"),
+            ["https://a.io/x", "https://b.io", "http://c.io"]
+        );
+        assert!(urls("xhttps://a.io https:// http: httpsé://ü").is_empty());
+        assert_eq!(urls("ünï https://ü.example/ä"), ["https://ü.example/ä"]);
+    }
+}
@@ -0,0 +1,553 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{debug, warn};
+
+use crate::{
+    config::LogFormat,
+    types::{AppEvent, DisplayMessage},
+};
+
+/// Message lines waiting for the writer; beyond this, lines are dropped
+/// rather than holding up the bus. Opening, closing and moving a log is
+/// never dropped, or later lines would go to the wrong file or none.
+const LOG_QUEUE: usize = 1024;
+/// Longest a written line may sit in a buffer before reaching the file.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Bytes read from the end of a log by the first attempt of [`tail`].
+const TAIL_WINDOW: u64 = 16 * 1024;
+/// Starts the later lines of a multi-line message in a text log.
+const CONTINUATION: &str = "    ";
+
+/// What the writer thread is asked to do.
+enum LogOp {
+    Open(String),
+    Close(String),
+    Line {
+        room: String,
+        line: String,
+    },
+    /// Go on writing the open logs in another directory or format.
+    Move {
+        log_dir: String,
+        format: LogFormat,
+    },
+}
+
+/// Bus subscriber that keeps one log file open per joined room; it needs a
+/// lossless receiver ([`EventBus::subscribe_lossless`]) to see every room
+/// entered and left.  The files are written on a blocking thread fed
+/// through a queue holding at most `LOG_QUEUE` lines, so a slow disk (iSH
+/// on an iPhone, say) costs log lines, never a stalled runtime.
+/// Runs until the bus is closed, then waits for the files to be flushed.
+///
+/// [`EventBus::subscribe_lossless`]: crate::bus::EventBus::subscribe_lossless
+pub async fn run(mut rx: UnboundedReceiver<AppEvent>, log_dir: String, mut format: LogFormat) {
+    let (tx, ops) = mpsc::channel();
+    let queued = Arc::new(AtomicUsize::new(0));
+    let writer = {
+        let queued = queued.clone();
+        tokio::task::spawn_blocking(move || write_loop(ops, &queued, &log_dir, format))
+    };
+    let mut dropped = 0usize;
+    while let Some(event) = rx.recv().await {
+        let op = match event {
+            AppEvent::RoomEntered(name) => LogOp::Open(name),
+            AppEvent::RoomLeft(name) => LogOp::Close(name),
+            AppEvent::RoomMessage { room, msg } => LogOp::Line {
+                line: Logger::format(format, &room, &msg),
+                room,
+            },
+            AppEvent::SettingsChanged {
+                log_dir,
+                log_format,
+                ..
+            } => {
+                format = log_format;
+                LogOp::Move { log_dir, format }
+            }
+            AppEvent::Ui(_) | AppEvent::Notify { .. } => continue,
+        };
+        if let LogOp::Line { .. } = op {
+            if queued.load(Ordering::Relaxed) >= LOG_QUEUE {
+                dropped += 1;
+                continue;
+            }
+            queued.fetch_add(1, Ordering::Relaxed);
+        }
+        if tx.send(op).is_err() {
+            break;
+        }
+        if dropped > 0 {
+            warn!("Log queue was full; dropped {dropped} log line(s)");
+            dropped = 0;
+        }
+    }
+    drop(tx);
+    let _ = writer.await;
+}
+
+/// The writer thread: applies queued operations in batches and flushes at
+/// most every `FLUSH_INTERVAL`, and once more when the queue closes.
+/// `queued` counts the lines sent but not yet written.
+fn write_loop(ops: mpsc::Receiver<LogOp>, queued: &AtomicUsize, log_dir: &str, format: LogFormat) {
+    let mut loggers: HashMap<String, Logger> = HashMap::new();
+    let mut settings = (log_dir.to_string(), format);
+    let mut last_flush = Instant::now();
+    loop {
+        match ops.recv_timeout(FLUSH_INTERVAL) {
+            Ok(op) => {
+                apply(&mut loggers, &mut settings, queued, op);
+                // Whatever queued up meanwhile goes into the same batch.
+                while let Ok(op) = ops.try_recv() {
+                    apply(&mut loggers, &mut settings, queued, op);
+                }
+                if last_flush.elapsed() < FLUSH_INTERVAL {
+                    continue;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        for log in loggers.values_mut() {
+            let _ = log.flush();
+        }
+        last_flush = Instant::now();
+    }
+    for log in loggers.values_mut() {
+        let _ = log.flush();
+    }
+}
+
+/// Carry out `op`; `settings` are the log directory and format in use.
+fn apply(
+    loggers: &mut HashMap<String, Logger>,
+    settings: &mut (String, LogFormat),
+    queued: &AtomicUsize,
+    op: LogOp,
+) {
+    let (log_dir, format) = (settings.0.as_str(), settings.1);
+    match op {
+        LogOp::Open(name) => match Logger::open(log_dir, &name, format) {
+            Ok(mut log) => {
+                let _ = log.log_event("session started");
+                loggers.insert(name, log);
+            }
+            Err(e) => warn!("Cannot open log for room '{name}': {e}"),
+        },
+        LogOp::Close(name) => {
+            debug!("Closing log for room '{name}'");
+            if let Some(mut log) = loggers.remove(&name) {
+                let _ = log.log_event("session ended");
+                let _ = log.flush();
+            }
+        }
+        LogOp::Line { room, line } => {
+            queued.fetch_sub(1, Ordering::Relaxed);
+            if let Some(log) = loggers.get_mut(&room) {
+                let _ = log.write_line(&line);
+            }
+        }
+        LogOp::Move {
+            log_dir: new_dir,
+            format: new_format,
+        } => {
+            if (new_dir.as_str(), new_format) == (log_dir, format) {
+                return;
+            }
+            // A log that cannot be opened in the new place stays where it is.
+            for (name, log) in loggers.iter_mut() {
+                match Logger::open(&new_dir, name, new_format) {
+                    Ok(mut moved) => {
+                        let _ = log.log_event("log continues elsewhere (config reloaded)");
+                        let _ = log.flush();
+                        let _ = moved.log_event("session continued (config reloaded)");
+                        *log = moved;
+                    }
+                    Err(e) => warn!("Cannot open log for room '{name}' in {new_dir}: {e}"),
+                }
+            }
+            *settings = (new_dir, new_format);
+        }
+    }
+}
+
+/// The last `n` lines of the log of `room_name`, oldest first, as system
+/// messages stamped with the time they were logged.  Reads backwards from
+/// the end, so long logs cost no more than the lines asked for.
+pub fn tail(
+    log_dir: &str,
+    room_name: &str,
+    n: usize,
+    format: LogFormat,
+) -> Result<Vec<DisplayMessage>> {
+    let mut file = File::open(log_path(log_dir, room_name, format))?;
+    let len = file.metadata()?.len();
+    let mut window = TAIL_WINDOW;
+    loop {
+        let start = len.saturating_sub(window);
+        file.seek(SeekFrom::Start(start))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let text = String::from_utf8_lossy(&buf);
+        // Entries, a message's continuation lines joined back to it.
+        let mut lines: Vec<String> = Vec::new();
+        for line in text.lines() {
+            match (format, line.strip_prefix(CONTINUATION), lines.last_mut()) {
+                (LogFormat::Text, Some(rest), Some(last)) => {
+                    last.push('\n');
+                    last.push_str(rest);
+                }
+                _ => lines.push(line.to_string()),
+            }
+        }
+        if start > 0 && !lines.is_empty() {
+            lines.remove(0); // Most likely cut.
+        }
+        if lines.len() >= n || start == 0 {
+            let from = lines.len().saturating_sub(n);
+            return Ok(lines[from..]
+                .iter()
+                .map(|line| parse_line(line, format))
+                .collect());
+        }
+        window *= 4;
+    }
+}
+
+/// A log line back as a system message; lines not in the log format are
+/// shown as they are.
+fn parse_line(line: &str, format: LogFormat) -> DisplayMessage {
+    if format == LogFormat::Jsonl {
+        return match serde_json::from_str::<Record>(line) {
+            Ok(record) => {
+                let mut msg = DisplayMessage::system(&record.text);
+                if record.kind == RecordKind::Chat {
+                    msg.sender = record.sender.into_owned();
+                    msg.verified = record.verified;
+                    msg.text = format!("{}: {}", msg.sender_label(), record.text);
+                    msg.sender.clear();
+                }
+                msg.timestamp = record.timestamp;
+                msg
+            }
+            Err(_) => DisplayMessage::system(line),
+        };
+    }
+    let parsed = line
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once("] "))
+        .and_then(|(ts, text)| Some((DateTime::parse_from_rfc3339(ts).ok()?, text)));
+    match parsed {
+        Some((ts, text)) => {
+            let mut msg = DisplayMessage::system(text.strip_prefix("*** ").unwrap_or(text));
+            msg.timestamp = ts.with_timezone(&Utc);
+            msg
+        }
+        None => DisplayMessage::system(line),
+    }
+}
+
+/// `<log_dir>/<room>.log` (`.jsonl` for JSONL), the room name made safe
+/// for a file name.
+fn log_path(log_dir: &str, room_name: &str, format: LogFormat) -> PathBuf {
+    let extension = match format {
+        LogFormat::Text => "log",
+        LogFormat::Jsonl => "jsonl",
+    };
+    PathBuf::from(log_dir).join(format!("{}.{extension}", file_stem(room_name)))
+}
+
+/// `room_name` made safe for a file name.
+pub(crate) fn file_stem(room_name: &str) -> String {
+    room_name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// One line of a JSONL log.
+#[derive(Serialize, Deserialize)]
+struct Record<'a> {
+    timestamp: DateTime<Utc>,
+    #[serde(borrow)]
+    room: Cow<'a, str>,
+    /// "Nick#disc"; empty for system events.
+    #[serde(borrow)]
+    sender: Cow<'a, str>,
+    #[serde(rename = "type")]
+    kind: RecordKind,
+    #[serde(borrow)]
+    text: Cow<'a, str>,
+    /// `false` when the sender's signature was missing or did not match.
+    verified: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RecordKind {
+    Chat,
+    System,
+}
+
+pub struct Logger {
+    writer: BufWriter<File>,
+    room: String,
+    format: LogFormat,
+}
+
+impl Logger {
+    /// Open (or create) the log file for `room_name` inside `log_dir`.
+    pub fn open(log_dir: &str, room_name: &str, format: LogFormat) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path(log_dir, room_name, format))?;
+
+        Ok(Self {
+            writer: BufWriter::new(file),
+            room: room_name.to_string(),
+            format,
+        })
+    }
+
+    /// The log line of a chat message or system event in `room`.
+    fn format(format: LogFormat, room: &str, msg: &DisplayMessage) -> String {
+        match format {
+            LogFormat::Text => {
+                let ts = msg.timestamp.to_rfc3339();
+                // Later lines of a message are indented, so none of them
+                // passes for a line of its own.
+                let text = msg.text.replace('\n', &format!("\n{CONTINUATION}"));
+                if msg.is_system {
+                    format!("[{}] *** {}\n", ts, text)
+                } else {
+                    format!("[{}] {}: {}\n", ts, msg.real_label(), text)
+                }
+            }
+            LogFormat::Jsonl => {
+                let record = Record {
+                    timestamp: msg.timestamp,
+                    room: Cow::Borrowed(room),
+                    sender: Cow::Borrowed(&msg.sender),
+                    kind: if msg.is_system {
+                        RecordKind::System
+                    } else {
+                        RecordKind::Chat
+                    },
+                    text: Cow::Borrowed(&msg.text),
+                    verified: msg.verified,
+                };
+                // Strings, a timestamp and a bool always serialize.
+                let mut line = serde_json::to_string(&record).expect("serializable log record");
+                line.push('\n');
+                line
+            }
+        }
+    }
+
+    /// Append a chat message or system event line.  Buffered: call
+    /// [`Logger::flush`] to be sure it reached the file.
+    pub fn log(&mut self, msg: &DisplayMessage) -> Result<()> {
+        let line = Self::format(self.format, &self.room, msg);
+        self.write_line(&line)
+    }
+
+    /// Append a plain system string (e.g. "session started").
+    pub fn log_event(&mut self, text: &str) -> Result<()> {
+        self.log(&DisplayMessage::system(text))
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        self.writer.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queued_lines_are_flushed_when_the_bus_closes() {
+        let dir = std::env::temp_dir().join(format!("chat-logs-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let task = tokio::spawn(run(rx, dir.to_string_lossy().into_owned(), LogFormat::Text));
+
+        tx.send(AppEvent::RoomEntered("lobby".to_string())).unwrap();
+        for text in ["one", "two"] {
+            let msg = DisplayMessage::chat("bob#1a2b", text);
+            tx.send(AppEvent::RoomMessage {
+                room: "lobby".to_string(),
+                msg,
+            })
+            .unwrap();
+        }
+        drop(tx);
+        task.await.unwrap();
+
+        let log = std::fs::read_to_string(dir.join("lobby.log")).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3, "{log}");
+        assert!(lines[0].ends_with("*** session started"));
+        assert!(lines[1].ends_with("bob#1a2b: one") && lines[2].ends_with("bob#1a2b: two"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn reloaded_settings_move_open_logs() {
+        let dir = std::env::temp_dir().join(format!("chat-logs-{}", rand::random::<u64>()));
+        let moved = dir.join("moved");
+        std::fs::create_dir_all(&moved).unwrap();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let task = tokio::spawn(run(rx, dir.to_string_lossy().into_owned(), LogFormat::Text));
+        let line = |text: &str| AppEvent::RoomMessage {
+            room: "lobby".to_string(),
+            msg: DisplayMessage::chat("bob#1a2b", text),
+        };
+
+        tx.send(AppEvent::RoomEntered("lobby".to_string())).unwrap();
+        tx.send(line("before")).unwrap();
+        tx.send(AppEvent::SettingsChanged {
+            theme: Default::default(),
+            format_messages: true,
+            log_dir: moved.to_string_lossy().into_owned(),
+            log_format: LogFormat::Jsonl,
+        })
+        .unwrap();
+        tx.send(line("after")).unwrap();
+        drop(tx);
+        task.await.unwrap();
+
+        let old = std::fs::read_to_string(dir.join("lobby.log")).unwrap();
+        assert!(
+            old.contains("bob#1a2b: before") && !old.contains("after"),
+            "{old}"
+        );
+        let new = std::fs::read_to_string(moved.join("lobby.jsonl")).unwrap();
+        assert!(
+            new.lines().last().unwrap().contains(r#""text":"after""#),
+            "{new}"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn tail_reads_back_the_last_lines() {
+        let dir = std::env::temp_dir().join(format!("chat-logs-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log_dir = dir.to_string_lossy().into_owned();
+        let mut log = Logger::open(&log_dir, "lobby", LogFormat::Text).unwrap();
+        // Several times `TAIL_WINDOW`, so the window has to grow.
+        for i in 0..2000 {
+            let mut msg = DisplayMessage::chat("bob#1a2b", &format!("line {i}"));
+            msg.timestamp = DateTime::from_timestamp(i, 0).unwrap();
+            log.log(&msg).unwrap();
+        }
+        log.log_event("session ended").unwrap();
+        let forged = format!("one\n[{}] *** two", Utc::now().to_rfc3339());
+        log.log(&DisplayMessage::chat("bob#1a2b", &forged)).unwrap();
+        log.flush().unwrap();
+
+        let last = tail(&log_dir, "lobby", 3, LogFormat::Text).unwrap();
+        let texts: Vec<&str> = last.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "bob#1a2b: line 1999",
+                "session ended",
+                &format!("bob#1a2b: {forged}")
+            ]
+        );
+        assert_eq!(last[0].timestamp.timestamp(), 1999);
+        assert!(last.iter().all(|m| m.is_system));
+
+        let many = tail(&log_dir, "lobby", 1500, LogFormat::Text).unwrap();
+        assert_eq!(
+            (many.len(), many[0].text.as_str()),
+            (1500, "bob#1a2b: line 502")
+        );
+        assert_eq!(
+            tail(&log_dir, "lobby", 5000, LogFormat::Text)
+                .unwrap()
+                .len(),
+            2002
+        );
+        assert!(tail(&log_dir, "elsewhere", 3, LogFormat::Text).is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn jsonl_logs_hold_one_object_per_line() {
+        let dir = std::env::temp_dir().join(format!("chat-logs-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log_dir = dir.to_string_lossy().into_owned();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let task = tokio::spawn(run(rx, log_dir.clone(), LogFormat::Jsonl));
+
+        tx.send(AppEvent::RoomEntered("lobby".to_string())).unwrap();
+        let mut msg = DisplayMessage::chat("bob#1a2b", "say \"hi\"\nthen leave");
+        msg.verified = false;
+        tx.send(AppEvent::RoomMessage {
+            room: "lobby".to_string(),
+            msg,
+        })
+        .unwrap();
+        drop(tx);
+        task.await.unwrap();
+
+        let log = std::fs::read_to_string(dir.join("lobby.jsonl")).unwrap();
+        let records: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2, "{log}");
+        assert_eq!(records[0]["type"], "system");
+        assert_eq!(records[0]["text"], "session started");
+        assert_eq!(records[1]["room"], "lobby");
+        assert_eq!(records[1]["sender"], "bob#1a2b");
+        assert_eq!(records[1]["type"], "chat");
+        assert_eq!(records[1]["text"], "say \"hi\"\nthen leave");
+        assert_eq!(records[1]["verified"], false);
+        assert!(DateTime::parse_from_rfc3339(records[1]["timestamp"].as_str().unwrap()).is_ok());
+
+        let last = tail(&log_dir, "lobby", 5, LogFormat::Jsonl).unwrap();
+        let texts: Vec<&str> = last.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "session started",
+                "bob#1a2b [unverified]: say \"hi\"\nthen leave"
+            ]
+        );
+        assert!(tail(&log_dir, "lobby", 5, LogFormat::Text).is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
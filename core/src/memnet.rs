@@ -0,0 +1,319 @@
+//! In-process network backend speaking the `NetworkCommand` / `NetworkEvent`
+//! contract, so several `App`s can talk to each other without real sockets.
+//!
+//! Each node is addressed as `/memory/<peer_id>`. Publishing delivers the
+//! payload to every other subscriber of the topic, mirroring GossipSub (which
+//! never echoes a node's own messages back to it, and lets a node publish to a
+//! topic it is not subscribed to).  Provider records and rendezvous
+//! registrations live in the hub, so lookups answer immediately and
+//! completely.  A blocked
+//! peer's messages are not delivered to the blocking node, and vice versa.
+//! `go_offline` cuts a node off like a dropped uplink until `go_online`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::mpsc;
+
+use crate::types::{NetworkCommand, NetworkEvent, PeerInfo};
+
+const ADDR_PREFIX: &str = "/memory/";
+
+#[derive(Default)]
+struct Hub {
+    /// peer_id → event sender of that node.
+    nodes: BTreeMap<String, mpsc::UnboundedSender<NetworkEvent>>,
+    /// topic → subscribed peer_ids.
+    topics: BTreeMap<String, BTreeSet<String>>,
+    /// DHT key → peer_ids providing it.
+    providers: BTreeMap<String, BTreeSet<String>>,
+    /// Rendezvous namespace → peer_ids registered under it.
+    registrations: BTreeMap<String, BTreeSet<String>>,
+    /// peer_id → peers it has blocked.
+    blocked: BTreeMap<String, BTreeSet<String>>,
+    /// Nodes cut off from everyone by `go_offline`.
+    offline: BTreeSet<String>,
+}
+
+impl Hub {
+    fn send(&self, peer_id: &str, event: NetworkEvent) {
+        if let Some(tx) = self.nodes.get(peer_id) {
+            let _ = tx.send(event);
+        }
+    }
+
+    /// `true` if either peer has blocked the other, or is offline.
+    fn blocked(&self, a: &str, b: &str) -> bool {
+        let blocks = |x: &str, y: &str| self.blocked.get(x).is_some_and(|s| s.contains(y));
+        blocks(a, b) || blocks(b, a) || self.offline.contains(a) || self.offline.contains(b)
+    }
+}
+
+/// Shared in-memory "internet" that nodes attach to.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    hub: Arc<Mutex<Hub>>,
+}
+
+impl MemoryNetwork {
+    /// The multiaddr-like address under which `peer_id` can be dialed.
+    pub fn addr_of(peer_id: &str) -> String {
+        format!("{ADDR_PREFIX}{peer_id}")
+    }
+
+    /// Drop every connection of `peer_id` and fail its dials until
+    /// `go_online`: everyone sees it disconnect, and it sees everyone go.
+    pub fn go_offline(&self, peer_id: &str) {
+        let mut hub = self.hub.lock().expect("hub lock");
+        if !hub.offline.insert(peer_id.to_string()) {
+            return;
+        }
+        for other in hub.nodes.keys().filter(|p| *p != peer_id) {
+            hub.send(peer_id, NetworkEvent::PeerDisconnected(other.clone()));
+            hub.send(other, NetworkEvent::PeerDisconnected(peer_id.to_string()));
+        }
+        hub.send(peer_id, NetworkEvent::ConnectionLost);
+    }
+
+    /// Let `peer_id` dial and be dialed again.
+    pub fn go_online(&self, peer_id: &str) {
+        self.hub.lock().expect("hub lock").offline.remove(peer_id);
+    }
+
+    /// Number of nodes subscribed to `topic`.
+    pub fn subscribers(&self, topic: &str) -> usize {
+        let hub = self.hub.lock().expect("hub lock");
+        hub.topics.get(topic).map_or(0, |members| members.len())
+    }
+
+    /// Attach a node and return its network service plus the same channel
+    /// pair `NetworkService::new` hands out.
+    pub fn attach(
+        &self,
+        peer_id: &str,
+    ) -> (
+        MemoryNode,
+        mpsc::UnboundedReceiver<NetworkEvent>,
+        mpsc::UnboundedSender<NetworkCommand>,
+    ) {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+
+        // Like a real swarm, report the listen address right away.
+        let _ = event_tx.send(NetworkEvent::ListeningOn(Self::addr_of(peer_id)));
+        self.hub
+            .lock()
+            .expect("hub lock")
+            .nodes
+            .insert(peer_id.to_string(), event_tx);
+
+        let node = MemoryNode {
+            peer_id: peer_id.to_string(),
+            hub: self.hub.clone(),
+            cmd_rx,
+        };
+        (node, event_rx, cmd_tx)
+    }
+}
+
+/// One attached node — drive it with `run()` like `NetworkService`.
+pub struct MemoryNode {
+    peer_id: String,
+    hub: Arc<Mutex<Hub>>,
+    cmd_rx: mpsc::UnboundedReceiver<NetworkCommand>,
+}
+
+impl MemoryNode {
+    /// Process commands until the app drops its sender, then detach.
+    pub async fn run(mut self) {
+        while let Some(cmd) = self.cmd_rx.recv().await {
+            if matches!(cmd, NetworkCommand::Shutdown) {
+                break;
+            }
+            self.handle_command(cmd);
+        }
+        self.detach();
+    }
+
+    fn handle_command(&mut self, cmd: NetworkCommand) {
+        let me = self.peer_id.clone();
+        let mut hub = self.hub.lock().expect("hub lock");
+        match cmd {
+            NetworkCommand::Subscribe(topic) => {
+                let members = hub.topics.entry(topic.clone()).or_default();
+                if !members.insert(me.clone()) {
+                    return;
+                }
+                let others: Vec<String> = members.iter().filter(|p| **p != me).cloned().collect();
+                // Learn about existing members first, then announce ourselves.
+                for other in &others {
+                    hub.send(
+                        &me,
+                        NetworkEvent::PeerSubscribed {
+                            topic: topic.clone(),
+                            peer_id: other.clone(),
+                        },
+                    );
+                }
+                for other in &others {
+                    hub.send(
+                        other,
+                        NetworkEvent::PeerSubscribed {
+                            topic: topic.clone(),
+                            peer_id: me.clone(),
+                        },
+                    );
+                }
+            }
+
+            NetworkCommand::Unsubscribe(topic) => {
+                let Some(members) = hub.topics.get_mut(&topic) else {
+                    return;
+                };
+                if !members.remove(&me) {
+                    return;
+                }
+                let others: Vec<String> = members.iter().cloned().collect();
+                for other in others {
+                    hub.send(&other, NetworkEvent::PeerDisconnected(me.clone()));
+                }
+            }
+
+            NetworkCommand::Publish { topic, data } => {
+                let Some(members) = hub.topics.get(&topic) else {
+                    return;
+                };
+                for other in members.iter().filter(|p| **p != me && !hub.blocked(p, &me)) {
+                    hub.send(
+                        other,
+                        NetworkEvent::MessageReceived {
+                            topic: topic.clone(),
+                            source: me.clone(),
+                            payload: data.clone(),
+                        },
+                    );
+                }
+            }
+
+            NetworkCommand::Dial(addr) => {
+                let target = addr.strip_prefix(ADDR_PREFIX).unwrap_or_default();
+                Self::connect(&hub, &me, target);
+            }
+
+            NetworkCommand::DialPeer(target) => Self::connect(&hub, &me, &target),
+
+            NetworkCommand::StartProviding(key) => {
+                hub.providers.entry(key).or_default().insert(me);
+            }
+
+            NetworkCommand::StopProviding(key) => {
+                if let Some(peers) = hub.providers.get_mut(&key) {
+                    peers.remove(&me);
+                }
+            }
+
+            NetworkCommand::FindProviders(key) => {
+                let peers = hub
+                    .providers
+                    .get(&key)
+                    .map(|p| p.iter().cloned().collect())
+                    .unwrap_or_default();
+                hub.send(&me, NetworkEvent::ProvidersFound { key, peers });
+            }
+
+            NetworkCommand::Register(namespace) => {
+                hub.registrations.entry(namespace).or_default().insert(me);
+            }
+
+            NetworkCommand::Unregister(namespace) => {
+                if let Some(peers) = hub.registrations.get_mut(&namespace) {
+                    peers.remove(&me);
+                }
+            }
+
+            NetworkCommand::Discover(namespace) => {
+                let peers = hub
+                    .registrations
+                    .get(&namespace)
+                    .map(|p| p.iter().filter(|p| **p != me).cloned().collect())
+                    .unwrap_or_default();
+                hub.send(&me, NetworkEvent::RendezvousFound { namespace, peers });
+            }
+
+            // No scores here: graylisting just stops delivery, like a block.
+            NetworkCommand::BlockPeer(peer) | NetworkCommand::GraylistPeer(peer) => {
+                hub.blocked.entry(me).or_default().insert(peer);
+            }
+
+            NetworkCommand::QueryListenAddrs => {
+                hub.send(&me, NetworkEvent::ListeningOn(MemoryNetwork::addr_of(&me)));
+            }
+
+            // Every link is direct and instant here.
+            NetworkCommand::QueryPeerInfo(peers) => {
+                let infos = peers
+                    .into_iter()
+                    .filter(|p| hub.nodes.contains_key(p) && *p != me && !hub.blocked(&me, p))
+                    .map(|peer_id| PeerInfo {
+                        peer_id,
+                        rtt_ms: Some(0),
+                        agent: "memnet".to_string(),
+                        ..PeerInfo::default()
+                    })
+                    .collect();
+                hub.send(&me, NetworkEvent::PeerInfo(infos));
+            }
+
+            // Handled by `run`.
+            NetworkCommand::Shutdown => {}
+        }
+    }
+
+    /// Connect `me` to `target` if it is attached, else report a failed dial.
+    /// Like GossipSub, both sides then learn each other's subscriptions.
+    fn connect(hub: &Hub, me: &str, target: &str) {
+        let online = !hub.offline.contains(me) && !hub.offline.contains(target);
+        if hub.nodes.contains_key(target) && target != me && online {
+            hub.send(me, NetworkEvent::PeerConnected(target.to_string()));
+            hub.send(target, NetworkEvent::PeerConnected(me.to_string()));
+            for (topic, members) in &hub.topics {
+                for (to, peer) in [(me, target), (target, me)] {
+                    if members.contains(peer) {
+                        let peer_id = peer.to_string();
+                        let topic = topic.clone();
+                        hub.send(to, NetworkEvent::PeerSubscribed { topic, peer_id });
+                    }
+                }
+            }
+        } else {
+            hub.send(me, NetworkEvent::DialFailed(target.to_string()));
+        }
+    }
+
+    /// Leave every topic and disappear from the hub.
+    fn detach(&self) {
+        let mut hub = self.hub.lock().expect("hub lock");
+        hub.nodes.remove(&self.peer_id);
+        hub.blocked.remove(&self.peer_id);
+        hub.offline.remove(&self.peer_id);
+        let hub = &mut *hub;
+        for peers in hub
+            .providers
+            .values_mut()
+            .chain(hub.registrations.values_mut())
+        {
+            peers.remove(&self.peer_id);
+        }
+        let mut notify = Vec::new();
+        for members in hub.topics.values_mut() {
+            if members.remove(&self.peer_id) {
+                notify.extend(members.iter().cloned());
+            }
+        }
+        for other in notify {
+            hub.send(&other, NetworkEvent::PeerDisconnected(self.peer_id.clone()));
+        }
+    }
+}
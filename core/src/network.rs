@@ -0,0 +1,1072 @@
+use std::{
+    collections::{HashMap, HashSet, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use libp2p::{
+    Multiaddr, PeerId, Swarm, SwarmBuilder, autonat,
+    core::{Transport, transport::ListenerId, upgrade},
+    dcutr, gossipsub, identify, kad, mdns,
+    multiaddr::Protocol,
+    noise, ping,
+    pnet::{PnetConfig, PreSharedKey},
+    relay, rendezvous,
+    swarm::{ConnectionId, NetworkBehaviour, behaviour::toggle::Toggle, dial_opts::DialOpts},
+    tcp, websocket, yamux,
+};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::GossipsubConfig,
+    types::{NetworkCommand, NetworkEvent, PeerInfo, Reachability},
+    wire::CHUNK_LEN,
+};
+
+// ── Bootstrap peers (IPFS public nodes) ──────────────────────────────────────
+
+/// DHT bootstrap nodes used unless the config names others
+/// (`bootstrap_peers`).
+pub const DEFAULT_BOOTSTRAP_PEERS: &[&str] = &[
+    "/dnsaddr/bootstrap.libp2p.io/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN",
+    "/dnsaddr/bootstrap.libp2p.io/p2p/QmQCU2EcMqAqQPR2i9bChDtGNJchTbq5TbXJJ16u19uLTa",
+    "/dnsaddr/bootstrap.libp2p.io/p2p/QmbLHAnMoJPWSCR5Zhtx6BHJX9KiKNN6tpvbUcqanj75Nb",
+    "/dnsaddr/bootstrap.libp2p.io/p2p/QmcZf59bWwK5XFi76CZX8cbJ4BhTzzA3gU1ZjYZcYW3dwt",
+];
+
+/// Application-specific score given to a flooding peer.  With the default
+/// weight (10) its score drops below the graylist threshold (-80).
+const SPAMMER_APP_SCORE: f64 = -10.0;
+
+/// How often rendezvous registrations are renewed; they live for
+/// `rendezvous::DEFAULT_TTL` (two hours).
+const REGISTER_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Smallest `max_transmit_size` accepted: a full chat chunk, signed and
+/// encrypted, fits with room to spare.  Larger payloads (history syncs,
+/// unchunked long messages) still need more.
+pub const MIN_TRANSMIT_SIZE: usize = 2 * CHUNK_LEN;
+
+/// How long the swarm keeps running after `Shutdown` so queued publishes
+/// reach our peers.
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(300);
+
+// ── Combined NetworkBehaviour ─────────────────────────────────────────────────
+
+#[derive(NetworkBehaviour)]
+struct ChatBehaviour {
+    gossipsub: gossipsub::Behaviour,
+    kademlia: kad::Behaviour<kad::store::MemoryStore>,
+    mdns: mdns::tokio::Behaviour,
+    relay_client: relay::client::Behaviour,
+    dcutr: dcutr::Behaviour,
+    identify: identify::Behaviour,
+    ping: ping::Behaviour,
+    autonat: autonat::Behaviour,
+    rendezvous: rendezvous::client::Behaviour,
+    rendezvous_server: Toggle<rendezvous::server::Behaviour>,
+}
+
+/// GossipSub settings from the `[network.gossipsub]` config section.
+/// Fails on mesh sizes GossipSub cannot work with: it needs
+/// `1 <= mesh_n_low <= mesh_n <= mesh_n_high`.
+pub fn gossipsub_config(tuning: &GossipsubConfig) -> Result<gossipsub::Config> {
+    anyhow::ensure!(
+        tuning.max_transmit_size >= MIN_TRANSMIT_SIZE,
+        "max_transmit_size must be at least {MIN_TRANSMIT_SIZE} bytes"
+    );
+    anyhow::ensure!(tuning.heartbeat_ms > 0, "heartbeat_ms must be positive");
+    anyhow::ensure!(
+        1 <= tuning.mesh_n_low
+            && tuning.mesh_n_low <= tuning.mesh_n
+            && tuning.mesh_n <= tuning.mesh_n_high,
+        "mesh sizes must satisfy 1 <= mesh_n_low <= mesh_n <= mesh_n_high"
+    );
+    let msg_id_fn = |msg: &gossipsub::Message| {
+        let mut hasher = DefaultHasher::new();
+        msg.data.hash(&mut hasher);
+        gossipsub::MessageId::from(hasher.finish().to_string())
+    };
+    gossipsub::ConfigBuilder::default()
+        .heartbeat_interval(Duration::from_millis(tuning.heartbeat_ms))
+        .validation_mode(gossipsub::ValidationMode::Strict)
+        .message_id_fn(msg_id_fn)
+        .mesh_n(tuning.mesh_n)
+        .mesh_n_low(tuning.mesh_n_low)
+        .mesh_n_high(tuning.mesh_n_high)
+        // GossipSub wants at most half the mesh, and no more than D_low,
+        // to be outbound; small meshes get fewer than the default 2.
+        .mesh_outbound_min(2.min(tuning.mesh_n_low).min(tuning.mesh_n / 2))
+        .flood_publish(tuning.flood_publish)
+        .max_transmit_size(tuning.max_transmit_size)
+        .build()
+        .map_err(|e| anyhow::anyhow!("invalid [network.gossipsub] settings: {e}"))
+}
+
+/// The behaviours of a node with identity `key`; the DHT starts out knowing
+/// `bootstrap`, `serve_rendezvous` makes it a rendezvous point.
+fn build_behaviour(
+    key: &libp2p::identity::Keypair,
+    relay_client: relay::client::Behaviour,
+    bootstrap: &[(PeerId, Multiaddr)],
+    serve_rendezvous: bool,
+    gossipsub_config: gossipsub::Config,
+) -> ChatBehaviour {
+    let local_peer_id = key.public().to_peer_id();
+
+    // ── GossipSub ──────────────────────────────────────────
+    let mut gossipsub = gossipsub::Behaviour::new(
+        gossipsub::MessageAuthenticity::Signed(key.clone()),
+        gossipsub_config,
+    )
+    .expect("valid gossipsub behaviour");
+    // Default scoring penalizes protocol misbehaviour; the app
+    // adds flooding through the application-specific score
+    // (`GraylistPeer`).
+    gossipsub
+        .with_peer_score(
+            gossipsub::PeerScoreParams::default(),
+            gossipsub::PeerScoreThresholds::default(),
+        )
+        .expect("valid peer score params");
+
+    // ── Kademlia ───────────────────────────────────────────
+    let mut kademlia =
+        kad::Behaviour::new(local_peer_id, kad::store::MemoryStore::new(local_peer_id));
+    kademlia.set_mode(Some(kad::Mode::Server));
+    for (peer_id, addr) in bootstrap {
+        kademlia.add_address(peer_id, addr.clone());
+    }
+
+    // ── mDNS ───────────────────────────────────────────────
+    let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id)
+        .expect("valid mdns behaviour");
+
+    // ── DCUtR & Identify ───────────────────────────────────
+    let dcutr = dcutr::Behaviour::new(local_peer_id);
+    let identify = identify::Behaviour::new(identify::Config::new(
+        "/chatapp/0.1.0".to_string(),
+        key.public(),
+    ));
+
+    // ── Ping ───────────────────────────────────────────────────
+    // Measures the round-trip time of every connection for `/peers`.
+    let ping = ping::Behaviour::new(ping::Config::new());
+
+    // ── AutoNAT ────────────────────────────────────────────────
+    // Asks connected peers to dial us back, and answers their probes.
+    let autonat = autonat::Behaviour::new(local_peer_id, autonat::Config::default());
+
+    // ── Rendezvous ─────────────────────────────────────────────
+    // Members register under their rooms' namespaces at the configured
+    // rendezvous points; joiners ask there for current addresses.
+    let rendezvous = rendezvous::client::Behaviour::new(key.clone());
+    let rendezvous_server = Toggle::from(
+        serve_rendezvous
+            .then(|| rendezvous::server::Behaviour::new(rendezvous::server::Config::default())),
+    );
+
+    ChatBehaviour {
+        gossipsub,
+        kademlia,
+        mdns,
+        relay_client,
+        dcutr,
+        identify,
+        ping,
+        autonat,
+        rendezvous,
+        rendezvous_server,
+    }
+}
+
+fn swarm_config(c: libp2p::swarm::Config) -> libp2p::swarm::Config {
+    c.with_idle_connection_timeout(Duration::from_secs(60))
+}
+
+/// Parse a swarm key: 64 hex digits, or a go-ipfs `swarm.key` file
+/// (`/key/swarm/psk/1.0.0/` header).
+pub fn parse_swarm_key(key: &str) -> Result<PreSharedKey> {
+    let key = key.trim();
+    let text = if key.starts_with("/key/swarm/") {
+        key.to_string()
+    } else {
+        format!("/key/swarm/psk/1.0.0/\n/base16/\n{key}")
+    };
+    text.parse()
+        .map_err(|e| anyhow::anyhow!("invalid swarm key: {e}"))
+}
+
+/// Parse a rendezvous point: a multiaddr ending in `/p2p/<peer ID>`.
+pub fn parse_rendezvous_point(addr: &str) -> Result<(PeerId, Multiaddr)> {
+    parse_peer_addr(addr, "rendezvous point")
+}
+
+/// Parse a DHT bootstrap peer: a multiaddr ending in `/p2p/<peer ID>`.
+pub fn parse_bootstrap_peer(addr: &str) -> Result<(PeerId, Multiaddr)> {
+    parse_peer_addr(addr, "bootstrap peer")
+}
+
+fn parse_peer_addr(addr: &str, what: &str) -> Result<(PeerId, Multiaddr)> {
+    let addr: Multiaddr = addr
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid {what} {addr}"))?;
+    match addr.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Ok((peer_id, addr)),
+        _ => anyhow::bail!("{what} {addr} does not end in /p2p/<peer ID>"),
+    }
+}
+
+// ── NetworkService ────────────────────────────────────────────────────────────
+
+pub struct NetworkService {
+    swarm: Swarm<ChatBehaviour>,
+    event_tx: mpsc::UnboundedSender<NetworkEvent>,
+    cmd_rx: mpsc::UnboundedReceiver<NetworkCommand>,
+    /// Running `get_providers` queries: requested key and providers so far.
+    provider_queries: HashMap<kad::QueryId, (String, HashSet<PeerId>)>,
+    /// Listener for the relay circuit reservation we hold or are requesting.
+    /// One is enough to be reachable.
+    relay_listener: Option<ListenerId>,
+    /// Port to listen on (`--port`); 0 for random.
+    port: u16,
+    /// Running a private swarm (pre-shared key): TCP only.
+    private: bool,
+    /// Rendezvous points from the config, dialed at startup.
+    rendezvous_points: Vec<(PeerId, Multiaddr)>,
+    /// Namespaces we keep registered at every rendezvous point.
+    registrations: HashSet<String>,
+    /// Discoveries waiting for a rendezvous point to connect.
+    pending_discoveries: HashSet<String>,
+    /// What we know about each connected peer, for `QueryPeerInfo`.
+    peers: HashMap<PeerId, PeerStats>,
+}
+
+/// Connection details of one peer, gathered from swarm, ping and identify
+/// events.
+struct PeerStats {
+    /// When the first of the current connections opened.
+    since: Instant,
+    /// Open connections and whether each goes through a relay.
+    connections: HashMap<ConnectionId, bool>,
+    rtt: Option<Duration>,
+    agent: String,
+}
+
+impl PeerStats {
+    fn info(&self, peer_id: &PeerId) -> PeerInfo {
+        PeerInfo {
+            peer_id: peer_id.to_string(),
+            relayed: self.connections.values().all(|relayed| *relayed),
+            rtt_ms: self.rtt.map(|rtt| rtt.as_millis() as u64),
+            agent: self.agent.clone(),
+            connected_secs: self.since.elapsed().as_secs(),
+        }
+    }
+}
+
+impl NetworkService {
+    /// Build the swarm, listening on `port` (TCP and QUIC; 0 for random)
+    /// and for WebSocket on the port after it, or, given a pre-shared key
+    /// `psk`, a private swarm only peers holding the same key can connect to
+    /// (TCP only).  Rooms are registered at
+    /// `rendezvous_points`; with `serve_rendezvous` this node is one too.
+    /// `gossipsub` tunes room messaging; invalid settings are an error.
+    /// Returns:
+    /// * the `NetworkService` (to be driven via `run()`)
+    /// * a receiver for network events
+    /// * a sender for network commands
+    pub fn new(
+        keypair: libp2p::identity::Keypair,
+        port: u16,
+        psk: Option<PreSharedKey>,
+        bootstrap_peers: Vec<(PeerId, Multiaddr)>,
+        rendezvous_points: Vec<(PeerId, Multiaddr)>,
+        serve_rendezvous: bool,
+        gossipsub: &GossipsubConfig,
+    ) -> Result<(
+        Self,
+        mpsc::UnboundedReceiver<NetworkEvent>,
+        mpsc::UnboundedSender<NetworkCommand>,
+    )> {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+
+        let local_peer_id = PeerId::from(keypair.public());
+        info!("Local peer id: {local_peer_id}");
+        let gossipsub_config = gossipsub_config(gossipsub)?;
+
+        let builder = SwarmBuilder::with_existing_identity(keypair.clone()).with_tokio();
+        let private = psk.is_some();
+        // The public bootstrap nodes cannot speak to a private swarm.
+        let bootstrap: Vec<_> = bootstrap_peers
+            .into_iter()
+            .filter(|(_, addr)| {
+                !private || !DEFAULT_BOOTSTRAP_PEERS.contains(&addr.to_string().as_str())
+            })
+            .collect();
+        let swarm = match psk {
+            None => builder
+                .with_tcp(
+                    tcp::Config::default(),
+                    noise::Config::new,
+                    yamux::Config::default,
+                )
+                .context("TCP transport setup")?
+                .with_quic()
+                // Plain `/ws` for browser peers, secured by Noise like TCP
+                // rather than by TLS, so there is no certificate to manage.
+                .with_other_transport(
+                    |key| -> Result<_, Box<dyn std::error::Error + Send + Sync>> {
+                        Ok(websocket::Config::new(tcp::tokio::Transport::new(
+                            tcp::Config::default(),
+                        ))
+                        .upgrade(upgrade::Version::V1)
+                        .authenticate(noise::Config::new(key)?)
+                        .multiplex(yamux::Config::default()))
+                    },
+                )
+                .context("WebSocket transport setup")?
+                .with_dns()
+                .context("DNS transport setup")?
+                .with_relay_client(noise::Config::new, yamux::Config::default)
+                .context("Relay client setup")?
+                .with_behaviour(|key, relay_client| {
+                    build_behaviour(
+                        key,
+                        relay_client,
+                        &bootstrap,
+                        serve_rendezvous,
+                        gossipsub_config,
+                    )
+                })
+                .context("Behaviour setup")?
+                .with_swarm_config(swarm_config)
+                .build(),
+            // Every TCP connection starts with the pre-shared key handshake,
+            // before Noise; pnet cannot protect QUIC, so there is none, and
+            // browsers cannot speak pnet, so there is no WebSocket either.
+            Some(psk) => {
+                info!("Private swarm, key fingerprint {}", psk.fingerprint());
+                builder
+                    .with_other_transport(
+                        |key| -> Result<_, Box<dyn std::error::Error + Send + Sync>> {
+                            let noise = noise::Config::new(key)?;
+                            Ok(tcp::tokio::Transport::new(tcp::Config::default())
+                                .and_then(move |socket, _| PnetConfig::new(psk).handshake(socket))
+                                .upgrade(upgrade::Version::V1)
+                                .authenticate(noise)
+                                .multiplex(yamux::Config::default()))
+                        },
+                    )
+                    .context("Private TCP transport setup")?
+                    .with_dns()
+                    .context("DNS transport setup")?
+                    .with_relay_client(noise::Config::new, yamux::Config::default)
+                    .context("Relay client setup")?
+                    .with_behaviour(|key, relay_client| {
+                        build_behaviour(
+                            key,
+                            relay_client,
+                            &bootstrap,
+                            serve_rendezvous,
+                            gossipsub_config,
+                        )
+                    })
+                    .context("Behaviour setup")?
+                    .with_swarm_config(swarm_config)
+                    .build()
+            }
+        };
+
+        Ok((
+            Self {
+                swarm,
+                event_tx,
+                cmd_rx,
+                provider_queries: HashMap::new(),
+                relay_listener: None,
+                port,
+                private,
+                rendezvous_points,
+                registrations: HashSet::new(),
+                pending_discoveries: HashSet::new(),
+                peers: HashMap::new(),
+            },
+            event_rx,
+            cmd_tx,
+        ))
+    }
+
+    /// Drive the swarm — call this inside a dedicated Tokio task.
+    pub async fn run(mut self) {
+        // Listen on TCP and QUIC (UDP), on random ports unless `--port`, and
+        // on WebSocket on the next TCP port.
+        let port = self.port;
+        if let Err(e) = self.swarm.listen_on(
+            format!("/ip4/0.0.0.0/tcp/{port}")
+                .parse()
+                .expect("valid multiaddr"),
+        ) {
+            warn!("TCP listen on port {port} failed: {e}");
+        }
+        if !self.private
+            && let Err(e) = self.swarm.listen_on(
+                format!("/ip4/0.0.0.0/udp/{port}/quic-v1")
+                    .parse()
+                    .expect("valid multiaddr"),
+            )
+        {
+            warn!("QUIC listen failed, continuing with TCP only: {e}");
+        }
+        let ws_port = if port == 0 {
+            Some(0)
+        } else {
+            port.checked_add(1)
+        };
+        if !self.private
+            && let Some(ws_port) = ws_port
+            && let Err(e) = self.swarm.listen_on(
+                format!("/ip4/0.0.0.0/tcp/{ws_port}/ws")
+                    .parse()
+                    .expect("valid multiaddr"),
+            )
+        {
+            warn!("WebSocket listen failed, browser peers cannot connect: {e}");
+        }
+
+        // Kick off DHT bootstrap.
+        let _ = self.swarm.behaviour_mut().kademlia.bootstrap();
+
+        let mut renew = tokio::time::interval(REGISTER_INTERVAL);
+        loop {
+            tokio::select! {
+                // ── Inbound swarm event ───────────────────────────────
+                event = self.swarm.next() => {
+                    match event {
+                        Some(e) => self.handle_swarm_event(e),
+                        None => break,
+                    }
+                }
+
+                // ── Outbound command from app ─────────────────────────
+                Some(cmd) = self.cmd_rx.recv() => {
+                    if matches!(cmd, NetworkCommand::Shutdown) {
+                        break;
+                    }
+                    self.handle_command(cmd);
+                }
+
+                // ── Rendezvous upkeep (first tick is immediate) ───────
+                _ = renew.tick() => {
+                    self.renew_rendezvous();
+                }
+            }
+        }
+
+        // Keep polling briefly so the last publishes are actually sent.
+        let grace = tokio::time::sleep(SHUTDOWN_GRACE);
+        tokio::pin!(grace);
+        loop {
+            tokio::select! {
+                Some(e) = self.swarm.next() => self.handle_swarm_event(e),
+                _ = &mut grace => break,
+            }
+        }
+    }
+
+    fn handle_swarm_event(&mut self, event: libp2p::swarm::SwarmEvent<ChatBehaviourEvent>) {
+        use libp2p::swarm::SwarmEvent;
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("Listening on {address}");
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::ListeningOn(address.to_string()));
+            }
+
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                info!("External address confirmed: {address}");
+                // Registrations need an address to hand out.
+                self.renew_rendezvous();
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::NewExternalAddr(address.to_string()));
+            }
+
+            SwarmEvent::ListenerClosed {
+                listener_id,
+                reason,
+                ..
+            } if self.relay_listener == Some(listener_id) => {
+                // Reservation refused or lost; the next relay we identify
+                // gets a try.
+                warn!("Relay reservation ended: {reason:?}");
+                self.relay_listener = None;
+            }
+
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                ..
+            } => {
+                debug!("Connected: {peer_id}");
+                self.peers
+                    .entry(peer_id)
+                    .or_insert_with(|| PeerStats {
+                        since: Instant::now(),
+                        connections: HashMap::new(),
+                        rtt: None,
+                        agent: String::new(),
+                    })
+                    .connections
+                    .insert(connection_id, endpoint.is_relayed());
+                if self.is_rendezvous_point(&peer_id) {
+                    self.register_at(peer_id);
+                    for namespace in std::mem::take(&mut self.pending_discoveries) {
+                        self.discover_at(peer_id, &namespace);
+                    }
+                }
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::PeerConnected(peer_id.to_string()));
+            }
+
+            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                debug!("Dial failed ({peer_id:?}): {error}");
+                if peer_id.is_some_and(|p| self.is_rendezvous_point(&p))
+                    && !self
+                        .rendezvous_points
+                        .iter()
+                        .any(|(p, _)| self.swarm.is_connected(p))
+                {
+                    // Nobody left to ask.
+                    for namespace in std::mem::take(&mut self.pending_discoveries) {
+                        self.rendezvous_found(namespace, Vec::new());
+                    }
+                }
+                let _ = self.event_tx.send(NetworkEvent::DialFailed(
+                    peer_id.map(|p| p.to_string()).unwrap_or_default(),
+                ));
+            }
+
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                num_established,
+                ..
+            } => {
+                if let Some(stats) = self.peers.get_mut(&peer_id) {
+                    stats.connections.remove(&connection_id);
+                }
+                if num_established > 0 {
+                    return;
+                }
+                debug!("Disconnected: {peer_id}");
+                self.peers.remove(&peer_id);
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::PeerDisconnected(peer_id.to_string()));
+                if self.swarm.connected_peers().next().is_none() {
+                    warn!("Lost every connection");
+                    let _ = self.event_tx.send(NetworkEvent::ConnectionLost);
+                }
+            }
+
+            SwarmEvent::Behaviour(behaviour_event) => {
+                self.handle_behaviour_event(behaviour_event);
+            }
+
+            _ => {}
+        }
+    }
+
+    fn handle_behaviour_event(&mut self, event: ChatBehaviourEvent) {
+        match event {
+            // ── GossipSub ─────────────────────────────────────────────
+            ChatBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                propagation_source,
+                message,
+                ..
+            }) => {
+                let source = message.source.unwrap_or(propagation_source);
+                let _ = self.event_tx.send(NetworkEvent::MessageReceived {
+                    topic: message.topic.to_string(),
+                    source: source.to_string(),
+                    payload: message.data,
+                });
+            }
+
+            ChatBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed { peer_id, topic }) => {
+                let _ = self.event_tx.send(NetworkEvent::PeerSubscribed {
+                    topic: topic.to_string(),
+                    peer_id: peer_id.to_string(),
+                });
+            }
+
+            ChatBehaviourEvent::Gossipsub(gossipsub::Event::Unsubscribed { peer_id, .. }) => {
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::PeerDisconnected(peer_id.to_string()));
+            }
+
+            // ── Kademlia ──────────────────────────────────────────────
+            ChatBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+                result: kad::QueryResult::Bootstrap(Ok(_)),
+                ..
+            }) => {
+                info!("Kademlia bootstrap complete");
+            }
+
+            ChatBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+                id,
+                result: kad::QueryResult::GetProviders(result),
+                step,
+                ..
+            }) => {
+                let Some((_, found)) = self.provider_queries.get_mut(&id) else {
+                    return;
+                };
+                if let Ok(kad::GetProvidersOk::FoundProviders { providers, .. }) = &result {
+                    found.extend(providers.iter().copied());
+                }
+                // A timeout ends the lookup with whatever was found so far.
+                if step.last || result.is_err() {
+                    let (key, found) = self.provider_queries.remove(&id).unwrap_or_default();
+                    debug!("Provider lookup for {key}: {} peer(s)", found.len());
+                    let _ = self.event_tx.send(NetworkEvent::ProvidersFound {
+                        key,
+                        peers: found.iter().map(PeerId::to_string).collect(),
+                    });
+                }
+            }
+
+            // ── mDNS ──────────────────────────────────────────────────
+            ChatBehaviourEvent::Mdns(mdns::Event::Discovered(peers)) => {
+                for (peer_id, addr) in peers {
+                    debug!("mDNS discovered: {peer_id} @ {addr}");
+                    self.swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&peer_id, addr);
+                    self.swarm
+                        .behaviour_mut()
+                        .gossipsub
+                        .add_explicit_peer(&peer_id);
+                }
+            }
+
+            ChatBehaviourEvent::Mdns(mdns::Event::Expired(peers)) => {
+                for (peer_id, _) in peers {
+                    self.swarm
+                        .behaviour_mut()
+                        .gossipsub
+                        .remove_explicit_peer(&peer_id);
+                }
+            }
+
+            // ── Identify ──────────────────────────────────────────────
+            ChatBehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. }) => {
+                if let Some(stats) = self.peers.get_mut(&peer_id) {
+                    stats.agent = info.agent_version.clone();
+                }
+                if info.protocols.contains(&relay::HOP_PROTOCOL_NAME) {
+                    self.reserve_relay(peer_id, &info.listen_addrs);
+                }
+                for addr in info.listen_addrs {
+                    self.swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&peer_id, addr);
+                }
+            }
+
+            // ── Relay client ──────────────────────────────────────────
+            ChatBehaviourEvent::RelayClient(relay::client::Event::ReservationReqAccepted {
+                relay_peer_id,
+                renewal: false,
+                ..
+            }) => {
+                info!("Relay reservation accepted by {relay_peer_id}");
+            }
+
+            // ── Rendezvous ────────────────────────────────────────────
+            ChatBehaviourEvent::Rendezvous(rendezvous::client::Event::Discovered {
+                registrations,
+                ..
+            }) => {
+                let own_id = *self.swarm.local_peer_id();
+                let mut found: HashMap<String, Vec<String>> = HashMap::new();
+                for registration in registrations {
+                    let peer_id = registration.record.peer_id();
+                    if peer_id == own_id {
+                        continue;
+                    }
+                    for addr in registration.record.addresses() {
+                        self.swarm
+                            .behaviour_mut()
+                            .kademlia
+                            .add_address(&peer_id, addr.clone());
+                    }
+                    found
+                        .entry(registration.namespace.to_string())
+                        .or_default()
+                        .push(peer_id.to_string());
+                }
+                for (namespace, peers) in found {
+                    self.rendezvous_found(namespace, peers);
+                }
+            }
+
+            ChatBehaviourEvent::Rendezvous(rendezvous::client::Event::DiscoverFailed {
+                rendezvous_node,
+                namespace,
+                error,
+            }) => {
+                debug!("Rendezvous discovery at {rendezvous_node} failed: {error:?}");
+                if let Some(namespace) = namespace {
+                    self.rendezvous_found(namespace.to_string(), Vec::new());
+                }
+            }
+
+            ChatBehaviourEvent::Rendezvous(rendezvous::client::Event::RegisterFailed {
+                rendezvous_node,
+                namespace,
+                error,
+            }) => {
+                warn!("Registering {namespace} at {rendezvous_node} failed: {error:?}");
+            }
+
+            // ── Ping ──────────────────────────────────────────────────
+            ChatBehaviourEvent::Ping(ping::Event {
+                peer,
+                result: Ok(rtt),
+                ..
+            }) => {
+                if let Some(stats) = self.peers.get_mut(&peer) {
+                    stats.rtt = Some(rtt);
+                }
+            }
+
+            // ── DCUtR ─────────────────────────────────────────────────
+            ChatBehaviourEvent::Dcutr(e) => {
+                info!("DCUtR event: {:?}", e);
+            }
+
+            // ── AutoNAT ───────────────────────────────────────────────
+            ChatBehaviourEvent::Autonat(autonat::Event::StatusChanged { old, new }) => {
+                info!("NAT status: {old:?} -> {new:?}");
+                let status = match new {
+                    autonat::NatStatus::Public(addr) => Reachability::Public(addr.to_string()),
+                    autonat::NatStatus::Private => Reachability::Private,
+                    autonat::NatStatus::Unknown => Reachability::Unknown,
+                };
+                let _ = self.event_tx.send(NetworkEvent::NatStatus(status));
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Listen through `relay` (a peer offering circuit relay v2) unless we
+    /// already have a reservation.  Its relayed address then arrives as a
+    /// normal `NewListenAddr` and ends up in room codes, so peers behind NAT
+    /// can be reached and upgraded to a direct connection by DCUtR.
+    fn reserve_relay(&mut self, relay: PeerId, addrs: &[Multiaddr]) {
+        if self.relay_listener.is_some() {
+            return;
+        }
+        let usable = addrs.iter().find(|addr| {
+            addr.iter().all(|p| match p {
+                Protocol::Ip4(ip) => !ip.is_loopback(),
+                Protocol::Ip6(ip) => !ip.is_loopback(),
+                Protocol::P2pCircuit | Protocol::P2p(_) => false,
+                _ => true,
+            })
+        });
+        let Some(addr) = usable else { return };
+        let circuit = addr
+            .clone()
+            .with(Protocol::P2p(relay))
+            .with(Protocol::P2pCircuit);
+        match self.swarm.listen_on(circuit.clone()) {
+            Ok(id) => {
+                debug!("Requesting relay reservation via {circuit}");
+                self.relay_listener = Some(id);
+            }
+            Err(e) => warn!("Cannot listen via relay {relay}: {e}"),
+        }
+    }
+
+    fn handle_command(&mut self, cmd: NetworkCommand) {
+        match cmd {
+            NetworkCommand::Subscribe(topic_str) => {
+                let topic = gossipsub::IdentTopic::new(&topic_str);
+                if let Err(e) = self.swarm.behaviour_mut().gossipsub.subscribe(&topic) {
+                    warn!("Subscribe error: {e}");
+                }
+            }
+
+            NetworkCommand::Unsubscribe(topic_str) => {
+                let topic = gossipsub::IdentTopic::new(&topic_str);
+                let _ = self.swarm.behaviour_mut().gossipsub.unsubscribe(&topic);
+            }
+
+            NetworkCommand::Publish {
+                topic: topic_str,
+                data,
+            } => {
+                let topic = gossipsub::IdentTopic::new(&topic_str);
+                if let Err(e) = self.swarm.behaviour_mut().gossipsub.publish(topic, data) {
+                    warn!("Publish error: {e}");
+                }
+            }
+
+            NetworkCommand::Dial(addr_str) => {
+                if let Ok(addr) = addr_str.parse::<Multiaddr>() {
+                    if let Err(e) = self.swarm.dial(addr) {
+                        warn!("Dial error: {e}");
+                    }
+                } else {
+                    warn!("Invalid multiaddr: {addr_str}");
+                }
+            }
+
+            NetworkCommand::DialPeer(peer_str) => match peer_str.parse::<PeerId>() {
+                Ok(peer_id) => {
+                    if let Err(e) = self.swarm.dial(DialOpts::peer_id(peer_id).build()) {
+                        warn!("Dial error: {e}");
+                        let _ = self.event_tx.send(NetworkEvent::DialFailed(peer_str));
+                    }
+                }
+                Err(_) => warn!("Invalid peer id: {peer_str}"),
+            },
+
+            NetworkCommand::StartProviding(key) => {
+                let key = kad::RecordKey::new(&key);
+                if let Err(e) = self.swarm.behaviour_mut().kademlia.start_providing(key) {
+                    warn!("Cannot announce room in the DHT: {e}");
+                }
+            }
+
+            NetworkCommand::StopProviding(key) => {
+                let key = kad::RecordKey::new(&key);
+                self.swarm.behaviour_mut().kademlia.stop_providing(&key);
+            }
+
+            NetworkCommand::FindProviders(key) => {
+                let id = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .get_providers(kad::RecordKey::new(&key));
+                self.provider_queries.insert(id, (key, HashSet::new()));
+            }
+
+            NetworkCommand::Register(namespace) => {
+                if self.registrations.insert(namespace) {
+                    self.renew_rendezvous();
+                }
+            }
+
+            NetworkCommand::Unregister(namespace) => {
+                if !self.registrations.remove(&namespace) {
+                    return;
+                }
+                let Ok(ns) = rendezvous::Namespace::new(namespace) else {
+                    return;
+                };
+                for (peer_id, _) in self.rendezvous_points.clone() {
+                    if self.swarm.is_connected(&peer_id) {
+                        self.swarm
+                            .behaviour_mut()
+                            .rendezvous
+                            .unregister(ns.clone(), peer_id);
+                    }
+                }
+            }
+
+            NetworkCommand::Discover(namespace) => {
+                let connected: Vec<PeerId> = self
+                    .rendezvous_points
+                    .iter()
+                    .map(|(p, _)| *p)
+                    .filter(|p| self.swarm.is_connected(p))
+                    .collect();
+                if self.rendezvous_points.is_empty() {
+                    self.rendezvous_found(namespace, Vec::new());
+                } else if connected.is_empty() {
+                    self.pending_discoveries.insert(namespace);
+                    self.dial_rendezvous_points();
+                } else {
+                    for peer_id in connected {
+                        self.discover_at(peer_id, &namespace);
+                    }
+                }
+            }
+
+            NetworkCommand::BlockPeer(peer_str) => match peer_str.parse::<PeerId>() {
+                Ok(peer_id) => self
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .blacklist_peer(&peer_id),
+                Err(_) => warn!("Invalid peer id: {peer_str}"),
+            },
+
+            NetworkCommand::GraylistPeer(peer_str) => match peer_str.parse::<PeerId>() {
+                Ok(peer_id) => {
+                    let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+                    if !gossipsub.set_application_score(&peer_id, SPAMMER_APP_SCORE) {
+                        debug!("Cannot score {peer_id}: not connected");
+                    }
+                }
+                Err(_) => warn!("Invalid peer id: {peer_str}"),
+            },
+
+            NetworkCommand::QueryListenAddrs => {
+                for addr in self.swarm.listeners() {
+                    let _ = self
+                        .event_tx
+                        .send(NetworkEvent::ListeningOn(addr.to_string()));
+                }
+            }
+
+            NetworkCommand::QueryPeerInfo(peers) => {
+                let infos = peers
+                    .iter()
+                    .filter_map(|p| p.parse::<PeerId>().ok())
+                    .filter_map(|p| self.peers.get(&p).map(|stats| stats.info(&p)))
+                    .collect();
+                let _ = self.event_tx.send(NetworkEvent::PeerInfo(infos));
+            }
+
+            // Handled by `run`.
+            NetworkCommand::Shutdown => {}
+        }
+    }
+}
+
+impl NetworkService {
+    fn is_rendezvous_point(&self, peer_id: &PeerId) -> bool {
+        self.rendezvous_points.iter().any(|(p, _)| p == peer_id)
+    }
+
+    fn dial_rendezvous_points(&mut self) {
+        for (peer_id, addr) in self.rendezvous_points.clone() {
+            if !self.swarm.is_connected(&peer_id)
+                && let Err(e) = self.swarm.dial(addr)
+            {
+                debug!("Cannot dial rendezvous point {peer_id}: {e}");
+            }
+        }
+    }
+
+    /// Register every namespace again at the points we are connected to,
+    /// and dial the others.
+    fn renew_rendezvous(&mut self) {
+        for (peer_id, _) in self.rendezvous_points.clone() {
+            if self.swarm.is_connected(&peer_id) {
+                self.register_at(peer_id);
+            }
+        }
+        self.dial_rendezvous_points();
+    }
+
+    fn register_at(&mut self, point: PeerId) {
+        for namespace in self.registrations.clone() {
+            let Ok(ns) = rendezvous::Namespace::new(namespace) else {
+                continue;
+            };
+            if let Err(e) = self
+                .swarm
+                .behaviour_mut()
+                .rendezvous
+                .register(ns, point, None)
+            {
+                // No external address yet: retried once one is confirmed.
+                debug!("Cannot register at {point}: {e}");
+                return;
+            }
+        }
+    }
+
+    fn discover_at(&mut self, point: PeerId, namespace: &str) {
+        match rendezvous::Namespace::new(namespace.to_string()) {
+            Ok(ns) => self
+                .swarm
+                .behaviour_mut()
+                .rendezvous
+                .discover(Some(ns), None, None, point),
+            Err(_) => self.rendezvous_found(namespace.to_string(), Vec::new()),
+        }
+    }
+
+    fn rendezvous_found(&self, namespace: String, peers: Vec<String>) {
+        debug!("Rendezvous lookup for {namespace}: {} peer(s)", peers.len());
+        let _ = self
+            .event_tx
+            .send(NetworkEvent::RendezvousFound { namespace, peers });
+    }
+}
+
+// Needed to drive the swarm in a loop (from `futures::StreamExt`).
+use futures::StreamExt;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swarm_key_accepts_hex_and_swarm_key_files() {
+        let hex = "0f".repeat(32);
+        let bare = parse_swarm_key(&hex).unwrap();
+        let file = parse_swarm_key(&format!("/key/swarm/psk/1.0.0/\n/base16/\n{hex}\n")).unwrap();
+        assert_eq!(
+            bare.fingerprint().to_string(),
+            file.fingerprint().to_string()
+        );
+        assert!(parse_swarm_key("0f0f").is_err());
+        assert!(parse_swarm_key(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn default_bootstrap_peers_parse() {
+        for addr in DEFAULT_BOOTSTRAP_PEERS {
+            let (_, parsed) = parse_bootstrap_peer(addr).unwrap();
+            assert_eq!(parsed.to_string(), *addr);
+        }
+        assert!(parse_bootstrap_peer("/ip4/10.0.0.1/tcp/4001").is_err());
+    }
+
+    #[test]
+    fn gossipsub_settings_are_checked() {
+        let defaults = GossipsubConfig::default();
+        let config = gossipsub_config(&defaults).unwrap();
+        assert_eq!(config.heartbeat_interval(), Duration::from_secs(10));
+        // A small private room: tiny mesh, quick heartbeat.
+        let small = GossipsubConfig {
+            heartbeat_ms: 500,
+            mesh_n: 2,
+            mesh_n_low: 1,
+            mesh_n_high: 3,
+            ..defaults.clone()
+        };
+        assert_eq!(gossipsub_config(&small).unwrap().mesh_n(), 2);
+
+        let inverted = GossipsubConfig {
+            mesh_n_low: 8,
+            ..defaults.clone()
+        };
+        assert!(gossipsub_config(&inverted).is_err());
+        let tiny = GossipsubConfig {
+            max_transmit_size: 1024,
+            ..defaults
+        };
+        assert!(gossipsub_config(&tiny).is_err());
+    }
+}
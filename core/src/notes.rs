@@ -0,0 +1,295 @@
+//! The shared notes of a room: lines every member can insert, change and
+//! delete, kept in step over the room topic without a coordinator.
+//!
+//! The document is a replicated growable array (RGA) of lines.  Each line
+//! is inserted after another (or at the top) under a fresh [`NoteId`];
+//! lines inserted after the same one are ordered newest first, so a line
+//! typed below another lands right under it whatever else arrives.  A
+//! line's text is a last-writer-wins register stamped with a `NoteId`, and
+//! deleting leaves a tombstone.  Merging a line is commutative, associative
+//! and idempotent, so members who saw the same lines in any order, twice,
+//! or in batches (`Notes` messages) show the same notes.
+//!
+//! Tombstones do not count toward the `MAX_NOTE_LINES` shown, but are
+//! kept, as later lines may sit after them.  What bounds memory is
+//! `MAX_NOTE_RECORDS`: past it the lines with the highest IDs go, which
+//! are the same ones whatever order lines came in.  A line's ID is above
+//! that of the line it was inserted after, so none is left waiting for a
+//! dropped one.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Result, anyhow, ensure};
+
+use crate::{
+    types::{NoteId, NoteLine},
+    wire::{MAX_NOTE_CLOCK, MAX_NOTE_LINES, MAX_NOTE_RECORDS},
+};
+
+#[derive(Debug)]
+pub struct Notes {
+    lines: BTreeMap<NoteId, NoteLine>,
+    /// Highest clock seen, ours included.
+    clock: u64,
+    /// Ours, to tell our writes from others' made at the same clock.
+    site: u64,
+}
+
+impl Default for Notes {
+    fn default() -> Self {
+        Self {
+            lines: BTreeMap::new(),
+            clock: 0,
+            site: rand::random(),
+        }
+    }
+}
+
+impl Notes {
+    /// Take in a line from a member; whether anything changed.  A line
+    /// inserted after one we do not have yet is kept but not shown until
+    /// that one arrives.
+    pub fn merge(&mut self, line: NoteLine) -> bool {
+        self.clock = self.clock.max(line.id.clock).max(line.edited.clock);
+        let full = self.lines.len() >= MAX_NOTE_RECORDS;
+        let beyond = full
+            && self
+                .lines
+                .last_key_value()
+                .is_some_and(|(last, _)| *last < line.id);
+        match self.lines.get_mut(&line.id) {
+            None if beyond => false,
+            None => {
+                self.lines.insert(line.id, line);
+                if full {
+                    self.lines.pop_last();
+                }
+                true
+            }
+            Some(known) => {
+                let mut changed = false;
+                if line.edited > known.edited && !known.deleted {
+                    known.text = line.text;
+                    known.edited = line.edited;
+                    changed = true;
+                }
+                if line.deleted && !known.deleted {
+                    known.deleted = true;
+                    known.text.clear();
+                    changed = true;
+                }
+                changed
+            }
+        }
+    }
+
+    /// A new line under `after` (`None`: at the top); the line to publish.
+    pub fn insert(&mut self, after: Option<NoteId>, text: String) -> Result<NoteLine> {
+        let shown = self.lines.values().filter(|line| !line.deleted).count();
+        ensure!(
+            shown < MAX_NOTE_LINES,
+            "The notes are full ({MAX_NOTE_LINES} lines)."
+        );
+        ensure!(
+            self.lines.len() < MAX_NOTE_RECORDS,
+            "The notes are full ({MAX_NOTE_RECORDS} lines, deleted ones included)."
+        );
+        if let Some(after) = after {
+            self.live(after)?;
+        }
+        let id = self.tick()?;
+        let line = NoteLine {
+            id,
+            after,
+            text,
+            edited: id,
+            deleted: false,
+        };
+        self.merge(line.clone());
+        Ok(line)
+    }
+
+    /// Replace the text of line `id`; the line to publish.
+    pub fn set(&mut self, id: NoteId, text: String) -> Result<NoteLine> {
+        self.live(id)?;
+        let edited = self.tick()?;
+        let line = NoteLine {
+            text,
+            edited,
+            ..self.lines[&id].clone()
+        };
+        self.merge(line.clone());
+        Ok(line)
+    }
+
+    /// Delete line `id`; the line to publish.
+    pub fn delete(&mut self, id: NoteId) -> Result<NoteLine> {
+        self.live(id)?;
+        let line = NoteLine {
+            deleted: true,
+            text: String::new(),
+            ..self.lines[&id].clone()
+        };
+        self.merge(line.clone());
+        Ok(line)
+    }
+
+    /// The lines as shown, top to bottom.
+    pub fn visible(&self) -> Vec<(NoteId, String)> {
+        let mut children: BTreeMap<Option<NoteId>, Vec<NoteId>> = BTreeMap::new();
+        for line in self.lines.values() {
+            children.entry(line.after).or_default().push(line.id);
+        }
+        // Depth first; siblings are in ascending order, so popping takes
+        // the newest first.
+        let mut stack = children.remove(&None).unwrap_or_default();
+        let mut shown = Vec::new();
+        while let Some(id) = stack.pop() {
+            let line = &self.lines[&id];
+            if !line.deleted {
+                shown.push((id, line.text.clone()));
+            }
+            stack.extend(children.remove(&Some(id)).unwrap_or_default());
+        }
+        shown
+    }
+
+    /// Every line we hold, tombstones included, for a joiner.
+    pub fn all(&self) -> impl Iterator<Item = &NoteLine> {
+        self.lines.values()
+    }
+
+    fn live(&self, id: NoteId) -> Result<()> {
+        match self.lines.get(&id) {
+            Some(line) if !line.deleted => Ok(()),
+            _ => Err(anyhow!("That line was deleted meanwhile.")),
+        }
+    }
+
+    /// A stamp for our next write.  `decode_notes` keeps others' clocks
+    /// to `MAX_NOTE_CLOCK`, so only our own writes could get past it.
+    fn tick(&mut self) -> Result<NoteId> {
+        ensure!(
+            self.clock < MAX_NOTE_CLOCK,
+            "The notes cannot take more changes."
+        );
+        self.clock += 1;
+        Ok(NoteId {
+            clock: self.clock,
+            site: self.site,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(notes: &Notes) -> Vec<String> {
+        notes.visible().into_iter().map(|(_, text)| text).collect()
+    }
+
+    #[test]
+    fn members_editing_at_once_end_up_with_the_same_notes() {
+        let mut amy = Notes::default();
+        let mut bob = Notes::default();
+        let top = amy.insert(None, "agenda".to_string()).unwrap();
+        bob.merge(top.clone());
+
+        // Both add under the top line, and both edit it, at once.
+        let mut from_amy = vec![amy.insert(Some(top.id), "budget".to_string()).unwrap()];
+        let mut from_bob = vec![bob.insert(Some(top.id), "venue".to_string()).unwrap()];
+        from_bob.push(
+            bob.insert(Some(from_bob[0].id), "  - the park".to_string())
+                .unwrap(),
+        );
+        from_amy.push(amy.set(top.id, "Agenda".to_string()).unwrap());
+        from_bob.push(bob.set(top.id, "AGENDA".to_string()).unwrap());
+        from_amy.push(amy.delete(from_amy[0].id).unwrap());
+
+        // Delivered in any order, and twice.
+        for line in from_bob.iter().rev().chain(&from_bob) {
+            amy.merge(line.clone());
+        }
+        for line in from_amy.iter().chain(&from_amy) {
+            bob.merge(line.clone());
+        }
+        assert_eq!(texts(&amy), texts(&bob));
+        assert_eq!(texts(&amy)[1..], ["venue", "  - the park"]);
+        // The edit with the higher stamp wins on both sides.
+        let winner = if from_amy[1].edited > from_bob[2].edited {
+            "Agenda"
+        } else {
+            "AGENDA"
+        };
+        assert_eq!(texts(&amy)[0], winner);
+
+        // A deleted line stays deleted, and is no place to insert.
+        assert!(!bob.merge(NoteLine {
+            text: "budget!".to_string(),
+            edited: NoteId { clock: 99, site: 0 },
+            ..from_amy[0].clone()
+        }));
+        assert!(amy.insert(Some(from_amy[0].id), "x".to_string()).is_err());
+        // A line whose predecessor has not arrived waits for it.
+        let mut late = Notes::default();
+        late.merge(from_bob[1].clone());
+        assert!(texts(&late).is_empty());
+        late.merge(top);
+        late.merge(from_bob[0].clone());
+        assert_eq!(texts(&late), ["agenda", "venue", "  - the park"]);
+    }
+
+    #[test]
+    fn deleted_lines_leave_room_and_the_bound_drops_the_same_lines_everywhere() {
+        let mut notes = Notes::default();
+        for _ in 0..3 {
+            let lines: Vec<_> = (0..MAX_NOTE_LINES)
+                .map(|i| notes.insert(None, i.to_string()).unwrap())
+                .collect();
+            assert!(notes.insert(None, "one too many".to_string()).is_err());
+            for line in lines {
+                notes.delete(line.id).unwrap();
+            }
+        }
+        assert!(notes.insert(None, "room again".to_string()).is_ok());
+
+        // Past the bound, members who got the lines in opposite orders
+        // keep the same ones.
+        let line = |clock: u64| {
+            let id = NoteId { clock, site: 7 };
+            NoteLine {
+                id,
+                after: None,
+                text: clock.to_string(),
+                edited: id,
+                deleted: false,
+            }
+        };
+        let lines: Vec<_> = (1..=MAX_NOTE_RECORDS as u64 + 5).map(line).collect();
+        let (mut up, mut down) = (Notes::default(), Notes::default());
+        lines.iter().for_each(|l| _ = up.merge(l.clone()));
+        lines.iter().rev().for_each(|l| _ = down.merge(l.clone()));
+        assert_eq!(up.all().count(), MAX_NOTE_RECORDS);
+        assert_eq!(texts(&up), texts(&down));
+    }
+
+    #[test]
+    fn a_clock_at_the_limit_stops_writes_instead_of_wrapping() {
+        let mut notes = Notes::default();
+        let id = NoteId {
+            clock: MAX_NOTE_CLOCK,
+            site: 1,
+        };
+        notes.merge(NoteLine {
+            id,
+            after: None,
+            text: "end".to_string(),
+            edited: id,
+            deleted: false,
+        });
+        assert!(notes.insert(None, "x".to_string()).is_err());
+        assert!(notes.set(id, "x".to_string()).is_err());
+        assert_eq!(texts(&notes), ["end"]);
+    }
+}
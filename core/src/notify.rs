@@ -0,0 +1,33 @@
+//! Deciding what deserves the user's attention.
+//!
+//! `App` publishes `UiEvent::Bell` and `AppEvent::Notify` for a mention, a
+//! direct message or any message while the frontend reports it is
+//! unfocused, unless the room is muted.  How to alert — bell, desktop
+//! popup — is up to the frontend.
+
+/// Whether `text` mentions `nick` as a whole word (case-insensitive), with
+/// or without a leading `@`.
+pub fn mentions(text: &str, nick: &str) -> bool {
+    if nick.is_empty() {
+        return false;
+    }
+    let text = text.to_lowercase();
+    let nick = nick.to_lowercase();
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    text.match_indices(&nick).any(|(at, _)| {
+        !is_word(text[..at].chars().next_back()) && !is_word(text[at + nick.len()..].chars().next())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mention_needs_a_whole_word() {
+        assert!(mentions("hey Seung, look", "seung"));
+        assert!(mentions("@seung!", "Seung"));
+        assert!(!mentions("seungho is here", "Seung"));
+        assert!(!mentions("anything", ""));
+    }
+}
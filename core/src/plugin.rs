@@ -0,0 +1,132 @@
+//! Hooks for bots and other extensions that run inside `App`.
+//!
+//! A [`Plugin`] is registered before the app starts (`App::with_plugin`)
+//! and called on the app task as messages, joins and commands come in.
+//! Hooks answer through the [`PluginContext`] they are handed: messages
+//! queued with [`PluginContext::send`] go out once the hook returns, signed
+//! and encrypted like anything the user types.
+//!
+//! ```no_run
+//! use chatting1_core::plugin::{Plugin, PluginContext};
+//! use chatting1_core::types::DisplayMessage;
+//!
+//! /// Answers "!ping" and greets newcomers.
+//! struct AutoResponder;
+//!
+//! impl Plugin for AutoResponder {
+//!     fn name(&self) -> &str {
+//!         "autoresponder"
+//!     }
+//!
+//!     fn on_message(&mut self, ctx: &mut PluginContext, room: &str, msg: &DisplayMessage) {
+//!         if msg.text.trim() == "!ping" {
+//!             ctx.send(room, "pong");
+//!         }
+//!     }
+//!
+//!     fn on_peer_join(&mut self, ctx: &mut PluginContext, room: &str, member: &str) {
+//!         ctx.send(room, format!("Welcome, {member}!"));
+//!     }
+//! }
+//!
+//! /// Appends every message to a file; `/chatlog` says where.
+//! struct LogBot(std::path::PathBuf);
+//!
+//! impl Plugin for LogBot {
+//!     fn name(&self) -> &str {
+//!         "logbot"
+//!     }
+//!
+//!     fn on_message(&mut self, _ctx: &mut PluginContext, room: &str, msg: &DisplayMessage) {
+//!         use std::io::Write;
+//!         if let Ok(mut file) = std::fs::OpenOptions::new().create(true).append(true).open(&self.0) {
+//!             let _ = writeln!(file, "[{room}] {}: {}", msg.sender, msg.text);
+//!         }
+//!     }
+//!
+//!     fn on_command(&mut self, ctx: &mut PluginContext, name: &str, _args: &str) -> bool {
+//!         if name != "chatlog" {
+//!             return false;
+//!         }
+//!         ctx.notice(format!("Logging to {}", self.0.display()));
+//!         true
+//!     }
+//! }
+//! ```
+
+use crate::types::DisplayMessage;
+
+/// An extension called by `App`.  Every hook has a no-op default, so a
+/// plugin implements only what it needs.  Hooks run on the app task:
+/// keep them quick, and hand slow work to a task of your own.
+pub trait Plugin: Send {
+    /// Short identifier, used in logs.
+    fn name(&self) -> &str;
+
+    /// A chat message from another member arrived in `room`.  Our own
+    /// messages, including those a plugin sent, are not passed in.
+    fn on_message(&mut self, _ctx: &mut PluginContext, _room: &str, _msg: &DisplayMessage) {}
+
+    /// `member` ("nick#disc") joined `room`.
+    fn on_peer_join(&mut self, _ctx: &mut PluginContext, _room: &str, _member: &str) {}
+
+    /// The user typed `/<name> <args>` and it is no built-in command.
+    /// Returns `true` if this plugin handled it; otherwise the next plugin
+    /// is asked, and the help is shown if none did.
+    fn on_command(&mut self, _ctx: &mut PluginContext, _name: &str, _args: &str) -> bool {
+        false
+    }
+
+    /// One line per command the plugin adds, `/usage — what it does`, for
+    /// the help and the command palette.
+    fn help(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// What a hook can see and do.
+pub struct PluginContext {
+    own_name: String,
+    active_room: Option<String>,
+    pub(crate) actions: Vec<PluginAction>,
+}
+
+/// Something a hook asked for, carried out by `App` after it returns.
+pub(crate) enum PluginAction {
+    Send { room: String, text: String },
+    Notice(String),
+}
+
+impl PluginContext {
+    pub(crate) fn new(own_name: String, active_room: Option<String>) -> Self {
+        Self {
+            own_name,
+            active_room,
+            actions: Vec::new(),
+        }
+    }
+
+    /// Our display name ("nick#disc").
+    pub fn own_name(&self) -> &str {
+        &self.own_name
+    }
+
+    /// The room the user is looking at, if any.
+    pub fn active_room(&self) -> Option<&str> {
+        self.active_room.as_deref()
+    }
+
+    /// Send `text` as a chat message to `room` (ignored if we are not in
+    /// it).
+    pub fn send(&mut self, room: &str, text: impl Into<String>) {
+        self.actions.push(PluginAction::Send {
+            room: room.to_string(),
+            text: text.into(),
+        });
+    }
+
+    /// Show `text` to the local user only.
+    pub fn notice(&mut self, text: impl Into<String>) {
+        self.actions.push(PluginAction::Notice(text.into()));
+    }
+}
@@ -0,0 +1,873 @@
+use anyhow::{Context, Result, anyhow, bail};
+use hkdf::Hkdf;
+use libp2p::{
+    PeerId,
+    identity::{Keypair, PublicKey, ed25519},
+};
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{
+    crypto::{RoomSalt, SALT_LEN, ed25519_key_of, legacy_salt},
+    types::Reachability,
+};
+
+/// HKDF info of a room's topic.
+const TOPIC_INFO: &[u8] = b"chatapp/v2/room-topic";
+
+/// Identifies a GossipSub topic for a given room: hex HKDF-SHA256 of the
+/// room name under its salt, so peers relaying the topic learn neither the
+/// name nor, without the salt from the room code, whether it is one they
+/// could guess.
+pub fn topic_for_room(room_name: &str, salt: &RoomSalt) -> String {
+    let mut okm = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(salt), room_name.as_bytes())
+        .expand(TOPIC_INFO, &mut okm)
+        .expect("32 bytes is a valid HKDF-SHA256 length");
+    let hex: String = okm.iter().map(|b| format!("{b:02x}")).collect();
+    format!("/chatapp/v2/rooms/{hex}")
+}
+
+/// GossipSub topic of the public room directory: owners list rooms on it,
+/// unencrypted, for anyone browsing.
+pub const DIRECTORY_TOPIC: &str = "/chatapp/v1/directory";
+
+/// GossipSub topic only `peer_id` subscribes to; carries its direct messages.
+pub fn inbox_topic(peer_id: &str) -> String {
+    format!("/chatapp/v1/inbox/{}", peer_id)
+}
+
+/// Addresses to put in a room code: the first direct QUIC address, the
+/// first other direct (TCP) address, the first direct WebSocket address (for
+/// browser peers, which can dial nothing else) and the first relayed
+/// address, in that order.  External addresses sort first in `listen_addrs`, so they win when
+/// known.  Relayed addresses get `/p2p/<own peer ID>` appended, which dialing
+/// through a relay needs.
+///
+/// `nat` reorders them: an address AutoNAT confirmed goes first; behind NAT
+/// the relayed address does, as direct ones only help on the same LAN.
+pub fn code_addrs(listen_addrs: &[String], own_peer_id: &str, nat: &Reachability) -> Vec<String> {
+    let is_relayed = |a: &&String| a.contains("/p2p-circuit");
+    let is_quic = |a: &&String| a.contains("/quic-v1");
+    let is_ws = |a: &&String| a.ends_with("/ws") || a.ends_with("/wss") || a.contains("/ws/");
+    let mut direct = listen_addrs.iter().filter(|a| !is_relayed(a));
+    let quic = direct.clone().find(is_quic);
+    let ws = direct.clone().find(is_ws);
+    let other = direct.find(|a| !is_quic(a) && !is_ws(a));
+    let relayed = listen_addrs.iter().find(is_relayed).map(|a| {
+        if a.ends_with("/p2p-circuit") {
+            format!("{a}/p2p/{own_peer_id}")
+        } else {
+            a.clone()
+        }
+    });
+    let mut addrs: Vec<String> = quic.into_iter().chain(other).chain(ws).cloned().collect();
+    match nat {
+        Reachability::Public(public) => {
+            addrs.retain(|a| a != public);
+            addrs.insert(0, public.clone());
+            addrs.extend(relayed);
+        }
+        Reachability::Private => {
+            addrs.splice(0..0, relayed);
+        }
+        Reachability::Unknown => addrs.extend(relayed),
+    }
+    addrs
+}
+
+/// Salt mixed into DHT keys so they do not collide with other applications'
+/// records for the same string.
+const DISCOVERY_SALT: &str = "chatapp/v1/room-discovery";
+/// Same for rendezvous namespaces.
+const RENDEZVOUS_SALT: &str = "chatapp/v1/room-rendezvous";
+
+/// Kademlia provider key for a room: hex SHA-256 of salt + room name.
+/// Members announce themselves under it so others can join by name alone.
+pub fn discovery_key(room_name: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(DISCOVERY_SALT)
+        .chain_update([0])
+        .chain_update(room_name)
+        .finalize();
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Rendezvous namespace of a room: like `discovery_key`, under its own salt.
+pub fn rendezvous_namespace(room_name: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(RENDEZVOUS_SALT)
+        .chain_update([0])
+        .chain_update(room_name)
+        .finalize();
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// ── Room name ─────────────────────────────────────────────────────────────────
+
+/// Longest room name, in characters.
+pub const MAX_ROOM_NAME_LEN: usize = 64;
+
+/// Characters a room name may not contain: path separators and what
+/// file systems reserve, as names end up in log file names.
+const RESERVED_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Whether `c` is invisible formatting that could make two names look
+/// alike: zero-width characters, bidi controls and the BOM.
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2069}' | '\u{FEFF}')
+}
+
+/// The room name `name` as typed, trimmed and in Unicode NFC, so that the
+/// same name typed on two systems derives the same topic and key; or why
+/// it cannot be one.  Names go into topics, room codes (NUL-separated) and
+/// log file names, so control characters, invisible formatting, reserved
+/// characters and a leading `.` are refused.
+pub fn normalize_room_name(name: &str) -> Result<String> {
+    let name: String = name.trim().nfc().collect();
+    if name.is_empty() {
+        bail!("Room name is empty.");
+    }
+    if name.chars().count() > MAX_ROOM_NAME_LEN {
+        bail!("Room name is longer than {MAX_ROOM_NAME_LEN} characters.");
+    }
+    if let Some(c) = name.chars().find(|&c| c.is_control() || is_invisible(c)) {
+        bail!(
+            "Room name contains the unprintable character U+{:04X}.",
+            c as u32
+        );
+    }
+    if let Some(c) = name.chars().find(|c| RESERVED_CHARS.contains(c)) {
+        bail!("Room name contains '{c}', which room names may not.");
+    }
+    if name.starts_with('.') {
+        bail!("Room name may not start with '.'.");
+    }
+    Ok(name)
+}
+
+/// Check that `name`, from a room code or a peer, is a room name exactly
+/// as `normalize_room_name` would leave it: one in another form would
+/// lead to a different topic than the one its members use.
+pub fn check_room_name(name: &str) -> Result<()> {
+    match normalize_room_name(name) {
+        Ok(normal) if normal == name => Ok(()),
+        Ok(_) => bail!("room name '{}' is not normalized", name.escape_debug()),
+        Err(e) => bail!("bad room name '{}': {e}", name.escape_debug()),
+    }
+}
+
+// ── Room code ─────────────────────────────────────────────────────────────────
+
+/// First byte of a v6 room code: a member's invite (`/invite`), like v4
+/// but signed by that member, with the owner's key and the owner's
+/// statement that it owns the room (`sign_ownership`) after the member's.
+const CODE_V6: u8 = 0x06;
+/// First byte of a v5 room code: v6 without the owner's statement, so the
+/// owner it names was only the member's word.  Refused.
+const CODE_V5: u8 = 0x05;
+/// First byte of a v4 room code: signed by the creator, whose key it
+/// carries instead of the peer ID.
+const CODE_V4: u8 = 0x04;
+/// First byte of a v3 room code: v2 plus a trailing CRC-32.
+const CODE_V3: u8 = 0x03;
+/// First byte of a v2 room code, followed by the room salt.  v1 codes start
+/// with the room name, which never contains control characters.
+const CODE_V2: u8 = 0x02;
+/// Length of the CRC-32 that ends a v3 or v4 code.
+const CRC_LEN: usize = 4;
+/// Length of the creator's Ed25519 public key in a v4 code.
+const CODE_KEY_LEN: usize = 32;
+/// Length of the creator's Ed25519 signature in a v4 code.
+const CODE_SIG_LEN: usize = 64;
+/// Prefixed to the bytes a room code signature covers, so it cannot be
+/// passed off as the signature of anything else.
+const CODE_SIGN_DOMAIN: &[u8] = b"chatapp/v1/room-code\0";
+/// Prefixed to the bytes an owner's statement covers.
+const OWNER_SIGN_DOMAIN: &[u8] = b"chatapp/v1/room-owner\0";
+
+/// Data embedded in a room code shared out-of-band.
+///
+/// Encoded as `0x04 salt[16] creator_key[32] room_name[\0addr…] sig[64]
+/// crc32[4]` → Base58.  The signature is the creator's, over everything
+/// before it, so a code altered on the way (another salt, or addresses
+/// leading somewhere else) is rejected, and the peer ID derived from the key
+/// is an owner joiners can check moderation messages against.  The CRC
+/// (big-endian) covers everything before it, so a mistyped code is rejected
+/// with a hint rather than failing its signature.
+///
+/// A member other than the owner makes a v6 code, `0x06 salt[16]
+/// member_key[32] owner_key[32] owner_sig[64] room_name[\0addr…] sig[64]
+/// crc32[4]`: it leads to the member, who signs it, and names the owner,
+/// whose `owner_sig` over the room (`sign_ownership`) must check out — a
+/// member cannot make itself or anyone else the owner.  v5 codes, the same
+/// without `owner_sig`, are refused.
+///
+/// Older codes still decode, unsigned: v3 (`0x03 salt[16]
+/// room_name\0peer_id\0addr[\0addr…] crc32[4]`), v2 (no CRC) and v1 (no
+/// version byte and salt; the room uses the name-derived legacy salt).
+#[derive(Debug, Clone)]
+pub struct RoomCodeData {
+    /// Human-readable room name (maps to GossipSub topic).
+    pub room_name: String,
+    /// libp2p Peer ID of the creator as a base58-encoded string.
+    pub peer_id: String,
+    /// Peer ID of the room's owner when a member made the code (v6); it
+    /// leads to that member, `peer_id`.  Empty in the owner's own codes.
+    pub owner: String,
+    /// The owner's statement that it owns the room (`sign_ownership`),
+    /// which a member's code must carry; empty in the owner's own codes.
+    pub owner_proof: Vec<u8>,
+    /// Multiaddrs the creator is listening on — typically one QUIC and one
+    /// TCP address, QUIC first.  May be empty.
+    pub addrs: Vec<String>,
+    /// Argon2 salt of the room key.
+    pub salt: RoomSalt,
+    /// Set by `decode`: whether the code carried its creator's signature
+    /// (v4).  `encode` ignores it and always signs.
+    pub signed: bool,
+}
+
+impl RoomCodeData {
+    /// Peer ID of the room's owner, as far as the code tells.
+    pub fn owner(&self) -> &str {
+        match self.owner.as_str() {
+            "" => &self.peer_id,
+            owner => owner,
+        }
+    }
+
+    /// Encode to a compact Base58 string safe to share over any channel,
+    /// signed with `keypair`, which must be the creator's (`peer_id`).
+    pub fn encode(&self, keypair: &Keypair) -> Result<String> {
+        if keypair.public().to_peer_id().to_string() != self.peer_id {
+            bail!("a room code can only be signed by the peer it names");
+        }
+        let key = keypair
+            .public()
+            .try_into_ed25519()
+            .map_err(|_| anyhow!("room codes need an Ed25519 identity key"))?;
+        let owner_key = match self.owner() {
+            owner if owner == self.peer_id => None,
+            owner => Some(ed25519_key_of(
+                &owner.parse().context("bad owner peer ID")?,
+            )?),
+        };
+        if owner_key.is_some()
+            && !verify_ownership(self.owner(), &self.room_name, &self.salt, &self.owner_proof)
+        {
+            bail!("a member's room code needs the owner's statement that it owns the room");
+        }
+        let mut raw = vec![if owner_key.is_some() {
+            CODE_V6
+        } else {
+            CODE_V4
+        }];
+        raw.extend_from_slice(&self.salt);
+        raw.extend_from_slice(&key.to_bytes());
+        if let Some(owner_key) = owner_key {
+            raw.extend_from_slice(&owner_key.to_bytes());
+            raw.extend_from_slice(&self.owner_proof);
+        }
+        // NUL-delimited: room_name\0addr… — no JSON overhead.
+        raw.extend_from_slice(self.room_name.as_bytes());
+        for addr in &self.addrs {
+            raw.push(0);
+            raw.extend_from_slice(addr.as_bytes());
+        }
+        let signature = keypair
+            .sign(&code_signing_bytes(&raw))
+            .context("sign room code")?;
+        raw.extend_from_slice(&signature);
+        raw.extend_from_slice(&crc32(&raw).to_be_bytes());
+        Ok(bs58::encode(raw).into_string())
+    }
+
+    /// Decode a Base58 room code string, checking the creator's signature
+    /// if it has one.
+    pub fn decode(code: &str) -> Result<Self> {
+        let bytes = bs58::decode(code)
+            .into_vec()
+            .context("base58 decode room code")?;
+        let (salt, fields) = match bytes.split_first() {
+            Some((&CODE_V4, _)) => return Self::decode_signed(checked_body(&bytes)?, false),
+            Some((&CODE_V6, _)) => return Self::decode_signed(checked_body(&bytes)?, true),
+            Some((&CODE_V5, _)) => bail!(
+                "bad room code: a member's code from an older version, which cannot show who owns the room — ask for a new one"
+            ),
+            Some((&CODE_V3, _)) => {
+                let body = checked_body(&bytes)?;
+                if body.len() < 1 + SALT_LEN {
+                    bail!("bad room code: too short");
+                }
+                let (salt, fields) = body[1..].split_at(SALT_LEN);
+                (Some(salt.try_into()?), fields)
+            }
+            Some((&CODE_V2, rest)) if rest.len() >= SALT_LEN => {
+                let (salt, fields) = rest.split_at(SALT_LEN);
+                (Some(salt.try_into()?), fields)
+            }
+            _ => (None, bytes.as_slice()),
+        };
+        let s = std::str::from_utf8(fields).context("room code is not valid UTF-8")?;
+        let parts: Vec<&str> = s.split('\0').collect();
+        if parts.len() < 3 {
+            bail!("invalid room code format");
+        }
+        check_room_name(parts[0])?;
+        Ok(Self {
+            salt: salt.unwrap_or_else(|| legacy_salt(parts[0])),
+            room_name: parts[0].to_string(),
+            peer_id: parts[1].to_string(),
+            owner: String::new(),
+            owner_proof: Vec::new(),
+            addrs: parts[2..]
+                .iter()
+                .filter(|a| !a.is_empty())
+                .map(|a| a.to_string())
+                .collect(),
+            signed: false,
+        })
+    }
+
+    /// Decode the body of a v4 code, or of a v6 one `with_owner`, its CRC
+    /// already checked.
+    fn decode_signed(body: &[u8], with_owner: bool) -> Result<Self> {
+        let owner_len = if with_owner {
+            CODE_KEY_LEN + CODE_SIG_LEN
+        } else {
+            0
+        };
+        if body.len() < 1 + SALT_LEN + CODE_KEY_LEN + owner_len + CODE_SIG_LEN {
+            bail!("bad room code: too short");
+        }
+        let (signed, signature) = body.split_at(body.len() - CODE_SIG_LEN);
+        let (salt, rest) = signed[1..].split_at(SALT_LEN);
+        let (key, rest) = rest.split_at(CODE_KEY_LEN);
+        let (owner, fields) = rest.split_at(owner_len);
+        let (owner, owner_proof) = owner.split_at(owner.len().min(CODE_KEY_LEN));
+        let key = ed25519::PublicKey::try_from_bytes(key)
+            .map_err(|_| anyhow!("bad room code: invalid creator key"))?;
+        let owner = match with_owner {
+            true => ed25519::PublicKey::try_from_bytes(owner)
+                .map(|owner| PeerId::from(PublicKey::from(owner)).to_string())
+                .map_err(|_| anyhow!("bad room code: invalid owner key"))?,
+            false => String::new(),
+        };
+        if !key.verify(&code_signing_bytes(signed), signature) {
+            bail!("bad room code: its signature does not match — the code was altered");
+        }
+        let fields = std::str::from_utf8(fields).context("room code is not valid UTF-8")?;
+        let mut parts = fields.split('\0');
+        let room_name = parts.next().unwrap_or_default();
+        check_room_name(room_name)?;
+        let salt: RoomSalt = salt.try_into()?;
+        if with_owner && !verify_ownership(&owner, room_name, &salt, owner_proof) {
+            bail!("bad room code: the owner it names did not sign for the room");
+        }
+        Ok(Self {
+            room_name: room_name.to_string(),
+            peer_id: PeerId::from(PublicKey::from(key)).to_string(),
+            owner,
+            owner_proof: owner_proof.to_vec(),
+            addrs: parts
+                .filter(|a| !a.is_empty())
+                .map(|a| a.to_string())
+                .collect(),
+            salt,
+            signed: true,
+        })
+    }
+}
+
+/// The owner's statement that it owns room `room_name` salted `salt`:
+/// `keypair`'s signature over a domain, the salt, its public key and the
+/// name.  Members put it in the codes they make and hand it to joiners, so
+/// neither can name an owner that did not sign for the room.
+pub fn sign_ownership(keypair: &Keypair, room_name: &str, salt: &RoomSalt) -> Result<Vec<u8>> {
+    let key = keypair
+        .public()
+        .try_into_ed25519()
+        .map_err(|_| anyhow!("room owners need an Ed25519 identity key"))?;
+    keypair
+        .sign(&ownership_bytes(&key, room_name, salt))
+        .context("sign room ownership")
+}
+
+/// Whether `proof` is `owner`'s (a peer ID) `sign_ownership` statement for
+/// room `room_name` salted `salt`.
+pub fn verify_ownership(owner: &str, room_name: &str, salt: &RoomSalt, proof: &[u8]) -> bool {
+    let Ok(key) = owner
+        .parse()
+        .map_err(|_| anyhow!("bad peer ID"))
+        .and_then(|p| ed25519_key_of(&p))
+    else {
+        return false;
+    };
+    key.verify(&ownership_bytes(&key, room_name, salt), proof)
+}
+
+fn ownership_bytes(owner: &ed25519::PublicKey, room_name: &str, salt: &RoomSalt) -> Vec<u8> {
+    [
+        OWNER_SIGN_DOMAIN,
+        salt,
+        &owner.to_bytes(),
+        room_name.as_bytes(),
+    ]
+    .concat()
+}
+
+/// A v3 or v4 code without its CRC, if the CRC matches.
+fn checked_body(bytes: &[u8]) -> Result<&[u8]> {
+    match bytes.split_last_chunk::<CRC_LEN>() {
+        Some((body, crc)) if crc32(body).to_be_bytes() == *crc => Ok(body),
+        Some(_) => bail!("bad room code: checksum mismatch — check it for typos"),
+        None => bail!("bad room code: too short"),
+    }
+}
+
+/// What a room code signature covers: the domain, then the code up to the
+/// signature.
+fn code_signing_bytes(code: &[u8]) -> Vec<u8> {
+    [CODE_SIGN_DOMAIN, code].concat()
+}
+
+/// CRC-32 (IEEE, as in zip and PNG) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+// ── Invite tokens ─────────────────────────────────────────────────────────────
+
+/// Separates the room code from the grant in an invite token; not in the
+/// Base58 alphabet.
+const INVITE_SEP: char = '-';
+/// Prefixed to the bytes an invite signature covers.
+const INVITE_SIGN_DOMAIN: &[u8] = b"chatapp/v1/invite\0";
+/// Length of a grant's ID, expiry and flags…
+const GRANT_FIELDS_LEN: usize = 8 + 8 + 1;
+/// …and of the whole grant, with the signature.
+const GRANT_LEN: usize = GRANT_FIELDS_LEN + CODE_SIG_LEN;
+/// Bit of the grant flags marking a single-use invite.
+const SINGLE_USE: u8 = 1;
+
+/// The owner's leave to join a room until `expires_ms`, handed out as an
+/// invite token: a room code, `-`, and this grant.
+///
+/// The grant is `id[8] expires_ms[8] flags[1] sig[64]` → Base58, numbers
+/// big-endian.  The signature is the owner's over a domain, the room's salt
+/// and name and the grant before it, so it holds for that room only.
+/// Members check it against the owner's key, which its peer ID inlines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invite {
+    /// Random; a single-use invite is spent on the first peer presenting it.
+    pub id: u64,
+    /// Unix time in milliseconds after which members refuse it.
+    pub expires_ms: i64,
+    pub single_use: bool,
+    signature: Vec<u8>,
+}
+
+impl Invite {
+    /// A new invite to room `room_name` (salted `salt`), signed with
+    /// `keypair`, which must be the owner's.
+    pub fn mint(
+        keypair: &Keypair,
+        room_name: &str,
+        salt: &RoomSalt,
+        expires_ms: i64,
+        single_use: bool,
+    ) -> Result<Self> {
+        let mut invite = Self {
+            id: rand::random(),
+            expires_ms,
+            single_use,
+            signature: Vec::new(),
+        };
+        let signed = invite.signing_bytes(room_name, salt);
+        invite.signature = keypair.sign(&signed).context("sign invite")?;
+        Ok(invite)
+    }
+
+    /// The grant alone, as members receive it in `PakeStart`.
+    pub fn encode(&self) -> String {
+        let mut raw = self.fields().to_vec();
+        raw.extend_from_slice(&self.signature);
+        bs58::encode(raw).into_string()
+    }
+
+    /// Decode a grant; its signature is checked by `verify`.
+    pub fn decode(grant: &str) -> Result<Self> {
+        let raw = bs58::decode(grant)
+            .into_vec()
+            .context("base58 decode invite")?;
+        if raw.len() != GRANT_LEN {
+            bail!("bad invite: wrong length");
+        }
+        let (fields, signature) = raw.split_at(GRANT_FIELDS_LEN);
+        Ok(Self {
+            id: u64::from_be_bytes(fields[..8].try_into()?),
+            expires_ms: i64::from_be_bytes(fields[8..16].try_into()?),
+            single_use: fields[16] & SINGLE_USE != 0,
+            signature: signature.to_vec(),
+        })
+    }
+
+    /// Whether `owner` (a peer ID) signed this invite to room `room_name`
+    /// salted `salt`.
+    pub fn verify(&self, owner: &str, room_name: &str, salt: &RoomSalt) -> bool {
+        let Ok(key) = owner
+            .parse()
+            .map_err(|_| anyhow!("bad peer ID"))
+            .and_then(|p| ed25519_key_of(&p))
+        else {
+            return false;
+        };
+        key.verify(&self.signing_bytes(room_name, salt), &self.signature)
+    }
+
+    fn fields(&self) -> [u8; GRANT_FIELDS_LEN] {
+        let mut fields = [0; GRANT_FIELDS_LEN];
+        fields[..8].copy_from_slice(&self.id.to_be_bytes());
+        fields[8..16].copy_from_slice(&self.expires_ms.to_be_bytes());
+        fields[16] = if self.single_use { SINGLE_USE } else { 0 };
+        fields
+    }
+
+    fn signing_bytes(&self, room_name: &str, salt: &RoomSalt) -> Vec<u8> {
+        [
+            INVITE_SIGN_DOMAIN,
+            salt,
+            room_name.as_bytes(),
+            &[0],
+            &self.fields(),
+        ]
+        .concat()
+    }
+}
+
+/// A room code or invite token as the user entered it: the code, and the
+/// invite if it is a token.
+pub fn split_invite(entry: &str) -> Result<(&str, Option<Invite>)> {
+    match entry.split_once(INVITE_SEP) {
+        Some((code, grant)) => Ok((code, Some(Invite::decode(grant)?))),
+        None => Ok((entry, None)),
+    }
+}
+
+/// An invite token for room `code` (which must be signed by the owner who
+/// signed `invite`).
+pub fn invite_token(code: &str, invite: &Invite) -> String {
+    format!("{code}{INVITE_SEP}{}", invite.encode())
+}
+
+// ── Active room state ─────────────────────────────────────────────────────────
+
+/// Name and GossipSub topic of a joined room, plus the subject line its
+/// owner set with `/topic`.
+#[derive(Debug, Clone)]
+pub struct RoomState {
+    pub name: String,
+    pub topic: String,
+    /// Set by the owner with `/topic`; empty if none.
+    pub subject: String,
+    /// Timestamp of the `Topic` message `subject` came from, so an older
+    /// one arriving late does not win.
+    pub subject_ms: i64,
+}
+
+impl RoomState {
+    pub fn new(name: &str, salt: &RoomSalt) -> Self {
+        Self {
+            name: name.to_string(),
+            topic: topic_for_room(name, salt),
+            subject: String::new(),
+            subject_ms: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Code data for room "lobby" created by `creator`.
+    fn lobby(creator: &Keypair, addrs: &[&str]) -> RoomCodeData {
+        RoomCodeData {
+            room_name: "lobby".to_string(),
+            peer_id: creator.public().to_peer_id().to_string(),
+            owner: String::new(),
+            owner_proof: Vec::new(),
+            addrs: addrs.iter().map(|a| a.to_string()).collect(),
+            salt: [7; SALT_LEN],
+            signed: false,
+        }
+    }
+
+    #[test]
+    fn a_members_invite_leads_to_it_and_names_the_owner() {
+        let (owner, member) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+        let mut data = lobby(&member, &["/ip4/5.6.7.8/tcp/4001"]);
+        data.owner = owner.public().to_peer_id().to_string();
+        // Without the owner's statement a member cannot name an owner.
+        assert!(data.encode(&member).is_err());
+        data.owner_proof = sign_ownership(&member, "lobby", &data.salt).unwrap();
+        assert!(data.encode(&member).is_err());
+        data.owner_proof = sign_ownership(&owner, "lobby", &data.salt).unwrap();
+        let decoded = RoomCodeData::decode(&data.encode(&member).unwrap()).unwrap();
+        assert_eq!(
+            (decoded.peer_id.as_str(), decoded.owner()),
+            (data.peer_id.as_str(), data.owner.as_str())
+        );
+        assert_eq!(decoded.addrs, data.addrs);
+        assert!(decoded.signed);
+
+        // The owner's own code names it once.
+        let own = RoomCodeData::decode(&lobby(&owner, &[]).encode(&owner).unwrap()).unwrap();
+        assert_eq!((own.owner.as_str(), own.owner()), ("", data.owner.as_str()));
+    }
+
+    #[test]
+    fn a_members_code_with_a_forged_or_no_owner_statement_is_refused() {
+        let (owner, member) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+        let mut data = lobby(&member, &[]);
+        data.owner = owner.public().to_peer_id().to_string();
+        data.owner_proof = sign_ownership(&owner, "lobby", &data.salt).unwrap();
+        let raw = bs58::decode(data.encode(&member).unwrap())
+            .into_vec()
+            .unwrap();
+        let body = &raw[..raw.len() - CRC_LEN - CODE_SIG_LEN];
+        let reseal = |mut body: Vec<u8>| {
+            body.extend_from_slice(&member.sign(&code_signing_bytes(&body)).unwrap());
+            body.extend_from_slice(&crc32(&body).to_be_bytes());
+            bs58::encode(body).into_string()
+        };
+
+        // The member signs a statement of its own in the owner's place.
+        let mut forged = body.to_vec();
+        let at = 1 + SALT_LEN + 2 * CODE_KEY_LEN;
+        forged[at..at + CODE_SIG_LEN]
+            .copy_from_slice(&sign_ownership(&member, "lobby", &data.salt).unwrap());
+        let err = RoomCodeData::decode(&reseal(forged)).unwrap_err();
+        assert!(err.to_string().contains("did not sign"), "{err}");
+
+        // A v5 code carries no statement at all.
+        let v5 = [&[CODE_V5], &body[1..at], &body[at + CODE_SIG_LEN..]].concat();
+        let err = RoomCodeData::decode(&reseal(v5)).unwrap_err();
+        assert!(err.to_string().contains("older version"), "{err}");
+    }
+
+    #[test]
+    fn room_names_are_normalized_and_unsafe_ones_refused() {
+        // "café" typed with a combining accent is the precomposed name.
+        assert_eq!(normalize_room_name("  cafe\u{301} ").unwrap(), "caf\u{e9}");
+        assert_eq!(
+            normalize_room_name(&"é".repeat(MAX_ROOM_NAME_LEN))
+                .unwrap()
+                .chars()
+                .count(),
+            MAX_ROOM_NAME_LEN
+        );
+        for bad in [
+            "",
+            "   ",
+            "a\0b",
+            "tab\there",
+            "rtl\u{202E}name",
+            "../etc",
+            "a/b",
+            "c:\\x",
+            ".hidden",
+        ] {
+            assert!(normalize_room_name(bad).is_err(), "{bad:?}");
+        }
+        assert!(normalize_room_name(&"x".repeat(MAX_ROOM_NAME_LEN + 1)).is_err());
+
+        // Codes carry names as their creator normalized them; any other
+        // form is refused rather than fixed up, as its topic would differ.
+        let creator = Keypair::generate_ed25519();
+        for name in ["cafe\u{301}", "a/b", " lobby"] {
+            let data = RoomCodeData {
+                room_name: name.to_string(),
+                ..lobby(&creator, &[])
+            };
+            assert!(
+                RoomCodeData::decode(&data.encode(&creator).unwrap()).is_err(),
+                "{name:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn room_code_round_trips_several_addresses() {
+        let creator = Keypair::generate_ed25519();
+        let data = lobby(
+            &creator,
+            &["/ip4/1.2.3.4/udp/4001/quic-v1", "/ip4/1.2.3.4/tcp/4001"],
+        );
+        let decoded = RoomCodeData::decode(&data.encode(&creator).unwrap()).unwrap();
+        assert_eq!(decoded.addrs, data.addrs);
+        assert_eq!((decoded.peer_id, decoded.salt), (data.peer_id, data.salt));
+        assert!(decoded.signed);
+
+        let bare = RoomCodeData::decode(&lobby(&creator, &[]).encode(&creator).unwrap()).unwrap();
+        assert_eq!((bare.room_name.as_str(), bare.addrs.len()), ("lobby", 0));
+    }
+
+    #[test]
+    fn altered_or_misattributed_code_is_rejected() {
+        let creator = Keypair::generate_ed25519();
+        let data = lobby(&creator, &["/ip4/1.2.3.4/tcp/4001"]);
+        assert!(data.encode(&Keypair::generate_ed25519()).is_err());
+
+        // Point the code elsewhere, fixing up the CRC as a forger would.
+        let raw = bs58::decode(data.encode(&creator).unwrap())
+            .into_vec()
+            .unwrap();
+        let mut body = raw[..raw.len() - CRC_LEN].to_vec();
+        let at = body.len() - CODE_SIG_LEN - 1;
+        body[at] = b'9';
+        let crc = crc32(&body).to_be_bytes();
+        body.extend_from_slice(&crc);
+        let err = RoomCodeData::decode(&bs58::encode(body).into_string()).unwrap_err();
+        assert!(err.to_string().contains("signature"), "{err}");
+    }
+
+    #[test]
+    fn code_addrs_prefer_quic_then_tcp_then_websocket_then_relay() {
+        let listen = [
+            "/ip4/1.2.3.4/tcp/4001/p2p/relay/p2p-circuit".to_string(),
+            "/ip4/10.0.0.2/tcp/5003/ws".to_string(),
+            "/ip4/10.0.0.2/tcp/5000".to_string(),
+            "/ip4/10.0.0.2/udp/5001/quic-v1".to_string(),
+            "/ip4/10.0.0.2/tcp/5002".to_string(),
+        ];
+        assert_eq!(
+            code_addrs(&listen, "me", &Reachability::Unknown),
+            [
+                "/ip4/10.0.0.2/udp/5001/quic-v1",
+                "/ip4/10.0.0.2/tcp/5000",
+                "/ip4/10.0.0.2/tcp/5003/ws",
+                "/ip4/1.2.3.4/tcp/4001/p2p/relay/p2p-circuit/p2p/me",
+            ]
+        );
+        assert_eq!(
+            code_addrs(&listen, "me", &Reachability::Private)[0],
+            "/ip4/1.2.3.4/tcp/4001/p2p/relay/p2p-circuit/p2p/me"
+        );
+        let public = Reachability::Public("/ip4/5.6.7.8/tcp/5000".to_string());
+        assert_eq!(
+            code_addrs(&listen, "me", &public),
+            [
+                "/ip4/5.6.7.8/tcp/5000",
+                "/ip4/10.0.0.2/udp/5001/quic-v1",
+                "/ip4/10.0.0.2/tcp/5000",
+                "/ip4/10.0.0.2/tcp/5003/ws",
+                "/ip4/1.2.3.4/tcp/4001/p2p/relay/p2p-circuit/p2p/me",
+            ]
+        );
+    }
+
+    #[test]
+    fn discovery_key_is_stable_and_per_room() {
+        assert_eq!(discovery_key("lobby"), discovery_key("lobby"));
+        assert_ne!(discovery_key("lobby"), discovery_key("lobby2"));
+        assert_eq!(discovery_key("lobby").len(), 64);
+        assert_ne!(rendezvous_namespace("lobby"), discovery_key("lobby"));
+        assert_eq!(rendezvous_namespace("lobby"), rendezvous_namespace("lobby"));
+    }
+
+    #[test]
+    fn topics_hide_the_room_name_behind_its_salt() {
+        let topic = topic_for_room("lobby", &[7; SALT_LEN]);
+        assert_eq!(topic, topic_for_room("lobby", &[7; SALT_LEN]));
+        assert!(!topic.contains("lobby"));
+        assert_ne!(topic, topic_for_room("lobby", &[8; SALT_LEN]));
+        assert_ne!(topic, topic_for_room("lobby2", &[7; SALT_LEN]));
+    }
+
+    #[test]
+    fn single_address_codes_still_decode() {
+        let legacy = bs58::encode(b"lobby\0peer\0/ip4/1.2.3.4/tcp/4001").into_string();
+        let decoded = RoomCodeData::decode(&legacy).unwrap();
+        assert_eq!(decoded.addrs, vec!["/ip4/1.2.3.4/tcp/4001"]);
+        assert_eq!(decoded.salt, legacy_salt("lobby"));
+        let empty = bs58::encode(b"lobby\0peer\0").into_string();
+        assert!(RoomCodeData::decode(&empty).unwrap().addrs.is_empty());
+
+        let mut v2 = vec![CODE_V2];
+        v2.extend_from_slice(&[9; SALT_LEN]);
+        v2.extend_from_slice(b"lobby\0peer\0/ip4/1.2.3.4/tcp/4001");
+        let decoded = RoomCodeData::decode(&bs58::encode(v2).into_string()).unwrap();
+        assert_eq!(
+            (decoded.room_name.as_str(), decoded.salt),
+            ("lobby", [9; SALT_LEN])
+        );
+
+        let mut v3 = vec![CODE_V3];
+        v3.extend_from_slice(&[9; SALT_LEN]);
+        v3.extend_from_slice(b"lobby\0peer\0/ip4/1.2.3.4/tcp/4001");
+        v3.extend_from_slice(&crc32(&v3).to_be_bytes());
+        let decoded = RoomCodeData::decode(&bs58::encode(v3).into_string()).unwrap();
+        assert_eq!((decoded.peer_id.as_str(), decoded.signed), ("peer", false));
+    }
+
+    #[test]
+    fn invites_hold_for_their_room_and_owner_only() {
+        let owner = Keypair::generate_ed25519();
+        let data = lobby(&owner, &["/ip4/1.2.3.4/tcp/4001"]);
+        let code = data.encode(&owner).unwrap();
+        let invite = Invite::mint(&owner, "lobby", &data.salt, 1_000, true).unwrap();
+        let token = invite_token(&code, &invite);
+        let (code_part, decoded) = split_invite(&token).unwrap();
+        assert_eq!(
+            (code_part, decoded.as_ref()),
+            (code.as_str(), Some(&invite))
+        );
+        assert!(RoomCodeData::decode(code_part).is_ok());
+        assert_eq!(split_invite(&code).unwrap(), (code.as_str(), None));
+
+        let invite = decoded.unwrap();
+        assert!(invite.single_use);
+        assert!(invite.verify(&data.peer_id, "lobby", &data.salt));
+        assert!(!invite.verify(&data.peer_id, "lobby2", &data.salt));
+        assert!(!invite.verify(&data.peer_id, "lobby", &[8; SALT_LEN]));
+        let other = Keypair::generate_ed25519()
+            .public()
+            .to_peer_id()
+            .to_string();
+        assert!(!invite.verify(&other, "lobby", &data.salt));
+        let extended = Invite {
+            expires_ms: 2_000,
+            ..invite
+        };
+        assert!(!extended.verify(&data.peer_id, "lobby", &data.salt));
+        assert!(split_invite(&format!("{code}-abc")).is_err());
+    }
+
+    #[test]
+    fn mistyped_code_fails_its_checksum() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let creator = Keypair::generate_ed25519();
+        let code = lobby(&creator, &["/ip4/1.2.3.4/tcp/4001"])
+            .encode(&creator)
+            .unwrap();
+        for at in [code.len() / 2, code.len() - 1] {
+            let mut typo = code.clone().into_bytes();
+            typo[at] = if typo[at] == b'z' { b'y' } else { b'z' };
+            let err = RoomCodeData::decode(&String::from_utf8(typo).unwrap()).unwrap_err();
+            assert!(err.to_string().contains("bad room code"), "{err}");
+        }
+    }
+}
@@ -0,0 +1,1494 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::config::{LogFormat, Persona, RecentRoom, ThemeConfig};
+
+// ── Display ──────────────────────────────────────────────────────────────────
+
+/// A message ready to render in the terminal.
+#[derive(Debug, Clone)]
+pub struct DisplayMessage {
+    pub timestamp: DateTime<Utc>,
+    /// "Nick#disc" for chat messages, empty for system events.
+    pub sender: String,
+    pub text: String,
+    pub is_system: bool,
+    /// `false` when the sender's signature was missing or did not match the
+    /// claimed name, so `sender` may be an impersonation.
+    pub verified: bool,
+    /// The sender is a contact whose safety number we confirmed
+    /// (`/verify`); shown as a ✔ badge.
+    pub trusted: bool,
+    /// Wire message ID of a chat message, what reactions refer to; 0 for
+    /// system lines, direct messages and history from other members.
+    pub msg_id: u64,
+    /// Whether anyone acknowledged a message we sent; `None` for others.
+    pub delivery: Option<Delivery>,
+    /// Reactions so far: emoji and how many members chose it.
+    pub reactions: Vec<(String, usize)>,
+    /// For a poll, its options and votes so far; `text` is the question.
+    pub poll: Option<PollTally>,
+    /// Someone else's message naming us (`@nick`); highlighted and listed
+    /// by `/mentions`.
+    pub mention: bool,
+    /// For a reply, the start of the message it answers, drawn above it.
+    pub quote: Option<Quote>,
+    /// Our name for the sender (`/alias`), shown instead of `sender`.
+    pub alias: Option<String>,
+    /// The glyph the sender picked as its avatar, drawn before the name.
+    pub avatar: Option<String>,
+    /// The thread of the room it was written in (`/thread`); `None` for
+    /// the room itself.
+    pub thread: Option<String>,
+}
+
+/// Most characters of the original message a reply quotes.
+pub const MAX_QUOTE_CHARS: usize = 80;
+
+/// What a reply shows of the message it answers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    /// Sender of the original; empty if it is not in our history.
+    pub sender: String,
+    /// Its first line, cut to `MAX_QUOTE_CHARS`.
+    pub text: String,
+}
+
+impl Quote {
+    /// Quote the start of `msg`.
+    pub fn of(msg: &DisplayMessage) -> Self {
+        let line = msg.text.lines().next().unwrap_or_default();
+        let mut text: String = line.chars().take(MAX_QUOTE_CHARS).collect();
+        if text.len() < msg.text.len() {
+            text.push('…');
+        }
+        Self {
+            sender: msg.sender.clone(),
+            text,
+        }
+    }
+
+    /// Stands in for an original we do not have.
+    pub fn missing() -> Self {
+        Self {
+            sender: String::new(),
+            text: "an earlier message".to_string(),
+        }
+    }
+}
+
+/// A poll as shown under its question.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PollTally {
+    /// Each option and how many members voted for it.
+    pub options: Vec<(String, usize)>,
+    pub closes_at: DateTime<Utc>,
+    /// Ended by its creator (`/endpoll`) or by `closes_at` passing.
+    pub closed: bool,
+}
+
+/// Delivery state of a message we sent, from `Ack`s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Published; no recipient has acknowledged it yet.
+    Pending,
+    /// At least one recipient acknowledged it.
+    Delivered,
+}
+
+impl DisplayMessage {
+    pub fn chat(sender: &str, text: &str) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            sender: sender.to_string(),
+            text: text.to_string(),
+            is_system: false,
+            verified: true,
+            trusted: false,
+            msg_id: 0,
+            delivery: None,
+            reactions: Vec::new(),
+            poll: None,
+            mention: false,
+            quote: None,
+            alias: None,
+            avatar: None,
+            thread: None,
+        }
+    }
+
+    pub fn system(text: &str) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            sender: String::new(),
+            text: text.to_string(),
+            is_system: true,
+            verified: true,
+            trusted: false,
+            msg_id: 0,
+            delivery: None,
+            reactions: Vec::new(),
+            poll: None,
+            mention: false,
+            quote: None,
+            alias: None,
+            avatar: None,
+            thread: None,
+        }
+    }
+
+    /// A private message between two peers; shown as "from → to".
+    pub fn direct(from: &str, to: &str, text: &str) -> Self {
+        Self::chat(&format!("{} → {}", from, to), text)
+    }
+
+    /// Mark the sender as unproven.
+    pub fn unverified(mut self) -> Self {
+        self.verified = false;
+        self
+    }
+
+    /// Mark the sender as a verified contact.
+    pub fn trusted(mut self) -> Self {
+        self.trusted = true;
+        self
+    }
+
+    /// Tag with the wire ID it was received under, so it can be reacted to.
+    pub fn with_id(mut self, msg_id: u64) -> Self {
+        self.msg_id = msg_id;
+        self
+    }
+
+    /// Mark as a reply to the quoted message.
+    pub fn with_quote(mut self, quote: Quote) -> Self {
+        self.quote = Some(quote);
+        self
+    }
+
+    /// Show the sender as `alias`, if there is one.
+    pub fn with_alias(mut self, alias: Option<String>) -> Self {
+        self.alias = alias;
+        self
+    }
+
+    /// Draw `avatar`, if there is one, before the sender.
+    pub fn with_avatar(mut self, avatar: Option<String>) -> Self {
+        self.avatar = avatar;
+        self
+    }
+
+    /// Put in `thread`, if there is one, rather than the room itself.
+    pub fn in_thread(mut self, thread: Option<String>) -> Self {
+        self.thread = thread;
+        self
+    }
+
+    /// Show `poll` under the text, its question.
+    pub fn with_poll(mut self, poll: PollTally) -> Self {
+        self.poll = Some(poll);
+        self
+    }
+
+    /// Mark as sent by us under wire ID `msg_id`, awaiting an `Ack`.
+    pub fn sent(mut self, msg_id: u64) -> Self {
+        self.msg_id = msg_id;
+        self.delivery = Some(Delivery::Pending);
+        self
+    }
+
+    /// Sender as shown to the user, after its avatar and by our alias for
+    /// it if any, flagged when unverified and badged when a verified
+    /// contact.
+    pub fn sender_label(&self) -> String {
+        let label = self.label(self.alias.as_deref().unwrap_or(&self.sender));
+        match &self.avatar {
+            Some(avatar) => format!("{avatar} {label}"),
+            None => label,
+        }
+    }
+
+    /// Like `sender_label`, with the name the sender chose rather than our
+    /// alias, and no avatar: for logs and exports, which others may read.
+    pub fn real_label(&self) -> String {
+        self.label(&self.sender)
+    }
+
+    fn label(&self, name: &str) -> String {
+        if !self.verified {
+            format!("{name} [unverified]")
+        } else if self.trusted {
+            format!("{name} ✔")
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// The message as terminal lines at most `width` cells wide.
+    /// Continuation lines are indented to where the text starts, unless the
+    /// prefix takes more than half the width.  A reply starts with one line
+    /// quoting the original; a poll's options follow one per line with
+    /// their votes, then reactions on a line of their own, like `👍 2  🎉 1`.
+    pub fn render_lines(&self, width: usize) -> Vec<String> {
+        let time = self.timestamp.format("%H:%M");
+        let prefix = if self.is_system {
+            format!("[{}] *** ", time)
+        } else {
+            format!("[{}] {}: ", time, self.sender_label())
+        };
+        let mark = match self.delivery {
+            Some(Delivery::Pending) => " …",
+            Some(Delivery::Delivered) => " ✓",
+            None => "",
+        };
+        let width = width.max(1);
+        let prefix_len = prefix.width();
+        let indent = if prefix_len * 2 <= width {
+            prefix_len
+        } else {
+            2.min(width - 1)
+        };
+        let mut lines = Vec::new();
+        if let Some(quote) = &self.quote {
+            // A quote is one line, however many the original had.
+            let text = quote.text.replace(['\n', '\t'], " ");
+            let line = match quote.sender.as_str() {
+                "" => format!("        ╭ {text}"),
+                sender => format!("        ╭ {sender}: {text}"),
+            };
+            lines.push(truncate(&line, width));
+        }
+        lines.extend(wrap(
+            &prefix,
+            &format!("{}{}", self.text, mark),
+            width,
+            indent,
+        ));
+        if let Some(poll) = &self.poll {
+            let pad = " ".repeat(indent);
+            for (n, (option, votes)) in (1..).zip(&poll.options) {
+                let bar = "█".repeat((*votes).min(MAX_POLL_BAR));
+                lines.extend(wrap(
+                    &pad,
+                    &format!("{n}) {option}  {bar} {votes}"),
+                    width,
+                    indent,
+                ));
+            }
+            let state = if poll.closed {
+                "poll closed".to_string()
+            } else {
+                format!(
+                    "/vote 1…{} until {}",
+                    poll.options.len(),
+                    poll.closes_at.format("%H:%M")
+                )
+            };
+            lines.extend(wrap(&pad, &state, width, indent));
+        }
+        if !self.reactions.is_empty() {
+            let counts: Vec<String> = self
+                .reactions
+                .iter()
+                .map(|(emoji, n)| format!("{} {}", emoji, n))
+                .collect();
+            lines.extend(wrap(&" ".repeat(indent), &counts.join("  "), width, indent));
+        }
+        lines
+    }
+}
+
+/// Most cells of the bar drawn for a poll option's votes.
+const MAX_POLL_BAR: usize = 20;
+
+/// `line` cut to `width` cells, ending in "…" if anything was cut.
+fn truncate(line: &str, width: usize) -> String {
+    if line.width() <= width {
+        return line.to_string();
+    }
+    let mut out = String::new();
+    let mut cells = 0;
+    for c in line.chars() {
+        let w = c.width().unwrap_or(0);
+        if cells + w + 1 > width {
+            break;
+        }
+        out.push(c);
+        cells += w;
+    }
+    out.push('…');
+    out
+}
+
+/// Spaces a tab in a message is shown as.
+const TAB: &str = "    ";
+
+/// `wrap_line` for each line of `body`, tabs expanded: the first after
+/// `prefix`, the others on lines of their own at `indent`.
+fn wrap(prefix: &str, body: &str, width: usize, indent: usize) -> Vec<String> {
+    let body = body.replace('\t', TAB);
+    let pad = " ".repeat(indent);
+    body.split('\n')
+        .enumerate()
+        .flat_map(|(n, line)| wrap_line(if n == 0 { prefix } else { &pad }, line, width, indent))
+        .collect()
+}
+
+/// Greedy word wrap of `body` after `prefix`, measured in terminal cells
+/// (CJK and most emoji take two); words wider than a line are split.
+/// `indent` must be less than `width`.
+fn wrap_line(prefix: &str, body: &str, width: usize, indent: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut len = 0;
+    // The prefix goes through the same splitting as a word, in case it is
+    // wider than the line; the first word of the body follows it directly.
+    for (n, word) in std::iter::once(prefix).chain(body.split(' ')).enumerate() {
+        let word_len = word.width();
+        if n > 1 {
+            if len + 1 + word_len <= width {
+                line.push(' ');
+                len += 1;
+            } else {
+                lines.push(std::mem::replace(&mut line, " ".repeat(indent)));
+                len = indent;
+            }
+        }
+        let mut rest = word;
+        loop {
+            let room = width.saturating_sub(len);
+            let (mut take, mut cells) = (0, 0);
+            for (i, c) in rest.char_indices() {
+                let w = c.width().unwrap_or(0);
+                if cells + w > room {
+                    break;
+                }
+                (take, cells) = (i + c.len_utf8(), cells + w);
+            }
+            // A glyph wider than a whole line still has to go somewhere.
+            if take == 0
+                && len <= indent
+                && line.chars().all(|c| c == ' ')
+                && let Some(c) = rest.chars().next()
+            {
+                (take, cells) = (c.len_utf8(), c.width().unwrap_or(0));
+            }
+            line.push_str(&rest[..take]);
+            len += cells;
+            rest = &rest[take..];
+            if rest.is_empty() {
+                break;
+            }
+            lines.push(std::mem::replace(&mut line, " ".repeat(indent)));
+            len = indent;
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+// ── Wire protocol ─────────────────────────────────────────────────────────────
+
+/// Serialised with `wire::encode` (versioned CBOR), then AES-256-GCM
+/// encrypted before transmission.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WireMessage {
+    pub msg_type: WireMessageType,
+    pub sender_nick: String,
+    pub sender_disc: String,
+    pub timestamp_ms: i64,
+    /// One emoji or other character the sender shows before its name
+    /// (`wire::validate_avatar`); empty for none.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub avatar: String,
+    /// Random per message; with the sender it identifies the message for
+    /// replay detection (`wire::SeenCache`).  0 from peers predating it.
+    #[serde(default)]
+    pub msg_id: u64,
+    pub text: String,
+    /// For a chat message answering another, that message's `msg_id`; 0
+    /// otherwise.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub reply_to: u64,
+    /// Set on the parts of a chat message too long for one payload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<Chunk>,
+    /// For a chat message in a thread of the room (`/thread`), its name
+    /// (`wire::validate_thread`); empty for the room itself.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub thread: String,
+    /// Sender's libp2p public key (protobuf, base64); empty when unsigned.
+    #[serde(default)]
+    pub public_key: String,
+    /// Ed25519 signature over the fields above (base64); see `wire::sign`.
+    #[serde(default)]
+    pub signature: String,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// Where a chat message sent in parts (`wire::split_chat`) belongs.  Peers
+/// predating chunks show each part as a message of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    /// Message ID of the whole message, shared by its parts.
+    pub id: u64,
+    /// Position of this part, from 0.
+    pub index: u16,
+    /// Number of parts.
+    pub total: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WireMessageType {
+    /// Normal chat message.
+    Chat,
+    /// Sent once after entering a room; `text` is the sender's peer ID.
+    Join,
+    /// Sent when leaving a room.
+    Leave,
+    /// Reply to `Join`: `text` is a JSON list of `RosterEntry`, the members
+    /// the sender knows (itself included).
+    RosterAnnounce,
+    /// Ask one member for recent messages; `text` is that member's name.
+    HistoryRequest,
+    /// Reply to `HistoryRequest`: `text` is a JSON list of `HistoryEntry`,
+    /// oldest first.
+    HistoryResponse,
+    /// Private message to one peer; only ever sent inside a
+    /// `DirectEnvelope` on the recipient's inbox topic.
+    Direct,
+    /// Direct: "what is the salt of room `text`?" — sent by a peer joining
+    /// by name, which has no room code to take the salt from.
+    SaltRequest,
+    /// Direct reply to `SaltRequest`: `text` is a JSON `RoomSaltReply`.
+    SaltResponse,
+    /// Direct: a joining peer opens the password check with a member —
+    /// `text` is a JSON `PakeStart`.
+    PakeStart,
+    /// Direct reply to `PakeStart`: `text` is a JSON `PakeReply`.
+    PakeReply,
+    /// Direct, owner or co-owner only: the room moved to a new key epoch —
+    /// `text` is a JSON `RoomRekey`.
+    Rekey,
+    /// Owner or co-owner: remove the peer whose ID is `text` from the
+    /// room.
+    Kick,
+    /// Owner or co-owner: like `Kick`, and members ignore that peer from
+    /// now on.
+    Ban,
+    /// Direct: "I received your message" — `text` is its `msg_id`.
+    Ack,
+    /// Chat messages a member missed while away, re-sent by a member who
+    /// was online: `text` is a JSON `ForwardBatch`.
+    Forward,
+    /// Owner or co-owner: `text` is the room's new subject line.
+    Topic,
+    /// Reply to `Join` from any member that knows an owner setting — the
+    /// subject, slow mode, who writes or who joins, and who owns the room:
+    /// `text` is the signed `Topic`, `SlowMode`, `Announcement`,
+    /// `InviteOnly`, `Capacity`, `GrantAdmin` or `TransferOwnership`
+    /// message as encoded for the wire, base64.
+    TopicSync,
+    /// An emoji reaction to a chat message: `text` is a JSON `Reaction`.
+    Reaction,
+    /// The sender's availability, on every change and periodically while
+    /// not online: `text` is a JSON `Presence`.
+    Presence,
+    /// Owner or co-owner: members wait `text` seconds between chat
+    /// messages; "0" turns slow mode off.
+    SlowMode,
+    /// A question for members to vote on: `text` is a JSON `Poll`, and
+    /// the message's `msg_id` identifies the poll.
+    Poll,
+    /// A member's choice in a poll: `text` is a JSON `Vote`.  Signed only;
+    /// a later vote replaces the member's earlier one.
+    Vote,
+    /// The poll's creator closes it early: `text` is its `msg_id`.
+    PollEnd,
+    /// Lines of the room's shared notes, new or changed, or all of them
+    /// for a joiner: `text` is a JSON array of `NoteLine`s (see `notes`).
+    Notes,
+    /// Owner only: "true" has members check the password of joiners with
+    /// a valid invite (`room::Invite`) only, "false" of anyone again.
+    /// Replayed to joiners as `TopicSync`.
+    InviteOnly,
+    /// Owner only: the most members the room holds, the owner included —
+    /// `text` is a number, "0" for no limit.  Replayed to joiners as
+    /// `TopicSync`.
+    Capacity,
+    /// Owner or co-owner: who may write in the room — `text` is a JSON
+    /// `Announcement`.  Replayed to joiners as `TopicSync`.
+    Announcement,
+    /// Owner only: make a member a co-owner, who may also set the topic,
+    /// slow mode and speakers and kick and ban, or no longer — `text` is
+    /// a JSON `AdminGrant`.  Replayed to joiners as `TopicSync`.
+    GrantAdmin,
+    /// Owner only: the member whose peer ID is `text` owns the room from
+    /// now on; the previous owner stays a co-owner.  Replayed to joiners
+    /// as `TopicSync`.
+    TransferOwnership,
+    /// Owner only: the room goes by another name — `text` is a JSON
+    /// `RoomRekey` whose `room` is the new name.  Members move to the
+    /// topic of the new name and to the key sent along.
+    Rename,
+    /// On the directory topic, unencrypted: a room its owner lists —
+    /// `text` is a JSON `PublicRoom`.  Sent again every minute, and when
+    /// asked with `ListingQuery`.
+    Listing,
+    /// On the directory topic: the owner took the room named in `text`
+    /// off the directory.
+    Unlisting,
+    /// On the directory topic: someone started browsing; owners answer
+    /// with their `Listing`s.
+    ListingQuery,
+}
+
+/// Payload of `SaltResponse`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomSaltReply {
+    pub room: String,
+    /// The room's Argon2 salt, base64.
+    pub salt: String,
+    /// Peer ID of the room's owner (its creator); empty if unknown.
+    #[serde(default)]
+    pub owner: String,
+    /// The owner's statement that it owns the room
+    /// (`room::sign_ownership`), base64; without it `owner` is ignored.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub owner_proof: String,
+}
+
+/// Payload of `PakeStart`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PakeStart {
+    pub room: String,
+    /// The joiner's SPAKE2 message, base64.
+    pub msg: String,
+    /// The grant of the invite token the joiner entered (`room::Invite`);
+    /// empty if it used a plain room code.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub invite: String,
+}
+
+/// Payload of `PakeReply`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PakeReply {
+    pub room: String,
+    /// The member's SPAKE2 message, base64.
+    pub msg: String,
+    /// Tag over the session key, base64 (see `RoomKey::pake_respond`).
+    pub confirm: String,
+    /// Key epoch of the room; 0 until the owner first rotated the key.
+    #[serde(default)]
+    pub epoch: u32,
+    /// The epoch's key encrypted with the handshake's session key, base64;
+    /// empty at epoch 0, when the password key is still in use.
+    #[serde(default)]
+    pub key: String,
+    /// Why the member would not check the password (an expired invite,
+    /// say); empty when it did.  `msg` and `confirm` are empty then.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub refused: String,
+    /// The room's member limit, when it refused because the room is full;
+    /// 0 otherwise.
+    #[serde(default)]
+    pub full: u32,
+    /// Peer ID of the owner as the member knows it, and the owner's
+    /// statement that it owns the room (`room::sign_ownership`), base64;
+    /// both empty if the member has none.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub owner: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub owner_proof: String,
+}
+
+/// Payload of `Rekey`.  The direct message carrying it is already encrypted
+/// to the recipient's identity key, so the room key is sent as is.  Also
+/// of `Rename`, with the new name as `room`, which only members with the
+/// current room key can read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomRekey {
+    pub room: String,
+    pub epoch: u32,
+    /// The new room key, base64.
+    pub key: String,
+    /// `Rename` only: the owner's statement that it owns the room under
+    /// the new name (`room::sign_ownership`), base64.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub owner_proof: String,
+}
+
+/// Payload on a peer's inbox topic.  `data` is a signed `Direct`
+/// `WireMessage`, encrypted with the key the sender and recipient share
+/// (`RoomKey::direct`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectEnvelope {
+    /// Sender's peer ID — the recipient needs it to derive the key.
+    pub from: String,
+    /// Ciphertext, base64.
+    pub data: String,
+}
+
+/// Payload of `Reaction`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reaction {
+    /// `msg_id` of the chat message reacted to.
+    pub target_msg_id: u64,
+    pub emoji: String,
+}
+
+/// Payload of `Poll`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Poll {
+    pub question: String,
+    pub options: Vec<String>,
+    /// Seconds from the message's timestamp until the poll closes.
+    pub duration_secs: u32,
+}
+
+/// Payload of `Vote`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vote {
+    /// `msg_id` of the poll.
+    pub poll_msg_id: u64,
+    /// Index into its options.
+    pub option: usize,
+}
+
+/// Payload of `Announcement`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Announcement {
+    /// An announcement room: only the owner and `speakers` write chat
+    /// messages and polls; everyone else reads (and reacts and votes).
+    pub on: bool,
+    /// Peer IDs of the members the owner lets write.
+    #[serde(default)]
+    pub speakers: Vec<String>,
+}
+
+/// Payload of `GrantAdmin`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdminGrant {
+    /// Peer ID of the member.
+    pub peer_id: String,
+    /// Whether it is a co-owner from now on.
+    pub admin: bool,
+}
+
+/// Names a line of the shared notes, and orders writes to it: a Lamport
+/// clock, then the writer's random site number to break ties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct NoteId {
+    pub clock: u64,
+    pub site: u64,
+}
+
+/// A line of the shared notes as sent in `Notes`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoteLine {
+    pub id: NoteId,
+    /// The line it was inserted after; `None` for the top.
+    pub after: Option<NoteId>,
+    pub text: String,
+    /// Stamp of the write `text` comes from; the latest one wins.
+    pub edited: NoteId,
+    /// Deleted lines stay, without text, so lines inserted after them
+    /// keep their place.
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+/// A change to the active room's notes, from the frontend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoteEdit {
+    /// A new line after `after`, or at the top.
+    Insert {
+        after: Option<NoteId>,
+        text: String,
+    },
+    /// A new line below the last.
+    Append(String),
+    Set {
+        id: NoteId,
+        text: String,
+    },
+    Delete(NoteId),
+}
+
+/// A member's availability, set with `/away`, `/dnd` and `/back` or by
+/// idling.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    #[default]
+    Online,
+    Away,
+    /// Away, and no notifications.
+    DoNotDisturb,
+}
+
+impl PresenceStatus {
+    /// Shown before the member's name in the roster.
+    pub fn icon(self) -> &'static str {
+        match self {
+            Self::Online => "●",
+            Self::Away => "◐",
+            Self::DoNotDisturb => "⊘",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Online => "online",
+            Self::Away => "away",
+            Self::DoNotDisturb => "do not disturb",
+        }
+    }
+}
+
+/// File format of `/export`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Markdown,
+    Html,
+    Text,
+}
+
+impl ExportFormat {
+    /// The format called `name` in `/export <name>`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "markdown" | "md" => Some(Self::Markdown),
+            "html" | "htm" => Some(Self::Html),
+            "txt" | "text" => Some(Self::Text),
+            _ => None,
+        }
+    }
+
+    /// File name extension of an export in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Html => "html",
+            Self::Text => "txt",
+        }
+    }
+}
+
+/// Payload of `Presence`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Presence {
+    pub status: PresenceStatus,
+    /// Why, e.g. "lunch"; empty if not given.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reason: String,
+}
+
+impl Presence {
+    /// "away: lunch", or just the status.
+    pub fn describe(&self) -> String {
+        match self.reason.as_str() {
+            "" => self.status.label().to_string(),
+            reason => format!("{}: {}", self.status.label(), reason),
+        }
+    }
+}
+
+/// Payload of `Forward`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardBatch {
+    /// Peer ID of the returning member; everyone else ignores the batch.
+    pub to: String,
+    /// The original signed `Chat` messages as encoded for the wire
+    /// (`wire::encode`), base64, oldest first.
+    pub messages: Vec<String>,
+}
+
+/// One past chat message, as carried in `HistoryResponse`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// "Nick#disc"
+    pub sender: String,
+    pub timestamp_ms: i64,
+    pub text: String,
+    /// The thread it was written in; empty for the room itself.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub thread: String,
+}
+
+/// A thread of a room, as listed by `/threads`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadSummary {
+    pub name: String,
+    /// Messages in it that we have seen.
+    pub messages: usize,
+    /// When the newest of them was written.
+    pub last: DateTime<Utc>,
+}
+
+/// A room in the public directory, as its owner lists it (`Listing`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublicRoom {
+    pub name: String,
+    /// What the room is about; may be empty.
+    pub description: String,
+    /// Members when it was listed, the owner included.
+    pub members: usize,
+    /// The owner's room code, to join with.
+    pub code: String,
+}
+
+/// One verified room member, as carried in `RosterAnnounce`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RosterEntry {
+    /// "Nick#disc"
+    pub name: String,
+    /// libp2p peer ID; empty if unknown.
+    pub peer_id: String,
+    /// The member's avatar, as last seen; empty for none.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub avatar: String,
+}
+
+// ── Join flow ─────────────────────────────────────────────────────────────────
+
+/// Progress of a room join, from dialing the creator to the final outcome.
+///
+/// ```text
+/// [Resolving →] Dialing → Searching → AwaitingVerification → Joined
+///                                                        ↘ Denied
+///                                                        ↘ TimedOut
+/// ```
+///
+/// `Resolving` only occurs when joining by room name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinState {
+    /// Looking up members of the room in the DHT.
+    Resolving,
+    /// Connecting to the address embedded in the room code.
+    Dialing,
+    /// Dialing is over; waiting to see a peer on the room topic.
+    Searching,
+    /// A member is present; the password handshake with it is running.
+    AwaitingVerification,
+    /// Password verified — the room is active.
+    Joined,
+    /// A member completed the handshake, and our password did not match.
+    Denied,
+    /// No member answered in time; the room was entered unverified.
+    TimedOut,
+}
+
+impl JoinState {
+    /// `true` once the flow has reached an outcome.
+    pub fn is_terminal(self) -> bool {
+        matches!(self, Self::Joined | Self::Denied | Self::TimedOut)
+    }
+
+    /// Short human-readable description for the progress line.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Resolving => "looking up room in the DHT…",
+            Self::Dialing => "dialing room host…",
+            Self::Searching => "looking for members…",
+            Self::AwaitingVerification => "member found, verifying password…",
+            Self::Joined => "verified",
+            Self::Denied => "access denied",
+            Self::TimedOut => "no verification received, entering unverified",
+        }
+    }
+}
+
+// ── Inter-task channels ───────────────────────────────────────────────────────
+
+/// Events flowing from the network task → application task.
+///
+/// Serializable so the stream can be recorded and replayed (`replay.rs`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NetworkEvent {
+    /// Raw encrypted payload received on a GossipSub topic.
+    MessageReceived {
+        topic: String,
+        /// Peer that published it (absent from older captures).
+        #[serde(default)]
+        source: String,
+        #[serde(with = "base64_bytes")]
+        payload: Vec<u8>,
+    },
+    /// A connection to the given peer was established.
+    PeerConnected(String),
+    /// An outgoing dial attempt failed.
+    DialFailed(String),
+    /// The last connection to the given peer closed.
+    PeerDisconnected(String),
+    /// The last connection to any peer closed: we are offline.
+    ConnectionLost,
+    /// A peer subscribed to one of our GossipSub topics.
+    PeerSubscribed {
+        topic: String,
+        peer_id: String,
+    },
+    ListeningOn(String),
+    NewExternalAddr(String),
+    /// AutoNAT changed its verdict on whether peers can dial us directly.
+    NatStatus(Reachability),
+    /// Result of `FindProviders`: peers that announced `key` (may be empty).
+    ProvidersFound {
+        key: String,
+        peers: Vec<String>,
+    },
+    /// Result of `Discover` from one rendezvous point: peers registered
+    /// under `namespace`, their addresses already known to the swarm (may
+    /// be empty, also when no point could be asked).
+    RendezvousFound {
+        namespace: String,
+        peers: Vec<String>,
+    },
+    /// Result of `QueryPeerInfo`: the asked-for peers we are connected to.
+    PeerInfo(Vec<PeerInfo>),
+    /// A message of room `room` as the app decrypted it (`App::with_capture`),
+    /// at `received_ms`; `owner` is who it took for the room's owner.  Only
+    /// captures hold it: a replay has none of the room keys.
+    RoomPlaintext {
+        room: String,
+        #[serde(default)]
+        owner: String,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+        received_ms: i64,
+    },
+}
+
+/// How we are connected to a peer, for `/peers`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    /// Every connection goes through a relay (no direct one, yet).
+    pub relayed: bool,
+    /// Round-trip time of the last successful ping, if any.
+    pub rtt_ms: Option<u64>,
+    /// Agent version the peer sent in identify; empty until it did.
+    pub agent: String,
+    /// Seconds since the first of the current connections opened.
+    pub connected_secs: u64,
+}
+
+impl PeerInfo {
+    /// "direct, 42 ms, rust-libp2p/0.56, connected 5m 3s".
+    pub fn describe(&self) -> String {
+        let mut parts = vec![if self.relayed { "relayed" } else { "direct" }.to_string()];
+        match self.rtt_ms {
+            Some(ms) => parts.push(format!("{ms} ms")),
+            None => parts.push("no ping yet".to_string()),
+        }
+        if !self.agent.is_empty() {
+            parts.push(self.agent.clone());
+        }
+        let secs = self.connected_secs;
+        parts.push(match secs {
+            0..60 => format!("connected {secs}s"),
+            60..3600 => format!("connected {}m {}s", secs / 60, secs % 60),
+            _ => format!("connected {}h {}m", secs / 3600, secs % 3600 / 60),
+        });
+        parts.join(", ")
+    }
+}
+
+/// Whether other peers can dial us directly, as probed by AutoNAT.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum Reachability {
+    /// Not probed yet, or too few peers to tell.
+    #[default]
+    Unknown,
+    /// Dial-backs succeeded on this address.
+    Public(String),
+    /// Dial-backs failed: behind NAT or a firewall.
+    Private,
+}
+
+/// Commands flowing from the application task → network task.
+#[derive(Debug)]
+pub enum NetworkCommand {
+    Subscribe(String),
+    Unsubscribe(String),
+    Publish {
+        topic: String,
+        data: Vec<u8>,
+    },
+    Dial(String),
+    /// Dial a peer by ID, using whatever addresses the DHT knows for it.
+    DialPeer(String),
+    /// Announce ourselves in the DHT as a provider of `key`.
+    StartProviding(String),
+    StopProviding(String),
+    /// Look up providers of `key`; answered with `ProvidersFound`.
+    FindProviders(String),
+    /// Keep ourselves registered under a namespace at the rendezvous points.
+    Register(String),
+    Unregister(String),
+    /// Ask the rendezvous points who is registered under a namespace;
+    /// answered with `RendezvousFound`.
+    Discover(String),
+    /// Stop receiving and forwarding GossipSub messages from a peer.
+    BlockPeer(String),
+    /// Give a flooding peer a GossipSub score low enough to be graylisted.
+    GraylistPeer(String),
+    QueryListenAddrs,
+    /// Report how we are connected to these peers; answered with `PeerInfo`.
+    QueryPeerInfo(Vec<String>),
+    /// Let what is already queued (our `Leave`s) go out, then stop `run()`.
+    Shutdown,
+}
+
+/// Events published by the application task on the `EventBus`.
+#[derive(Debug, Clone)]
+pub enum AppEvent {
+    /// Something the terminal UI should render.
+    Ui(UiEvent),
+    /// A room was entered (created, or joined after verification).
+    RoomEntered(String),
+    /// A joined room was left.
+    RoomLeft(String),
+    /// A message that belongs to `room`'s transcript.
+    RoomMessage { room: String, msg: DisplayMessage },
+    /// `msg` in `room` deserves a desktop notification (`notify.rs`).
+    Notify { room: String, msg: DisplayMessage },
+    /// The config file was read again (`/reload`): the settings the other
+    /// tasks apply while running.
+    SettingsChanged {
+        theme: ThemeConfig,
+        format_messages: bool,
+        log_dir: String,
+        log_format: LogFormat,
+    },
+}
+
+/// UI-facing events, carried on the bus inside `AppEvent::Ui`.
+#[derive(Debug, Clone)]
+pub enum UiEvent {
+    NewMessage(DisplayMessage),
+    /// Update the header and sidebar. `rooms` lists every joined room in
+    /// switch order (Alt+1…9); `members` is the active room's roster, us
+    /// first.
+    StatusUpdate {
+        room: Option<String>,
+        /// Subject of the active room (`/topic`); empty if none.
+        subject: String,
+        /// Thread of the active room being shown (`/thread`); `None` for
+        /// the room itself.
+        thread: Option<String>,
+        peers: usize,
+        rooms: Vec<String>,
+        members: Vec<String>,
+    },
+    /// Navigate to the main menu.
+    ShowMainMenu,
+    /// Room was created — show the code to share.
+    RoomCreated {
+        name: String,
+        code: String,
+    },
+    /// The code of room `name`, asked for with `/code`.
+    RoomCode {
+        name: String,
+        code: String,
+    },
+    /// Successfully joined a room.
+    RoomJoined(String),
+    /// Joined room `from` goes by `to` from now on (`/rename`).
+    RoomRenamed {
+        from: String,
+        to: String,
+    },
+    /// Show room `name` with `history` as its transcript — after a switch,
+    /// or when history sync filled in older messages.
+    RoomSwitched {
+        name: String,
+        history: Vec<DisplayMessage>,
+    },
+    /// Ring the terminal bell (a mention, or a message while unfocused).
+    Bell,
+    /// A message we sent to `room` was acknowledged by a recipient.
+    MessageDelivered {
+        room: String,
+        msg_id: u64,
+    },
+    /// The reactions to message `msg_id` in `room` changed.
+    Reactions {
+        room: String,
+        msg_id: u64,
+        reactions: Vec<(String, usize)>,
+    },
+    /// The options or state of poll `msg_id` in `room` changed.
+    PollTally {
+        room: String,
+        msg_id: u64,
+        poll: PollTally,
+    },
+    /// The shared notes of `room` changed, or were asked for (`ShowNotes`):
+    /// every line, top to bottom.
+    Notes {
+        room: String,
+        lines: Vec<(NoteId, String)>,
+    },
+    /// The threads of `room` changed, or were asked for (`ShowThreads`):
+    /// every one we know, most recently active first.
+    Threads {
+        room: String,
+        threads: Vec<ThreadSummary>,
+    },
+    /// The join flow for `room` moved to a new state.
+    JoinProgress {
+        room: String,
+        state: JoinState,
+    },
+    /// Wrong password, or the members would not check it: why, then.
+    AccessDenied(Option<String>),
+    /// A join was turned away: room `room` already holds `max_members`.
+    RoomFull {
+        room: String,
+        max_members: u32,
+    },
+    /// Nickname was changed successfully.
+    NicknameChanged(String),
+    /// Avatar was set (`Some`) or removed; also sent at start if we have
+    /// one.
+    AvatarChanged(Option<String>),
+    Error(String),
+    /// Every command the app takes, plugins' included, as (usage, what it
+    /// does); sent once at start, for help screens and command pickers.
+    Commands(Vec<(String, String)>),
+    /// Show the help (`/help`, or a command nobody understood).
+    ShowHelp,
+    /// The recent rooms list changed (sent at start too), newest first.
+    RecentRooms(Vec<RecentRoom>),
+    /// The public rooms found in the directory, most members first; sent
+    /// on `BrowseRooms` and whenever it changes after.
+    PublicRooms(Vec<PublicRoom>),
+}
+
+/// Commands flowing from the CLI task → application task.
+#[derive(Debug)]
+pub enum CliCommand {
+    SendMessage(String),
+    /// `persona` in these three: who to be in the room, remembered for
+    /// next time; `None` is whoever we were there last (`[personas]`).
+    /// Create a room; `max_members` limits it, the owner included (0: no
+    /// limit).
+    CreateRoom {
+        name: String,
+        password: String,
+        persona: Option<Persona>,
+        max_members: u32,
+    },
+    JoinRoom {
+        code: String,
+        password: String,
+        persona: Option<Persona>,
+    },
+    /// Join by room name, finding members through the DHT.
+    JoinRoomByName {
+        name: String,
+        password: String,
+        persona: Option<Persona>,
+    },
+    LeaveRoom,
+    /// Make another joined room the active one.
+    SwitchRoom(String),
+    ListRooms,
+    ListPeers,
+    /// List recent messages that mentioned us, in any room.
+    ListMentions,
+    /// List the links posted lately in the active room, numbered.
+    ListLinks,
+    /// Private message to one member of the active room (`nick` or
+    /// `nick#disc`).
+    DirectMessage {
+        to: String,
+        text: String,
+    },
+    /// Show the safety number shared with a member of the active room;
+    /// `mark` `Some(true)` / `Some(false)` confirms / revokes it as verified.
+    Verify {
+        who: String,
+        mark: Option<bool>,
+    },
+    /// Pin the identity key of a member of the active room in the contacts
+    /// without comparing safety numbers.
+    Trust(String),
+    /// Show a member of the active room as `alias` from now on, or by its
+    /// own nickname again with `None`.
+    Alias {
+        who: String,
+        alias: Option<String>,
+    },
+    /// List the contacts.
+    ListContacts,
+    /// Drop everything a member of the active room says, from now on.
+    Block(String),
+    /// Lift a block, by name or peer ID.
+    Unblock(String),
+    /// List the blocked peers.
+    ListBlocked,
+    /// React with `emoji` to the latest message in the active room from
+    /// `to` (a member, as for `DirectMessage`), or from anyone but us.
+    React {
+        to: Option<String>,
+        emoji: String,
+    },
+    /// Send `text` to the active room as a reply to the message with wire
+    /// ID `to`.
+    Reply {
+        to: u64,
+        text: String,
+    },
+    /// Ask the active room `question`, open for `minutes` (default 10).
+    Poll {
+        question: String,
+        options: Vec<String>,
+        minutes: Option<u32>,
+    },
+    /// Vote for option `n` (from 1) of the latest open poll in the active
+    /// room.
+    Vote(usize),
+    /// Close our latest open poll in the active room.
+    EndPoll,
+    /// Send the active room's notes as `UiEvent::Notes`.
+    ShowNotes,
+    /// Change the active room's notes, for every member.
+    EditNote(NoteEdit),
+    /// Show thread `name` of the active room, and send there from now on;
+    /// `None` goes back to the room itself.
+    OpenThread(Option<String>),
+    /// Send the active room's threads as `UiEvent::Threads`.
+    ShowThreads,
+    /// Announce our availability in every room.
+    SetPresence(Presence),
+    /// No key was pressed for a while (`true`), or one was again.  Sets us
+    /// away and back unless the user chose a status.
+    SetIdle(bool),
+    /// Show the last lines (default 20) of the active room's log file, for
+    /// context from earlier sessions.
+    History(Option<usize>),
+    /// Write the active room's history to a file in the log directory.
+    Export(ExportFormat),
+    /// Show the active room's subject, or (owner or co-owner) set it.
+    Topic(Option<String>),
+    /// Show the active room's slow mode, or (owner or co-owner) set it:
+    /// seconds members wait between messages, 0 for off.
+    SlowMode(Option<u32>),
+    /// Show whether the active room is an announcement room and who may
+    /// write in it, or (owner or co-owner) turn it on or off.
+    Announcement(Option<bool>),
+    /// Owner or co-owner: let a member write in the announcement room
+    /// (`allow`), or no longer.
+    Speaker {
+        who: String,
+        allow: bool,
+    },
+    /// Owner only: show a new invite token to the active room, good for
+    /// `hours` and, if `single_use`, for one joiner.
+    MintInvite {
+        hours: u32,
+        single_use: bool,
+    },
+    /// Show whether the active room takes joiners with an invite only, or
+    /// (owner only) set it.
+    InviteOnly(Option<bool>),
+    /// Show the active room's member limit, or (owner only) set it; 0
+    /// lifts it.
+    Capacity(Option<u32>),
+    /// Owner only: list the active room in the public directory with a
+    /// description (`Some`), or take it off (`None`).
+    ListPublic(Option<String>),
+    /// Look for public rooms in the directory.
+    BrowseRooms,
+    /// Owner or co-owner: remove a member from the active room.
+    Kick(String),
+    /// Owner or co-owner: remove a member and ignore it from now on.
+    Ban(String),
+    /// Owner only: make a member of the active room a co-owner (`admin`),
+    /// or no longer.
+    GrantAdmin {
+        who: String,
+        admin: bool,
+    },
+    /// Owner only: hand the active room to a member, staying a co-owner.
+    TransferOwnership(String),
+    /// Show a member's role in the active room, its peer ID and our alias
+    /// for it.
+    Whois(String),
+    /// Owner only: move the active room to a fresh key.
+    Rekey,
+    /// Owner only: give the active room a new name.
+    RenameRoom(String),
+    /// Show a fresh code to the active room that leads to us at the
+    /// addresses we listen on and are relayed at now, naming its owner.
+    Invite,
+    /// Show the active room's code again.
+    ShowCode,
+    /// Drop a room from the recent rooms list.
+    ForgetRecent(String),
+    /// Keep the active room's code and password in the vault.
+    SaveRoom,
+    /// Enter a room saved with `SaveRoom` again; `None` lists them.
+    Rejoin(Option<String>),
+    /// Drop a saved room from the vault.
+    ForgetRoom(String),
+    ChangeNickname(String),
+    /// Set our avatar: one emoji or other character; empty removes it.
+    ChangeAvatar(String),
+    /// Mute or unmute notifications for the active room.
+    ToggleMute,
+    /// Read the config file again and apply what can change while running.
+    ReloadConfig,
+    /// The terminal gained (`true`) or lost focus.
+    SetFocus(bool),
+    /// A `/name args` command that is not built in, for the plugins.
+    Plugin {
+        name: String,
+        args: String,
+    },
+    Help,
+    Quit,
+}
+
+/// Serde adapter storing byte payloads as base64 strings in `--record` captures.
+pub mod base64_bytes {
+    use base64::{Engine, engine::general_purpose::STANDARD as B64};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&B64.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(d)?;
+        B64.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_info_reads_as_one_line() {
+        let mut info = PeerInfo {
+            agent: "rust-libp2p/0.56".into(),
+            ..PeerInfo::default()
+        };
+        assert_eq!(
+            info.describe(),
+            "direct, no ping yet, rust-libp2p/0.56, connected 0s"
+        );
+        info = PeerInfo {
+            relayed: true,
+            rtt_ms: Some(42),
+            connected_secs: 303,
+            ..info
+        };
+        assert_eq!(
+            info.describe(),
+            "relayed, 42 ms, rust-libp2p/0.56, connected 5m 3s"
+        );
+        info.connected_secs = 7380;
+        info.agent.clear();
+        assert_eq!(info.describe(), "relayed, 42 ms, connected 2h 3m");
+    }
+
+    #[test]
+    fn long_message_wraps_under_the_text() {
+        let mut msg = DisplayMessage::system("one two three four five");
+        msg.timestamp = DateTime::from_timestamp(0, 0).unwrap();
+        // "[00:00] *** " is 12 wide.
+        assert_eq!(
+            msg.render_lines(24),
+            vec![
+                "[00:00] *** one two",
+                "            three four",
+                "            five"
+            ]
+        );
+        assert!(msg.render_lines(8).iter().all(|l| l.width() <= 8));
+        let all: String = msg.render_lines(8).concat().split_whitespace().collect();
+        assert_eq!(all, "[00:00]***onetwothreefourfive");
+    }
+
+    #[test]
+    fn line_breaks_and_tabs_keep_a_snippet_indented() {
+        let mut msg = DisplayMessage::system("fn main() {\n\tok();\n}");
+        msg.timestamp = DateTime::from_timestamp(0, 0).unwrap();
+        assert_eq!(
+            msg.render_lines(40),
+            vec![
+                "[00:00] *** fn main() {",
+                "                ok();",
+                "            }"
+            ]
+        );
+    }
+
+    #[test]
+    fn wide_glyphs_wrap_by_cells() {
+        let mut msg = DisplayMessage::system("你好世界 🎉🎉🎉");
+        msg.timestamp = DateTime::from_timestamp(0, 0).unwrap();
+        assert_eq!(
+            msg.render_lines(24),
+            vec!["[00:00] *** 你好世界", "            🎉🎉🎉"]
+        );
+        // A two-cell glyph on a one-cell line cannot be split further.
+        for width in [1, 4, 7] {
+            let lines = msg.render_lines(width);
+            assert!(lines.iter().all(|l| l.width() <= width.max(2)), "{lines:?}");
+            let all: String = lines.concat().split_whitespace().collect();
+            assert_eq!(all, "[00:00]***你好世界🎉🎉🎉");
+        }
+    }
+
+    #[test]
+    fn a_poll_lists_its_options_with_their_votes() {
+        let mut poll = PollTally {
+            options: vec![("Pizza".to_string(), 2), ("Sushi".to_string(), 0)],
+            closes_at: DateTime::from_timestamp(600, 0).unwrap(),
+            closed: false,
+        };
+        let mut msg = DisplayMessage::chat("amy#3c4d", "Lunch?").with_poll(poll.clone());
+        msg.timestamp = DateTime::from_timestamp(0, 0).unwrap();
+        // "[00:00] amy#3c4d: " is 18 wide.
+        let pad = " ".repeat(18);
+        assert_eq!(
+            msg.render_lines(60),
+            vec![
+                "[00:00] amy#3c4d: Lunch?".to_string(),
+                format!("{pad}1) Pizza  ██ 2"),
+                format!("{pad}2) Sushi   0"),
+                format!("{pad}/vote 1…2 until 00:10"),
+            ]
+        );
+        poll.closed = true;
+        msg.poll = Some(poll);
+        assert_eq!(msg.render_lines(60)[3], format!("{pad}poll closed"));
+    }
+
+    #[test]
+    fn reply_quotes_the_original_on_one_line() {
+        let original = DisplayMessage::chat("bob#1a2b", &"word ".repeat(30));
+        let quote = Quote::of(&original);
+        assert_eq!(quote.text.chars().count(), MAX_QUOTE_CHARS + 1);
+        assert!(quote.text.ends_with('…'));
+
+        let mut reply = DisplayMessage::chat("amy#3c4d", "agreed").with_quote(quote);
+        reply.timestamp = DateTime::from_timestamp(0, 0).unwrap();
+        let lines = reply.render_lines(40);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("        ╭ bob#1a2b: word word"));
+        assert_eq!(lines[0].width(), 40);
+        assert_eq!(lines[1], "[00:00] amy#3c4d: agreed");
+    }
+}
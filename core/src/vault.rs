@@ -0,0 +1,271 @@
+//! Room credentials saved with `/save`, so `/rejoin <room>` needs neither
+//! the code nor the password again.
+//!
+//! Each entry is sealed with [`RoomKey::vault`], a key derived from our
+//! identity key and, once `chat vault passphrase` has set one, a passphrase
+//! stretched with Argon2.  Without a passphrase whoever holds the identity
+//! key (in `~/.chatrc`) can open the file; with one they also need the
+//! passphrase.  The file is TOML keyed by room name, written mode 600;
+//! names are not secret, they are in every room code.
+
+use std::{borrow::Cow, collections::BTreeMap, path::PathBuf};
+
+use anyhow::{Context, Result, bail};
+use base64::{Engine, engine::general_purpose::STANDARD as B64};
+use libp2p::identity::Keypair;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use zeroize::Zeroizing;
+
+use crate::{
+    config::write_private,
+    crypto::{RoomKey, RoomSalt, random_salt},
+};
+
+/// Plaintext of `Vault::check`, to tell a wrong passphrase from a corrupt
+/// entry.
+const CHECK_PLAINTEXT: &[u8] = b"chatapp-vault-check";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Vault {
+    /// Base64 Argon2 salt of the passphrase; empty when there is none.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    passphrase_salt: String,
+    /// Base64 of `CHECK_PLAINTEXT` sealed with the passphrase key.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    check: String,
+    /// Room name → its sealed credentials.
+    #[serde(default)]
+    rooms: BTreeMap<String, Sealed>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Sealed {
+    /// Base64 of the encrypted JSON `{code, password}`.
+    sealed: String,
+}
+
+/// What it takes to enter a room again.
+pub struct Credentials {
+    /// Room code to join with; empty to join by name through the DHT.
+    pub code: String,
+    pub password: Zeroizing<String>,
+}
+
+/// The plaintext inside a `Sealed` entry.
+#[derive(Serialize, Deserialize)]
+struct Plain<'a> {
+    #[serde(borrow)]
+    code: Cow<'a, str>,
+    #[serde(borrow)]
+    password: Cow<'a, str>,
+}
+
+impl Vault {
+    /// Load from `path`; a missing file is an empty vault, a corrupt one is
+    /// logged and treated as empty (it is overwritten on the next change).
+    pub fn load(path: PathBuf) -> Self {
+        let vault = match std::fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable room vault {}: {e}", path.display());
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        Self { path, ..vault }
+    }
+
+    /// Persist to the file the vault was loaded from, readable only by us.
+    pub fn save(&self) -> Result<()> {
+        write_private(&self.path, &toml::to_string_pretty(self)?)
+    }
+
+    /// Whether opening the vault takes a passphrase.
+    pub fn has_passphrase(&self) -> bool {
+        !self.passphrase_salt.is_empty()
+    }
+
+    /// The key the entries are sealed with.  Fails if the vault has a
+    /// passphrase and `passphrase` is missing or wrong.
+    pub fn key(&self, own: &Keypair, passphrase: Option<&str>) -> Result<RoomKey> {
+        if !self.has_passphrase() {
+            return RoomKey::vault(own, None);
+        }
+        let Some(passphrase) = passphrase else {
+            bail!("The room vault is locked: restart chat and enter its passphrase");
+        };
+        let salt: RoomSalt = B64
+            .decode(&self.passphrase_salt)
+            .ok()
+            .and_then(|salt| salt.try_into().ok())
+            .context("room vault salt is corrupt")?;
+        let key = RoomKey::vault(own, Some((passphrase, &salt)))?;
+        let check = B64
+            .decode(&self.check)
+            .context("room vault check is corrupt")?;
+        match key.decrypt(&check) {
+            Ok(plain) if plain == CHECK_PLAINTEXT => Ok(key),
+            _ => bail!("Wrong passphrase for the room vault"),
+        }
+    }
+
+    /// Re-seal every entry under `passphrase`, or under the identity key
+    /// alone when it is `None`, and save.  `old` is the current key, from
+    /// [`Vault::key`].
+    pub fn set_passphrase(
+        &mut self,
+        own: &Keypair,
+        old: &RoomKey,
+        passphrase: Option<&str>,
+    ) -> Result<()> {
+        let mut opened = Vec::new();
+        for room in self.rooms.keys() {
+            if let Some(credentials) = self.open(old, room)? {
+                opened.push((room.clone(), credentials));
+            }
+        }
+        let key = match passphrase {
+            Some(passphrase) => {
+                let salt = random_salt();
+                let key = RoomKey::vault(own, Some((passphrase, &salt)))?;
+                self.passphrase_salt = B64.encode(salt);
+                self.check = B64.encode(key.encrypt(CHECK_PLAINTEXT)?);
+                key
+            }
+            None => {
+                self.passphrase_salt.clear();
+                self.check.clear();
+                RoomKey::vault(own, None)?
+            }
+        };
+        for (room, credentials) in &opened {
+            self.seal(&key, room, credentials)?;
+        }
+        self.save()
+    }
+
+    /// Names of the saved rooms, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.rooms.keys()
+    }
+
+    /// Seal `credentials` for `room` with `key`, replacing any saved
+    /// before, and save.
+    pub fn store(&mut self, key: &RoomKey, room: &str, credentials: &Credentials) -> Result<()> {
+        self.seal(key, room, credentials)?;
+        self.save()
+    }
+
+    fn seal(&mut self, key: &RoomKey, room: &str, credentials: &Credentials) -> Result<()> {
+        let plain = Zeroizing::new(serde_json::to_vec(&Plain {
+            code: Cow::Borrowed(&credentials.code),
+            password: Cow::Borrowed(&credentials.password),
+        })?);
+        let sealed = B64.encode(key.encrypt(&plain)?);
+        self.rooms.insert(room.to_string(), Sealed { sealed });
+        Ok(())
+    }
+
+    /// The credentials saved for `room`, if any.  Fails if they were sealed
+    /// with another identity key or the file was tampered with.
+    pub fn open(&self, key: &RoomKey, room: &str) -> Result<Option<Credentials>> {
+        let Some(entry) = self.rooms.get(room) else {
+            return Ok(None);
+        };
+        let sealed = B64
+            .decode(&entry.sealed)
+            .context("saved room is not base64")?;
+        let plain = Zeroizing::new(key.decrypt(&sealed)?);
+        let Plain { code, password } =
+            serde_json::from_slice(&plain).context("saved room is corrupt")?;
+        Ok(Some(Credentials {
+            code: code.into_owned(),
+            password: Zeroizing::new(password.into_owned()),
+        }))
+    }
+
+    /// Drop what was saved for `room` and save.  `false` if nothing was.
+    pub fn forget(&mut self, room: &str) -> Result<bool> {
+        if self.rooms.remove(room).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_rooms_survive_a_reload_and_only_open_with_our_key() {
+        let path = std::env::temp_dir().join(format!("chat-vault-{}.toml", std::process::id()));
+        let key = RoomKey::vault(&Keypair::generate_ed25519(), None).unwrap();
+        let mut vault = Vault::load(path.clone());
+        let credentials = Credentials {
+            code: "chat2:abc".to_string(),
+            password: Zeroizing::new("hunter2".to_string()),
+        };
+        vault.store(&key, "lobby", &credentials).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("hunter2"));
+
+        let reloaded = Vault::load(path.clone());
+        assert_eq!(reloaded.names().collect::<Vec<_>>(), ["lobby"]);
+        let opened = reloaded.open(&key, "lobby").unwrap().unwrap();
+        assert_eq!(
+            (opened.code.as_str(), opened.password.as_str()),
+            ("chat2:abc", "hunter2")
+        );
+        assert!(reloaded.open(&key, "elsewhere").unwrap().is_none());
+        let other = RoomKey::vault(&Keypair::generate_ed25519(), None).unwrap();
+        assert!(reloaded.open(&other, "lobby").is_err());
+
+        assert!(vault.forget("lobby").unwrap());
+        assert!(!vault.forget("lobby").unwrap());
+        assert_eq!(Vault::load(path.clone()).names().count(), 0);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn a_passphrase_reseals_the_entries_and_is_needed_to_open_them() {
+        let path =
+            std::env::temp_dir().join(format!("chat-vault-pass-{}.toml", std::process::id()));
+        let own = Keypair::generate_ed25519();
+        let mut vault = Vault::load(path.clone());
+        let plain = vault.key(&own, None).unwrap();
+        let credentials = Credentials {
+            code: "chat2:abc".to_string(),
+            password: Zeroizing::new("hunter2".to_string()),
+        };
+        vault.store(&plain, "lobby", &credentials).unwrap();
+        vault
+            .set_passphrase(&own, &plain, Some("correct horse"))
+            .unwrap();
+
+        let reloaded = Vault::load(path.clone());
+        assert!(reloaded.has_passphrase());
+        assert!(reloaded.key(&own, None).is_err());
+        assert!(reloaded.key(&own, Some("wrong")).is_err());
+        assert!(reloaded.open(&plain, "lobby").is_err());
+        let key = reloaded.key(&own, Some("correct horse")).unwrap();
+        let opened = reloaded.open(&key, "lobby").unwrap().unwrap();
+        assert_eq!(opened.password.as_str(), "hunter2");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut reloaded = reloaded;
+        reloaded.set_passphrase(&own, &key, None).unwrap();
+        let cleared = Vault::load(path.clone());
+        assert!(!cleared.has_passphrase());
+        let key = cleared.key(&own, None).unwrap();
+        assert!(cleared.open(&key, "lobby").unwrap().is_some());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,1515 @@
+//! Strict decoding of decrypted wire payloads.
+//!
+//! Anything arriving from the room is untrusted — even after AES-GCM, a
+//! member who knows the password can send arbitrary bytes.  All inbound
+//! plaintext goes through `decode`, which bounds sizes before allocating and
+//! validates every field before the message reaches the rest of the app.
+//!
+//! Plaintext format: one version byte (`WIRE_VERSION`) followed by the
+//! `WireMessage` as CBOR.  Version 1 was bare JSON; it is still accepted
+//! (recognised by its leading `{`) while older peers are around.  A leading
+//! `WIRE_ZSTD` byte instead marks a zstd-compressed version-2 payload, sent
+//! in place of long ones (`compress`).
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::Read,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use base64::{Engine, engine::general_purpose::STANDARD as B64};
+use libp2p::{
+    PeerId,
+    identity::{Keypair, PublicKey},
+};
+use ruzstd::{
+    decoding::StreamingDecoder,
+    encoding::{CompressionLevel, compress_to_vec},
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    identity::discriminator,
+    room::check_room_name,
+    types::{
+        AdminGrant, Announcement, ForwardBatch, HistoryEntry, NoteLine, Poll, Presence, PublicRoom,
+        Reaction, RoomRekey, RosterEntry, Vote, WireMessage, WireMessageType,
+    },
+};
+
+/// Largest decrypted payload we are willing to parse.
+pub const MAX_PAYLOAD_LEN: usize = 64 * 1024;
+/// Largest encrypted payload (payload + nonce + GCM tag).
+pub const MAX_CIPHERTEXT_LEN: usize = MAX_PAYLOAD_LEN + 12 + 16;
+/// Maximum nickname length in characters.
+pub const MAX_NICK_LEN: usize = 32;
+/// Longest avatar in bytes: room for an emoji joined from several.
+pub const MAX_AVATAR_LEN: usize = 32;
+/// Maximum message text length in bytes.
+pub const MAX_TEXT_LEN: usize = 16 * 1024;
+/// Longest chat message we send or reassemble, in bytes.
+pub const MAX_CHAT_LEN: usize = 64 * 1024;
+/// Text bytes per part of a chat message sent in chunks: small enough that
+/// a part, in base64, still fits a `Forward` batch.
+pub const CHUNK_LEN: usize = 8 * 1024;
+/// Most parts one chat message may have.
+pub const MAX_CHUNKS: u16 = 16;
+/// Chat messages being reassembled at once per room.
+const MAX_PARTIAL: usize = 16;
+/// A message whose parts have not all arrived by then is given up.
+pub const CHUNK_TIMEOUT_MS: i64 = 60 * 1000;
+/// Discriminators are this many lowercase hex digits or more (see
+/// `identity::discriminator`)…
+pub const MIN_DISC_LEN: usize = 4;
+/// …and at most this many.
+pub const MAX_DISC_LEN: usize = 16;
+/// Most messages served in one `HistoryResponse`.
+pub const MAX_HISTORY_SYNC: usize = 50;
+/// Most messages buffered for one absent member (and carried back to it).
+pub const MAX_FORWARD: usize = 50;
+/// Absent members buffered for per room.
+pub const MAX_ABSENT: usize = 16;
+/// Forwarded messages older than this are not shown.
+pub const FORWARD_MAX_AGE_MS: i64 = 24 * 60 * 60 * 1000;
+/// Maximum room subject (`/topic`) length in characters.
+pub const MAX_SUBJECT_LEN: usize = 200;
+/// Maximum reaction length in bytes — room for emoji ZWJ sequences.
+pub const MAX_EMOJI_LEN: usize = 32;
+/// Maximum reason of a `Presence` (`/away lunch`) in characters.
+pub const MAX_PRESENCE_REASON_LEN: usize = 80;
+/// Maximum thread name (`/thread`) length in characters.
+pub const MAX_THREAD_LEN: usize = 32;
+/// Maximum description of a public room (`/public`) in characters.
+pub const MAX_DESCRIPTION_LEN: usize = 120;
+/// Version byte leading every payload we send.
+pub const WIRE_VERSION: u8 = 2;
+/// Leading byte of a compressed payload: the rest is a zstd frame holding a
+/// whole version-2 payload, version byte included.
+pub const WIRE_ZSTD: u8 = 3;
+/// Payloads shorter than this are always sent as they are.
+pub const COMPRESS_THRESHOLD: usize = 1024;
+
+/// Serialize `wire` for encryption: version byte + CBOR.
+pub fn encode(wire: &WireMessage) -> Result<Vec<u8>> {
+    let mut out = vec![WIRE_VERSION];
+    ciborium::into_writer(wire, &mut out).context("encode wire message")?;
+    Ok(out)
+}
+
+/// Compress an encoded payload of at least `COMPRESS_THRESHOLD` bytes,
+/// if that makes it smaller.  Peers from before compression cannot read the
+/// result, hence the `compress_messages` setting.
+pub fn compress(payload: Vec<u8>) -> Vec<u8> {
+    if payload.len() < COMPRESS_THRESHOLD {
+        return payload;
+    }
+    let mut packed = vec![WIRE_ZSTD];
+    packed.extend(compress_to_vec(
+        payload.as_slice(),
+        CompressionLevel::Fastest,
+    ));
+    if packed.len() < payload.len() {
+        packed
+    } else {
+        payload
+    }
+}
+
+/// Unpack a `WIRE_ZSTD` payload.  The output is bounded like any payload,
+/// so a small frame cannot inflate into an unbounded allocation.
+fn decompress(packed: &[u8]) -> Result<Vec<u8>> {
+    let decoder = StreamingDecoder::new(packed).map_err(|e| anyhow!("zstd frame: {e}"))?;
+    let mut payload = Vec::new();
+    decoder
+        .take(MAX_PAYLOAD_LEN as u64 + 1)
+        .read_to_end(&mut payload)
+        .context("decompress payload")?;
+    ensure!(
+        payload.len() <= MAX_PAYLOAD_LEN,
+        "decompressed payload too large"
+    );
+    ensure!(
+        payload.first() == Some(&WIRE_VERSION),
+        "compressed payload is not version {WIRE_VERSION}"
+    );
+    Ok(payload)
+}
+
+/// Parse and validate a decrypted payload.
+pub fn decode(plaintext: &[u8]) -> Result<WireMessage> {
+    ensure!(
+        plaintext.len() <= MAX_PAYLOAD_LEN,
+        "payload too large ({} bytes)",
+        plaintext.len()
+    );
+    let unpacked;
+    let plaintext = match plaintext.split_first() {
+        Some((&WIRE_ZSTD, packed)) => {
+            unpacked = decompress(packed)?;
+            unpacked.as_slice()
+        }
+        _ => plaintext,
+    };
+    let mut wire: WireMessage = match plaintext.split_first() {
+        Some((&WIRE_VERSION, body)) => ciborium::from_reader(body)?,
+        // Version 1: plain JSON object, no version byte.
+        Some((b'{', _)) => serde_json::from_slice(plaintext)?,
+        Some((version, _)) => bail!("unsupported wire version {version}"),
+        None => bail!("empty payload"),
+    };
+
+    validate_nickname(&wire.sender_nick)?;
+    validate_discriminator(&wire.sender_disc)?;
+    ensure!(wire.text.len() <= MAX_TEXT_LEN, "text too long");
+    if let Some(chunk) = wire.chunk {
+        ensure!(
+            wire.msg_type == WireMessageType::Chat,
+            "chunk of a non-chat message"
+        );
+        ensure!(
+            (2..=MAX_CHUNKS).contains(&chunk.total) && chunk.index < chunk.total,
+            "bad chunk {}/{}",
+            chunk.index,
+            chunk.total
+        );
+    }
+    if !wire.thread.is_empty() {
+        ensure!(
+            wire.msg_type == WireMessageType::Chat,
+            "thread of a non-chat message"
+        );
+        validate_thread(&wire.thread)?;
+    }
+    // Only what is shown as a message may break lines; a topic, say, is
+    // shown on one.
+    wire.text = match wire.msg_type {
+        WireMessageType::Chat | WireMessageType::Direct => sanitize_text(&wire.text),
+        _ => sanitize_line(&wire.text),
+    };
+    Ok(wire)
+}
+
+/// A nickname must be non-empty, at most `MAX_NICK_LEN` characters and free
+/// of control characters (which could rewrite the terminal).
+pub fn validate_nickname(nick: &str) -> Result<()> {
+    if nick.trim().is_empty() {
+        bail!("empty nickname");
+    }
+    ensure!(nick.chars().count() <= MAX_NICK_LEN, "nickname too long");
+    ensure!(
+        !nick.chars().any(char::is_control),
+        "control character in nickname"
+    );
+    Ok(())
+}
+
+/// A thread name is one word: non-empty, at most `MAX_THREAD_LEN`
+/// characters, without spaces or control characters.
+pub fn validate_thread(name: &str) -> Result<()> {
+    ensure!(!name.is_empty(), "empty thread name");
+    ensure!(
+        name.chars().count() <= MAX_THREAD_LEN,
+        "thread name too long"
+    );
+    ensure!(
+        !name.chars().any(|c| c.is_control() || c.is_whitespace()),
+        "a thread name is one word"
+    );
+    Ok(())
+}
+
+/// An avatar is one emoji or other character as the user sees it — one
+/// grapheme cluster, so flags and joined emoji count — at most
+/// `MAX_AVATAR_LEN` bytes, and visible: not blank, of zero width or a
+/// control character.
+pub fn validate_avatar(avatar: &str) -> Result<()> {
+    ensure!(
+        avatar.graphemes(true).count() == 1,
+        "an avatar is one emoji or character"
+    );
+    ensure!(avatar.len() <= MAX_AVATAR_LEN, "avatar too long");
+    ensure!(
+        avatar.width() > 0 && !avatar.chars().any(|c| c.is_control() || c.is_whitespace()),
+        "avatar not visible"
+    );
+    Ok(())
+}
+
+/// Domain separator so a message signature cannot be replayed as anything else.
+const SIGNING_DOMAIN: &str = "chatapp-wire-v1";
+
+/// Bytes covered by a message signature: every field except the key and
+/// signature themselves.
+fn signing_bytes(wire: &WireMessage) -> Result<Vec<u8>> {
+    let fields = (
+        SIGNING_DOMAIN,
+        &wire.msg_type,
+        &wire.sender_nick,
+        &wire.sender_disc,
+        wire.timestamp_ms,
+        wire.msg_id,
+        &wire.text,
+    );
+    // Only replies sign `reply_to` and only parts their `chunk`, so
+    // everything else verifies as it did before the fields existed.
+    let mut bytes = match (wire.reply_to, wire.chunk) {
+        (0, None) => serde_json::to_vec(&fields)?,
+        (reply_to, None) => serde_json::to_vec(&(fields, reply_to))?,
+        (reply_to, Some(chunk)) => serde_json::to_vec(&(fields, reply_to, chunk))?,
+    };
+    // Likewise an avatar, after the rest, and a thread after that (in
+    // brackets, so it cannot pass for an avatar).
+    if !wire.avatar.is_empty() {
+        bytes.extend(serde_json::to_vec(&wire.avatar)?);
+    }
+    if !wire.thread.is_empty() {
+        bytes.extend(serde_json::to_vec(&(&wire.thread,))?);
+    }
+    Ok(bytes)
+}
+
+/// Attach our public key and a signature over `wire`.
+pub fn sign(wire: &mut WireMessage, keypair: &Keypair) -> Result<()> {
+    let signature = keypair
+        .sign(&signing_bytes(wire)?)
+        .context("sign wire message")?;
+    wire.public_key = B64.encode(keypair.public().encode_protobuf());
+    wire.signature = B64.encode(signature);
+    Ok(())
+}
+
+/// The signer of `wire`, if its signature is valid *and* the claimed
+/// discriminator is the one derived from the signing key — otherwise anyone
+/// could sign with their own key under someone else's name.
+pub fn verify(wire: &WireMessage) -> Option<PeerId> {
+    let key = B64.decode(&wire.public_key).ok()?;
+    let key = PublicKey::try_decode_protobuf(&key).ok()?;
+    let signature = B64.decode(&wire.signature).ok()?;
+    if !key.verify(&signing_bytes(wire).ok()?, &signature) {
+        return None;
+    }
+    let peer_id = key.to_peer_id();
+    (discriminator(&peer_id, wire.sender_disc.len()) == wire.sender_disc).then_some(peer_id)
+}
+
+/// How far a message's timestamp may be from our clock before it is
+/// treated as a replay.
+pub const REPLAY_WINDOW_MS: i64 = 10 * 60 * 1000;
+/// Most message IDs remembered per room.
+const SEEN_CAPACITY: usize = 4096;
+/// Payloads a peer may send back to back before it is rate limited.
+pub const RATE_BURST: f64 = 50.0;
+/// Payloads per second a peer may send in the long run.
+pub const RATE_PER_SEC: f64 = 10.0;
+/// Peers tracked by `RateLimiter` before idle ones are forgotten.
+const RATE_PEERS: usize = 1024;
+/// Chat messages we may send back to back before being asked to wait.
+pub const SEND_BURST: f64 = 5.0;
+/// Chat messages per second we may send in the long run.
+pub const SEND_PER_SEC: f64 = 1.0;
+/// Longest slow mode (`/slow`) an owner may set, in seconds.
+pub const MAX_SLOW_MODE_SECS: u32 = 60 * 60;
+/// Most options a poll may offer.
+pub const MAX_POLL_OPTIONS: usize = 10;
+/// Maximum poll question length in characters.
+pub const MAX_POLL_QUESTION_LEN: usize = 200;
+/// Maximum poll option length in characters.
+pub const MAX_POLL_OPTION_LEN: usize = 80;
+/// Longest a poll may stay open, in seconds.
+pub const MAX_POLL_SECS: u32 = 24 * 60 * 60;
+/// Most speakers an announcement room may have.
+pub const MAX_SPEAKERS: usize = 32;
+/// Most lines a room's shared notes show, and a `Notes` message carries.
+pub const MAX_NOTE_LINES: usize = 200;
+/// Most lines a room's shared notes hold, deleted ones included; past
+/// it, those with the highest `NoteId`s are dropped.
+pub const MAX_NOTE_RECORDS: usize = 20 * MAX_NOTE_LINES;
+/// Maximum line of the shared notes in characters.
+pub const MAX_NOTE_LINE_LEN: usize = 200;
+/// Highest clock a line of the shared notes may carry: more writes than
+/// any room makes, yet far enough from `u64::MAX` that counting on from
+/// it cannot overflow.
+pub const MAX_NOTE_CLOCK: u64 = 1 << 48;
+
+/// Recently seen `(sender, msg_id)` pairs, so a captured payload published
+/// again is dropped.  IDs are remembered for `REPLAY_WINDOW_MS`; anything
+/// older is rejected by its timestamp instead.
+#[derive(Default)]
+pub struct SeenCache {
+    /// Insertion order, with the message timestamp, for pruning.
+    order: VecDeque<(i64, (String, u64))>,
+    ids: HashSet<(String, u64)>,
+}
+
+impl SeenCache {
+    /// `true` if `wire` is new: timestamped within the window and not seen
+    /// before.  Messages without an ID (`msg_id == 0`) only get the
+    /// timestamp check.
+    pub fn check(&mut self, wire: &WireMessage, now_ms: i64) -> bool {
+        if (now_ms - wire.timestamp_ms).abs() > REPLAY_WINDOW_MS {
+            return false;
+        }
+        while let Some((ts, key)) = self.order.front()
+            && (now_ms - ts > REPLAY_WINDOW_MS || self.order.len() >= SEEN_CAPACITY)
+        {
+            self.ids.remove(key);
+            self.order.pop_front();
+        }
+        if wire.msg_id == 0 {
+            return true;
+        }
+        let key = (
+            format!("{}#{}", wire.sender_nick, wire.sender_disc),
+            wire.msg_id,
+        );
+        if !self.ids.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back((wire.timestamp_ms, key));
+        true
+    }
+}
+
+/// Samples kept per sender by `SkewEstimator`.
+const SKEW_SAMPLES: usize = 8;
+/// Senders tracked by `SkewEstimator`; beyond this the map starts over.
+const SKEW_SENDERS: usize = 1024;
+/// Relayed messages are never placed further back than this.
+pub const MAX_BACKDATE_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+
+/// Per-sender estimate of how far its clock is off ours, from when its live
+/// messages arrive versus the time they claim.  Network delay only ever adds
+/// to that difference, so the smallest recent one is the estimate.
+#[derive(Default)]
+pub struct SkewEstimator {
+    /// sender → recent `receive time − claimed time` samples, in ms
+    samples: HashMap<String, VecDeque<i64>>,
+}
+
+impl SkewEstimator {
+    /// Note a live, signed message from `sender` claiming `claimed_ms`,
+    /// received at `now_ms`.  Claims outside `REPLAY_WINDOW_MS` are not
+    /// samples (`SeenCache` drops those messages anyway).
+    pub fn observe(&mut self, sender: &str, claimed_ms: i64, now_ms: i64) {
+        let offset = now_ms - claimed_ms;
+        if offset.abs() > REPLAY_WINDOW_MS {
+            return;
+        }
+        if self.samples.len() >= SKEW_SENDERS && !self.samples.contains_key(sender) {
+            self.samples.clear();
+        }
+        let samples = self.samples.entry(sender.to_string()).or_default();
+        if samples.len() >= SKEW_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(offset);
+    }
+
+    /// Estimated `our clock − sender's clock` in ms; 0 for senders not
+    /// heard live.
+    pub fn offset(&self, sender: &str) -> i64 {
+        self.samples
+            .get(sender)
+            .and_then(|samples| samples.iter().min().copied())
+            .unwrap_or(0)
+    }
+
+    /// A time `sender` claimed, moved onto our clock and clamped: never in
+    /// our future, never more than `MAX_BACKDATE_MS` in our past.
+    pub fn adjust(&self, sender: &str, claimed_ms: i64, now_ms: i64) -> i64 {
+        claimed_ms
+            .saturating_add(self.offset(sender))
+            .clamp(now_ms - MAX_BACKDATE_MS, now_ms)
+    }
+}
+
+/// Refuse chat text we would not send (or reassemble).
+pub fn validate_chat(text: &str) -> Result<()> {
+    ensure!(
+        text.len() <= MAX_CHAT_LEN,
+        "Message too long ({} bytes; at most {} KiB).",
+        text.len(),
+        MAX_CHAT_LEN / 1024
+    );
+    Ok(())
+}
+
+/// Split chat `text` into the parts it is sent in: at most `CHUNK_LEN`
+/// bytes each, cut between characters.  Short text is one part.
+pub fn split_chat(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while rest.len() > CHUNK_LEN {
+        let mut cut = CHUNK_LEN;
+        while !rest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        let (part, tail) = rest.split_at(cut);
+        parts.push(part);
+        rest = tail;
+    }
+    parts.push(rest);
+    parts
+}
+
+/// Parts of chat messages sent in chunks, collected per sender until each
+/// message is whole.  Bounded: at most `MAX_PARTIAL` messages at a time,
+/// each given up after `CHUNK_TIMEOUT_MS` or when it outgrows
+/// `MAX_CHAT_LEN`.
+#[derive(Default)]
+pub struct Reassembler {
+    /// (sender, whole message's ID) → what arrived of it
+    partial: HashMap<(String, u64), Partial>,
+}
+
+struct Partial {
+    /// When its first part arrived.
+    since_ms: i64,
+    /// By index; `None` until that part arrives.
+    parts: Vec<Option<WireMessage>>,
+}
+
+impl Reassembler {
+    /// Add `part` (which carries a `chunk`) from `sender`.  Once all parts
+    /// are in, returns the whole message: the first part with the joined
+    /// text, the shared ID and no `chunk`.
+    pub fn accept(&mut self, sender: &str, part: WireMessage, now_ms: i64) -> Option<WireMessage> {
+        let chunk = part.chunk?;
+        self.partial
+            .retain(|_, partial| now_ms - partial.since_ms <= CHUNK_TIMEOUT_MS);
+        let key = (sender.to_string(), chunk.id);
+        if self.partial.len() >= MAX_PARTIAL && !self.partial.contains_key(&key) {
+            let oldest = self
+                .partial
+                .iter()
+                .min_by_key(|(_, partial)| partial.since_ms)
+                .map(|(key, _)| key.clone())?;
+            self.partial.remove(&oldest);
+        }
+        let parts = &mut self
+            .partial
+            .entry(key.clone())
+            .or_insert_with(|| Partial {
+                since_ms: now_ms,
+                parts: vec![None; usize::from(chunk.total)],
+            })
+            .parts;
+        if parts.len() != usize::from(chunk.total) {
+            return None; // Parts disagreeing on their number.
+        }
+        parts[usize::from(chunk.index)] = Some(part);
+        let len: usize = parts.iter().flatten().map(|p| p.text.len()).sum();
+        if len > MAX_CHAT_LEN {
+            self.partial.remove(&key);
+            return None;
+        }
+        if parts.iter().any(Option::is_none) {
+            return None;
+        }
+        let mut parts = self.partial.remove(&key)?.parts.into_iter().flatten();
+        let mut whole = parts.next()?;
+        for part in parts {
+            whole.text.push_str(&part.text);
+        }
+        whole.msg_id = chunk.id;
+        whole.chunk = None;
+        Some(whole)
+    }
+}
+
+/// Outcome of `RateLimiter::check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateVerdict {
+    Allow,
+    /// Over the limit; drop the payload.
+    Drop,
+    /// Over the limit for the first time since it last behaved — drop the
+    /// payload and penalize the peer.
+    Flood,
+}
+
+/// Per-peer token bucket over inbound payloads, checked before decryption so
+/// a flooding peer costs no more than a hash-map lookup.
+#[derive(Default)]
+pub struct RateLimiter {
+    /// peer → (tokens left, time of last refill, flagged as flooding)
+    buckets: HashMap<String, (f64, i64, bool)>,
+}
+
+impl RateLimiter {
+    pub fn check(&mut self, peer: &str, now_ms: i64) -> RateVerdict {
+        if self.buckets.len() >= RATE_PEERS && !self.buckets.contains_key(peer) {
+            // Forget peers whose bucket has refilled: they are idle.
+            self.buckets.retain(|_, (tokens, last, _)| {
+                *tokens + (now_ms - *last) as f64 / 1000.0 * RATE_PER_SEC < RATE_BURST
+            });
+        }
+        let (tokens, last, flagged) = self
+            .buckets
+            .entry(peer.to_string())
+            .or_insert((RATE_BURST, now_ms, false));
+        let elapsed = (now_ms - *last).max(0) as f64 / 1000.0;
+        *tokens = (*tokens + elapsed * RATE_PER_SEC).min(RATE_BURST);
+        *last = now_ms;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            *flagged = false;
+            RateVerdict::Allow
+        } else if *flagged {
+            RateVerdict::Drop
+        } else {
+            *flagged = true;
+            RateVerdict::Flood
+        }
+    }
+}
+
+/// Token bucket over the chat messages we send ourselves, so a stuck key
+/// or a script does not flood the room, and get us graylisted by every
+/// member's `RateLimiter` on the way.
+pub struct SendLimiter {
+    tokens: f64,
+    /// Time of the last refill; `None` before the first message.
+    last_ms: Option<i64>,
+}
+
+impl Default for SendLimiter {
+    fn default() -> Self {
+        Self {
+            tokens: SEND_BURST,
+            last_ms: None,
+        }
+    }
+}
+
+impl SendLimiter {
+    /// Take a token for a message sent at `now_ms`, or say how long until
+    /// there is one.
+    pub fn check(&mut self, now_ms: i64) -> Result<(), Duration> {
+        let elapsed = (now_ms - self.last_ms.unwrap_or(now_ms)).max(0) as f64 / 1000.0;
+        self.tokens = (self.tokens + elapsed * SEND_PER_SEC).min(SEND_BURST);
+        self.last_ms = Some(now_ms);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / SEND_PER_SEC))
+        }
+    }
+}
+
+/// Parse the `text` of a `SlowMode`: whole seconds, at most
+/// `MAX_SLOW_MODE_SECS`.
+pub fn decode_slow_mode(text: &str) -> Result<u32> {
+    let secs: u32 = text
+        .parse()
+        .context("slow mode is not a number of seconds")?;
+    ensure!(secs <= MAX_SLOW_MODE_SECS, "slow mode too long");
+    Ok(secs)
+}
+
+/// Parse the `text` of an `InviteOnly`: "true" or "false".
+pub fn decode_invite_only(text: &str) -> Result<bool> {
+    text.parse()
+        .context("invite-only mode is not true or false")
+}
+
+/// Parse the `text` of a `Listing`: a room with a name and a description
+/// of at most `MAX_DESCRIPTION_LEN` characters.  Its code is checked
+/// against the signer by the app.
+pub fn decode_listing(text: &str) -> Result<PublicRoom> {
+    let room: PublicRoom = serde_json::from_str(text)?;
+    ensure!(!room.name.is_empty(), "listed room has no name");
+    ensure!(
+        room.description.chars().count() <= MAX_DESCRIPTION_LEN,
+        "room description too long"
+    );
+    Ok(room)
+}
+
+/// Parse the `text` of a `Capacity`: a number of members, 0 for no
+/// limit.
+pub fn decode_capacity(text: &str) -> Result<u32> {
+    text.parse().context("member limit is not a number")
+}
+
+/// Parse the `text` of a `GrantAdmin`: its member must be a valid peer
+/// ID.
+pub fn decode_admin_grant(text: &str) -> Result<AdminGrant> {
+    let grant: AdminGrant = serde_json::from_str(text)?;
+    ensure!(
+        grant.peer_id.parse::<PeerId>().is_ok(),
+        "co-owner is not a peer ID"
+    );
+    Ok(grant)
+}
+
+/// Parse the `text` of a `TransferOwnership`: the new owner's peer ID.
+pub fn decode_new_owner(text: &str) -> Result<PeerId> {
+    text.parse().context("new owner is not a peer ID")
+}
+
+/// Parse the `text` of a `Rename`: a new room name, valid and normalized
+/// as `room::normalize_room_name` leaves names, and the key that goes
+/// with it.
+pub fn decode_rename(text: &str) -> Result<RoomRekey> {
+    let rename: RoomRekey = serde_json::from_str(text)?;
+    check_room_name(&rename.room)?;
+    Ok(rename)
+}
+
+/// Parse the `text` of an `Announcement`: at most `MAX_SPEAKERS` valid,
+/// distinct peer IDs.
+pub fn decode_announcement(text: &str) -> Result<Announcement> {
+    let announcement: Announcement = serde_json::from_str(text)?;
+    ensure!(
+        announcement.speakers.len() <= MAX_SPEAKERS,
+        "too many speakers"
+    );
+    for (i, speaker) in announcement.speakers.iter().enumerate() {
+        ensure!(
+            speaker.parse::<PeerId>().is_ok(),
+            "speaker is not a peer ID"
+        );
+        ensure!(
+            !announcement.speakers[..i].contains(speaker),
+            "speaker listed twice"
+        );
+    }
+    Ok(announcement)
+}
+
+/// Parse the `text` of a `RosterAnnounce`, dropping entries whose name is not
+/// a valid `nick#disc`, and peer IDs that do not parse and invalid avatars.
+pub fn decode_roster(text: &str) -> Result<Vec<RosterEntry>> {
+    let entries: Vec<RosterEntry> = serde_json::from_str(text)?;
+    Ok(entries
+        .into_iter()
+        .filter(|e| validate_display_name(&e.name).is_ok())
+        .map(|e| RosterEntry {
+            peer_id: valid_peer_id(&e.peer_id),
+            avatar: if validate_avatar(&e.avatar).is_ok() {
+                e.avatar
+            } else {
+                String::new()
+            },
+            name: e.name,
+        })
+        .collect())
+}
+
+/// Parse the `text` of a `HistoryResponse`: at most `MAX_HISTORY_SYNC`
+/// entries, invalid senders and threads dropped, texts sanitized.
+pub fn decode_history(text: &str) -> Result<Vec<HistoryEntry>> {
+    let entries: Vec<HistoryEntry> = serde_json::from_str(text)?;
+    ensure!(
+        entries.len() <= MAX_HISTORY_SYNC,
+        "too many history entries"
+    );
+    Ok(entries
+        .into_iter()
+        .filter(|e| validate_display_name(&e.sender).is_ok())
+        .filter(|e| e.thread.is_empty() || validate_thread(&e.thread).is_ok())
+        .map(|e| HistoryEntry {
+            text: sanitize_text(&e.text),
+            ..e
+        })
+        .collect())
+}
+
+/// Parse the `text` of a `Forward`: the recipient's peer ID and at most
+/// `MAX_FORWARD` messages, each decoded like a payload of its own.  Entries
+/// that are malformed or not `Chat` are dropped; signatures are left to the
+/// caller.
+pub fn decode_forward(text: &str) -> Result<(String, Vec<WireMessage>)> {
+    let batch: ForwardBatch = serde_json::from_str(text)?;
+    ensure!(
+        batch.messages.len() <= MAX_FORWARD,
+        "too many forwarded messages"
+    );
+    let messages = batch
+        .messages
+        .iter()
+        .filter_map(|m| decode(&B64.decode(m).ok()?).ok())
+        .filter(|w| w.msg_type == WireMessageType::Chat)
+        .collect();
+    Ok((batch.to, messages))
+}
+
+/// Parse the `text` of a `Reaction`: a nonzero target and one short token
+/// of printable characters.
+pub fn decode_reaction(text: &str) -> Result<Reaction> {
+    let reaction: Reaction = serde_json::from_str(text)?;
+    validate_emoji(&reaction.emoji)?;
+    ensure!(reaction.target_msg_id != 0, "reaction without a target");
+    Ok(reaction)
+}
+
+/// Parse the `text` of a `Poll`: a question and 2 to `MAX_POLL_OPTIONS`
+/// distinct options, all short and free of control characters, open for
+/// at most `MAX_POLL_SECS`.
+pub fn decode_poll(text: &str) -> Result<Poll> {
+    let poll: Poll = serde_json::from_str(text)?;
+    let is_plain = |s: &str, max: usize| {
+        !s.trim().is_empty() && s.chars().count() <= max && !s.chars().any(char::is_control)
+    };
+    ensure!(
+        is_plain(&poll.question, MAX_POLL_QUESTION_LEN),
+        "poll question empty, too long or not plain text"
+    );
+    ensure!(
+        (2..=MAX_POLL_OPTIONS).contains(&poll.options.len()),
+        "a poll takes 2 to {MAX_POLL_OPTIONS} options"
+    );
+    for (i, option) in poll.options.iter().enumerate() {
+        ensure!(
+            is_plain(option, MAX_POLL_OPTION_LEN),
+            "poll option empty, too long or not plain text"
+        );
+        ensure!(
+            !poll.options[..i].contains(option),
+            "poll option '{option}' given twice"
+        );
+    }
+    ensure!(
+        (1..=MAX_POLL_SECS).contains(&poll.duration_secs),
+        "a poll stays open 1s to {}h",
+        MAX_POLL_SECS / 3600
+    );
+    Ok(poll)
+}
+
+/// Parse the `text` of a `Vote`; whether the option exists is for the
+/// caller, who knows the poll.
+pub fn decode_vote(text: &str) -> Result<Vote> {
+    let vote: Vote = serde_json::from_str(text)?;
+    ensure!(vote.poll_msg_id != 0, "vote without a poll");
+    Ok(vote)
+}
+
+/// Parse the `text` of a `Notes`: at most `MAX_NOTE_LINES` lines of at
+/// most `MAX_NOTE_LINE_LEN` characters without control characters,
+/// clocks up to `MAX_NOTE_CLOCK`, each inserted after an earlier line.
+/// Deleted lines lose any text they came with.
+pub fn decode_notes(text: &str) -> Result<Vec<NoteLine>> {
+    let lines: Vec<NoteLine> = serde_json::from_str(text)?;
+    ensure!(lines.len() <= MAX_NOTE_LINES, "too many note lines");
+    lines
+        .into_iter()
+        .map(|line| {
+            validate_note(&line.text)?;
+            ensure!(
+                line.edited >= line.id,
+                "note line edited before it was written"
+            );
+            ensure!(
+                line.edited.clock <= MAX_NOTE_CLOCK,
+                "note line clock {} out of range",
+                line.edited.clock
+            );
+            ensure!(
+                line.after.is_none_or(|after| after < line.id),
+                "note line inserted after a later one"
+            );
+            Ok(NoteLine {
+                text: if line.deleted {
+                    String::new()
+                } else {
+                    line.text
+                },
+                ..line
+            })
+        })
+        .collect()
+}
+
+/// What a line of the shared notes may say.
+pub fn validate_note(text: &str) -> Result<()> {
+    ensure!(
+        text.chars().count() <= MAX_NOTE_LINE_LEN,
+        "note line longer than {MAX_NOTE_LINE_LEN} characters"
+    );
+    ensure!(
+        !text.chars().any(char::is_control),
+        "note line contains control characters"
+    );
+    Ok(())
+}
+
+/// Parse the `text` of a `Presence`: a short reason without control
+/// characters.
+pub fn decode_presence(text: &str) -> Result<Presence> {
+    let presence: Presence = serde_json::from_str(text)?;
+    ensure!(
+        presence.reason.chars().count() <= MAX_PRESENCE_REASON_LEN,
+        "presence reason too long"
+    );
+    ensure!(
+        !presence.reason.chars().any(char::is_control),
+        "presence reason contains control characters"
+    );
+    Ok(presence)
+}
+
+/// What may be sent as a reaction: non-empty, at most `MAX_EMOJI_LEN`
+/// bytes, no whitespace or control characters.
+pub fn validate_emoji(emoji: &str) -> Result<()> {
+    ensure!(!emoji.is_empty(), "empty reaction");
+    ensure!(emoji.len() <= MAX_EMOJI_LEN, "reaction too long");
+    ensure!(
+        !emoji.chars().any(|c| c.is_whitespace() || c.is_control()),
+        "reaction contains whitespace or control characters"
+    );
+    Ok(())
+}
+
+/// `nick#disc`, both parts valid.
+pub fn validate_display_name(name: &str) -> Result<()> {
+    let Some((nick, disc)) = name.rsplit_once('#') else {
+        bail!("missing discriminator");
+    };
+    validate_nickname(nick)?;
+    validate_discriminator(disc)
+}
+
+/// `peer_id` if it parses as a libp2p peer ID, else empty.
+pub fn valid_peer_id(peer_id: &str) -> String {
+    match peer_id.parse::<libp2p::PeerId>() {
+        Ok(_) => peer_id.to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+fn validate_discriminator(disc: &str) -> Result<()> {
+    ensure!(
+        (MIN_DISC_LEN..=MAX_DISC_LEN).contains(&disc.len())
+            && disc
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)),
+        "malformed discriminator"
+    );
+    Ok(())
+}
+
+/// Replace control characters in message text so escape sequences cannot
+/// reach the terminal.  Line breaks and tabs stay: a pasted snippet keeps
+/// its lines and indentation.
+pub fn sanitize_text(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\n' | '\t' => c,
+            c if c.is_control() => '\u{FFFD}',
+            c => c,
+        })
+        .collect()
+}
+
+/// Like `sanitize_text`, for text shown on a single line: line breaks and
+/// tabs are replaced too.
+pub fn sanitize_line(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_control() { '\u{FFFD}' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Chunk;
+
+    fn message(nick: &str, disc: &str, text: &str) -> WireMessage {
+        WireMessage {
+            msg_type: WireMessageType::Chat,
+            sender_nick: nick.to_string(),
+            sender_disc: disc.to_string(),
+            timestamp_ms: 0,
+            msg_id: 7,
+            text: text.to_string(),
+            avatar: String::new(),
+            reply_to: 0,
+            chunk: None,
+            thread: String::new(),
+            public_key: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    fn encoded(nick: &str, disc: &str, text: &str) -> Vec<u8> {
+        encode(&message(nick, disc, text)).unwrap()
+    }
+
+    #[test]
+    fn accepts_well_formed_message() {
+        let bytes = encoded("Seung", "3f2a", "hello");
+        assert_eq!(bytes[0], WIRE_VERSION);
+        assert_eq!(decode(&bytes).unwrap(), message("Seung", "3f2a", "hello"));
+    }
+
+    #[test]
+    fn accepts_v1_json_and_rejects_unknown_versions() {
+        let json = serde_json::to_vec(&message("Seung", "3f2a", "old")).unwrap();
+        assert_eq!(decode(&json).unwrap().text, "old");
+
+        let mut future = encoded("Seung", "3f2a", "new");
+        future[0] = WIRE_ZSTD + 1;
+        assert!(decode(&future).is_err());
+        assert!(decode(&[]).is_err());
+    }
+
+    #[test]
+    fn long_payloads_are_compressed_and_round_trip() {
+        let short = encoded("Seung", "3f2a", "hello");
+        assert_eq!(compress(short.clone()), short);
+
+        let paste = "fn main() { println!(\"hello\"); }\n".repeat(200);
+        let long = encoded("Seung", "3f2a", &paste);
+        let packed = compress(long.clone());
+        assert_eq!(packed[0], WIRE_ZSTD);
+        assert!(
+            packed.len() < long.len() / 4,
+            "{} of {}",
+            packed.len(),
+            long.len()
+        );
+        assert_eq!(decode(&packed).unwrap(), decode(&long).unwrap());
+    }
+
+    #[test]
+    fn compressed_payloads_are_bounded_and_checked() {
+        // A few hundred bytes that would inflate past the payload limit.
+        let bomb = [&[WIRE_VERSION][..], &vec![0u8; MAX_PAYLOAD_LEN * 4]].concat();
+        let packed = compress(bomb);
+        assert!(packed.len() < 1024);
+        assert!(
+            decode(&packed)
+                .unwrap_err()
+                .to_string()
+                .contains("too large")
+        );
+
+        // No compressed payload inside a compressed payload.
+        let inner = compress(encoded("Seung", "3f2a", &"x".repeat(4096)));
+        let nested = compress([inner.as_slice(), &vec![b'x'; 4096]].concat());
+        assert_eq!(nested[0], WIRE_ZSTD);
+        assert!(decode(&nested).is_err());
+        assert!(decode(&[WIRE_ZSTD, 1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_identity_fields() {
+        assert!(decode(&encoded("", "3f2a", "x")).is_err());
+        assert!(decode(&encoded("evil\u{1b}[2J", "3f2a", "x")).is_err());
+        assert!(decode(&encoded(&"a".repeat(MAX_NICK_LEN + 1), "3f2a", "x")).is_err());
+        assert!(decode(&encoded("Seung", "3F2A", "x")).is_err());
+        assert!(decode(&encoded("Seung", "3f2", "x")).is_err());
+        assert!(decode(&encoded("Seung", &"3f2a".repeat(MAX_DISC_LEN / 4 + 1), "x")).is_err());
+        assert!(decode(&encoded("Seung", "3f2a1", "x")).is_ok());
+    }
+
+    #[test]
+    fn an_avatar_is_one_character_as_seen() {
+        for avatar in ["🦊", "é", "e\u{301}", "👩‍💻", "🇰🇷", "字"] {
+            assert!(validate_avatar(avatar).is_ok(), "{avatar}");
+        }
+        for avatar in ["", "ab", "🦊🦊", " ", "\u{1b}", "\u{200b}"] {
+            assert!(validate_avatar(avatar).is_err(), "{avatar:?}");
+        }
+        assert!(validate_avatar(&format!("e{}", "\u{301}".repeat(MAX_AVATAR_LEN))).is_err());
+    }
+
+    #[test]
+    fn bounds_sizes_and_sanitizes_text() {
+        assert!(decode(&encoded("Seung", "3f2a", &"x".repeat(MAX_TEXT_LEN + 1))).is_err());
+        assert!(decode(&vec![b' '; MAX_PAYLOAD_LEN + 1]).is_err());
+        let wire = decode(&encoded("Seung", "3f2a", "a\u{1b}[31mb")).unwrap();
+        assert_eq!(wire.text, "a\u{FFFD}[31mb");
+        let wire = decode(&encoded("Seung", "3f2a", "fn main() {\n\tok\r\n}")).unwrap();
+        assert_eq!(wire.text, "fn main() {\n\tok\u{FFFD}\n}");
+        let topic = WireMessage {
+            msg_type: WireMessageType::Topic,
+            ..message("Seung", "3f2a", "two\nlines")
+        };
+        assert_eq!(
+            decode(&encode(&topic).unwrap()).unwrap().text,
+            "two\u{FFFD}lines"
+        );
+    }
+
+    #[test]
+    fn seen_cache_drops_replays_and_stale_messages() {
+        let mut seen = SeenCache::default();
+        let wire = message("Seung", "3f2a", "hi");
+        assert!(seen.check(&wire, 1_000));
+        assert!(!seen.check(&wire, 2_000));
+        assert!(seen.check(
+            &WireMessage {
+                msg_id: 8,
+                ..wire.clone()
+            },
+            2_000
+        ));
+        // Same ID from someone else is a different message.
+        assert!(seen.check(&message("Kim", "00ff", "hi"), 2_000));
+        // Once the ID has expired, the timestamp gives the replay away.
+        assert!(!seen.check(&wire, REPLAY_WINDOW_MS + 1_001));
+    }
+
+    #[test]
+    fn rate_limiter_flags_a_flood_once_and_recovers() {
+        let mut rate = RateLimiter::default();
+        for _ in 0..RATE_BURST as usize {
+            assert_eq!(rate.check("flooder", 0), RateVerdict::Allow);
+        }
+        assert_eq!(rate.check("flooder", 0), RateVerdict::Flood);
+        assert_eq!(rate.check("flooder", 0), RateVerdict::Drop);
+        assert_eq!(rate.check("calm", 0), RateVerdict::Allow);
+        // One second refills `RATE_PER_SEC` tokens.
+        assert_eq!(rate.check("flooder", 1_000), RateVerdict::Allow);
+    }
+
+    #[test]
+    fn skew_is_the_smallest_delay_and_adjusted_times_are_clamped() {
+        let mut skew = SkewEstimator::default();
+        // Clock 5 minutes fast, with 200 and 50 ms of network delay.
+        let now = 1_700_000_000_000;
+        skew.observe("fast#1a2b", now + 300_000 - 200, now);
+        skew.observe("fast#1a2b", now + 300_000 - 50, now);
+        skew.observe("broken#3c4d", now + 3_600_000, now);
+        assert_eq!(skew.offset("fast#1a2b"), -299_950);
+        assert_eq!(skew.offset("broken#3c4d"), 0);
+
+        assert_eq!(skew.adjust("fast#1a2b", now + 240_000, now), now - 59_950);
+        assert_eq!(skew.adjust("broken#3c4d", now + 3_600_000, now), now);
+        assert_eq!(skew.adjust("broken#3c4d", 0, now), now - MAX_BACKDATE_MS);
+    }
+
+    #[test]
+    fn send_limiter_says_how_long_to_wait() {
+        let mut send = SendLimiter::default();
+        for _ in 0..SEND_BURST as usize {
+            assert_eq!(send.check(10_000), Ok(()));
+        }
+        assert_eq!(send.check(10_000), Err(Duration::from_secs(1)));
+        assert_eq!(send.check(10_400), Err(Duration::from_millis(600)));
+        assert_eq!(send.check(11_000), Ok(()));
+    }
+
+    #[test]
+    fn slow_mode_is_bounded_seconds() {
+        assert_eq!(decode_slow_mode("30").unwrap(), 30);
+        assert_eq!(decode_slow_mode("0").unwrap(), 0);
+        assert!(decode_slow_mode("3601").is_err());
+        assert!(decode_slow_mode("-1").is_err() && decode_slow_mode("soon").is_err());
+    }
+
+    #[test]
+    fn speakers_are_a_short_list_of_peer_ids() {
+        let peer = libp2p::PeerId::random().to_string();
+        let text = |speakers: Vec<String>| {
+            serde_json::to_string(&Announcement { on: true, speakers }).unwrap()
+        };
+        assert_eq!(
+            decode_announcement(&text(vec![peer.clone()]))
+                .unwrap()
+                .speakers,
+            std::slice::from_ref(&peer)
+        );
+        assert!(!decode_announcement(r#"{"on":false}"#).unwrap().on);
+        assert!(decode_announcement(&text(vec!["junk".to_string()])).is_err());
+        assert!(decode_announcement(&text(vec![peer.clone(), peer])).is_err());
+        let many = (0..=MAX_SPEAKERS)
+            .map(|_| libp2p::PeerId::random().to_string())
+            .collect();
+        assert!(decode_announcement(&text(many)).is_err());
+    }
+
+    #[test]
+    fn roster_drops_invalid_entries() {
+        let peer = libp2p::PeerId::random().to_string();
+        let text = serde_json::to_string(&[
+            RosterEntry {
+                name: "Seung#3f2a".into(),
+                peer_id: peer.clone(),
+                avatar: "🦊".into(),
+            },
+            RosterEntry {
+                name: "nodisc".into(),
+                peer_id: peer.clone(),
+                avatar: String::new(),
+            },
+            RosterEntry {
+                name: "Kim#00ff".into(),
+                peer_id: "junk".into(),
+                avatar: "\u{1b}[2J".into(),
+            },
+        ])
+        .unwrap();
+        let roster = decode_roster(&text).unwrap();
+        assert_eq!(roster.len(), 2);
+        assert_eq!(
+            (roster[0].peer_id.as_str(), roster[0].avatar.as_str()),
+            (peer.as_str(), "🦊")
+        );
+        assert_eq!(
+            (roster[1].peer_id.as_str(), roster[1].avatar.as_str()),
+            ("", "")
+        );
+    }
+
+    #[test]
+    fn history_is_bounded_and_sanitized() {
+        let entry = |sender: &str, text: &str| HistoryEntry {
+            sender: sender.into(),
+            timestamp_ms: 0,
+            text: text.into(),
+            thread: String::new(),
+        };
+        let in_thread = |thread: &str| HistoryEntry {
+            thread: thread.into(),
+            ..entry("Seung#3f2a", "x")
+        };
+        let text = serde_json::to_string(&[
+            entry("Seung#3f2a", "a\u{7}b"),
+            entry("bad", "x"),
+            in_thread("design"),
+            in_thread("two words"),
+        ])
+        .unwrap();
+        assert_eq!(
+            decode_history(&text).unwrap(),
+            vec![entry("Seung#3f2a", "a\u{FFFD}b"), in_thread("design")]
+        );
+        let many = vec![entry("Seung#3f2a", "x"); MAX_HISTORY_SYNC + 1];
+        assert!(decode_history(&serde_json::to_string(&many).unwrap()).is_err());
+    }
+
+    #[test]
+    fn forward_keeps_only_chat_and_is_bounded() {
+        let mut join = message("Seung", "3f2a", "peer");
+        join.msg_type = WireMessageType::Join;
+        let batch = |messages: Vec<Vec<u8>>| {
+            serde_json::to_string(&ForwardBatch {
+                to: "me".into(),
+                messages: messages.iter().map(|m| B64.encode(m)).collect(),
+            })
+            .unwrap()
+        };
+        let text = batch(vec![
+            encoded("Seung", "3f2a", "hi"),
+            encode(&join).unwrap(),
+            b"junk".to_vec(),
+        ]);
+        let (to, messages) = decode_forward(&text).unwrap();
+        assert_eq!(to, "me");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].text, "hi");
+        let many = vec![encoded("Seung", "3f2a", "x"); MAX_FORWARD + 1];
+        assert!(decode_forward(&batch(many)).is_err());
+    }
+
+    #[test]
+    fn reactions_are_one_short_token() {
+        let text = |emoji: &str| {
+            serde_json::to_string(&Reaction {
+                target_msg_id: 7,
+                emoji: emoji.into(),
+            })
+            .unwrap()
+        };
+        assert_eq!(decode_reaction(&text("👍")).unwrap().target_msg_id, 7);
+        assert_eq!(decode_reaction(&text("👩‍💻")).unwrap().emoji, "👩‍💻");
+        assert!(decode_reaction(&text("")).is_err());
+        assert!(decode_reaction(&text("a b")).is_err());
+        assert!(decode_reaction(&text("\u{1b}[2J")).is_err());
+        assert!(decode_reaction(&text(&"👍".repeat(9))).is_err());
+        assert!(decode_reaction(r#"{"target_msg_id":0,"emoji":"👍"}"#).is_err());
+    }
+
+    #[test]
+    fn polls_have_a_few_short_distinct_options() {
+        let text = |options: &[&str], secs: u32| {
+            let poll = Poll {
+                question: "Lunch?".into(),
+                options: options.iter().map(|o| o.to_string()).collect(),
+                duration_secs: secs,
+            };
+            serde_json::to_string(&poll).unwrap()
+        };
+        assert_eq!(
+            decode_poll(&text(&["Pizza", "Sushi"], 600))
+                .unwrap()
+                .options
+                .len(),
+            2
+        );
+        assert!(decode_poll(&text(&["Pizza"], 600)).is_err());
+        assert!(decode_poll(&text(&["Pizza", "Pizza"], 600)).is_err());
+        assert!(decode_poll(&text(&["Pizza", " "], 600)).is_err());
+        assert!(decode_poll(&text(&["Pizza", "\u{1b}[2J"], 600)).is_err());
+        assert!(decode_poll(&text(&["Pizza", &"x".repeat(MAX_POLL_OPTION_LEN + 1)], 600)).is_err());
+        assert!(
+            decode_poll(&text(
+                &["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k"],
+                600
+            ))
+            .is_err()
+        );
+        assert!(decode_poll(&text(&["Pizza", "Sushi"], 0)).is_err());
+        assert!(decode_poll(&text(&["Pizza", "Sushi"], MAX_POLL_SECS + 1)).is_err());
+        assert_eq!(
+            decode_vote(r#"{"poll_msg_id":7,"option":1}"#)
+                .unwrap()
+                .option,
+            1
+        );
+        assert!(decode_vote(r#"{"poll_msg_id":0,"option":1}"#).is_err());
+    }
+
+    #[test]
+    fn note_lines_are_short_plain_text() {
+        let line = |text: &str, deleted: bool| {
+            let id = crate::types::NoteId { clock: 3, site: 9 };
+            NoteLine {
+                id,
+                after: None,
+                text: text.into(),
+                edited: id,
+                deleted,
+            }
+        };
+        let text = |lines: &[NoteLine]| serde_json::to_string(lines).unwrap();
+        assert_eq!(
+            decode_notes(&text(&[line("milk", false)])).unwrap()[0].text,
+            "milk"
+        );
+        assert_eq!(
+            decode_notes(&text(&[line("milk", true)])).unwrap()[0].text,
+            ""
+        );
+        assert!(decode_notes(&text(&[line("\u{1b}[2J", false)])).is_err());
+        assert!(decode_notes(&text(&[line(&"x".repeat(MAX_NOTE_LINE_LEN + 1), false)])).is_err());
+        assert!(decode_notes(&text(&vec![line("", false); MAX_NOTE_LINES + 1])).is_err());
+        let mut stale = line("milk", false);
+        stale.edited.clock = 2;
+        assert!(decode_notes(&text(&[stale])).is_err());
+        let mut far = line("milk", false);
+        far.edited.clock = MAX_NOTE_CLOCK + 1;
+        assert!(decode_notes(&text(&[far])).is_err());
+        let mut backwards = line("milk", false);
+        backwards.after = Some(crate::types::NoteId { clock: 4, site: 0 });
+        assert!(decode_notes(&text(&[backwards])).is_err());
+    }
+
+    #[test]
+    fn presence_reason_is_short_plain_text() {
+        let online = decode_presence(r#"{"status":"online"}"#).unwrap();
+        assert_eq!(online, Presence::default());
+        let away = decode_presence(r#"{"status":"do_not_disturb","reason":"exam"}"#).unwrap();
+        assert_eq!(away.describe(), "do not disturb: exam");
+        assert!(decode_presence(r#"{"status":"away","reason":"\u001b[2J"}"#).is_err());
+        let long = format!(r#"{{"status":"away","reason":"{}"}}"#, "z".repeat(81));
+        assert!(decode_presence(&long).is_err());
+        assert!(decode_presence(r#"{"status":"asleep"}"#).is_err());
+    }
+
+    #[test]
+    fn signature_binds_text_and_discriminator() {
+        let keypair = Keypair::generate_ed25519();
+        let disc = discriminator(&keypair.public().to_peer_id(), MIN_DISC_LEN);
+        let mut wire = message("Seung", &disc, "hello");
+        assert_eq!(verify(&wire), None);
+
+        sign(&mut wire, &keypair).unwrap();
+        assert_eq!(verify(&wire), Some(keypair.public().to_peer_id()));
+
+        let mut tampered = wire.clone();
+        tampered.text = "goodbye".to_string();
+        assert_eq!(verify(&tampered), None);
+
+        // A reply's target is signed too.
+        let mut reply = WireMessage {
+            reply_to: 9,
+            ..message("Seung", &disc, "yes")
+        };
+        sign(&mut reply, &keypair).unwrap();
+        assert!(verify(&reply).is_some());
+        reply.reply_to = 10;
+        assert_eq!(verify(&reply), None);
+
+        // So is a part's place in its message.
+        let chunk = Chunk {
+            id: 5,
+            index: 0,
+            total: 2,
+        };
+        let mut part = WireMessage {
+            chunk: Some(chunk),
+            ..message("Seung", &disc, "first")
+        };
+        sign(&mut part, &keypair).unwrap();
+        assert!(verify(&part).is_some());
+        part.chunk = Some(Chunk { index: 1, ..chunk });
+        assert_eq!(verify(&part), None);
+
+        // And an avatar.
+        let mut fox = WireMessage {
+            avatar: "🦊".to_string(),
+            ..message("Seung", &disc, "hi")
+        };
+        sign(&mut fox, &keypair).unwrap();
+        assert!(verify(&fox).is_some());
+        fox.avatar = "🐺".to_string();
+        assert_eq!(verify(&fox), None);
+
+        // And a thread: a message cannot be moved to another one.
+        let mut threaded = WireMessage {
+            thread: "design".to_string(),
+            ..message("Seung", &disc, "hi")
+        };
+        sign(&mut threaded, &keypair).unwrap();
+        assert!(verify(&threaded).is_some());
+        threaded.thread = "random".to_string();
+        assert_eq!(verify(&threaded), None);
+        threaded.thread.clear();
+        assert_eq!(verify(&threaded), None);
+
+        // Someone else's name, signed with our key.
+        let mut stolen = message("Seung", if disc == "0000" { "0001" } else { "0000" }, "hi");
+        sign(&mut stolen, &keypair).unwrap();
+        assert_eq!(verify(&stolen), None);
+
+        // A longer discriminator of the same key is just as good.
+        let long = discriminator(&keypair.public().to_peer_id(), MAX_DISC_LEN);
+        assert!(long.starts_with(&disc));
+        let mut wire = message("Seung", &long, "hello");
+        sign(&mut wire, &keypair).unwrap();
+        assert!(verify(&wire).is_some());
+    }
+
+    fn parts(text: &str, id: u64) -> Vec<WireMessage> {
+        let parts = split_chat(text);
+        let total = parts.len() as u16;
+        (0..)
+            .zip(parts)
+            .map(|(index, part)| WireMessage {
+                chunk: Some(Chunk { id, index, total }),
+                ..message("Seung", "3f2a", part)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_chat_cuts_between_characters() {
+        assert_eq!(split_chat("hi"), vec!["hi"]);
+        let text = "é".repeat(CHUNK_LEN); // two bytes each
+        let parts = split_chat(&text);
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|p| p.len() <= CHUNK_LEN));
+        assert_eq!(parts.concat(), text);
+    }
+
+    #[test]
+    fn reassembles_parts_in_any_order() {
+        let text = "abc".repeat(CHUNK_LEN);
+        let mut parts = parts(&text, 42);
+        assert_eq!(parts.len(), 3);
+        parts.reverse();
+        let mut chunks = Reassembler::default();
+        assert!(chunks.accept("seung", parts[0].clone(), 0).is_none());
+        // A repeated part changes nothing.
+        assert!(chunks.accept("seung", parts[0].clone(), 0).is_none());
+        // The same message ID from someone else is another message.
+        assert!(chunks.accept("mallory", parts[1].clone(), 0).is_none());
+        assert!(chunks.accept("seung", parts[1].clone(), 0).is_none());
+        let whole = chunks.accept("seung", parts[2].clone(), 0).unwrap();
+        assert_eq!(whole.text, text);
+        assert_eq!(whole.msg_id, 42);
+        assert_eq!(whole.chunk, None);
+        assert!(chunks.accept("seung", parts[1].clone(), 0).is_none());
+    }
+
+    #[test]
+    fn reassembly_gives_up_on_late_or_inconsistent_parts() {
+        let text = "abc".repeat(CHUNK_LEN);
+        let parts = parts(&text, 42);
+        let mut chunks = Reassembler::default();
+        chunks.accept("seung", parts[0].clone(), 0);
+        chunks.accept("seung", parts[1].clone(), 0);
+        assert!(
+            chunks
+                .accept("seung", parts[2].clone(), CHUNK_TIMEOUT_MS + 1)
+                .is_none()
+        );
+
+        // Parts disagreeing on how many there are.
+        let first = WireMessage {
+            chunk: Some(Chunk {
+                id: 7,
+                index: 0,
+                total: 2,
+            }),
+            ..parts[0].clone()
+        };
+        let liar = WireMessage {
+            chunk: Some(Chunk {
+                id: 7,
+                index: 1,
+                total: 3,
+            }),
+            ..parts[1].clone()
+        };
+        assert!(chunks.accept("seung", first, 0).is_none());
+        assert!(chunks.accept("seung", liar, 0).is_none());
+
+        // No more than `MAX_CHAT_LEN` in total, whatever the parts claim.
+        let mut big = Reassembler::default();
+        let total = MAX_CHUNKS;
+        for index in 0..total {
+            let part = WireMessage {
+                chunk: Some(Chunk {
+                    id: 9,
+                    index,
+                    total,
+                }),
+                ..message("Seung", "3f2a", &"x".repeat(MAX_TEXT_LEN))
+            };
+            assert!(big.accept("seung", part, 0).is_none());
+        }
+
+        // Parts must make sense to decode at all.
+        let bad = WireMessage {
+            chunk: Some(Chunk {
+                id: 1,
+                index: 2,
+                total: 2,
+            }),
+            ..message("Seung", "3f2a", "x")
+        };
+        assert!(decode(&encode(&bad).unwrap()).is_err());
+        let single = WireMessage {
+            chunk: Some(Chunk {
+                id: 1,
+                index: 0,
+                total: 1,
+            }),
+            ..message("Seung", "3f2a", "x")
+        };
+        assert!(decode(&encode(&single).unwrap()).is_err());
+    }
+
+    #[test]
+    fn survives_garbage() {
+        for input in [
+            &b""[..],
+            b"{",
+            b"[]",
+            b"null",
+            b"{\"msg_type\":\"Chat\"}",
+            &[0xff; 64],
+        ] {
+            assert!(decode(input).is_err());
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Command-line arguments (`chat --help`).  A few can also be given as
+//! `CHATAPP_*` environment variables, for containers and services that run
+//! without a `~/.chatrc` of their own: the flag wins over the variable,
+//! and either over the config file.
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::{
+    identity::IdentityOptions, replay::ReplayOptions, settings::ConfigOptions, simulate::SimOptions,
+};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "chat",
+    version,
+    about = "Peer-to-peer, end-to-end encrypted terminal chat"
+)]
+pub struct Args {
+    /// Config file to use instead of ~/.chatrc.
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Identity profile to use, made on first use: its own key, nickname,
+    /// config, contacts, saved rooms and logs.  Without it, a picker asks
+    /// at start once there are profiles.
+    #[arg(long, value_name = "NAME", conflicts_with = "config")]
+    pub profile: Option<String>,
+
+    /// Keep the config, identity, contacts, saved rooms, logs and profiles
+    /// in `chat-data` next to the executable instead of the home directory.
+    #[arg(long, conflicts_with = "config")]
+    pub portable: bool,
+
+    /// Like --portable, in DIR.
+    #[arg(long, value_name = "DIR", conflicts_with = "config")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Nickname for this session (the config file is left unchanged).
+    #[arg(long, env = "CHATAPP_NICKNAME")]
+    pub nickname: Option<String>,
+
+    /// Directory for room logs, overriding the config file.
+    #[arg(long, value_name = "DIR", env = "CHATAPP_LOG_DIR")]
+    pub log_dir: Option<String>,
+
+    /// TCP and QUIC (UDP) port to listen on, WebSocket on the next one;
+    /// random by default.
+    #[arg(long, default_value_t = 0, env = "CHATAPP_PORT")]
+    pub port: u16,
+
+    /// DHT bootstrap node (multiaddr ending in /p2p/<peer ID>) to use
+    /// instead of the configured ones; repeat for several (commas between
+    /// them in the variable).
+    #[arg(
+        long,
+        value_name = "MULTIADDR",
+        env = "CHATAPP_BOOTSTRAP",
+        value_delimiter = ','
+    )]
+    pub bootstrap: Vec<String>,
+
+    /// Do not bootstrap the DHT from any node.
+    #[arg(long, conflicts_with = "bootstrap")]
+    pub no_bootstrap: bool,
+
+    /// Skip the menu and join the room with this code.
+    #[arg(long, value_name = "ROOM_CODE")]
+    pub join: Option<String>,
+
+    /// Leave the mouse to the terminal, for its own text selection, instead
+    /// of scrolling and clicking with it (`/mouse` switches in the chat).
+    #[arg(long)]
+    pub no_mouse: bool,
+
+    /// No full-screen UI: print messages line by line and read commands
+    /// from stdin, for screen readers and braille terminals.
+    #[arg(long)]
+    pub plain: bool,
+
+    /// Capture the network event stream to FILE (see `chat replay`).
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Load-test with in-process peers on an in-memory network.
+    Simulate(SimOptions),
+    /// Run the app against a capture made with `--record`.
+    Replay(ReplayOptions),
+    /// Export the identity to a file, or import one, to move it to another
+    /// machine.
+    Identity(IdentityOptions),
+    /// Print or change a setting of the config file.
+    Config(ConfigOptions),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{identity::IdentityCommand, settings::ConfigCommand};
+
+    #[test]
+    fn parses_flags_and_subcommands() {
+        let args = Args::try_parse_from(["chat", "--port", "4001", "--join", "abc"]).unwrap();
+        assert_eq!((args.port, args.join.as_deref()), (4001, Some("abc")));
+        assert!(args.command.is_none());
+
+        let args = Args::try_parse_from(["chat", "--bootstrap", "a", "--bootstrap", "b"]).unwrap();
+        assert_eq!(args.bootstrap, ["a", "b"]);
+        assert!(Args::try_parse_from(["chat", "--bootstrap", "a", "--no-bootstrap"]).is_err());
+
+        let args = Args::try_parse_from(["chat", "replay", "cap.jsonl", "--speed", "2"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Replay(r)) if r.speed == 2.0));
+
+        assert!(Args::try_parse_from(["chat", "simulate", "--peers", "1"]).is_err());
+        assert!(Args::try_parse_from(["chat", "replay", "cap.jsonl", "--speed", "0"]).is_err());
+
+        let command = <Args as clap::CommandFactory>::command();
+        let env = |id: &str| {
+            let arg = command.get_arguments().find(|a| a.get_id() == id).unwrap();
+            arg.get_env()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let names = ["nickname", "log_dir", "port", "bootstrap"].map(env);
+        assert_eq!(
+            names,
+            [
+                "CHATAPP_NICKNAME",
+                "CHATAPP_LOG_DIR",
+                "CHATAPP_PORT",
+                "CHATAPP_BOOTSTRAP"
+            ]
+        );
+
+        let args =
+            Args::try_parse_from(["chat", "--data-dir", "/mnt/usb/chat", "--profile", "work"])
+                .unwrap();
+        assert_eq!(args.data_dir, Some(PathBuf::from("/mnt/usb/chat")));
+        assert!(Args::try_parse_from(["chat", "--portable", "--config", "x"]).is_err());
+
+        let args = Args::try_parse_from([
+            "chat",
+            "--profile",
+            "work",
+            "identity",
+            "import",
+            "id.txt",
+            "--force",
+        ])
+        .unwrap();
+        assert_eq!(args.profile.as_deref(), Some("work"));
+        assert!(matches!(
+            args.command,
+            Some(Command::Identity(IdentityOptions {
+                command: IdentityCommand::Import { force: true, .. }
+            }))
+        ));
+
+        let args =
+            Args::try_parse_from(["chat", "config", "set", "theme.preset", "light"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Config(ConfigOptions { command: ConfigCommand::Set { key, value } }))
+                if key == "theme.preset" && value == "light"
+        ));
+    }
+}
@@ -1,98 +1,440 @@
 use std::{
-    collections::VecDeque,
-    io::{self, Write},
+    collections::{HashMap, VecDeque},
+    num::NonZeroU16,
+    ops::Range,
 };
 
 use anyhow::Result;
-use crossterm::{
-    cursor,
-    event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
-    execute,
-    style::{self, Color, Stylize},
-    terminal::{self, ClearType},
+use crossterm::event::{
+    DisableBracketedPaste, DisableFocusChange, DisableMouseCapture, EnableBracketedPaste,
+    EnableFocusChange, EnableMouseCapture, Event, EventStream, KeyCode, KeyEvent, KeyEventKind,
+    KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
 };
 use futures::StreamExt;
+use ratatui::{
+    DefaultTerminal, Frame,
+    buffer::{Buffer, CellDiffOption},
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, List, ListItem, Padding, Paragraph},
+};
 use tokio::sync::mpsc;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use chatting1_core::{
+    config::{Persona, RecentRoom, ThemeConfig},
+    links,
+    types::{
+        AppEvent, CliCommand, Delivery, DisplayMessage, ExportFormat, JoinState, NoteEdit, NoteId,
+        Presence, PresenceStatus, PublicRoom, ThreadSummary, UiEvent,
+    },
+};
 
-use crate::types::{CliCommand, DisplayMessage, UiEvent};
+use crate::{
+    input::{InputLine, MAX_INPUT_LEN, visible_window},
+    keys::KeyBindings,
+    markup,
+    theme::{self, Theme},
+};
 
 const MAX_MESSAGES: usize = 500;
-const MAX_INPUT_LEN: usize = 2048;
+/// Width of the member sidebar in the chat screen (hidden on narrow terminals).
+const SIDEBAR_WIDTH: u16 = 24;
+/// Messages moved per mouse-wheel notch.
+const WHEEL_STEP: usize = 3;
+/// Without a key press for this long, the user is shown as away.
+const IDLE_AFTER: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+/// Rows of the chat screen not used by messages (header, borders, input box).
+const CHAT_CHROME_ROWS: u16 = 5;
+/// Main menu entries: the key each one stands for, and its label.
+const MENU_ITEMS: [(char, &str); 7] = [
+    ('1', "Create room"),
+    ('2', "Join room"),
+    ('3', "Change nickname"),
+    ('4', "Join room by name"),
+    ('b', "Browse public rooms"),
+    ('a', "Change avatar"),
+    ('q', "Quit"),
+];
+/// Key of the first recent room on the main menu; the others follow.
+const FIRST_RECENT_KEY: char = '5';
+/// Matches the command palette lists at most.
+const PALETTE_ROWS: usize = 10;
+/// A paste into the chat longer than this many characters, or of more
+/// lines, waits for Enter before going into the input.
+const PASTE_CONFIRM_CHARS: usize = 500;
+const PASTE_CONFIRM_LINES: usize = 5;
 
 // ── Screen state ──────────────────────────────────────────────────────────────
 
 #[derive(PartialEq)]
 enum Screen {
     MainMenu,
-    CreateRoom { step: u8 },
-    JoinRoom { step: u8 },
+    CreateRoom {
+        step: u8,
+    },
+    /// Join by room code, or by room name through the DHT when `by_name`.
+    /// Step 2 waits for a rejoin with saved credentials.
+    JoinRoom {
+        step: u8,
+        by_name: bool,
+    },
     ChangeNickname,
+    ChangeAvatar,
     Chat,
+    /// The active room's shared notes, line `selected` picked for editing
+    /// in the input; with `adding`, a new line below it instead.
+    Notes {
+        selected: usize,
+        adding: bool,
+    },
+    /// The active room's threads, row `selected` picked (0 is the room
+    /// itself).
+    Threads {
+        selected: usize,
+    },
+    /// The public room directory, room `selected` picked.
+    PublicRooms {
+        selected: usize,
+    },
 }
 
 // ── CLI state ─────────────────────────────────────────────────────────────────
 
 struct CliState {
+    screen: Screen,
     messages: VecDeque<DisplayMessage>,
-    input_buffer: String,
+    /// How many messages the view is scrolled up from the newest; 0 follows
+    /// new messages.
+    scroll: usize,
+    input: InputLine,
     current_room: Option<String>,
+    /// Topic of the active room set by its owner; empty if none.
+    subject: String,
+    /// Thread of the active room being shown; `None` for the room itself.
+    thread: Option<String>,
     peer_count: usize,
+    /// Every joined room, in Alt+1…9 order.
+    rooms: Vec<String>,
+    /// Members of the active room, shown in the sidebar.
+    members: Vec<String>,
     /// Currently masking input (password entry).
     masking: bool,
     /// Label shown before the input field (e.g. "Room name: ").
     prompt_label: String,
     /// Current nickname (kept in sync with the app layer).
     nickname: String,
+    /// Current avatar, likewise.
+    avatar: Option<String>,
+    /// Latest join-flow state, shown as a progress line above the prompt.
+    join_progress: Option<(String, JoinState)>,
+    /// Room name entered in the first create-room step, and the member
+    /// limit typed after it (0: none).
+    create_name: String,
+    create_max: u32,
+    /// Room code (or name) entered in the first join-room step.
+    join_code: String,
+    /// Persona typed after the room name or code (` as …`).
+    entry_persona: Option<Persona>,
+    /// Ring the terminal bell after the next draw.
+    bell: bool,
+    /// Unread messages of the rooms not on screen.
+    unread: HashMap<String, usize>,
+    /// Index in `messages` of the first message that arrived unseen: while
+    /// in another room, or while scrolled up.  A divider is drawn above it.
+    first_unread: Option<usize>,
+    /// Wire ID of the message picked with Alt+Up / Alt+Down; the next line
+    /// sent replies to it.
+    reply_to: Option<u64>,
+    /// Room code shown as a QR code over the chat until a key is pressed.
+    qr_code: Option<String>,
+    /// Mouse captured: wheel and clicks drive the UI, and selecting text
+    /// needs the terminal's override (usually Shift).  `/mouse` toggles it.
+    mouse: bool,
+    /// Draw `*bold*`, `_italic_` and `` `code` `` in messages as such.
+    formatting: bool,
+    theme: Theme,
+    keys: KeyBindings,
+    /// Every command as (usage, what it does), from the app.
+    commands: Vec<(String, String)>,
+    /// Help overlay open, scrolled down by this many lines.
+    help_scroll: Option<usize>,
+    /// Command palette open (Ctrl+P).
+    palette: Option<Palette>,
+    /// Rooms created or joined lately, newest first, from the app.
+    recent: Vec<RecentRoom>,
+    /// The next recent room key removes the room from the list instead.
+    pruning: bool,
+    /// A paste too big to take unasked: Enter puts it in the input, Esc
+    /// drops it.
+    pending_paste: Option<String>,
+    /// The active room's shared notes, once asked for with `/notes`.
+    notes: Vec<(NoteId, String)>,
+    /// The active room's threads, once asked for with `/threads`.
+    threads: Vec<ThreadSummary>,
+    /// The public rooms found so far, once asked for with `/browse`.
+    public_rooms: Vec<PublicRoom>,
+}
+
+/// The Ctrl+P command palette: a query and the match picked.
+#[derive(Default)]
+struct Palette {
+    query: InputLine,
+    /// Index into `palette_matches` of the query.
+    selected: usize,
 }
 
 impl CliState {
-    fn new(nickname: String) -> Self {
+    fn new(nickname: String, mouse: bool) -> Self {
         Self {
+            screen: Screen::MainMenu,
             messages: VecDeque::new(),
-            input_buffer: String::new(),
+            scroll: 0,
+            input: InputLine::default(),
             current_room: None,
+            subject: String::new(),
+            thread: None,
             peer_count: 0,
+            rooms: Vec::new(),
+            members: Vec::new(),
             masking: false,
             prompt_label: String::new(),
             nickname,
+            avatar: None,
+            join_progress: None,
+            create_name: String::new(),
+            create_max: 0,
+            join_code: String::new(),
+            entry_persona: None,
+            bell: false,
+            unread: HashMap::new(),
+            first_unread: None,
+            reply_to: None,
+            qr_code: None,
+            mouse,
+            formatting: true,
+            theme: Theme::default(),
+            keys: KeyBindings::default(),
+            commands: Vec::new(),
+            help_scroll: None,
+            palette: None,
+            recent: Vec::new(),
+            pruning: false,
+            pending_paste: None,
+            notes: Vec::new(),
+            threads: Vec::new(),
+            public_rooms: Vec::new(),
         }
     }
 
     fn push_message(&mut self, msg: DisplayMessage) {
         if self.messages.len() >= MAX_MESSAGES {
             self.messages.pop_front();
+            self.first_unread = self.first_unread.and_then(|i| i.checked_sub(1));
         }
+        let unseen = self.scroll > 0 && counts_as_unread(&msg);
         self.messages.push_back(msg);
+        if unseen && self.first_unread.is_none() {
+            self.first_unread = Some(self.messages.len() - 1);
+        }
+        // Keep the history being read in place.
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.messages.len());
+        }
+    }
+
+    /// A message was recorded in `room`; count it if that room is not shown.
+    fn note_room_message(&mut self, room: &str, msg: &DisplayMessage) {
+        if self.current_room.as_deref() != Some(room) && counts_as_unread(msg) {
+            *self.unread.entry(room.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Unread messages of the active room still below the view.
+    fn unread_below(&self) -> usize {
+        let Some(first) = self.first_unread else {
+            return 0;
+        };
+        let below = self.messages.len() - self.scroll.min(self.messages.len());
+        self.messages
+            .iter()
+            .skip(first.max(below))
+            .filter(|m| counts_as_unread(m))
+            .count()
+    }
+
+    /// Scroll the chat view up (positive) or down (negative) by `delta`
+    /// messages.
+    fn scroll_by(&mut self, delta: isize) {
+        self.scroll = self
+            .scroll
+            .saturating_add_signed(delta)
+            .min(self.messages.len());
+    }
+
+    /// Pick the next older (or newer) message that can be replied to.
+    /// Moving past the newest one drops the selection.
+    fn select_reply(&mut self, older: bool) {
+        let replyable = |m: &DisplayMessage| !m.is_system && m.msg_id != 0;
+        let len = self.messages.len();
+        let current = self
+            .reply_to
+            .and_then(|id| self.messages.iter().rposition(|m| m.msg_id == id));
+        let next = if older {
+            self.messages
+                .range(..current.unwrap_or(len))
+                .rposition(replyable)
+        } else {
+            let start = current.map_or(len, |i| i + 1);
+            self.messages
+                .range(start..)
+                .position(replyable)
+                .map(|i| i + start)
+        };
+        match next {
+            Some(i) => {
+                self.reply_to = Some(self.messages[i].msg_id);
+                // Keep it from sliding below the view.
+                self.scroll = self.scroll.min(len - 1 - i);
+            }
+            None if !older => self.reply_to = None,
+            None => {}
+        }
+    }
+
+    /// The message being replied to, if it is still shown.
+    fn reply_target(&self) -> Option<&DisplayMessage> {
+        let id = self.reply_to?;
+        self.messages.iter().rev().find(|m| m.msg_id == id)
+    }
+
+    /// Switch to a text prompt screen.
+    fn open_prompt(&mut self, screen: Screen, label: &str, masking: bool) {
+        self.screen = screen;
+        self.input.clear();
+        if masking {
+            self.input.reserve_all();
+        }
+        self.prompt_label = label.to_string();
+        self.masking = masking;
+    }
+
+    /// Pick line `selected` of the notes, its text in the input to edit.
+    fn pick_note(&mut self, selected: usize) {
+        let Some((_, text)) = self.notes.get(selected) else {
+            return;
+        };
+        let text = text.clone();
+        self.input.clear();
+        self.input.paste(&text, false);
+        self.screen = Screen::Notes {
+            selected,
+            adding: false,
+        };
+    }
+
+    /// A join ended without us in the room: say why, and go back to the
+    /// active room if there is one.
+    fn turned_away(&mut self, text: &str) {
+        self.input.clear();
+        self.masking = false;
+        self.push_message(DisplayMessage::system(text));
+        if self.current_room.is_some() {
+            self.join_progress = None;
+            self.screen = Screen::Chat;
+        }
+    }
+
+    fn back_to_menu(&mut self) {
+        self.input.clear();
+        self.prompt_label.clear();
+        self.masking = false;
+        self.screen = Screen::MainMenu;
+    }
+
+    /// Use the theme and formatting of a reloaded config; a theme naming a
+    /// color that is not one leaves the current theme.
+    fn apply_settings(&mut self, config: &ThemeConfig, formatting: bool) {
+        match Theme::new(config, theme::no_color()) {
+            Ok(theme) => self.theme = theme,
+            Err(e) => self.push_message(DisplayMessage::system(&format!(
+                "[!] Theme not changed: {e}."
+            ))),
+        }
+        self.formatting = formatting;
+    }
+
+    /// A room became active in the UI: reset the input and show the chat.
+    fn enter_chat(&mut self, name: String) {
+        self.messages.clear();
+        self.scroll = 0;
+        self.first_unread = None;
+        self.current_room = Some(name);
+        self.reply_to = None;
+        self.qr_code = None;
+        self.input.clear();
+        self.masking = false;
+        self.screen = Screen::Chat;
     }
 }
 
+/// Whether `msg` adds to an unread count: chat from someone else.
+fn counts_as_unread(msg: &DisplayMessage) -> bool {
+    !msg.is_system && msg.delivery.is_none()
+}
+
 // ── Public entry point ────────────────────────────────────────────────────────
 
+/// How the terminal UI looks and takes keys, from the config and flags.
+pub struct UiOptions {
+    /// Capture the mouse.
+    pub mouse: bool,
+    /// Draw `*bold*`, `_italic_` and `` `code` `` in messages as such.
+    pub formatting: bool,
+    pub theme: Theme,
+    pub keys: KeyBindings,
+}
+
 /// Runs the full CLI lifecycle.  Call from a dedicated Tokio task.
 pub async fn run_cli(
     cli_cmd_tx: mpsc::UnboundedSender<CliCommand>,
-    ui_event_rx: mpsc::UnboundedReceiver<UiEvent>,
+    ui_event_rx: mpsc::UnboundedReceiver<AppEvent>,
     nickname: String,
+    join_code: Option<String>,
+    options: UiOptions,
 ) -> Result<()> {
-    // Enter alternate screen + raw mode.
-    terminal::enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(
-        stdout,
-        terminal::EnterAlternateScreen,
-        cursor::Hide,
-        terminal::Clear(ClearType::All)
-    )?;
-
-    let result = cli_inner(cli_cmd_tx, ui_event_rx, &mut stdout, nickname).await;
+    let UiOptions {
+        mouse,
+        formatting,
+        theme,
+        keys,
+    } = options;
+    // Alternate screen + raw mode; also restores the terminal on panic.
+    let mut terminal = ratatui::init();
+    // Focus changes decide notifications; a paste arrives as one event, so
+    // a line break in it does not send.
+    let _ = crossterm::execute!(std::io::stdout(), EnableFocusChange, EnableBracketedPaste);
+    if mouse {
+        let _ = crossterm::execute!(std::io::stdout(), EnableMouseCapture);
+    }
+
+    let state = CliState {
+        formatting,
+        theme,
+        keys,
+        ..CliState::new(nickname, mouse)
+    };
+    let result = cli_inner(cli_cmd_tx, ui_event_rx, &mut terminal, state, join_code).await;
 
     // Cleanup — always restore terminal.
-    let _ = execute!(
-        stdout,
-        terminal::LeaveAlternateScreen,
-        cursor::Show
+    let _ = crossterm::execute!(
+        std::io::stdout(),
+        DisableMouseCapture,
+        DisableFocusChange,
+        DisableBracketedPaste
     );
-    let _ = terminal::disable_raw_mode();
+    ratatui::restore();
 
     result
 }
@@ -101,18 +443,25 @@ pub async fn run_cli(
 
 async fn cli_inner(
     cmd_tx: mpsc::UnboundedSender<CliCommand>,
-    mut ui_rx: mpsc::UnboundedReceiver<UiEvent>,
-    stdout: &mut io::Stdout,
-    nickname: String,
+    mut ui_rx: mpsc::UnboundedReceiver<AppEvent>,
+    terminal: &mut DefaultTerminal,
+    mut state: CliState,
+    join_code: Option<String>,
 ) -> Result<()> {
-    let mut state = CliState::new(nickname);
+    // `--join`: skip the menu and go straight to the password prompt.
+    if let Some(code) = join_code {
+        state.join_code = code.trim().to_string();
+        let screen = Screen::JoinRoom {
+            step: 1,
+            by_name: false,
+        };
+        state.open_prompt(screen, "Password (leave blank for none): ", true);
+    }
     let mut event_stream = EventStream::new();
+    let mut last_key = tokio::time::Instant::now();
+    let mut idle = false;
 
-    let mut screen = Screen::MainMenu;
-    let mut create_name = String::new();
-    let mut join_code = String::new();
-
-    draw_main_menu(stdout, &state.nickname)?;
+    terminal.draw(|f| draw(f, &state))?;
 
     loop {
         tokio::select! {
@@ -120,469 +469,2798 @@ async fn cli_inner(
             Some(Ok(event)) = event_stream.next() => {
                 match event {
                     Event::Key(key) => {
-                        let quit = handle_key(
-                            key,
-                            &mut state,
-                            &mut screen,
-                            &mut create_name,
-                            &mut join_code,
-                            &cmd_tx,
-                            stdout,
-                        ).await?;
-                        if quit { break; }
-
-                        // Redraw after input
-                        match &screen {
-                            Screen::MainMenu => draw_main_menu(stdout, &state.nickname)?,
-                            Screen::CreateRoom { .. }
-                            | Screen::JoinRoom { .. }
-                            | Screen::ChangeNickname => {
-                                redraw_prompt(stdout, &state)?
-                            }
-                            Screen::Chat => redraw_chat(stdout, &state)?,
+                        last_key = tokio::time::Instant::now();
+                        if std::mem::take(&mut idle) {
+                            let _ = cmd_tx.send(CliCommand::SetIdle(false));
+                        }
+                        if handle_key(key, &mut state, &cmd_tx) {
+                            break;
                         }
                     }
-
-                    Event::Resize(_, _) => {
-                        match &screen {
-                            Screen::MainMenu => draw_main_menu(stdout, &state.nickname)?,
-                            Screen::Chat => redraw_chat(stdout, &state)?,
-                            _ => {}
+                    // Motion and the other buttons do nothing.
+                    Event::Mouse(mouse) if matches!(
+                        mouse.kind,
+                        MouseEventKind::ScrollUp
+                            | MouseEventKind::ScrollDown
+                            | MouseEventKind::Down(MouseButton::Left)
+                    ) => {
+                        let size = terminal.size()?;
+                        let area = Rect::new(0, 0, size.width, size.height);
+                        if handle_mouse(mouse, area, &mut state, &cmd_tx) {
+                            break;
                         }
                     }
-
-                    _ => {}
+                    Event::Paste(text) => {
+                        last_key = tokio::time::Instant::now();
+                        if std::mem::take(&mut idle) {
+                            let _ = cmd_tx.send(CliCommand::SetIdle(false));
+                        }
+                        handle_paste(text, &mut state);
+                    }
+                    Event::FocusGained => {
+                        let _ = cmd_tx.send(CliCommand::SetFocus(true));
+                        continue;
+                    }
+                    Event::FocusLost => {
+                        let _ = cmd_tx.send(CliCommand::SetFocus(false));
+                        continue;
+                    }
+                    // Layout is recomputed on every draw.
+                    Event::Resize(_, _) => {}
+                    _ => continue,
                 }
             }
 
+            // ── Idle detection ────────────────────────────────────────
+            _ = tokio::time::sleep_until(last_key + IDLE_AFTER), if !idle => {
+                idle = true;
+                let _ = cmd_tx.send(CliCommand::SetIdle(true));
+                continue;
+            }
+
             // ── App event (message, status, navigation) ───────────────
-            Some(ui_event) = ui_rx.recv() => {
-                match ui_event {
-                    UiEvent::NewMessage(msg) => {
-                        state.push_message(msg);
-                        if screen == Screen::Chat {
-                            redraw_chat(stdout, &state)?;
-                        }
+            bus_event = ui_rx.recv() => {
+                match bus_event {
+                    Some(AppEvent::Ui(event)) => handle_ui_event(event, &mut state),
+                    Some(AppEvent::RoomMessage { room, msg }) => state.note_room_message(&room, &msg),
+                    Some(AppEvent::SettingsChanged { theme, format_messages, .. }) => {
+                        state.apply_settings(&theme, format_messages)
                     }
-
-                    UiEvent::StatusUpdate { room, peers } => {
-                        state.current_room = room;
-                        state.peer_count = peers;
-                        if screen == Screen::Chat {
-                            redraw_header(stdout, &state)?;
+                    // Other subscribers handle non-UI events.
+                    Some(_) => continue,
+                    None => break,
+                }
+                // Fold a burst of queued events into a single redraw.
+                loop {
+                    match ui_rx.try_recv() {
+                        Ok(AppEvent::Ui(event)) => handle_ui_event(event, &mut state),
+                        Ok(AppEvent::RoomMessage { room, msg }) => state.note_room_message(&room, &msg),
+                        Ok(AppEvent::SettingsChanged { theme, format_messages, .. }) => {
+                            state.apply_settings(&theme, format_messages)
                         }
+                        Ok(_) => {}
+                        Err(_) => break,
                     }
+                }
+            }
+        }
 
-                    UiEvent::RoomCreated { name, code } => {
-                        state.messages.clear();
-                        state.current_room = Some(name.clone());
-                        state.input_buffer.clear();
-                        state.masking = false;
-                        screen = Screen::Chat;
-
-                        let msg = DisplayMessage::system(&format!(
-                            "Room '{}' created. Share this code: {}",
-                            name, code
-                        ));
-                        state.push_message(msg);
-                        redraw_chat(stdout, &state)?;
-                    }
+        terminal.draw(|f| draw(f, &state))?;
+        if std::mem::take(&mut state.bell) {
+            use std::io::Write;
+            let mut out = std::io::stdout();
+            let _ = out.write_all(b"\x07").and_then(|_| out.flush());
+        }
+    }
+    Ok(())
+}
 
-                    UiEvent::RoomJoined(name) => {
-                        state.messages.clear();
-                        state.current_room = Some(name.clone());
-                        state.input_buffer.clear();
-                        state.masking = false;
-                        screen = Screen::Chat;
+fn handle_ui_event(event: UiEvent, state: &mut CliState) {
+    match event {
+        UiEvent::NewMessage(msg) => state.push_message(msg),
+
+        UiEvent::StatusUpdate {
+            room,
+            subject,
+            thread,
+            peers,
+            rooms,
+            members,
+        } => {
+            state.current_room = room;
+            state.subject = subject;
+            state.thread = thread;
+            state.peer_count = peers;
+            state.unread.retain(|name, _| rooms.contains(name));
+            state.rooms = rooms;
+            state.members = members;
+        }
 
-                        let msg = DisplayMessage::system(&format!("Joined room '{}'", name));
-                        state.push_message(msg);
-                        redraw_chat(stdout, &state)?;
-                    }
+        UiEvent::RoomCreated { name, code } => {
+            state.enter_chat(name.clone());
+            state.push_message(DisplayMessage::system(&format!(
+                "Room '{}' created. Share this code: {}",
+                name, code
+            )));
+            state.qr_code = Some(code);
+        }
 
-                    UiEvent::AccessDenied => {
-                        state.input_buffer.clear();
-                        state.masking = false;
-                        let msg = DisplayMessage::system("Access denied — wrong password.");
-                        state.push_message(msg);
-                        redraw_chat(stdout, &state)?;
-                    }
+        UiEvent::RoomCode { name, code } => {
+            state.push_message(DisplayMessage::system(&format!(
+                "Code for '{}': {}",
+                name, code
+            )));
+            state.qr_code = Some(code);
+        }
 
-                    UiEvent::ShowMainMenu => {
-                        state.messages.clear();
-                        state.input_buffer.clear();
-                        state.current_room = None;
-                        screen = Screen::MainMenu;
-                        draw_main_menu(stdout, &state.nickname)?;
-                    }
+        UiEvent::RoomJoined(name) => {
+            state.enter_chat(name.clone());
+            state.push_message(DisplayMessage::system(&format!("Joined room '{}'", name)));
+            if let Some((_, JoinState::TimedOut)) = state.join_progress.take() {
+                state.push_message(DisplayMessage::system(
+                    "No member answered — password could not be verified.",
+                ));
+            }
+        }
 
-                    UiEvent::NicknameChanged(new_nick) => {
-                        state.nickname = new_nick.clone();
-                        state.input_buffer.clear();
-                        state.prompt_label.clear();
-                        screen = Screen::MainMenu;
-                        draw_main_menu(stdout, &state.nickname)?;
-                    }
+        UiEvent::RoomRenamed { from, to } => {
+            if state.current_room.as_deref() == Some(from.as_str()) {
+                state.current_room = Some(to.clone());
+            }
+            if let Some(unread) = state.unread.remove(&from) {
+                state.unread.insert(to, unread);
+            }
+        }
 
-                    UiEvent::Error(err) => {
-                        let msg = DisplayMessage::system(&format!("[!] {}", err));
-                        state.push_message(msg);
-                        if screen == Screen::Chat {
-                            redraw_chat(stdout, &state)?;
-                        }
-                    }
+        UiEvent::RoomSwitched { name, history } => {
+            // The divider goes above the oldest of the last `unread`
+            // messages that count.
+            let unread = state.unread.remove(&name).unwrap_or(0);
+            state.first_unread = (unread > 0)
+                .then(|| {
+                    history
+                        .iter()
+                        .enumerate()
+                        .rev()
+                        .filter(|(_, m)| counts_as_unread(m))
+                        .nth(unread - 1)
+                        .map(|(i, _)| i)
+                })
+                .flatten();
+            state.messages = history.into();
+            state.scroll = 0;
+            state.current_room = Some(name);
+            state.screen = Screen::Chat;
+            state.notes.clear();
+            state.threads.clear();
+        }
+
+        UiEvent::Bell => state.bell = true,
+
+        UiEvent::MessageDelivered { room, msg_id } => {
+            if state.current_room.as_deref() == Some(room.as_str())
+                && let Some(msg) = state.messages.iter_mut().find(|m| m.msg_id == msg_id)
+            {
+                msg.delivery = Some(Delivery::Delivered);
+            }
+        }
+
+        UiEvent::Reactions {
+            room,
+            msg_id,
+            reactions,
+        } => {
+            if state.current_room.as_deref() == Some(room.as_str())
+                && let Some(msg) = state.messages.iter_mut().find(|m| m.msg_id == msg_id)
+            {
+                msg.reactions = reactions;
+            }
+        }
+
+        UiEvent::PollTally { room, msg_id, poll } => {
+            if state.current_room.as_deref() == Some(room.as_str())
+                && let Some(msg) = state
+                    .messages
+                    .iter_mut()
+                    .find(|m| m.poll.is_some() && m.msg_id == msg_id)
+            {
+                msg.poll = Some(poll);
+            }
+        }
+
+        UiEvent::Notes { room, lines } => {
+            if state.current_room.as_deref() != Some(room.as_str()) {
+                return;
+            }
+            let was_empty = state.notes.is_empty();
+            state.notes = lines;
+            if let Screen::Notes { selected, adding } = &mut state.screen {
+                // Opened before they arrived: add at the end.
+                if was_empty && *adding {
+                    *selected = state.notes.len().saturating_sub(1);
                 }
+                *selected = (*selected).min(state.notes.len().saturating_sub(1));
+                *adding |= state.notes.is_empty();
+            }
+        }
+
+        UiEvent::Threads { room, threads } => {
+            if state.current_room.as_deref() != Some(room.as_str()) {
+                return;
+            }
+            state.threads = threads;
+            if let Screen::Threads { selected } = &mut state.screen {
+                *selected = (*selected).min(state.threads.len());
             }
         }
+
+        UiEvent::PublicRooms(rooms) => {
+            state.public_rooms = rooms;
+            if let Screen::PublicRooms { selected } = &mut state.screen {
+                *selected = (*selected).min(state.public_rooms.len().saturating_sub(1));
+            }
+        }
+
+        UiEvent::JoinProgress {
+            room,
+            state: join_state,
+        } => {
+            state.join_progress = Some((room, join_state));
+        }
+
+        UiEvent::AccessDenied(why) => {
+            let why = why.unwrap_or_else(|| "wrong password".to_string());
+            state.turned_away(&format!("Access denied — {why}."));
+        }
+
+        UiEvent::RoomFull { room, max_members } => {
+            state.turned_away(&format!(
+                "Room is full — '{room}' holds at most {max_members} members."
+            ));
+        }
+
+        UiEvent::ShowMainMenu => {
+            state.join_progress = None;
+            state.messages.clear();
+            state.first_unread = None;
+            state.current_room = None;
+            state.back_to_menu();
+        }
+
+        UiEvent::NicknameChanged(new_nick) => {
+            state.nickname = new_nick;
+            state.back_to_menu();
+        }
+
+        UiEvent::AvatarChanged(avatar) => {
+            state.avatar = avatar;
+            if state.screen == Screen::ChangeAvatar {
+                state.back_to_menu();
+            }
+        }
+
+        UiEvent::Error(err) => {
+            state.push_message(DisplayMessage::system(&format!("[!] {}", err)));
+        }
+
+        UiEvent::Commands(commands) => state.commands = commands,
+
+        UiEvent::RecentRooms(recent) => {
+            state.pruning &= !recent.is_empty();
+            state.recent = recent;
+        }
+
+        UiEvent::ShowHelp => {
+            state.palette = None;
+            state.help_scroll = Some(0);
+        }
     }
-    Ok(())
 }
 
 // ── Key handling ──────────────────────────────────────────────────────────────
 
 /// Returns `true` when the user confirmed quit.
-async fn handle_key(
+fn handle_key(
     key: KeyEvent,
     state: &mut CliState,
-    screen: &mut Screen,
-    create_name: &mut String,
-    join_code: &mut String,
     cmd_tx: &mpsc::UnboundedSender<CliCommand>,
-    stdout: &mut io::Stdout,
-) -> Result<bool> {
+) -> bool {
     // Ignore key-release and key-repeat events (Windows sends both Press and Release).
     if key.kind == KeyEventKind::Release {
-        return Ok(false);
+        return false;
     }
 
     // Ctrl-C anywhere → quit
-    if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+    if state.keys.quit.matches(&key) {
         let _ = cmd_tx.send(CliCommand::Quit);
-        return Ok(true);
+        return true;
+    }
+
+    // Alt+1…9 → switch to the Nth joined room
+    if key.modifiers.contains(state.keys.switch_room)
+        && matches!(state.screen, Screen::Chat | Screen::MainMenu)
+        && let KeyCode::Char(c @ '1'..='9') = key.code
+    {
+        let index = c as usize - '1' as usize;
+        if let Some(name) = state.rooms.get(index) {
+            let _ = cmd_tx.send(CliCommand::SwitchRoom(name.clone()));
+        }
+        return false;
+    }
+
+    // Enter takes a paste waiting to be confirmed, Esc drops it.
+    if let Some(text) = state.pending_paste.take() {
+        match key.code {
+            KeyCode::Enter => state.input.paste(&text, true),
+            KeyCode::Esc => {}
+            _ => state.pending_paste = Some(text),
+        }
+        return false;
+    }
+
+    // Any key closes the QR popup.
+    if state.screen == Screen::Chat && state.qr_code.take().is_some() {
+        return false;
     }
 
-    match screen {
+    // Arrows and pages scroll the help; any other key closes it.
+    if let Some(scroll) = state.help_scroll {
+        state.help_scroll = match key.code {
+            KeyCode::Up => Some(scroll.saturating_sub(1)),
+            KeyCode::Down => Some(scroll + 1),
+            KeyCode::PageUp => Some(scroll.saturating_sub(page_size())),
+            KeyCode::PageDown => Some(scroll + page_size()),
+            _ if state.keys.scroll_up.matches(&key) => Some(scroll.saturating_sub(page_size())),
+            _ if state.keys.scroll_down.matches(&key) => Some(scroll + page_size()),
+            _ => None,
+        }
+        .map(|scroll| scroll.min(help_max_scroll(state)));
+        return false;
+    }
+    if state.palette.is_some() {
+        handle_palette_key(key, state, cmd_tx);
+        return false;
+    }
+    if key.code == KeyCode::F(1) && matches!(state.screen, Screen::Chat | Screen::MainMenu) {
+        state.help_scroll = Some(0);
+        return false;
+    }
+
+    match state.screen {
         // ── Main menu ─────────────────────────────────────────────────
         Screen::MainMenu => match key.code {
             KeyCode::Char('1') => {
-                *screen = Screen::CreateRoom { step: 0 };
-                state.input_buffer.clear();
-                state.prompt_label = "Room name: ".to_string();
-                draw_prompt(stdout, "Room name: ", false)?;
+                state.open_prompt(
+                    Screen::CreateRoom { step: 0 },
+                    "Room name [max n] [as nick +key]: ",
+                    false,
+                );
             }
             KeyCode::Char('2') => {
-                *screen = Screen::JoinRoom { step: 0 };
-                state.input_buffer.clear();
-                state.prompt_label = "Room code: ".to_string();
-                draw_prompt(stdout, "Room code: ", false)?;
+                let screen = Screen::JoinRoom {
+                    step: 0,
+                    by_name: false,
+                };
+                state.open_prompt(screen, "Room code [as nick +key]: ", false);
             }
             KeyCode::Char('3') => {
-                *screen = Screen::ChangeNickname;
-                state.input_buffer.clear();
                 let label = format!("New nickname (current: {}): ", state.nickname);
-                state.prompt_label = label.clone();
-                draw_prompt(stdout, &label, false)?;
+                state.open_prompt(Screen::ChangeNickname, &label, false);
+            }
+            KeyCode::Char('4') => {
+                let screen = Screen::JoinRoom {
+                    step: 0,
+                    by_name: true,
+                };
+                state.open_prompt(screen, "Room name [as nick +key]: ", false);
+            }
+            KeyCode::Char('b') | KeyCode::Char('B') => {
+                state.screen = Screen::PublicRooms { selected: 0 };
+                let _ = cmd_tx.send(CliCommand::BrowseRooms);
+            }
+            KeyCode::Char('a') | KeyCode::Char('A') => {
+                let current = state.avatar.as_deref().unwrap_or("none");
+                let label =
+                    format!("Avatar, one emoji or character (current: {current}; - for none): ");
+                state.open_prompt(Screen::ChangeAvatar, &label, false);
+            }
+            KeyCode::Char(c @ '5'..='9') => {
+                let index = c as usize - FIRST_RECENT_KEY as usize;
+                if let Some(room) = state.recent.get(index).cloned() {
+                    if std::mem::take(&mut state.pruning) {
+                        let _ = cmd_tx.send(CliCommand::ForgetRecent(room.name));
+                    } else {
+                        open_recent(room, state, cmd_tx);
+                    }
+                }
+            }
+            KeyCode::Char('x') | KeyCode::Char('X') if !state.recent.is_empty() => {
+                state.pruning = !state.pruning;
             }
             KeyCode::Char('q') | KeyCode::Char('Q') => {
                 let _ = cmd_tx.send(CliCommand::Quit);
-                return Ok(true);
+                return true;
+            }
+            KeyCode::Esc if state.pruning => state.pruning = false,
+            // Back to the active room (opened with /menu)
+            KeyCode::Esc if state.current_room.is_some() => {
+                state.screen = Screen::Chat;
             }
             _ => {}
         },
 
         // ── Create room ───────────────────────────────────────────────
         Screen::CreateRoom { step } => match key.code {
-            KeyCode::Enter => {
-                match step {
-                    0 => {
-                        *create_name = state.input_buffer.trim().to_string();
-                        state.input_buffer.clear();
-                        *step = 1;
-                        state.masking = true;
-                        state.prompt_label = "Password (leave blank for none): ".to_string();
-                        draw_prompt(stdout, "Password (leave blank for none): ", true)?;
-                    }
-                    _ => {
-                        let password = state.input_buffer.clone();
-                        let name = create_name.clone();
-                        state.input_buffer.clear();
-                        state.masking = false;
-                        let _ = cmd_tx.send(CliCommand::CreateRoom { name, password });
-                    }
-                }
+            KeyCode::Enter if step == 0 => {
+                let (name, persona) = split_persona(state.input.as_str());
+                let (name, max_members) = split_capacity(name);
+                state.create_name = name.to_string();
+                state.create_max = max_members;
+                state.entry_persona = persona;
+                state.open_prompt(
+                    Screen::CreateRoom { step: 1 },
+                    "Password (leave blank for none): ",
+                    true,
+                );
             }
-            KeyCode::Esc => {
-                state.input_buffer.clear();
+            KeyCode::Enter => {
+                let password = state.input.take();
+                let name = state.create_name.clone();
                 state.masking = false;
-                *screen = Screen::MainMenu;
+                let persona = state.entry_persona.take();
+                let max_members = state.create_max;
+                let _ = cmd_tx.send(CliCommand::CreateRoom {
+                    name,
+                    password,
+                    persona,
+                    max_members,
+                });
+            }
+            KeyCode::Esc => state.back_to_menu(),
+            _ => {
+                state.input.handle_key(key);
             }
-            _ => handle_text_input(key, &mut state.input_buffer),
         },
 
         // ── Join room ─────────────────────────────────────────────────
-        Screen::JoinRoom { step } => match key.code {
+        Screen::JoinRoom { step, by_name } => match key.code {
+            KeyCode::Esc => state.back_to_menu(),
+            // Rejoining with the saved password: nothing to type.
+            _ if step == 2 => {}
+            KeyCode::Enter if step == 0 => {
+                let (code, persona) = split_persona(state.input.as_str());
+                state.join_code = code.to_string();
+                state.entry_persona = persona;
+                state.open_prompt(
+                    Screen::JoinRoom { step: 1, by_name },
+                    "Password (leave blank for none): ",
+                    true,
+                );
+            }
             KeyCode::Enter => {
-                match step {
-                    0 => {
-                        *join_code = state.input_buffer.trim().to_string();
-                        state.input_buffer.clear();
-                        *step = 1;
-                        state.masking = true;
-                        state.prompt_label = "Password (leave blank for none): ".to_string();
-                        draw_prompt(stdout, "Password (leave blank for none): ", true)?;
+                let password = state.input.take();
+                let code = state.join_code.clone();
+                state.masking = false;
+                let persona = state.entry_persona.take();
+                let _ = cmd_tx.send(if by_name {
+                    CliCommand::JoinRoomByName {
+                        name: code,
+                        password,
+                        persona,
                     }
-                    _ => {
-                        let password = state.input_buffer.clone();
-                        let code = join_code.clone();
-                        state.input_buffer.clear();
-                        state.masking = false;
-                        let _ = cmd_tx.send(CliCommand::JoinRoom { code, password });
+                } else {
+                    CliCommand::JoinRoom {
+                        code,
+                        password,
+                        persona,
                     }
-                }
+                });
             }
-            KeyCode::Esc => {
-                state.input_buffer.clear();
-                state.masking = false;
-                *screen = Screen::MainMenu;
+            _ => {
+                state.input.handle_key(key);
             }
-            _ => handle_text_input(key, &mut state.input_buffer),
         },
 
         // ── Change nickname ───────────────────────────────────────────
         Screen::ChangeNickname => match key.code {
             KeyCode::Enter => {
-                let new_nick = state.input_buffer.trim().to_string();
-                state.input_buffer.clear();
-                state.prompt_label.clear();
-                if !new_nick.is_empty() {
-                    let _ = cmd_tx.send(CliCommand::ChangeNickname(new_nick));
-                } else {
+                let new_nick = state.input.as_str().trim().to_string();
+                if new_nick.is_empty() {
                     // Empty input → cancel, return to menu
-                    *screen = Screen::MainMenu;
-                    draw_main_menu(stdout, &state.nickname)?;
+                    state.back_to_menu();
+                } else {
+                    state.input.clear();
+                    let _ = cmd_tx.send(CliCommand::ChangeNickname(new_nick));
                 }
             }
-            KeyCode::Esc => {
-                state.input_buffer.clear();
-                state.prompt_label.clear();
-                *screen = Screen::MainMenu;
+            KeyCode::Esc => state.back_to_menu(),
+            _ => {
+                state.input.handle_key(key);
+            }
+        },
+
+        // ── Change avatar ─────────────────────────────────────────────
+        Screen::ChangeAvatar => match key.code {
+            KeyCode::Enter => match state.input.take().trim() {
+                "" => state.back_to_menu(),
+                "-" => {
+                    let _ = cmd_tx.send(CliCommand::ChangeAvatar(String::new()));
+                }
+                avatar => {
+                    let _ = cmd_tx.send(CliCommand::ChangeAvatar(avatar.to_string()));
+                }
+            },
+            KeyCode::Esc => state.back_to_menu(),
+            _ => {
+                state.input.handle_key(key);
             }
-            _ => handle_text_input(key, &mut state.input_buffer),
         },
 
         // ── Chat ──────────────────────────────────────────────────────
         Screen::Chat => match key.code {
-            KeyCode::Enter => {
-                let input = state.input_buffer.trim().to_string();
-                state.input_buffer.clear();
-                if !input.is_empty() {
-                    match input.as_str() {
-                        "/quit" => {
-                            let _ = cmd_tx.send(CliCommand::LeaveRoom);
-                        }
-                        "/peers" => {
-                            let _ = cmd_tx.send(CliCommand::ListPeers);
-                        }
-                        "/help" => {
-                            let _ = cmd_tx.send(CliCommand::Help);
-                        }
-                        _ if input.starts_with('/') => {
-                            let _ = cmd_tx.send(CliCommand::Help);
-                        }
-                        _ => {
-                            let _ = cmd_tx.send(CliCommand::SendMessage(input));
-                        }
-                    }
+            _ if state.keys.scroll_up.matches(&key) => state.scroll_by(page_size() as isize),
+            _ if state.keys.scroll_down.matches(&key) => state.scroll_by(-(page_size() as isize)),
+            _ if state.keys.command_palette.matches(&key) => {
+                state.palette = Some(Palette::default())
+            }
+            _ if state.keys.send.matches(&key) => {
+                let input = state.input.take();
+                state.scroll = 0;
+                if input.trim().is_empty() {
+                } else if input.contains('\n') {
+                    // A multi-line paste is one message, and never a
+                    // command: a pasted `/leave` line is just text.
+                    let text = input.trim_matches('\n').to_string();
+                    let _ = cmd_tx.send(chat_command(text, state));
+                } else {
+                    handle_chat_input(input, state, cmd_tx);
                 }
             }
+            KeyCode::Home if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                state.scroll = state.messages.len();
+            }
+            KeyCode::End if key.modifiers.contains(KeyModifiers::CONTROL) => state.scroll = 0,
+            KeyCode::Up if key.modifiers.contains(KeyModifiers::ALT) => state.select_reply(true),
+            KeyCode::Down if key.modifiers.contains(KeyModifiers::ALT) => state.select_reply(false),
+            KeyCode::Esc if state.reply_to.is_some() => state.reply_to = None,
+            KeyCode::Char('?') if state.input.as_str().is_empty() => state.help_scroll = Some(0),
             _ => {
-                if state.input_buffer.len() < MAX_INPUT_LEN {
-                    handle_text_input(key, &mut state.input_buffer);
+                // Typing snaps back to the newest messages.
+                if state.input.handle_key(key) {
+                    state.scroll = 0;
                 }
             }
         },
-    }
-    Ok(false)
-}
-
-fn handle_text_input(key: KeyEvent, buf: &mut String) {
-    match key.code {
-        KeyCode::Char(c) => buf.push(c),
-        KeyCode::Backspace => { buf.pop(); }
-        _ => {}
-    }
-}
-
-// ── Drawing ───────────────────────────────────────────────────────────────────
-
-fn draw_main_menu(stdout: &mut io::Stdout, nickname: &str) -> Result<()> {
-    let (width, height) = terminal::size()?;
-    execute!(stdout, terminal::Clear(ClearType::All))?;
-
-    let title = "=== P2P Chat ===";
-    let logged_in = format!("Logged in as: {}", nickname);
-    let items = [
-        "[1] Create room",
-        "[2] Join room",
-        "[3] Change nickname",
-        "[Q] Quit",
-    ];
-
-    let start_row = height / 2 - 4;
-    let col = (width / 2).saturating_sub(12);
 
-    execute!(stdout, cursor::MoveTo(col, start_row))?;
-    execute!(stdout, style::PrintStyledContent(title.bold()))?;
+        // ── Shared notes ──────────────────────────────────────────────
+        Screen::Notes { selected, adding } => match key.code {
+            KeyCode::Esc => {
+                state.input.clear();
+                state.screen = Screen::Chat;
+            }
+            KeyCode::Up if adding => state.pick_note(selected),
+            KeyCode::Up => state.pick_note(selected.saturating_sub(1)),
+            KeyCode::Down if adding || selected + 1 < state.notes.len() => {
+                state.pick_note(selected + usize::from(!adding));
+            }
+            KeyCode::Down => {
+                state.input.clear();
+                state.screen = Screen::Notes {
+                    selected,
+                    adding: true,
+                };
+            }
+            KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                state.input.clear();
+                state.screen = Screen::Notes {
+                    selected,
+                    adding: true,
+                };
+            }
+            KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) && !adding => {
+                if let Some(&(id, _)) = state.notes.get(selected) {
+                    state.input.clear();
+                    let _ = cmd_tx.send(CliCommand::EditNote(NoteEdit::Delete(id)));
+                }
+            }
+            _ if state.keys.send.matches(&key) => {
+                let text = state.input.as_str().to_string();
+                let line = state.notes.get(selected);
+                if adding {
+                    let after = line.map(|&(id, _)| id);
+                    state.input.clear();
+                    // The new line shows up right below; keep adding under it.
+                    let selected = if after.is_some() { selected + 1 } else { 0 };
+                    state.screen = Screen::Notes {
+                        selected,
+                        adding: true,
+                    };
+                    let _ = cmd_tx.send(CliCommand::EditNote(NoteEdit::Insert { after, text }));
+                } else if let Some((id, old)) = line
+                    && *old != text
+                {
+                    let _ = cmd_tx.send(CliCommand::EditNote(NoteEdit::Set { id: *id, text }));
+                }
+            }
+            _ => {
+                state.input.handle_key(key);
+            }
+        },
 
-    execute!(stdout, cursor::MoveTo(col, start_row + 1))?;
-    execute!(stdout, style::PrintStyledContent(logged_in.dark_grey()))?;
+        // ── Threads ───────────────────────────────────────────────────
+        Screen::Threads { selected } => match key.code {
+            KeyCode::Esc => state.screen = Screen::Chat,
+            KeyCode::Up => {
+                state.screen = Screen::Threads {
+                    selected: selected.saturating_sub(1),
+                }
+            }
+            KeyCode::Down => {
+                state.screen = Screen::Threads {
+                    selected: (selected + 1).min(state.threads.len()),
+                };
+            }
+            KeyCode::Enter => {
+                // Row 0 is the room itself.
+                let thread = selected
+                    .checked_sub(1)
+                    .and_then(|i| state.threads.get(i))
+                    .map(|t| t.name.clone());
+                state.screen = Screen::Chat;
+                let _ = cmd_tx.send(CliCommand::OpenThread(thread));
+            }
+            _ => {}
+        },
 
-    for (i, item) in items.iter().enumerate() {
-        execute!(stdout, cursor::MoveTo(col, start_row + 3 + i as u16))?;
-        execute!(stdout, style::Print(item))?;
+        // ── Public rooms ──────────────────────────────────────────────
+        Screen::PublicRooms { selected } => match key.code {
+            KeyCode::Esc => state.back_to_menu(),
+            KeyCode::Up => {
+                state.screen = Screen::PublicRooms {
+                    selected: selected.saturating_sub(1),
+                }
+            }
+            KeyCode::Down => {
+                let last = state.public_rooms.len().saturating_sub(1);
+                state.screen = Screen::PublicRooms {
+                    selected: (selected + 1).min(last),
+                };
+            }
+            KeyCode::Enter => {
+                if let Some(room) = state.public_rooms.get(selected) {
+                    let label = format!("Password for '{}' (leave blank for none): ", room.name);
+                    state.join_code = room.code.clone();
+                    state.entry_persona = None;
+                    state.join_progress = None;
+                    state.open_prompt(
+                        Screen::JoinRoom {
+                            step: 1,
+                            by_name: false,
+                        },
+                        &label,
+                        true,
+                    );
+                }
+            }
+            _ => {}
+        },
     }
-
-    execute!(stdout, cursor::MoveTo(col, start_row + 8))?;
-    execute!(stdout, style::Print("> "))?;
-    execute!(stdout, cursor::Show)?;
-    stdout.flush()?;
-    Ok(())
+    false
 }
 
-fn draw_prompt(stdout: &mut io::Stdout, label: &str, _masking: bool) -> Result<()> {
-    let (_, height) = terminal::size()?;
-    execute!(stdout, cursor::MoveTo(0, height - 1), terminal::Clear(ClearType::CurrentLine))?;
-    execute!(stdout, style::Print(label))?;
-    execute!(stdout, cursor::Show)?;
-    stdout.flush()?;
-    Ok(())
+/// Pasted `text`: into the palette query or the input, whole.  In the chat
+/// a long paste waits for Enter, and its lines stay apart (each one is sent
+/// as a message); in the prompts it is one line.
+fn handle_paste(text: String, state: &mut CliState) {
+    if let Some(palette) = &mut state.palette {
+        palette.query.paste(&text, false);
+        return;
+    }
+    match state.screen {
+        Screen::MainMenu => {}
+        Screen::Chat => {
+            state.qr_code = None;
+            state.help_scroll = None;
+            state.scroll = 0;
+            if text.chars().count() > PASTE_CONFIRM_CHARS
+                || text.lines().count() > PASTE_CONFIRM_LINES
+            {
+                state.pending_paste = Some(text);
+            } else {
+                state.input.paste(&text, true);
+            }
+        }
+        _ => state.input.paste(&text, false),
+    }
 }
 
-fn redraw_prompt(stdout: &mut io::Stdout, state: &CliState) -> Result<()> {
-    let (width, height) = terminal::size()?;
-    execute!(stdout, cursor::MoveTo(0, height - 1), terminal::Clear(ClearType::CurrentLine))?;
-
-    let input_display = if state.masking {
-        "•".repeat(state.input_buffer.len())
-    } else {
-        state.input_buffer.clone()
-    };
-
-    // Scroll to end: only show the tail of the input that fits on one line.
-    // This prevents long inputs (e.g. room codes) from wrapping and leaving
-    // uncleared artefacts on previous lines.
-    let label_len = state.prompt_label.chars().count();
-    let available = (width as usize).saturating_sub(label_len);
-    let char_count = input_display.chars().count();
-    let visible_input: String = if char_count > available {
-        input_display.chars().skip(char_count - available).collect()
-    } else {
-        input_display
+/// A key while the command palette is open: edit the query, move the
+/// pick, run it (Enter) or close (Esc).  A command that needs arguments is
+/// put in the input to finish instead of run.
+fn handle_palette_key(
+    key: KeyEvent,
+    state: &mut CliState,
+    cmd_tx: &mpsc::UnboundedSender<CliCommand>,
+) {
+    let Some(palette) = &mut state.palette else {
+        return;
     };
-
-    execute!(stdout, style::Print(format!("{}{}", state.prompt_label, visible_input)))?;
-    execute!(stdout, cursor::Show)?;
-    stdout.flush()?;
-    Ok(())
-}
-
-fn redraw_chat(stdout: &mut io::Stdout, state: &CliState) -> Result<()> {
-    let (width, height) = terminal::size()?;
-    let w = width as usize;
-    let h = height as u16;
-
-    // ── Header (row 0) ──────────────────────────────────────────────
-    execute!(stdout, cursor::MoveTo(0, 0), terminal::Clear(ClearType::CurrentLine))?;
-    let room_str = state
-        .current_room
-        .as_deref()
-        .unwrap_or("(no room)");
-    let header = format!(
-        " Room: {}  |  {} peer(s) online",
-        room_str, state.peer_count
-    );
-    let header_truncated = truncate_str(&header, w);
-    execute!(stdout, style::PrintStyledContent(header_truncated.clone().on(Color::DarkBlue).white()))?;
-
-    // Pad remainder of header row
-    let pad = w.saturating_sub(header_truncated.len());
-    if pad > 0 {
-        execute!(stdout, style::PrintStyledContent(" ".repeat(pad).on(Color::DarkBlue)))?;
+    match key.code {
+        KeyCode::Esc => state.palette = None,
+        KeyCode::Up => palette.selected = palette.selected.saturating_sub(1),
+        KeyCode::Down => {
+            let last = palette_matches(&state.commands, palette.query.as_str())
+                .len()
+                .saturating_sub(1);
+            palette.selected = (palette.selected + 1).min(last);
+        }
+        KeyCode::Enter => {
+            let matches = palette_matches(&state.commands, palette.query.as_str());
+            let picked = matches.get(palette.selected.min(matches.len().saturating_sub(1)));
+            let Some(usage) = picked.map(|&i| state.commands[i].0.clone()) else {
+                return;
+            };
+            state.palette = None;
+            let name = usage
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            if usage.contains('<') {
+                state.input.clear();
+                for c in format!("{name} ").chars() {
+                    state.input.handle_key(KeyEvent::from(KeyCode::Char(c)));
+                }
+            } else {
+                state.scroll = 0;
+                handle_chat_input(name, state, cmd_tx);
+            }
+        }
+        _ => {
+            if palette.query.handle_key(key) {
+                palette.selected = 0;
+            }
+        }
     }
+}
 
-    // ── Separator (row 1) ────────────────────────────────────────────
-    execute!(stdout, cursor::MoveTo(0, 1), terminal::Clear(ClearType::CurrentLine))?;
-    execute!(stdout, style::Print("\u{2500}".repeat(w)))?;
-
-    // ── Messages (rows 2 .. h-3) ─────────────────────────────────────
-    let msg_area_height = (h.saturating_sub(4)) as usize;
-    let msgs: Vec<&DisplayMessage> = state
-        .messages
+/// Indices into `commands` whose usage or description fuzzily matches
+/// `query`, best first; all of them for an empty query.
+fn palette_matches(commands: &[(String, String)], query: &str) -> Vec<usize> {
+    let mut scored: Vec<(i32, usize)> = commands
         .iter()
-        .rev()
-        .take(msg_area_height)
-        .collect::<Vec<_>>()
-        .into_iter()
-        .rev()
+        .enumerate()
+        .filter_map(|(i, (usage, about))| {
+            // A match in the command itself beats one in its description.
+            let score = fuzzy_score(query, usage)
+                .map(|s| s + 100)
+                .or_else(|| fuzzy_score(query, &format!("{usage} {about}")))?;
+            Some((score, i))
+        })
         .collect();
+    // Stable: equally good matches keep the help's order.
+    scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    scored.into_iter().map(|(_, i)| i).collect()
+}
 
-    for row in 0..msg_area_height {
-        let screen_row = (row + 2) as u16;
-        execute!(stdout, cursor::MoveTo(0, screen_row), terminal::Clear(ClearType::CurrentLine))?;
-        if let Some(msg) = msgs.get(row) {
-            let rendered = msg.render(w);
-            if msg.is_system {
-                execute!(stdout, style::PrintStyledContent(rendered.dark_grey()))?;
-            } else {
-                execute!(stdout, style::Print(rendered))?;
-            }
+/// How well `query` matches `text` as a subsequence, ignoring case and the
+/// query's spaces: `None` unless its characters all appear in order, and
+/// more for characters that follow each other or start a word, less for
+/// characters skipped in between.
+fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+    for wanted in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let at = (next..text.len()).find(|&i| text[i] == wanted)?;
+        score += 1 - (at - next).min(5) as i32;
+        if previous.is_some_and(|p| p + 1 == at) {
+            score += 3;
         }
+        if at == 0 || !text[at - 1].is_alphanumeric() {
+            score += 2;
+        }
+        previous = Some(at);
+        next = at + 1;
     }
+    Some(score)
+}
 
-    // ── Separator (row h-2) ──────────────────────────────────────────
-    execute!(stdout, cursor::MoveTo(0, h - 2), terminal::Clear(ClearType::CurrentLine))?;
-    execute!(stdout, style::Print("\u{2500}".repeat(w)))?;
+/// A wheel turn or left click at the terminal size `area`: the wheel
+/// scrolls the chat, a click in the input box puts the cursor there, and a
+/// click on a main menu entry picks it.  Returns `true` when that was Quit.
+fn handle_mouse(
+    mouse: MouseEvent,
+    area: Rect,
+    state: &mut CliState,
+    cmd_tx: &mpsc::UnboundedSender<CliCommand>,
+) -> bool {
+    let at = Position::new(mouse.column, mouse.row);
+    // Over the help, the wheel scrolls it and a click closes it.
+    if let Some(scroll) = state.help_scroll {
+        state.help_scroll = match mouse.kind {
+            MouseEventKind::ScrollUp => Some(scroll.saturating_sub(WHEEL_STEP)),
+            MouseEventKind::ScrollDown => Some((scroll + WHEEL_STEP).min(help_max_scroll(state))),
+            _ => None,
+        };
+        return false;
+    }
+    if state.palette.is_some() {
+        return false;
+    }
+    match (&state.screen, mouse.kind) {
+        (Screen::Chat, MouseEventKind::ScrollUp) => state.scroll_by(WHEEL_STEP as isize),
+        (Screen::Chat, MouseEventKind::ScrollDown) => state.scroll_by(-(WHEEL_STEP as isize)),
+        (Screen::Chat, _) => {
+            // Like any key, a click closes the QR popup.
+            if state.qr_code.take().is_some() {
+                return false;
+            }
+            let [_, _, input] = chat_layout(area);
+            let field = Block::new().borders(Borders::ALL).inner(input);
+            if input.contains(at) {
+                // After the "> " prompt.
+                let column = at.x.saturating_sub(field.x + 2) as usize;
+                state
+                    .input
+                    .click(column, (field.width as usize).saturating_sub(2));
+            }
+        }
+        (Screen::MainMenu, MouseEventKind::Down(_)) => {
+            let lines = menu_lines(state);
+            let menu = menu_area(area, &lines);
+            let row = at.y.wrapping_sub(menu.y) as usize;
+            if let Some(&(_, Some(code))) = lines.get(row)
+                && menu.contains(at)
+            {
+                return handle_key(KeyEvent::from(code), state, cmd_tx);
+            }
+        }
+        _ => {}
+    }
+    false
+}
 
-    // ── Input bar (row h-1) ──────────────────────────────────────────
-    execute!(stdout, cursor::MoveTo(0, h - 1), terminal::Clear(ClearType::CurrentLine))?;
-    let input_display = format!("> {}", state.input_buffer);
-    let input_truncated = truncate_str(&input_display, w);
-    execute!(stdout, style::Print(&input_truncated))?;
+/// A recent room was picked on the main menu: rejoin it with the saved
+/// password, or ask for the password with its code (or name) filled in.
+fn open_recent(room: RecentRoom, state: &mut CliState, cmd_tx: &mpsc::UnboundedSender<CliCommand>) {
+    let by_name = room.code.is_empty();
+    state.join_progress = None;
+    if room.saved {
+        let label = format!("Rejoining '{}' with the saved password… ", room.name);
+        state.open_prompt(Screen::JoinRoom { step: 2, by_name }, &label, false);
+        let _ = cmd_tx.send(CliCommand::Rejoin(Some(room.name)));
+        return;
+    }
+    let label = format!("Password for '{}' (leave blank for none): ", room.name);
+    state.join_code = if by_name { room.name } else { room.code };
+    state.entry_persona = None;
+    state.open_prompt(Screen::JoinRoom { step: 1, by_name }, &label, true);
+}
 
-    // Position cursor at end of input
-    let cursor_x = input_truncated.len().min(w.saturating_sub(1)) as u16;
-    execute!(stdout, cursor::MoveTo(cursor_x, h - 1), cursor::Show)?;
+/// `/mouse`: capture the mouse, or leave it to the terminal so text can be
+/// selected as usual.
+fn toggle_mouse(state: &mut CliState) {
+    state.mouse = !state.mouse;
+    let (result, text) = if state.mouse {
+        (
+            crossterm::execute!(std::io::stdout(), EnableMouseCapture),
+            "Mouse on: the wheel scrolls, clicks pick and place the cursor. /mouse to select text.",
+        )
+    } else {
+        (
+            crossterm::execute!(std::io::stdout(), DisableMouseCapture),
+            "Mouse off: select text as usual. /mouse to turn it back on.",
+        )
+    };
+    match result {
+        Ok(()) => state.push_message(DisplayMessage::system(text)),
+        Err(e) => state.push_message(DisplayMessage::system(&format!(
+            "[!] Cannot change mouse capture: {e}"
+        ))),
+    }
+}
 
-    stdout.flush()?;
-    Ok(())
+/// A line submitted in the chat screen: a `/command` or a message.
+fn handle_chat_input(
+    input: String,
+    state: &mut CliState,
+    cmd_tx: &mpsc::UnboundedSender<CliCommand>,
+) {
+    let cmd = match input.as_str() {
+        "/menu" => {
+            state.screen = Screen::MainMenu;
+            return;
+        }
+        "/mouse" => {
+            toggle_mouse(state);
+            return;
+        }
+        "/notes" => {
+            state.notes.clear();
+            state.screen = Screen::Notes {
+                selected: 0,
+                adding: true,
+            };
+            CliCommand::ShowNotes
+        }
+        "/threads" => {
+            state.threads.clear();
+            state.screen = Screen::Threads { selected: 0 };
+            CliCommand::ShowThreads
+        }
+        "/browse" => {
+            state.screen = Screen::PublicRooms { selected: 0 };
+            CliCommand::BrowseRooms
+        }
+        _ if !input.starts_with('/') => chat_command(input, state),
+        _ => match parse_command(&input, &state.rooms, &state.messages, &state.notes) {
+            Ok(cmd) => cmd,
+            Err(notice) => {
+                state.push_message(DisplayMessage::system(&notice));
+                return;
+            }
+        },
+    };
+    let _ = cmd_tx.send(cmd);
 }
 
-fn redraw_header(stdout: &mut io::Stdout, state: &CliState) -> Result<()> {
-    let (width, _) = terminal::size()?;
-    let w = width as usize;
+/// Send `text` as a chat message, in reply to the selected one if any.
+fn chat_command(text: String, state: &mut CliState) -> CliCommand {
+    // Speaking up means the user has caught up.
+    state.first_unread = None;
+    match state.reply_to.take() {
+        Some(to) => CliCommand::Reply { to, text },
+        None => CliCommand::SendMessage(text),
+    }
+}
 
-    execute!(stdout, cursor::MoveTo(0, 0), terminal::Clear(ClearType::CurrentLine))?;
-    let room_str = state.current_room.as_deref().unwrap_or("(no room)");
-    let header = format!(
-        " Room: {}  |  {} peer(s) online",
-        room_str, state.peer_count
+/// Split a room name or code typed to create or join the room from the
+/// persona after ` as `: a nickname, `+key` for a key of its own, both, or
+/// `-` for the usual identity.  No ` as ` gives `None`: whoever we were
+/// there last time.
+pub(crate) fn split_persona(entry: &str) -> (&str, Option<Persona>) {
+    let Some((room, who)) = entry.trim().rsplit_once(" as ") else {
+        return (entry.trim(), None);
+    };
+    let mut persona = Persona::default();
+    let mut nick = Vec::new();
+    for word in who.split_whitespace() {
+        match word {
+            "+key" => persona.own_key = true,
+            "-" => {}
+            _ => nick.push(word),
+        }
+    }
+    if !nick.is_empty() {
+        persona.nickname = Some(nick.join(" "));
+    }
+    (room.trim(), Some(persona))
+}
+
+/// Split a room name typed to create the room from the member limit after
+/// ` max `; 0 without one.
+pub(crate) fn split_capacity(name: &str) -> (&str, u32) {
+    if let Some((room, max)) = name.rsplit_once(" max ")
+        && let Ok(max @ 1..) = max.trim().parse::<u32>()
+    {
+        return (room.trim(), max);
+    }
+    (name, 0)
+}
+
+/// The command a `/command` line stands for, shared with the plain
+/// frontend.  `rooms` are the joined rooms (`/switch 2`), `messages` the
+/// active room's (`/reply`), `notes` its notes as last shown (`/note set
+/// 2 …`).  Fails with what to tell the user.
+pub(crate) fn parse_command(
+    input: &str,
+    rooms: &[String],
+    messages: &VecDeque<DisplayMessage>,
+    notes: &[(NoteId, String)],
+) -> Result<CliCommand, String> {
+    let cmd = match input {
+        "/quit" => CliCommand::LeaveRoom,
+        "/peers" => CliCommand::ListPeers,
+        "/mentions" => CliCommand::ListMentions,
+        "/links" => CliCommand::ListLinks,
+        "/help" => CliCommand::Help,
+        "/rooms" => CliCommand::ListRooms,
+        "/mute" => CliCommand::ToggleMute,
+        "/reload" => CliCommand::ReloadConfig,
+        "/rekey" => CliCommand::Rekey,
+        "/contacts" => CliCommand::ListContacts,
+        "/blocks" => CliCommand::ListBlocked,
+        "/history" => CliCommand::History(None),
+        "/export" => CliCommand::Export(ExportFormat::default()),
+        "/back" => CliCommand::SetPresence(Presence::default()),
+        "/away" | "/dnd" => CliCommand::SetPresence(presence_command(input)),
+        "/code" => CliCommand::ShowCode,
+        "/invite" => CliCommand::Invite,
+        "/save" => CliCommand::SaveRoom,
+        "/rejoin" => CliCommand::Rejoin(None),
+        "/topic" => CliCommand::Topic(None),
+        "/slow" => CliCommand::SlowMode(None),
+        "/announce" => CliCommand::Announcement(None),
+        "/inviteonly" => CliCommand::InviteOnly(None),
+        "/capacity" => CliCommand::Capacity(None),
+        "/capacity off" => CliCommand::Capacity(Some(0)),
+        "/token" => CliCommand::MintInvite {
+            hours: 0,
+            single_use: false,
+        },
+        "/endpoll" => CliCommand::EndPoll,
+        "/notes" => CliCommand::ShowNotes,
+        "/threads" => CliCommand::ShowThreads,
+        "/browse" => CliCommand::BrowseRooms,
+        "/public" => CliCommand::ListPublic(Some(String::new())),
+        "/public off" => CliCommand::ListPublic(None),
+        "/thread" => CliCommand::OpenThread(None),
+        _ if input.starts_with("/switch ") => {
+            let target = input["/switch ".len()..].trim();
+            // Accept either a room name or its 1-based number.
+            let name = target
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| rooms.get(n.wrapping_sub(1)))
+                .cloned()
+                .unwrap_or_else(|| target.to_string());
+            CliCommand::SwitchRoom(name)
+        }
+        _ if input.starts_with("/msg ") => {
+            let rest = input["/msg ".len()..].trim_start();
+            match rest.split_once(' ') {
+                Some((to, text)) if !text.trim().is_empty() => CliCommand::DirectMessage {
+                    to: to.to_string(),
+                    text: text.trim().to_string(),
+                },
+                _ => CliCommand::Help,
+            }
+        }
+        _ if input.starts_with("/verify ") => {
+            let mut words = input["/verify ".len()..].split_whitespace();
+            let who = words.next().unwrap_or_default().to_string();
+            match words.next() {
+                None if !who.is_empty() => CliCommand::Verify { who, mark: None },
+                Some("confirm") => CliCommand::Verify {
+                    who,
+                    mark: Some(true),
+                },
+                Some("revoke") => CliCommand::Verify {
+                    who,
+                    mark: Some(false),
+                },
+                _ => CliCommand::Help,
+            }
+        }
+        _ if input.starts_with("/react ") => {
+            let words: Vec<&str> = input["/react ".len()..].split_whitespace().collect();
+            match words.as_slice() {
+                [emoji] => CliCommand::React {
+                    to: None,
+                    emoji: emoji.to_string(),
+                },
+                [who, emoji] => CliCommand::React {
+                    to: Some(who.to_string()),
+                    emoji: emoji.to_string(),
+                },
+                _ => CliCommand::Help,
+            }
+        }
+        _ if input.starts_with("/reply ") => {
+            let rest = input["/reply ".len()..].trim_start();
+            let Some((who, text)) = rest.split_once(' ').filter(|(_, t)| !t.trim().is_empty())
+            else {
+                return Ok(CliCommand::Help);
+            };
+            // The latest message from that member ("nick" or "nick#disc").
+            let target = messages.iter().rev().find(|m| {
+                !m.is_system
+                    && m.msg_id != 0
+                    && (m.sender == who
+                        || m.sender
+                            .split_once('#')
+                            .is_some_and(|(nick, _)| nick == who))
+            });
+            match target {
+                Some(m) => CliCommand::Reply {
+                    to: m.msg_id,
+                    text: text.trim().to_string(),
+                },
+                None => return Err(format!("[!] No message from {} to reply to.", who)),
+            }
+        }
+        _ if input.starts_with("/poll ") => {
+            let rest = input["/poll ".len()..].trim_start();
+            // An optional number of minutes before the quoted words.
+            let (minutes, rest) = match rest.split_once(' ') {
+                Some((minutes, rest)) if minutes.parse::<u32>().is_ok() => {
+                    (minutes.parse().ok(), rest)
+                }
+                _ => (None, rest),
+            };
+            match quoted_words(rest).as_deref() {
+                Some([question, options @ ..]) if options.len() >= 2 => CliCommand::Poll {
+                    question: question.clone(),
+                    options: options.to_vec(),
+                    minutes,
+                },
+                _ => CliCommand::Help,
+            }
+        }
+        _ if input.starts_with("/note ") => {
+            let rest = input["/note ".len()..].trim();
+            let (action, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+            let (n, text) = rest.split_once(' ').unwrap_or((rest, ""));
+            let line = |n: &str| match n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| notes.get(n.wrapping_sub(1)))
+            {
+                Some(&(id, _)) => Ok(id),
+                None => Err(format!("[!] No line {n} in the notes; /notes lists them.")),
+            };
+            let edit = match action {
+                "add" if !rest.is_empty() => NoteEdit::Append(rest.to_string()),
+                "set" => NoteEdit::Set {
+                    id: line(n)?,
+                    text: text.to_string(),
+                },
+                "del" => NoteEdit::Delete(line(n)?),
+                _ => return Ok(CliCommand::Help),
+            };
+            CliCommand::EditNote(edit)
+        }
+        _ if input.starts_with("/vote ") => match input["/vote ".len()..].trim().parse() {
+            Ok(n) => CliCommand::Vote(n),
+            Err(_) => CliCommand::Help,
+        },
+        _ if input.starts_with("/topic ") => {
+            CliCommand::Topic(Some(input["/topic ".len()..].trim().to_string()))
+        }
+        _ if input.starts_with("/alias ") => {
+            let rest = input["/alias ".len()..].trim();
+            let (who, alias) = rest.split_once(' ').unwrap_or((rest, ""));
+            // Quotes are optional around a name with spaces.
+            let alias = alias.trim();
+            let alias = alias
+                .strip_prefix('"')
+                .and_then(|a| a.strip_suffix('"'))
+                .unwrap_or(alias);
+            CliCommand::Alias {
+                who: who.to_string(),
+                alias: (!alias.is_empty()).then(|| alias.to_string()),
+            }
+        }
+        _ if input.starts_with("/trust ") => {
+            CliCommand::Trust(input["/trust ".len()..].trim().to_string())
+        }
+        _ if input.starts_with("/history ") => match input["/history ".len()..].trim().parse() {
+            Ok(count) => CliCommand::History(Some(count)),
+            Err(_) => CliCommand::Help,
+        },
+        _ if input.starts_with("/export ") => {
+            match ExportFormat::from_name(input["/export ".len()..].trim()) {
+                Some(format) => CliCommand::Export(format),
+                None => CliCommand::Help,
+            }
+        }
+        _ if input.starts_with("/away ") || input.starts_with("/dnd ") => {
+            CliCommand::SetPresence(presence_command(input))
+        }
+        _ if input.starts_with("/block ") => {
+            CliCommand::Block(input["/block ".len()..].trim().to_string())
+        }
+        _ if input.starts_with("/unblock ") => {
+            CliCommand::Unblock(input["/unblock ".len()..].trim().to_string())
+        }
+        _ if input.starts_with("/slow ") => match input["/slow ".len()..].trim() {
+            "off" => CliCommand::SlowMode(Some(0)),
+            secs => match secs.trim_end_matches('s').parse() {
+                Ok(secs) => CliCommand::SlowMode(Some(secs)),
+                Err(_) => CliCommand::Help,
+            },
+        },
+        _ if input.starts_with("/thread ") => {
+            CliCommand::OpenThread(Some(input["/thread ".len()..].trim().to_string()))
+        }
+        _ if input.starts_with("/announce ") => match input["/announce ".len()..].trim() {
+            "on" => CliCommand::Announcement(Some(true)),
+            "off" => CliCommand::Announcement(Some(false)),
+            _ => CliCommand::Help,
+        },
+        _ if input.starts_with("/inviteonly ") => match input["/inviteonly ".len()..].trim() {
+            "on" => CliCommand::InviteOnly(Some(true)),
+            "off" => CliCommand::InviteOnly(Some(false)),
+            _ => CliCommand::Help,
+        },
+        _ if input.starts_with("/public ") => {
+            CliCommand::ListPublic(Some(input["/public ".len()..].trim().to_string()))
+        }
+        _ if input.starts_with("/capacity ") => match input["/capacity ".len()..].trim().parse() {
+            Ok(max) if max > 0 => CliCommand::Capacity(Some(max)),
+            _ => CliCommand::Help,
+        },
+        _ if input.starts_with("/token ") => {
+            // Optional hours, then an optional `once`.
+            let rest = input["/token ".len()..].trim();
+            let (rest, single_use) = match rest.strip_suffix("once") {
+                Some(rest) => (rest.trim(), true),
+                None => (rest, false),
+            };
+            match rest {
+                "" => CliCommand::MintInvite {
+                    hours: 0,
+                    single_use,
+                },
+                hours => match hours.parse() {
+                    Ok(hours) if hours > 0 => CliCommand::MintInvite { hours, single_use },
+                    _ => CliCommand::Help,
+                },
+            }
+        }
+        _ if input.starts_with("/speaker ") => {
+            let rest = input["/speaker ".len()..].trim();
+            match rest.strip_suffix(" off") {
+                Some(who) => CliCommand::Speaker {
+                    who: who.trim().to_string(),
+                    allow: false,
+                },
+                None => CliCommand::Speaker {
+                    who: rest.to_string(),
+                    allow: true,
+                },
+            }
+        }
+        _ if input.starts_with("/kick ") => {
+            CliCommand::Kick(input["/kick ".len()..].trim().to_string())
+        }
+        _ if input.starts_with("/ban ") => {
+            CliCommand::Ban(input["/ban ".len()..].trim().to_string())
+        }
+        _ if input.starts_with("/admin ") => {
+            let rest = input["/admin ".len()..].trim();
+            match rest.strip_suffix(" off") {
+                Some(who) => CliCommand::GrantAdmin {
+                    who: who.trim().to_string(),
+                    admin: false,
+                },
+                None => CliCommand::GrantAdmin {
+                    who: rest.to_string(),
+                    admin: true,
+                },
+            }
+        }
+        _ if input.starts_with("/transfer ") => {
+            CliCommand::TransferOwnership(input["/transfer ".len()..].trim().to_string())
+        }
+        _ if input.starts_with("/rename ") => {
+            CliCommand::RenameRoom(input["/rename ".len()..].trim().to_string())
+        }
+        _ if input.starts_with("/whois ") => {
+            CliCommand::Whois(input["/whois ".len()..].trim().to_string())
+        }
+        _ if input.starts_with("/rejoin ") => {
+            CliCommand::Rejoin(Some(input["/rejoin ".len()..].trim().to_string()))
+        }
+        _ if input.starts_with("/forget ") => {
+            CliCommand::ForgetRoom(input["/forget ".len()..].trim().to_string())
+        }
+        _ => {
+            let rest = input.strip_prefix('/').unwrap_or(input);
+            let (name, args) = rest.split_once(' ').unwrap_or((rest, ""));
+            CliCommand::Plugin {
+                name: name.to_string(),
+                args: args.trim().to_string(),
+            }
+        }
+    };
+    Ok(cmd)
+}
+
+/// `"a b" "c"` as its quoted words; `None` unless every word is quoted.
+fn quoted_words(text: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let (word, after) = rest.strip_prefix('"')?.split_once('"')?;
+        words.push(word.to_string());
+        rest = after.trim_start();
+    }
+    Some(words)
+}
+
+/// The status asked for by `/away [reason]` or `/dnd [reason]`.
+fn presence_command(input: &str) -> Presence {
+    let (command, reason) = input.split_once(' ').unwrap_or((input, ""));
+    let status = match command {
+        "/dnd" => PresenceStatus::DoNotDisturb,
+        _ => PresenceStatus::Away,
+    };
+    Presence {
+        status,
+        reason: reason.trim().to_string(),
+    }
+}
+
+/// A row of a message on screen and the links on it: columns and URL.
+struct MessageRow {
+    line: Line<'static>,
+    links: Vec<(Range<u16>, String)>,
+}
+
+/// The rows of `msg` as `render_lines` cut them.  System lines and a
+/// reply's quote row are dimmed; on the row after the quote (the first,
+/// otherwise) the sender's name is drawn in its own color.  With
+/// `formatting`, `*bold*`, `_italic_` and `` `code` `` in the text are
+/// drawn as such, also across rows.  Links are underlined either way.
+fn message_lines(
+    msg: &DisplayMessage,
+    rows: Vec<String>,
+    formatting: bool,
+    theme: &Theme,
+) -> Vec<MessageRow> {
+    let quote_rows = usize::from(msg.quote.is_some() && !msg.is_system);
+    let base = if msg.is_system {
+        theme.system
+    } else if msg.mention {
+        theme.mention
+    } else if msg.delivery.is_some() {
+        theme.own
+    } else {
+        Style::new()
+    };
+    let label = msg.sender_label();
+    let mut lines: Vec<MessageRow> = Vec::new();
+    let mut bodies = Vec::new();
+    for (row, text) in rows.into_iter().enumerate() {
+        if row < quote_rows {
+            lines.push(MessageRow {
+                line: Line::from(Span::styled(text, theme.system)),
+                links: Vec::new(),
+            });
+            continue;
+        }
+        // The row starts "[HH:MM] " and then the label, unless it was too
+        // long to fit and got wrapped.
+        let rest = text.get(8..).and_then(|r| r.strip_prefix(label.as_str()));
+        let line = match rest {
+            Some(rest) if row == quote_rows && !msg.is_system => {
+                bodies.push(rest.to_string());
+                Line::from(vec![
+                    Span::styled(text[..8].to_string(), base),
+                    Span::styled(label.clone(), sender_style(&msg.sender, theme)),
+                ])
+            }
+            _ => {
+                bodies.push(text);
+                Line::default()
+            }
+        };
+        lines.push(MessageRow {
+            line,
+            links: Vec::new(),
+        });
+    }
+    // The bodies are marked up as one text, a space standing for each row
+    // break, so a span or link can go on on the next row.
+    let urls: Vec<&str> = links::find(&msg.text)
+        .into_iter()
+        .map(|r| &msg.text[r])
+        .collect();
+    let markup = markup::parse(&bodies.join(" "), &urls, formatting && !msg.is_system);
+    let mut styles = markup.styles.into_iter().enumerate();
+    for (row, body) in lines[quote_rows..].iter_mut().zip(&bodies) {
+        let mut column = row.line.width() as u16;
+        let mut run = String::new();
+        let mut run_style = base;
+        for c in body.chars() {
+            let Some((i, Some(style))) = styles.next() else {
+                continue;
+            };
+            let width = c.width().unwrap_or(0) as u16;
+            let link = markup
+                .links
+                .iter()
+                .find(|(link, _)| link.contains(&i) && c != ' ');
+            if let Some((_, url)) = link {
+                match row.links.last_mut() {
+                    Some((columns, last)) if columns.end == column && last == url => {
+                        columns.end += width
+                    }
+                    _ => row.links.push((column..column + width, url.clone())),
+                }
+            }
+            column += width;
+            let style = base.patch(style);
+            if style != run_style && !run.is_empty() {
+                row.line
+                    .push_span(Span::styled(std::mem::take(&mut run), run_style));
+            }
+            run_style = style;
+            run.push(c);
+        }
+        if !run.is_empty() {
+            row.line.push_span(Span::styled(run, run_style));
+        }
+        styles.next(); // The row break.
+    }
+    lines
+}
+
+/// Make the cells at `columns` of row `y` a link to `url` (OSC 8), for
+/// terminals that let it be clicked; others ignore the escape sequence.
+fn hyperlink(buf: &mut Buffer, x: u16, y: u16, columns: Range<u16>, url: &str) {
+    for column in columns {
+        let Some(cell) = buf.cell_mut(Position::new(x + column, y)) else {
+            break;
+        };
+        // Each cell is written with its own link; terminals join them.  The
+        // escape sequence has no width of its own.
+        let width = NonZeroU16::new(cell.symbol().width().max(1) as u16).unwrap_or(NonZeroU16::MIN);
+        let symbol = format!("\x1b]8;;{url}\x1b\\{}\x1b]8;;\x1b\\", cell.symbol());
+        cell.set_symbol(&symbol)
+            .set_diff_option(CellDiffOption::ForcedWidth(width));
+    }
+}
+
+/// The style of `sender` ("nick#disc"): an FNV-1a hash into the theme's
+/// sender colors, so it is the same on every run.
+fn sender_style(sender: &str, theme: &Theme) -> Style {
+    if theme.senders.is_empty() {
+        return Style::new();
+    }
+    let hash = sender.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    Style::new().fg(theme.senders[(hash % theme.senders.len() as u64) as usize])
+}
+
+/// Furthest the view can scroll up (in messages) so that the oldest message
+/// still starts at the top of a `height`-row pane, one row going to the
+/// history indicator.
+fn max_scroll(state: &CliState, width: usize, height: u16) -> usize {
+    let rows = (height as usize).saturating_sub(1);
+    let mut used = 0;
+    for (shown, msg) in state.messages.iter().enumerate() {
+        if used >= rows {
+            return state.messages.len() - shown;
+        }
+        used += msg.render_lines(width).len() + usize::from(state.first_unread == Some(shown));
+    }
+    0
+}
+
+/// "── new messages ──" centred in `width` columns.
+fn divider_text(width: usize) -> String {
+    let label = " new messages ";
+    let side = width.saturating_sub(label.len()) / 2;
+    format!("{}{}{}", "─".repeat(side), label, "─".repeat(side))
+}
+
+/// Lines the help overlay can scroll down before its end shows.
+fn help_max_scroll(state: &CliState) -> usize {
+    // Headings and the blank line between the sections, then the borders.
+    let lines = state.commands.len() + key_help(&state.keys).len() + 3;
+    let rows = crossterm::terminal::size().map_or(24, |(_, h)| h) as usize;
+    lines.saturating_sub(rows.saturating_sub(2))
+}
+
+/// Messages per PageUp / PageDown: one screenful, keeping a line of context.
+fn page_size() -> usize {
+    let rows = crossterm::terminal::size().map_or(24, |(_, h)| h);
+    (rows.saturating_sub(CHAT_CHROME_ROWS + 1) as usize).max(1)
+}
+
+// ── Drawing ───────────────────────────────────────────────────────────────────
+
+fn draw(f: &mut Frame, state: &CliState) {
+    match state.screen {
+        Screen::MainMenu => draw_main_menu(f, state),
+        Screen::CreateRoom { .. }
+        | Screen::JoinRoom { .. }
+        | Screen::ChangeNickname
+        | Screen::ChangeAvatar => {
+            draw_main_menu(f, state);
+            draw_prompt(f, state);
+        }
+        Screen::Chat => draw_chat(f, state),
+        Screen::Notes { selected, adding } => draw_notes(f, state, selected, adding),
+        Screen::Threads { selected } => draw_threads(f, state, selected),
+        Screen::PublicRooms { selected } => draw_public_rooms(f, state, selected),
+    }
+    if let Some(palette) = &state.palette {
+        draw_palette(f, state, palette);
+    }
+    if let Some(scroll) = state.help_scroll {
+        draw_help(f, state, scroll);
+    }
+    state.theme.apply(f.buffer_mut());
+    // Drawn after, so the QR code stays dark on light in any theme.
+    if let Some(code) = &state.qr_code
+        && state.screen == Screen::Chat
+    {
+        draw_qr(f, code);
+    }
+}
+
+/// The keys listed in the help, those `[keys]` can change as bound.
+fn key_help(bound: &KeyBindings) -> [(String, String); 12] {
+    [
+        (bound.send.label(), "send the message or command"),
+        ("F1, ?".to_string(), "this help (? with an empty input)"),
+        (
+            bound.command_palette.label(),
+            "command palette: find and run any command",
+        ),
+        (bound.switch_room_label(), "switch to the Nth joined room"),
+        (
+            "Alt+↑ / Alt+↓".to_string(),
+            "pick a message to reply to (Esc cancels)",
+        ),
+        (
+            format!(
+                "{} / {}",
+                bound.scroll_up.label(),
+                bound.scroll_down.label()
+            ),
+            "scroll the chat (the mouse wheel too)",
+        ),
+        (
+            "Ctrl+Home / End".to_string(),
+            "oldest message / back to live",
+        ),
+        (
+            "← → Home End".to_string(),
+            "move the cursor (or click in the input)",
+        ),
+        ("Ctrl+W".to_string(), "delete the previous word"),
+        ("Ctrl+U".to_string(), "delete everything before the cursor"),
+        (
+            "Esc".to_string(),
+            "cancel; in the main menu, back to the chat",
+        ),
+        (bound.quit.label(), "quit"),
+    ]
+    .map(|(key, about)| (key, about.to_string()))
+}
+
+/// The help overlay: every command, then the keys, scrolled by `scroll`
+/// lines (as far as there is more to see).
+fn draw_help(f: &mut Frame, state: &CliState, scroll: usize) {
+    let keys = key_help(&state.keys).into_iter();
+    let usage_width = state
+        .commands
+        .iter()
+        .cloned()
+        .chain(keys.clone())
+        .map(|(usage, _)| usage.width())
+        .max()
+        .unwrap_or(0);
+    let entry = |(usage, about): (String, String)| {
+        let pad = " ".repeat(usage_width - usage.width() + 2);
+        Line::from(vec![
+            Span::raw(usage).bold(),
+            Span::raw(pad),
+            Span::raw(about),
+        ])
+    };
+    let heading = |text| Line::from(Span::styled(text, state.theme.accent.bold()));
+    let mut lines = vec![heading("Commands")];
+    lines.extend(state.commands.iter().cloned().map(entry));
+    lines.push(Line::default());
+    lines.push(heading("Keys"));
+    lines.extend(keys.map(entry));
+
+    let area = f.area();
+    let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 4;
+    let popup = centered(area, width, lines.len() as u16 + 2);
+    let visible = popup.height.saturating_sub(2) as usize;
+    let scroll = scroll.min(lines.len().saturating_sub(visible));
+    let block = Block::new()
+        .borders(Borders::ALL)
+        .title(" Help ")
+        .title_bottom(" ↑↓ PgUp PgDn scroll · any other key closes ")
+        .padding(Padding::horizontal(1));
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(lines)
+            .block(block)
+            .scroll((scroll as u16, 0)),
+        popup,
     );
-    let header_truncated = truncate_str(&header, w);
-    execute!(stdout, style::PrintStyledContent(header_truncated.clone().on(Color::DarkBlue).white()))?;
+}
 
-    let pad = w.saturating_sub(header_truncated.len());
-    if pad > 0 {
-        execute!(stdout, style::PrintStyledContent(" ".repeat(pad).on(Color::DarkBlue)))?;
+/// The command palette near the top: the query, then the best matches with
+/// the pick highlighted.
+fn draw_palette(f: &mut Frame, state: &CliState, palette: &Palette) {
+    let matches = palette_matches(&state.commands, palette.query.as_str());
+    let selected = palette.selected.min(matches.len().saturating_sub(1));
+    let area = f.area();
+    let width = 64.min(area.width);
+    let rows = matches.len().clamp(1, PALETTE_ROWS) as u16;
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + 1.min(area.height),
+        width,
+        (rows + 3).min(area.height.saturating_sub(1)),
+    );
+    let block = Block::new()
+        .borders(Borders::ALL)
+        .title(" Run a command ")
+        .title_bottom(" ↑↓ pick · Enter run · Esc close ");
+    let inner = block.inner(popup);
+    f.render_widget(Clear, popup);
+    f.render_widget(block, popup);
+    if inner.height == 0 {
+        return;
     }
 
-    stdout.flush()?;
-    Ok(())
+    let available = (inner.width as usize).saturating_sub(2);
+    let (visible, column) =
+        visible_window(palette.query.as_str(), palette.query.cursor(), available);
+    f.render_widget(
+        Paragraph::new(format!("> {visible}")),
+        Rect { height: 1, ..inner },
+    );
+    f.set_cursor_position(Position::new(inner.x + 2 + column as u16, inner.y));
+
+    // Keep the pick in view.
+    let first = (selected + 1).saturating_sub(PALETTE_ROWS);
+    let mut lines: Vec<Line> = matches
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(PALETTE_ROWS)
+        .map(|(row, &i)| {
+            let (usage, about) = &state.commands[i];
+            let about = Span::styled(format!("  {about}"), state.theme.system);
+            let line = Line::from(vec![Span::raw(usage.clone()).bold(), about]);
+            if row == selected {
+                line.reversed()
+            } else {
+                line
+            }
+        })
+        .collect();
+    if lines.is_empty() {
+        lines.push(Line::from(Span::styled(
+            "No matching command",
+            state.theme.system,
+        )));
+    }
+    let list = Rect {
+        y: inner.y + 1,
+        height: inner.height - 1,
+        ..inner
+    };
+    f.render_widget(Paragraph::new(lines), list);
+}
+
+fn draw_main_menu(f: &mut Frame, state: &CliState) {
+    let lines = menu_lines(state);
+    let menu = menu_area(f.area(), &lines);
+    let lines: Vec<Line> = lines.into_iter().map(|(line, _)| line).collect();
+    f.render_widget(Paragraph::new(lines), menu);
+}
+
+/// The main menu: a header, one line per entry of `MENU_ITEMS`, the recent
+/// rooms, then the way back to the chat if rooms are open.  Each line comes
+/// with the key a click on it presses.
+fn menu_lines(state: &CliState) -> Vec<(Line<'static>, Option<KeyCode>)> {
+    let dim = |text: String| Line::from(Span::styled(text, state.theme.system));
+    let mut lines = vec![
+        (Line::from("=== P2P Chat ===".bold()), None),
+        (
+            dim(format!(
+                "Logged in as: {}{}",
+                state
+                    .avatar
+                    .as_ref()
+                    .map_or(String::new(), |a| format!("{a} ")),
+                state.nickname
+            )),
+            None,
+        ),
+        (Line::default(), None),
+    ];
+    for (key, label) in MENU_ITEMS {
+        let line = Line::from(format!("[{}] {}", key.to_ascii_uppercase(), label));
+        lines.push((line, Some(KeyCode::Char(key))));
+    }
+    if !state.recent.is_empty() {
+        lines.push((Line::default(), None));
+        let title = if state.pruning {
+            "Remove which room? (Esc: keep all)"
+        } else {
+            "Recent rooms:"
+        };
+        lines.push((dim(title.to_string()), None));
+        let now = chrono::Utc::now();
+        let keys = (FIRST_RECENT_KEY..).take(state.recent.len());
+        for (key, room) in keys.zip(&state.recent) {
+            let saved = if room.saved { " · saved" } else { "" };
+            let line = format!(
+                "[{key}] {} ({}{saved})",
+                room.name,
+                ago(room.last_joined, now)
+            );
+            lines.push((Line::from(line), Some(KeyCode::Char(key))));
+        }
+        if !state.pruning {
+            lines.push((
+                dim("[X] Remove a recent room".to_string()),
+                Some(KeyCode::Char('x')),
+            ));
+        }
+    }
+    if !state.rooms.is_empty() {
+        let back = format!("[Esc] Back to chat ({} room(s) open)", state.rooms.len());
+        lines.push((dim(back), Some(KeyCode::Esc)));
+    }
+    lines
+}
+
+/// "just now", "5m ago", "3h ago", "2d ago".
+fn ago(then: chrono::DateTime<chrono::Utc>, now: chrono::DateTime<chrono::Utc>) -> String {
+    let secs = (now - then).num_seconds().max(0);
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// Where the menu `lines` are drawn on a terminal of size `area`.
+fn menu_area(area: Rect, lines: &[(Line, Option<KeyCode>)]) -> Rect {
+    let width = lines
+        .iter()
+        .map(|(line, _)| line.width())
+        .max()
+        .unwrap_or(0) as u16;
+    centered(area, width, lines.len() as u16)
+}
+
+/// Bottom-of-screen prompt for the create / join / nickname steps.
+fn draw_prompt(f: &mut Frame, state: &CliState) {
+    let area = f.area();
+    let [_, progress_row, input_row] = Layout::vertical([
+        Constraint::Min(0),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .areas(area);
+
+    f.render_widget(Clear, progress_row);
+    if let Some((room, join_state)) = &state.join_progress
+        && matches!(state.screen, Screen::JoinRoom { .. })
+    {
+        let line = format!("Joining '{}': {}", room, join_state.label());
+        f.render_widget(
+            Paragraph::new(Span::styled(line, state.theme.system)),
+            progress_row,
+        );
+    }
+
+    let input_display = if state.masking {
+        "•".repeat(state.input.char_count())
+    } else {
+        state.input.as_str().to_string()
+    };
+    // Show the part of the input around the cursor that fits after the label.
+    let label_len = state.prompt_label.width();
+    let available = (input_row.width as usize).saturating_sub(label_len);
+    let (visible, column) = visible_window(&input_display, state.input.cursor(), available);
+    let cursor_x = input_row.x + (label_len + column) as u16;
+
+    f.render_widget(Clear, input_row);
+    f.render_widget(
+        Paragraph::new(format!("{}{}", state.prompt_label, visible)),
+        input_row,
+    );
+    f.set_cursor_position(Position::new(cursor_x, input_row.y));
+}
+
+/// Header, messages (with the sidebar) and input box of the chat screen.
+fn chat_layout(area: Rect) -> [Rect; 3] {
+    Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(1),
+        Constraint::Length(3),
+    ])
+    .areas(area)
+}
+
+fn draw_chat(f: &mut Frame, state: &CliState) {
+    let [header, body, input] = chat_layout(f.area());
+
+    // ── Header ──────────────────────────────────────────────────────
+    f.render_widget(
+        Paragraph::new(header_text(state)).style(state.theme.header),
+        header,
+    );
+
+    // ── Messages + member sidebar ───────────────────────────────────
+    let (messages, sidebar) = if body.width >= SIDEBAR_WIDTH * 3 {
+        let [m, s] =
+            Layout::horizontal([Constraint::Min(1), Constraint::Length(SIDEBAR_WIDTH)]).areas(body);
+        (m, Some(s))
+    } else {
+        (body, None)
+    };
+
+    let msg_block = Block::new().borders(Borders::TOP);
+    let msg_area = msg_block.inner(messages);
+    f.render_widget(msg_block, messages);
+    let width = msg_area.width as usize;
+    let total = state.messages.len();
+    // Scrolled all the way up, the oldest message sits at the top of the
+    // rows left over by the indicator.
+    let scroll = state.scroll.min(max_scroll(state, width, msg_area.height));
+    // While scrolled up, the last row tells the user they are not live.
+    let (msg_area, indicator) = if scroll > 0 {
+        let [m, i] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(msg_area);
+        (m, Some(i))
+    } else {
+        (msg_area, None)
+    };
+    // Fill the rows from the newest visible message upwards; the oldest one
+    // may be cut at the top.
+    let rows = msg_area.height as usize;
+    let mut lines: Vec<Line> = Vec::new();
+    let mut links = Vec::new();
+    for (i, msg) in state.messages.iter().enumerate().take(total - scroll).rev() {
+        if lines.len() >= rows {
+            break;
+        }
+        let rendered = message_lines(msg, msg.render_lines(width), state.formatting, &state.theme);
+        let shown = rendered.len().min(rows - lines.len());
+        let selected = state.reply_to.is_some_and(|id| id == msg.msg_id);
+        for MessageRow {
+            line,
+            links: row_links,
+        } in rendered.into_iter().rev().take(shown)
+        {
+            links.push(row_links);
+            lines.push(if selected { line.reversed() } else { line });
+        }
+        if state.first_unread == Some(i) && lines.len() < rows {
+            links.push(Vec::new());
+            lines.push(Line::from(Span::styled(
+                divider_text(width),
+                state.theme.accent,
+            )));
+        }
+    }
+    lines.reverse();
+    links.reverse();
+    f.render_widget(Paragraph::new(lines), msg_area);
+    for (row, row_links) in links.into_iter().enumerate() {
+        for (columns, url) in row_links {
+            let y = msg_area.y + row as u16;
+            hyperlink(f.buffer_mut(), msg_area.x, y, columns, &url);
+        }
+    }
+    if let Some(indicator) = indicator {
+        let text = format!(
+            " Viewing history — {} newer message(s) below · PgDn / Ctrl+End to return ",
+            scroll
+        );
+        f.render_widget(
+            Paragraph::new(Span::styled(text, state.theme.accent.reversed())),
+            indicator,
+        );
+    }
+
+    if let Some(sidebar) = sidebar {
+        let items: Vec<ListItem> = state
+            .members
+            .iter()
+            .map(|m| ListItem::new(Span::raw(m.as_str())))
+            .collect();
+        let title = format!(" Members ({}) ", state.peer_count);
+        f.render_widget(
+            List::new(items).block(
+                Block::new()
+                    .borders(Borders::TOP | Borders::LEFT)
+                    .title(title),
+            ),
+            sidebar,
+        );
+    }
+
+    // ── Input box ───────────────────────────────────────────────────
+    let mut input_block = Block::new()
+        .borders(Borders::ALL)
+        .border_style(state.theme.input);
+    if let Some(text) = &state.pending_paste {
+        let (chars, lines) = (text.chars().count(), text.lines().count());
+        let room = MAX_INPUT_LEN - state.input.char_count();
+        let cut = if chars > room {
+            format!(" (only {room} fit)")
+        } else {
+            String::new()
+        };
+        let title = format!(
+            " Paste {chars} characters, {lines} lines{cut}? Enter to insert · Esc to drop "
+        );
+        input_block = input_block.title(Span::styled(title, state.theme.accent));
+    } else if let Some(target) = state.reply_target() {
+        let title = format!(" Replying to {} · Esc to cancel ", target.sender);
+        input_block = input_block.title(title);
+    }
+    draw_input(f, state, input_block, input);
+}
+
+/// The input line in `block` at `area`, scrolled to keep the cursor in
+/// view.
+fn draw_input(f: &mut Frame, state: &CliState, block: Block, area: Rect) {
+    let input_area = block.inner(area);
+    f.render_widget(block, area);
+    let available = (input_area.width as usize).saturating_sub(2);
+    let (visible, column) = visible_window(state.input.as_str(), state.input.cursor(), available);
+    let cursor_x = input_area.x + 2 + column as u16;
+    f.render_widget(Paragraph::new(format!("> {}", visible)), input_area);
+    f.set_cursor_position(Position::new(cursor_x, input_area.y));
+}
+
+/// The notes screen: the active room's shared notes, numbered, with the
+/// picked line (or the new one being added) highlighted above the input.
+fn draw_notes(f: &mut Frame, state: &CliState, selected: usize, adding: bool) {
+    let [header, body, input] = chat_layout(f.area());
+    let room = state.current_room.as_deref().unwrap_or_default();
+    let title = format!(" Notes of '{room}' — every member can edit them");
+    f.render_widget(Paragraph::new(title).style(state.theme.header), header);
+
+    let mut rows: Vec<Line> = (1..)
+        .zip(&state.notes)
+        .map(|(n, (_, text))| Line::from(format!("{n:>3}  {text}")))
+        .collect();
+    let current = if adding {
+        let at = if rows.is_empty() {
+            0
+        } else {
+            (selected + 1).min(rows.len())
+        };
+        rows.insert(
+            at,
+            Line::from(Span::styled("  +  new line", state.theme.system)),
+        );
+        at
+    } else {
+        selected
+    };
+    if let Some(row) = rows.get_mut(current) {
+        *row = std::mem::take(row).reversed();
+    }
+    let block = Block::new().borders(Borders::TOP);
+    let area = block.inner(body);
+    f.render_widget(block, body);
+    let scroll = (current + 1).saturating_sub(area.height as usize);
+    f.render_widget(Paragraph::new(rows).scroll((scroll as u16, 0)), area);
+
+    let keys = if adding {
+        " Enter adds the line · ↑↓ pick a line · Esc back to the chat "
+    } else {
+        " Enter saves · Ctrl+O new line below · Ctrl+D deletes · Esc back "
+    };
+    let block = Block::new()
+        .borders(Borders::ALL)
+        .border_style(state.theme.input)
+        .title(keys);
+    draw_input(f, state, block, input);
+}
+
+fn draw_threads(f: &mut Frame, state: &CliState, selected: usize) {
+    let [header, body, footer] = chat_layout(f.area());
+    let room = state.current_room.as_deref().unwrap_or_default();
+    let title = format!(" Threads of '{room}'");
+    f.render_widget(Paragraph::new(title).style(state.theme.header), header);
+
+    let here = |thread: Option<&str>| {
+        if state.thread.as_deref() == thread {
+            "▸"
+        } else {
+            " "
+        }
+    };
+    let mut rows = vec![Line::from(format!(
+        " {} {room} (the room itself)",
+        here(None)
+    ))];
+    rows.extend(state.threads.iter().map(|t| {
+        let last = t.last.format("%H:%M");
+        Line::from(format!(
+            " {} {}  {} message(s), last at {last}",
+            here(Some(&t.name)),
+            t.name,
+            t.messages
+        ))
+    }));
+    if state.threads.is_empty() {
+        rows.push(Line::from(Span::styled(
+            "   No threads yet: /thread <name> starts one.",
+            state.theme.system,
+        )));
+    }
+    if let Some(row) = rows.get_mut(selected) {
+        *row = std::mem::take(row).reversed();
+    }
+    let block = Block::new().borders(Borders::TOP);
+    let area = block.inner(body);
+    f.render_widget(block, body);
+    let scroll = (selected + 1).saturating_sub(area.height as usize);
+    f.render_widget(Paragraph::new(rows).scroll((scroll as u16, 0)), area);
+
+    let keys =
+        Paragraph::new(" ↑↓ pick · Enter opens · Esc back to the chat ").style(state.theme.system);
+    f.render_widget(keys, footer);
+}
+
+fn draw_public_rooms(f: &mut Frame, state: &CliState, selected: usize) {
+    let [header, body, footer] = chat_layout(f.area());
+    f.render_widget(
+        Paragraph::new(" Public rooms").style(state.theme.header),
+        header,
+    );
+
+    let mut rows: Vec<Line> = state
+        .public_rooms
+        .iter()
+        .map(|room| {
+            let about = if room.description.is_empty() {
+                String::new()
+            } else {
+                format!(" · {}", room.description)
+            };
+            Line::from(format!(" {}  {} member(s){about}", room.name, room.members))
+        })
+        .collect();
+    if let Some(row) = rows.get_mut(selected) {
+        *row = std::mem::take(row).reversed();
+    }
+    if rows.is_empty() {
+        let text = "   Looking for rooms… owners list theirs with /public.";
+        rows.push(Line::from(Span::styled(text, state.theme.system)));
+    }
+    let block = Block::new().borders(Borders::TOP);
+    let area = block.inner(body);
+    f.render_widget(block, body);
+    let scroll = (selected + 1).saturating_sub(area.height as usize);
+    f.render_widget(Paragraph::new(rows).scroll((scroll as u16, 0)), area);
+
+    let keys =
+        Paragraph::new(" ↑↓ pick · Enter joins · Esc back to the menu ").style(state.theme.system);
+    f.render_widget(keys, footer);
 }
 
-fn truncate_str(s: &str, max: usize) -> String {
-    if s.chars().count() <= max {
-        s.to_string()
+/// Popup with `code` as a QR code, dark modules on a light background
+/// whatever the terminal colors.  The code stays in the transcript, so on
+/// a terminal too small for the QR the popup just says so.
+fn draw_qr(f: &mut Frame, code: &str) {
+    let rows = qr_rows(code);
+    let qr_width = rows.first().map_or(0, |r| r.width()) as u16;
+    // Border plus a quiet zone of two modules on every side.
+    let (width, height) = (qr_width + 6, rows.len() as u16 + 4);
+    let area = f.area();
+    let block = Block::new()
+        .borders(Borders::ALL)
+        .title(" Room code ")
+        .title_bottom(" Any key to close ");
+    let (popup, lines) = if !rows.is_empty() && width <= area.width && height <= area.height {
+        let block = block.padding(Padding::new(2, 2, 1, 1)).black().on_white();
+        let lines: Vec<Line> = rows.into_iter().map(Line::from).collect();
+        (
+            centered(area, width, height),
+            Paragraph::new(lines).block(block),
+        )
     } else {
-        s.chars().take(max.saturating_sub(1)).collect::<String>() + "…"
+        let text = "Enlarge the terminal to scan the QR code.";
+        let popup = centered(area, text.len() as u16 + 2, 3);
+        (popup, Paragraph::new(text).block(block))
+    };
+    f.render_widget(Clear, popup);
+    f.render_widget(lines, popup);
+}
+
+/// `code` as QR rows of Unicode half blocks, two modules per row, without
+/// the quiet zone.  Empty if the code is too long for a QR code.
+fn qr_rows(code: &str) -> Vec<String> {
+    use qrcode::render::unicode::Dense1x2;
+    let Ok(qr) = qrcode::QrCode::with_error_correction_level(code, qrcode::EcLevel::L) else {
+        return Vec::new();
+    };
+    let image = qr.render::<Dense1x2>().quiet_zone(false).build();
+    image.lines().map(str::to_string).collect()
+}
+
+/// Header line: active room, peer count and, with several rooms open, the
+/// numbered room list (active one in brackets).
+fn header_text(state: &CliState) -> String {
+    let room_str = state.current_room.as_deref().unwrap_or("(no room)");
+    let mut header = format!(" Room: {}", room_str);
+    if let Some(thread) = &state.thread {
+        header.push_str(&format!(" › {thread}"));
+    }
+    if !state.subject.is_empty() {
+        header.push_str(&format!(" — {}", state.subject));
+    }
+    header.push_str(&format!("  |  {} peer(s) online", state.peer_count));
+    let below = state.unread_below();
+    if below > 0 {
+        header.push_str(&format!("  |  {} new below", below));
+    }
+    if state.rooms.len() > 1 {
+        let tabs: Vec<String> = state
+            .rooms
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                if Some(name) == state.current_room.as_ref() {
+                    format!("[{}:{}]", i + 1, name)
+                } else {
+                    match state.unread.get(name) {
+                        Some(n) => format!("{}:{}({})", i + 1, name, n),
+                        None => format!("{}:{}", i + 1, name),
+                    }
+                }
+            })
+            .collect();
+        header.push_str("  |  ");
+        header.push_str(&tabs.join(" "));
+    }
+    header
+}
+
+/// `width` × `height` rectangle centred in `area` (clamped to it).
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting_spans_wrapped_rows_and_can_be_turned_off() {
+        let msg = DisplayMessage::chat("bob#1a2b", "see *the whole* `x_1`");
+        let rows = msg.render_lines(28);
+        assert_eq!(rows.len(), 2, "{rows:?}");
+        let text = |row: &MessageRow| {
+            row.line
+                .spans
+                .iter()
+                .map(|s| s.content.as_ref())
+                .collect::<String>()
+        };
+        let bold = |row: &MessageRow, word: &str| {
+            let is_bold = |s: &Span| {
+                s.style
+                    .add_modifier
+                    .contains(ratatui::style::Modifier::BOLD)
+            };
+            row.line
+                .spans
+                .iter()
+                .any(|s| s.content.contains(word) && is_bold(s))
+        };
+
+        let lines = message_lines(&msg, rows.clone(), true, &Theme::default());
+        assert_eq!(text(&lines[0]), rows[0].replace('*', ""));
+        assert!(bold(&lines[0], "the") && bold(&lines[1], "whole"));
+        assert!(!text(&lines[1]).contains('`'));
+        let plain = message_lines(&msg, rows.clone(), false, &Theme::default());
+        assert_eq!(plain.iter().map(text).collect::<Vec<_>>(), rows);
+    }
+
+    #[test]
+    fn links_cut_by_wrapping_point_at_the_whole_url() {
+        let url = "https://example.com/a/long/path";
+        let msg = DisplayMessage::chat("bob#1a2b", &format!("at {url}"));
+        let rows = msg.render_lines(30);
+        let time = msg.timestamp.format("%H:%M");
+        assert_eq!(
+            rows,
+            [
+                format!("[{time}] bob#1a2b: at"),
+                "  https://example.com/a/long/p".into(),
+                "  ath".into()
+            ]
+        );
+        let lines = message_lines(&msg, rows, true, &Theme::default());
+        assert!(lines[0].links.is_empty());
+        assert_eq!(lines[1].links, [(2..30, url.to_string())]);
+        assert_eq!(lines[2].links, [(2..5, url.to_string())]);
+    }
+
+    #[test]
+    fn a_persona_follows_the_room_after_as() {
+        assert_eq!(split_persona(" lobby "), ("lobby", None));
+        let (room, persona) = split_persona("lan party as Ghost +key");
+        assert_eq!(room, "lan party");
+        assert_eq!(
+            persona,
+            Some(Persona {
+                nickname: Some("Ghost".to_string()),
+                own_key: true
+            })
+        );
+        assert_eq!(
+            split_persona("lobby as -"),
+            ("lobby", Some(Persona::default()))
+        );
+        assert_eq!(split_capacity("game night max 8"), ("game night", 8));
+        assert_eq!(split_capacity("max headroom"), ("max headroom", 0));
+        assert_eq!(split_capacity("lobby max 0"), ("lobby max 0", 0));
+    }
+
+    #[test]
+    fn a_poll_is_a_quoted_question_and_options() {
+        let parse = |input: &str| parse_command(input, &[], &VecDeque::new(), &[]).unwrap();
+        let CliCommand::Poll {
+            question,
+            options,
+            minutes,
+        } = parse(r#"/poll 5 "Where to?" "the park" "Kim's""#)
+        else {
+            panic!("not a poll");
+        };
+        assert_eq!((question.as_str(), minutes), ("Where to?", Some(5)));
+        assert_eq!(options, ["the park", "Kim's"]);
+        assert!(matches!(
+            parse(r#"/poll "Lunch?" "Pizza" "Sushi""#),
+            CliCommand::Poll { minutes: None, .. }
+        ));
+        assert!(matches!(
+            parse(r#"/poll "Lunch?" "Pizza""#),
+            CliCommand::Help
+        ));
+        assert!(matches!(
+            parse(r#"/poll Lunch? "Pizza" "Sushi""#),
+            CliCommand::Help
+        ));
+        assert!(matches!(
+            parse(r#"/poll "Lunch?" "Pizza" "Sushi"#),
+            CliCommand::Help
+        ));
+        assert!(matches!(parse("/vote 2"), CliCommand::Vote(2)));
+        assert!(matches!(parse("/endpoll"), CliCommand::EndPoll));
+    }
+
+    #[test]
+    fn speakers_are_named_and_taken_back_with_off() {
+        let parse = |input: &str| parse_command(input, &[], &VecDeque::new(), &[]).unwrap();
+        assert!(matches!(parse("/announce"), CliCommand::Announcement(None)));
+        assert!(matches!(
+            parse("/announce on"),
+            CliCommand::Announcement(Some(true))
+        ));
+        assert!(matches!(parse("/announce soon"), CliCommand::Help));
+        assert!(
+            matches!(parse("/speaker bob#1a2b"), CliCommand::Speaker { who, allow: true } if who == "bob#1a2b")
+        );
+        assert!(
+            matches!(parse("/speaker bob off"), CliCommand::Speaker { who, allow: false } if who == "bob")
+        );
+    }
+
+    #[test]
+    fn co_owners_are_named_and_taken_back_with_off() {
+        let parse = |input: &str| parse_command(input, &[], &VecDeque::new(), &[]).unwrap();
+        assert!(
+            matches!(parse("/admin bob#1a2b"), CliCommand::GrantAdmin { who, admin: true } if who == "bob#1a2b")
+        );
+        assert!(
+            matches!(parse("/admin bob off"), CliCommand::GrantAdmin { who, admin: false } if who == "bob")
+        );
+        assert!(
+            matches!(parse("/transfer bob"), CliCommand::TransferOwnership(who) if who == "bob")
+        );
+        assert!(matches!(parse("/whois bob"), CliCommand::Whois(who) if who == "bob"));
+    }
+
+    #[test]
+    fn a_new_room_name_is_the_rest_of_the_line() {
+        let parse = |input: &str| parse_command(input, &[], &VecDeque::new(), &[]).unwrap();
+        assert!(
+            matches!(parse("/rename  the den "), CliCommand::RenameRoom(name) if name == "the den")
+        );
+    }
+
+    #[test]
+    fn tokens_take_hours_and_once_in_that_order() {
+        let parse = |input: &str| parse_command(input, &[], &VecDeque::new(), &[]).unwrap();
+        assert!(matches!(
+            parse("/token"),
+            CliCommand::MintInvite {
+                hours: 0,
+                single_use: false
+            }
+        ));
+        assert!(matches!(
+            parse("/token 48"),
+            CliCommand::MintInvite {
+                hours: 48,
+                single_use: false
+            }
+        ));
+        assert!(matches!(
+            parse("/token once"),
+            CliCommand::MintInvite {
+                hours: 0,
+                single_use: true
+            }
+        ));
+        assert!(matches!(
+            parse("/token 2 once"),
+            CliCommand::MintInvite {
+                hours: 2,
+                single_use: true
+            }
+        ));
+        assert!(matches!(parse("/token once 2"), CliCommand::Help));
+        assert!(matches!(parse("/token 0"), CliCommand::Help));
+        assert!(matches!(
+            parse("/inviteonly on"),
+            CliCommand::InviteOnly(Some(true))
+        ));
+        assert!(matches!(
+            parse("/capacity 12"),
+            CliCommand::Capacity(Some(12))
+        ));
+        assert!(matches!(
+            parse("/capacity off"),
+            CliCommand::Capacity(Some(0))
+        ));
+        assert!(
+            matches!(parse("/public board games"), CliCommand::ListPublic(Some(d)) if d == "board games")
+        );
+        assert!(matches!(parse("/public off"), CliCommand::ListPublic(None)));
+    }
+
+    #[test]
+    fn unread_in_other_room_marks_divider_on_return() {
+        let mut state = CliState::new("me".to_string(), true);
+        state.current_room = Some("a".to_string());
+        let chat = |text: &str| DisplayMessage::chat("bob#1a2b", text);
+
+        let mut history = vec![chat("old")];
+        for text in ["one", "two"] {
+            state.note_room_message("b", &chat(text));
+            history.push(chat(text));
+        }
+        history.push(DisplayMessage::system("carol joined the room"));
+        state.note_room_message("a", &chat("seen"));
+        assert_eq!(state.unread.get("b"), Some(&2));
+        assert_eq!(state.unread.get("a"), None);
+
+        handle_ui_event(
+            UiEvent::RoomSwitched {
+                name: "b".to_string(),
+                history,
+            },
+            &mut state,
+        );
+        assert_eq!(state.first_unread, Some(1));
+        assert!(state.unread.is_empty());
+    }
+
+    #[test]
+    fn messages_arriving_while_scrolled_up_are_unread() {
+        let mut state = CliState::new("me".to_string(), true);
+        for text in ["a", "b"] {
+            state.push_message(DisplayMessage::chat("bob#1a2b", text));
+        }
+        state.scroll = 1;
+        state.push_message(DisplayMessage::system("carol joined the room"));
+        state.push_message(DisplayMessage::chat("bob#1a2b", "c"));
+        state.push_message(DisplayMessage::chat("me#0000", "mine").sent(1));
+        assert_eq!(state.first_unread, Some(3));
+        assert_eq!(state.unread_below(), 1);
+    }
+
+    #[test]
+    fn alt_arrows_pick_a_message_to_reply_to() {
+        let mut state = CliState::new("me".to_string(), true);
+        state.screen = Screen::Chat;
+        state.push_message(DisplayMessage::chat("bob#1a2b", "first").with_id(1));
+        state.push_message(DisplayMessage::system("carol joined the room"));
+        state.push_message(DisplayMessage::chat("bob#1a2b", "second").with_id(2));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+
+        for _ in 0..3 {
+            handle_key(key(KeyCode::Up, KeyModifiers::ALT), &mut state, &tx);
+        }
+        assert_eq!(state.reply_to, Some(1));
+        handle_key(key(KeyCode::Down, KeyModifiers::ALT), &mut state, &tx);
+        assert_eq!(state.reply_to, Some(2));
+
+        for c in "agreed".chars() {
+            handle_key(key(KeyCode::Char(c), KeyModifiers::NONE), &mut state, &tx);
+        }
+        handle_key(key(KeyCode::Enter, KeyModifiers::NONE), &mut state, &tx);
+        assert!(matches!(rx.try_recv(), Ok(CliCommand::Reply { to: 2, text }) if text == "agreed"));
+        assert_eq!(state.reply_to, None);
+    }
+
+    #[test]
+    fn pastes_go_in_whole_and_long_ones_wait_for_enter() {
+        let mut state = CliState::new("me".to_string(), true);
+        state.screen = Screen::Chat;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let press = |code, state: &mut CliState| handle_key(KeyEvent::from(code), state, &tx);
+
+        handle_paste("first line\nsecond line\n".to_string(), &mut state);
+        assert!(rx.try_recv().is_err());
+        press(KeyCode::Enter, &mut state);
+        assert!(
+            matches!(rx.try_recv(), Ok(CliCommand::SendMessage(text)) if text == "first line\nsecond line")
+        );
+        assert!(rx.try_recv().is_err());
+
+        // Pasted commands are text, and indentation survives.
+        handle_paste("/leave\n\tfn main() {}".to_string(), &mut state);
+        press(KeyCode::Enter, &mut state);
+        assert!(
+            matches!(rx.try_recv(), Ok(CliCommand::SendMessage(text)) if text == "/leave\n    fn main() {}")
+        );
+        for c in "  spaced ".chars() {
+            press(KeyCode::Char(c), &mut state);
+        }
+        press(KeyCode::Enter, &mut state);
+        assert!(matches!(rx.try_recv(), Ok(CliCommand::SendMessage(text)) if text == "  spaced "));
+
+        let long = "x".repeat(PASTE_CONFIRM_CHARS + 1);
+        handle_paste(long.clone(), &mut state);
+        press(KeyCode::Char('y'), &mut state);
+        assert!(state.input.as_str().is_empty() && state.pending_paste.is_some());
+        press(KeyCode::Esc, &mut state);
+        assert!(state.pending_paste.is_none() && state.input.as_str().is_empty());
+        handle_paste(long.clone(), &mut state);
+        press(KeyCode::Enter, &mut state);
+        assert_eq!(state.input.as_str(), long);
+
+        // A room code pasted with its line break does not submit the prompt.
+        state.input.clear();
+        state.open_prompt(
+            Screen::JoinRoom {
+                step: 0,
+                by_name: false,
+            },
+            "Room code: ",
+            false,
+        );
+        handle_paste("chat2:abc\n".to_string(), &mut state);
+        assert_eq!(state.input.as_str(), "chat2:abc");
+    }
+
+    #[test]
+    fn clicks_pick_menu_entries_and_place_the_input_cursor() {
+        let click = |column, row| MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        };
+        let area = Rect::new(0, 0, 80, 24);
+        let mut state = CliState::new("me".to_string(), true);
+        let (tx, _rx) = mpsc::unbounded_channel();
+
+        // The header does nothing; the third entry opens its prompt.
+        let lines = menu_lines(&state);
+        let menu = menu_area(area, &lines);
+        handle_mouse(click(menu.x, menu.y), area, &mut state, &tx);
+        assert!(state.screen == Screen::MainMenu);
+        let row = lines
+            .iter()
+            .position(|(_, key)| *key == Some(KeyCode::Char('3')))
+            .unwrap();
+        handle_mouse(
+            click(menu.x + 2, menu.y + row as u16),
+            area,
+            &mut state,
+            &tx,
+        );
+        assert!(state.screen == Screen::ChangeNickname);
+
+        state.enter_chat("lobby".to_string());
+        for c in "hello".chars() {
+            state.input.handle_key(KeyEvent::from(KeyCode::Char(c)));
+        }
+        // Input row 22, text after the border and "> ".
+        handle_mouse(click(4, 22), area, &mut state, &tx);
+        assert_eq!(state.input.cursor(), 1);
+        handle_mouse(click(4, 5), area, &mut state, &tx);
+        assert_eq!(state.input.cursor(), 1);
+    }
+
+    #[test]
+    fn recent_rooms_rejoin_ask_for_the_password_or_get_pruned() {
+        let mut state = CliState::new("me".to_string(), true);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let room = |name: &str, code: &str, saved| RecentRoom {
+            name: name.to_string(),
+            code: code.to_string(),
+            last_joined: chrono::Utc::now() - chrono::Duration::hours(3),
+            saved,
+        };
+        let recent = vec![room("lobby", "", true), room("den", "chat2:xyz", false)];
+        handle_ui_event(UiEvent::RecentRooms(recent), &mut state);
+        let lines = menu_lines(&state);
+        let texts: Vec<String> = lines.iter().map(|(line, _)| line.to_string()).collect();
+        assert!(
+            texts.contains(&"[5] lobby (3h ago · saved)".to_string()),
+            "{texts:?}"
+        );
+        assert!(texts.contains(&"[6] den (3h ago)".to_string()));
+        let press =
+            |c, state: &mut CliState| handle_key(KeyEvent::from(KeyCode::Char(c)), state, &tx);
+
+        press('5', &mut state);
+        assert!(matches!(rx.try_recv(), Ok(CliCommand::Rejoin(Some(name))) if name == "lobby"));
+        assert!(
+            state.screen
+                == Screen::JoinRoom {
+                    step: 2,
+                    by_name: true
+                }
+        );
+        state.back_to_menu();
+
+        press('6', &mut state);
+        assert!(
+            state.screen
+                == Screen::JoinRoom {
+                    step: 1,
+                    by_name: false
+                }
+        );
+        assert!(state.masking);
+        assert_eq!(state.join_code, "chat2:xyz");
+        state.back_to_menu();
+
+        press('x', &mut state);
+        assert!(
+            menu_lines(&state)
+                .iter()
+                .any(|(l, _)| l.to_string().starts_with("Remove which"))
+        );
+        press('6', &mut state);
+        assert!(matches!(rx.try_recv(), Ok(CliCommand::ForgetRecent(name)) if name == "den"));
+        assert!(!state.pruning);
+        assert_eq!(ago(chrono::Utc::now(), chrono::Utc::now()), "just now");
+    }
+
+    #[test]
+    fn palette_finds_commands_fuzzily_and_runs_or_prefills_them() {
+        let commands: Vec<(String, String)> = [
+            ("/rooms", "list joined rooms"),
+            ("/mute", "toggle notifications for this room"),
+            ("/msg <nick> <text>", "private message to one member"),
+        ]
+        .iter()
+        .map(|&(usage, about)| (usage.to_string(), about.to_string()))
+        .collect();
+        assert_eq!(palette_matches(&commands, ""), [0, 1, 2]);
+        // Close together beats far apart; the command name beats the
+        // description.
+        assert_eq!(palette_matches(&commands, "mt"), [1, 2, 0]);
+        assert_eq!(palette_matches(&commands, "ms"), [2, 0, 1]);
+        assert_eq!(palette_matches(&commands, "private"), [2]);
+        assert!(palette_matches(&commands, "zz").is_empty());
+
+        let mut state = CliState::new("me".to_string(), true);
+        state.commands = commands;
+        state.enter_chat("lobby".to_string());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let press = |state: &mut CliState, code, modifiers| {
+            handle_key(KeyEvent::new(code, modifiers), state, &tx);
+        };
+
+        press(&mut state, KeyCode::Char('p'), KeyModifiers::CONTROL);
+        for c in "mt".chars() {
+            press(&mut state, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        press(&mut state, KeyCode::Enter, KeyModifiers::NONE);
+        assert!(matches!(rx.try_recv(), Ok(CliCommand::ToggleMute)));
+        assert!(state.palette.is_none());
+
+        // Arguments needed: the command waits in the input.
+        press(&mut state, KeyCode::Char('p'), KeyModifiers::CONTROL);
+        press(&mut state, KeyCode::Down, KeyModifiers::NONE);
+        press(&mut state, KeyCode::Down, KeyModifiers::NONE);
+        press(&mut state, KeyCode::Down, KeyModifiers::NONE);
+        press(&mut state, KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(state.input.as_str(), "/msg ");
+        assert!(rx.try_recv().is_err());
+
+        // `?` types itself unless the input is empty, where it opens the help.
+        press(&mut state, KeyCode::Char('?'), KeyModifiers::NONE);
+        assert_eq!((state.input.as_str(), state.help_scroll), ("/msg ?", None));
+        state.input.clear();
+        press(&mut state, KeyCode::Char('?'), KeyModifiers::NONE);
+        assert_eq!(state.help_scroll, Some(0));
+        press(&mut state, KeyCode::Char('x'), KeyModifiers::NONE);
+        assert_eq!((state.input.as_str(), state.help_scroll), ("", None));
+    }
+
+    #[test]
+    fn remapped_keys_take_over_from_the_usual_ones() {
+        let config = chatting1_core::config::KeysConfig {
+            send: Some("alt+enter".to_string()),
+            quit: Some("ctrl+q".to_string()),
+            command_palette: Some("f2".to_string()),
+            switch_room: Some("ctrl".to_string()),
+            ..Default::default()
+        };
+        let keys = KeyBindings::new(&config).unwrap();
+        let mut state = CliState {
+            keys,
+            ..CliState::new("me".to_string(), true)
+        };
+        state.enter_chat("lobby".to_string());
+        state.rooms = vec!["lobby".to_string(), "dev".to_string()];
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut press =
+            |code, modifiers| handle_key(KeyEvent::new(code, modifiers), &mut state, &tx);
+
+        press(KeyCode::Char('h'), KeyModifiers::NONE);
+        press(KeyCode::Enter, KeyModifiers::NONE);
+        assert!(rx.try_recv().is_err());
+        press(KeyCode::Enter, KeyModifiers::ALT);
+        assert!(matches!(rx.try_recv(), Ok(CliCommand::SendMessage(text)) if text == "h"));
+        press(KeyCode::Char('2'), KeyModifiers::CONTROL);
+        assert!(matches!(rx.try_recv(), Ok(CliCommand::SwitchRoom(room)) if room == "dev"));
+
+        assert!(!press(KeyCode::Char('c'), KeyModifiers::CONTROL));
+        press(KeyCode::F(2), KeyModifiers::NONE);
+        assert!(state.palette.is_some());
+        let help = key_help(&state.keys);
+        assert_eq!(
+            (help[0].0.as_str(), help[11].0.as_str()),
+            ("Alt+Enter", "Ctrl+Q")
+        );
+        assert!(handle_key(
+            KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL),
+            &mut state,
+            &tx
+        ));
+    }
+
+    #[test]
+    fn the_notes_screen_edits_inserts_and_deletes_lines() {
+        let mut state = CliState::new("me".to_string(), true);
+        state.enter_chat("lobby".to_string());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let type_line = |state: &mut CliState, text: &str| {
+            for c in text.chars() {
+                handle_key(KeyEvent::from(KeyCode::Char(c)), state, &tx);
+            }
+            handle_key(KeyEvent::from(KeyCode::Enter), state, &tx);
+        };
+        type_line(&mut state, "/notes");
+        assert!(matches!(rx.try_recv(), Ok(CliCommand::ShowNotes)));
+        let id = |clock| NoteId { clock, site: 1 };
+        let notes = |lines: &[(u64, &str)]| UiEvent::Notes {
+            room: "lobby".to_string(),
+            lines: lines
+                .iter()
+                .map(|&(clock, text)| (id(clock), text.to_string()))
+                .collect(),
+        };
+        handle_ui_event(notes(&[(1, "milk"), (2, "eggs")]), &mut state);
+        // Opened while still empty: adding goes below the last line.
+        assert!(
+            state.screen
+                == Screen::Notes {
+                    selected: 1,
+                    adding: true
+                }
+        );
+        type_line(&mut state, "bread");
+        assert!(
+            matches!(rx.try_recv(), Ok(CliCommand::EditNote(NoteEdit::Insert { after: Some(after), text })) if after == id(2) && text == "bread")
+        );
+
+        // Up picks a line to edit; Ctrl+D deletes it, Ctrl+O adds below.
+        handle_ui_event(notes(&[(1, "milk"), (2, "eggs"), (3, "bread")]), &mut state);
+        for _ in 0..2 {
+            handle_key(KeyEvent::from(KeyCode::Up), &mut state, &tx);
+        }
+        assert_eq!(state.input.as_str(), "eggs");
+        handle_key(KeyEvent::from(KeyCode::Backspace), &mut state, &tx);
+        handle_key(KeyEvent::from(KeyCode::Enter), &mut state, &tx);
+        assert!(
+            matches!(rx.try_recv(), Ok(CliCommand::EditNote(NoteEdit::Set { id: line, text })) if line == id(2) && text == "egg")
+        );
+        handle_key(
+            KeyEvent::new(KeyCode::Char('d'), KeyModifiers::CONTROL),
+            &mut state,
+            &tx,
+        );
+        assert!(
+            matches!(rx.try_recv(), Ok(CliCommand::EditNote(NoteEdit::Delete(line))) if line == id(2))
+        );
+        handle_key(
+            KeyEvent::new(KeyCode::Char('o'), KeyModifiers::CONTROL),
+            &mut state,
+            &tx,
+        );
+        type_line(&mut state, "jam");
+        assert!(
+            matches!(rx.try_recv(), Ok(CliCommand::EditNote(NoteEdit::Insert { after: Some(after), .. })) if after == id(2))
+        );
+        handle_key(KeyEvent::from(KeyCode::Esc), &mut state, &tx);
+        assert!(state.screen == Screen::Chat);
+    }
+
+    #[test]
+    fn a_thread_is_picked_from_the_list_and_the_room_is_row_0() {
+        let mut state = CliState::new("me".to_string(), true);
+        state.enter_chat("lobby".to_string());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        for c in "/threads".chars() {
+            handle_key(KeyEvent::from(KeyCode::Char(c)), &mut state, &tx);
+        }
+        handle_key(KeyEvent::from(KeyCode::Enter), &mut state, &tx);
+        assert!(matches!(rx.try_recv(), Ok(CliCommand::ShowThreads)));
+        let thread = |name: &str| ThreadSummary {
+            name: name.to_string(),
+            messages: 1,
+            last: chrono::Utc::now(),
+        };
+        let threads = vec![thread("design"), thread("ops")];
+        handle_ui_event(
+            UiEvent::Threads {
+                room: "lobby".to_string(),
+                threads,
+            },
+            &mut state,
+        );
+        for key in [KeyCode::Down, KeyCode::Down, KeyCode::Down, KeyCode::Up] {
+            handle_key(KeyEvent::from(key), &mut state, &tx);
+        }
+        handle_key(KeyEvent::from(KeyCode::Enter), &mut state, &tx);
+        assert!(
+            matches!(rx.try_recv(), Ok(CliCommand::OpenThread(Some(name))) if name == "design")
+        );
+        assert!(state.screen == Screen::Chat);
+        let parse = |input: &str| parse_command(input, &[], &VecDeque::new(), &[]).unwrap();
+        assert!(matches!(parse("/thread"), CliCommand::OpenThread(None)));
+    }
+
+    #[test]
+    fn room_code_qr_packs_two_modules_per_row() {
+        let rows = qr_rows(&"7xKpQm3NvBsRtYdEfGhJ2cLwAoP9uXiZ".repeat(8));
+        let modules = rows[0].width();
+        assert!(rows.iter().all(|r| r.width() == modules));
+        assert_eq!(rows.len(), modules.div_ceil(2));
+
+        let mut state = CliState::new("me".to_string(), true);
+        state.enter_chat("lobby".to_string());
+        handle_ui_event(
+            UiEvent::RoomCode {
+                name: "lobby".to_string(),
+                code: "abc".to_string(),
+            },
+            &mut state,
+        );
+        let (tx, _rx) = mpsc::unbounded_channel();
+        handle_key(
+            KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE),
+            &mut state,
+            &tx,
+        );
+        assert_eq!((state.qr_code.as_deref(), state.input.as_str()), (None, ""));
     }
 }
@@ -1,68 +1,214 @@
-use anyhow::{Context, Result};
-use base64::{engine::general_purpose::STANDARD as B64, Engine};
-use libp2p::{
-    identity::{self, Keypair},
-    PeerId,
+//! `chat identity export FILE` and `chat identity import FILE`: move an
+//! identity (key pair, nickname, contacts) to another machine in an
+//! archive sealed with a passphrase.  Either works on the config `--config`
+//! or `--profile` picks, `~/.chatrc` by default.
+//!
+//! `chat identity vault-passphrase` sets the passphrase the room vault
+//! (rooms kept with `/save`) is sealed with on top of the identity key, and
+//! [`unlock_vault`] asks for it when chat starts.
+
+use std::{
+    io::{self, BufRead, IsTerminal, Write},
+    path::PathBuf,
 };
 
-use crate::config::Config;
+use anyhow::{Context, Result, bail, ensure};
+use clap::Subcommand;
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use zeroize::Zeroizing;
+
+use chatting1_core::{
+    Config, Identity,
+    contacts::Contacts,
+    identity::{Archive, store_key, stored_key},
+    vault::Vault,
+};
 
-pub struct Identity {
-    pub keypair: Keypair,
-    pub peer_id: PeerId,
-    pub nickname: String,
-    /// First 4 hex chars derived from the Peer ID bytes — e.g. "3f2a".
-    pub discriminator: String,
+/// Tries at the vault passphrase before chat starts with the vault locked.
+const UNLOCK_TRIES: usize = 3;
+
+/// Options of the `identity` subcommand.
+#[derive(Debug, clap::Args)]
+pub struct IdentityOptions {
+    #[command(subcommand)]
+    pub command: IdentityCommand,
 }
 
-impl Identity {
-    /// Load or generate an Ed25519 keypair from `config`, then build the identity.
-    /// Saves updated config if a new keypair was generated.
-    pub fn load_or_create(config: &mut Config) -> Result<Self> {
-        let keypair = match &config.private_key_b64 {
-            Some(b64) => {
-                let bytes = B64.decode(b64).context("decode private key base64")?;
-                Keypair::from_protobuf_encoding(&bytes).context("decode keypair from protobuf")?
+#[derive(Debug, Subcommand)]
+pub enum IdentityCommand {
+    /// Write the identity to FILE, sealed with a passphrase.
+    Export {
+        #[arg(value_name = "FILE")]
+        path: PathBuf,
+    },
+    /// Make the identity in FILE, written by `export`, this one.
+    Import {
+        #[arg(value_name = "FILE")]
+        path: PathBuf,
+        /// Replace the other identity the config already holds.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Set, change or remove (empty answer) the passphrase the rooms kept
+    /// with /save are sealed with.
+    VaultPassphrase,
+}
+
+pub fn run(opts: IdentityOptions, mut config: Config) -> Result<()> {
+    match opts.command {
+        IdentityCommand::Export { path } => {
+            let Some(private_key_b64) = stored_key(&mut config)? else {
+                bail!(
+                    "{} holds no identity yet: run chat once to make one.",
+                    config.path.display()
+                );
+            };
+            let identity = Identity::load_or_create(&mut config)?;
+            let contacts = Contacts::load(config.resolve(&config.contacts_file).into());
+            let archive = Archive {
+                private_key_b64: private_key_b64.to_string(),
+                nickname: config.nickname.clone(),
+                contacts: contacts
+                    .iter()
+                    .map(|(id, c)| (id.clone(), c.clone()))
+                    .collect(),
+            };
+            let passphrase = read_passphrase("Passphrase for the archive: ")?;
+            ensure!(!passphrase.is_empty(), "The archive needs a passphrase.");
+            let again = read_passphrase("Passphrase again: ")?;
+            ensure!(passphrase == again, "The passphrases differ.");
+            std::fs::write(&path, archive.seal(&passphrase)?)
+                .with_context(|| format!("Cannot write {}", path.display()))?;
+            println!(
+                "Exported {} with {} contacts to {}.",
+                identity.display_name(),
+                archive.contacts.len(),
+                path.display()
+            );
+        }
+        IdentityCommand::Import { path, force } => {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Cannot read {}", path.display()))?;
+            let passphrase = read_passphrase("Passphrase of the archive: ")?;
+            let archive = Archive::open(&text, &passphrase)?;
+            if let Some(current) = stored_key(&mut config)?
+                && *current != archive.private_key_b64
+                && !force
+            {
+                bail!(
+                    "{} already holds another identity; --force replaces it, and it is lost unless exported first.",
+                    config.path.display()
+                );
             }
-            None => {
-                let kp = identity::Keypair::generate_ed25519();
-                let bytes = kp
-                    .to_protobuf_encoding()
-                    .context("encode keypair to protobuf")?;
-                config.private_key_b64 = Some(B64.encode(&bytes));
-                kp
+            store_key(&mut config, &archive.private_key_b64)?;
+            if archive.nickname.is_some() {
+                config.nickname = archive.nickname.clone();
             }
-        };
-
-        let peer_id = PeerId::from(keypair.public());
-        let discriminator = discriminator_from_peer_id(&peer_id);
-
-        let nickname = config
-            .nickname
-            .clone()
-            .unwrap_or_else(|| format!("Peer{}", &discriminator));
+            config.save()?;
+            let mut contacts = Contacts::load(config.resolve(&config.contacts_file).into());
+            contacts.import(
+                archive
+                    .contacts
+                    .iter()
+                    .map(|(id, c)| (id.clone(), c.clone())),
+            )?;
+            let identity = Identity::load_or_create(&mut config)?;
+            println!(
+                "Imported {} with {} contacts into {}.",
+                identity.display_name(),
+                archive.contacts.len(),
+                config.path.display()
+            );
+        }
+        IdentityCommand::VaultPassphrase => {
+            let identity = Identity::load_or_create(&mut config)?;
+            let mut vault = Vault::load(config.resolve(&config.rooms_file).into());
+            let current = if vault.has_passphrase() {
+                Some(read_passphrase("Current passphrase of the room vault: ")?)
+            } else {
+                None
+            };
+            let old = vault.key(&identity.keypair, current.as_deref().map(String::as_str))?;
+            let passphrase = read_passphrase("New passphrase (Enter to remove it): ")?;
+            if passphrase.is_empty() {
+                vault.set_passphrase(&identity.keypair, &old, None)?;
+                println!("The room vault is sealed with the identity key alone.");
+            } else {
+                let again = read_passphrase("Passphrase again: ")?;
+                ensure!(passphrase == again, "The passphrases differ.");
+                vault.set_passphrase(&identity.keypair, &old, Some(&passphrase))?;
+                println!("The room vault now needs its passphrase when chat starts.");
+            }
+        }
+    }
+    Ok(())
+}
 
-        Ok(Self {
-            keypair,
-            peer_id,
-            nickname,
-            discriminator,
-        })
+/// Ask for the passphrase of the room vault when it has one and stdin is a
+/// terminal.  `None` leaves it locked: chat runs, but `/save` and `/rejoin`
+/// fail until the next start.
+pub fn unlock_vault(config: &Config, identity: &Identity) -> Result<Option<Zeroizing<String>>> {
+    let vault = Vault::load(config.resolve(&config.rooms_file).into());
+    if !vault.has_passphrase() || !io::stdin().is_terminal() {
+        return Ok(None);
     }
+    for _ in 0..UNLOCK_TRIES {
+        let passphrase = read_passphrase("Passphrase of the room vault (Enter to skip): ")?;
+        if passphrase.is_empty() {
+            return Ok(None);
+        }
+        match vault.key(&identity.keypair, Some(&passphrase)) {
+            Ok(_) => return Ok(Some(passphrase)),
+            Err(e) => println!("{e}."),
+        }
+    }
+    println!("Starting with the room vault locked.");
+    Ok(None)
+}
 
-    /// Returns the formatted display name, e.g. `"Seung#3f2a"`.
-    pub fn display_name(&self) -> String {
-        format!("{}#{}", self.nickname, self.discriminator)
+/// Read a passphrase without echoing it, or a line of stdin when that is
+/// not a terminal (scripts).
+fn read_passphrase(prompt: &str) -> Result<Zeroizing<String>> {
+    print!("{prompt}");
+    io::stdout().flush()?;
+    if !io::stdin().is_terminal() {
+        let line = Zeroizing::new(
+            io::stdin()
+                .lock()
+                .lines()
+                .next()
+                .transpose()?
+                .unwrap_or_default(),
+        );
+        return Ok(Zeroizing::new(line.trim_end_matches('\r').to_string()));
     }
+    crossterm::terminal::enable_raw_mode()?;
+    let passphrase = read_hidden();
+    let _ = crossterm::terminal::disable_raw_mode();
+    println!();
+    passphrase
 }
 
-/// Derive a 4-character hex discriminator from the first two bytes of the
-/// multihash bytes of a Peer ID.
-pub fn discriminator_from_peer_id(peer_id: &PeerId) -> String {
-    let bytes = peer_id.to_bytes();
-    // bytes[0] and bytes[1] are the multihash code + length prefix.
-    // Take bytes 2..4 which are the start of the actual key hash.
-    let a = bytes.get(2).copied().unwrap_or(bytes[0]);
-    let b = bytes.get(3).copied().unwrap_or(bytes[1]);
-    format!("{:02x}{:02x}", a, b)
+fn read_hidden() -> Result<Zeroizing<String>> {
+    // Reserved up front so typing never reallocates and leaves a copy.
+    let mut passphrase = Zeroizing::new(String::with_capacity(1024));
+    loop {
+        let Event::Key(key) = crossterm::event::read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter => return Ok(passphrase),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                bail!("Cancelled.")
+            }
+            KeyCode::Char(c) if passphrase.len() < passphrase.capacity() - 4 => passphrase.push(c),
+            KeyCode::Backspace => {
+                passphrase.pop();
+            }
+            _ => {}
+        }
+    }
 }
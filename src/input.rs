@@ -0,0 +1,313 @@
+//! Single-line text input with a cursor, used by every prompt in the TUI.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_width::UnicodeWidthChar;
+use zeroize::Zeroize;
+
+/// Longest input accepted, in characters.
+pub const MAX_INPUT_LEN: usize = 2048;
+/// Spaces a tab in a multi-line paste becomes, keeping code indented.
+const TAB_WIDTH: usize = 4;
+
+/// Text being typed plus the cursor position, counted in characters (not
+/// bytes) so multi-byte input edits correctly.
+#[derive(Default)]
+pub struct InputLine {
+    text: String,
+    cursor: usize,
+}
+
+impl InputLine {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Character index of the cursor (0 = before the first character).
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn char_count(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    /// Empty the line, zeroing the old text: it may have been a password.
+    pub fn clear(&mut self) {
+        self.text.zeroize();
+        self.cursor = 0;
+    }
+
+    /// Make room for the longest input up front, so typing a password
+    /// never reallocates and leaves its first characters in freed memory.
+    pub fn reserve_all(&mut self) {
+        self.text.reserve(MAX_INPUT_LEN * char::MAX_LEN_UTF8);
+    }
+
+    /// Return the text and leave the line empty.
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        std::mem::take(&mut self.text)
+    }
+
+    /// Put the cursor where `column` (in cells) of a field `width` cells
+    /// wide was clicked, as laid out by `visible_window`.  Clicks past the
+    /// text land at its end.
+    pub fn click(&mut self, column: usize, width: usize) {
+        let widths: Vec<usize> = self
+            .text
+            .chars()
+            .map(|c| shown(c).width().unwrap_or(0))
+            .collect();
+        let mut at = window_start(&widths, self.cursor, width);
+        let mut used = 0;
+        while at < widths.len() && used + widths[at] <= column {
+            used += widths[at];
+            at += 1;
+        }
+        self.cursor = at;
+    }
+
+    /// Apply an editing or movement key.  Returns `false` for keys that are
+    /// not input keys, so the caller can handle them.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('w') if ctrl => self.delete_word(),
+            KeyCode::Char('u') if ctrl => self.kill_to_start(),
+            KeyCode::Char(_) if ctrl => return false,
+            KeyCode::Char(c) => self.insert(c),
+            KeyCode::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.remove(self.cursor);
+                }
+            }
+            KeyCode::Delete => self.remove(self.cursor),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.char_count()),
+            KeyCode::Home if !ctrl => self.cursor = 0,
+            KeyCode::End if !ctrl => self.cursor = self.char_count(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Insert pasted `text` at the cursor, as far as it fits.  Line breaks
+    /// are kept as `\n` when `multiline`, and become spaces otherwise, but
+    /// for trailing ones, which are dropped; tabs become `TAB_WIDTH` spaces
+    /// when `multiline` and one otherwise; other control characters are
+    /// dropped.
+    pub fn paste(&mut self, text: &str, multiline: bool) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let text = if multiline {
+            text.as_str()
+        } else {
+            text.trim_end_matches('\n')
+        };
+        for c in text.chars() {
+            match c {
+                '\n' if multiline => self.insert(c),
+                '\t' if multiline => (0..TAB_WIDTH).for_each(|_| self.insert(' ')),
+                '\n' | '\t' => self.insert(' '),
+                c if c.is_control() => {}
+                c => self.insert(c),
+            }
+        }
+    }
+
+    fn insert(&mut self, c: char) {
+        if self.char_count() >= MAX_INPUT_LEN {
+            return;
+        }
+        let at = self.byte_index(self.cursor);
+        self.text.insert(at, c);
+        self.cursor += 1;
+    }
+
+    /// Remove the character at char index `at`, if any.
+    fn remove(&mut self, at: usize) {
+        if at < self.char_count() {
+            self.text.remove(self.byte_index(at));
+        }
+    }
+
+    /// Ctrl+W: delete back to the start of the previous word.
+    fn delete_word(&mut self) {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut start = self.cursor;
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.text
+            .replace_range(self.byte_index(start)..self.byte_index(self.cursor), "");
+        self.cursor = start;
+    }
+
+    /// Ctrl+U: delete everything before the cursor.
+    fn kill_to_start(&mut self) {
+        self.text.replace_range(..self.byte_index(self.cursor), "");
+        self.cursor = 0;
+    }
+
+    fn byte_index(&self, chars: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(chars)
+            .map_or(self.text.len(), |(i, _)| i)
+    }
+}
+
+/// The part of `text` to show in a field `width` cells wide so that char
+/// index `cursor` stays visible, and the cursor's column (in cells) within
+/// it.  Wide glyphs (CJK, most emoji) take two cells; a pasted line break
+/// shows as `↵`.
+pub fn visible_window(text: &str, cursor: usize, width: usize) -> (String, usize) {
+    let cells: Vec<(char, usize)> = text
+        .chars()
+        .map(shown)
+        .map(|c| (c, c.width().unwrap_or(0)))
+        .collect();
+    let widths: Vec<usize> = cells.iter().map(|&(_, w)| w).collect();
+    let start = window_start(&widths, cursor, width);
+    let column = widths[start..cursor].iter().sum();
+    let mut used = 0;
+    let shown = cells[start..]
+        .iter()
+        .take_while(|(_, w)| {
+            used += w;
+            used <= width
+        })
+        .map(|(c, _)| c)
+        .collect();
+    (shown, column)
+}
+
+/// How `c` is drawn in the field.
+fn shown(c: char) -> char {
+    if c == '\n' { '↵' } else { c }
+}
+
+/// Index of the first character `visible_window` shows, given the cell
+/// widths of all of them.
+fn window_start(widths: &[usize], cursor: usize, width: usize) -> usize {
+    // Keep one cell free for the cursor when it sits at the end.
+    let mut start = cursor;
+    let mut column = 0;
+    while start > 0 && column + widths[start - 1] < width {
+        start -= 1;
+        column += widths[start];
+    }
+    start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(line: &mut InputLine, code: KeyCode) {
+        line.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    fn ctrl(line: &mut InputLine, c: char) {
+        line.handle_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL));
+    }
+
+    fn typed(text: &str) -> InputLine {
+        let mut line = InputLine::default();
+        for c in text.chars() {
+            press(&mut line, KeyCode::Char(c));
+        }
+        line
+    }
+
+    #[test]
+    fn inserts_and_deletes_at_the_cursor() {
+        let mut line = typed("héllo");
+        press(&mut line, KeyCode::Home);
+        press(&mut line, KeyCode::Right);
+        press(&mut line, KeyCode::Char('X'));
+        assert_eq!((line.as_str(), line.cursor()), ("hXéllo", 2));
+        press(&mut line, KeyCode::Delete);
+        press(&mut line, KeyCode::Backspace);
+        assert_eq!((line.as_str(), line.cursor()), ("hllo", 1));
+        press(&mut line, KeyCode::End);
+        press(&mut line, KeyCode::Right);
+        assert_eq!(line.cursor(), 4);
+    }
+
+    #[test]
+    fn word_and_line_kills_stop_at_the_cursor() {
+        let mut line = typed("say hello  world");
+        for _ in 0..5 {
+            press(&mut line, KeyCode::Left);
+        }
+        ctrl(&mut line, 'w');
+        assert_eq!((line.as_str(), line.cursor()), ("say world", 4));
+        ctrl(&mut line, 'u');
+        assert_eq!((line.as_str(), line.cursor()), ("world", 0));
+    }
+
+    #[test]
+    fn reserved_line_never_reallocates() {
+        let mut line = InputLine::default();
+        line.reserve_all();
+        let (buf, capacity) = (line.text.as_ptr(), line.text.capacity());
+        for _ in 0..MAX_INPUT_LEN + 1 {
+            press(&mut line, KeyCode::Char('🔑'));
+        }
+        assert_eq!(line.char_count(), MAX_INPUT_LEN);
+        assert_eq!((line.text.as_ptr(), line.text.capacity()), (buf, capacity));
+    }
+
+    #[test]
+    fn pastes_keep_line_breaks_only_where_asked() {
+        let mut line = typed("ab");
+        press(&mut line, KeyCode::Left);
+        line.paste("one\r\ntwo\t3\x1b\n", true);
+        assert_eq!((line.as_str(), line.cursor()), ("aone\ntwo    3\nb", 14));
+        assert_eq!(visible_window(line.as_str(), 0, 20).0, "aone↵two    3↵b");
+
+        let mut line = InputLine::default();
+        line.paste("chat2:abc\n", false);
+        line.paste("x\ny", false);
+        assert_eq!(line.as_str(), "chat2:abcx y");
+        line.paste(&"z".repeat(MAX_INPUT_LEN), false);
+        assert_eq!(line.char_count(), MAX_INPUT_LEN);
+    }
+
+    #[test]
+    fn window_follows_the_cursor() {
+        assert_eq!(visible_window("abcdef", 6, 4), ("def".to_string(), 3));
+        assert_eq!(visible_window("abcdef", 0, 4), ("abcd".to_string(), 0));
+        assert_eq!(visible_window("ab", 1, 4), ("ab".to_string(), 1));
+    }
+
+    #[test]
+    fn click_moves_the_cursor_to_the_cell_clicked() {
+        let mut line = typed("a你b");
+        line.click(1, 8);
+        assert_eq!(line.cursor(), 1);
+        // Either half of the wide glyph puts the cursor before it.
+        line.click(2, 8);
+        assert_eq!(line.cursor(), 1);
+        line.click(3, 8);
+        assert_eq!(line.cursor(), 2);
+        line.click(20, 8);
+        assert_eq!(line.cursor(), 3);
+
+        // Scrolled: column 0 is the first character shown, not the first typed.
+        let mut line = typed("abcdef");
+        line.click(0, 4);
+        assert_eq!(line.cursor(), 3);
+    }
+
+    #[test]
+    fn window_counts_wide_glyphs_as_two_cells() {
+        assert_eq!(visible_window("你好世界", 4, 5), ("世界".to_string(), 4));
+        assert_eq!(visible_window("你好世界", 0, 5), ("你好".to_string(), 0));
+        assert_eq!(visible_window("a你b", 2, 8), ("a你b".to_string(), 3));
+    }
+}
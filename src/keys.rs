@@ -0,0 +1,272 @@
+//! Key chords of the terminal UI from `[keys]`: what sends, quits, scrolls,
+//! switches rooms and opens the command palette, for terminals where the
+//! usual chords cannot be typed.
+
+use anyhow::{Result, anyhow, bail, ensure};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use chatting1_core::config::KeysConfig;
+
+/// A key with the modifiers held for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl Chord {
+    const fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        Self { code, modifiers }
+    }
+
+    /// `ctrl+p`, `alt+enter`, `f2`: a key name after any modifiers.
+    fn parse(text: &str) -> Result<Self> {
+        let (mods, key) = text.rsplit_once('+').unwrap_or(("", text));
+        // `ctrl++` binds the plus key itself.
+        let (mods, key) = match key {
+            "" if mods.ends_with('+') => (mods.trim_end_matches('+'), "+"),
+            _ => (mods, key),
+        };
+        let code = match key.to_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" | "ins" => KeyCode::Insert,
+            "space" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" | "pgup" => KeyCode::PageUp,
+            "pagedown" | "pgdn" => KeyCode::PageDown,
+            name => match (name.chars().next(), name.chars().count()) {
+                (Some(c), 1) => KeyCode::Char(c),
+                (Some('f'), _) => match name[1..].parse() {
+                    Ok(n @ 1..=12) => KeyCode::F(n),
+                    _ => bail!("'{key}' is not a key"),
+                },
+                _ => bail!("'{key}' is not a key"),
+            },
+        };
+        Ok(Self::new(code, parse_modifiers(mods)?))
+    }
+
+    /// Whether `key` is this chord.  Extra modifiers do not matter, nor
+    /// the case of a letter (Shift may or may not be reported with it).
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        let code = match (key.code, self.code) {
+            (KeyCode::Char(a), KeyCode::Char(b)) => a.to_lowercase().eq(b.to_lowercase()),
+            (a, b) => a == b,
+        };
+        code && key.modifiers.contains(self.modifiers)
+    }
+
+    /// As the help shows it: `Ctrl+P`, `PgUp`.
+    pub fn label(&self) -> String {
+        let key = match self.code {
+            KeyCode::Enter => "Enter".to_string(),
+            KeyCode::PageUp => "PgUp".to_string(),
+            KeyCode::PageDown => "PgDn".to_string(),
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(c) => c.to_uppercase().to_string(),
+            code => code.to_string(),
+        };
+        format!("{}{key}", modifiers_label(self.modifiers))
+    }
+}
+
+/// `ctrl+alt`: modifiers joined by `+`; empty for none.
+fn parse_modifiers(text: &str) -> Result<KeyModifiers> {
+    let mut modifiers = KeyModifiers::NONE;
+    for name in text.split('+').filter(|name| !name.is_empty()) {
+        modifiers |= match name.to_lowercase().as_str() {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" | "meta" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => bail!("'{name}' is not a modifier (ctrl, alt or shift)"),
+        };
+    }
+    Ok(modifiers)
+}
+
+/// `Ctrl+Alt+`, to go before a key.
+fn modifiers_label(modifiers: KeyModifiers) -> String {
+    [
+        (KeyModifiers::CONTROL, "Ctrl+"),
+        (KeyModifiers::ALT, "Alt+"),
+        (KeyModifiers::SHIFT, "Shift+"),
+    ]
+    .into_iter()
+    .filter(|&(modifier, _)| modifiers.contains(modifier))
+    .map(|(_, label)| label)
+    .collect()
+}
+
+pub struct KeyBindings {
+    pub send: Chord,
+    pub quit: Chord,
+    pub scroll_up: Chord,
+    pub scroll_down: Chord,
+    /// Held with 1…9 to switch to the Nth joined room.
+    pub switch_room: KeyModifiers,
+    pub command_palette: Chord,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            send: Chord::new(KeyCode::Enter, KeyModifiers::NONE),
+            quit: Chord::new(KeyCode::Char('c'), KeyModifiers::CONTROL),
+            scroll_up: Chord::new(KeyCode::PageUp, KeyModifiers::NONE),
+            scroll_down: Chord::new(KeyCode::PageDown, KeyModifiers::NONE),
+            switch_room: KeyModifiers::ALT,
+            command_palette: Chord::new(KeyCode::Char('p'), KeyModifiers::CONTROL),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// The usual chords with those `config` names instead.  Fails on a
+    /// chord that is not one, a plain character (it could not be typed
+    /// any more), and two actions on one chord.
+    pub fn new(config: &KeysConfig) -> Result<Self> {
+        let mut keys = Self::default();
+        let chords = [
+            ("send", &config.send, &mut keys.send),
+            ("quit", &config.quit, &mut keys.quit),
+            ("scroll_up", &config.scroll_up, &mut keys.scroll_up),
+            ("scroll_down", &config.scroll_down, &mut keys.scroll_down),
+            (
+                "command_palette",
+                &config.command_palette,
+                &mut keys.command_palette,
+            ),
+        ];
+        for (name, text, chord) in chords {
+            let Some(text) = text else { continue };
+            let parsed = Chord::parse(text.trim()).map_err(|e| anyhow!("keys.{name}: {e}"))?;
+            ensure!(
+                !matches!(parsed.code, KeyCode::Char(_))
+                    || !(parsed.modifiers - KeyModifiers::SHIFT).is_empty(),
+                "keys.{name}: '{text}' would take a character from typing; add ctrl+ or alt+"
+            );
+            *chord = parsed;
+        }
+        if let Some(text) = &config.switch_room {
+            keys.switch_room =
+                parse_modifiers(text.trim()).map_err(|e| anyhow!("keys.switch_room: {e}"))?;
+            ensure!(
+                !(keys.switch_room - KeyModifiers::SHIFT).is_empty(),
+                "keys.switch_room: '{text}' would take the digits from typing; use ctrl or alt"
+            );
+        }
+        let all = [
+            keys.send,
+            keys.quit,
+            keys.scroll_up,
+            keys.scroll_down,
+            keys.command_palette,
+        ];
+        for (i, chord) in all.iter().enumerate() {
+            ensure!(
+                !all[..i].contains(chord),
+                "keys: {} is bound twice",
+                chord.label()
+            );
+        }
+        Ok(keys)
+    }
+
+    /// `Alt+1…9`, as the help shows it.
+    pub fn switch_room_label(&self) -> String {
+        format!("{}1…9", modifiers_label(self.switch_room))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chords_from_the_config_replace_the_usual_ones() {
+        let config = KeysConfig {
+            send: Some("alt+enter".to_string()),
+            quit: Some("Ctrl+Q".to_string()),
+            scroll_up: Some("f7".to_string()),
+            switch_room: Some("ctrl+alt".to_string()),
+            ..KeysConfig::default()
+        };
+        let keys = KeyBindings::new(&config).unwrap();
+        assert!(
+            keys.send
+                .matches(&KeyEvent::new(KeyCode::Enter, KeyModifiers::ALT))
+        );
+        assert!(
+            !keys
+                .send
+                .matches(&KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE))
+        );
+        assert!(keys.quit.matches(&KeyEvent::new(
+            KeyCode::Char('Q'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT
+        )));
+        assert_eq!(keys.scroll_up.code, KeyCode::F(7));
+        assert!(
+            keys.scroll_down
+                .matches(&KeyEvent::new(KeyCode::PageDown, KeyModifiers::NONE))
+        );
+        assert_eq!(keys.switch_room_label(), "Ctrl+Alt+1…9");
+        assert_eq!(
+            (keys.quit.label(), keys.command_palette.label()),
+            ("Ctrl+Q".to_string(), "Ctrl+P".to_string())
+        );
+        assert_eq!(
+            Chord::parse("ctrl++").unwrap(),
+            Chord::new(KeyCode::Char('+'), KeyModifiers::CONTROL)
+        );
+
+        for (config, err) in [
+            (
+                KeysConfig {
+                    quit: Some("hyper+q".to_string()),
+                    ..KeysConfig::default()
+                },
+                "keys.quit: 'hyper' is not a modifier (ctrl, alt or shift)",
+            ),
+            (
+                KeysConfig {
+                    send: Some("f13".to_string()),
+                    ..KeysConfig::default()
+                },
+                "keys.send: 'f13' is not a key",
+            ),
+            (
+                KeysConfig {
+                    command_palette: Some("p".to_string()),
+                    ..KeysConfig::default()
+                },
+                "keys.command_palette: 'p' would take a character from typing; add ctrl+ or alt+",
+            ),
+            (
+                KeysConfig {
+                    quit: Some("pageup".to_string()),
+                    ..KeysConfig::default()
+                },
+                "keys: PgUp is bound twice",
+            ),
+            (
+                KeysConfig {
+                    switch_room: Some(String::new()),
+                    ..KeysConfig::default()
+                },
+                "keys.switch_room: '' would take the digits from typing; use ctrl or alt",
+            ),
+        ] {
+            assert_eq!(KeyBindings::new(&config).err().unwrap().to_string(), err);
+        }
+    }
+}
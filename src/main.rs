@@ -1,23 +1,27 @@
-mod app;
+mod args;
 mod cli;
-mod config;
-mod crypto;
 mod identity;
-mod logger;
-mod network;
-mod room;
-mod types;
-
-use anyhow::Result;
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
-
-use crate::{
-    app::App,
-    config::Config,
-    identity::Identity,
-    network::NetworkService,
+mod input;
+mod keys;
+mod markup;
+mod notify;
+mod plain;
+mod replay;
+mod settings;
+mod simulate;
+mod theme;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use tracing_subscriber::{EnvFilter, fmt, prelude::*};
+
+use chatting1_core::{
+    App, Config, EventBus, Identity, NetworkService, config, logger, network, types::CliCommand,
+    wire,
 };
 
+use crate::args::{Args, Command};
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialise tracing (write to stderr so it doesn't pollute the TUI).
@@ -26,65 +30,274 @@ async fn main() -> Result<()> {
         .with(EnvFilter::from_default_env())
         .init();
 
+    // ── Subcommands ───────────────────────────────────────────────────────────
+    let args = Args::parse();
+    let (replay_opts, identity_opts, config_opts) = match args.command {
+        Some(Command::Simulate(opts)) => return simulate::run(opts).await,
+        Some(Command::Replay(opts)) => (Some(opts), None, None),
+        Some(Command::Identity(opts)) => (None, Some(opts), None),
+        Some(Command::Config(opts)) => (None, None, Some(opts)),
+        None => (None, None, None),
+    };
+
     // ── Config & identity ─────────────────────────────────────────────────────
-    let mut config = Config::load_or_default();
+    let data_dir = match (args.data_dir, args.portable) {
+        (Some(dir), _) => Some(dir),
+        (None, true) => Some(portable_dir()?),
+        (None, false) => None,
+    };
+    let profiles_dir = data_dir
+        .as_ref()
+        .map_or_else(config::profiles_dir, |dir| dir.join("profiles"));
+    let profile = match (&args.config, args.profile) {
+        (Some(_), _) => None,
+        (None, Some(name)) => Some(name),
+        // Scripts get the default config rather than a question.
+        (None, None) if config_opts.is_some() => None,
+        (None, None) => pick_profile(&profiles_dir)?,
+    };
+    let mut config = match (args.config, profile, data_dir) {
+        (_, Some(name), _) => Config::load_profile_in(&profiles_dir, &name)?,
+        (None, None, Some(dir)) => Config::load_dir(&dir)?,
+        (path, None, _) => Config::load(path.unwrap_or_else(Config::default_path))?,
+    };
+    if let Some(opts) = identity_opts {
+        return identity::run(opts, config);
+    }
+    if let Some(opts) = config_opts {
+        return settings::run(opts, config);
+    }
+    // A replay runs under a throwaway key and files, removed afterwards.
+    let replay_dir = match &replay_opts {
+        Some(_) => {
+            let dir = std::env::temp_dir().join(format!("chat-replay-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+            config = replay::sandbox(config, &dir);
+            Some(dir)
+        }
+        None => None,
+    };
     let mut identity = Identity::load_or_create(&mut config)?;
 
     // Prompt for nickname on first run (before TUI takes over).
-    if config.nickname.is_none() {
+    if config.nickname.is_none() && args.nickname.is_none() {
         let nick = prompt_nickname()?;
         identity.nickname = nick.clone();
         config.nickname = Some(nick);
     }
     config.save()?;
+    let vault_passphrase = identity::unlock_vault(&config, &identity)?;
 
-    // ── Network service ───────────────────────────────────────────────────────
-    let (net_service, net_event_rx, net_cmd_tx) =
-        NetworkService::new(identity.keypair.clone())?;
+    // Flags apply to this session only, so they are set after saving.
+    if let Some(nick) = args.nickname {
+        identity.nickname = nick.trim().chars().take(wire::MAX_NICK_LEN).collect();
+        wire::validate_nickname(&identity.nickname)
+            .map_err(|e| anyhow::anyhow!("Invalid --nickname: {e}"))?;
+    }
+    if let Some(dir) = args.log_dir {
+        // From the working directory, unlike a path in the config file.
+        config.log_dir = std::path::absolute(&dir)?.to_string_lossy().into_owned();
+    }
+    if args.no_bootstrap {
+        config.bootstrap_peers.clear();
+    } else if !args.bootstrap.is_empty() {
+        config.bootstrap_peers = args.bootstrap;
+    }
+    config.ensure_log_dir()?;
+    let theme = theme::Theme::new(&config.theme, theme::no_color())?;
+    let keys = keys::KeyBindings::new(&config.keys)?;
+
+    // ── Network service (or a recorded capture) ──────────────────────────────
+    let (net_event_rx, net_cmd_tx, net_handle, capture) = match &replay_opts {
+        Some(opts) => {
+            let (net_event_rx, net_cmd_tx) = replay::replay(opts)?;
+            (net_event_rx, net_cmd_tx, None, None)
+        }
+        None => {
+            let psk = config
+                .swarm_key
+                .as_deref()
+                .map(network::parse_swarm_key)
+                .transpose()?;
+            let bootstrap_peers = config
+                .bootstrap_peers
+                .iter()
+                .map(|p| network::parse_bootstrap_peer(p))
+                .collect::<Result<Vec<_>>>()?;
+            let rendezvous_points = config
+                .rendezvous_points
+                .iter()
+                .map(|p| network::parse_rendezvous_point(p))
+                .collect::<Result<Vec<_>>>()?;
+            let (net_service, net_event_rx, net_cmd_tx) = NetworkService::new(
+                identity.keypair.clone(),
+                args.port,
+                psk,
+                bootstrap_peers,
+                rendezvous_points,
+                config.rendezvous_server,
+                &config.network.gossipsub,
+            )?;
+
+            // Network task — drives the libp2p swarm.
+            let net_handle = tokio::spawn(async move {
+                net_service.run().await;
+            });
+
+            let (net_event_rx, capture) = match &args.record {
+                Some(path) => {
+                    let (net_event_rx, capture) = replay::record(net_event_rx, path)?;
+                    (net_event_rx, Some(capture))
+                }
+                None => (net_event_rx, None),
+            };
+            (net_event_rx, net_cmd_tx, Some(net_handle), capture)
+        }
+    };
 
     // ── Inter-task channels ───────────────────────────────────────────────────
     let (cli_cmd_tx, cli_cmd_rx) = tokio::sync::mpsc::unbounded_channel();
-    let (ui_event_tx, ui_event_rx) = tokio::sync::mpsc::unbounded_channel();
+    let bus = EventBus::new();
+
+    // Subscribe before the app starts so no early event is missed. The
+    // screen and the logger must see every event, so they never lag.
+    let ui_event_rx = bus.subscribe_lossless();
+    let log_event_rx = bus.subscribe_lossless();
+    let notify_event_rx = bus.subscribe();
 
     // ── Spawn tasks ───────────────────────────────────────────────────────────
 
     let initial_nickname = identity.nickname.clone();
+    let mouse = config.mouse && !args.no_mouse;
+    let formatting = config.format_messages;
 
-    // Network task — drives the libp2p swarm.
-    tokio::spawn(async move {
-        net_service.run().await;
-    });
+    // Logger task — writes room transcripts from bus events.
+    let logger_handle = tokio::spawn(logger::run(
+        log_event_rx,
+        config.resolve(&config.log_dir),
+        config.log_format,
+    ));
+
+    // Notification task — desktop notifications for mentions.
+    tokio::spawn(notify::run(notify_event_rx));
 
     // Application task — owns state and orchestrates everything.
-    let app = App::new(
-        identity,
-        config,
-        net_event_rx,
-        net_cmd_tx,
-        cli_cmd_rx,
-        ui_event_tx,
-    );
+    let app = App::new(identity, config, net_event_rx, net_cmd_tx, cli_cmd_rx, bus);
+    let app = match vault_passphrase {
+        Some(passphrase) => app.with_vault_passphrase(passphrase),
+        None => app,
+    };
+    let app = match capture {
+        Some(tx) => app.with_capture(tx),
+        None => app,
+    };
     let app_handle = tokio::spawn(async move {
         if let Err(e) = app.run().await {
             tracing::error!("App error: {e}");
         }
     });
 
+    // SIGHUP reloads the config, as `/reload` does.
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut hangups = signal(SignalKind::hangup())?;
+        let reload_tx = cli_cmd_tx.clone();
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                if reload_tx.send(CliCommand::ReloadConfig).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
     // CLI task — owns the terminal (runs until the user quits).
-    cli::run_cli(cli_cmd_tx, ui_event_rx, initial_nickname).await?;
+    if args.plain {
+        plain::run_plain(cli_cmd_tx, ui_event_rx, args.join).await?;
+    } else {
+        let options = cli::UiOptions {
+            mouse,
+            formatting,
+            theme,
+            keys,
+        };
+        cli::run_cli(
+            cli_cmd_tx,
+            ui_event_rx,
+            initial_nickname,
+            args.join,
+            options,
+        )
+        .await?;
+    }
 
-    // Give the app a moment to clean up.
-    let _ = tokio::time::timeout(
-        std::time::Duration::from_millis(500),
-        app_handle,
-    )
+    // Give the app a moment to clean up; dropping its bus lets the logger
+    // drain the remaining events and finish, and the network sends the
+    // rooms our leave announcements before it stops.
+    let _ = tokio::time::timeout(std::time::Duration::from_millis(500), async {
+        let _ = app_handle.await;
+        let _ = logger_handle.await;
+        if let Some(net_handle) = net_handle {
+            let _ = net_handle.await;
+        }
+    })
     .await;
+    if let Some(dir) = replay_dir {
+        let _ = std::fs::remove_dir_all(dir);
+    }
 
     Ok(())
 }
 
 /// Blocking stdin prompt for the nickname.
 /// Called before the crossterm TUI starts, so plain I/O is fine.
+/// `chat-data` next to the executable, for `--portable`.
+fn portable_dir() -> Result<std::path::PathBuf> {
+    let exe = std::env::current_exe()?;
+    let dir = exe
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("{} has no directory", exe.display()))?;
+    Ok(dir.join("chat-data"))
+}
+
+/// Ask which profile in `dir` to use, if any were made: `None` for the
+/// default identity (`~/.chatrc`, or the data directory's).  A name not
+/// listed makes a new profile.
+fn pick_profile(dir: &std::path::Path) -> Result<Option<String>> {
+    use std::io::{self, BufRead, Write};
+    let profiles = Config::profiles_in(dir);
+    if profiles.is_empty() {
+        return Ok(None);
+    }
+    println!("Profiles:");
+    println!("  0) default");
+    for (i, name) in profiles.iter().enumerate() {
+        println!("  {}) {}", i + 1, name);
+    }
+    loop {
+        print!("Profile (number, or a new name) [0]: ");
+        io::stdout().flush()?;
+        let Some(line) = io::stdin().lock().lines().next().transpose()? else {
+            return Ok(None);
+        };
+        let choice = line.trim();
+        let picked = match choice.parse::<usize>() {
+            _ if choice.is_empty() => return Ok(None),
+            Ok(0) => return Ok(None),
+            Ok(n) => profiles
+                .get(n - 1)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("There is no profile {n}.")),
+            Err(_) => config::validate_profile_name(choice).map(|_| choice.to_string()),
+        };
+        match picked {
+            Ok(name) => return Ok(Some(name)),
+            Err(e) => println!("{e}"),
+        }
+    }
+}
+
 fn prompt_nickname() -> Result<String> {
     use std::io::{self, BufRead, Write};
     print!("Welcome! Enter your nickname: ");
@@ -100,6 +313,6 @@ fn prompt_nickname() -> Result<String> {
     Ok(if nick.is_empty() {
         "Anonymous".to_string()
     } else {
-        nick.chars().take(32).collect()
+        nick.chars().take(wire::MAX_NICK_LEN).collect()
     })
 }
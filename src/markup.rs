@@ -0,0 +1,219 @@
+//! Markdown-lite in chat messages: `*bold*`, `_italic_` and `` `code` ``,
+//! and underlined web links.  A marker only counts at the edge of a word
+//! and when it is closed, so `2 * 3 * 4` and `snake_case_name` stay as
+//! typed; inside code and links nothing is formatted.
+
+use std::ops::Range;
+
+use chatting1_core::links;
+use ratatui::style::{Color, Modifier, Style};
+
+/// How a code span is drawn.
+const CODE: Style = Style::new().fg(Color::White).bg(Color::DarkGray);
+/// How a link is drawn.
+const LINK: Style = Style::new().add_modifier(Modifier::UNDERLINED);
+
+/// How to draw a text.
+pub struct Markup {
+    /// The style of each character, to be patched onto the message's own;
+    /// `None` for the markers that took effect, which are not drawn.
+    pub styles: Vec<Option<Style>>,
+    /// The links: the characters they cover and the URL.
+    pub links: Vec<(Range<usize>, String)>,
+}
+
+/// Markup of `text`, the rows of a message joined by spaces.  `urls` are
+/// the links in the message itself: one cut by a row break continues on
+/// the next row.  Without `formatting` only links are styled.
+pub fn parse(text: &str, urls: &[&str], formatting: bool) -> Markup {
+    let chars: Vec<char> = text.chars().collect();
+    let links: Vec<(Range<usize>, String)> = links::find(text)
+        .into_iter()
+        .map(|r| {
+            let start = text[..r.start].chars().count();
+            extend_link(&chars, start..start + text[r].chars().count(), urls)
+        })
+        .collect();
+    let mut styles = Vec::with_capacity(chars.len());
+    let (mut bold, mut italic) = (false, false);
+    let style = |bold: bool, italic: bool| {
+        let mut style = Style::new();
+        if bold {
+            style = style.add_modifier(Modifier::BOLD);
+        }
+        if italic {
+            style = style.add_modifier(Modifier::ITALIC);
+        }
+        style
+    };
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if let Some((link, _)) = links.iter().find(|(link, _)| link.start == i) {
+            // Spaces in a link are row breaks and the indent after them.
+            let link_style = style(bold, italic).patch(LINK);
+            styles.extend(
+                chars[link.clone()]
+                    .iter()
+                    .map(|&c| Some(if c == ' ' { Style::new() } else { link_style })),
+            );
+            i = link.end;
+            continue;
+        }
+        if !formatting {
+            styles.push(Some(Style::new()));
+            i += 1;
+            continue;
+        }
+        match c {
+            '`' => {
+                if let Some(end) = (i + 2..chars.len()).find(|&j| chars[j] == '`') {
+                    styles.push(None);
+                    styles.extend(std::iter::repeat_n(
+                        Some(style(bold, italic).patch(CODE)),
+                        end - i - 1,
+                    ));
+                    styles.push(None);
+                    i = end + 1;
+                    continue;
+                }
+            }
+            '*' | '_' => {
+                let open = if c == '*' { bold } else { italic };
+                let toggles = if open {
+                    closes(&chars, i)
+                } else {
+                    opens(&chars, i)
+                        && (i + 2..chars.len()).any(|j| chars[j] == c && closes(&chars, j))
+                };
+                if toggles {
+                    styles.push(None);
+                    if c == '*' {
+                        bold = !bold;
+                    } else {
+                        italic = !italic;
+                    }
+                    i += 1;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        styles.push(Some(style(bold, italic)));
+        i += 1;
+    }
+    Markup { styles, links }
+}
+
+/// The link at `link` in `chars`, carried on over row breaks (and the
+/// indent after them) while it falls short of the URL in `urls` it starts.
+fn extend_link(chars: &[char], mut link: Range<usize>, urls: &[&str]) -> (Range<usize>, String) {
+    let mut url: String = chars[link.clone()].iter().collect();
+    while chars.get(link.end) == Some(&' ')
+        && let Some(full) = urls
+            .iter()
+            .find(|u| u.len() > url.len() && u.starts_with(url.as_str()))
+    {
+        let spaces = chars[link.end..].iter().take_while(|&&c| c == ' ').count();
+        let missing = &full[url.len()..];
+        let more: String = chars[link.end + spaces..]
+            .iter()
+            .zip(missing.chars())
+            .take_while(|&(c, m)| *c == m)
+            .map(|(c, _)| *c)
+            .collect();
+        if more.is_empty() {
+            break;
+        }
+        link.end += spaces + more.chars().count();
+        url.push_str(&more);
+    }
+    (link, url)
+}
+
+/// The marker at `i` can open a span: it starts a word.
+fn opens(chars: &[char], i: usize) -> bool {
+    let before = i.checked_sub(1).map(|j| chars[j]);
+    let after = chars.get(i + 1);
+    before.is_none_or(|c| !c.is_alphanumeric()) && after.is_some_and(|c| !c.is_whitespace())
+}
+
+/// The marker at `i` can close a span: it ends a word.
+fn closes(chars: &[char], i: usize) -> bool {
+    let before = i.checked_sub(1).map(|j| chars[j]);
+    let after = chars.get(i + 1);
+    before.is_some_and(|c| !c.is_whitespace()) && after.is_none_or(|c| !c.is_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` as runs of one style each, markers dropped.
+    fn runs(text: &str) -> Vec<(String, Style)> {
+        let mut runs: Vec<(String, Style)> = Vec::new();
+        for (c, style) in text.chars().zip(parse(text, &[], true).styles) {
+            let Some(style) = style else { continue };
+            match runs.last_mut() {
+                Some((run, last)) if *last == style => run.push(c),
+                _ => runs.push((c.to_string(), style)),
+            }
+        }
+        runs
+    }
+
+    #[test]
+    fn markers_format_words_and_are_left_alone_elsewhere() {
+        let bold = Style::new().add_modifier(Modifier::BOLD);
+        let italic = Style::new().add_modifier(Modifier::ITALIC);
+        assert_eq!(
+            runs("a *big* _new_ `x_y*z`!"),
+            [
+                ("a ".to_string(), Style::new()),
+                ("big".to_string(), bold),
+                (" ".to_string(), Style::new()),
+                ("new".to_string(), italic),
+                (" ".to_string(), Style::new()),
+                ("x_y*z".to_string(), CODE),
+                ("!".to_string(), Style::new()),
+            ]
+        );
+        assert_eq!(runs("*_both_*"), [("both".to_string(), bold.patch(italic))]);
+        // Spans may cross the line breaks between wrapped rows.
+        assert_eq!(runs("*one\ntwo*"), [("one\ntwo".to_string(), bold)]);
+        for plain in [
+            "2 * 3 * 4",
+            "snake_case_name",
+            "*unclosed",
+            "a `lone tick",
+            "**",
+        ] {
+            assert_eq!(runs(plain), [(plain.to_string(), Style::new())], "{plain}");
+        }
+    }
+
+    #[test]
+    fn links_are_underlined_left_unformatted_and_followed_across_rows() {
+        let text = "go *https://a.io/x_y_z* now";
+        let markup = parse(text, &[], true);
+        assert_eq!(markup.links, [(4..22, "https://a.io/x_y_z".to_string())]);
+        let underlined =
+            |s: &Option<Style>| s.is_some_and(|s| s.add_modifier.contains(Modifier::UNDERLINED));
+        assert!(markup.styles[4..22].iter().all(underlined));
+        let drawn = text
+            .chars()
+            .zip(&markup.styles)
+            .filter(|(_, s)| s.is_some())
+            .map(|(c, _)| c);
+        assert_eq!(drawn.collect::<String>(), "go https://a.io/x_y_z now");
+
+        // A link too long for a row is cut; the next row carries on with it.
+        let rows = "see https://a.io/lo   ng/path. bye";
+        let markup = parse(rows, &["https://a.io/long/path"], false);
+        assert_eq!(
+            markup.links,
+            [(4..29, "https://a.io/long/path".to_string())]
+        );
+        assert_eq!(markup.styles[20], Some(Style::new()));
+    }
+}
@@ -0,0 +1,44 @@
+//! Desktop notifications, driven by `AppEvent::Notify` on the bus.
+//!
+//! `App` decides *when* to notify (see `chatting1_core::notify`: a mention,
+//! or any message while the terminal is unfocused, unless the room is
+//! muted); this task only shows them.  The terminal bell is rung by the TUI (`UiEvent::Bell`) so it never
+//! interleaves with a frame being drawn.
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use chatting1_core::types::{AppEvent, DisplayMessage};
+
+/// Show a desktop notification for every `Notify` until the bus closes.
+pub async fn run(mut rx: broadcast::Receiver<AppEvent>) {
+    loop {
+        match rx.recv().await {
+            Ok(AppEvent::Notify { room, msg }) => {
+                // D-Bus and friends block; keep them off the runtime.
+                tokio::task::spawn_blocking(move || show(&room, &msg));
+            }
+            Ok(_) | Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(feature = "desktop-notify")]
+fn show(room: &str, msg: &DisplayMessage) {
+    let result = notify_rust::Notification::new()
+        .summary(&format!("{} in {}", msg.sender_label(), room))
+        .body(&msg.text)
+        .appname("chat")
+        .show();
+    if let Err(e) = result {
+        tracing::debug!("Desktop notification failed: {e}");
+    }
+}
+
+#[cfg(not(feature = "desktop-notify"))]
+fn show(room: &str, msg: &DisplayMessage) {
+    tracing::debug!(
+        "Not notifying about {} in {room}: built without desktop-notify",
+        msg.sender
+    );
+}
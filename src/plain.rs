@@ -0,0 +1,538 @@
+//! `--plain`: a line-by-line frontend for screen readers and braille
+//! terminals.  There is no alternate screen and nothing is ever redrawn:
+//! each message is printed once, as a line on stdout, and commands and
+//! messages are read a line at a time from stdin.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    io::Write,
+};
+
+use anyhow::Result;
+use tokio::sync::mpsc;
+
+use chatting1_core::{
+    config::{Persona, RecentRoom},
+    types::{AppEvent, CliCommand, DisplayMessage, JoinState, NoteId, PollTally, UiEvent},
+};
+
+use crate::cli::{parse_command, split_capacity, split_persona};
+
+/// Messages of the active room kept for `/reply`.
+const KEPT_MESSAGES: usize = 100;
+/// Commands of the plain frontend itself, listed before the app's by
+/// `/help`: they stand in for the main menu.
+const PLAIN_COMMANDS: &[(&str, &str)] = &[
+    (
+        "/create <room> [max n] [as nick +key]",
+        "create a room; asks for its password",
+    ),
+    (
+        "/join <code> [as nick +key]",
+        "join a room by its code; asks for the password",
+    ),
+    (
+        "/find <room> [as nick +key]",
+        "join a room by name; asks for the password",
+    ),
+    ("/recent", "list the rooms entered lately"),
+    ("/nick <name>", "change your nickname"),
+    (
+        "/avatar [glyph]",
+        "show one emoji or character before your name; none removes it",
+    ),
+    ("/exit", "quit (end of input does too)"),
+];
+
+/// A room to enter once the next line brings its password, and who to be
+/// there.
+enum Entry {
+    /// With the member limit, 0 for none.
+    Create(String, Option<Persona>, u32),
+    Join(String, Option<Persona>),
+    Find(String, Option<Persona>),
+}
+
+#[derive(Default)]
+struct Plain {
+    /// The active room.
+    room: Option<String>,
+    /// Every joined room, for `/switch <n>`.
+    rooms: Vec<String>,
+    /// The active room's latest messages.
+    messages: VecDeque<DisplayMessage>,
+    /// Every command as (usage, what it does), from the app.
+    commands: Vec<(String, String)>,
+    recent: Vec<RecentRoom>,
+    entry: Option<Entry>,
+    /// Ring the terminal bell after printing.
+    bell: bool,
+    /// The active room's shared notes as last printed, for `/note set <n>`.
+    notes: Vec<(NoteId, String)>,
+    /// `/notes` was typed: print them when they come.
+    notes_asked: bool,
+    /// `/threads` was typed, likewise.
+    threads_asked: bool,
+    /// `/browse` was typed: public rooms printed so far, by code, to
+    /// print only new ones as they come.
+    browsing: Option<HashSet<String>>,
+}
+
+/// Runs the plain frontend until `/exit`, the end of stdin or the app
+/// closing the bus.
+pub async fn run_plain(
+    cmd_tx: mpsc::UnboundedSender<CliCommand>,
+    mut ui_rx: mpsc::UnboundedReceiver<AppEvent>,
+    join_code: Option<String>,
+) -> Result<()> {
+    // Reading stdin blocks, so it gets a thread of its own.
+    let (line_tx, mut lines) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            if line_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut plain = Plain::default();
+    let mut out = vec![
+        "Plain mode. /help lists the commands; /create, /join or /find enters a room.".to_string(),
+    ];
+    if let Some(code) = join_code {
+        out.extend(plain.ask_password(Entry::Join(code.trim().to_string(), None)));
+    }
+    print(&out, false)?;
+    loop {
+        let out = tokio::select! {
+            line = lines.recv() => {
+                let Some(line) = line else {
+                    let _ = cmd_tx.send(CliCommand::Quit);
+                    break;
+                };
+                let (out, cmd) = plain.on_line(&line);
+                if let Some(cmd) = cmd {
+                    let quit = matches!(cmd, CliCommand::Quit);
+                    let _ = cmd_tx.send(cmd);
+                    if quit {
+                        break;
+                    }
+                }
+                out
+            }
+            event = ui_rx.recv() => match event {
+                Some(AppEvent::Ui(event)) => plain.on_event(event),
+                Some(AppEvent::RoomMessage { room, msg }) => plain.on_room_message(&room, &msg),
+                Some(_) => continue,
+                None => break,
+            },
+        };
+        print(&out, std::mem::take(&mut plain.bell))?;
+    }
+    Ok(())
+}
+
+/// Write `lines` to stdout, then the bell if `bell`.
+fn print(lines: &[String], bell: bool) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    for line in lines {
+        writeln!(stdout, "{line}")?;
+    }
+    if bell {
+        stdout.write_all(b"\x07")?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// `msg` as one line: `nick#disc: text`, or a system line as it is.
+fn message_line(msg: &DisplayMessage) -> String {
+    if msg.is_system {
+        return msg.text.clone();
+    }
+    let reply = match &msg.quote {
+        Some(quote) if !quote.sender.is_empty() => format!(" (replying to {})", quote.sender),
+        _ => String::new(),
+    };
+    let poll = match &msg.poll {
+        Some(poll) => format!(" [poll: {} — /vote <n>]", poll_options(poll)),
+        None => String::new(),
+    };
+    // Later lines are indented, so none passes for a message of its own.
+    let text = msg.text.replace('\n', "\n    ");
+    format!("{}{}: {}{}", msg.sender_label(), reply, text, poll)
+}
+
+/// `1) Pizza 2, 2) Sushi 0`: a poll's options and their votes.
+fn poll_options(poll: &PollTally) -> String {
+    let options: Vec<String> = (1..)
+        .zip(&poll.options)
+        .map(|(n, (option, votes))| format!("{n}) {option} {votes}"))
+        .collect();
+    options.join(", ")
+}
+
+impl Plain {
+    /// What a line read from stdin prints and the command it sends.
+    fn on_line(&mut self, line: &str) -> (Vec<String>, Option<CliCommand>) {
+        if let Some(entry) = self.entry.take() {
+            let password = line.to_string();
+            let cmd = match entry {
+                Entry::Create(name, persona, max_members) => CliCommand::CreateRoom {
+                    name,
+                    password,
+                    persona,
+                    max_members,
+                },
+                Entry::Join(code, persona) => CliCommand::JoinRoom {
+                    code,
+                    password,
+                    persona,
+                },
+                Entry::Find(name, persona) => CliCommand::JoinRoomByName {
+                    name,
+                    password,
+                    persona,
+                },
+            };
+            return (Vec::new(), Some(cmd));
+        }
+        let line = line.trim();
+        let (command, arg) = line
+            .split_once(' ')
+            .map_or((line, ""), |(c, a)| (c, a.trim()));
+        match (command, arg) {
+            ("", _) => (Vec::new(), None),
+            ("/create" | "/join" | "/find", entry) if !entry.is_empty() => {
+                let (room, persona) = split_persona(entry);
+                let room = room.to_string();
+                let entry = match command {
+                    "/create" => {
+                        let (room, max_members) = split_capacity(&room);
+                        Entry::Create(room.to_string(), persona, max_members)
+                    }
+                    "/join" => Entry::Join(room, persona),
+                    _ => Entry::Find(room, persona),
+                };
+                (self.ask_password(entry), None)
+            }
+            ("/nick", name) if !name.is_empty() => (
+                Vec::new(),
+                Some(CliCommand::ChangeNickname(name.to_string())),
+            ),
+            ("/avatar", avatar) => (
+                Vec::new(),
+                Some(CliCommand::ChangeAvatar(avatar.to_string())),
+            ),
+            ("/recent", "") => (self.recent_lines(), None),
+            ("/exit", "") => (Vec::new(), Some(CliCommand::Quit)),
+            _ if !line.starts_with('/') => match self.room {
+                Some(_) => (Vec::new(), Some(CliCommand::SendMessage(line.to_string()))),
+                None => (
+                    vec!["Not in a room: /create, /join or /find one first.".to_string()],
+                    None,
+                ),
+            },
+            _ => match parse_command(line, &self.rooms, &self.messages, &self.notes) {
+                Ok(cmd) => {
+                    self.notes_asked |= matches!(cmd, CliCommand::ShowNotes);
+                    self.threads_asked |= matches!(cmd, CliCommand::ShowThreads);
+                    if matches!(cmd, CliCommand::BrowseRooms) {
+                        self.browsing = Some(HashSet::new());
+                    }
+                    (Vec::new(), Some(cmd))
+                }
+                Err(notice) => (vec![notice], None),
+            },
+        }
+    }
+
+    fn ask_password(&mut self, entry: Entry) -> Vec<String> {
+        self.entry = Some(entry);
+        vec!["Password, on the next line (empty for none; it is echoed):".to_string()]
+    }
+
+    fn recent_lines(&self) -> Vec<String> {
+        if self.recent.is_empty() {
+            return vec!["No recent rooms.".to_string()];
+        }
+        let mut lines =
+            vec!["Recent rooms (/rejoin the saved ones, /join with the code):".to_string()];
+        for room in &self.recent {
+            let saved = if room.saved { ", saved" } else { "" };
+            let code = if room.code.is_empty() {
+                String::new()
+            } else {
+                format!(", code {}", room.code)
+            };
+            lines.push(format!("{}{saved}{code}", room.name));
+        }
+        lines
+    }
+
+    /// What an event from the app prints.
+    fn on_event(&mut self, event: UiEvent) -> Vec<String> {
+        match event {
+            UiEvent::NewMessage(msg) => {
+                let line = message_line(&msg);
+                if !msg.is_system {
+                    self.keep(msg);
+                }
+                vec![line]
+            }
+            UiEvent::StatusUpdate { room, rooms, .. } => {
+                self.room = room;
+                self.rooms = rooms;
+                Vec::new()
+            }
+            UiEvent::ShowMainMenu => {
+                self.room = None;
+                self.messages.clear();
+                vec!["Not in a room. /create, /join, /find or /rejoin enters one.".to_string()]
+            }
+            UiEvent::RoomCreated { name, code } => {
+                let line = format!("Room '{}' created. Share this code: {}", name, code);
+                self.enter(name);
+                vec![line]
+            }
+            UiEvent::RoomCode { name, code } => vec![format!("Code for '{}': {}", name, code)],
+            UiEvent::RoomJoined(name) => {
+                let line = format!("Joined room '{}'.", name);
+                self.enter(name);
+                vec![line]
+            }
+            // The owner's line about it follows as a room message.
+            UiEvent::RoomRenamed { from, to } => {
+                if self.room.as_deref() == Some(from.as_str()) {
+                    self.room = Some(to);
+                }
+                Vec::new()
+            }
+            UiEvent::RoomSwitched { name, history } => {
+                // The same room again is history sync filling in.
+                let switched = self.room.as_deref() != Some(name.as_str());
+                self.room = Some(name.clone());
+                self.messages.clear();
+                if switched {
+                    self.notes.clear();
+                }
+                history
+                    .into_iter()
+                    .filter(|m| !m.is_system)
+                    .for_each(|m| self.keep(m));
+                if switched {
+                    vec![format!("Now in room '{}'.", name)]
+                } else {
+                    Vec::new()
+                }
+            }
+            UiEvent::Bell => {
+                self.bell = true;
+                Vec::new()
+            }
+            UiEvent::Reactions {
+                room,
+                msg_id,
+                reactions,
+            } => {
+                let msg = self.messages.iter().find(|m| m.msg_id == msg_id);
+                match msg {
+                    Some(msg) if self.room.as_deref() == Some(room.as_str()) => {
+                        let counts: Vec<String> =
+                            reactions.iter().map(|(e, n)| format!("{e} {n}")).collect();
+                        vec![format!(
+                            "Reactions to {}: \"{}\": {}",
+                            msg.sender,
+                            msg.text,
+                            counts.join(", ")
+                        )]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            UiEvent::PollTally { room, msg_id, poll } => {
+                let msg = self.messages.iter_mut().find(|m| m.msg_id == msg_id);
+                match msg {
+                    // Closing prints the result as a line of its own.
+                    Some(msg) if self.room.as_deref() == Some(room.as_str()) && !poll.closed => {
+                        let line = format!("Votes on \"{}\": {}", msg.text, poll_options(&poll));
+                        msg.poll = Some(poll);
+                        vec![line]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            UiEvent::Notes { room, lines } if self.room.as_deref() == Some(room.as_str()) => {
+                self.notes = lines;
+                if !std::mem::take(&mut self.notes_asked) {
+                    return vec![format!("The notes of '{room}' changed; /notes shows them.")];
+                }
+                if self.notes.is_empty() {
+                    return vec![format!(
+                        "The notes of '{room}' are empty; /note add <text> starts them."
+                    )];
+                }
+                let mut out = vec![format!(
+                    "Notes of '{room}' (/note add|set|del changes them):"
+                )];
+                out.extend(
+                    (1..)
+                        .zip(&self.notes)
+                        .map(|(n, (_, text))| format!("{n:>3}  {text}")),
+                );
+                out
+            }
+            UiEvent::Notes { .. } => Vec::new(),
+            UiEvent::PublicRooms(rooms) => {
+                let Some(printed) = &mut self.browsing else {
+                    return Vec::new();
+                };
+                if rooms.is_empty() && printed.is_empty() {
+                    return vec![
+                        "No public rooms yet; they show up here as owners list them.".to_string(),
+                    ];
+                }
+                let mut out = Vec::new();
+                for room in rooms.iter().filter(|r| printed.insert(r.code.clone())) {
+                    let about = if room.description.is_empty() {
+                        String::new()
+                    } else {
+                        format!(": {}", room.description)
+                    };
+                    out.push(format!(
+                        "Public room '{}', {} member(s){about}",
+                        room.name, room.members
+                    ));
+                    out.push(format!("  /join {}", room.code));
+                }
+                out
+            }
+            UiEvent::Threads { room, threads } if std::mem::take(&mut self.threads_asked) => {
+                if threads.is_empty() {
+                    return vec![format!(
+                        "'{room}' has no threads yet; /thread <name> starts one."
+                    )];
+                }
+                let mut out = vec![format!("Threads of '{room}' (/thread <name> opens one):")];
+                out.extend(threads.iter().map(|t| {
+                    let last = t.last.format("%H:%M");
+                    format!("  {}  {} message(s), last at {last}", t.name, t.messages)
+                }));
+                out
+            }
+            UiEvent::Threads { .. } => Vec::new(),
+            UiEvent::JoinProgress { room, state } => match state {
+                // `RoomJoined` and `AccessDenied` tell these.
+                JoinState::Joined | JoinState::Denied => Vec::new(),
+                _ => vec![format!("Joining '{}': {}", room, state.label())],
+            },
+            UiEvent::RoomFull { room, max_members } => {
+                vec![format!(
+                    "Room is full — '{room}' holds at most {max_members} members."
+                )]
+            }
+            UiEvent::AccessDenied(why) => {
+                vec![format!(
+                    "Access denied — {}.",
+                    why.as_deref().unwrap_or("wrong password")
+                )]
+            }
+            UiEvent::NicknameChanged(nick) => vec![format!("You are now {}.", nick)],
+            UiEvent::AvatarChanged(Some(avatar)) => vec![format!("Your avatar is {avatar}.")],
+            UiEvent::AvatarChanged(None) => vec!["You have no avatar.".to_string()],
+            UiEvent::Error(err) => vec![format!("[!] {}", err)],
+            UiEvent::Commands(commands) => {
+                self.commands = commands;
+                Vec::new()
+            }
+            UiEvent::ShowHelp => {
+                let mut lines = vec!["Commands:".to_string()];
+                let plain = PLAIN_COMMANDS
+                    .iter()
+                    .map(|&(usage, about)| (usage.to_string(), about.to_string()));
+                for (usage, about) in plain.chain(self.commands.iter().cloned()) {
+                    lines.push(format!("{usage} — {about}"));
+                }
+                lines
+            }
+            UiEvent::RecentRooms(recent) => {
+                self.recent = recent;
+                Vec::new()
+            }
+            UiEvent::MessageDelivered { .. } => Vec::new(),
+        }
+    }
+
+    /// A message in a room other than the active one, marked with its room.
+    fn on_room_message(&mut self, room: &str, msg: &DisplayMessage) -> Vec<String> {
+        if self.room.as_deref() == Some(room) {
+            return Vec::new();
+        }
+        vec![format!("#{} {}", room, message_line(msg))]
+    }
+
+    fn enter(&mut self, room: String) {
+        self.room = Some(room);
+        self.messages.clear();
+        self.notes.clear();
+    }
+
+    fn keep(&mut self, msg: DisplayMessage) {
+        if self.messages.len() == KEPT_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rooms_are_entered_with_a_password_line_and_messages_print_once() {
+        let mut plain = Plain::default();
+        let (out, cmd) = plain.on_line("hello");
+        assert!(cmd.is_none() && out[0].starts_with("Not in a room"));
+
+        let (out, cmd) = plain.on_line("/create lobby");
+        assert!(cmd.is_none() && out[0].starts_with("Password"));
+        let (_, cmd) = plain.on_line(" secret ");
+        assert!(
+            matches!(cmd, Some(CliCommand::CreateRoom { name, password, persona: None, max_members: 0 }) if name == "lobby" && password == " secret ")
+        );
+
+        let out = plain.on_event(UiEvent::RoomCreated {
+            name: "lobby".into(),
+            code: "chat2:abc".into(),
+        });
+        assert_eq!(out, ["Room 'lobby' created. Share this code: chat2:abc"]);
+        let history = vec![DisplayMessage::chat("bob#1a2b", "earlier")];
+        assert!(
+            plain
+                .on_event(UiEvent::RoomSwitched {
+                    name: "lobby".into(),
+                    history
+                })
+                .is_empty()
+        );
+
+        let mut msg = DisplayMessage::chat("bob#1a2b", "hi all");
+        msg.msg_id = 7;
+        assert_eq!(
+            plain.on_event(UiEvent::NewMessage(msg.clone())),
+            ["bob#1a2b: hi all"]
+        );
+        assert!(plain.on_room_message("lobby", &msg).is_empty());
+        assert_eq!(
+            plain.on_room_message("other", &msg),
+            ["#other bob#1a2b: hi all"]
+        );
+
+        let (_, cmd) = plain.on_line("/reply bob thanks");
+        assert!(matches!(cmd, Some(CliCommand::Reply { to: 7, text }) if text == "thanks"));
+        let (_, cmd) = plain.on_line("good morning");
+        assert!(matches!(cmd, Some(CliCommand::SendMessage(text)) if text == "good morning"));
+        assert!(matches!(plain.on_line("/exit").1, Some(CliCommand::Quit)));
+    }
+}
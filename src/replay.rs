@@ -0,0 +1,216 @@
+//! Record-and-replay of the `NetworkEvent` stream.
+//!
+//! `chat --record FILE` tees every network event into a JSON-lines capture
+//! (one `{"at_ms": …, "event": …}` frame per line), and with it each room
+//! message as the app decrypted it (`NetworkEvent::RoomPlaintext`), since
+//! the room keys are not kept.  `chat replay FILE [--speed X]` runs the
+//! normal app and TUI against that capture instead of libp2p, under a
+//! throwaway config ([`sandbox`]), re-emitting the events with their
+//! original spacing divided by `X`, so ordering and rendering bugs can be
+//! reproduced deterministically.
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, time::Instant};
+use tracing::{debug, warn};
+
+use chatting1_core::{
+    config::{Config, KeyStorage},
+    types::{NetworkCommand, NetworkEvent},
+};
+
+#[derive(Serialize, Deserialize)]
+struct Frame {
+    /// Milliseconds since recording started.
+    at_ms: u64,
+    event: NetworkEvent,
+}
+
+/// Options of the `replay` subcommand.
+#[derive(Debug, clap::Args)]
+pub struct ReplayOptions {
+    /// Capture file written by `--record`.
+    #[arg(value_name = "FILE")]
+    pub path: PathBuf,
+    /// Time scaling: 2.0 replays twice as fast, 0.5 at half speed.
+    #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+    pub speed: f64,
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse() {
+        Ok(x) if x > 0.0 => Ok(x),
+        Ok(_) => Err("must be positive".to_string()),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+/// Tee `rx` into a capture file at `path`, readable by its owner only as
+/// it holds room messages in the clear.  Returns the receiver the app
+/// should read from instead, and the sender to give `App::with_capture`.
+pub fn record(
+    mut rx: mpsc::UnboundedReceiver<NetworkEvent>,
+    path: &Path,
+) -> Result<(
+    mpsc::UnboundedReceiver<NetworkEvent>,
+    mpsc::UnboundedSender<NetworkEvent>,
+)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let file = options
+        .open(path)
+        .with_context(|| format!("create capture file {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    let (tx, out_rx) = mpsc::unbounded_channel();
+    let (capture_tx, mut decrypted) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let start = Instant::now();
+        loop {
+            // Only network events go on to the app; it sent the others.
+            let (event, forward) = tokio::select! {
+                event = rx.recv() => match event {
+                    Some(event) => (event, true),
+                    None => break,
+                },
+                Some(event) = decrypted.recv() => (event, false),
+            };
+            let frame = Frame {
+                at_ms: start.elapsed().as_millis() as u64,
+                event,
+            };
+            match serde_json::to_string(&frame) {
+                Ok(line) => {
+                    if let Err(e) = writeln!(writer, "{line}").and_then(|_| writer.flush()) {
+                        warn!("Capture write failed: {e}");
+                    }
+                }
+                Err(e) => warn!("Capture encode failed: {e}"),
+            }
+            if forward && tx.send(frame.event).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok((out_rx, capture_tx))
+}
+
+/// `config` with all a replay could change kept under `dir` instead: a
+/// fresh key and no personas, recent rooms, contacts, saved rooms or logs
+/// of the user's, who only lends the look and the key bindings.
+pub fn sandbox(config: Config, dir: &Path) -> Config {
+    let file = |name: &str| dir.join(name).to_string_lossy().into_owned();
+    Config {
+        nickname: Some("replay".to_string()),
+        avatar: None,
+        private_key_b64: None,
+        key_storage: KeyStorage::Config,
+        key_file: None,
+        log_dir: file("logs"),
+        recent_rooms: Vec::new(),
+        personas: BTreeMap::new(),
+        contacts_file: file("contacts.toml"),
+        rooms_file: file("rooms.toml"),
+        path: dir.join("config.toml"),
+        ..config
+    }
+}
+
+/// Load a capture and stand in for the network service: returns the same
+/// channel pair as `NetworkService::new`. Commands from the app are logged
+/// and otherwise ignored.
+pub fn replay(
+    opts: &ReplayOptions,
+) -> Result<(
+    mpsc::UnboundedReceiver<NetworkEvent>,
+    mpsc::UnboundedSender<NetworkCommand>,
+)> {
+    let file = File::open(&opts.path)
+        .with_context(|| format!("open capture file {}", opts.path.display()))?;
+    let mut frames = Vec::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let frame: Frame = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: bad frame", opts.path.display(), n + 1))?;
+        frames.push(frame);
+    }
+
+    let (event_tx, event_rx) = mpsc::unbounded_channel();
+    let (cmd_tx, mut cmd_rx) = mpsc::unbounded_channel::<NetworkCommand>();
+    let speed = opts.speed;
+
+    tokio::spawn(async move {
+        let start = Instant::now();
+        for frame in frames {
+            let offset = Duration::from_millis(frame.at_ms).div_f64(speed);
+            tokio::time::sleep_until(start + offset).await;
+            if event_tx.send(frame.event).is_err() {
+                return;
+            }
+        }
+        debug!("Replay finished");
+        // Keep the sender alive so the app does not see a closed network.
+        std::future::pending::<()>().await;
+    });
+
+    tokio::spawn(async move {
+        while let Some(cmd) = cmd_rx.recv().await {
+            debug!("Replay: ignoring {cmd:?}");
+        }
+    });
+
+    Ok((event_rx, cmd_tx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn recorded_stream_replays_in_order() {
+        let path = std::env::temp_dir().join(format!("chat-capture-{}.jsonl", std::process::id()));
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (mut tapped, _capture) = record(rx, &path).unwrap();
+
+        tx.send(NetworkEvent::PeerConnected("a".into())).unwrap();
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        tx.send(NetworkEvent::MessageReceived {
+            topic: "t".into(),
+            source: "b".into(),
+            payload: vec![0, 1, 255],
+        })
+        .unwrap();
+        drop(tx);
+        while tapped.recv().await.is_some() {}
+
+        let opts = ReplayOptions {
+            path: path.clone(),
+            speed: 2.0,
+        };
+        let (mut events, _cmds) = replay(&opts).unwrap();
+        let start = Instant::now();
+        assert!(matches!(events.recv().await, Some(NetworkEvent::PeerConnected(p)) if p == "a"));
+        match events.recv().await {
+            Some(NetworkEvent::MessageReceived { payload, .. }) => {
+                assert_eq!(payload, vec![0, 1, 255]);
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,52 @@
+//! `chat config get KEY` and `chat config set KEY VALUE`: read and change
+//! settings from scripts without editing the TOML by hand.  Either works on
+//! the config `--config` or `--profile` picks, `~/.chatrc` by default.
+
+use anyhow::{Result, bail};
+use clap::Subcommand;
+
+use chatting1_core::Config;
+
+use crate::{keys::KeyBindings, theme::Theme};
+
+/// Options of the `config` subcommand.
+#[derive(Debug, clap::Args)]
+pub struct ConfigOptions {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print a setting: strings as they are, anything else as TOML.
+    Get {
+        /// Name in the file, with dots into tables: `theme.preset`.
+        key: String,
+    },
+    /// Change a setting, checked before the file is written.
+    Set {
+        /// Name in the file, with dots into tables: `theme.preset`.
+        key: String,
+        /// TOML value (`false`, `4001`, `["a", "b"]`), or else a string.
+        value: String,
+    },
+}
+
+pub fn run(opts: ConfigOptions, mut config: Config) -> Result<()> {
+    match opts.command {
+        ConfigCommand::Get { key } => match config.get(&key)? {
+            Some(toml::Value::String(value)) => println!("{value}"),
+            Some(toml::Value::Table(table)) => print!("{}", toml::to_string_pretty(&table)?),
+            Some(value) => println!("{value}"),
+            None => bail!("{key} is not set in {}.", config.path.display()),
+        },
+        ConfigCommand::Set { key, value } => {
+            config.set(&key, &value)?;
+            // Colors and key chords are only understood by the terminal UI.
+            Theme::new(&config.theme, false)?;
+            KeyBindings::new(&config.keys)?;
+            config.save()?;
+        }
+    }
+    Ok(())
+}
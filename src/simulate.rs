@@ -0,0 +1,332 @@
+//! `chat simulate` — load-test the application layer with many in-process
+//! peers on a `MemoryNetwork`, then report throughput, end-to-end latency
+//! percentiles and memory use.
+
+use std::{
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+use tokio::sync::{broadcast, mpsc};
+
+use chatting1_core::{
+    app::App,
+    bus::EventBus,
+    config::{Config, KeyStorage},
+    identity::Identity,
+    memnet::MemoryNetwork,
+    types::{AppEvent, CliCommand, UiEvent},
+    wire,
+};
+
+/// Prefix of synthetic messages: `sim:<peer>:<seq>:<sent_micros>`.
+const SIM_PREFIX: &str = "sim:";
+/// How long to keep collecting after the last message was sent.
+const DRAIN_TIME: Duration = Duration::from_secs(2);
+/// Per-peer limit for finishing the join flow.
+const JOIN_LIMIT: Duration = Duration::from_secs(30);
+/// Highest `--rate`: members drop what a peer sends beyond `RATE_PER_SEC`,
+/// so a faster rate would measure that flood protection, not delivery.
+const MAX_RATE: f64 = wire::RATE_PER_SEC;
+
+/// Options of the `simulate` subcommand.
+#[derive(Debug, clap::Args)]
+pub struct SimOptions {
+    /// Number of simulated peers (at least 2).
+    #[arg(long, default_value_t = 10, value_parser = parse_peers)]
+    pub peers: usize,
+    #[arg(long, default_value = "simulation")]
+    pub room: String,
+    /// Messages per second sent by each peer (at most 10).
+    #[arg(long, default_value_t = 1.0, value_parser = parse_rate)]
+    pub rate: f64,
+    /// How long to send, in seconds.
+    #[arg(long, default_value = "10", value_parser = parse_secs)]
+    pub duration: Duration,
+}
+
+fn parse_peers(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(n) if n >= 2 => Ok(n),
+        Ok(_) => Err("must be at least 2".to_string()),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse() {
+        Ok(r) if r > 0.0 && r <= MAX_RATE => Ok(r),
+        Ok(r) if r > 0.0 => Err(format!("must be at most {MAX_RATE}")),
+        Ok(_) => Err("must be positive".to_string()),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+fn parse_secs(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("{e}"))
+}
+
+/// One simulated peer.
+struct SimPeer {
+    display_name: String,
+    cli_tx: mpsc::UnboundedSender<CliCommand>,
+    events: broadcast::Receiver<AppEvent>,
+}
+
+impl SimPeer {
+    /// Start peer `index` with a key of its own, keeping its config,
+    /// contacts and rooms files under `dir` rather than the user's.
+    fn spawn(net: &MemoryNetwork, index: usize, dir: &Path) -> Result<Self> {
+        let file = |kind: &str| dir.join(format!("sim{index}-{kind}.toml"));
+        let mut config = Config {
+            nickname: Some(format!("sim{index}")),
+            key_storage: KeyStorage::Config,
+            path: file("config"),
+            contacts_file: file("contacts").to_string_lossy().into_owned(),
+            rooms_file: file("rooms").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let identity = Identity::load_or_create(&mut config)?;
+        let display_name = identity.display_name();
+        let (node, net_event_rx, net_cmd_tx) = net.attach(&identity.peer_id.to_string());
+        let (cli_tx, cli_rx) = mpsc::unbounded_channel();
+        let bus = EventBus::new();
+        let events = bus.subscribe();
+
+        tokio::spawn(node.run());
+        let app =
+            App::new(identity, config, net_event_rx, net_cmd_tx, cli_rx, bus).without_send_limit();
+        tokio::spawn(async move {
+            let _ = app.run().await;
+        });
+        Ok(Self {
+            display_name,
+            cli_tx,
+            events,
+        })
+    }
+
+    async fn wait_for<T>(&mut self, mut f: impl FnMut(&UiEvent) -> Option<T>) -> Result<T> {
+        let wait = async {
+            loop {
+                match self.events.recv().await {
+                    Ok(AppEvent::Ui(event)) => {
+                        if let Some(value) = f(&event) {
+                            return Ok(value);
+                        }
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => bail!("peer stopped"),
+                }
+            }
+        };
+        tokio::time::timeout(JOIN_LIMIT, wait)
+            .await
+            .context("timed out waiting for peer")?
+    }
+}
+
+pub async fn run(opts: SimOptions) -> Result<()> {
+    println!(
+        "Simulating {} peers in room '{}' at {} msg/s each for {:.1}s…",
+        opts.peers,
+        opts.room,
+        opts.rate,
+        opts.duration.as_secs_f64()
+    );
+    let dir = std::env::temp_dir().join(format!("chat-simulate-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let result = simulate(&opts, &dir).await;
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+/// Form the room, run the load and report, with peers' files under `dir`.
+async fn simulate(opts: &SimOptions, dir: &Path) -> Result<()> {
+    let rss_before = resident_memory_kib();
+
+    // ── Spin up peers and form the room ───────────────────────────────────────
+    let net = MemoryNetwork::default();
+    let mut peers = (0..opts.peers)
+        .map(|i| SimPeer::spawn(&net, i, dir))
+        .collect::<Result<Vec<_>>>()?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let setup_start = Instant::now();
+    let _ = peers[0].cli_tx.send(CliCommand::CreateRoom {
+        name: opts.room.clone(),
+        password: String::new(),
+        persona: None,
+        max_members: 0,
+    });
+    let code = peers[0]
+        .wait_for(|e| match e {
+            UiEvent::RoomCreated { code, .. } => Some(code.clone()),
+            _ => None,
+        })
+        .await?;
+    for peer in &mut peers[1..] {
+        let _ = peer.cli_tx.send(CliCommand::JoinRoom {
+            code: code.clone(),
+            password: String::new(),
+            persona: None,
+        });
+        peer.wait_for(|e| matches!(e, UiEvent::RoomJoined(_)).then_some(()))
+            .await?;
+    }
+    println!("Room formed in {:.2}s", setup_start.elapsed().as_secs_f64());
+
+    // ── Collectors ────────────────────────────────────────────────────────────
+    let clock = Instant::now();
+    let (latency_tx, mut latency_rx) = mpsc::unbounded_channel::<Duration>();
+    let accepted = Arc::new(AtomicU64::new(0));
+    for peer in &mut peers {
+        let mut events = peer.events.resubscribe();
+        let latency_tx = latency_tx.clone();
+        let accepted = accepted.clone();
+        let own_name = peer.display_name.clone();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(AppEvent::Ui(UiEvent::NewMessage(msg))) if !msg.is_system => {
+                        let Some(sent) = parse_sent_micros(&msg.text) else {
+                            continue;
+                        };
+                        // Our own messages are echoed once the app took
+                        // them: that counts a send, anyone else's a delivery.
+                        if msg.sender == own_name {
+                            accepted.fetch_add(1, Ordering::Relaxed);
+                        } else {
+                            let now = clock.elapsed().as_micros() as u64;
+                            let _ =
+                                latency_tx.send(Duration::from_micros(now.saturating_sub(sent)));
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!("Simulation collector lagged by {n} events");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+    drop(latency_tx);
+
+    // ── Senders ───────────────────────────────────────────────────────────────
+    let period = Duration::from_secs_f64(1.0 / opts.rate);
+    let mut senders = Vec::new();
+    for (index, peer) in peers.iter().enumerate() {
+        let cli_tx = peer.cli_tx.clone();
+        let duration = opts.duration;
+        senders.push(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            let mut seq = 0u64;
+            while clock.elapsed() < duration {
+                ticker.tick().await;
+                let sent = clock.elapsed().as_micros();
+                let text = format!("{SIM_PREFIX}{index}:{seq}:{sent}");
+                if cli_tx.send(CliCommand::SendMessage(text)).is_err() {
+                    break;
+                }
+                seq += 1;
+            }
+            seq
+        }));
+    }
+    let mut asked = 0u64;
+    for sender in senders {
+        asked += sender.await.unwrap_or(0);
+    }
+    tokio::time::sleep(DRAIN_TIME).await;
+    // Only what the apps took counts; the rest was refused.
+    let sent = accepted.load(Ordering::Relaxed);
+    let elapsed = clock.elapsed();
+    let rss_after = resident_memory_kib();
+
+    for peer in &peers {
+        let _ = peer.cli_tx.send(CliCommand::Quit);
+    }
+
+    // ── Report ────────────────────────────────────────────────────────────────
+    let mut latencies = Vec::new();
+    while let Ok(latency) = latency_rx.try_recv() {
+        latencies.push(latency);
+    }
+    latencies.sort();
+    let expected = sent * (opts.peers as u64 - 1);
+    let delivered = latencies.len() as u64;
+
+    println!();
+    println!("Messages sent:       {sent}");
+    if sent < asked {
+        println!("Messages refused:    {}", asked - sent);
+    }
+    println!(
+        "Messages delivered:  {delivered} / {expected} ({:.1}%)",
+        if expected == 0 {
+            0.0
+        } else {
+            delivered as f64 * 100.0 / expected as f64
+        }
+    );
+    println!(
+        "Throughput:          {:.1} deliveries/s",
+        delivered as f64 / elapsed.as_secs_f64()
+    );
+    if !latencies.is_empty() {
+        println!(
+            "Latency p50/p90/p99: {} / {} / {}  (max {})",
+            fmt_ms(percentile(&latencies, 50.0)),
+            fmt_ms(percentile(&latencies, 90.0)),
+            fmt_ms(percentile(&latencies, 99.0)),
+            fmt_ms(*latencies.last().expect("non-empty")),
+        );
+    }
+    match (rss_before, rss_after) {
+        (Some(before), Some(after)) => println!(
+            "Resident memory:     {} KiB → {} KiB (+{} KiB)",
+            before,
+            after,
+            after.saturating_sub(before)
+        ),
+        _ => println!("Resident memory:     n/a on this platform"),
+    }
+    Ok(())
+}
+
+fn parse_sent_micros(text: &str) -> Option<u64> {
+    text.strip_prefix(SIM_PREFIX)?
+        .rsplit(':')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Nearest-rank percentile of an ascending, non-empty slice.
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn fmt_ms(d: Duration) -> String {
+    format!("{:.2}ms", d.as_secs_f64() * 1000.0)
+}
+
+/// Current resident set size, where the OS exposes it cheaply.
+fn resident_memory_kib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|l| l.strip_prefix("VmRSS:"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
@@ -0,0 +1,176 @@
+//! Colors of the terminal UI: a preset from `[theme]`, with the colors it
+//! names on top.  `NO_COLOR` (see no-color.org) turns every color off.
+
+use anyhow::{Result, anyhow};
+use ratatui::{
+    buffer::Buffer,
+    style::{Color, Modifier, Style},
+};
+
+use chatting1_core::config::{ThemeConfig, ThemePreset};
+
+/// Sender name colors on a dark background.
+const DARK_SENDERS: &[Color] = &[
+    Color::Green,
+    Color::Cyan,
+    Color::Red,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightBlue,
+    Color::LightCyan,
+];
+/// Sender name colors on a light background: none of the pale ones.
+const LIGHT_SENDERS: &[Color] = &[
+    Color::Green,
+    Color::Blue,
+    Color::Red,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Indexed(94),  // Brown.
+    Color::Indexed(130), // Dark orange.
+];
+
+pub struct Theme {
+    pub header: Style,
+    /// System lines, dimmed text and quotes.
+    pub system: Style,
+    pub own: Style,
+    pub mention: Style,
+    /// Border of the input box.
+    pub input: Style,
+    /// Dividers and headings.
+    pub accent: Style,
+    /// Sender names are hashed into these; empty leaves them uncolored.
+    pub senders: &'static [Color],
+    /// No colors at all: whatever was drawn in color loses it.
+    pub mono: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::preset(ThemePreset::Dark)
+    }
+}
+
+/// Whether `NO_COLOR` asks for no colors (see no-color.org: set and not
+/// empty).
+pub fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+}
+
+impl Theme {
+    /// The theme `config` describes; `no_color` (`NO_COLOR` set) makes it
+    /// monochrome whatever it says.  Fails on a color that is not one.
+    pub fn new(config: &ThemeConfig, no_color: bool) -> Result<Self> {
+        if no_color || config.preset == ThemePreset::Mono {
+            return Ok(Self::preset(ThemePreset::Mono));
+        }
+        let mut theme = Self::preset(config.preset);
+        let color = |name: &str, value: &Option<String>| -> Result<Option<Color>> {
+            value
+                .as_deref()
+                .map(|v| {
+                    v.parse()
+                        .map_err(|_| anyhow!("theme.{name}: '{v}' is not a color"))
+                })
+                .transpose()
+        };
+        if let Some(fg) = color("header_fg", &config.header_fg)? {
+            theme.header = theme.header.fg(fg);
+        }
+        if let Some(bg) = color("header_bg", &config.header_bg)? {
+            theme.header = theme.header.bg(bg);
+        }
+        for (name, value, style) in [
+            ("system", &config.system, &mut theme.system),
+            ("own", &config.own, &mut theme.own),
+            ("mention", &config.mention, &mut theme.mention),
+            ("input", &config.input, &mut theme.input),
+            ("accent", &config.accent, &mut theme.accent),
+        ] {
+            if let Some(fg) = color(name, value)? {
+                *style = style.fg(fg);
+            }
+        }
+        Ok(theme)
+    }
+
+    fn preset(preset: ThemePreset) -> Self {
+        match preset {
+            ThemePreset::Dark => Self {
+                header: Style::new().fg(Color::White).bg(Color::Blue),
+                system: Style::new().fg(Color::DarkGray),
+                own: Style::new(),
+                mention: Style::new().fg(Color::LightMagenta),
+                input: Style::new(),
+                accent: Style::new().fg(Color::Yellow),
+                senders: DARK_SENDERS,
+                mono: false,
+            },
+            ThemePreset::Light => Self {
+                header: Style::new().fg(Color::White).bg(Color::Blue),
+                system: Style::new().fg(Color::DarkGray),
+                own: Style::new().fg(Color::Blue),
+                mention: Style::new().fg(Color::Magenta),
+                input: Style::new().fg(Color::DarkGray),
+                accent: Style::new().fg(Color::Blue),
+                senders: LIGHT_SENDERS,
+                mono: false,
+            },
+            ThemePreset::Mono => Self {
+                header: Style::new().add_modifier(Modifier::REVERSED),
+                system: Style::new().add_modifier(Modifier::DIM),
+                own: Style::new(),
+                mention: Style::new().add_modifier(Modifier::BOLD),
+                input: Style::new(),
+                accent: Style::new().add_modifier(Modifier::BOLD),
+                senders: &[],
+                mono: true,
+            },
+        }
+    }
+
+    /// In a monochrome theme, take the colors out of everything drawn to
+    /// `buf` (code spans, the menu, …), leaving bold, dim and reverse.
+    pub fn apply(&self, buf: &mut Buffer) {
+        if self.mono {
+            for cell in &mut buf.content {
+                cell.set_fg(Color::Reset).set_bg(Color::Reset);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_take_named_colors_and_no_color_wins() {
+        let config = ThemeConfig {
+            preset: ThemePreset::Light,
+            header_bg: Some("#102030".to_string()),
+            mention: Some("lightred".to_string()),
+            ..ThemeConfig::default()
+        };
+        let theme = Theme::new(&config, false).unwrap();
+        assert_eq!(
+            theme.header,
+            Style::new()
+                .fg(Color::White)
+                .bg(Color::Rgb(0x10, 0x20, 0x30))
+        );
+        assert_eq!(theme.mention, Style::new().fg(Color::LightRed));
+        assert_eq!(theme.own, Style::new().fg(Color::Blue));
+
+        let mono = Theme::new(&config, true).unwrap();
+        assert!(mono.mono && mono.senders.is_empty() && mono.mention.fg.is_none());
+
+        let bad = ThemeConfig {
+            own: Some("blurple".to_string()),
+            ..ThemeConfig::default()
+        };
+        let err = Theme::new(&bad, false).err().unwrap().to_string();
+        assert_eq!(err, "theme.own: 'blurple' is not a color");
+    }
+}